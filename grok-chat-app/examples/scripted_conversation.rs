@@ -0,0 +1,66 @@
+//! A three-turn conversation through the `GrokChat` facade, answered by the
+//! in-process mock of the xAI API so it runs without an API key:
+//!
+//!     cargo run --example scripted_conversation --features mock
+
+use anyhow::Result;
+use futures_util::StreamExt;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use grok_chat_app::client::mock::{MockReply, MockUpstream};
+use grok_chat_app::prelude::*;
+
+const SCRIPT: [(&str, &str); 3] = [
+    ("Name a river in Portugal.", "The Douro."),
+    ("Where does it rise?", "In Spain, in the Picos de Urbión."),
+    (
+        "Summarize our chat.",
+        "You asked about the Douro; it rises in Spain.",
+    ),
+];
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // Answers the Nth request with the Nth scripted reply
+    let turn = AtomicUsize::new(0);
+    let upstream = MockUpstream::start(move |body| {
+        let (_, reply) = SCRIPT[turn.fetch_add(1, Ordering::SeqCst) % SCRIPT.len()];
+        if body["stream"] == true {
+            let words: Vec<&str> = reply.split_inclusive(' ').collect();
+            MockReply::stream(&words, Duration::from_millis(40))
+        } else {
+            MockReply::completion(reply)
+        }
+    })
+    .await;
+
+    let chat = GrokChat::new(upstream.config())?;
+    let session = chat.new_session("grok-4-0709").await?;
+
+    for (prompt, _) in &SCRIPT[..2] {
+        println!("You:  {}", prompt);
+        let reply = session.send(*prompt).await?;
+        println!("Grok: {}", reply.message.content);
+    }
+
+    // The last turn streams word by word
+    let (prompt, _) = SCRIPT[2];
+    println!("You:  {}", prompt);
+    print!("Grok: ");
+    let mut chunks = session.stream(prompt).await?;
+    while let Some(chunk) = chunks.next().await {
+        print!("{}", chunk?.content);
+        std::io::stdout().flush()?;
+    }
+    println!();
+
+    let history = session.history().await?;
+    println!(
+        "\n{} messages stored in session {}",
+        history.len(),
+        session.id()
+    );
+    Ok(())
+}
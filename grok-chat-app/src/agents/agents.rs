@@ -0,0 +1,220 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::capabilities::ModelProfiles;
+use crate::models::ApiChatRequest;
+
+/// A `[default.agents.<name>]` section of `config.toml`. Every key is
+/// optional; unset ones fall back to the configured defaults.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AgentSettings {
+    pub description: Option<String>,
+    /// A model or alias.
+    pub model: Option<String>,
+    pub system_prompt: Option<String>,
+    pub max_tokens: Option<i32>,
+    pub temperature: Option<f32>,
+    /// Names of the tools the agent may call.
+    #[serde(default)]
+    pub tools: Vec<String>,
+}
+
+/// A named model with its own system prompt, parameters and tools, such as
+/// `reviewer` or `summarizer`. Picked with `--agent`, `/agent` or a request's
+/// `agent` field; sessions may name a default one.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Agent {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Resolved, never an alias.
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    pub tools: Vec<String>,
+}
+
+impl Agent {
+    pub fn new(name: &str, settings: &AgentSettings, model: String) -> Self {
+        Self {
+            name: name.to_string(),
+            description: settings.description.clone(),
+            model,
+            system_prompt: settings.system_prompt.clone(),
+            max_tokens: settings.max_tokens,
+            temperature: settings.temperature,
+            tools: settings.tools.clone(),
+        }
+    }
+
+    /// Fails when the agent asks its model for something the model's
+    /// profile rules out, rather than having it silently dropped on every
+    /// request. A model without a profile can't be checked, so it must be
+    /// given one.
+    pub fn validate(&self, profiles: &ModelProfiles) -> Result<()> {
+        let Some(profile) = profiles.profile_for(&self.model) else {
+            bail!(
+                "agents.{}: model '{}' has no profile; describe it under \
+                 [default.model_profiles.{}]",
+                self.name,
+                self.model,
+                self.model
+            );
+        };
+        if let Some(temperature) = self.temperature {
+            if !profile.temperature {
+                bail!(
+                    "agents.{}: {} does not support temperature",
+                    self.name,
+                    self.model
+                );
+            }
+            if !(0.0..=2.0).contains(&temperature) {
+                bail!("agents.{}: temperature must be within 0.0-2.0", self.name);
+            }
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            if max_tokens <= 0 {
+                bail!("agents.{}: max_tokens must be positive", self.name);
+            }
+            if let Some(limit) = profile
+                .max_output_tokens
+                .filter(|&limit| max_tokens > limit)
+            {
+                bail!(
+                    "agents.{}: {} allows max_tokens up to {}",
+                    self.name,
+                    self.model,
+                    limit
+                );
+            }
+        }
+        if !self.tools.is_empty() && !profile.tools {
+            bail!(
+                "agents.{}: {} does not support tools",
+                self.name,
+                self.model
+            );
+        }
+        Ok(())
+    }
+
+    /// Puts the agent's model, system prompt and parameters into `request`.
+    /// Ones the agent leaves unset keep the request's.
+    pub fn apply(&self, request: &mut ApiChatRequest) {
+        request.model = self.model.clone();
+        if let Some(prompt) = &self.system_prompt {
+            request.system_prompt = Some(prompt.clone());
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            request.max_tokens = Some(max_tokens);
+        }
+        if let Some(temperature) = self.temperature {
+            request.temperature = Some(temperature);
+        }
+    }
+}
+
+/// An agent name that matches no configured agent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownAgent {
+    pub name: String,
+    /// Names of the configured agents, sorted.
+    pub available: Vec<String>,
+}
+
+impl fmt::Display for UnknownAgent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.available.is_empty() {
+            return write!(
+                f,
+                "Unknown agent '{}' (no agents are configured; add them under \
+                 [default.agents.<name>] in config.toml)",
+                self.name
+            );
+        }
+        write!(
+            f,
+            "Unknown agent '{}' (available: {})",
+            self.name,
+            self.available.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownAgent {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agent(model: &str, settings: AgentSettings) -> Agent {
+        Agent::new("reviewer", &settings, model.to_string())
+    }
+
+    #[test]
+    fn test_agents_are_checked_against_model_profiles() {
+        let profiles = ModelProfiles::default();
+        let reviewer = AgentSettings {
+            system_prompt: Some("Review the diff.".to_string()),
+            max_tokens: Some(4_000),
+            temperature: Some(0.2),
+            tools: vec!["read_file".to_string()],
+            ..AgentSettings::default()
+        };
+        agent("grok-3", reviewer.clone())
+            .validate(&profiles)
+            .unwrap();
+
+        let error = |model, settings| {
+            agent(model, settings)
+                .validate(&profiles)
+                .unwrap_err()
+                .to_string()
+        };
+        // Reasoning models pick their own temperature
+        assert_eq!(
+            error("grok-4-0709", reviewer.clone()),
+            "agents.reviewer: grok-4-0709 does not support temperature"
+        );
+        assert_eq!(
+            error(
+                "grok-2-vision-1212",
+                AgentSettings {
+                    tools: vec!["read_file".to_string()],
+                    ..AgentSettings::default()
+                }
+            ),
+            "agents.reviewer: grok-2-vision-1212 does not support tools"
+        );
+        assert_eq!(
+            error(
+                "grok-code-fast-1",
+                AgentSettings {
+                    max_tokens: Some(50_000),
+                    ..AgentSettings::default()
+                }
+            ),
+            "agents.reviewer: grok-code-fast-1 allows max_tokens up to 10000"
+        );
+        assert!(error("llama-3", AgentSettings::default()).contains("has no profile"));
+    }
+
+    #[test]
+    fn test_unknown_agent_lists_available_names() {
+        let error = UnknownAgent {
+            name: "critic".to_string(),
+            available: vec!["reviewer".to_string(), "summarizer".to_string()],
+        };
+        assert_eq!(
+            error.to_string(),
+            "Unknown agent 'critic' (available: reviewer, summarizer)"
+        );
+    }
+}
@@ -0,0 +1,3 @@
+pub use self::agents::*;
+#[allow(clippy::module_inception)]
+mod agents;
@@ -0,0 +1,121 @@
+//! Annotated messages as an evaluation dataset: one JSON line per message
+//! with a rating, note or labels, holding the conversation that led up to
+//! it, the message itself and the annotation.
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::database::Database;
+use crate::models::{Annotation, ApiMessage, Message, Rating};
+
+/// One line of `GET /annotations/export`.
+#[derive(Debug, Clone, Serialize)]
+pub struct AnnotatedExample {
+    pub session_id: String,
+    pub message_id: i64,
+    /// The messages before this one that were still part of the
+    /// conversation, system prompt and seeded examples included.
+    pub context: Vec<ApiMessage>,
+    pub response: ApiMessage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    pub annotation: Annotation,
+}
+
+/// Every annotated message, or only those rated `rating`: sessions oldest
+/// first, messages in conversation order.
+pub async fn annotated_examples(
+    database: &Database,
+    rating: Option<Rating>,
+) -> Result<Vec<AnnotatedExample>> {
+    let mut examples = Vec::new();
+    for session_id in database.annotated_sessions(rating).await? {
+        // Replaced replies too: they may have been annotated before being
+        // regenerated
+        let messages = database.get_messages_with_superseded(&session_id).await?;
+        examples.extend(session_examples(&messages, rating));
+    }
+    Ok(examples)
+}
+
+fn session_examples(messages: &[Message], rating: Option<Rating>) -> Vec<AnnotatedExample> {
+    messages
+        .iter()
+        .enumerate()
+        .filter_map(|(i, message)| {
+            let annotation = message.annotation.clone()?;
+            if rating.is_some() && annotation.rating != rating {
+                return None;
+            }
+            let context = messages[..i]
+                .iter()
+                .filter(|earlier| earlier.superseded_by.is_none())
+                .map(|earlier| ApiMessage::new(earlier.role.clone(), earlier.content.clone()))
+                .collect();
+            Some(AnnotatedExample {
+                session_id: message.session_id.clone(),
+                message_id: message.id,
+                context,
+                response: ApiMessage::new(message.role.clone(), message.content.clone()),
+                model: message.model.clone(),
+                annotation,
+            })
+        })
+        .collect()
+}
+
+/// `examples` as JSON Lines.
+pub fn to_jsonl(examples: &[AnnotatedExample]) -> Result<String> {
+    let mut out = String::new();
+    for example in examples {
+        out.push_str(&serde_json::to_string(example)?);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::MessageRole;
+
+    fn message(id: i64, role: MessageRole, content: &str) -> Message {
+        let mut message = Message::new("s1".to_string(), role, content.to_string(), None);
+        message.id = id;
+        message
+    }
+
+    #[test]
+    fn test_examples_carry_the_live_conversation_before_them() {
+        let mut replaced = message(2, MessageRole::Assistant, "Paris?");
+        replaced.superseded_by = Some(3);
+        replaced.annotation = Some(Annotation {
+            rating: Some(Rating::Down),
+            ..Annotation::default()
+        });
+        let mut reply = message(3, MessageRole::Assistant, "Lisbon");
+        reply.annotation = Some(Annotation {
+            rating: Some(Rating::Up),
+            labels: vec!["geography".to_string()],
+            ..Annotation::default()
+        });
+        let messages = vec![
+            message(1, MessageRole::User, "Capital of Portugal?"),
+            replaced,
+            reply,
+            message(4, MessageRole::User, "Thanks"),
+        ];
+
+        let all = session_examples(&messages, None);
+        assert_eq!(all.iter().map(|e| e.message_id).collect::<Vec<_>>(), [2, 3]);
+        let up = session_examples(&messages, Some(Rating::Up));
+        assert_eq!(up.len(), 1);
+        let contents: Vec<&str> = up[0].context.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["Capital of Portugal?"]);
+        assert_eq!(up[0].response.content, "Lisbon");
+
+        let line = to_jsonl(&up).unwrap();
+        assert_eq!(line.lines().count(), 1);
+        assert!(line.contains(r#""annotation":{"rating":"up","labels":["geography"]}"#));
+    }
+}
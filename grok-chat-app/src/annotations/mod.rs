@@ -0,0 +1,3 @@
+pub use self::annotations::*;
+#[allow(clippy::module_inception)]
+mod annotations;
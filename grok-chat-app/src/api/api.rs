@@ -1,39 +1,447 @@
 use anyhow::Result;
+use arc_swap::ArcSwap;
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    response::{Html, IntoResponse, Json},
-    routing::{get, post},
+    async_trait,
+    body::{Body, Bytes},
+    extract::{ConnectInfo, DefaultBodyLimit, FromRequestParts, Path, Query, Request, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html, IntoResponse, Json, Response,
+    },
+    routing::{get, patch, post},
     Router,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
-use uuid::Uuid;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex as StdMutex};
+use tokio::sync::{mpsc, Mutex, OwnedMutexGuard, RwLock};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
 
-use crate::client::{ChatResponse, ChatService};
-use crate::config::Config;
+use crate::agents::Agent;
+use crate::annotations::{annotated_examples, to_jsonl};
+use crate::archive::{export_archive, import_archive, read_tar_gz, ArchiveWriter};
+use crate::audit::{Actor, AuditEntry, AuditFilter, AuditOp, AuditRecord};
+use crate::capabilities::{ModelProfile, ParamWarning, RequestDefaults};
+use crate::cli::{parse_since, parse_until, ImportFormat};
+use crate::client::{
+    is_context_length_exceeded, is_offline, split_model, ChatResponse, ChatService, CircuitOpen,
+    ModelHealth, ModelMigration, RetiredModel, UnknownProvider,
+};
+use crate::config::{config_file_path, Config};
+use crate::context::{pinned_count, session_context_dropping, ContextRetry, DEFAULT_TOKEN_BUDGET};
 use crate::database::Database;
-use crate::models::{ApiMessage, ChatSession, Message, MessageRole};
+use crate::dedupe::{check_similarity, dedupe_sessions, DEFAULT_SIMILARITY};
+use crate::diff::{word_diff, DiffSpan};
+use crate::export::{ExportChunks, SessionChanged};
+use crate::finetune::{FinetuneExport, FinetuneFilter};
+use crate::import::{import_sessions, parse_export};
+use crate::media::MediaStore;
+use crate::models::{
+    Annotation, ApiChatRequest, ApiMessage, ChatRequest, ChatSession, CompletionStats,
+    Conversation, Message, MessageRole, ModelUsage, Rating, RestoreReport, SessionExport,
+    SessionShare, SessionSnapshot, SnapshotRemoved, TokenLogprob, UsageStats,
+};
+use crate::outbox::Outbox;
+use crate::preflight::startup_config;
+use crate::presets::{PromptPreset, UnknownPreset};
+use crate::pricing::{Cost, CostEstimate, CostGuard, CostNotConfirmed, PricingTable};
+use crate::redact::Redactor;
+use crate::reload::{self, InvalidConfig, ReloadReport};
+use crate::replay::{replay, ReplayOptions};
+use crate::report::{self, ReportPeriod, ReportZone};
+use crate::session_templates::{SessionTemplate, UnknownSessionTemplate};
+use crate::templates::PromptTemplate;
 
 #[derive(Clone)]
 pub struct AppState {
     pub chat_service: ChatService,
+    /// Swapped by `POST /admin/reload` and the config file watcher; see
+    /// `crate::reload`.
+    pub config: Arc<ArcSwap<Config>>,
     pub database: Database,
+    pub pricing: Arc<ArcSwap<PricingTable>>,
     pub sessions: Arc<RwLock<HashMap<String, Vec<Message>>>>,
+    pub session_locks: SessionLocks,
 }
 
-#[derive(Deserialize)]
+/// One lock per session, so concurrent sends to a session run one after the
+/// other and each reply is generated with the previous exchange in context.
+/// A session's entry is dropped once nobody holds or waits for its lock.
+#[derive(Clone, Default)]
+pub struct SessionLocks {
+    locks: Arc<StdMutex<HashMap<String, Arc<Mutex<()>>>>>,
+}
+
+impl SessionLocks {
+    /// Waits for the session's lock; it is released when the guard drops.
+    pub async fn lock(&self, session_id: &str) -> SessionGuard {
+        let lock = self
+            .locks
+            .lock()
+            .unwrap()
+            .entry(session_id.to_string())
+            .or_default()
+            .clone();
+        // Built before waiting so a cancelled wait still cleans up the entry
+        let mut guard = SessionGuard {
+            guard: None,
+            locks: self.clone(),
+            session_id: session_id.to_string(),
+        };
+        guard.guard = Some(lock.lock_owned().await);
+        guard
+    }
+
+    /// Sessions with a send in flight or queued.
+    pub fn len(&self) -> usize {
+        self.locks.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+pub struct SessionGuard {
+    guard: Option<OwnedMutexGuard<()>>,
+    locks: SessionLocks,
+    session_id: String,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        // Waiters clone the entry under the map lock, so with it held here a
+        // count of one means the map's own reference is the last
+        let mut locks = self.locks.locks.lock().unwrap();
+        self.guard.take();
+        if locks
+            .get(&self.session_id)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            locks.remove(&self.session_id);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct CreateSessionRequest {
     pub model: Option<String>,
     pub title: Option<String>,
+    /// Prompt preset the session's messages are sent with.
+    #[serde(default)]
+    pub prompt_preset: Option<String>,
+    /// Agent that answers messages which don't name one.
+    #[serde(default)]
+    pub agent: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
+pub struct ExportSessionQuery {
+    /// Keep the messages the session's template seeded it with.
+    #[serde(default)]
+    pub include_seeded: bool,
+}
+
+#[derive(Deserialize, Default)]
+pub struct CreateSessionQuery {
+    /// Session template whose messages the new session starts with.
+    #[serde(default)]
+    pub template: Option<String>,
+}
+
+/// Fields left out keep the value they had.
+#[derive(Serialize, Deserialize, Default)]
+pub struct UpdateSessionRequest {
+    /// A preset name, or null for the default prompt.
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub prompt_preset: Option<Option<String>>,
+    /// An agent name, or null for none.
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub agent: Option<Option<String>>,
+}
+
+/// Reads a field that is there as `Some`, even when it is `null`, so a field
+/// set to `null` differs from one left out.
+fn present<'de, D: serde::Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeletedPrompt {
+    pub name: String,
+    /// Sessions that used the preset and now get the default prompt.
+    pub sessions_reset: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct SendMessageRequest {
     pub message: String,
     pub model: Option<String>,
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Send even when the request could cost more than the cost threshold.
+    #[serde(default)]
+    pub confirm_cost: bool,
+    /// Agent to answer with, instead of the session's default one.
+    #[serde(default)]
+    pub agent: Option<String>,
+    /// Drop the oldest history and send again if the model rejects the
+    /// conversation as too long, when `context_retry` is `ask`.
+    #[serde(default)]
+    pub shed_context: bool,
+    /// Answer with server-sent events as the reply arrives.
+    #[serde(default)]
+    pub stream: bool,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct UpdateMessageRequest {
+    /// Omit (or send no body) to flip the current flag.
+    pub pinned: Option<bool>,
+}
+
+/// Fields left out keep the value they had.
+#[derive(Deserialize, Default)]
+pub struct AnnotateRequest {
+    pub rating: Option<Rating>,
+    pub note: Option<String>,
+    pub labels: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct AnnotationExportQuery {
+    /// Only messages rated `up` or `down`; all annotated ones by default.
+    pub rating: Option<Rating>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct ReportQuery {
+    /// `day`, `week` (the default) or `month`.
+    #[serde(default)]
+    pub period: ReportPeriod,
+}
+
+#[derive(Deserialize, Default)]
+pub struct AuditQuery {
+    pub since: Option<String>,
+    pub actor: Option<String>,
+    /// An operation such as `session.delete`, or a family such as
+    /// `maintenance`.
+    pub op: Option<String>,
+    /// Defaults to [`DEFAULT_AUDIT_PAGE`].
+    pub limit: Option<i64>,
+    #[serde(default)]
+    pub offset: i64,
+}
+
+pub const DEFAULT_AUDIT_PAGE: i64 = 50;
+
+#[derive(Deserialize, Default)]
+pub struct DedupeQuery {
+    /// Only report the duplicate clusters; leave the sessions as they are.
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Defaults to [`DEFAULT_SIMILARITY`].
+    pub similarity: Option<f64>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct MessagesQuery {
+    /// Also list the kept earlier generations of regenerated replies.
+    #[serde(default)]
+    pub include_superseded: bool,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct RegenerateRequest {
+    /// Defaults to the model that wrote the reply being replaced.
+    pub model: Option<String>,
+    /// Send even when it could cost more than the cost threshold.
+    #[serde(default)]
+    pub confirm_cost: bool,
+}
+
+/// A reply and the generation it replaced.
+#[derive(Serialize)]
+pub struct ReplyDiff {
+    pub message: Message,
+    pub previous: Message,
+    /// Word-level changes from `previous` to `message`.
+    pub diff: Vec<DiffSpan>,
+}
+
+impl ReplyDiff {
+    fn new(message: Message, previous: Message) -> Self {
+        let diff = word_diff(&previous.content, &message.content);
+        Self {
+            message,
+            previous,
+            diff,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct ShareSessionRequest {
+    /// Without it the link works until it is revoked.
+    pub expires_in_hours: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateSnapshotRequest {
+    pub label: String,
+}
+
+#[derive(Serialize)]
+pub struct ShareLink {
+    #[serde(flatten)]
+    pub share: SessionShare,
+    /// Paths on this server: the transcript as JSON, and as a page for browsers.
+    pub url: String,
+    pub view_url: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RenderTemplateRequest {
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+    /// When set, the rendered text is sent into this session.
+    pub session_id: Option<String>,
+    pub model: Option<String>,
+    #[serde(default)]
+    pub confirm_cost: bool,
+    #[serde(default)]
+    pub shed_context: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReplayRequest {
+    /// A session export, as returned by `sessions export --format json`.
+    pub export: SessionExport,
+    /// Defaults to the exported session's model.
+    pub model: Option<String>,
+    pub max_tokens: Option<i32>,
+    pub temperature: Option<f32>,
+    pub fail_threshold: Option<f64>,
+    /// Replay turns that could cost more than the cost threshold.
+    #[serde(default)]
+    pub confirm_cost: bool,
+}
+
+#[derive(Serialize)]
+pub struct RenderTemplateResponse {
+    pub text: String,
+    pub warnings: Vec<String>,
+    pub session_id: Option<String>,
+    /// Assistant reply, present when the text was sent into a session.
+    pub response: Option<String>,
+    /// The API was unreachable, so the text waits in the session's outbox.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub queued: bool,
+    /// Set when the model was retired and the session moved to its
+    /// replacement.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub migration: Option<ModelMigration>,
+    /// Oldest messages left out after the model rejected the conversation
+    /// as too long, so the reply didn't see the whole history.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shed_messages: Option<usize>,
+    /// Secrets masked in the text before it was sent into the session.
+    pub redactions: usize,
+    /// Side effects of post-processing the reply, such as files written.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub effects: Vec<String>,
+    /// What the send could have cost, worked out beforehand; absent when the
+    /// cost guard is off.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_cost: Option<CostEstimate>,
+    /// What the send did cost, from the usage reported.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<Cost>,
+}
+
+/// Result of the stateless `/chat` endpoint; nothing is persisted.
+#[derive(Serialize)]
+pub struct ChatCompletionResult {
+    pub content: String,
+    pub model: String,
+    pub finish_reason: Option<String>,
+    pub usage: Option<UsageStats>,
+    /// Secrets masked in the request before it was sent.
+    pub redactions: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<Vec<TokenLogprob>>,
+    /// Parameters dropped or clamped to suit the model.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<ParamWarning>,
+    /// Side effects of post-processing the reply, such as files written.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub effects: Vec<String>,
+    /// Set when the requested model was retired and the request went to its
+    /// replacement instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub migration: Option<ModelMigration>,
+    /// What the request could have cost, worked out before it was sent;
+    /// absent when the cost guard is off.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_cost: Option<CostEstimate>,
+    /// What the request did cost, from the usage reported.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<Cost>,
+}
+
+/// An entry of `GET /models`; `profile` is null for models without one.
+#[derive(Serialize)]
+pub struct ModelInfo {
+    pub id: String,
+    /// The provider serving the model; see `crate::client::Providers`.
+    pub provider: String,
+    pub profile: Option<ModelProfile>,
+}
+
+#[derive(Serialize)]
+pub struct ModelUsageReport {
+    #[serde(flatten)]
+    pub usage: ModelUsage,
+    pub cost: Cost,
+}
+
+/// What the server allows, so clients can adapt: a read-only server turns
+/// away every request but GET, HEAD and OPTIONS.
+#[derive(Debug, Serialize)]
+pub struct ServerCapabilities {
+    pub read_only: bool,
+    pub version: String,
+}
+
+#[derive(Serialize)]
+pub struct StatsResponse {
+    pub total_sessions: i64,
+    pub total_messages: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    /// Sum over models with known pricing; see `unpriced_models` for the rest.
+    pub estimated_cost_usd: f64,
+    pub unpriced_models: Vec<String>,
+    /// Latency, success rate and breaker state of models used since startup.
+    pub models: Vec<ModelHealth>,
 }
 
 #[derive(Serialize)]
@@ -61,51 +469,285 @@ impl<T> ApiResponse<T> {
     }
 }
 
-pub async fn run_server(host: String, port: u16) -> Result<()> {
-    let config = Config::from_env()?;
-    let chat_service = ChatService::new(&config);
-    let database = Database::new(&config).await?;
+impl AppState {
+    /// Opens the database and pricing table `config` points at.
+    pub async fn new(config: Config) -> Result<Self> {
+        let chat_service = ChatService::new(&config)?;
+        let database = Database::new(&config).await?;
+        let pricing = Arc::new(ArcSwap::from_pointee(PricingTable::load(&config)?));
 
-    let state = AppState {
-        chat_service,
-        database,
-        sessions: Arc::new(RwLock::new(HashMap::new())),
-    };
+        Ok(Self {
+            chat_service,
+            config: Arc::new(ArcSwap::from_pointee(config)),
+            database,
+            pricing,
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            session_locks: SessionLocks::default(),
+        })
+    }
 
-    let app = Router::new()
-        .route("/", get(index_handler))
-        .route("/health", get(health_handler))
-        .route(
-            "/sessions",
-            get(list_sessions_handler).post(create_session_handler),
-        )
-        .route("/sessions/:session_id", get(get_session_handler))
-        .route(
-            "/sessions/:session_id/messages",
-            get(get_messages_handler).post(send_message_handler),
-        )
-        .route("/models", get(list_models_handler));
+    /// The chat service, defaulting to the system prompt configured now
+    /// rather than the one it was built with.
+    fn chat_service(&self) -> ChatService {
+        let config = self.config.load();
+        self.chat_service
+            .clone()
+            .with_system_prompt(config.system_prompt())
+            .with_defaults(RequestDefaults::from_config(&config))
+    }
+}
+
+pub async fn run_server(host: String, port: u16, skip_preflight: bool) -> Result<()> {
+    let config = startup_config(skip_preflight).await?;
+    let state = AppState::new(config).await?;
 
-    let app = app.with_state(state);
+    let retry_state = state.clone();
+    // Dropping the watcher stops it, so it lives as long as the server
+    let config_path = config_file_path().filter(|path| {
+        path.parent()
+            .is_none_or(|dir| dir.as_os_str().is_empty() || dir.is_dir())
+    });
+    let _watcher = match config_path {
+        Some(path) => {
+            match reload::watch(
+                path.clone(),
+                state.config.clone(),
+                state.pricing.clone(),
+                state.database.clone(),
+            ) {
+                Ok(watcher) => {
+                    println!("👀 Reloading configuration when {} changes", path.display());
+                    Some(watcher)
+                }
+                Err(e) => {
+                    eprintln!("⚠️  Not watching {} for changes: {}", path.display(), e);
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+    if state.config.load().server_read_only() {
+        println!("🔒 Read-only: only GET requests are served");
+    }
+    let app = router(state);
 
     let addr = format!("{}:{}", host, port);
     println!("🚀 Grok Chat API server starting on http://{}", addr);
     println!("📖 API Documentation:");
     println!("   GET  /health - Health check");
+    println!("   GET  /capabilities - Whether the server is read-only");
+    println!("   POST /chat - Stateless chat completion");
     println!("   GET  /sessions - List chat sessions");
-    println!("   POST /sessions - Create new session");
+    println!("   POST /sessions?template= - Create new session, optionally from a template");
+    println!("   POST /sessions/import?format= - Import a ChatGPT, generic or archive export");
     println!("   GET  /sessions/:id - Get session details");
+    println!("   PATCH /sessions/:id - Choose the session's prompt preset and agent");
     println!("   GET  /sessions/:id/messages - Get session messages");
-    println!("   POST /sessions/:id/messages - Send message to session");
-    println!("   GET  /models - List available models");
+    println!("   POST /sessions/:id/messages - Send message to session, streamed as SSE with \"stream\": true");
+    println!("   PATCH /sessions/:id/messages/:msg_id - Pin or unpin a message");
+    println!("   GET/POST/DELETE /sessions/:id/messages/:msg_id/annotations - Rate, note or label a message");
+    println!(
+        "   GET  /annotations/export?rating= - Annotated messages with their context, as JSONL"
+    );
+    println!(
+        "   GET  /sessions/:id/export?include_seeded= - Download a session as JSON (resumable)"
+    );
+    println!("   POST/DELETE /sessions/:id/share - Create or revoke a read-only link");
+    println!("   GET/POST /sessions/:id/snapshots - List or take restore points");
+    println!("   POST /sessions/:id/restore/:snapshot_id - Roll the session back to a snapshot");
+    println!("   GET  /shared/:token - A shared session and its messages");
+    println!("   GET  /shared/:token/view - A shared session as a web page");
+    println!("   GET  /models - List available models with their profiles");
+    println!("   GET  /agents - List the configured agents");
+    println!("   GET  /usage - Token usage and estimated cost per model");
+    println!("   GET  /stats - Totals across all sessions, plus model health");
+    println!("   GET  /templates - List prompt templates");
+    println!("   POST /templates - Create prompt template");
+    println!("   GET/PUT/DELETE /templates/:name - Manage a template");
+    println!("   POST /templates/:name/render - Fill a template (optionally send it)");
+    println!("   GET  /prompts - List system prompt presets");
+    println!("   POST /prompts - Create a prompt preset");
+    println!("   GET/PUT/DELETE /prompts/:name - Manage a preset");
+    println!("   GET  /session-templates - List session templates");
+    println!("   POST /session-templates - Create a session template");
+    println!("   GET/PUT/DELETE /session-templates/:name - Manage a session template");
+    println!("   POST /replay - Re-send an exported session and compare the replies");
+    println!("   GET  /export?since= - Download every session as a .tar.gz archive");
+    println!(
+        "   GET  /export/finetune?tag=&rating=&since=&until=&per_turn= - Sessions as fine-tuning JSONL"
+    );
+    println!("   GET  /media/:hash - An attached image (ETag/If-None-Match aware)");
+    println!("   POST /maintenance/dedupe?dry_run= - Find and merge duplicate sessions");
+    println!("   POST /admin/reload - Re-read the configuration without restarting");
+    println!("   GET  /audit?since=&actor=&op= - Who changed what, newest first");
     println!();
 
+    tokio::spawn(retry_queued(retry_state));
+
     let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
 
+/// Every route of the API, serving `state`.
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/", get(index_handler))
+        .route("/health", get(health_handler))
+        .route("/capabilities", get(capabilities_handler))
+        .route("/chat", post(chat_handler))
+        .route(
+            "/sessions",
+            get(list_sessions_handler).post(create_session_handler),
+        )
+        .route(
+            "/sessions/import",
+            post(import_sessions_handler).layer(DefaultBodyLimit::max(IMPORT_BODY_LIMIT)),
+        )
+        .route(
+            "/sessions/:session_id",
+            get(get_session_handler).patch(update_session_handler),
+        )
+        .route(
+            "/sessions/:session_id/messages",
+            get(get_messages_handler).post(send_message_handler),
+        )
+        .route(
+            "/sessions/:session_id/messages/:message_id",
+            patch(update_message_handler),
+        )
+        .route(
+            "/sessions/:session_id/messages/:message_id/annotations",
+            get(get_annotation_handler)
+                .post(annotate_handler)
+                .delete(delete_annotation_handler),
+        )
+        .route("/annotations/export", get(export_annotations_handler))
+        .route(
+            "/sessions/:session_id/messages/:message_id/regenerate",
+            post(regenerate_handler),
+        )
+        .route(
+            "/sessions/:session_id/messages/:message_id/diff",
+            get(message_diff_handler),
+        )
+        .route("/sessions/:session_id/export", get(export_session_handler))
+        .route(
+            "/sessions/:session_id/share",
+            post(share_session_handler).delete(revoke_share_handler),
+        )
+        .route(
+            "/sessions/:session_id/snapshots",
+            get(list_snapshots_handler).post(create_snapshot_handler),
+        )
+        .route(
+            "/sessions/:session_id/restore/:snapshot_id",
+            post(restore_snapshot_handler),
+        )
+        // Read-only and open to anyone holding the token, which is the only
+        // thing these look at
+        .route("/shared/:token", get(shared_session_handler))
+        .route("/shared/:token/view", get(shared_session_view_handler))
+        .route("/models", get(list_models_handler))
+        .route("/agents", get(list_agents_handler))
+        .route("/usage", get(usage_handler))
+        .route("/reports/usage", get(usage_report_handler))
+        .route("/stats", get(stats_handler))
+        .route(
+            "/templates",
+            get(list_templates_handler).post(create_template_handler),
+        )
+        .route(
+            "/templates/:name",
+            get(get_template_handler)
+                .put(update_template_handler)
+                .delete(delete_template_handler),
+        )
+        .route("/templates/:name/render", post(render_template_handler))
+        .route(
+            "/prompts",
+            get(list_prompts_handler).post(create_prompt_handler),
+        )
+        .route(
+            "/prompts/:name",
+            get(get_prompt_handler)
+                .put(update_prompt_handler)
+                .delete(delete_prompt_handler),
+        )
+        .route(
+            "/session-templates",
+            get(list_session_templates_handler).post(create_session_template_handler),
+        )
+        .route(
+            "/session-templates/:name",
+            get(get_session_template_handler)
+                .put(update_session_template_handler)
+                .delete(delete_session_template_handler),
+        )
+        .route("/replay", post(replay_handler))
+        .route("/export", get(export_handler))
+        .route("/export/finetune", get(export_finetune_handler))
+        .route("/media/:hash", get(media_handler))
+        .route("/maintenance/dedupe", post(dedupe_handler))
+        .route("/admin/reload", post(reload_handler))
+        .route("/audit", get(audit_handler))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            read_only_guard,
+        ))
+        .with_state(state)
+}
+
+/// Who the audit log records for a request. There are no accounts, so it is
+/// the client's address, plus its `X-Request-Id` or a generated one.
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Actor {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> std::result::Result<Self, Self::Rejection> {
+        let name = match parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+            Some(ConnectInfo(addr)) => format!("api:{}", addr.ip()),
+            None => "api".to_string(),
+        };
+        let request_id = parts
+            .headers
+            .get("x-request-id")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        Ok(Actor::new(name).with_request_id(request_id))
+    }
+}
+
+/// Turns away requests that could change something while the server is
+/// read-only. The setting is read per request, so a reload can flip it.
+async fn read_only_guard(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let safe = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    if safe || !state.config.load().server_read_only() {
+        return next.run(request).await;
+    }
+    (
+        StatusCode::FORBIDDEN,
+        Json(ApiResponse::<()>::error(format!(
+            "This server is read-only; {} {} is not allowed",
+            request.method(),
+            request.uri().path()
+        ))),
+    )
+        .into_response()
+}
+
 async fn index_handler() -> Html<&'static str> {
     Html(
         r#"<!DOCTYPE html>
@@ -144,6 +786,7 @@ async fn index_handler() -> Html<&'static str> {
     <div class="container">
         <h1>🤖 Grok Chat API</h1>
         <p>Welcome to the Grok Chat API! This is a local chat application that interfaces with xAI's Grok models.</p>
+        <p id="read-only" hidden><strong>🔒 Read-only:</strong> this server only answers GET requests, so sending messages and other changes are turned off.</p>
 
         <h2>Available Endpoints</h2>
 
@@ -152,15 +795,31 @@ async fn index_handler() -> Html<&'static str> {
             <p>Health check endpoint</p>
         </div>
 
+        <div class="endpoint">
+            <div class="method">GET /capabilities</div>
+            <p>What the server allows: <code>read_only</code> is true when every request but GET is refused with a <code>403</code></p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">POST /chat</div>
+            <p>Stateless chat completion (nothing is stored)</p>
+            <p><strong>Body:</strong> <code>{"message": "Hello, Grok!", "model": "grok-4-0709", "logprobs": true}</code></p>
+        </div>
+
         <div class="endpoint">
             <div class="method">GET /sessions</div>
             <p>List all chat sessions</p>
         </div>
 
         <div class="endpoint">
-            <div class="method">POST /sessions</div>
-            <p>Create a new chat session</p>
-            <p><strong>Body:</strong> <code>{"model": "grok-4-0709", "title": "My Chat"}</code></p>
+            <div class="method">POST /sessions?template=code-review</div>
+            <p>Create a new chat session; with <code>template</code>, it starts with the session template's system prompt and example messages; <code>agent</code> names the agent that answers its messages</p>
+            <p><strong>Body:</strong> <code>{"model": "grok-4-0709", "title": "My Chat", "agent": "reviewer"}</code></p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">POST /sessions/import?format=chatgpt|generic|archive</div>
+            <p>Import conversations from another tool's export, or a <code>GET /export</code> archive (the file is the body); already imported ones are skipped</p>
         </div>
 
         <div class="endpoint">
@@ -168,6 +827,12 @@ async fn index_handler() -> Html<&'static str> {
             <p>Get details of a specific session</p>
         </div>
 
+        <div class="endpoint">
+            <div class="method">PATCH /sessions/{session_id}</div>
+            <p>Choose the prompt preset the session's messages are sent with, or the agent that answers them; <code>null</code> clears either, and fields left out are kept</p>
+            <p><strong>Body:</strong> <code>{"prompt_preset": "reviewer", "agent": "summarizer"}</code></p>
+        </div>
+
         <div class="endpoint">
             <div class="method">GET /sessions/{session_id}/messages</div>
             <p>Get all messages in a session</p>
@@ -175,13 +840,137 @@ async fn index_handler() -> Html<&'static str> {
 
         <div class="endpoint">
             <div class="method">POST /sessions/{session_id}/messages</div>
-            <p>Send a message to a session</p>
-            <p><strong>Body:</strong> <code>{"message": "Hello, Grok!", "model": "grok-4-0709"}</code></p>
+            <p>Send a message to a session; <code>agent</code> answers with a configured agent instead of the session's default one. With <code>"stream": true</code> the reply arrives as server-sent events: <code>delta</code> events with its <code>content</code>, then <code>done</code> with the stored <code>message</code>, or <code>error</code>. A <code>: ping</code> comment keeps the connection open while the model thinks</p>
+            <p><strong>Body:</strong> <code>{"message": "Hello, Grok!", "model": "grok-4-0709", "agent": "reviewer"}</code></p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">PATCH /sessions/{session_id}/messages/{message_id}</div>
+            <p>Pin a message so it is always sent as context; omit <code>pinned</code> to toggle</p>
+            <p><strong>Body:</strong> <code>{"pinned": true}</code></p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">POST /sessions/{session_id}/messages/{message_id}/annotations</div>
+            <p>Rate, note or label a message for an evaluation dataset; fields left out keep their value. <code>GET</code> reads the annotation and <code>DELETE</code> removes it. Annotations are exported and imported with the message but never sent to the model</p>
+            <p><strong>Body:</strong> <code>{"rating": "down", "note": "Ignored the question", "labels": ["off-topic"]}</code></p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">GET /annotations/export?rating=down</div>
+            <p>Annotated messages as JSON Lines, each with the conversation before it, the message and its annotation; <code>rating</code> keeps only <code>up</code> or <code>down</code> ones</p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">GET /sessions/{session_id}/export?include_seeded=false</div>
+            <p>Download the session with its messages as JSON, leaving out the examples its template seeded unless <code>include_seeded=true</code>. The bytes stay the same until the session changes, so an interrupted download resumes with <code>Range</code>; send the <code>ETag</code> as <code>If-Match</code> to get <code>412</code> instead if it has changed since</p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">POST /sessions/{session_id}/share</div>
+            <p>Create a read-only link to the session, replacing any earlier one; <code>DELETE</code> revokes it</p>
+            <p><strong>Body (optional):</strong> <code>{"expires_in_hours": 24}</code></p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">POST /sessions/{session_id}/snapshots</div>
+            <p>Take a snapshot of the session as it is now; <code>GET</code> lists them</p>
+            <p><strong>Body:</strong> <code>{"label": "before the agent run"}</code></p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">POST /sessions/{session_id}/restore/{snapshot_id}</div>
+            <p>Roll the session back to a snapshot. Messages written since are moved to an archive, and later snapshots can no longer be restored (<code>409</code>)</p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">GET /shared/{token}</div>
+            <p>The shared session and its messages; <code>/shared/{token}/view</code> shows them as a page. Nothing else is reachable with the token</p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">GET /prompts</div>
+            <p>List named system prompt presets; <code>POST</code> creates one, <code>GET/PUT/DELETE /prompts/{name}</code> manage it</p>
+            <p><strong>Body:</strong> <code>{"name": "reviewer", "body": "You review code.", "is_default": false}</code></p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">GET /session-templates</div>
+            <p>List session templates, a system prompt plus example exchanges new sessions can start from; <code>POST</code> creates one, <code>GET/PUT/DELETE /session-templates/{name}</code> manage it</p>
+            <p><strong>Body:</strong> <code>{"name": "code-review", "system_prompt": "You review code.", "messages": [{"role": "user", "content": "..."}, {"role": "assistant", "content": "..."}]}</code></p>
         </div>
 
         <div class="endpoint">
             <div class="method">GET /models</div>
-            <p>List available Grok models</p>
+            <p>List available models across providers, each with its provider and profile (context window, output limit, supported parameters)</p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">GET /agents</div>
+            <p>List the configured agents, each with its model, system prompt, parameters and tools</p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">GET /usage</div>
+            <p>Token usage and estimated cost per model</p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">GET /stats</div>
+            <p>Session, message, token and cost totals, plus each model's success rate, p95 latency and circuit breaker state</p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">GET / POST /templates</div>
+            <p>List or create prompt templates with <code>{{variable}}</code> placeholders</p>
+            <p><strong>Body:</strong> <code>{"name": "review", "body": "Review this {{lang}} code: {{code}}"}</code></p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">GET / PUT / DELETE /templates/{name}</div>
+            <p>Fetch, replace or delete a template</p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">POST /templates/{name}/render</div>
+            <p>Fill a template; pass <code>session_id</code> to send the result into a session</p>
+            <p><strong>Body:</strong> <code>{"variables": {"lang": "Rust"}, "session_id": "..."}</code></p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">POST /replay</div>
+            <p>Re-send an exported session's prompts and score the new replies against the old ones</p>
+            <p><strong>Body:</strong> <code>{"export": {"session": {...}, "messages": [...]}, "model": "grok-3-mini", "fail_threshold": 0.8}</code></p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">GET /export?since=2025-01-01</div>
+            <p>Download every session (optionally only those updated since a date) with messages and usage as a <code>.tar.gz</code>; import it elsewhere with <code>format=archive</code></p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">GET /export/finetune?tag=golden&amp;rating=up&amp;since=2025-01-01&amp;until=2025-06-30&amp;per_turn=true</div>
+            <p>Stream conversations as OpenAI fine-tuning JSONL, one <code>{"messages": [...]}</code> line per session or, with <code>per_turn</code>, per exchange; seeded and system messages are left out and secrets redacted</p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">GET /media/{hash}</div>
+            <p>Stream an attached image by the SHA-256 of its bytes; the hash is its <code>ETag</code>, so <code>If-None-Match</code> gets a <code>304</code></p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">POST /maintenance/dedupe?dry_run=true&amp;similarity=0.8</div>
+            <p>Find sessions holding the same conversation and, unless <code>dry_run</code>, merge each group into its oldest session without losing a message</p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">POST /admin/reload</div>
+            <p>Re-read the configuration and apply default parameters, model aliases and prices without a restart. Lists what was <code>reloaded</code> and what is <code>restart_required</code>; an invalid configuration is rejected with a <code>422</code> and its <code>errors</code>, keeping the running one</p>
+        </div>
+
+        <div class="endpoint">
+            <div class="method">GET /audit?since=2025-01-01&amp;actor=api:127.0.0.1&amp;op=session&amp;limit=50&amp;offset=0</div>
+            <p>The append-only log of every change: when, by whom (the client address, or <code>cli:&lt;user&gt;</code>), the operation, the session and message it touched, and the <code>X-Request-Id</code> it came with. Newest first</p>
         </div>
 
         <h2>Terminal Usage</h2>
@@ -191,6 +980,16 @@ async fn index_handler() -> Html<&'static str> {
         <h2>Configuration</h2>
         <p>Set your xAI API key in the <code>XAI_API_KEY</code> environment variable.</p>
     </div>
+    <script>
+        // A read-only server refuses everything but GET, so only list those
+        fetch('/capabilities').then(r => r.json()).then(({ data }) => {
+            if (!data || !data.read_only) return;
+            document.getElementById('read-only').hidden = false;
+            for (const endpoint of document.querySelectorAll('.endpoint')) {
+                endpoint.hidden = !endpoint.querySelector('.method').textContent.startsWith('GET');
+            }
+        });
+    </script>
 </body>
 </html>"#,
     )
@@ -200,43 +999,166 @@ async fn health_handler() -> impl IntoResponse {
     Json(ApiResponse::success("OK"))
 }
 
-async fn list_sessions_handler(State(state): State<AppState>) -> impl IntoResponse {
-    match state.database.list_sessions(Some(50), Some(0)).await {
-        Ok(sessions) => Json(ApiResponse::success(sessions)).into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::<()>::error(e.to_string())),
-        )
-            .into_response(),
-    }
+async fn capabilities_handler(State(state): State<AppState>) -> impl IntoResponse {
+    Json(ApiResponse::success(ServerCapabilities {
+        read_only: state.config.load().server_read_only(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    }))
 }
 
-async fn create_session_handler(
+async fn chat_handler(
     State(state): State<AppState>,
-    Json(request): Json<CreateSessionRequest>,
+    Json(request): Json<ChatRequest>,
 ) -> impl IntoResponse {
-    let model = request.model.unwrap_or_else(|| "grok-4-0709".to_string());
-    let session = ChatSession::new(model, request.title);
-
-    match state.database.create_session(session.clone()).await {
-        Ok(_) => Json(ApiResponse::success(session)).into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::<()>::error(e.to_string())),
+    if request.stream.unwrap_or(false) {
+        return (
+            StatusCode::NOT_IMPLEMENTED,
+            Json(ApiResponse::<()>::error(
+                "Streaming not supported in this endpoint".to_string(),
+            )),
+        )
+            .into_response();
+    }
+
+    let model = match resolve_model(&state, Some(&request.model)) {
+        Ok(model) => model,
+        Err((status, error)) => {
+            return (status, Json(ApiResponse::<()>::error(error))).into_response();
+        }
+    };
+    let logprobs = request.logprobs.unwrap_or(false);
+    let mut api_request = ApiChatRequest {
+        messages: Conversation::new().user(request.message).into_messages(),
+        model: model.clone(),
+        max_tokens: request.max_tokens,
+        temperature: request.temperature,
+        stream: Some(false),
+        system_prompt: request.system_prompt,
+        logprobs,
+        top_logprobs: if logprobs {
+            request.top_logprobs.unwrap_or(0)
+        } else {
+            0
+        },
+    };
+    if request.dry_run {
+        let dry_run = state.chat_service().dry_run(api_request);
+        return Json(ApiResponse::success(dry_run)).into_response();
+    }
+    let redactions = state.chat_service().redact(&mut api_request);
+    let warnings = state.chat_service().fit_to_model(&mut api_request);
+    let estimated_cost = match guard_cost(&state, &api_request, request.confirm_cost) {
+        Ok(estimate) => estimate,
+        Err((status, error)) => {
+            return (status, Json(ApiResponse::<()>::error(error))).into_response();
+        }
+    };
+
+    let config = state.config.load();
+    match state
+        .chat_service()
+        .send_request_migrating(api_request, None, &config)
+        .await
+    {
+        Ok((ChatResponse::Complete(response), migration)) => {
+            let content = response
+                .get_content()
+                .unwrap_or_else(|_| "No response content".to_string());
+            let model = migration.as_ref().map_or(model, |m| m.to.clone());
+            if let Some(usage) = response.get_usage() {
+                if let Err(e) = state
+                    .database
+                    .record_usage(None, &model, usage, &response.completion_stats())
+                    .await
+                {
+                    eprintln!("Failed to record usage: {}", e);
+                }
+            }
+            let result = ChatCompletionResult {
+                content,
+                model: response.model.clone(),
+                finish_reason: response.get_finish_reason(),
+                usage: response.get_usage().cloned(),
+                redactions,
+                logprobs: response.get_logprobs().map(|logprobs| logprobs.to_vec()),
+                warnings,
+                effects: response.effects.clone(),
+                estimated_cost,
+                cost: response
+                    .get_usage()
+                    .map(|usage| state.pricing.load().estimate_cost(usage, &model)),
+                migration,
+            };
+            Json(ApiResponse::success(result)).into_response()
+        }
+        Ok((ChatResponse::Stream(_), _)) => (
+            StatusCode::NOT_IMPLEMENTED,
+            Json(ApiResponse::<()>::error(
+                "Streaming not supported in this endpoint".to_string(),
+            )),
         )
             .into_response(),
+        Err(e) => {
+            let (status, error) = upstream_error(e);
+            (status, Json(ApiResponse::<()>::error(error))).into_response()
+        }
     }
 }
 
-async fn get_session_handler(
+/// Replays synchronously, one turn after another. A report that misses its
+/// `fail_threshold` is still a 200; check `passed`. A turn over the cost
+/// threshold stops the replay with a 402 unless `confirm_cost` is set.
+async fn replay_handler(
     State(state): State<AppState>,
-    Path(session_id): Path<String>,
+    Json(request): Json<ReplayRequest>,
 ) -> impl IntoResponse {
-    match state.database.get_session(&session_id).await {
-        Ok(Some(session)) => Json(ApiResponse::success(session)).into_response(),
-        Ok(None) => (
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse::<()>::error("Session not found".to_string())),
+    let model = request
+        .model
+        .as_deref()
+        .unwrap_or(&request.export.session.model);
+    let model = match resolve_model(&state, Some(model)) {
+        Ok(model) => model,
+        Err((status, error)) => {
+            return (status, Json(ApiResponse::<()>::error(error))).into_response();
+        }
+    };
+    let config = state.config.load();
+    let options = ReplayOptions {
+        model,
+        system_prompt: config.system_prompt().to_string(),
+        max_tokens: request.max_tokens,
+        temperature: request.temperature,
+        fail_threshold: request.fail_threshold,
+        cost_guard: Some(CostGuard::from_config(&config)),
+        confirm_cost: request.confirm_cost,
+    };
+
+    match replay(
+        &state.chat_service(),
+        &state.pricing.load(),
+        &request.export,
+        &options,
+    )
+    .await
+    {
+        Ok(report) => {
+            for usage in report.turns.iter().filter_map(|turn| turn.usage.as_ref()) {
+                if let Err(e) = state
+                    .database
+                    .record_usage(None, &report.model, usage, &CompletionStats::default())
+                    .await
+                {
+                    eprintln!("Failed to record usage: {}", e);
+                }
+            }
+            Json(ApiResponse::success(report)).into_response()
+        }
+        Err(e) if e.is::<CostNotConfirmed>() => (
+            StatusCode::PAYMENT_REQUIRED,
+            Json(ApiResponse::<()>::error(format!(
+                "{}; set confirm_cost to send it anyway",
+                e
+            ))),
         )
             .into_response(),
         Err(e) => (
@@ -247,12 +1169,80 @@ async fn get_session_handler(
     }
 }
 
-async fn get_messages_handler(
+async fn list_sessions_handler(State(state): State<AppState>) -> impl IntoResponse {
+    match state.database.list_sessions(Some(50), Some(0)).await {
+        Ok(sessions) => Json(ApiResponse::success(sessions)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn create_session_handler(
     State(state): State<AppState>,
-    Path(session_id): Path<String>,
+    actor: Actor,
+    Query(query): Query<CreateSessionQuery>,
+    Json(request): Json<CreateSessionRequest>,
 ) -> impl IntoResponse {
-    match state.database.get_messages(&session_id).await {
-        Ok(messages) => Json(ApiResponse::success(messages)).into_response(),
+    let agent = match request
+        .agent
+        .as_deref()
+        .map(|name| find_agent(&state, name))
+    {
+        Some(Ok(agent)) => Some(agent),
+        Some(Err((status, error))) => {
+            return (status, Json(ApiResponse::<()>::error(error))).into_response();
+        }
+        None => None,
+    };
+    let model = request
+        .model
+        .as_deref()
+        .or(agent.as_ref().map(|agent| agent.model.as_str()));
+    let model = match resolve_model(&state, model) {
+        Ok(model) => model,
+        Err((status, error)) => {
+            return (status, Json(ApiResponse::<()>::error(error))).into_response();
+        }
+    };
+    let mut session = ChatSession::new(model, request.title);
+    session.agent = agent.map(|agent| agent.name);
+    if let Some(name) = request.prompt_preset {
+        if let Err((status, error)) = check_preset(&state, &name).await {
+            return (status, Json(ApiResponse::<()>::error(error))).into_response();
+        }
+        session.prompt_preset = Some(name);
+    }
+
+    let created = match query.template {
+        Some(name) => {
+            let template = match state.database.resolve_session_template(&name).await {
+                Ok(template) => template,
+                Err(e) => {
+                    let status = if e.is::<UnknownSessionTemplate>() {
+                        StatusCode::BAD_REQUEST
+                    } else {
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    };
+                    return (status, Json(ApiResponse::<()>::error(e.to_string()))).into_response();
+                }
+            };
+            state
+                .database
+                .create_seeded_session(&session, &template, &actor)
+                .await
+                .map(drop)
+        }
+        None => state
+            .database
+            .create_session_audited(session.clone(), &actor)
+            .await
+            .map(drop),
+    };
+    match created {
+        Ok(()) => Json(ApiResponse::success(session)).into_response(),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ApiResponse::<()>::error(e.to_string())),
@@ -261,14 +1251,149 @@ async fn get_messages_handler(
     }
 }
 
-async fn send_message_handler(
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    pub format: ImportFormat,
+}
+
+/// Large enough for an archive of a long history; other bodies keep axum's
+/// 2 MB default.
+const IMPORT_BODY_LIMIT: usize = 256 * 1024 * 1024;
+
+/// The body is the export file itself.
+async fn import_sessions_handler(
     State(state): State<AppState>,
-    Path(session_id): Path<String>,
-    Json(request): Json<SendMessageRequest>,
+    Query(query): Query<ImportQuery>,
+    body: Bytes,
 ) -> impl IntoResponse {
-    // Get existing messages for context
-    let existing_messages = match state.database.get_messages(&session_id).await {
-        Ok(msgs) => msgs,
+    let bad_request = |message: String| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(message)),
+        )
+            .into_response()
+    };
+
+    let imported = if query.format == ImportFormat::Archive {
+        match read_tar_gz(body.as_ref()) {
+            Ok(parsed) => import_archive(&state.database, parsed).await,
+            Err(e) => return bad_request(format!("{:#}", e)),
+        }
+    } else {
+        let Ok(json) = std::str::from_utf8(&body) else {
+            return bad_request("The export is not valid UTF-8".to_string());
+        };
+        match parse_export(query.format, json) {
+            Ok(parsed) => import_sessions(&state.database, parsed).await,
+            Err(e) => return bad_request(format!("{:#}", e)),
+        }
+    };
+    match imported {
+        Ok(report) => Json(ApiResponse::success(report)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(format!("{:#}", e))),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    pub since: Option<String>,
+}
+
+/// Streams a `.tar.gz` of every session as it is written. Sessions that fail
+/// to serialize are listed under `skipped` in its `index.json`; an error after
+/// the download has started can only cut it short.
+async fn export_handler(
+    State(state): State<AppState>,
+    Query(query): Query<ExportQuery>,
+) -> impl IntoResponse {
+    let since = match query.since.as_deref().map(parse_since).transpose() {
+        Ok(since) => since,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::<()>::error(format!("since: {}", e))),
+            )
+                .into_response();
+        }
+    };
+
+    let (sender, receiver) = mpsc::channel(4);
+    let database = state.database.clone();
+    tokio::spawn(async move {
+        if let Err(e) = export_archive(&database, since, ArchiveWriter::streaming(sender)).await {
+            eprintln!("Archive export failed: {:#}", e);
+        }
+    });
+
+    let filename = format!(
+        "grok-sessions-{}.tar.gz",
+        chrono::Utc::now().format("%Y%m%d")
+    );
+    (
+        [
+            (header::CONTENT_TYPE, "application/gzip".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        Body::from_stream(ReceiverStream::new(receiver).map(Ok::<_, std::io::Error>)),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize, Default)]
+pub struct FinetuneQuery {
+    /// Only sessions with a message carrying this annotation label.
+    pub tag: Option<String>,
+    pub rating: Option<Rating>,
+    pub since: Option<String>,
+    /// Inclusive: a date takes in the whole day.
+    pub until: Option<String>,
+    #[serde(default)]
+    pub per_turn: bool,
+}
+
+/// Streams sessions as fine-tuning JSONL, a session's examples at a time,
+/// chosen and redacted as by `sessions export-finetune`. An error after the
+/// download has started can only cut it short.
+async fn export_finetune_handler(
+    State(state): State<AppState>,
+    Query(query): Query<FinetuneQuery>,
+) -> impl IntoResponse {
+    let bad_date = |field: &str, e: String| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(format!("{}: {}", field, e))),
+        )
+            .into_response()
+    };
+    let since = match query.since.as_deref().map(parse_since).transpose() {
+        Ok(since) => since,
+        Err(e) => return bad_date("since", e),
+    };
+    let until = match query.until.as_deref().map(parse_until).transpose() {
+        Ok(until) => until,
+        Err(e) => return bad_date("until", e),
+    };
+    let filter = FinetuneFilter {
+        tag: query.tag,
+        rating: query.rating,
+        since,
+        until,
+        per_turn: query.per_turn,
+    };
+
+    let export = async {
+        let redactor = Redactor::from_config(&state.config.load())?;
+        FinetuneExport::open(state.database.clone(), filter, redactor).await
+    };
+    let mut export = match export.await {
+        Ok(export) => export,
         Err(e) => {
             return (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -278,57 +1403,265 @@ async fn send_message_handler(
         }
     };
 
-    // Convert to API messages
-    let mut api_messages: Vec<ApiMessage> = existing_messages
-        .into_iter()
-        .map(|msg| ApiMessage {
-            role: msg.role.to_string(),
-            content: msg.content,
-        })
-        .collect();
-
-    // Add the new user message
-    api_messages.push(ApiMessage {
-        role: "user".to_string(),
-        content: request.message.clone(),
+    let (sender, receiver) = mpsc::channel(4);
+    tokio::spawn(async move {
+        loop {
+            match export.next().await {
+                Ok(Some(lines)) => {
+                    if sender.send(lines).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    eprintln!("Fine-tuning export failed: {:#}", e);
+                    return;
+                }
+            }
+        }
     });
 
-    // Save user message to database
-    let user_message = Message::user(session_id.clone(), request.message);
-    if let Err(e) = state.database.create_message(user_message).await {
-        return (
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(ReceiverStream::new(receiver).map(Ok::<_, std::io::Error>)),
+    )
+        .into_response()
+}
+
+/// Streams a blob from the media store. Content never changes under its hash,
+/// so the hash is the ETag and clients may cache the response for good.
+async fn media_handler(
+    State(state): State<AppState>,
+    Path(hash): Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let not_found = || {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error("Media not found".to_string())),
+        )
+            .into_response()
+    };
+    let failed = |e: anyhow::Error| {
+        (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ApiResponse::<()>::error(e.to_string())),
         )
-            .into_response();
+            .into_response()
+    };
+
+    let media = match state.database.find_media(&hash).await {
+        Ok(Some(media)) => media,
+        Ok(None) => return not_found(),
+        Err(e) => return failed(e),
+    };
+    let etag = format!("\"{}\"", media.hash);
+    let caching = [
+        (header::ETAG, etag.clone()),
+        (
+            header::CACHE_CONTROL,
+            "public, max-age=31536000, immutable".to_string(),
+        ),
+    ];
+    if etag_matches(headers.get(header::IF_NONE_MATCH), &etag) {
+        return (StatusCode::NOT_MODIFIED, caching).into_response();
     }
 
-    // Send to Grok API
-    let model = request.model.unwrap_or_else(|| "grok-4-0709".to_string());
-    match state
-        .chat_service
-        .send_message(api_messages, model, Some(2048), Some(0.7), false)
+    match MediaStore::new(state.config.load().media_dir())
+        .open(&media.hash)
         .await
     {
-        Ok(ChatResponse::Complete(response)) => {
-            let content = response
-                .get_content()
-                .unwrap_or_else(|_| "No response content".to_string());
+        Ok(Some((file, size))) => (
+            caching,
+            [
+                (header::CONTENT_TYPE, media.mime_type),
+                (header::CONTENT_LENGTH, size.to_string()),
+            ],
+            Body::from_stream(tokio_util::io::ReaderStream::new(file)),
+        )
+            .into_response(),
+        Ok(None) => not_found(),
+        Err(e) => failed(e),
+    }
+}
 
-            // Save assistant response to database
-            let assistant_message =
-                Message::assistant(session_id.clone(), content.clone(), Some(model));
-            if let Err(e) = state.database.create_message(assistant_message).await {
-                eprintln!("Failed to save assistant message: {}", e);
+/// Whether an `If-None-Match` header names `etag`. Weak validators match too,
+/// as the header only asks whether the client's copy is still good.
+fn etag_matches(if_none_match: Option<&HeaderValue>, etag: &str) -> bool {
+    let Some(value) = if_none_match.and_then(|value| value.to_str().ok()) else {
+        return false;
+    };
+    value.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
+
+/// What a `Range` header asks of a representation `total` bytes long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ByteRange {
+    /// No range, or one this server answers with everything: other units,
+    /// several ranges or a malformed one, as RFC 9110 allows.
+    Whole,
+    /// Bytes `first..=last`.
+    Part(u64, u64),
+    /// Starts past the end.
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    fn parse(header: Option<&str>, total: u64) -> Self {
+        let Some(spec) = header.and_then(|header| header.trim().strip_prefix("bytes=")) else {
+            return ByteRange::Whole;
+        };
+        let Some((first, last)) = spec.trim().split_once('-').filter(|_| !spec.contains(','))
+        else {
+            return ByteRange::Whole;
+        };
+        let (first, last) = match (first.parse::<u64>(), last.parse::<u64>()) {
+            // The final `last` bytes
+            (Err(_), Ok(suffix)) if first.is_empty() => {
+                if suffix == 0 || total == 0 {
+                    return ByteRange::Unsatisfiable;
+                }
+                (total.saturating_sub(suffix), total - 1)
             }
+            (Ok(first), Err(_)) if last.is_empty() => (first, total.saturating_sub(1)),
+            (Ok(first), Ok(last)) if first <= last => (first, last.min(total.saturating_sub(1))),
+            _ => return ByteRange::Whole,
+        };
+        if first >= total {
+            return ByteRange::Unsatisfiable;
+        }
+        ByteRange::Part(first, last)
+    }
+}
 
-            Json(ApiResponse::success(content)).into_response()
+/// Streams a session and its messages as the JSON of `sessions export
+/// --format json`, read a page at a time. Downloads resume with `Range`;
+/// `If-Match` or `If-Range` with the `ETag` makes sure the pieces are of the
+/// same export. A session that changes while it is read answers 412, or cuts
+/// a started download short. Seeded messages are left out unless
+/// `include_seeded=true`.
+async fn export_session_handler(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+    Query(query): Query<ExportSessionQuery>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let failed = |e: anyhow::Error| {
+        let status = if e.is::<SessionChanged>() {
+            StatusCode::PRECONDITION_FAILED
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR
+        };
+        (status, Json(ApiResponse::<()>::error(e.to_string()))).into_response()
+    };
+    let open = || async {
+        match ExportChunks::open(state.database.clone(), &session_id, query.include_seeded).await {
+            Ok(Some(chunks)) => Ok(chunks),
+            Ok(None) => Err((
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::<()>::error("Session not found".to_string())),
+            )
+                .into_response()),
+            Err(e) => Err(failed(e)),
         }
-        Ok(ChatResponse::Stream(_)) => (
-            StatusCode::NOT_IMPLEMENTED,
-            Json(ApiResponse::<()>::error(
-                "Streaming not supported in this endpoint".to_string(),
-            )),
+    };
+    // Resuming needs the same bytes, so weak validators never match
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+    };
+    let names = |value: &str, etag: &str| {
+        value
+            .split(',')
+            .map(str::trim)
+            .any(|candidate| candidate == "*" || candidate == etag)
+    };
+
+    let measured = match open().await {
+        Ok(chunks) => chunks,
+        Err(response) => return response,
+    };
+    let etag = measured.etag();
+    if header(header::IF_MATCH).is_some_and(|value| !names(value, &etag)) {
+        return (
+            StatusCode::PRECONDITION_FAILED,
+            [(header::ETAG, etag)],
+            Json(ApiResponse::<()>::error(format!(
+                "Session {} has changed since that ETag; download it again",
+                session_id
+            ))),
+        )
+            .into_response();
+    }
+    if etag_matches(headers.get(header::IF_NONE_MATCH), &etag) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+    let total = match measured.byte_len().await {
+        Ok(total) => total,
+        Err(e) => return failed(e),
+    };
+    let range = match header(header::IF_RANGE) {
+        Some(value) if value != etag => ByteRange::Whole,
+        _ => ByteRange::parse(header(header::RANGE), total),
+    };
+
+    let chunks = match open().await {
+        Ok(chunks) if chunks.etag() == etag => chunks,
+        Ok(_) => return failed(SessionChanged { session_id }.into()),
+        Err(response) => return response,
+    };
+    let (status, first, last) = match range {
+        ByteRange::Whole => (StatusCode::OK, 0, total.saturating_sub(1)),
+        ByteRange::Part(first, last) => (StatusCode::PARTIAL_CONTENT, first, last),
+        ByteRange::Unsatisfiable => {
+            return (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [
+                    (header::ETAG, etag),
+                    (header::CONTENT_RANGE, format!("bytes */{}", total)),
+                ],
+            )
+                .into_response();
+        }
+    };
+    let (sender, receiver) = mpsc::channel(4);
+    tokio::spawn(chunks.send_range(first, last, sender));
+
+    let mut response = (
+        status,
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"session-{}.json\"", session_id),
+            ),
+            (header::ETAG, etag),
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+            (header::CONTENT_LENGTH, (last + 1 - first).to_string()),
+        ],
+        Body::from_stream(ReceiverStream::new(receiver)),
+    )
+        .into_response();
+    if status == StatusCode::PARTIAL_CONTENT {
+        if let Ok(value) = HeaderValue::from_str(&format!("bytes {}-{}/{}", first, last, total)) {
+            response.headers_mut().insert(header::CONTENT_RANGE, value);
+        }
+    }
+    response
+}
+
+async fn get_session_handler(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+) -> impl IntoResponse {
+    match state.database.get_session(&session_id).await {
+        Ok(Some(session)) => Json(ApiResponse::success(session)).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error("Session not found".to_string())),
         )
             .into_response(),
         Err(e) => (
@@ -339,9 +1672,43 @@ async fn send_message_handler(
     }
 }
 
-async fn list_models_handler(State(state): State<AppState>) -> impl IntoResponse {
-    match state.chat_service.list_available_models().await {
-        Ok(models) => Json(ApiResponse::success(models)).into_response(),
+async fn update_session_handler(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+    Json(request): Json<UpdateSessionRequest>,
+) -> impl IntoResponse {
+    if let Some(Some(name)) = &request.prompt_preset {
+        if let Err((status, error)) = check_preset(&state, name).await {
+            return (status, Json(ApiResponse::<()>::error(error))).into_response();
+        }
+    }
+    if let Some(Some(name)) = &request.agent {
+        if let Err((status, error)) = find_agent(&state, name) {
+            return (status, Json(ApiResponse::<()>::error(error))).into_response();
+        }
+    }
+    let updated = async {
+        if let Some(name) = &request.prompt_preset {
+            state
+                .database
+                .set_session_prompt(&session_id, name.as_deref())
+                .await?;
+        }
+        if let Some(name) = &request.agent {
+            state
+                .database
+                .set_session_agent(&session_id, name.as_deref())
+                .await?;
+        }
+        state.database.get_session(&session_id).await
+    };
+    match updated.await {
+        Ok(Some(session)) => Json(ApiResponse::success(session)).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error("Session not found".to_string())),
+        )
+            .into_response(),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ApiResponse::<()>::error(e.to_string())),
@@ -350,47 +1717,3943 @@ async fn list_models_handler(State(state): State<AppState>) -> impl IntoResponse
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_api_response_success() {
-        let response = ApiResponse::success("test data");
-        assert!(response.success);
-        assert_eq!(response.data, Some("test data"));
-        assert_eq!(response.error, None);
-    }
+/// Unknown preset names are the client's mistake, so they answer 400 with the
+/// saved names.
+async fn check_preset(
+    state: &AppState,
+    name: &str,
+) -> std::result::Result<PromptPreset, (StatusCode, String)> {
+    state.database.resolve_prompt(name).await.map_err(|e| {
+        let status = if e.is::<UnknownPreset>() {
+            StatusCode::BAD_REQUEST
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR
+        };
+        (status, e.to_string())
+    })
+}
 
-    #[test]
-    fn test_api_response_error() {
-        let response: ApiResponse<()> = ApiResponse::error("test error".to_string());
-        assert!(!response.success);
-        assert_eq!(response.data, None);
-        assert_eq!(response.error, Some("test error".to_string()));
+async fn get_messages_handler(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+    Query(query): Query<MessagesQuery>,
+) -> impl IntoResponse {
+    let messages = if query.include_superseded {
+        state
+            .database
+            .get_messages_with_superseded(&session_id)
+            .await
+    } else {
+        state.database.get_messages(&session_id).await
+    };
+    match messages {
+        Ok(messages) => Json(ApiResponse::success(messages)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
     }
+}
 
-    #[test]
+async fn update_message_handler(
+    State(state): State<AppState>,
+    Path((session_id, message_id)): Path<(String, i64)>,
+    body: Bytes,
+) -> impl IntoResponse {
+    let request = if body.is_empty() {
+        UpdateMessageRequest::default()
+    } else {
+        match serde_json::from_slice::<UpdateMessageRequest>(&body) {
+            Ok(request) => request,
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::<()>::error(e.to_string())),
+                )
+                    .into_response();
+            }
+        }
+    };
+    match state
+        .database
+        .set_message_pinned(&session_id, message_id, request.pinned)
+        .await
+    {
+        Ok(Some(message)) => Json(ApiResponse::success(message)).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error("Message not found".to_string())),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+fn message_not_found() -> axum::response::Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiResponse::<()>::error("Message not found".to_string())),
+    )
+        .into_response()
+}
+
+async fn get_annotation_handler(
+    State(state): State<AppState>,
+    Path((session_id, message_id)): Path<(String, i64)>,
+) -> impl IntoResponse {
+    match state
+        .database
+        .message_annotation(&session_id, message_id)
+        .await
+    {
+        Ok(Some(annotation)) => Json(ApiResponse::success(annotation)).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(
+                "Message not annotated".to_string(),
+            )),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+/// Rates, notes or labels a message, on top of what it already had. An
+/// annotation left with nothing in it is removed.
+async fn annotate_handler(
+    State(state): State<AppState>,
+    Path((session_id, message_id)): Path<(String, i64)>,
+    Json(request): Json<AnnotateRequest>,
+) -> impl IntoResponse {
+    let result = async {
+        let current = state
+            .database
+            .message_annotation(&session_id, message_id)
+            .await?
+            .unwrap_or_default();
+        let annotation = Annotation {
+            rating: request.rating.or(current.rating),
+            note: request.note.or(current.note),
+            labels: request.labels.unwrap_or(current.labels),
+        }
+        .normalized();
+        let found = state
+            .database
+            .set_annotation(&session_id, message_id, &annotation)
+            .await?;
+        Ok::<_, anyhow::Error>(found.then_some(annotation))
+    }
+    .await;
+    match result {
+        Ok(Some(annotation)) => Json(ApiResponse::success(annotation)).into_response(),
+        Ok(None) => message_not_found(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn delete_annotation_handler(
+    State(state): State<AppState>,
+    Path((session_id, message_id)): Path<(String, i64)>,
+) -> impl IntoResponse {
+    match state
+        .database
+        .set_annotation(&session_id, message_id, &Annotation::default())
+        .await
+    {
+        Ok(true) => Json(ApiResponse::success(())).into_response(),
+        Ok(false) => message_not_found(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+/// Annotated messages as JSON Lines, each with the conversation before it.
+async fn export_annotations_handler(
+    State(state): State<AppState>,
+    Query(query): Query<AnnotationExportQuery>,
+) -> impl IntoResponse {
+    let jsonl = async { to_jsonl(&annotated_examples(&state.database, query.rating).await?) };
+    match jsonl.await {
+        Ok(body) => ([(header::CONTENT_TYPE, "application/x-ndjson")], body).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+/// Replaces the session's last reply with a new one, answering with both
+/// and the diff between them.
+async fn regenerate_handler(
+    State(state): State<AppState>,
+    actor: Actor,
+    Path((session_id, message_id)): Path<(String, i64)>,
+    body: Bytes,
+) -> impl IntoResponse {
+    let request = if body.is_empty() {
+        RegenerateRequest::default()
+    } else {
+        match serde_json::from_slice::<RegenerateRequest>(&body) {
+            Ok(request) => request,
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::<()>::error(e.to_string())),
+                )
+                    .into_response();
+            }
+        }
+    };
+    match regenerate_in_session(&state, &session_id, message_id, request, &actor).await {
+        Ok(reply) => Json(ApiResponse::success(reply)).into_response(),
+        Err((status, error)) => (status, Json(ApiResponse::<()>::error(error))).into_response(),
+    }
+}
+
+/// A reply against the newest generation it replaced.
+async fn message_diff_handler(
+    State(state): State<AppState>,
+    Path((session_id, message_id)): Path<(String, i64)>,
+) -> impl IntoResponse {
+    let found = async {
+        let message = state
+            .database
+            .get_messages(&session_id)
+            .await?
+            .into_iter()
+            .find(|message| message.id == message_id);
+        let Some(message) = message else {
+            return Ok(None);
+        };
+        let previous = state
+            .database
+            .previous_generations(&session_id, message_id)
+            .await?
+            .pop();
+        Ok::<_, anyhow::Error>(previous.map(|previous| ReplyDiff::new(message, previous)))
+    };
+    match found.await {
+        Ok(Some(reply)) => Json(ApiResponse::success(reply)).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(
+                "Message not found or never regenerated".to_string(),
+            )),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+/// Gives the session a new read-only link; an earlier one stops working.
+async fn share_session_handler(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+    body: Bytes,
+) -> impl IntoResponse {
+    let request = if body.is_empty() {
+        ShareSessionRequest::default()
+    } else {
+        match serde_json::from_slice::<ShareSessionRequest>(&body) {
+            Ok(request) => request,
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::<()>::error(e.to_string())),
+                )
+                    .into_response();
+            }
+        }
+    };
+    if request.expires_in_hours == Some(0) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(
+                "expires_in_hours must be at least 1".to_string(),
+            )),
+        )
+            .into_response();
+    }
+
+    let shared = async {
+        if state.database.get_session(&session_id).await?.is_none() {
+            return Ok(None);
+        }
+        let expires_at = request
+            .expires_in_hours
+            .map(|hours| chrono::Utc::now() + chrono::Duration::hours(hours.into()));
+        let share = SessionShare::new(session_id.clone(), expires_at);
+        state.database.create_share(&share).await?;
+        Ok::<_, anyhow::Error>(Some(share))
+    };
+    match shared.await {
+        Ok(Some(share)) => {
+            let link = ShareLink {
+                url: format!("/shared/{}", share.token),
+                view_url: format!("/shared/{}/view", share.token),
+                share,
+            };
+            (StatusCode::CREATED, Json(ApiResponse::success(link))).into_response()
+        }
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error("Session not found".to_string())),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn revoke_share_handler(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+) -> impl IntoResponse {
+    match state.database.revoke_share(&session_id).await {
+        Ok(true) => Json(ApiResponse::success("revoked")).into_response(),
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(
+                "Session is not shared".to_string(),
+            )),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn create_snapshot_handler(
+    State(state): State<AppState>,
+    actor: Actor,
+    Path(session_id): Path<String>,
+    Json(request): Json<CreateSnapshotRequest>,
+) -> impl IntoResponse {
+    let label = request.label.trim();
+    if label.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(
+                "label must not be empty".to_string(),
+            )),
+        )
+            .into_response();
+    }
+    // Taken between sends, so the snapshot never splits an exchange
+    let _guard = state.session_locks.lock(&session_id).await;
+    match state
+        .database
+        .create_snapshot(&session_id, label, &actor)
+        .await
+    {
+        Ok(Some(snapshot)) => (
+            StatusCode::CREATED,
+            Json(ApiResponse::<SessionSnapshot>::success(snapshot)),
+        )
+            .into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error("Session not found".to_string())),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn list_snapshots_handler(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+) -> impl IntoResponse {
+    let snapshots = async {
+        if state.database.get_session(&session_id).await?.is_none() {
+            return Ok(None);
+        }
+        state.database.snapshots(&session_id).await.map(Some)
+    };
+    match snapshots.await {
+        Ok(Some(snapshots)) => {
+            Json(ApiResponse::<Vec<SessionSnapshot>>::success(snapshots)).into_response()
+        }
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error("Session not found".to_string())),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+/// Rolls a session back to a snapshot. A snapshot an earlier restore went
+/// back past is a 409.
+async fn restore_snapshot_handler(
+    State(state): State<AppState>,
+    actor: Actor,
+    Path((session_id, snapshot_id)): Path<(String, i64)>,
+) -> impl IntoResponse {
+    let _guard = state.session_locks.lock(&session_id).await;
+    let restored = state
+        .database
+        .restore_snapshot(&session_id, snapshot_id, &actor)
+        .await;
+    match restored {
+        Ok(Some(report)) => {
+            state.sessions.write().await.remove(&session_id);
+            Json(ApiResponse::<RestoreReport>::success(report)).into_response()
+        }
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error("Snapshot not found".to_string())),
+        )
+            .into_response(),
+        Err(e) => {
+            let status = if e.is::<SnapshotRemoved>() {
+                StatusCode::CONFLICT
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            (status, Json(ApiResponse::<()>::error(e.to_string()))).into_response()
+        }
+    }
+}
+
+async fn shared_session_handler(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> impl IntoResponse {
+    match state.database.shared_session(&token).await {
+        Ok(Some(export)) => Json(ApiResponse::success(export)).into_response(),
+        Ok(None) => share_not_found(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn shared_session_view_handler(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> impl IntoResponse {
+    match state.database.shared_session(&token).await {
+        Ok(Some(export)) => Html(export.to_html()).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Html("<!DOCTYPE html><title>Not found</title><p>This link has expired or was revoked.</p>"),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+/// Unknown, expired and revoked tokens look the same.
+fn share_not_found() -> axum::response::Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiResponse::<()>::error(
+            "Share link not found; it may have expired or been revoked".to_string(),
+        )),
+    )
+        .into_response()
+}
+
+async fn send_message_handler(
+    State(state): State<AppState>,
+    actor: Actor,
+    Path(session_id): Path<String>,
+    Json(request): Json<SendMessageRequest>,
+) -> impl IntoResponse {
+    let agent = match session_agent(&state, &session_id, request.agent.as_deref()).await {
+        Ok(agent) => agent,
+        Err((status, error)) => {
+            return (status, Json(ApiResponse::<()>::error(error))).into_response();
+        }
+    };
+    let model = request
+        .model
+        .as_deref()
+        .or(agent.as_ref().map(|agent| agent.model.as_str()));
+    let model = match resolve_model(&state, model) {
+        Ok(model) => model,
+        Err((status, error)) => {
+            return (status, Json(ApiResponse::<()>::error(error))).into_response();
+        }
+    };
+    let options = SendOptions {
+        confirm_cost: request.confirm_cost,
+        shed_context: request.shed_context,
+        agent,
+        ..SendOptions::default()
+    };
+    if request.dry_run {
+        return match session_request(&state, &session_id, request.message, model, &options).await {
+            Ok((api_request, dropped)) => Json(ApiResponse::success(
+                state
+                    .chat_service
+                    .dry_run(api_request)
+                    .with_dropped(dropped),
+            ))
+            .into_response(),
+            Err((status, error)) => (status, Json(ApiResponse::<()>::error(error))).into_response(),
+        };
+    }
+    if request.stream {
+        let timing = StreamTiming::from_config(&state.config.load());
+        return stream_to_session(
+            state,
+            session_id,
+            request.message,
+            model,
+            options,
+            actor,
+            timing,
+        )
+        .await;
+    }
+    match send_to_session(&state, &session_id, request.message, model, options, &actor).await {
+        Ok(SessionReply::Sent(reply)) => {
+            let mut headers = HeaderMap::new();
+            let mut header = |name, value: String| {
+                if let Ok(value) = HeaderValue::from_str(&value) {
+                    headers.insert(name, value);
+                }
+            };
+            if let Some(migration) = &reply.migration {
+                header(MIGRATION_HEADER, migration.to_string());
+            }
+            if reply.shed_messages > 0 {
+                header(CONTEXT_SHED_HEADER, reply.shed_messages.to_string());
+            }
+            if reply.redactions > 0 {
+                header(REDACTIONS_HEADER, reply.redactions.to_string());
+            }
+            if let Some(usd) = reply.estimated_cost.and_then(|e| e.cost.total_usd()) {
+                header(ESTIMATED_COST_HEADER, format!("{:.6}", usd));
+            }
+            if let Some(usd) = reply.cost.and_then(|cost| cost.total_usd()) {
+                header(COST_HEADER, format!("{:.6}", usd));
+            }
+            for effect in &reply.effects {
+                if let Ok(value) = HeaderValue::from_str(effect) {
+                    headers.append(EFFECT_HEADER, value);
+                }
+            }
+            (headers, Json(ApiResponse::success(reply.content))).into_response()
+        }
+        Ok(SessionReply::Queued(message)) => {
+            (StatusCode::ACCEPTED, Json(ApiResponse::success(message))).into_response()
+        }
+        Err((status, error)) => (status, Json(ApiResponse::<()>::error(error))).into_response(),
+    }
+}
+
+/// Resolves a requested model name or alias, defaulting to the configured
+/// model. Unknown aliases are the client's mistake, so they answer 400.
+fn resolve_model(
+    state: &AppState,
+    name: Option<&str>,
+) -> std::result::Result<String, (StatusCode, String)> {
+    state
+        .config
+        .load()
+        .resolve_model(name)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+/// Unknown agent names are the client's mistake, so they answer 400 with the
+/// configured names.
+fn find_agent(state: &AppState, name: &str) -> std::result::Result<Agent, (StatusCode, String)> {
+    state
+        .config
+        .load()
+        .agent(name)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+/// The agent that answers a message sent into a session: the one named, or
+/// else the session's default. A default agent since removed from the config
+/// is passed over rather than failing every send.
+async fn session_agent(
+    state: &AppState,
+    session_id: &str,
+    name: Option<&str>,
+) -> std::result::Result<Option<Agent>, (StatusCode, String)> {
+    if let Some(name) = name {
+        return find_agent(state, name).map(Some);
+    }
+    let session = state
+        .database
+        .get_session(session_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(session
+        .and_then(|session| session.agent)
+        .and_then(|name| state.config.load().agent(&name).ok()))
+}
+
+/// Names the retired model a session was moved off, and its replacement, as
+/// `grok-2 -> grok-3`.
+const MIGRATION_HEADER: &str = "x-model-migration";
+
+/// How many of the oldest messages were left out of a session send after
+/// the model rejected the conversation as too long; absent when none were.
+const CONTEXT_SHED_HEADER: &str = "x-context-shed";
+
+/// What a session send could have cost in USD, worked out before sending;
+/// absent when the cost guard is off or the model is unpriced.
+const ESTIMATED_COST_HEADER: &str = "x-estimated-cost-usd";
+
+/// What a session send did cost in USD, from the usage reported.
+const COST_HEADER: &str = "x-cost-usd";
+
+/// How many secrets were masked in a session send before it went upstream;
+/// absent when none were.
+const REDACTIONS_HEADER: &str = "x-redactions";
+
+/// A side effect of post-processing a session reply, such as a file written;
+/// repeated for each one.
+const EFFECT_HEADER: &str = "x-post-process-effect";
+
+/// Holds back `request` when it could cost more than the cost threshold and
+/// the client didn't confirm it: a 402 naming the estimate. In `warn` mode it
+/// is sent regardless. `None` when the guard is off.
+fn guard_cost(
+    state: &AppState,
+    request: &ApiChatRequest,
+    confirmed: bool,
+) -> std::result::Result<Option<CostEstimate>, (StatusCode, String)> {
+    let guard = CostGuard::from_config(&state.config.load());
+    if guard.is_off() {
+        return Ok(None);
+    }
+    let estimate = state
+        .chat_service()
+        .estimate_cost(request, &state.pricing.load());
+    guard.admit(&estimate, confirmed).map_err(|e| {
+        (
+            StatusCode::PAYMENT_REQUIRED,
+            format!("{}; set confirm_cost to send it anyway", e),
+        )
+    })?;
+    Ok(Some(estimate))
+}
+
+/// The answer for a failed upstream request. A retired model or unknown
+/// provider is named in the request, so it answers 400; a retired model with
+/// the closest model still offered.
+fn upstream_error(e: anyhow::Error) -> (StatusCode, String) {
+    let status = if e.is::<CircuitOpen>() {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else if e.is::<RetiredModel>() || e.is::<UnknownProvider>() {
+        StatusCode::BAD_REQUEST
+    } else if is_context_length_exceeded(&e) {
+        StatusCode::PAYLOAD_TOO_LARGE
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    (status, e.to_string())
+}
+
+/// Per-send overrides for a message sent into a session.
+#[derive(Debug, Clone, Default)]
+struct SendOptions {
+    /// Override the agent's and the configured defaults.
+    max_tokens: Option<i32>,
+    temperature: Option<f32>,
+    /// Send even when it could cost more than the cost threshold.
+    confirm_cost: bool,
+    /// Drop old history on a context length error in `ask` mode too.
+    shed_context: bool,
+    /// Answers with its system prompt and parameters; recorded on the reply.
+    agent: Option<Agent>,
+}
+
+/// The assistant's reply to a message sent into a session.
+struct SentReply {
+    content: String,
+    /// Made when the model was retired.
+    migration: Option<ModelMigration>,
+    /// Oldest messages left out to fit the model's context window.
+    shed_messages: usize,
+    /// Secrets masked in the request before it was sent.
+    redactions: usize,
+    /// Side effects of post-processing the reply, such as files written.
+    effects: Vec<String>,
+    estimated_cost: Option<CostEstimate>,
+    cost: Option<Cost>,
+}
+
+/// What became of a message sent into a session.
+enum SessionReply {
+    Sent(SentReply),
+    /// The API was unreachable; the stored message waits in the outbox.
+    Queued(Message),
+}
+
+fn outbox(state: &AppState) -> Outbox {
+    Outbox::new(state.database.clone(), state.chat_service())
+}
+
+/// Appends a user message to a session, sends the whole conversation upstream
+/// and stores the assistant reply, returning its content. Sends to the same
+/// session are serialized from reading the history to storing the reply.
+/// While the API is unreachable, or earlier messages still wait in the
+/// session's outbox, the message is queued behind them instead.
+async fn send_to_session(
+    state: &AppState,
+    session_id: &str,
+    message: String,
+    model: String,
+    options: SendOptions,
+    actor: &Actor,
+) -> std::result::Result<SessionReply, (StatusCode, String)> {
+    let internal = |e: anyhow::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    let _guard = state.session_locks.lock(session_id).await;
+
+    let outbox = outbox(state);
+    let waiting = state
+        .database
+        .queued_messages(session_id)
+        .await
+        .map_err(internal)?;
+    if !waiting.is_empty() {
+        let queued = outbox
+            .queue(session_id, message, Some(model))
+            .await
+            .map_err(internal)?;
+        return match outbox.flush_session(session_id).await {
+            Ok(replies) => Ok(SessionReply::Sent(SentReply {
+                content: replies
+                    .last()
+                    .map_or_else(String::new, |reply| reply.content.clone()),
+                migration: None,
+                shed_messages: 0,
+                redactions: 0,
+                effects: Vec::new(),
+                estimated_cost: None,
+                cost: None,
+            })),
+            Err(e) if is_offline(&e) => Ok(SessionReply::Queued(queued)),
+            Err(e) => Err(internal(e)),
+        };
+    }
+
+    let existing_messages = state
+        .database
+        .get_messages(session_id)
+        .await
+        .map_err(internal)?;
+    let (mut request, _) = conversation_request(
+        state,
+        session_id,
+        &existing_messages,
+        Some(message.clone()),
+        model.clone(),
+        &options,
+    )
+    .await?;
+    let redactions = state.chat_service().redact(&mut request);
+    let estimated_cost = guard_cost(state, &request, options.confirm_cost)?;
+    let user_message = Message::user(
+        session_id.to_string(),
+        state.chat_service().stored_text(message.clone()),
+    );
+
+    // Send to Grok API
+    let config = state.config.load();
+    let result = state
+        .chat_service()
+        .send_request_shedding(
+            request,
+            None,
+            &config,
+            pinned_count(&existing_messages),
+            may_shed(&config, &options),
+        )
+        .await;
+    if let Err(e) = &result {
+        if is_offline(e) {
+            let queued = outbox
+                .queue(session_id, message, Some(model))
+                .await
+                .map_err(internal)?;
+            return Ok(SessionReply::Queued(queued));
+        }
+        // The model saw none of it, so the message isn't kept either and can
+        // be sent again with less history
+        if is_context_length_exceeded(e) {
+            return Err(too_long(&config, result.err().expect("checked above")));
+        }
+    }
+
+    // Save user message to database
+    state
+        .database
+        .create_message_audited(user_message, actor)
+        .await
+        .map_err(internal)?;
+
+    match result {
+        Ok((ChatResponse::Complete(response), migration, shed_messages)) => {
+            let reply = UpstreamReply {
+                content: response
+                    .get_content()
+                    .unwrap_or_else(|_| "No response content".to_string()),
+                model,
+                migration,
+                usage: response.get_usage().cloned(),
+                stats: response.completion_stats(),
+                resumes: None,
+            };
+            let (cost, _) = store_reply(state, session_id, &reply, options.agent, actor).await;
+
+            Ok(SessionReply::Sent(SentReply {
+                content: reply.content,
+                migration: reply.migration,
+                shed_messages,
+                redactions,
+                effects: response.effects.clone(),
+                estimated_cost,
+                cost,
+            }))
+        }
+        Ok((ChatResponse::Stream(_), _, _)) => Err((
+            StatusCode::NOT_IMPLEMENTED,
+            "Streaming not supported in this endpoint".to_string(),
+        )),
+        Err(e) => Err(upstream_error(e)),
+    }
+}
+
+/// Idle time after which a streamed reply gets a `: ping` comment, well
+/// inside the minute proxies such as nginx and Cloudflare let a connection
+/// sit idle. Reasoning models can think silently for longer than that.
+const HEARTBEAT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// The pace of a streamed session send.
+#[derive(Debug, Clone, Copy)]
+struct StreamTiming {
+    /// Idle time before each heartbeat.
+    heartbeat: std::time::Duration,
+    /// Longest the send may run, from sending to the last chunk.
+    max: std::time::Duration,
+}
+
+impl StreamTiming {
+    fn from_config(config: &Config) -> Self {
+        Self {
+            heartbeat: HEARTBEAT_INTERVAL,
+            max: config.stream_max(),
+        }
+    }
+}
+
+/// The last event of a streamed session send once the turn is stored.
+#[derive(Serialize)]
+struct StreamDone {
+    /// The stored reply; absent if saving it failed.
+    message: Option<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<UsageStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cost: Option<Cost>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    estimated_cost: Option<CostEstimate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    migration: Option<ModelMigration>,
+    shed_messages: usize,
+}
+
+type EventSender = mpsc::Sender<std::result::Result<Event, Infallible>>;
+
+fn event(name: &str, data: impl Serialize) -> Event {
+    Event::default()
+        .event(name)
+        .json_data(data)
+        .expect("event data serializes")
+}
+
+/// Like [`send_to_session`], but answers with server-sent events as the
+/// reply arrives: `delta` events with its `content`, then `done` once the
+/// turn is stored, or `queued` when the API is unreachable. A failure, or a
+/// send running past `timing.max`, ends the stream with an `error` event
+/// and stores nothing. A `: ping` comment goes out after each idle
+/// `timing.heartbeat`, and a client that disconnects takes the upstream
+/// request with it. Failures before sending, such as the cost guard's,
+/// answer as usual.
+async fn stream_to_session(
+    state: AppState,
+    session_id: String,
+    message: String,
+    model: String,
+    options: SendOptions,
+    actor: Actor,
+    timing: StreamTiming,
+) -> Response {
+    let error = |(status, error): (StatusCode, String)| {
+        (status, Json(ApiResponse::<()>::error(error))).into_response()
+    };
+    let internal = |e: anyhow::Error| error((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+    let guard = state.session_locks.lock(&session_id).await;
+
+    match state.database.queued_messages(&session_id).await {
+        Ok(waiting) if !waiting.is_empty() => {
+            return error((
+                StatusCode::CONFLICT,
+                "Earlier messages wait in the session's outbox; send without \"stream\" to queue behind them"
+                    .to_string(),
+            ))
+        }
+        Ok(_) => {}
+        Err(e) => return internal(e),
+    }
+    let existing_messages = match state.database.get_messages(&session_id).await {
+        Ok(messages) => messages,
+        Err(e) => return internal(e),
+    };
+    let request = conversation_request(
+        &state,
+        &session_id,
+        &existing_messages,
+        Some(message.clone()),
+        model.clone(),
+        &options,
+    )
+    .await;
+    let mut request = match request {
+        Ok((request, _)) => request,
+        Err(e) => return error(e),
+    };
+    request.stream = Some(true);
+    let estimated_cost = match guard_cost(&state, &request, options.confirm_cost) {
+        Ok(estimate) => estimate,
+        Err(e) => return error(e),
+    };
+    let pinned = pinned_count(&existing_messages);
+
+    let (events, receiver) = mpsc::channel(32);
+    tokio::spawn(async move {
+        let _guard = guard;
+        let config = state.config.load_full();
+        let relay = relay_reply(
+            &state,
+            request,
+            pinned,
+            may_shed(&config, &options),
+            &events,
+        );
+        let outcome = tokio::select! {
+            // The client is gone; dropping the relay drops the upstream request
+            _ = events.closed() => return,
+            outcome = tokio::time::timeout(timing.max, relay) => outcome,
+        };
+        let last = match outcome {
+            Ok(Ok((reply, shed_messages))) => {
+                let user_message = Message::user(
+                    session_id.clone(),
+                    state.chat_service().stored_text(message),
+                );
+                if let Err(e) = state
+                    .database
+                    .create_message_audited(user_message, &actor)
+                    .await
+                {
+                    eprintln!("Failed to save user message: {}", e);
+                }
+                let (cost, message) =
+                    store_reply(&state, &session_id, &reply, options.agent, &actor).await;
+                let done = StreamDone {
+                    message,
+                    usage: reply.usage,
+                    cost,
+                    estimated_cost,
+                    migration: reply.migration,
+                    shed_messages,
+                };
+                event("done", done)
+            }
+            Ok(Err(e)) if is_offline(&e) => {
+                match outbox(&state)
+                    .queue(&session_id, message, Some(model))
+                    .await
+                {
+                    Ok(queued) => event("queued", queued),
+                    Err(e) => stream_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                }
+            }
+            Ok(Err(e)) if is_context_length_exceeded(&e) => {
+                let (status, error) = too_long(&config, e);
+                stream_error(status, error)
+            }
+            Ok(Err(e)) => {
+                let (status, error) = upstream_error(e);
+                stream_error(status, error)
+            }
+            Err(_) => stream_error(
+                StatusCode::GATEWAY_TIMEOUT,
+                format!(
+                    "The reply took longer than stream_max_secs ({}s) and was abandoned",
+                    timing.max.as_secs()
+                ),
+            ),
+        };
+        let _ = events.send(Ok(last)).await;
+    });
+
+    let sse = Sse::new(ReceiverStream::new(receiver))
+        .keep_alive(KeepAlive::new().interval(timing.heartbeat).text("ping"));
+    (
+        [
+            (header::CACHE_CONTROL, "no-cache"),
+            (header::HeaderName::from_static(NO_BUFFERING_HEADER), "no"),
+        ],
+        sse,
+    )
+        .into_response()
+}
+
+/// Asks nginx not to buffer a streamed reply, which would hold back its
+/// events and heartbeats.
+const NO_BUFFERING_HEADER: &str = "x-accel-buffering";
+
+fn stream_error(status: StatusCode, error: String) -> Event {
+    event(
+        "error",
+        serde_json::json!({"error": error, "status": status.as_u16()}),
+    )
+}
+
+/// Sends `request` streamed, relaying its content to `events` as `delta`
+/// events, and returns the whole reply and how many messages were shed.
+async fn relay_reply(
+    state: &AppState,
+    request: ApiChatRequest,
+    pinned: usize,
+    shed: bool,
+    events: &EventSender,
+) -> Result<(UpstreamReply, usize)> {
+    let started = std::time::Instant::now();
+    let model = request.model.clone();
+    let config = state.config.load_full();
+    let (response, migration, shed_messages) = state
+        .chat_service()
+        .send_request_shedding(request, None, &config, pinned, shed)
+        .await?;
+    let ChatResponse::Stream(mut chunks) = response else {
+        anyhow::bail!("Expected a streamed response");
+    };
+
+    let mut content = String::new();
+    let mut usage = None;
+    let mut finish_reason = None;
+    let mut resumes = None;
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk?;
+        resumes = chunk.resumes.or(resumes);
+        if !chunk.content.is_empty() {
+            content.push_str(&chunk.content);
+            let delta = event("delta", serde_json::json!({"content": chunk.content}));
+            // A closed channel is noticed by the caller, which drops this
+            let _ = events.send(Ok(delta)).await;
+        }
+        if let Some(processed) = chunk.processed {
+            content = processed.content;
+        }
+        usage = chunk.usage.or(usage);
+        finish_reason = chunk.finish_reason.or(finish_reason);
+    }
+    let reply = UpstreamReply {
+        content,
+        model,
+        migration,
+        usage,
+        stats: CompletionStats {
+            latency_ms: Some(started.elapsed().as_millis() as u64),
+            finish_reason,
+        },
+        resumes,
+    };
+    Ok((reply, shed_messages))
+}
+
+/// Whether a send rejected as too long for the model's context window may be
+/// sent again without the oldest history.
+fn may_shed(config: &Config, options: &SendOptions) -> bool {
+    match config.context_retry() {
+        ContextRetry::Off => false,
+        ContextRetry::Auto => true,
+        ContextRetry::Ask => options.shed_context,
+    }
+}
+
+/// The answer for a send rejected as too long, saying how to shed history
+/// when `context_retry` is `ask`.
+fn too_long(config: &Config, e: anyhow::Error) -> (StatusCode, String) {
+    let (status, mut error) = upstream_error(e);
+    if config.context_retry() == ContextRetry::Ask {
+        error.push_str("; send again with \"shed_context\": true to leave out the oldest messages");
+    }
+    (status, error)
+}
+
+/// A reply the API gave to a message sent into a session.
+struct UpstreamReply {
+    content: String,
+    /// The model sent to, before any migration.
+    model: String,
+    migration: Option<ModelMigration>,
+    usage: Option<UsageStats>,
+    stats: CompletionStats,
+    /// How often a cut-off stream was resumed to complete it.
+    resumes: Option<u32>,
+}
+
+/// Stores `reply` after the user message it answers: moves the session off
+/// a retired model, records usage and saves the assistant message. Failures
+/// are logged rather than losing a reply the client already has. Returns
+/// the reply's cost and the saved message.
+async fn store_reply(
+    state: &AppState,
+    session_id: &str,
+    reply: &UpstreamReply,
+    agent: Option<Agent>,
+    actor: &Actor,
+) -> (Option<Cost>, Option<Message>) {
+    let model = match &reply.migration {
+        Some(migration) => {
+            if let Err(e) = state
+                .database
+                .migrate_model(&migration.from, &migration.to, Some(session_id), actor)
+                .await
+            {
+                eprintln!("Failed to migrate session model: {}", e);
+            }
+            migration.to.clone()
+        }
+        None => reply.model.clone(),
+    };
+
+    let mut cost = None;
+    if let Some(usage) = &reply.usage {
+        cost = Some(state.pricing.load().estimate_cost(usage, &model));
+        if let Err(e) = state
+            .database
+            .record_usage(Some(session_id), &model, usage, &reply.stats)
+            .await
+        {
+            eprintln!("Failed to record usage: {}", e);
+        }
+    }
+
+    let mut message =
+        Message::assistant(session_id.to_string(), reply.content.clone(), Some(model));
+    message.agent = agent.map(|agent| agent.name);
+    message.resumes = reply.resumes;
+    match state.database.create_message_audited(message, actor).await {
+        Ok(message) => (cost, Some(message)),
+        Err(e) => {
+            eprintln!("Failed to save assistant message: {}", e);
+            (cost, None)
+        }
+    }
+}
+
+/// Sends the conversation before a session's last reply again and stores
+/// the new reply in its place. The old reply is kept, up to
+/// `kept_generations` per reply, for comparison. Held back like a send when
+/// it could cost more than the cost threshold.
+async fn regenerate_in_session(
+    state: &AppState,
+    session_id: &str,
+    message_id: i64,
+    request: RegenerateRequest,
+    actor: &Actor,
+) -> std::result::Result<ReplyDiff, (StatusCode, String)> {
+    let internal = |e: anyhow::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    let _guard = state.session_locks.lock(session_id).await;
+
+    let mut messages = state
+        .database
+        .get_messages(session_id)
+        .await
+        .map_err(internal)?;
+    if !messages.iter().any(|message| message.id == message_id) {
+        return Err((StatusCode::NOT_FOUND, "Message not found".to_string()));
+    }
+    let previous = messages.pop().expect("the message was found");
+    if previous.id != message_id || previous.role != MessageRole::Assistant {
+        return Err((
+            StatusCode::CONFLICT,
+            "Only the session's last reply can be regenerated".to_string(),
+        ));
+    }
+    let mut model = resolve_model(
+        state,
+        request.model.as_deref().or(previous.model.as_deref()),
+    )?;
+    let options = SendOptions {
+        confirm_cost: request.confirm_cost,
+        agent: previous
+            .agent
+            .as_deref()
+            .and_then(|name| state.config.load().agent(name).ok()),
+        ..SendOptions::default()
+    };
+
+    let (request, _) =
+        conversation_request(state, session_id, &messages, None, model.clone(), &options).await?;
+    guard_cost(state, &request, options.confirm_cost)?;
+    let config = state.config.load();
+    let shed = config.context_retry() == ContextRetry::Auto;
+    let response = match state
+        .chat_service()
+        .send_request_shedding(request, None, &config, pinned_count(&messages), shed)
+        .await
+    {
+        Ok((ChatResponse::Complete(response), migration, _)) => {
+            if let Some(migration) = migration {
+                state
+                    .database
+                    .migrate_model(&migration.from, &migration.to, Some(session_id), actor)
+                    .await
+                    .map_err(internal)?;
+                model = migration.to;
+            }
+            response
+        }
+        Ok((ChatResponse::Stream(_), _, _)) => {
+            return Err((
+                StatusCode::NOT_IMPLEMENTED,
+                "Streaming not supported in this endpoint".to_string(),
+            ))
+        }
+        Err(e) if is_offline(&e) => return Err((StatusCode::SERVICE_UNAVAILABLE, e.to_string())),
+        Err(e) => return Err(upstream_error(e)),
+    };
+    let content = response
+        .get_content()
+        .unwrap_or_else(|_| "No response content".to_string());
+    if let Some(usage) = response.get_usage() {
+        if let Err(e) = state
+            .database
+            .record_usage(
+                Some(session_id),
+                &model,
+                usage,
+                &response.completion_stats(),
+            )
+            .await
+        {
+            eprintln!("Failed to record usage: {}", e);
+        }
+    }
+
+    let mut reply = Message::assistant(session_id.to_string(), content, Some(model));
+    reply.agent = options.agent.map(|agent| agent.name);
+    let message = state
+        .database
+        .supersede_message(
+            &previous,
+            reply,
+            state.config.load().kept_generations(),
+            actor,
+        )
+        .await
+        .map_err(internal)?;
+    Ok(ReplyDiff::new(message, previous))
+}
+
+/// The request a message sent into a session goes upstream as: the session's
+/// history within the context budget, the message, and the agent's system
+/// prompt or else the session's prompt preset. Also returns the older turns
+/// the budget left out.
+async fn session_request(
+    state: &AppState,
+    session_id: &str,
+    message: String,
+    model: String,
+    options: &SendOptions,
+) -> std::result::Result<(ApiChatRequest, Vec<ApiMessage>), (StatusCode, String)> {
+    // Get existing messages for context
+    let existing_messages = state
+        .database
+        .get_messages(session_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    conversation_request(
+        state,
+        session_id,
+        &existing_messages,
+        Some(message),
+        model,
+        options,
+    )
+    .await
+}
+
+/// The request for `messages` of a session, within the context budget and
+/// followed by `message` when there is one.
+async fn conversation_request(
+    state: &AppState,
+    session_id: &str,
+    messages: &[Message],
+    message: Option<String>,
+    model: String,
+    options: &SendOptions,
+) -> std::result::Result<(ApiChatRequest, Vec<ApiMessage>), (StatusCode, String)> {
+    let (history, dropped) = session_context_dropping(messages, DEFAULT_TOKEN_BUDGET);
+    let agent = options.agent.as_ref();
+    let system_prompt = match agent.and_then(|agent| agent.system_prompt.clone()) {
+        Some(prompt) => Some(prompt),
+        None => state
+            .database
+            .session_prompt(session_id)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .map(|preset| preset.body),
+    };
+
+    let mut conversation = Conversation::new().history(history);
+    if let Some(message) = message {
+        conversation = conversation.user(message);
+    }
+    let request = ApiChatRequest {
+        messages: conversation.into_messages(),
+        model,
+        max_tokens: options
+            .max_tokens
+            .or(agent.and_then(|agent| agent.max_tokens)),
+        temperature: options
+            .temperature
+            .or(agent.and_then(|agent| agent.temperature)),
+        stream: Some(false),
+        system_prompt,
+        ..Default::default()
+    };
+    Ok((request, dropped))
+}
+
+/// How often the server retries messages queued while the API was unreachable.
+const QUEUED_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Retries queued messages until the API answers again. Each session is
+/// flushed under its lock, so live sends stay in order.
+async fn retry_queued(state: AppState) {
+    let mut ticks = tokio::time::interval(QUEUED_RETRY_INTERVAL);
+    loop {
+        ticks.tick().await;
+        if let Err(e) = flush_queued(&state).await {
+            eprintln!("Failed to send queued messages: {:#}", e);
+        }
+    }
+}
+
+/// Sends every session's queued messages, stopping quietly while offline.
+async fn flush_queued(state: &AppState) -> Result<usize> {
+    let outbox = outbox(state);
+    let mut sent = 0;
+    for session_id in state.database.sessions_with_queued().await? {
+        let _guard = state.session_locks.lock(&session_id).await;
+        match outbox.flush_session(&session_id).await {
+            Ok(replies) => sent += replies.len(),
+            Err(e) if is_offline(&e) => break,
+            Err(e) => eprintln!("Failed to send queued messages in {}: {:#}", session_id, e),
+        }
+    }
+    Ok(sent)
+}
+
+async fn usage_report(state: &AppState) -> Result<Vec<ModelUsageReport>> {
+    let usage = state.database.usage_by_model().await?;
+    Ok(usage
+        .into_iter()
+        .map(|usage| {
+            let cost = state.pricing.load().cost_for_tokens(
+                usage.prompt_tokens,
+                usage.completion_tokens,
+                &usage.model,
+            );
+            ModelUsageReport { usage, cost }
+        })
+        .collect())
+}
+
+async fn usage_handler(State(state): State<AppState>) -> impl IntoResponse {
+    match usage_report(&state).await {
+        Ok(report) => Json(ApiResponse::success(report)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+/// The same report as `grok-chat-app report --format json`.
+async fn usage_report_handler(
+    State(state): State<AppState>,
+    Query(query): Query<ReportQuery>,
+) -> impl IntoResponse {
+    let report = report::usage_report(
+        &state.database,
+        &state.pricing.load(),
+        query.period,
+        ReportZone::from_config(&state.config.load()),
+        chrono::Utc::now(),
+    )
+    .await;
+    match report {
+        Ok(report) => Json(ApiResponse::success(report)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+/// The same report as `grok-chat-app sessions dedupe --output json`.
+async fn dedupe_handler(
+    State(state): State<AppState>,
+    actor: Actor,
+    Query(query): Query<DedupeQuery>,
+) -> impl IntoResponse {
+    let similarity = query.similarity.unwrap_or(DEFAULT_SIMILARITY);
+    if let Err(e) = check_similarity(similarity) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response();
+    }
+    match dedupe_sessions(&state.database, similarity, !query.dry_run, &actor).await {
+        Ok(report) => Json(ApiResponse::success(report)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+/// Re-reads the configuration as the file watcher does. An invalid one is a
+/// 422 whose `data` lists every problem found.
+async fn reload_handler(State(state): State<AppState>, actor: Actor) -> impl IntoResponse {
+    match reload::apply(&state.config, &state.pricing, Config::load()) {
+        Ok(report) => {
+            let record = AuditRecord::new(AuditOp::ConfigReload).detail(report.to_string());
+            if let Err(e) = state.database.record_audit(&actor, &record).await {
+                eprintln!("Failed to write the audit log: {}", e);
+            }
+            Json(ApiResponse::<ReloadReport>::success(report)).into_response()
+        }
+        Err(invalid) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ApiResponse::<InvalidConfig> {
+                success: false,
+                error: Some(invalid.to_string()),
+                data: Some(invalid),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+/// Audit entries, newest first, `limit` (at most 500) at a time.
+async fn audit_handler(
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> impl IntoResponse {
+    let since = match query.since.as_deref().map(parse_since).transpose() {
+        Ok(since) => since,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::<()>::error(format!("since: {}", e))),
+            )
+                .into_response();
+        }
+    };
+    let filter = AuditFilter {
+        since,
+        actor: query.actor,
+        op: query.op,
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_AUDIT_PAGE).clamp(1, 500);
+    match state
+        .database
+        .audit_entries(&filter, limit, query.offset.max(0))
+        .await
+    {
+        Ok(entries) => Json(ApiResponse::<Vec<AuditEntry>>::success(entries)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn stats_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let stats = async {
+        let report = usage_report(&state).await?;
+        Ok::<_, anyhow::Error>(StatsResponse {
+            total_sessions: state.database.get_total_sessions().await?,
+            total_messages: state.database.get_total_messages().await?,
+            prompt_tokens: report.iter().map(|r| r.usage.prompt_tokens).sum(),
+            completion_tokens: report.iter().map(|r| r.usage.completion_tokens).sum(),
+            estimated_cost_usd: report.iter().filter_map(|r| r.cost.total_usd()).sum(),
+            unpriced_models: report
+                .iter()
+                .filter(|r| r.cost == Cost::Unknown)
+                .map(|r| r.usage.model.clone())
+                .collect(),
+            models: state.chat_service.breakers().health(),
+        })
+    };
+
+    match stats.await {
+        Ok(stats) => Json(ApiResponse::success(stats)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn list_templates_handler(State(state): State<AppState>) -> impl IntoResponse {
+    match state.database.list_templates().await {
+        Ok(templates) => Json(ApiResponse::success(templates)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn create_template_handler(
+    State(state): State<AppState>,
+    Json(template): Json<PromptTemplate>,
+) -> impl IntoResponse {
+    match state.database.get_template(&template.name).await {
+        Ok(Some(_)) => {
+            return (
+                StatusCode::CONFLICT,
+                Json(ApiResponse::<()>::error(format!(
+                    "Template '{}' already exists",
+                    template.name
+                ))),
+            )
+                .into_response();
+        }
+        Ok(None) => {}
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::<()>::error(e.to_string())),
+            )
+                .into_response();
+        }
+    }
+
+    match state.database.create_template(&template).await {
+        Ok(()) => (StatusCode::CREATED, Json(ApiResponse::success(template))).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn get_template_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    match state.database.get_template(&name).await {
+        Ok(Some(template)) => Json(ApiResponse::success(template)).into_response(),
+        Ok(None) => template_not_found(&name),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn update_template_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(template): Json<PromptTemplate>,
+) -> impl IntoResponse {
+    match state.database.update_template(&name, &template).await {
+        Ok(true) => Json(ApiResponse::success(template)).into_response(),
+        Ok(false) => template_not_found(&name),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn delete_template_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    match state.database.delete_template(&name).await {
+        Ok(true) => Json(ApiResponse::success("deleted")).into_response(),
+        Ok(false) => template_not_found(&name),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn render_template_handler(
+    State(state): State<AppState>,
+    actor: Actor,
+    Path(name): Path<String>,
+    Json(request): Json<RenderTemplateRequest>,
+) -> impl IntoResponse {
+    let template = match state.database.get_template(&name).await {
+        Ok(Some(template)) => template,
+        Ok(None) => return template_not_found(&name),
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::<()>::error(e.to_string())),
+            )
+                .into_response();
+        }
+    };
+
+    let rendered = match template.render(&request.variables) {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::<()>::error(e.to_string())),
+            )
+                .into_response();
+        }
+    };
+
+    let mut result = RenderTemplateResponse {
+        text: rendered.text,
+        warnings: rendered.warnings,
+        session_id: request.session_id.clone(),
+        response: None,
+        queued: false,
+        migration: None,
+        shed_messages: None,
+        redactions: 0,
+        effects: Vec::new(),
+        estimated_cost: None,
+        cost: None,
+    };
+
+    if let Some(session_id) = request.session_id {
+        let agent = match session_agent(&state, &session_id, None).await {
+            Ok(agent) => agent,
+            Err((status, error)) => {
+                return (status, Json(ApiResponse::<()>::error(error))).into_response();
+            }
+        };
+        let model = request
+            .model
+            .or(template.model)
+            .or(agent.as_ref().map(|agent| agent.model.clone()));
+        let model = match resolve_model(&state, model.as_deref()) {
+            Ok(model) => model,
+            Err((status, error)) => {
+                return (status, Json(ApiResponse::<()>::error(error))).into_response();
+            }
+        };
+        let options = SendOptions {
+            max_tokens: template.max_tokens,
+            temperature: template.temperature,
+            confirm_cost: request.confirm_cost,
+            shed_context: request.shed_context,
+            agent,
+        };
+        match send_to_session(
+            &state,
+            &session_id,
+            result.text.clone(),
+            model,
+            options,
+            &actor,
+        )
+        .await
+        {
+            Ok(SessionReply::Sent(reply)) => {
+                result.response = Some(reply.content);
+                result.migration = reply.migration;
+                result.shed_messages = Some(reply.shed_messages).filter(|&shed| shed > 0);
+                result.redactions = reply.redactions;
+                result.effects = reply.effects;
+                result.estimated_cost = reply.estimated_cost;
+                result.cost = reply.cost;
+            }
+            Ok(SessionReply::Queued(_)) => result.queued = true,
+            Err((status, error)) => {
+                return (status, Json(ApiResponse::<()>::error(error))).into_response();
+            }
+        }
+    }
+
+    Json(ApiResponse::success(result)).into_response()
+}
+
+fn template_not_found(name: &str) -> axum::response::Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiResponse::<()>::error(format!(
+            "Template '{}' not found",
+            name
+        ))),
+    )
+        .into_response()
+}
+
+async fn list_prompts_handler(State(state): State<AppState>) -> impl IntoResponse {
+    match state.database.list_prompts().await {
+        Ok(presets) => Json(ApiResponse::success(presets)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn create_prompt_handler(
+    State(state): State<AppState>,
+    Json(preset): Json<PromptPreset>,
+) -> impl IntoResponse {
+    match state.database.get_prompt(&preset.name).await {
+        Ok(Some(_)) => {
+            return (
+                StatusCode::CONFLICT,
+                Json(ApiResponse::<()>::error(format!(
+                    "Prompt preset '{}' already exists",
+                    preset.name
+                ))),
+            )
+                .into_response();
+        }
+        Ok(None) => {}
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::<()>::error(e.to_string())),
+            )
+                .into_response();
+        }
+    }
+
+    match state.database.create_prompt(&preset).await {
+        Ok(()) => (StatusCode::CREATED, Json(ApiResponse::success(preset))).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn get_prompt_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    match state.database.get_prompt(&name).await {
+        Ok(Some(preset)) => Json(ApiResponse::success(preset)).into_response(),
+        Ok(None) => prompt_not_found(&name),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn update_prompt_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(preset): Json<PromptPreset>,
+) -> impl IntoResponse {
+    if preset.name != name {
+        match state.database.get_prompt(&preset.name).await {
+            Ok(Some(_)) => {
+                return (
+                    StatusCode::CONFLICT,
+                    Json(ApiResponse::<()>::error(format!(
+                        "Prompt preset '{}' already exists",
+                        preset.name
+                    ))),
+                )
+                    .into_response();
+            }
+            Ok(None) => {}
+            Err(e) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ApiResponse::<()>::error(e.to_string())),
+                )
+                    .into_response();
+            }
+        }
+    }
+    match state.database.update_prompt(&name, &preset).await {
+        Ok(true) => Json(ApiResponse::success(preset)).into_response(),
+        Ok(false) => prompt_not_found(&name),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn delete_prompt_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    match state.database.delete_prompt(&name).await {
+        Ok(Some(sessions_reset)) => {
+            let warning = (sessions_reset > 0).then(|| {
+                format!(
+                    "{} session(s) used '{}' and now get the default prompt",
+                    sessions_reset, name
+                )
+            });
+            Json(ApiResponse::success(DeletedPrompt {
+                name,
+                sessions_reset,
+                warning,
+            }))
+            .into_response()
+        }
+        Ok(None) => prompt_not_found(&name),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn list_session_templates_handler(State(state): State<AppState>) -> impl IntoResponse {
+    match state.database.list_session_templates().await {
+        Ok(templates) => Json(ApiResponse::success(templates)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn create_session_template_handler(
+    State(state): State<AppState>,
+    Json(template): Json<SessionTemplate>,
+) -> impl IntoResponse {
+    if let Err(e) = template.validate() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response();
+    }
+    match state.database.get_session_template(&template.name).await {
+        Ok(Some(_)) => {
+            return (
+                StatusCode::CONFLICT,
+                Json(ApiResponse::<()>::error(format!(
+                    "Session template '{}' already exists",
+                    template.name
+                ))),
+            )
+                .into_response();
+        }
+        Ok(None) => {}
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::<()>::error(e.to_string())),
+            )
+                .into_response();
+        }
+    }
+
+    match state.database.create_session_template(&template).await {
+        Ok(()) => (StatusCode::CREATED, Json(ApiResponse::success(template))).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn get_session_template_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    match state.database.get_session_template(&name).await {
+        Ok(Some(template)) => Json(ApiResponse::success(template)).into_response(),
+        Ok(None) => session_template_not_found(&name),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn update_session_template_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(template): Json<SessionTemplate>,
+) -> impl IntoResponse {
+    if let Err(e) = template.validate() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response();
+    }
+    match state
+        .database
+        .update_session_template(&name, &template)
+        .await
+    {
+        Ok(true) => Json(ApiResponse::success(template)).into_response(),
+        Ok(false) => session_template_not_found(&name),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn delete_session_template_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    match state.database.delete_session_template(&name).await {
+        Ok(true) => Json(ApiResponse::success(name)).into_response(),
+        Ok(false) => session_template_not_found(&name),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+fn session_template_not_found(name: &str) -> axum::response::Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiResponse::<()>::error(format!(
+            "Session template '{}' not found",
+            name
+        ))),
+    )
+        .into_response()
+}
+
+fn prompt_not_found(name: &str) -> axum::response::Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiResponse::<()>::error(format!(
+            "Prompt preset '{}' not found",
+            name
+        ))),
+    )
+        .into_response()
+}
+
+async fn list_models_handler(State(state): State<AppState>) -> impl IntoResponse {
+    match state.chat_service.list_available_models().await {
+        Ok(models) => {
+            let profiles = state.chat_service.profiles();
+            let models: Vec<ModelInfo> = models
+                .into_iter()
+                .map(|id| ModelInfo {
+                    provider: split_model(&id).0.to_string(),
+                    profile: profiles.profile_for(&id),
+                    id,
+                })
+                .collect();
+            Json(ApiResponse::success(models)).into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn list_agents_handler(State(state): State<AppState>) -> impl IntoResponse {
+    Json(ApiResponse::success(state.config.load().agents()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_api_response_success() {
+        let response = ApiResponse::success("test data");
+        assert!(response.success);
+        assert_eq!(response.data, Some("test data"));
+        assert_eq!(response.error, None);
+    }
+
+    #[test]
+    fn test_api_response_error() {
+        let response: ApiResponse<()> = ApiResponse::<()>::error("test error".to_string());
+        assert!(!response.success);
+        assert_eq!(response.data, None);
+        assert_eq!(response.error, Some("test error".to_string()));
+    }
+
+    #[test]
     fn test_create_session_request() {
         let request = CreateSessionRequest {
             model: Some("grok-4-0709".to_string()),
             title: Some("Test Session".to_string()),
+            prompt_preset: None,
+            agent: None,
+        };
+
+        let json = serde_json::to_string(&request).unwrap();
+        assert!(json.contains("grok-4-0709"));
+        assert!(json.contains("Test Session"));
+    }
+
+    #[test]
+    fn test_send_message_request() {
+        let request = SendMessageRequest {
+            message: "Hello, Grok!".to_string(),
+            model: Some("grok-3".to_string()),
+            dry_run: false,
+            confirm_cost: false,
+            agent: None,
+            shed_context: false,
+            stream: false,
+        };
+
+        let json = serde_json::to_string(&request).unwrap();
+        assert!(json.contains("Hello, Grok!"));
+        assert!(json.contains("grok-3"));
+    }
+
+    #[test]
+    fn test_chat_request_logprobs_optional() {
+        let request: ChatRequest =
+            serde_json::from_str(r#"{"message": "Hi", "model": "grok-3"}"#).unwrap();
+        assert_eq!(request.logprobs, None);
+
+        let request: ChatRequest = serde_json::from_str(
+            r#"{"message": "Hi", "model": "grok-3", "logprobs": true, "top_logprobs": 5}"#,
+        )
+        .unwrap();
+        assert_eq!(request.logprobs, Some(true));
+        assert_eq!(request.top_logprobs, Some(5));
+    }
+
+    #[test]
+    fn test_render_template_request_defaults() {
+        let request: RenderTemplateRequest = serde_json::from_str("{}").unwrap();
+        assert!(request.variables.is_empty());
+        assert!(request.session_id.is_none());
+
+        let request: RenderTemplateRequest =
+            serde_json::from_str(r#"{"variables": {"lang": "Rust"}, "session_id": "abc"}"#)
+                .unwrap();
+        assert_eq!(request.variables["lang"], "Rust");
+        assert_eq!(request.session_id.as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn test_report_query_defaults_to_a_week() {
+        let query: ReportQuery = serde_json::from_str("{}").unwrap();
+        assert_eq!(query.period, ReportPeriod::Week);
+        let query: ReportQuery = serde_json::from_str(r#"{"period": "month"}"#).unwrap();
+        assert_eq!(query.period, ReportPeriod::Month);
+        assert!(serde_json::from_str::<ReportQuery>(r#"{"period": "year"}"#).is_err());
+    }
+
+    #[test]
+    fn test_etag_matches_lists_weak_tags_and_wildcards() {
+        let header = |value: &'static str| HeaderValue::from_static(value);
+        let etag = "\"abc\"";
+        assert!(etag_matches(Some(&header("\"abc\"")), etag));
+        assert!(etag_matches(Some(&header("\"x\", W/\"abc\"")), etag));
+        assert!(etag_matches(Some(&header("*")), etag));
+        assert!(!etag_matches(Some(&header("\"abcd\"")), etag));
+        assert!(!etag_matches(None, etag));
+    }
+
+    #[test]
+    fn test_dedupe_query_applies_unless_dry_run() {
+        let query: DedupeQuery = serde_json::from_str("{}").unwrap();
+        assert!(!query.dry_run);
+        assert_eq!(query.similarity, None);
+        let query: DedupeQuery =
+            serde_json::from_str(r#"{"dry_run": true, "similarity": 0.9}"#).unwrap();
+        assert!(query.dry_run);
+        assert_eq!(query.similarity, Some(0.9));
+    }
+
+    #[test]
+    fn test_model_usage_report_serialization() {
+        let report = ModelUsageReport {
+            usage: ModelUsage {
+                model: "mystery".to_string(),
+                requests: 1,
+                prompt_tokens: 2,
+                completion_tokens: 3,
+            },
+            cost: Cost::Unknown,
+        };
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["model"], "mystery");
+        assert_eq!(json["cost"]["pricing"], "unknown");
+    }
+
+    #[tokio::test]
+    async fn test_share_links_give_read_only_access_until_revoked() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            ..Config::default()
+        };
+        let state = AppState {
+            chat_service: ChatService::new(&config).unwrap(),
+            database: Database::new(&config).await.unwrap(),
+            pricing: Arc::new(ArcSwap::from_pointee(PricingTable::default())),
+            config: Arc::new(ArcSwap::from_pointee(config)),
+            sessions: Arc::default(),
+            session_locks: SessionLocks::default(),
+        };
+        let session = state
+            .database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        state
+            .database
+            .create_message(Message::user(session.id.clone(), "<hello>".to_string()))
+            .await
+            .unwrap();
+        let share = |body: &'static str| {
+            share_session_handler(
+                State(state.clone()),
+                Path(session.id.clone()),
+                Bytes::from(body),
+            )
+        };
+
+        let response = share(r#"{"expires_in_hours": 0}"#).await.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = share_session_handler(
+            State(state.clone()),
+            Path("missing".to_string()),
+            Bytes::new(),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = share("").await.into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let link: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let token = link["data"]["token"].as_str().unwrap().to_string();
+        assert_eq!(link["data"]["view_url"], format!("/shared/{}/view", token));
+
+        let shared =
+            |token: &str| shared_session_handler(State(state.clone()), Path(token.to_string()));
+        let response = shared(&token).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = shared_session_view_handler(State(state.clone()), Path(token.clone()))
+            .await
+            .into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("&lt;hello&gt;"));
+        // The token is not a session id
+        assert_eq!(
+            shared(&session.id).await.into_response().status(),
+            StatusCode::NOT_FOUND
+        );
+
+        let response = revoke_share_handler(State(state.clone()), Path(session.id.clone()))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            shared(&token).await.into_response().status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sessions_start_from_a_template() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            ..Config::default()
+        };
+        let state = AppState {
+            chat_service: ChatService::new(&config).unwrap(),
+            database: Database::new(&config).await.unwrap(),
+            pricing: Arc::new(ArcSwap::from_pointee(PricingTable::default())),
+            config: Arc::new(ArcSwap::from_pointee(config)),
+            sessions: Arc::default(),
+            session_locks: SessionLocks::default(),
+        };
+        let template: SessionTemplate = serde_json::from_str(
+            r#"{"name": "review", "system_prompt": "Review code.",
+                "messages": [{"role": "user", "content": "x.unwrap()"},
+                             {"role": "assistant", "content": "Avoid unwrap."}]}"#,
+        )
+        .unwrap();
+        let response = create_session_template_handler(State(state.clone()), Json(template))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let create = |template: Option<&str>| {
+            create_session_handler(
+                State(state.clone()),
+                Actor::new("test"),
+                Query(CreateSessionQuery {
+                    template: template.map(str::to_string),
+                }),
+                Json(CreateSessionRequest {
+                    model: Some("grok-3".to_string()),
+                    title: None,
+                    prompt_preset: None,
+                    agent: None,
+                }),
+            )
+        };
+        let response = create(Some("missing")).await.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = create(Some("review")).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let session_id = json["data"]["id"].as_str().unwrap();
+        let messages = state.database.get_messages(session_id).await.unwrap();
+        assert_eq!(messages.len(), 3);
+        assert!(messages.iter().all(|m| m.seeded));
+        assert_eq!(messages[0].role, MessageRole::System);
+    }
+
+    #[tokio::test]
+    async fn test_media_streams_with_an_etag_and_revalidates() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            media_dir: Some(dir.path().join("media")),
+            ..Config::default()
+        };
+        let state = AppState {
+            chat_service: ChatService::new(&config).unwrap(),
+            database: Database::new(&config).await.unwrap(),
+            pricing: Arc::new(ArcSwap::from_pointee(PricingTable::default())),
+            config: Arc::new(ArcSwap::from_pointee(config)),
+            sessions: Arc::default(),
+            session_locks: SessionLocks::default(),
+        };
+        let session = state
+            .database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        let message = state
+            .database
+            .create_message(Message::user(session.id.clone(), "see".to_string()))
+            .await
+            .unwrap();
+        let media = MediaStore::new(state.config.load().media_dir())
+            .put(b"GIF89a", "image/gif")
+            .await
+            .unwrap();
+        state
+            .database
+            .add_attachment(message.id, &media)
+            .await
+            .unwrap();
+        let get = |hash: &str, headers: HeaderMap| {
+            media_handler(State(state.clone()), Path(hash.to_string()), headers)
+        };
+
+        let response = get(&media.hash, HeaderMap::new()).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/gif");
+        let etag = response.headers()[header::ETAG].clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"GIF89a");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, etag);
+        let response = get(&media.hash, headers).await.into_response();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = get("../chat.db", HeaderMap::new()).await.into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_annotations_merge_and_export_as_jsonl() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            ..Config::default()
+        };
+        let state = AppState {
+            chat_service: ChatService::new(&config).unwrap(),
+            database: Database::new(&config).await.unwrap(),
+            pricing: Arc::new(ArcSwap::from_pointee(PricingTable::default())),
+            config: Arc::new(ArcSwap::from_pointee(config)),
+            sessions: Arc::default(),
+            session_locks: SessionLocks::default(),
+        };
+        let session = state
+            .database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        state
+            .database
+            .create_message(Message::user(session.id.clone(), "Hi".to_string()))
+            .await
+            .unwrap();
+        let reply = state
+            .database
+            .create_message(Message::assistant(
+                session.id.clone(),
+                "Go away".to_string(),
+                Some("grok-3".to_string()),
+            ))
+            .await
+            .unwrap();
+        let annotate = |id: i64, body: serde_json::Value| {
+            annotate_handler(
+                State(state.clone()),
+                Path((session.id.clone(), id)),
+                Json(serde_json::from_value(body).unwrap()),
+            )
+        };
+
+        let response = annotate(reply.id, serde_json::json!({"rating": "down"}))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        annotate(
+            reply.id,
+            serde_json::json!({"note": " Rude ", "labels": ["tone", "tone"]}),
+        )
+        .await;
+        let response = annotate(999, serde_json::json!({"rating": "up"}))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let export = |rating: Option<Rating>| {
+            export_annotations_handler(
+                State(state.clone()),
+                Query(AnnotationExportQuery { rating }),
+            )
+        };
+        let response = export(Some(Rating::Down)).await.into_response();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/x-ndjson"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let line: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            line["context"],
+            serde_json::json!([{"role": "user", "content": "Hi"}])
+        );
+        assert_eq!(line["response"]["content"], "Go away");
+        assert_eq!(
+            line["annotation"],
+            serde_json::json!({"rating": "down", "note": "Rude", "labels": ["tone"]})
+        );
+
+        let response = export(Some(Rating::Up)).await.into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_sends_to_a_session_see_each_other() {
+        use crate::client::mock::{MockReply, MockUpstream};
+
+        // Slow replies give an unserialized second send time to read the
+        // history before the first reply is stored
+        let upstream = MockUpstream::start(|body| {
+            let messages = body["messages"].as_array().unwrap();
+            let prompt = messages.last().unwrap()["content"].as_str().unwrap();
+            MockReply::Delayed(
+                Duration::from_millis(150),
+                Box::new(MockReply::completion(&format!("re: {}", prompt))),
+            )
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            ..upstream.config()
         };
+        let state = AppState {
+            chat_service: ChatService::new(&config).unwrap(),
+            database: Database::new(&config).await.unwrap(),
+            pricing: Arc::new(ArcSwap::from_pointee(PricingTable::default())),
+            config: Arc::new(ArcSwap::from_pointee(config)),
+            sessions: Arc::default(),
+            session_locks: SessionLocks::default(),
+        };
+        let session = state
+            .database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
 
-        let json = serde_json::to_string(&request).unwrap();
-        assert!(json.contains("grok-4-0709"));
-        assert!(json.contains("Test Session"));
+        let actor = Actor::new("test");
+        let send = |message: &str| {
+            send_to_session(
+                &state,
+                &session.id,
+                message.to_string(),
+                "grok-3".to_string(),
+                SendOptions::default(),
+                &actor,
+            )
+        };
+        let (first, second) = tokio::join!(send("one"), send("two"));
+        first.unwrap();
+        second.unwrap();
+
+        let requests = upstream.requests();
+        let earlier = requests[0]["messages"].as_array().unwrap();
+        let later = requests[1]["messages"].as_array().unwrap();
+        let prompt = earlier.last().unwrap()["content"].as_str().unwrap();
+        let contents: Vec<&str> = later
+            .iter()
+            .filter_map(|message| message["content"].as_str())
+            .collect();
+        assert!(contents.contains(&prompt));
+        assert!(contents.contains(&format!("re: {}", prompt).as_str()));
+
+        let stored: Vec<String> = state
+            .database
+            .get_messages(&session.id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|message| message.content)
+            .collect();
+        assert_eq!(stored.len(), 4);
+        assert_eq!(stored[1], format!("re: {}", stored[0]));
+        assert_eq!(stored[3], format!("re: {}", stored[2]));
+        assert!(state.session_locks.is_empty());
     }
 
-    #[test]
-    fn test_send_message_request() {
+    #[tokio::test]
+    async fn test_session_dry_run_matches_what_is_sent() {
+        use crate::client::mock::MockUpstream;
+
+        let upstream = MockUpstream::echo("done").await;
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            ..upstream.config()
+        })
+        .await
+        .unwrap();
+        let session = state
+            .database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        state
+            .database
+            .create_message(Message::user(session.id.clone(), "earlier".to_string()))
+            .await
+            .unwrap();
+
         let request = SendMessageRequest {
-            message: "Hello, Grok!".to_string(),
+            message: "hello".to_string(),
             model: Some("grok-3".to_string()),
+            dry_run: true,
+            confirm_cost: false,
+            agent: None,
+            shed_context: false,
+            stream: false,
         };
+        let response = send_message_handler(
+            State(state.clone()),
+            Actor::new("test"),
+            Path(session.id.clone()),
+            Json(request),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let dry_run: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(upstream.requests().is_empty());
+        assert_eq!(
+            state
+                .database
+                .get_messages(&session.id)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
 
-        let json = serde_json::to_string(&request).unwrap();
-        assert!(json.contains("Hello, Grok!"));
-        assert!(json.contains("grok-3"));
+        send_to_session(
+            &state,
+            &session.id,
+            "hello".to_string(),
+            "grok-3".to_string(),
+            SendOptions::default(),
+            &Actor::new("test"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(upstream.requests(), [dry_run["data"]["body"].clone()]);
+    }
+
+    #[tokio::test]
+    async fn test_session_sends_report_their_redactions() {
+        use crate::client::mock::MockUpstream;
+
+        let upstream = MockUpstream::echo("Noted").await;
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            ..upstream.config()
+        })
+        .await
+        .unwrap();
+        let session = state
+            .database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+
+        let response = send_message_handler(
+            State(state.clone()),
+            Actor::new("test"),
+            Path(session.id.clone()),
+            Json(SendMessageRequest {
+                message: "Write to me@example.com".to_string(),
+                model: None,
+                dry_run: false,
+                confirm_cost: false,
+                agent: None,
+                shed_context: false,
+                stream: false,
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[REDACTIONS_HEADER], "1");
+        let sent = upstream.requests()[0]["messages"].to_string();
+        assert!(sent.contains("[REDACTED:email]"));
+    }
+
+    #[tokio::test]
+    async fn test_session_sends_report_post_processing_effects() {
+        use crate::client::mock::MockUpstream;
+
+        let upstream = MockUpstream::echo("Here:\n```rust\nfn main() {}\n```").await;
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            extract_code_to: Some(dir.path().join("code")),
+            ..upstream.config()
+        })
+        .await
+        .unwrap();
+        let session = state
+            .database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+
+        let response = send_message_handler(
+            State(state.clone()),
+            Actor::new("test"),
+            Path(session.id.clone()),
+            Json(SendMessageRequest {
+                message: "Write a program".to_string(),
+                model: None,
+                dry_run: false,
+                confirm_cost: false,
+                agent: None,
+                shed_context: false,
+                stream: false,
+            }),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let effects: Vec<_> = response.headers().get_all(EFFECT_HEADER).iter().collect();
+        assert_eq!(effects.len(), 1);
+        assert!(effects[0].to_str().unwrap().contains("(1 line)"));
+    }
+
+    #[tokio::test]
+    async fn test_costly_sends_need_confirming() {
+        use crate::client::mock::MockUpstream;
+        use crate::pricing::GuardMode;
+
+        let upstream = MockUpstream::echo("done").await;
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            cost_threshold_usd: 0.000_001,
+            cost_guard: GuardMode::Block,
+            ..upstream.config()
+        })
+        .await
+        .unwrap();
+        let session = state
+            .database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+
+        let send = |confirm_cost| {
+            send_message_handler(
+                State(state.clone()),
+                Actor::new("test"),
+                Path(session.id.clone()),
+                Json(SendMessageRequest {
+                    message: "hello".to_string(),
+                    model: Some("grok-3".to_string()),
+                    dry_run: false,
+                    confirm_cost,
+                    agent: None,
+                    shed_context: false,
+                    stream: false,
+                }),
+            )
+        };
+        let response = send(false).await.into_response();
+        assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+        assert!(upstream.requests().is_empty());
+        assert!(state
+            .database
+            .get_messages(&session.id)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let response = send(true).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(upstream.requests().len(), 1);
+        let usd = |name| {
+            response.headers()[name]
+                .to_str()
+                .unwrap()
+                .parse::<f64>()
+                .unwrap()
+        };
+        assert!(usd(ESTIMATED_COST_HEADER) > 0.000_001);
+        // The mock reports 2 tokens, far fewer than max_tokens allows
+        assert!(usd(COST_HEADER) < usd(ESTIMATED_COST_HEADER));
+
+        // Regenerating and replaying send upstream too
+        let reply = state.database.get_messages(&session.id).await.unwrap()[1].id;
+        let regenerate = |body: &'static str| {
+            regenerate_handler(
+                State(state.clone()),
+                Actor::new("test"),
+                Path((session.id.clone(), reply)),
+                Bytes::from(body),
+            )
+        };
+        let response = regenerate("").await.into_response();
+        assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+        let response = regenerate(r#"{"confirm_cost": true}"#)
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let export = state
+            .database
+            .export_session(&session.id)
+            .await
+            .unwrap()
+            .unwrap();
+        let replay = |confirm_cost| {
+            replay_handler(
+                State(state.clone()),
+                Json(ReplayRequest {
+                    export: export.clone(),
+                    model: None,
+                    max_tokens: None,
+                    temperature: None,
+                    fail_threshold: None,
+                    confirm_cost,
+                }),
+            )
+        };
+        let response = replay(false).await.into_response();
+        assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(upstream.requests().len(), 2);
+        let response = replay(true).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(upstream.requests().len(), 3);
+    }
+
+    #[test]
+    fn test_byte_ranges_parse() {
+        let parse = |header| ByteRange::parse(Some(header), 100);
+        assert_eq!(parse("bytes=10-19"), ByteRange::Part(10, 19));
+        assert_eq!(parse("bytes=90-"), ByteRange::Part(90, 99));
+        assert_eq!(parse("bytes=90-500"), ByteRange::Part(90, 99));
+        assert_eq!(parse("bytes=-30"), ByteRange::Part(70, 99));
+        assert_eq!(parse("bytes=-300"), ByteRange::Part(0, 99));
+        assert_eq!(parse("bytes=100-"), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=-0"), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=0-1,5-6"), ByteRange::Whole);
+        assert_eq!(parse("bytes=20-10"), ByteRange::Whole);
+        assert_eq!(parse("items=0-1"), ByteRange::Whole);
+        assert_eq!(ByteRange::parse(None, 100), ByteRange::Whole);
+    }
+
+    #[tokio::test]
+    async fn test_finetune_export_streams_matching_sessions_redacted() {
+        use tower::ServiceExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            ..Config::default()
+        })
+        .await
+        .unwrap();
+        for (question, label) in [
+            ("Key xai-AbCdEfGhIjKlMnOpQrStUvWxYz0123?", "golden"),
+            ("Hi", "meh"),
+        ] {
+            let session = state
+                .database
+                .create_session(ChatSession::new("grok-3".to_string(), None))
+                .await
+                .unwrap();
+            state
+                .database
+                .create_message(Message::system(session.id.clone(), "Be brief.".to_string()))
+                .await
+                .unwrap();
+            state
+                .database
+                .create_message(Message::user(session.id.clone(), question.to_string()))
+                .await
+                .unwrap();
+            let reply = state
+                .database
+                .create_message(Message::assistant(
+                    session.id.clone(),
+                    "Rotate it".to_string(),
+                    Some("grok-3".to_string()),
+                ))
+                .await
+                .unwrap();
+            let annotation = Annotation {
+                labels: vec![label.to_string()],
+                ..Annotation::default()
+            };
+            state
+                .database
+                .set_annotation(&session.id, reply.id, &annotation)
+                .await
+                .unwrap();
+        }
+
+        let get = |uri: &str| {
+            let request = axum::http::Request::builder()
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap();
+            router(state.clone()).oneshot(request)
+        };
+        let response = get("/export/finetune?tag=golden&until=2999-12-31")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/x-ndjson"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(
+            body,
+            "{\"messages\":[{\"role\":\"user\",\"content\":\"Key [REDACTED:api_key]?\"},\
+             {\"role\":\"assistant\",\"content\":\"Rotate it\"}]}\n"
+        );
+
+        let body = axum::body::to_bytes(
+            get("/export/finetune?per_turn=true")
+                .await
+                .unwrap()
+                .into_body(),
+            usize::MAX,
+        )
+        .await
+        .unwrap();
+        assert_eq!(body.iter().filter(|&&byte| byte == b'\n').count(), 2);
+        let response = get("/export/finetune?until=yesterday").await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_session_exports_resume_from_a_byte_offset() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            ..Config::default()
+        })
+        .await
+        .unwrap();
+        let session = state
+            .database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        for i in 0..20 {
+            state
+                .database
+                .create_message(Message::user(session.id.clone(), format!("message {}", i)))
+                .await
+                .unwrap();
+        }
+        let whole = serde_json::to_vec(
+            &state
+                .database
+                .export_session(&session.id)
+                .await
+                .unwrap()
+                .unwrap(),
+        )
+        .unwrap();
+
+        let export = |pairs: &[(header::HeaderName, &str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(name.clone(), HeaderValue::from_str(value).unwrap());
+            }
+            export_session_handler(
+                State(state.clone()),
+                Path(session.id.clone()),
+                Query(ExportSessionQuery::default()),
+                headers,
+            )
+        };
+        let body = |response: Response| async {
+            axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap()
+                .to_vec()
+        };
+
+        let response = export(&[]).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_LENGTH],
+            whole.len().to_string()
+        );
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(body(response).await, whole);
+
+        // The download broke off after 100 bytes
+        let response = export(&[(header::RANGE, "bytes=100-"), (header::IF_MATCH, &etag)])
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers()[header::CONTENT_RANGE],
+            format!("bytes 100-{}/{}", whole.len() - 1, whole.len())
+        );
+        assert_eq!(body(response).await, whole[100..]);
+
+        let response = export(&[(header::RANGE, "bytes=10-19")])
+            .await
+            .into_response();
+        assert_eq!(body(response).await, whole[10..20]);
+        let response = export(&[(header::RANGE, &format!("bytes={}-", whole.len()))])
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+        // The session changed between the two requests
+        state
+            .database
+            .create_message(Message::user(session.id.clone(), "late".to_string()))
+            .await
+            .unwrap();
+        let response = export(&[(header::RANGE, "bytes=100-"), (header::IF_MATCH, &etag)])
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        assert_ne!(response.headers()[header::ETAG], etag.as_str());
+        let response = export(&[(header::RANGE, "bytes=100-"), (header::IF_RANGE, &etag)])
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body(response).await.len() > whole.len());
+
+        let response = export_session_handler(
+            State(state.clone()),
+            Path("missing".to_string()),
+            Query(ExportSessionQuery::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_session_exports_change_etag_on_pins_and_annotations() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            ..Config::default()
+        })
+        .await
+        .unwrap();
+        let session = state
+            .database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        let message = state
+            .database
+            .create_message(Message::user(session.id.clone(), "Keep this".to_string()))
+            .await
+            .unwrap();
+        let export = |pairs: &[(header::HeaderName, &str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(name.clone(), HeaderValue::from_str(value).unwrap());
+            }
+            export_session_handler(
+                State(state.clone()),
+                Path(session.id.clone()),
+                Query(ExportSessionQuery::default()),
+                headers,
+            )
+        };
+        let etag = |response: &Response| {
+            response.headers()[header::ETAG]
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        let first = etag(&export(&[]).await.into_response());
+        state
+            .database
+            .set_message_pinned(&session.id, message.id, Some(true))
+            .await
+            .unwrap();
+        let response = export(&[(header::RANGE, "bytes=10-"), (header::IF_MATCH, &first)])
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+        let pinned = etag(&response);
+        let annotation = Annotation {
+            rating: Some(Rating::Up),
+            ..Annotation::default()
+        };
+        state
+            .database
+            .set_annotation(&session.id, message.id, &annotation)
+            .await
+            .unwrap();
+        let response = export(&[(header::RANGE, "bytes=10-"), (header::IF_MATCH, &pinned)])
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    }
+
+    #[tokio::test]
+    async fn test_agents_answer_and_are_recorded_on_replies() {
+        use crate::agents::AgentSettings;
+        use crate::client::mock::MockUpstream;
+
+        let upstream = MockUpstream::echo("done").await;
+        let dir = tempfile::tempdir().unwrap();
+        let reviewer = AgentSettings {
+            model: Some("grok-3".to_string()),
+            system_prompt: Some("Review the code.".to_string()),
+            max_tokens: Some(500),
+            ..AgentSettings::default()
+        };
+        let state = AppState::new(Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            agents: [("reviewer".to_string(), reviewer)].into(),
+            ..upstream.config()
+        })
+        .await
+        .unwrap();
+        let session = state
+            .database
+            .create_session(ChatSession::new("grok-4-0709".to_string(), None))
+            .await
+            .unwrap();
+
+        let send = |agent: Option<&str>| {
+            send_message_handler(
+                State(state.clone()),
+                Actor::new("test"),
+                Path(session.id.clone()),
+                Json(SendMessageRequest {
+                    message: "hello".to_string(),
+                    model: None,
+                    dry_run: false,
+                    confirm_cost: false,
+                    agent: agent.map(str::to_string),
+                    shed_context: false,
+                    stream: false,
+                }),
+            )
+        };
+        let response = send(Some("writer")).await.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json["error"],
+            "Unknown agent 'writer' (available: reviewer)"
+        );
+
+        let response = send(Some("reviewer")).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let sent = &upstream.requests()[0];
+        assert_eq!(sent["model"], "grok-3");
+        assert_eq!(sent["max_tokens"], 500);
+        assert_eq!(sent["messages"][0]["content"], "Review the code.");
+        let messages = state.database.get_messages(&session.id).await.unwrap();
+        assert_eq!(messages[1].agent.as_deref(), Some("reviewer"));
+        assert_eq!(messages[1].model.as_deref(), Some("grok-3"));
+
+        // The session's default agent answers messages that name none
+        let response = update_session_handler(
+            State(state.clone()),
+            Path(session.id.clone()),
+            Json(serde_json::from_str(r#"{"agent": "reviewer"}"#).unwrap()),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(None).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let messages = state.database.get_messages(&session.id).await.unwrap();
+        assert_eq!(messages[3].agent.as_deref(), Some("reviewer"));
+
+        let response = update_session_handler(
+            State(state.clone()),
+            Path(session.id.clone()),
+            Json(serde_json::from_str(r#"{"agent": null}"#).unwrap()),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(None).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let messages = state.database.get_messages(&session.id).await.unwrap();
+        assert_eq!(messages[5].agent, None);
+        assert_eq!(upstream.requests()[2]["model"], "grok-4-0709");
+    }
+
+    #[tokio::test]
+    async fn test_regenerating_keeps_bounded_generations_and_diffs() {
+        use crate::client::mock::{MockReply, MockUpstream};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = AtomicUsize::new(0);
+        let upstream = MockUpstream::start(move |_| {
+            let replies = ["The sky is grey today.", "The sky is green today."];
+            MockReply::completion(replies[calls.fetch_add(1, Ordering::SeqCst) % 2])
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            kept_generations: 1,
+            ..upstream.config()
+        })
+        .await
+        .unwrap();
+        let session = state
+            .database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        let question = state
+            .database
+            .create_message(Message::user(session.id.clone(), "Colour?".to_string()))
+            .await
+            .unwrap();
+        let first = state
+            .database
+            .create_message(Message::assistant(
+                session.id.clone(),
+                "The sky is blue today.".to_string(),
+                Some("grok-3".to_string()),
+            ))
+            .await
+            .unwrap();
+
+        async fn regenerate(
+            state: &AppState,
+            session_id: &str,
+            id: i64,
+        ) -> (StatusCode, serde_json::Value) {
+            let response = regenerate_handler(
+                State(state.clone()),
+                Actor::new("test"),
+                Path((session_id.to_string(), id)),
+                Bytes::new(),
+            )
+            .await
+            .into_response();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (status, serde_json::from_slice(&body).unwrap())
+        }
+
+        let (status, _) = regenerate(&state, &session.id, question.id).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (status, body) = regenerate(&state, &session.id, first.id).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["data"]["previous"]["content"],
+            "The sky is blue today."
+        );
+        assert_eq!(
+            body["data"]["diff"],
+            serde_json::json!([
+                {"op": "equal", "text": "The sky is "},
+                {"op": "delete", "text": "blue"},
+                {"op": "insert", "text": "grey"},
+                {"op": "equal", "text": " today."},
+            ])
+        );
+        // The replaced reply is not sent back as context
+        let sent = &upstream.requests()[0]["messages"];
+        assert_eq!(
+            sent.as_array().unwrap().last().unwrap()["content"],
+            "Colour?"
+        );
+
+        let second = body["data"]["message"]["id"].as_i64().unwrap();
+        let (status, body) = regenerate(&state, &session.id, second).await;
+        assert_eq!(status, StatusCode::OK);
+        let third = body["data"]["message"]["id"].as_i64().unwrap();
+
+        let current = state.database.get_messages(&session.id).await.unwrap();
+        assert_eq!(current.len(), 2);
+        assert_eq!(current[1].content, "The sky is green today.");
+        // Only one earlier generation is kept: the grey one
+        let all = state
+            .database
+            .get_messages_with_superseded(&session.id)
+            .await
+            .unwrap();
+        let superseded: Vec<_> = all
+            .iter()
+            .filter(|message| message.superseded_by == Some(third))
+            .map(|message| message.content.as_str())
+            .collect();
+        assert_eq!(superseded, ["The sky is grey today."]);
+        assert_eq!(all.len(), 3);
+
+        let response =
+            message_diff_handler(State(state.clone()), Path((session.id.clone(), third)))
+                .await
+                .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = message_diff_handler(
+            State(state.clone()),
+            Path((session.id.clone(), question.id)),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_overlong_sessions_shed_history_once_asked() {
+        use crate::client::mock::{MockReply, MockUpstream};
+
+        let upstream = MockUpstream::start(|body| {
+            match body["messages"].as_array().map_or(0, Vec::len) {
+                0..=6 => MockReply::completion("fits"),
+                _ => MockReply::Json(
+                    400,
+                    serde_json::json!({"error": "This model's maximum context length is 131072 tokens"}),
+                ),
+            }
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            context_retry: ContextRetry::Ask,
+            ..upstream.config()
+        })
+        .await
+        .unwrap();
+        let session = state
+            .database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        let pinned = state
+            .database
+            .create_message(Message::user(
+                session.id.clone(),
+                "Use British spelling".to_string(),
+            ))
+            .await
+            .unwrap();
+        state
+            .database
+            .set_message_pinned(&session.id, pinned.id, Some(true))
+            .await
+            .unwrap();
+        for turn in 0..3 {
+            state
+                .database
+                .create_message(Message::user(
+                    session.id.clone(),
+                    format!("question {}", turn),
+                ))
+                .await
+                .unwrap();
+            state
+                .database
+                .create_message(Message::assistant(
+                    session.id.clone(),
+                    format!("answer {}", turn),
+                    None,
+                ))
+                .await
+                .unwrap();
+        }
+
+        let send = |shed_context| {
+            send_message_handler(
+                State(state.clone()),
+                Actor::new("test"),
+                Path(session.id.clone()),
+                Json(SendMessageRequest {
+                    message: "latest".to_string(),
+                    model: Some("grok-3".to_string()),
+                    dry_run: false,
+                    confirm_cost: false,
+                    agent: None,
+                    shed_context,
+                    stream: false,
+                }),
+            )
+        };
+
+        // Refused without asking, and the message isn't kept
+        let response = send(false).await.into_response();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("shed_context"));
+        assert_eq!(
+            state
+                .database
+                .get_messages(&session.id)
+                .await
+                .unwrap()
+                .len(),
+            7
+        );
+
+        let response = send(true).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let shed: usize = response.headers()[CONTEXT_SHED_HEADER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(shed > 0);
+        let sent = upstream.requests().pop().unwrap();
+        let contents: Vec<&str> = sent["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|message| message["content"].as_str())
+            .collect();
+        assert!(contents.contains(&"Use British spelling"));
+        assert_eq!(contents.last(), Some(&"latest"));
+        assert!(!contents.contains(&"question 0"));
+        assert_eq!(
+            state
+                .database
+                .get_messages(&session.id)
+                .await
+                .unwrap()
+                .len(),
+            9
+        );
+    }
+
+    #[tokio::test]
+    async fn test_renaming_a_prompt_onto_another_conflicts() {
+        use tower::ServiceExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            ..Config::default()
+        })
+        .await
+        .unwrap();
+        for name in ["terse", "reviewer"] {
+            state
+                .database
+                .create_prompt(&PromptPreset::new(
+                    name.to_string(),
+                    "Be brief.".to_string(),
+                ))
+                .await
+                .unwrap();
+        }
+        let rename = |to: &str| {
+            let request = axum::http::Request::builder()
+                .method(Method::PUT)
+                .uri("/prompts/terse")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    serde_json::json!({"name": to, "body": "Be briefer."}).to_string(),
+                ))
+                .unwrap();
+            router(state.clone()).oneshot(request)
+        };
+
+        let response = rename("reviewer").await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let kept = state
+            .database
+            .get_prompt("reviewer")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(kept.body, "Be brief.");
+
+        let response = rename("short").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(state.database.get_prompt("terse").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_restoring_snapshots_rolls_back_until_passed() {
+        use tower::ServiceExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            ..Config::default()
+        })
+        .await
+        .unwrap();
+        let session = state
+            .database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        let say = |text: &str| Message::user(session.id.clone(), text.to_string());
+        let send = |method: Method, uri: String, body: &'static str| {
+            let request = axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            async {
+                let response = router(state.clone()).oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (
+                    status,
+                    serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+                )
+            }
+        };
+        let snapshots = format!("/sessions/{}/snapshots", session.id);
+
+        state.database.create_message(say("kept")).await.unwrap();
+        let (status, before) =
+            send(Method::POST, snapshots.clone(), r#"{"label": "before"}"#).await;
+        assert_eq!(status, StatusCode::CREATED);
+        state
+            .database
+            .create_message(say("agent step"))
+            .await
+            .unwrap();
+        let (_, after) = send(Method::POST, snapshots.clone(), r#"{"label": "after"}"#).await;
+        let (status, _) = send(Method::POST, snapshots.clone(), r#"{"label": " "}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let restore = |snapshot: &serde_json::Value| {
+            format!(
+                "/sessions/{}/restore/{}",
+                session.id, snapshot["data"]["id"]
+            )
+        };
+        let (status, report) = send(Method::POST, restore(&before), "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["data"]["archived"], 1);
+        let messages = state.database.get_messages(&session.id).await.unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "kept");
+
+        let (status, error) = send(Method::POST, restore(&after), "").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(error["error"].as_str().unwrap().contains("no longer"));
+        let (status, _) = send(
+            Method::POST,
+            format!("/sessions/{}/restore/999", session.id),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, listed) = send(Method::GET, snapshots, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(listed["data"][1]["removed_by"], before["data"]["id"]);
+    }
+
+    #[tokio::test]
+    async fn test_sends_while_offline_queue_until_the_api_is_back() {
+        use crate::client::mock::MockUpstream;
+
+        async fn state_for(config: Config) -> AppState {
+            AppState {
+                chat_service: ChatService::new(&config).unwrap(),
+                database: Database::new(&config).await.unwrap(),
+                pricing: Arc::new(ArcSwap::from_pointee(PricingTable::default())),
+                config: Arc::new(ArcSwap::from_pointee(config)),
+                sessions: Arc::default(),
+                session_locks: SessionLocks::default(),
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let database_url = format!("sqlite:{}", dir.path().join("chat.db").display());
+        // A port nothing listens on refuses the connection
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let offline = state_for(Config {
+            xai_api_key: "test-key".to_string(),
+            xai_base_url: format!("http://{}/v1", closed.local_addr().unwrap()),
+            database_url: database_url.clone(),
+            ..Config::default()
+        })
+        .await;
+        drop(closed);
+        let session = offline
+            .database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+
+        for text in ["one", "two"] {
+            let reply = send_to_session(
+                &offline,
+                &session.id,
+                text.to_string(),
+                "grok-3".to_string(),
+                SendOptions::default(),
+                &Actor::new("test"),
+            )
+            .await
+            .unwrap();
+            assert!(matches!(reply, SessionReply::Queued(message) if message.is_queued()));
+        }
+
+        let upstream = MockUpstream::echo("done").await;
+        let online = state_for(Config {
+            database_url,
+            ..upstream.config()
+        })
+        .await;
+        assert_eq!(flush_queued(&online).await.unwrap(), 2);
+        assert_eq!(upstream.requests().len(), 2);
+
+        let stored = online.database.get_messages(&session.id).await.unwrap();
+        let contents: Vec<&str> = stored.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["one", "done", "two", "done"]);
+        assert!(stored.iter().all(|m| !m.is_queued()));
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_names_the_client_and_request() {
+        use tower::ServiceExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            ..Config::default()
+        })
+        .await
+        .unwrap();
+        let request = axum::http::Request::builder()
+            .method(Method::POST)
+            .uri("/sessions")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("x-request-id", "req-42")
+            .extension(ConnectInfo(SocketAddr::from(([10, 0, 0, 7], 5000))))
+            .body(Body::from(r#"{"model": "grok-3"}"#))
+            .unwrap();
+        let response = router(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let get = |uri: &str| {
+            let request = axum::http::Request::builder()
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap();
+            router(state.clone()).oneshot(request)
+        };
+        let response = get("/audit?actor=api:10.0.0.7&op=session&since=2020-01-01")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let entries: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let entries = entries["data"].as_array().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["operation"], "session.create");
+        assert_eq!(entries[0]["request_id"], "req-42");
+
+        let response = get("/audit?op=message").await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let entries: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(entries["data"], serde_json::json!([]));
+        let response = get("/audit?since=whenever").await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_read_only_server_refuses_every_mutating_route() {
+        use tower::ServiceExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            server_read_only: true,
+            ..Config::default()
+        })
+        .await
+        .unwrap();
+        let session = state
+            .database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        let message = state
+            .database
+            .create_message(Message::user(session.id.clone(), "hello".to_string()))
+            .await
+            .unwrap();
+        let send = |method: Method, uri: String, body: &'static str| {
+            let request = axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            router(state.clone()).oneshot(request)
+        };
+
+        let s = &session.id;
+        let m = format!("/sessions/{}/messages/{}", s, message.id);
+        let mutating = [
+            (Method::POST, "/chat".to_string()),
+            (Method::POST, "/sessions".to_string()),
+            (Method::POST, "/sessions/import?format=generic".to_string()),
+            (Method::PATCH, format!("/sessions/{}", s)),
+            (Method::POST, format!("/sessions/{}/messages", s)),
+            (Method::PATCH, m.clone()),
+            (Method::POST, format!("{}/annotations", m)),
+            (Method::DELETE, format!("{}/annotations", m)),
+            (Method::POST, format!("{}/regenerate", m)),
+            (Method::POST, format!("/sessions/{}/share", s)),
+            (Method::DELETE, format!("/sessions/{}/share", s)),
+            (Method::POST, format!("/sessions/{}/snapshots", s)),
+            (Method::POST, format!("/sessions/{}/restore/1", s)),
+            (Method::POST, "/templates".to_string()),
+            (Method::PUT, "/templates/t".to_string()),
+            (Method::DELETE, "/templates/t".to_string()),
+            (Method::POST, "/templates/t/render".to_string()),
+            (Method::POST, "/prompts".to_string()),
+            (Method::PUT, "/prompts/p".to_string()),
+            (Method::DELETE, "/prompts/p".to_string()),
+            (Method::POST, "/session-templates".to_string()),
+            (Method::PUT, "/session-templates/t".to_string()),
+            (Method::DELETE, "/session-templates/t".to_string()),
+            (Method::POST, "/replay".to_string()),
+            (Method::POST, "/maintenance/dedupe".to_string()),
+            (Method::POST, "/admin/reload".to_string()),
+        ];
+        for (method, uri) in mutating {
+            let label = format!("{} {}", method, uri);
+            let response = send(method, uri, "{}").await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", label);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            let error = error["error"].as_str().unwrap();
+            assert!(error.contains("read-only"), "{}", label);
+        }
+        assert_eq!(state.database.get_total_sessions().await.unwrap(), 1);
+        assert_eq!(state.database.get_messages(s).await.unwrap().len(), 1);
+
+        let response = send(Method::GET, format!("/sessions/{}/messages", s), "")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(Method::GET, "/capabilities".to_string(), "")
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let capabilities: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(capabilities["data"]["read_only"], true);
+
+        // A reload that turns read-only off takes effect on the next request
+        let writable = Config {
+            server_read_only: false,
+            ..(**state.config.load()).clone()
+        };
+        state.config.store(Arc::new(writable));
+        let response = send(
+            Method::POST,
+            "/prompts".to_string(),
+            r#"{"name": "terse", "body": "Be brief."}"#,
+        )
+        .await
+        .unwrap();
+        assert!(response.status().is_success(), "{}", response.status());
+    }
+
+    #[tokio::test]
+    async fn test_models_are_listed_with_their_provider() {
+        use crate::client::mock::{MockReply, MockUpstream};
+        use tower::ServiceExt;
+
+        let xai = MockUpstream::start(|_| {
+            MockReply::Json(200, serde_json::json!({"data": [{"id": "grok-3"}]}))
+        })
+        .await;
+        let local = MockUpstream::start(|_| {
+            MockReply::Json(200, serde_json::json!({"data": [{"id": "llama3"}]}))
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            providers: [("local".to_string(), local.provider())].into(),
+            ..xai.config()
+        })
+        .await
+        .unwrap();
+
+        let request = axum::http::Request::builder()
+            .uri("/models")
+            .body(Body::empty())
+            .unwrap();
+        let response = router(state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let models: Vec<(&str, &str)> = body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|model| {
+                (
+                    model["id"].as_str().unwrap(),
+                    model["provider"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(models, [("grok-3", "xai"), ("local/llama3", "local")]);
+    }
+
+    /// A session send streamed with `timing` scaled down: 10ms of test time
+    /// stands for a second, so heartbeats come every 150ms.
+    async fn stream_send(
+        state: &AppState,
+        session_id: &str,
+        message: &str,
+        max: Duration,
+    ) -> Response {
+        let timing = StreamTiming {
+            heartbeat: Duration::from_millis(150),
+            max,
+        };
+        stream_to_session(
+            state.clone(),
+            session_id.to_string(),
+            message.to_string(),
+            "grok-3".to_string(),
+            SendOptions::default(),
+            Actor::new("test"),
+            timing,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_streamed_sends_stay_alive_while_the_model_thinks() {
+        use crate::client::mock::{MockReply, MockUpstream};
+
+        // 40 seconds of silent thinking before the first chunk
+        let upstream = MockUpstream::start(|_| {
+            MockReply::Delayed(
+                Duration::from_millis(400),
+                Box::new(MockReply::stream(&["Deep", " thought"], Duration::ZERO)),
+            )
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            ..upstream.config()
+        })
+        .await
+        .unwrap();
+        let session = state
+            .database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+
+        let response = stream_send(&state, &session.id, "Think", Duration::from_secs(60)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+        assert_eq!(response.headers()[NO_BUFFERING_HEADER], "no");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        let first_delta = body.find("event: delta").expect("deltas were relayed");
+        let pings = body[..first_delta].matches(": ping\n\n").count();
+        assert!(pings >= 2, "{} heartbeats before the first chunk", pings);
+        assert!(body.contains(r#"data: {"content":"Deep"}"#), "{}", body);
+        assert!(body.contains("event: done"), "{}", body);
+
+        let stored = state.database.get_messages(&session.id).await.unwrap();
+        let contents: Vec<&str> = stored.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["Think", "Deep thought"]);
+        assert!(state.session_locks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_streamed_sends_end_on_timeout_or_disconnect() {
+        use crate::client::mock::{MockReply, MockUpstream};
+
+        let upstream = MockUpstream::start(|_| {
+            MockReply::Delayed(
+                Duration::from_secs(30),
+                Box::new(MockReply::stream(&["late"], Duration::ZERO)),
+            )
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            ..upstream.config()
+        })
+        .await
+        .unwrap();
+        let session = state
+            .database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+
+        // Past the longest a stream may run, it ends with an error event
+        let response = stream_send(&state, &session.id, "one", Duration::from_millis(300)).await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let last = body.trim_end().lines().last().unwrap();
+        assert!(body.contains("event: error"), "{}", body);
+        assert!(last.contains(r#""status":504"#), "{}", body);
+
+        // A client that hangs up during the silence releases the session
+        // without waiting for the model
+        let response = stream_send(&state, &session.id, "two", Duration::from_secs(60)).await;
+        while upstream.requests().len() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!state.session_locks.is_empty());
+        drop(response);
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while !state.session_locks.is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the send was abandoned with its client");
+
+        assert!(state
+            .database
+            .get_messages(&session.id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_streamed_sends_record_resumed_replies() {
+        use crate::client::mock::{MockReply, MockUpstream};
+
+        let upstream = MockUpstream::start(|body| {
+            let messages = body["messages"].as_array().unwrap();
+            if messages.last().unwrap()["content"] == "Greet" {
+                MockReply::cut_off(&["Hel"])
+            } else {
+                MockReply::stream(&["lo"], Duration::ZERO)
+            }
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            stream_resume_attempts: 2,
+            ..upstream.config()
+        })
+        .await
+        .unwrap();
+        let session = state
+            .database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+
+        let response = stream_send(&state, &session.id, "Greet", Duration::from_secs(60)).await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("event: done"));
+
+        let stored = state.database.get_messages(&session.id).await.unwrap();
+        assert_eq!(stored[1].content, "Hello");
+        assert_eq!(stored[1].resumes, Some(1));
     }
 }
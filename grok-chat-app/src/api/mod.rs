@@ -1,2 +1,3 @@
 pub use self::api::*;
+#[allow(clippy::module_inception)]
 mod api;
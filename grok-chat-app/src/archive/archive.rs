@@ -0,0 +1,517 @@
+//! Archives of the whole session database, for backups and for moving to
+//! another machine: every session (or those active since a date) with its
+//! messages and usage, as a directory or a `.tar.gz`, plus an index and the
+//! audit log. Importing restores the sessions only; the log stays a record
+//! of the database it came from.
+//!
+//! Sessions are read and written one at a time, so memory use does not grow
+//! with the database. `sessions import --format archive` reads either form
+//! back, keeping session IDs, models and timestamps.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+
+use crate::database::Database;
+use crate::import::ImportReport;
+use crate::models::{ChatSession, Message, MessageRole, UsageRecord};
+
+pub const INDEX_FILE: &str = "index.json";
+/// The audit log entries of the same period, one JSON object per line.
+pub const AUDIT_FILE: &str = "audit.jsonl";
+const SESSIONS_DIR: &str = "sessions";
+const ARCHIVE_VERSION: u32 = 1;
+
+/// One file under `sessions/`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedSession {
+    pub session: ChatSession,
+    pub messages: Vec<Message>,
+    #[serde(default)]
+    pub usage: Vec<UsageRecord>,
+}
+
+/// `index.json`, written last so it can list what was skipped.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveIndex {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub since: Option<DateTime<Utc>>,
+    pub sessions: Vec<ArchiveEntry>,
+    pub skipped: Vec<SkippedSession>,
+    /// Lines in `audit.jsonl`; archives from before the audit log have none.
+    #[serde(default)]
+    pub audit_entries: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    pub id: String,
+    pub title: Option<String>,
+    pub model: String,
+    pub updated_at: DateTime<Utc>,
+    pub messages: usize,
+    /// Path within the archive.
+    pub file: String,
+}
+
+/// A session left out because it could not be read or serialized.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkippedSession {
+    pub id: String,
+    pub error: String,
+}
+
+/// Whether `path` names a `.tar.gz` rather than a directory.
+pub fn is_tar_gz(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    name.ends_with(".tar.gz") || name.ends_with(".tgz")
+}
+
+/// Where archive entries go. A `.tar.gz` is compressed entry by entry and
+/// handed on as it grows, to a file or to the HTTP response.
+pub struct ArchiveWriter {
+    target: Target,
+}
+
+enum Target {
+    Directory(PathBuf),
+    TarGz {
+        builder: tar::Builder<GzEncoder<Vec<u8>>>,
+        output: Output,
+    },
+}
+
+enum Output {
+    File(File),
+    Channel(mpsc::Sender<Vec<u8>>),
+}
+
+impl ArchiveWriter {
+    /// A `.tar.gz` file for such paths, otherwise a directory, created if
+    /// needed. Existing files with the same names are replaced.
+    pub fn create(path: &Path) -> Result<Self> {
+        if is_tar_gz(path) {
+            let file = File::create(path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            return Ok(Self::tar_gz(Output::File(file)));
+        }
+        std::fs::create_dir_all(path.join(SESSIONS_DIR))
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        Ok(Self {
+            target: Target::Directory(path.to_path_buf()),
+        })
+    }
+
+    /// A `.tar.gz` sent through `sender` chunk by chunk.
+    pub fn streaming(sender: mpsc::Sender<Vec<u8>>) -> Self {
+        Self::tar_gz(Output::Channel(sender))
+    }
+
+    fn tar_gz(output: Output) -> Self {
+        let encoder = GzEncoder::new(Vec::new(), Compression::default());
+        Self {
+            target: Target::TarGz {
+                builder: tar::Builder::new(encoder),
+                output,
+            },
+        }
+    }
+
+    async fn add(&mut self, name: &str, bytes: &[u8]) -> Result<()> {
+        match &mut self.target {
+            Target::Directory(dir) => {
+                let path = dir.join(name);
+                std::fs::write(&path, bytes)
+                    .with_context(|| format!("Failed to write {}", path.display()))
+            }
+            Target::TarGz { builder, output } => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(bytes.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(Utc::now().timestamp().max(0) as u64);
+                builder.append_data(&mut header, name, bytes)?;
+                // The encoder only appends, so what it has written so far can go
+                let compressed = std::mem::take(builder.get_mut().get_mut());
+                output.write(compressed).await
+            }
+        }
+    }
+
+    async fn finish(self) -> Result<()> {
+        match self.target {
+            Target::Directory(_) => Ok(()),
+            Target::TarGz {
+                builder,
+                mut output,
+            } => {
+                let rest = builder.into_inner()?.finish()?;
+                output.write(rest).await?;
+                if let Output::File(file) = &mut output {
+                    file.flush()?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Output {
+    async fn write(&mut self, bytes: Vec<u8>) -> Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        match self {
+            Output::File(file) => Ok(file.write_all(&bytes)?),
+            Output::Channel(sender) => sender
+                .send(bytes)
+                .await
+                .map_err(|_| anyhow::anyhow!("The archive download was cancelled")),
+        }
+    }
+}
+
+/// Writes every session updated since `since` (all of them when `None`) and
+/// the index. A session that fails to load or serialize is listed under
+/// `skipped` instead of ending the export.
+pub async fn export_archive(
+    database: &Database,
+    since: Option<DateTime<Utc>>,
+    mut writer: ArchiveWriter,
+) -> Result<ArchiveIndex> {
+    let mut index = ArchiveIndex {
+        version: ARCHIVE_VERSION,
+        exported_at: Utc::now(),
+        since,
+        sessions: Vec::new(),
+        skipped: Vec::new(),
+        audit_entries: 0,
+    };
+
+    for session in database.sessions_updated_since(since).await? {
+        let archived = match archive_session(database, session.clone()).await {
+            Ok(archived) => archived,
+            Err(e) => {
+                index.skipped.push(SkippedSession {
+                    id: session.id,
+                    error: format!("{:#}", e),
+                });
+                continue;
+            }
+        };
+        let file = format!("{}/{}.json", SESSIONS_DIR, file_stem(&session.id));
+        writer.add(&file, &archived.1).await?;
+        index.sessions.push(ArchiveEntry {
+            id: session.id,
+            title: session.title,
+            model: session.model,
+            updated_at: session.updated_at,
+            messages: archived.0,
+            file,
+        });
+    }
+
+    let mut audit = Vec::new();
+    for entry in database.audit_log_since(since).await? {
+        serde_json::to_writer(&mut audit, &entry)?;
+        audit.push(b'\n');
+        index.audit_entries += 1;
+    }
+    writer.add(AUDIT_FILE, &audit).await?;
+
+    writer
+        .add(INDEX_FILE, &serde_json::to_vec_pretty(&index)?)
+        .await?;
+    writer.finish().await?;
+    Ok(index)
+}
+
+/// The message count and JSON of one session.
+async fn archive_session(database: &Database, session: ChatSession) -> Result<(usize, Vec<u8>)> {
+    let archived = ArchivedSession {
+        messages: database.get_messages(&session.id).await?,
+        usage: database.usage_for_session(&session.id).await?,
+        session,
+    };
+    Ok((
+        archived.messages.len(),
+        serde_json::to_vec_pretty(&archived)?,
+    ))
+}
+
+/// Session IDs are UUIDs, but imported ones need not be; keep them from
+/// escaping the sessions directory.
+fn file_stem(id: &str) -> String {
+    id.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// The sessions found in an archive, and how many of its files were not
+/// readable session files.
+#[derive(Debug, Clone, Default)]
+pub struct ParsedArchive {
+    pub sessions: Vec<ArchivedSession>,
+    pub malformed: usize,
+}
+
+impl ParsedArchive {
+    fn add(&mut self, bytes: &[u8]) {
+        match serde_json::from_slice::<ArchivedSession>(bytes) {
+            Ok(session) => self.sessions.push(session),
+            Err(_) => self.malformed += 1,
+        }
+    }
+}
+
+/// Reads an archive directory or `.tar.gz` file written by [`export_archive`].
+pub fn read_archive(path: &Path) -> Result<ParsedArchive> {
+    if !path.is_dir() {
+        let file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        return read_tar_gz(file);
+    }
+
+    let dir = path.join(SESSIONS_DIR);
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .with_context(|| format!("{} has no {} directory", path.display(), SESSIONS_DIR))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    entries.sort();
+
+    let mut parsed = ParsedArchive::default();
+    for entry in entries {
+        if entry.extension().is_some_and(|ext| ext == "json") {
+            parsed.add(&std::fs::read(&entry)?);
+        }
+    }
+    Ok(parsed)
+}
+
+/// Reads a `.tar.gz` archive from any reader, such as an upload.
+pub fn read_tar_gz(reader: impl Read) -> Result<ParsedArchive> {
+    let mut archive = tar::Archive::new(GzDecoder::new(reader));
+    let mut parsed = ParsedArchive::default();
+    let mut found_index = false;
+
+    for entry in archive.entries().context("Not a .tar.gz archive")? {
+        let mut entry = entry.context("Corrupt archive")?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        if path == INDEX_FILE {
+            found_index = true;
+            continue;
+        }
+        if !(path.starts_with(SESSIONS_DIR) && path.ends_with(".json")) {
+            continue;
+        }
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+        parsed.add(&bytes);
+    }
+
+    if !found_index && parsed.sessions.is_empty() {
+        bail!("Not a session archive: no {} or session files", INDEX_FILE);
+    }
+    Ok(parsed)
+}
+
+/// Restores archived sessions under their original IDs. Sessions that
+/// already exist are counted as duplicates and left untouched.
+pub async fn import_archive(database: &Database, parsed: ParsedArchive) -> Result<ImportReport> {
+    let mut report = ImportReport {
+        malformed: parsed.malformed,
+        ..ImportReport::default()
+    };
+
+    for archived in parsed.sessions {
+        if archived
+            .messages
+            .iter()
+            .any(|message| matches!(message.role, MessageRole::Other(_)))
+        {
+            report.malformed += 1;
+            continue;
+        }
+        if database.get_session(&archived.session.id).await?.is_some() {
+            report.duplicates += 1;
+            continue;
+        }
+
+        database
+            .import_session(&archived.session, &archived.messages, &archived.usage, None)
+            .await
+            .with_context(|| format!("Failed to import session {}", archived.session.id))?;
+        report.sessions_imported += 1;
+        report.messages_imported += archived.messages.len();
+        report.session_ids.push(archived.session.id);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::models::{CompletionStats, UsageStats};
+
+    async fn database(dir: &Path, name: &str) -> Database {
+        let config = Config {
+            database_url: format!("sqlite:{}?mode=rwc", dir.join(name).display()),
+            ..Config::default()
+        };
+        Database::new(&config).await.unwrap()
+    }
+
+    async fn seeded(dir: &Path) -> (Database, ChatSession) {
+        let db = database(dir, "source.db").await;
+        let session = db
+            .create_session(ChatSession::new("grok-3".to_string(), Some("Trip".into())))
+            .await
+            .unwrap();
+        db.create_message(Message::user(session.id.clone(), "Plan a trip".into()))
+            .await
+            .unwrap();
+        let mut reply = Message::assistant(session.id.clone(), "Go to Porto".into(), None);
+        reply.pinned = true;
+        db.create_message(reply).await.unwrap();
+        let usage = UsageStats {
+            prompt_tokens: 10,
+            completion_tokens: 4,
+            total_tokens: 14,
+        };
+        let stats = CompletionStats {
+            latency_ms: Some(420),
+            finish_reason: Some("stop".to_string()),
+        };
+        db.record_usage(Some(&session.id), "grok-3", &usage, &stats)
+            .await
+            .unwrap();
+        (db, session)
+    }
+
+    #[tokio::test]
+    async fn test_tar_gz_round_trip_restores_sessions_and_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, session) = seeded(dir.path()).await;
+        let path = dir.path().join("all.tar.gz");
+
+        let index = export_archive(&source, None, ArchiveWriter::create(&path).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(index.sessions.len(), 1);
+        assert_eq!(index.sessions[0].messages, 2);
+        assert!(index.skipped.is_empty());
+
+        let target = database(dir.path(), "target.db").await;
+        let report = import_archive(&target, read_archive(&path).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(report.sessions_imported, 1);
+        assert_eq!(report.messages_imported, 2);
+
+        let restored = target.export_session(&session.id).await.unwrap().unwrap();
+        assert_eq!(restored.session.title.as_deref(), Some("Trip"));
+        assert_eq!(restored.session.model, "grok-3");
+        assert!(restored.messages[1].pinned);
+        let usage = target.usage_for_session(&session.id).await.unwrap();
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0].completion_tokens, 4);
+        assert_eq!(usage[0].latency_ms, Some(420));
+
+        // Importing again changes nothing
+        let again = import_archive(&target, read_archive(&path).unwrap())
+            .await
+            .unwrap();
+        assert_eq!((again.sessions_imported, again.duplicates), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn test_directory_export_filters_by_date_and_skips_bad_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, _) = seeded(dir.path()).await;
+        // A role this version cannot read, as a newer version might store
+        let corrupt = ChatSession {
+            id: "corrupt".to_string(),
+            ..ChatSession::new("grok-3".to_string(), None)
+        };
+        source
+            .import_session(&corrupt, &[], &[], None)
+            .await
+            .unwrap();
+        let pool = sqlx::SqlitePool::connect(&format!(
+            "sqlite:{}",
+            dir.path().join("source.db").display()
+        ))
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO messages (session_id, role, content, timestamp) \
+             VALUES ('corrupt', 'wizard', 'abracadabra', '2025-01-01T00:00:00Z')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let out = dir.path().join("export");
+        let index = export_archive(&source, None, ArchiveWriter::create(&out).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(index.sessions.len(), 1);
+        assert_eq!(index.skipped.len(), 1);
+        assert_eq!(index.skipped[0].id, "corrupt");
+        assert!(out.join(INDEX_FILE).exists());
+        assert_eq!(read_archive(&out).unwrap().sessions.len(), 1);
+
+        let future = Utc::now() + chrono::Duration::days(1);
+        let later = dir.path().join("later");
+        let index = export_archive(
+            &source,
+            Some(future),
+            ArchiveWriter::create(&later).unwrap(),
+        )
+        .await
+        .unwrap();
+        assert!(index.sessions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_streaming_sends_chunks_that_form_an_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, _) = seeded(dir.path()).await;
+        let (sender, mut receiver) = mpsc::channel(1);
+
+        let export = tokio::spawn(async move {
+            export_archive(&source, None, ArchiveWriter::streaming(sender)).await
+        });
+        let mut bytes = Vec::new();
+        let mut chunks = 0;
+        while let Some(chunk) = receiver.recv().await {
+            bytes.extend(chunk);
+            chunks += 1;
+        }
+        export.await.unwrap().unwrap();
+
+        assert!(chunks > 1);
+        assert_eq!(read_tar_gz(bytes.as_slice()).unwrap().sessions.len(), 1);
+        assert!(read_tar_gz(&b"not an archive"[..]).is_err());
+    }
+
+    #[test]
+    fn test_file_stem_stays_in_the_directory() {
+        assert_eq!(file_stem("../../etc/passwd"), "______etc_passwd");
+        assert_eq!(file_stem("3f36f6fd-6573"), "3f36f6fd-6573");
+    }
+}
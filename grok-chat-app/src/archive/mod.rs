@@ -0,0 +1,3 @@
+pub use self::archive::*;
+#[allow(clippy::module_inception)]
+mod archive;
@@ -0,0 +1,143 @@
+//! The audit log: who changed what, and when. Entries are only ever
+//! appended; the database refuses to update or delete them, and nothing here
+//! offers a way to.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use uuid::Uuid;
+
+/// Who made a change. The HTTP server has no accounts, so requests are
+/// known by the address they came from; commands by the local user.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Actor {
+    pub name: String,
+    /// Ties the entries one HTTP request wrote together.
+    pub request_id: Option<String>,
+}
+
+impl Actor {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            request_id: None,
+        }
+    }
+
+    /// The user running a command, as `cli:<login>`.
+    pub fn cli() -> Self {
+        let user = std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_else(|_| "unknown".to_string());
+        Self::new(format!("cli:{}", user))
+    }
+
+    /// `request_id`, or a new one when the client sent none.
+    pub fn with_request_id(mut self, request_id: Option<String>) -> Self {
+        self.request_id = Some(request_id.unwrap_or_else(|| Uuid::new_v4().to_string()));
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditOp {
+    SessionCreate,
+    SessionDelete,
+    SessionMigrateModel,
+    SessionSnapshot,
+    SessionRestore,
+    MessageCreate,
+    ConfigReload,
+    MaintenancePrune,
+    MaintenanceVacuum,
+    MaintenanceMedia,
+    MaintenanceDedupe,
+}
+
+impl AuditOp {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditOp::SessionCreate => "session.create",
+            AuditOp::SessionDelete => "session.delete",
+            AuditOp::SessionMigrateModel => "session.migrate_model",
+            AuditOp::SessionSnapshot => "session.snapshot",
+            AuditOp::SessionRestore => "session.restore",
+            AuditOp::MessageCreate => "message.create",
+            AuditOp::ConfigReload => "config.reload",
+            AuditOp::MaintenancePrune => "maintenance.prune",
+            AuditOp::MaintenanceVacuum => "maintenance.vacuum",
+            AuditOp::MaintenanceMedia => "maintenance.media",
+            AuditOp::MaintenanceDedupe => "maintenance.dedupe",
+        }
+    }
+}
+
+impl fmt::Display for AuditOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What an entry is about, before it is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub op: AuditOp,
+    pub session_id: Option<String>,
+    pub message_id: Option<i64>,
+    /// Anything else worth knowing, such as how many sessions a prune
+    /// removed.
+    pub detail: Option<String>,
+}
+
+impl AuditRecord {
+    pub fn new(op: AuditOp) -> Self {
+        Self {
+            op,
+            session_id: None,
+            message_id: None,
+            detail: None,
+        }
+    }
+
+    pub fn session(mut self, session_id: impl Into<String>) -> Self {
+        self.session_id = Some(session_id.into());
+        self
+    }
+
+    pub fn message(mut self, message_id: i64) -> Self {
+        self.message_id = Some(message_id);
+        self
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+/// A stored entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub timestamp: DateTime<Utc>,
+    pub actor: String,
+    pub operation: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// Narrows `Database::audit_entries`; unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub since: Option<DateTime<Utc>>,
+    pub actor: Option<String>,
+    /// An operation, or a family of them: `maintenance` matches
+    /// `maintenance.prune` and the rest.
+    pub op: Option<String>,
+}
@@ -0,0 +1,3 @@
+pub use self::audit::*;
+#[allow(clippy::module_inception)]
+mod audit;
@@ -0,0 +1,164 @@
+//! API key storage in the OS keyring: the macOS Keychain, Windows Credential
+//! Manager or the Secret Service on Linux. Keys are stored per profile. Builds
+//! without the `keyring` feature, and other platforms, report the keyring as
+//! unavailable so callers fall back to `XAI_API_KEY`.
+
+use anyhow::Result;
+use std::fmt;
+
+/// Service name keys are stored under; the entry's user is the profile name.
+pub const KEYRING_SERVICE: &str = "grok-chat";
+
+/// Profile used for keyring entries when none is selected.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// When set, the configuration never consults the keyring.
+pub const NO_KEYRING_VAR: &str = "GROK_NO_KEYRING";
+
+/// Where the API key in use came from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ApiKeySource {
+    #[default]
+    Missing,
+    Env,
+    ConfigFile,
+    Keyring,
+}
+
+impl fmt::Display for ApiKeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiKeySource::Missing => write!(f, "not set"),
+            ApiKeySource::Env => write!(f, "XAI_API_KEY environment variable"),
+            ApiKeySource::ConfigFile => write!(f, "config file"),
+            ApiKeySource::Keyring => write!(f, "OS keyring"),
+        }
+    }
+}
+
+/// The API rejected a key offered to `auth login`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidApiKey;
+
+impl fmt::Display for InvalidApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The xAI API rejected this key")
+    }
+}
+
+impl std::error::Error for InvalidApiKey {}
+
+/// There is no usable keyring in this build or on this machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyringUnavailable(pub String);
+
+impl fmt::Display for KeyringUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No OS keyring available: {}", self.0)
+    }
+}
+
+impl std::error::Error for KeyringUnavailable {}
+
+pub fn keyring_enabled() -> bool {
+    std::env::var_os(NO_KEYRING_VAR).is_none()
+}
+
+/// The key stored for `profile`, or `None` if there is none.
+pub fn stored_api_key(profile: &str) -> Result<Option<String>> {
+    store::get(profile)
+}
+
+/// The secret stored under `name`, as `keyring:<name>` config values refer
+/// to. Keys saved with `auth login` are stored under their profile's name.
+pub fn stored_secret(name: &str) -> Result<Option<String>> {
+    store::get(name)
+}
+
+pub fn store_api_key(profile: &str, key: &str) -> Result<()> {
+    store::set(profile, key)
+}
+
+/// Removes the key stored for `profile`, returning whether there was one.
+pub fn delete_api_key(profile: &str) -> Result<bool> {
+    store::delete(profile)
+}
+
+#[cfg(all(
+    feature = "keyring",
+    any(target_os = "linux", target_os = "macos", target_os = "windows")
+))]
+mod store {
+    use super::{KeyringUnavailable, KEYRING_SERVICE};
+    use anyhow::Result;
+    use keyring::{Entry, Error};
+
+    /// The Secret Service backend drives its own tokio runtime, which panics
+    /// inside ours, so every keyring call runs on a short-lived thread.
+    fn off_runtime<T: Send>(call: impl FnOnce() -> Result<T> + Send) -> Result<T> {
+        std::thread::scope(|scope| {
+            scope
+                .spawn(call)
+                .join()
+                .unwrap_or_else(|_| Err(KeyringUnavailable("backend panicked".into()).into()))
+        })
+    }
+
+    fn entry(profile: &str) -> Result<Entry> {
+        Entry::new(KEYRING_SERVICE, profile).map_err(unavailable)
+    }
+
+    fn unavailable(error: Error) -> anyhow::Error {
+        match error {
+            Error::PlatformFailure(e) | Error::NoStorageAccess(e) => {
+                KeyringUnavailable(e.to_string()).into()
+            }
+            other => other.into(),
+        }
+    }
+
+    pub fn get(profile: &str) -> Result<Option<String>> {
+        off_runtime(|| match entry(profile)?.get_password() {
+            Ok(key) => Ok(Some(key)),
+            Err(Error::NoEntry) => Ok(None),
+            Err(e) => Err(unavailable(e)),
+        })
+    }
+
+    pub fn set(profile: &str, key: &str) -> Result<()> {
+        off_runtime(|| entry(profile)?.set_password(key).map_err(unavailable))
+    }
+
+    pub fn delete(profile: &str) -> Result<bool> {
+        off_runtime(|| match entry(profile)?.delete_credential() {
+            Ok(()) => Ok(true),
+            Err(Error::NoEntry) => Ok(false),
+            Err(e) => Err(unavailable(e)),
+        })
+    }
+}
+
+#[cfg(not(all(
+    feature = "keyring",
+    any(target_os = "linux", target_os = "macos", target_os = "windows")
+)))]
+mod store {
+    use super::KeyringUnavailable;
+    use anyhow::Result;
+
+    fn unavailable() -> anyhow::Error {
+        KeyringUnavailable("this build has no keyring support".to_string()).into()
+    }
+
+    pub fn get(_profile: &str) -> Result<Option<String>> {
+        Err(unavailable())
+    }
+
+    pub fn set(_profile: &str, _key: &str) -> Result<()> {
+        Err(unavailable())
+    }
+
+    pub fn delete(_profile: &str) -> Result<bool> {
+        Err(unavailable())
+    }
+}
@@ -0,0 +1,3 @@
+pub use self::auth::*;
+#[allow(clippy::module_inception)]
+mod auth;
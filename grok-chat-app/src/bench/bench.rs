@@ -0,0 +1,403 @@
+//! `bench`: load-tests the HTTP API server. Concurrent workers each create a
+//! session and send messages into it until the requested number of messages
+//! is reached; the report gives latency percentiles, error rates and
+//! throughput per kind of call.
+
+use anyhow::{anyhow, Result};
+use reqwest::{Client, RequestBuilder};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// What to run.
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    /// Base URL of the server, e.g. `http://localhost:3000`.
+    pub target: String,
+    /// Workers sending at once, each into its own session.
+    pub concurrency: usize,
+    /// Messages to send in total.
+    pub requests: usize,
+    /// Model sessions are created with; `None` for the server's default.
+    pub model: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    CreateSession,
+    SendMessage,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::CreateSession => write!(f, "create session"),
+            Operation::SendMessage => write!(f, "send message"),
+        }
+    }
+}
+
+/// One timed call.
+#[derive(Debug, Clone)]
+struct Sample {
+    operation: Operation,
+    latency: Duration,
+    /// What went wrong, e.g. `HTTP 503`; `None` when the call succeeded.
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OperationStats {
+    pub operation: Operation,
+    pub count: usize,
+    pub errors: usize,
+    pub error_rate: f64,
+    /// Latencies of the calls that succeeded, in milliseconds, so fast
+    /// failures don't flatter them.
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+/// The `bench` result; `--output json` prints it as is.
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchReport {
+    pub target: String,
+    pub concurrency: usize,
+    pub elapsed_ms: u64,
+    /// Calls of either kind completed per second.
+    pub throughput_rps: f64,
+    pub operations: Vec<OperationStats>,
+    /// How many calls failed with each error.
+    pub errors: BTreeMap<String, usize>,
+}
+
+impl BenchReport {
+    fn new(options: &BenchOptions, elapsed: Duration, samples: &[Sample]) -> Self {
+        let mut by_operation: BTreeMap<Operation, Vec<&Sample>> = BTreeMap::new();
+        let mut errors = BTreeMap::new();
+        for sample in samples {
+            by_operation
+                .entry(sample.operation)
+                .or_default()
+                .push(sample);
+            if let Some(error) = &sample.error {
+                *errors.entry(error.clone()).or_insert(0) += 1;
+            }
+        }
+
+        let operations = by_operation
+            .into_iter()
+            .map(|(operation, samples)| {
+                let mut latencies: Vec<Duration> = samples
+                    .iter()
+                    .filter(|sample| sample.error.is_none())
+                    .map(|sample| sample.latency)
+                    .collect();
+                latencies.sort();
+                let failed = samples.len() - latencies.len();
+                OperationStats {
+                    operation,
+                    count: samples.len(),
+                    errors: failed,
+                    error_rate: failed as f64 / samples.len() as f64,
+                    p50_ms: millis(percentile(&latencies, 50)),
+                    p95_ms: millis(percentile(&latencies, 95)),
+                    p99_ms: millis(percentile(&latencies, 99)),
+                    max_ms: millis(latencies.last().copied().unwrap_or_default()),
+                }
+            })
+            .collect();
+
+        let seconds = elapsed.as_secs_f64();
+        Self {
+            target: options.target.clone(),
+            concurrency: options.concurrency,
+            elapsed_ms: elapsed.as_millis() as u64,
+            throughput_rps: if seconds > 0.0 {
+                samples.len() as f64 / seconds
+            } else {
+                0.0
+            },
+            operations,
+            errors,
+        }
+    }
+
+    /// The report as a table, for `--output text`.
+    pub fn table(&self) -> String {
+        let mut out = format!(
+            "{} · concurrency {} · {:.1}s · {:.1} req/s\n\n",
+            self.target,
+            self.concurrency,
+            self.elapsed_ms as f64 / 1000.0,
+            self.throughput_rps
+        );
+        out.push_str(&format!(
+            "{:<16} {:>6} {:>7} {:>9} {:>9} {:>9} {:>9}\n",
+            "operation", "count", "errors", "p50", "p95", "p99", "max"
+        ));
+        for stats in &self.operations {
+            out.push_str(&format!(
+                "{:<16} {:>6} {:>6.1}% {:>7.0}ms {:>7.0}ms {:>7.0}ms {:>7.0}ms\n",
+                stats.operation.to_string(),
+                stats.count,
+                stats.error_rate * 100.0,
+                stats.p50_ms,
+                stats.p95_ms,
+                stats.p99_ms,
+                stats.max_ms
+            ));
+        }
+        if !self.errors.is_empty() {
+            out.push_str("\nErrors:\n");
+            for (error, count) in &self.errors {
+                out.push_str(&format!("  {:>6}  {}\n", count, error));
+            }
+        }
+        out
+    }
+}
+
+/// Nearest-rank percentile of `sorted`; zero when it is empty.
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Runs the benchmark. Fails only when the server can't be reached at all;
+/// failed calls during the run are counted in the report.
+pub async fn run_bench(options: &BenchOptions) -> Result<BenchReport> {
+    let client = Client::builder()
+        .pool_max_idle_per_host(options.concurrency)
+        .build()?;
+    let target = options.target.trim_end_matches('/').to_string();
+    client
+        .get(format!("{}/health", target))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| anyhow!("No API server at {}: {}", target, e))?;
+
+    let options = Arc::new(BenchOptions {
+        target,
+        ..options.clone()
+    });
+    let next = Arc::new(AtomicUsize::new(0));
+    let started = Instant::now();
+    let workers: Vec<_> = (0..options.concurrency.min(options.requests))
+        .map(|_| tokio::spawn(worker(client.clone(), options.clone(), next.clone())))
+        .collect();
+    let mut samples = Vec::new();
+    for worker in workers {
+        samples.extend(worker.await?);
+    }
+
+    Ok(BenchReport::new(&options, started.elapsed(), &samples))
+}
+
+/// Takes message slots until none are left. A worker creates its session
+/// before its first message; when that fails, the slot is spent on the
+/// failed call and the next one tries again.
+async fn worker(client: Client, options: Arc<BenchOptions>, next: Arc<AtomicUsize>) -> Vec<Sample> {
+    let mut samples = Vec::new();
+    let mut session_id: Option<String> = None;
+    while next.fetch_add(1, Ordering::Relaxed) < options.requests {
+        let id = match &session_id {
+            Some(id) => id.clone(),
+            None => {
+                let create = client
+                    .post(format!("{}/sessions", options.target))
+                    .json(&json!({ "model": options.model, "title": "bench" }));
+                let (sample, body) = call(Operation::CreateSession, create).await;
+                samples.push(sample);
+                match body.as_ref().and_then(|body| body["data"]["id"].as_str()) {
+                    Some(id) => session_id.insert(id.to_string()).clone(),
+                    None => continue,
+                }
+            }
+        };
+        let send = client
+            .post(format!("{}/sessions/{}/messages", options.target, id))
+            .json(&json!({ "message": options.message }));
+        samples.push(call(Operation::SendMessage, send).await.0);
+    }
+    samples
+}
+
+/// Sends `request` and times it through to the end of the response body.
+/// Only a 200 counts as success: a 202 means the server couldn't reach the
+/// model and queued the message.
+async fn call(operation: Operation, request: RequestBuilder) -> (Sample, Option<Value>) {
+    let started = Instant::now();
+    let result = match request.send().await {
+        Ok(response) if response.status() == reqwest::StatusCode::OK => response
+            .json::<Value>()
+            .await
+            .map_err(|_| "invalid response body".to_string()),
+        Ok(response) => Err(format!("HTTP {}", response.status().as_u16())),
+        Err(e) if e.is_timeout() => Err("timed out".to_string()),
+        Err(e) if e.is_connect() => Err("connection failed".to_string()),
+        Err(_) => Err("request failed".to_string()),
+    };
+    let sample = Sample {
+        operation,
+        latency: started.elapsed(),
+        error: result.as_ref().err().cloned(),
+    };
+    (sample, result.ok())
+}
+
+/// An API server on an ephemeral port with a throwaway database, in front of
+/// an in-process fake xAI API that answers every completion after a fixed
+/// delay. Benchmarking against it measures the server rather than the model.
+#[cfg(feature = "mock")]
+pub struct MockTarget {
+    url: String,
+    database: std::path::PathBuf,
+    server: tokio::task::JoinHandle<()>,
+    _upstream: crate::client::mock::MockUpstream,
+}
+
+#[cfg(feature = "mock")]
+impl MockTarget {
+    pub async fn start(delay: Duration) -> Result<Self> {
+        use crate::api::{router, AppState};
+        use crate::client::mock::{MockReply, MockUpstream};
+        use crate::config::Config;
+
+        let upstream = MockUpstream::start(move |_| {
+            MockReply::Delayed(delay, Box::new(MockReply::completion("pong")))
+        })
+        .await;
+        let database =
+            std::env::temp_dir().join(format!("grok-chat-bench-{}.db", uuid::Uuid::new_v4()));
+        let config = Config {
+            database_url: format!("sqlite:{}", database.display()),
+            ..upstream.config()
+        };
+        let app = router(AppState::new(config).await?);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let server = tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+        Ok(Self {
+            url,
+            database,
+            server,
+            _upstream: upstream,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+#[cfg(feature = "mock")]
+impl Drop for MockTarget {
+    fn drop(&mut self) {
+        self.server.abort();
+        for suffix in ["", "-wal", "-shm", "-journal"] {
+            let mut path = self.database.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(operation: Operation, millis: u64, error: Option<&str>) -> Sample {
+        Sample {
+            operation,
+            latency: Duration::from_millis(millis),
+            error: error.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_percentiles_use_nearest_rank() {
+        let sorted: Vec<Duration> = (1..=200).map(Duration::from_millis).collect();
+
+        assert_eq!(percentile(&sorted, 50), Duration::from_millis(100));
+        assert_eq!(percentile(&sorted, 95), Duration::from_millis(190));
+        assert_eq!(percentile(&sorted, 99), Duration::from_millis(198));
+        assert_eq!(percentile(&sorted[..1], 99), Duration::from_millis(1));
+        assert_eq!(percentile(&[], 50), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_report_separates_operations_and_failures() {
+        let options = BenchOptions {
+            target: "http://localhost:3000".to_string(),
+            concurrency: 2,
+            requests: 3,
+            model: None,
+            message: "hi".to_string(),
+        };
+        let samples = [
+            sample(Operation::CreateSession, 5, None),
+            sample(Operation::SendMessage, 100, None),
+            sample(Operation::SendMessage, 300, None),
+            sample(Operation::SendMessage, 1, Some("HTTP 503")),
+        ];
+
+        let report = BenchReport::new(&options, Duration::from_secs(2), &samples);
+
+        assert_eq!(report.throughput_rps, 2.0);
+        assert_eq!(report.errors, BTreeMap::from([("HTTP 503".to_string(), 1)]));
+        let send = &report.operations[1];
+        assert_eq!(send.operation, Operation::SendMessage);
+        assert_eq!((send.count, send.errors), (3, 1));
+        assert!((send.error_rate - 1.0 / 3.0).abs() < 1e-9);
+        // The failed call's 1ms is left out of the latencies
+        assert_eq!((send.p50_ms, send.max_ms), (100.0, 300.0));
+        assert!(report.table().contains("send message"));
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_bench_against_mock_target() {
+        let target = MockTarget::start(Duration::from_millis(10)).await.unwrap();
+        let options = BenchOptions {
+            target: target.url().to_string(),
+            concurrency: 3,
+            requests: 7,
+            model: None,
+            message: "ping".to_string(),
+        };
+
+        let report = run_bench(&options).await.unwrap();
+
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        let counts: Vec<_> = report
+            .operations
+            .iter()
+            .map(|stats| (stats.operation, stats.count))
+            .collect();
+        assert_eq!(
+            counts,
+            [(Operation::CreateSession, 3), (Operation::SendMessage, 7)]
+        );
+        assert!(report.operations[1].p50_ms >= 10.0);
+    }
+}
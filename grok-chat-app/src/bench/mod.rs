@@ -0,0 +1,3 @@
+pub use self::bench::*;
+#[allow(clippy::module_inception)]
+mod bench;
@@ -0,0 +1,517 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::config::Config;
+use crate::models::ApiChatRequest;
+
+/// What a model accepts and supports. Requests are fitted to it before they
+/// are sent, since the API answers unsupported parameters with a 400.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ModelProfile {
+    /// Prompt plus reply, in tokens.
+    pub context_window: u32,
+    /// Upper bound for `max_tokens`.
+    pub max_output_tokens: Option<i32>,
+    /// Used when a request leaves `max_tokens` unset.
+    pub default_max_tokens: Option<i32>,
+    /// Used when a request leaves `temperature` unset.
+    pub default_temperature: Option<f32>,
+    pub temperature: bool,
+    pub logprobs: bool,
+    pub streaming: bool,
+    pub tools: bool,
+    pub vision: bool,
+    /// Thinks before answering; informational.
+    pub reasoning: bool,
+}
+
+impl ModelProfile {
+    const fn chat(context_window: u32) -> Self {
+        Self {
+            context_window,
+            max_output_tokens: None,
+            default_max_tokens: None,
+            default_temperature: None,
+            temperature: true,
+            logprobs: true,
+            streaming: true,
+            tools: true,
+            vision: false,
+            reasoning: false,
+        }
+    }
+
+    /// Reasoning models pick their own sampling and report no logprobs.
+    const fn reasoning(context_window: u32) -> Self {
+        Self {
+            temperature: false,
+            logprobs: false,
+            reasoning: true,
+            ..Self::chat(context_window)
+        }
+    }
+
+    /// One line for pickers and listings, e.g. `256K context, reasoning, vision`.
+    pub fn summary(&self) -> String {
+        let mut parts = vec![format!("{} context", compact(self.context_window))];
+        if let Some(limit) = self.max_output_tokens {
+            parts.push(format!("≤{} output", compact(limit.max(0) as u32)));
+        }
+        if self.reasoning {
+            parts.push("reasoning".to_string());
+        }
+        if !self.temperature {
+            parts.push("no temperature".to_string());
+        }
+        if self.vision {
+            parts.push("vision".to_string());
+        }
+        if !self.streaming {
+            parts.push("no streaming".to_string());
+        }
+        parts.join(", ")
+    }
+
+    fn fit(&self, request: &mut ApiChatRequest, defaults: RequestDefaults) -> Vec<ParamWarning> {
+        let model = request.model.clone();
+        let mut warnings = Vec::new();
+        let mut warn = |parameter, change| {
+            warnings.push(ParamWarning {
+                model: model.clone(),
+                parameter,
+                change,
+            })
+        };
+
+        // Defaults are only filled in where the model takes them, and within
+        // its limit, so only what was asked for is warned about
+        if request.temperature.is_none() && self.temperature {
+            request.temperature = self.default_temperature.or(defaults.temperature);
+        }
+        if !self.temperature && request.temperature.take().is_some() {
+            warn("temperature", ParamChange::Dropped);
+        }
+        if !self.logprobs && request.logprobs {
+            request.logprobs = false;
+            request.top_logprobs = 0;
+            warn("logprobs", ParamChange::Dropped);
+        }
+
+        if request.max_tokens.is_none() {
+            request.max_tokens = self
+                .default_max_tokens
+                .or(defaults.max_tokens)
+                .map(|tokens| {
+                    self.max_output_tokens
+                        .map_or(tokens, |limit| tokens.min(limit))
+                });
+        }
+        if let (Some(requested), Some(limit)) = (request.max_tokens, self.max_output_tokens) {
+            if requested > limit {
+                request.max_tokens = Some(limit);
+                warn(
+                    "max_tokens",
+                    ParamChange::Clamped {
+                        requested,
+                        sent: limit,
+                    },
+                );
+            }
+        }
+        warnings
+    }
+}
+
+fn compact(tokens: u32) -> String {
+    match tokens {
+        t if t >= 1_000_000 && t % 1_000_000 == 0 => format!("{}M", t / 1_000_000),
+        t if t >= 1_000 => format!("{}K", t / 1_000),
+        t => t.to_string(),
+    }
+}
+
+/// What requests that leave `temperature` or `max_tokens` unset get when
+/// their model's profile has no default of its own: the configured ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RequestDefaults {
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<i32>,
+}
+
+impl RequestDefaults {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            temperature: Some(config.temperature()),
+            max_tokens: Some(config.max_tokens()),
+        }
+    }
+}
+
+/// A `[default.model_profiles.<prefix>]` section of `config.toml`; set keys
+/// replace those of the built-in profile the prefix resolves to.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileOverride {
+    pub context_window: Option<u32>,
+    pub max_output_tokens: Option<i32>,
+    pub default_max_tokens: Option<i32>,
+    pub default_temperature: Option<f32>,
+    pub temperature: Option<bool>,
+    pub logprobs: Option<bool>,
+    pub streaming: Option<bool>,
+    pub tools: Option<bool>,
+    pub vision: Option<bool>,
+    pub reasoning: Option<bool>,
+}
+
+impl ProfileOverride {
+    fn apply(&self, base: ModelProfile) -> ModelProfile {
+        ModelProfile {
+            context_window: self.context_window.unwrap_or(base.context_window),
+            max_output_tokens: self.max_output_tokens.or(base.max_output_tokens),
+            default_max_tokens: self.default_max_tokens.or(base.default_max_tokens),
+            default_temperature: self.default_temperature.or(base.default_temperature),
+            temperature: self.temperature.unwrap_or(base.temperature),
+            logprobs: self.logprobs.unwrap_or(base.logprobs),
+            streaming: self.streaming.unwrap_or(base.streaming),
+            tools: self.tools.unwrap_or(base.tools),
+            vision: self.vision.unwrap_or(base.vision),
+            reasoning: self.reasoning.unwrap_or(base.reasoning),
+        }
+    }
+}
+
+/// A parameter changed to suit the model.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ParamWarning {
+    pub model: String,
+    pub parameter: &'static str,
+    #[serde(flatten)]
+    pub change: ParamChange,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum ParamChange {
+    Dropped,
+    Clamped { requested: i32, sent: i32 },
+}
+
+impl fmt::Display for ParamWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.change {
+            ParamChange::Dropped => write!(
+                f,
+                "{} does not support {}; it was left out",
+                self.model, self.parameter
+            ),
+            ParamChange::Clamped { requested, sent } => write!(
+                f,
+                "{} allows {} up to {}; {} was lowered",
+                self.model, self.parameter, sent, requested
+            ),
+        }
+    }
+}
+
+// Ordered only for readability: lookups take the longest matching prefix.
+const BUILTIN_PROFILES: &[(&str, ModelProfile)] = &[
+    (
+        "grok-4",
+        ModelProfile {
+            vision: true,
+            ..ModelProfile::reasoning(256_000)
+        },
+    ),
+    (
+        "grok-4-fast-reasoning",
+        ModelProfile {
+            max_output_tokens: Some(30_000),
+            vision: true,
+            ..ModelProfile::reasoning(2_000_000)
+        },
+    ),
+    (
+        "grok-4-fast-non-reasoning",
+        ModelProfile {
+            max_output_tokens: Some(30_000),
+            vision: true,
+            ..ModelProfile::chat(2_000_000)
+        },
+    ),
+    (
+        "grok-code-fast-1",
+        ModelProfile {
+            max_output_tokens: Some(10_000),
+            ..ModelProfile::reasoning(256_000)
+        },
+    ),
+    ("grok-3", ModelProfile::chat(131_072)),
+    (
+        "grok-3-mini",
+        ModelProfile {
+            temperature: true,
+            logprobs: true,
+            ..ModelProfile::reasoning(131_072)
+        },
+    ),
+    (
+        "grok-2-vision",
+        ModelProfile {
+            max_output_tokens: Some(8_192),
+            tools: false,
+            vision: true,
+            ..ModelProfile::chat(32_768)
+        },
+    ),
+];
+
+/// Profiles keyed by model id prefix. Models matching none are sent as-is.
+#[derive(Debug, Clone)]
+pub struct ModelProfiles {
+    profiles: BTreeMap<String, ModelProfile>,
+}
+
+impl Default for ModelProfiles {
+    fn default() -> Self {
+        let profiles = BUILTIN_PROFILES
+            .iter()
+            .map(|(prefix, profile)| (prefix.to_string(), *profile))
+            .collect();
+        Self { profiles }
+    }
+}
+
+impl ModelProfiles {
+    /// The built-in profiles with `overrides` (from `config.toml`) applied.
+    /// An override for a new prefix starts from the profile that prefix
+    /// resolves to, or a permissive one.
+    pub fn with_overrides(overrides: &BTreeMap<String, ProfileOverride>) -> Result<Self> {
+        let mut profiles = Self::default();
+        for (prefix, settings) in overrides {
+            let base = profiles
+                .profile_for(prefix)
+                .unwrap_or(ModelProfile::chat(131_072));
+            let profile = settings.apply(base);
+            for (name, value) in [
+                ("max_output_tokens", profile.max_output_tokens),
+                ("default_max_tokens", profile.default_max_tokens),
+            ] {
+                if value.is_some_and(|tokens| tokens <= 0) {
+                    bail!("model_profiles.{}: {} must be positive", prefix, name);
+                }
+            }
+            if profile.context_window == 0 {
+                bail!("model_profiles.{}: context_window must be positive", prefix);
+            }
+            profiles.profiles.insert(prefix.clone(), profile);
+        }
+        Ok(profiles)
+    }
+
+    /// Exact match first, then the longest known prefix, so dated or
+    /// suffixed ids resolve.
+    pub fn profile_for(&self, model: &str) -> Option<ModelProfile> {
+        if let Some(profile) = self.profiles.get(model) {
+            return Some(*profile);
+        }
+        self.profiles
+            .iter()
+            .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, profile)| *profile)
+    }
+
+    /// Fills unset parameters with the model's defaults, or else `defaults`,
+    /// then drops the ones it does not support and lowers `max_tokens` to its
+    /// limit. Streaming is left alone, since callers depend on the response
+    /// kind.
+    pub fn fit(
+        &self,
+        request: &mut ApiChatRequest,
+        defaults: RequestDefaults,
+    ) -> Vec<ParamWarning> {
+        match self.profile_for(&request.model) {
+            Some(profile) => profile.fit(request, defaults),
+            None => {
+                request.temperature = request.temperature.or(defaults.temperature);
+                request.max_tokens = request.max_tokens.or(defaults.max_tokens);
+                Vec::new()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(model: &str) -> ApiChatRequest {
+        ApiChatRequest::builder()
+            .model(model)
+            .user("hi")
+            .temperature(0.2)
+            .max_tokens(50_000)
+            .logprobs(true)
+            .top_logprobs(3)
+            .build()
+    }
+
+    #[test]
+    fn test_reasoning_models_lose_sampling_parameters() {
+        let profiles = ModelProfiles::default();
+        let mut sent = request("grok-4-0709");
+        let warnings = profiles.fit(&mut sent, RequestDefaults::default());
+
+        assert_eq!(sent.temperature, None);
+        assert!(!sent.logprobs);
+        assert_eq!(sent.top_logprobs, 0);
+        assert_eq!(sent.max_tokens, Some(50_000));
+        let dropped: Vec<&str> = warnings.iter().map(|w| w.parameter).collect();
+        assert_eq!(dropped, ["temperature", "logprobs"]);
+        assert_eq!(
+            warnings[0].to_string(),
+            "grok-4-0709 does not support temperature; it was left out"
+        );
+    }
+
+    #[test]
+    fn test_fast_models_clamp_max_tokens() {
+        let profiles = ModelProfiles::default();
+        let mut sent = request("grok-4-fast-non-reasoning");
+        let warnings = profiles.fit(&mut sent, RequestDefaults::default());
+
+        // The longer prefix wins over grok-4: sampling is kept, output capped
+        assert_eq!(sent.temperature, Some(0.2));
+        assert!(sent.logprobs);
+        assert_eq!(sent.max_tokens, Some(30_000));
+        assert_eq!(
+            warnings,
+            [ParamWarning {
+                model: "grok-4-fast-non-reasoning".to_string(),
+                parameter: "max_tokens",
+                change: ParamChange::Clamped {
+                    requested: 50_000,
+                    sent: 30_000
+                },
+            }]
+        );
+        // Fitting again changes nothing more
+        assert!(profiles
+            .fit(&mut sent, RequestDefaults::default())
+            .is_empty());
+    }
+
+    #[test]
+    fn test_chat_and_unknown_models_are_untouched() {
+        let profiles = ModelProfiles::default();
+        for model in ["grok-3", "grok-3-beta", "my-proxy/llama"] {
+            let mut sent = request(model);
+            assert!(
+                profiles
+                    .fit(&mut sent, RequestDefaults::default())
+                    .is_empty(),
+                "{}",
+                model
+            );
+            let json = |request: &ApiChatRequest| serde_json::to_value(request).unwrap();
+            assert_eq!(json(&sent), json(&request(model)));
+        }
+        assert!(profiles.profile_for("grok-3-mini-beta").unwrap().reasoning);
+        assert!(profiles.profile_for("llama").is_none());
+    }
+
+    #[test]
+    fn test_overrides_replace_single_fields() {
+        let overrides = BTreeMap::from([
+            (
+                "grok-4".to_string(),
+                ProfileOverride {
+                    temperature: Some(true),
+                    default_temperature: Some(0.3),
+                    default_max_tokens: Some(1_000),
+                    ..Default::default()
+                },
+            ),
+            (
+                "local-".to_string(),
+                ProfileOverride {
+                    max_output_tokens: Some(512),
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let profiles = ModelProfiles::with_overrides(&overrides).unwrap();
+
+        let grok4 = profiles.profile_for("grok-4-0709").unwrap();
+        assert!(grok4.temperature && !grok4.logprobs && grok4.vision);
+        let mut sent = ApiChatRequest::builder().model("grok-4-0709").build();
+        assert!(profiles
+            .fit(&mut sent, RequestDefaults::default())
+            .is_empty());
+        assert_eq!(sent.temperature, Some(0.3));
+        assert_eq!(sent.max_tokens, Some(1_000));
+
+        let mut local = request("local-7b");
+        profiles.fit(&mut local, RequestDefaults::default());
+        assert_eq!(local.max_tokens, Some(512));
+
+        let bad = BTreeMap::from([(
+            "grok-3".to_string(),
+            ProfileOverride {
+                max_output_tokens: Some(0),
+                ..Default::default()
+            },
+        )]);
+        let error = ModelProfiles::with_overrides(&bad).unwrap_err();
+        assert!(error.to_string().contains("model_profiles.grok-3"));
+    }
+
+    #[test]
+    fn test_configured_defaults_fill_in_behind_the_profile() {
+        let overrides = BTreeMap::from([(
+            "grok-3".to_string(),
+            ProfileOverride {
+                default_temperature: Some(0.3),
+                ..Default::default()
+            },
+        )]);
+        let profiles = ModelProfiles::with_overrides(&overrides).unwrap();
+        let defaults = RequestDefaults {
+            temperature: Some(0.7),
+            max_tokens: Some(40_000),
+        };
+        let unset = |model: &str| ApiChatRequest::builder().model(model).build();
+
+        let mut sent = unset("grok-3");
+        assert!(profiles.fit(&mut sent, defaults).is_empty());
+        assert_eq!(sent.temperature, Some(0.3));
+        assert_eq!(sent.max_tokens, Some(40_000));
+
+        // Left out or lowered quietly where the model can't take them
+        let mut sent = unset("grok-code-fast-1");
+        assert!(profiles.fit(&mut sent, defaults).is_empty());
+        assert_eq!(sent.temperature, None);
+        assert_eq!(sent.max_tokens, Some(10_000));
+
+        let mut sent = unset("my-proxy/llama");
+        profiles.fit(&mut sent, defaults);
+        assert_eq!(sent.temperature, Some(0.7));
+    }
+
+    #[test]
+    fn test_summary() {
+        let profiles = ModelProfiles::default();
+        assert_eq!(
+            profiles.profile_for("grok-4-0709").unwrap().summary(),
+            "256K context, reasoning, no temperature, vision"
+        );
+        assert_eq!(
+            profiles.profile_for("grok-code-fast-1").unwrap().summary(),
+            "256K context, ≤10K output, reasoning, no temperature"
+        );
+    }
+}
@@ -0,0 +1,3 @@
+pub use self::capabilities::*;
+#[allow(clippy::module_inception)]
+mod capabilities;
@@ -0,0 +1,628 @@
+//! A high-level entry point for using the crate as a library.
+//!
+//! [`GrokChat`] ties together the configuration, the [`ChatService`] and
+//! session storage: the SQLite database with the `server` feature, otherwise
+//! an in-memory store that lives as long as the `GrokChat` value.
+//!
+//! ```no_run
+//! # async fn run() -> anyhow::Result<()> {
+//! use grok_chat_app::prelude::*;
+//!
+//! let chat = GrokChat::from_env().await?;
+//! let session = chat.new_session("grok-4-0709").await?;
+//!
+//! let reply = session.send("Name three rivers in Portugal.").await?;
+//! println!("{}", reply.message.content);
+//!
+//! let reply = session.send("Which is the longest?").await?;
+//! println!("{} ({:?} tokens)", reply.message.content, reply.usage);
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::{anyhow, bail, Result};
+use futures_util::{stream, Stream, StreamExt};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::client::{ChatResponse, ChatService, ContentStream};
+use crate::config::Config;
+use crate::context::{session_context, DEFAULT_TOKEN_BUDGET};
+use crate::models::{
+    ApiChatRequest, ChatSession, CompletionStats, Conversation, Message, StreamChunk, UsageStats,
+};
+use crate::postprocess::PostProcessor;
+
+#[cfg(feature = "server")]
+use crate::database::Database;
+
+/// Talks to Grok and keeps track of sessions. Cheap to clone; clones share
+/// the connection pool and the session store.
+#[derive(Clone)]
+pub struct GrokChat {
+    inner: Arc<Inner>,
+}
+
+#[derive(Clone)]
+struct Inner {
+    config: Config,
+    service: ChatService,
+    store: Store,
+}
+
+impl GrokChat {
+    /// Reads the configuration like the CLI does (environment, config file,
+    /// keyring). With the `server` feature sessions are saved in the database
+    /// at `DATABASE_URL`; without it they are kept in memory.
+    pub async fn from_env() -> Result<Self> {
+        let config = Config::from_env()?;
+
+        #[cfg(feature = "server")]
+        {
+            let database = Database::new(&config).await?;
+            Self::with_database(config, database)
+        }
+
+        #[cfg(not(feature = "server"))]
+        Self::new(config)
+    }
+
+    /// Keeps sessions in memory only. Fails on settings `Config::load` would
+    /// have rejected.
+    pub fn new(config: Config) -> Result<Self> {
+        Self::with_store(config, Store::Memory(Arc::default()))
+    }
+
+    /// Saves sessions in `database`, where the CLI and HTTP API see them too.
+    #[cfg(feature = "server")]
+    pub fn with_database(config: Config, database: Database) -> Result<Self> {
+        Self::with_store(config, Store::Database(database))
+    }
+
+    fn with_store(config: Config, store: Store) -> Result<Self> {
+        let service = ChatService::new(&config)?;
+        Ok(Self {
+            inner: Arc::new(Inner {
+                config,
+                service,
+                store,
+            }),
+        })
+    }
+
+    /// Runs `processor` on every reply after the ones configured with
+    /// `post_processors`, before the reply is stored. Clones made earlier
+    /// keep the processors they had.
+    ///
+    /// ```no_run
+    /// # async fn run() -> anyhow::Result<()> {
+    /// use grok_chat_app::postprocess::{PostProcessor, ProcessedResponse, ResponseContext};
+    /// use grok_chat_app::prelude::*;
+    ///
+    /// struct Shout;
+    ///
+    /// impl PostProcessor for Shout {
+    ///     fn process(&self, content: &str, _ctx: &ResponseContext) -> ProcessedResponse {
+    ///         ProcessedResponse::unchanged(&content.to_uppercase())
+    ///     }
+    /// }
+    ///
+    /// let chat = GrokChat::from_env().await?.with_post_processor(Shout);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_post_processor(self, processor: impl PostProcessor + 'static) -> Self {
+        let mut inner = Arc::unwrap_or_clone(self.inner);
+        inner.service = inner.service.with_post_processor(processor);
+        Self {
+            inner: Arc::new(inner),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.inner.config
+    }
+
+    pub fn service(&self) -> &ChatService {
+        &self.inner.service
+    }
+
+    /// Starts a session; `model` may be a configured alias.
+    pub async fn new_session(&self, model: &str) -> Result<Session> {
+        let model = self.inner.config.resolve_model(Some(model))?;
+        let session = self
+            .inner
+            .store
+            .create_session(ChatSession::new(model, None))
+            .await?;
+        Ok(self.handle(session))
+    }
+
+    /// Resumes a stored session.
+    pub async fn session(&self, id: &str) -> Result<Option<Session>> {
+        let session = self.inner.store.get_session(id).await?;
+        Ok(session.map(|session| self.handle(session)))
+    }
+
+    /// Stored sessions, most recently used first.
+    pub async fn sessions(&self) -> Result<Vec<ChatSession>> {
+        self.inner.store.list_sessions().await
+    }
+
+    fn handle(&self, session: ChatSession) -> Session {
+        Session {
+            chat: self.clone(),
+            session,
+        }
+    }
+}
+
+/// The assistant's answer to [`Session::send`].
+#[derive(Debug, Clone)]
+pub struct Reply {
+    /// The stored assistant message; `tokens_used` holds the completion tokens.
+    pub message: Message,
+    pub usage: Option<UsageStats>,
+    /// Secrets masked in the request before it was sent.
+    pub redactions: usize,
+    /// Side effects of post-processing the reply, such as files written.
+    pub effects: Vec<String>,
+}
+
+/// Chunks of a streamed reply. The turn is saved once the stream ends.
+pub type ReplyStream = Pin<Box<dyn Stream<Item = Result<StreamChunk>> + Send>>;
+
+/// One conversation. Every turn sends the whole stored history as context.
+#[derive(Clone)]
+pub struct Session {
+    chat: GrokChat,
+    session: ChatSession,
+}
+
+impl Session {
+    pub fn id(&self) -> &str {
+        &self.session.id
+    }
+
+    pub fn model(&self) -> &str {
+        &self.session.model
+    }
+
+    pub fn info(&self) -> &ChatSession {
+        &self.session
+    }
+
+    /// Messages exchanged so far, oldest first.
+    pub async fn history(&self) -> Result<Vec<Message>> {
+        self.chat.inner.store.messages(self.id()).await
+    }
+
+    /// Sends `text` and waits for the complete reply. The turn is stored only
+    /// when the request succeeds, so a failed send can simply be retried.
+    pub async fn send(&self, text: impl Into<String>) -> Result<Reply> {
+        let text = text.into();
+        let mut request = self.request(&text, false).await?;
+        let redactions = self.chat.inner.service.redact(&mut request);
+
+        let response = match self.chat.inner.service.send_request(request, None).await? {
+            ChatResponse::Complete(response) => response,
+            ChatResponse::Stream(_) => bail!("Expected a complete response"),
+        };
+        let content = response.get_content()?;
+        let usage = response.get_usage().cloned();
+
+        let message = self
+            .save_turn(
+                text,
+                content,
+                usage.as_ref(),
+                response.completion_stats(),
+                None,
+            )
+            .await?;
+        Ok(Reply {
+            message,
+            usage,
+            redactions,
+            effects: response.effects,
+        })
+    }
+
+    /// Sends `text` and yields the reply as it arrives. The turn is stored
+    /// after the last chunk, post-processed if processors are configured (the
+    /// final chunk then carries the stored reply in `processed`); dropping the
+    /// stream early stores nothing.
+    ///
+    /// ```no_run
+    /// # async fn run(session: grok_chat_app::Session) -> anyhow::Result<()> {
+    /// use futures_util::StreamExt;
+    ///
+    /// let mut chunks = session.stream("Tell me a short story").await?;
+    /// while let Some(chunk) = chunks.next().await {
+    ///     print!("{}", chunk?.content);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn stream(&self, text: impl Into<String>) -> Result<ReplyStream> {
+        let text = text.into();
+        let request = self.request(&text, true).await?;
+
+        let started = Instant::now();
+        let chunks = match self.chat.inner.service.send_request(request, None).await? {
+            ChatResponse::Stream(chunks) => chunks,
+            ChatResponse::Complete(_) => bail!("Expected a streamed response"),
+        };
+
+        let state = StreamState {
+            session: self.clone(),
+            chunks: Some(chunks),
+            prompt: text,
+            reply: String::new(),
+            usage: None,
+            finish_reason: None,
+            resumes: None,
+            started,
+        };
+        Ok(Box::pin(stream::unfold(state, StreamState::next)))
+    }
+
+    async fn request(&self, text: &str, stream: bool) -> Result<ApiChatRequest> {
+        let config = &self.chat.inner.config;
+        let history = self.history().await?;
+        let messages = Conversation::new()
+            .system_prompt(config.system_prompt())
+            .history(session_context(&history, DEFAULT_TOKEN_BUDGET))
+            .user(text)
+            .into_messages();
+
+        Ok(ApiChatRequest::builder()
+            .messages(messages)
+            .model(self.model())
+            .stream(stream)
+            .build())
+    }
+
+    async fn save_turn(
+        &self,
+        prompt: String,
+        reply: String,
+        usage: Option<&UsageStats>,
+        stats: CompletionStats,
+        resumes: Option<u32>,
+    ) -> Result<Message> {
+        // Created in order, since stored history is sorted by timestamp
+        let prompt = Message::user(
+            self.id().to_string(),
+            self.chat.inner.service.stored_text(prompt),
+        );
+        let mut reply =
+            Message::assistant(self.id().to_string(), reply, Some(self.model().to_string()));
+        reply.tokens_used = usage.map(|usage| usage.completion_tokens);
+        reply.resumes = resumes;
+
+        self.chat
+            .inner
+            .store
+            .save_turn(&self.session, prompt, reply, usage, &stats)
+            .await
+    }
+}
+
+struct StreamState {
+    session: Session,
+    /// `None` once the upstream stream has ended and the turn was saved.
+    chunks: Option<ContentStream>,
+    prompt: String,
+    reply: String,
+    usage: Option<UsageStats>,
+    finish_reason: Option<String>,
+    resumes: Option<u32>,
+    /// When the request was sent; the stream's end gives the latency.
+    started: Instant,
+}
+
+impl StreamState {
+    async fn next(mut self) -> Option<(Result<StreamChunk>, Self)> {
+        let chunks = self.chunks.as_mut()?;
+        match chunks.next().await {
+            Some(Ok(chunk)) => {
+                self.reply.push_str(&chunk.content);
+                if chunk.usage.is_some() {
+                    self.usage = chunk.usage.clone();
+                }
+                if chunk.finish_reason.is_some() {
+                    self.finish_reason = chunk.finish_reason.clone();
+                }
+                self.resumes = chunk.resumes.or(self.resumes);
+                if let Some(processed) = &chunk.processed {
+                    self.reply = processed.content.clone();
+                }
+                Some((Ok(chunk), self))
+            }
+            Some(Err(e)) => {
+                self.chunks = None;
+                Some((Err(e), self))
+            }
+            None => {
+                self.chunks = None;
+                let prompt = std::mem::take(&mut self.prompt);
+                let reply = std::mem::take(&mut self.reply);
+                let stats = CompletionStats {
+                    latency_ms: Some(self.started.elapsed().as_millis() as u64),
+                    finish_reason: self.finish_reason.take(),
+                };
+                let saved = self
+                    .session
+                    .save_turn(prompt, reply, self.usage.as_ref(), stats, self.resumes)
+                    .await;
+                // A failed save is reported as a final error item
+                saved.err().map(|e| (Err(e), self))
+            }
+        }
+    }
+}
+
+/// Where sessions live.
+#[derive(Clone)]
+enum Store {
+    Memory(Arc<Mutex<Vec<MemorySession>>>),
+    #[cfg(feature = "server")]
+    Database(Database),
+}
+
+struct MemorySession {
+    session: ChatSession,
+    messages: Vec<Message>,
+}
+
+impl Store {
+    async fn create_session(&self, session: ChatSession) -> Result<ChatSession> {
+        match self {
+            Store::Memory(sessions) => {
+                sessions.lock().unwrap().push(MemorySession {
+                    session: session.clone(),
+                    messages: Vec::new(),
+                });
+                Ok(session)
+            }
+            #[cfg(feature = "server")]
+            Store::Database(database) => database.create_session(session).await,
+        }
+    }
+
+    async fn get_session(&self, id: &str) -> Result<Option<ChatSession>> {
+        match self {
+            Store::Memory(sessions) => Ok(sessions
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|stored| stored.session.id == id)
+                .map(|stored| stored.session.clone())),
+            #[cfg(feature = "server")]
+            Store::Database(database) => database.get_session(id).await,
+        }
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<ChatSession>> {
+        match self {
+            Store::Memory(sessions) => {
+                let mut list: Vec<ChatSession> = sessions
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|stored| stored.session.clone())
+                    .collect();
+                list.sort_by_key(|session| std::cmp::Reverse(session.updated_at));
+                Ok(list)
+            }
+            #[cfg(feature = "server")]
+            Store::Database(database) => database.list_sessions(None, None).await,
+        }
+    }
+
+    async fn messages(&self, id: &str) -> Result<Vec<Message>> {
+        match self {
+            Store::Memory(sessions) => sessions
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|stored| stored.session.id == id)
+                .map(|stored| stored.messages.clone())
+                .ok_or_else(|| anyhow!("Session '{}' not found", id)),
+            #[cfg(feature = "server")]
+            Store::Database(database) => database.get_messages(id).await,
+        }
+    }
+
+    /// Stores a prompt and its reply, returning the stored reply.
+    #[cfg_attr(not(feature = "server"), allow(unused_variables))]
+    async fn save_turn(
+        &self,
+        session: &ChatSession,
+        prompt: Message,
+        reply: Message,
+        usage: Option<&UsageStats>,
+        stats: &CompletionStats,
+    ) -> Result<Message> {
+        match self {
+            Store::Memory(sessions) => {
+                let mut sessions = sessions.lock().unwrap();
+                let stored = sessions
+                    .iter_mut()
+                    .find(|stored| stored.session.id == session.id)
+                    .ok_or_else(|| anyhow!("Session '{}' not found", session.id))?;
+
+                let mut saved = Vec::with_capacity(2);
+                for mut message in [prompt, reply] {
+                    message.id = stored.messages.len() as i64 + 1;
+                    stored.messages.push(message.clone());
+                    saved.push(message);
+                }
+                stored.session.update_timestamp();
+                Ok(saved.pop().unwrap())
+            }
+            #[cfg(feature = "server")]
+            Store::Database(database) => {
+                database.create_message(prompt).await?;
+                let reply = database.create_message(reply).await?;
+                if let Some(usage) = usage {
+                    database
+                        .record_usage(Some(&session.id), &session.model, usage, stats)
+                        .await?;
+                }
+                database.update_session(&session.id, None).await?;
+                Ok(reply)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock::{MockReply, MockUpstream};
+    use crate::models::MessageRole;
+    use crate::postprocess::{ProcessedResponse, ResponseContext};
+    use std::time::Duration;
+
+    /// Replies "reply N" to the Nth request, streaming when asked to.
+    async fn scripted_upstream() -> MockUpstream {
+        let turn = std::sync::atomic::AtomicUsize::new(0);
+        MockUpstream::start(move |body| {
+            let n = turn.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+            if body["stream"] == true {
+                MockReply::stream(&["reply ", &n.to_string()], Duration::ZERO)
+            } else {
+                MockReply::completion(&format!("reply {}", n))
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_session_sends_history() {
+        let upstream = scripted_upstream().await;
+        let chat = GrokChat::new(upstream.config()).unwrap();
+        let session = chat.new_session("grok-3").await.unwrap();
+
+        let first = session.send("one").await.unwrap();
+        assert_eq!(first.message.content, "reply 1");
+        assert_eq!(first.message.role, MessageRole::Assistant);
+        assert_eq!(first.message.tokens_used, Some(1));
+        assert_eq!(first.usage.unwrap().total_tokens, 2);
+
+        let mut chunks = session.stream("two").await.unwrap();
+        let mut streamed = String::new();
+        while let Some(chunk) = chunks.next().await {
+            streamed.push_str(&chunk.unwrap().content);
+        }
+        assert_eq!(streamed, "reply 2");
+
+        let history = session.history().await.unwrap();
+        let contents: Vec<&str> = history.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["one", "reply 1", "two", "reply 2"]);
+
+        // The second request carried the system prompt and the first turn
+        let sent = &upstream.requests()[1]["messages"];
+        assert_eq!(sent.as_array().unwrap().len(), 4);
+        assert_eq!(sent[0]["role"], "system");
+        assert_eq!(sent[2]["content"], "reply 1");
+
+        let sessions = chat.sessions().await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].model, "grok-3");
+        assert!(chat.session(session.id()).await.unwrap().is_some());
+    }
+
+    struct Bracket;
+
+    impl PostProcessor for Bracket {
+        fn process(&self, content: &str, ctx: &ResponseContext) -> ProcessedResponse {
+            ProcessedResponse {
+                content: format!("[{}]", content),
+                effects: vec![format!("bracketed a {} reply", ctx.model)],
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registered_processors_shape_stored_replies() {
+        let upstream = scripted_upstream().await;
+        let chat = GrokChat::new(upstream.config())
+            .unwrap()
+            .with_post_processor(Bracket);
+        let session = chat.new_session("grok-3").await.unwrap();
+
+        let reply = session.send("one").await.unwrap();
+        assert_eq!(reply.message.content, "[reply 1]");
+        assert_eq!(reply.effects, ["bracketed a grok-3 reply"]);
+
+        // Chunks arrive raw; the last one carries the processed reply
+        let chunks: Vec<StreamChunk> = session
+            .stream("two")
+            .await
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        let streamed: String = chunks.iter().map(|chunk| chunk.content.as_str()).collect();
+        assert_eq!(streamed, "reply 2");
+        let processed = chunks.last().unwrap().processed.as_ref().unwrap();
+        assert_eq!(processed.content, "[reply 2]");
+
+        let history = session.history().await.unwrap();
+        assert_eq!(history[3].content, "[reply 2]");
+    }
+
+    #[tokio::test]
+    async fn test_failed_send_stores_nothing() {
+        let upstream = MockUpstream::start(|_| {
+            MockReply::Json(400, serde_json::json!({"error": "bad request"}))
+        })
+        .await;
+        let chat = GrokChat::new(upstream.config()).unwrap();
+        let session = chat.new_session("grok-3").await.unwrap();
+
+        assert!(session.send("hello").await.is_err());
+        assert!(session.history().await.unwrap().is_empty());
+        assert!(chat.new_session("fast").await.is_err());
+    }
+
+    #[cfg(feature = "server")]
+    #[tokio::test]
+    async fn test_database_sessions_survive_the_facade() {
+        let upstream = scripted_upstream().await;
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            database_url: format!("sqlite:{}?mode=rwc", dir.path().join("chat.db").display()),
+            ..upstream.config()
+        };
+
+        let chat =
+            GrokChat::with_database(config.clone(), Database::new(&config).await.unwrap()).unwrap();
+        let id = chat.new_session("grok-3").await.unwrap().id().to_string();
+        chat.session(&id)
+            .await
+            .unwrap()
+            .unwrap()
+            .send("one")
+            .await
+            .unwrap();
+
+        let reopened =
+            GrokChat::with_database(config.clone(), Database::new(&config).await.unwrap()).unwrap();
+        assert_eq!(reopened.sessions().await.unwrap()[0].id, id);
+        let history = reopened
+            .session(&id)
+            .await
+            .unwrap()
+            .unwrap()
+            .history()
+            .await
+            .unwrap();
+        assert_eq!(history[1].content, "reply 1");
+        assert_eq!(history[1].tokens_used, Some(1));
+    }
+}
@@ -0,0 +1,3 @@
+pub use self::chat::*;
+#[allow(clippy::module_inception)]
+mod chat;
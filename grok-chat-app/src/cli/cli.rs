@@ -0,0 +1,1406 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::agents::{Agent, UnknownAgent};
+use crate::auth::InvalidApiKey;
+use crate::client::{mentions_context_length, CircuitOpen, UnknownProvider, XaiError};
+use crate::config::{Config, MissingApiKey, DEFAULT_MODEL, DEFAULT_SYSTEM_PROMPT};
+use crate::i18n::{tr, Locale};
+use crate::models::{MessageRole, Rating, TokenLogprob, UsageStats};
+use crate::preflight::{IssueKind, PreflightFailed};
+use crate::presets::UnknownPreset;
+use crate::pricing::{Cost, CostEstimate, CostNotConfirmed};
+use crate::replay::ReplayRegression;
+
+/// Chat with xAI's Grok models from the shell, a terminal UI or an HTTP server.
+#[derive(Debug, Parser)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Print only the reply: no banners, session notes or hints on stderr
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Use a `[profiles.<name>]` section of the config file [env: GROK_PROFILE]
+    #[arg(long, global = true, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Send prompts as typed, without masking keys and emails [env: GROK_NO_REDACT]
+    #[arg(long, global = true)]
+    pub no_redact: bool,
+
+    /// Language for messages, such as `es` [env: GROK_LOCALE; default: from LANG]
+    #[arg(long, global = true, value_name = "LANG")]
+    pub locale: Option<Locale>,
+
+    #[command(flatten)]
+    pub legacy: LegacyArgs,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Send one message, or chat line by line when no message is given
+    Chat(ChatArgs),
+
+    /// Open the full-screen terminal UI
+    #[cfg(feature = "terminal")]
+    Tui(TuiArgs),
+
+    /// Run the HTTP API server
+    #[cfg(feature = "server")]
+    Serve(ServeArgs),
+
+    /// Inspect and manage saved sessions
+    #[cfg(feature = "server")]
+    #[command(subcommand)]
+    Sessions(SessionsCommand),
+
+    /// Manage named system prompt presets
+    #[cfg(feature = "server")]
+    #[command(subcommand)]
+    Prompts(PromptsCommand),
+
+    /// Manage session templates: a system prompt and example exchanges new
+    /// sessions start from
+    #[cfg(feature = "server")]
+    #[command(subcommand)]
+    SessionTemplates(SessionTemplatesCommand),
+
+    /// List the models available to your API key
+    Models(ModelsArgs),
+
+    /// Re-send an exported session's prompts and compare the new replies
+    Replay(ReplayArgs),
+
+    /// Play a scripted conversation from a YAML file into a new session,
+    /// checking each reply against the script's expectations
+    #[cfg(feature = "server")]
+    Run(RunArgs),
+
+    /// Verify the configuration, API key and database
+    Check,
+
+    /// Prune old sessions and compact the database
+    #[cfg(feature = "server")]
+    Maintenance(MaintenanceArgs),
+
+    /// Load-test the HTTP API server and report latency and throughput
+    #[cfg(feature = "server")]
+    Bench(BenchArgs),
+
+    /// Summarize tokens, cost and latency over the last day, week or month
+    #[cfg(feature = "server")]
+    Report(ReportArgs),
+
+    /// Serve chat history to MCP clients (editors, agents) over stdio
+    #[cfg(feature = "mcp")]
+    Mcp,
+
+    /// Create or inspect the config file
+    #[command(subcommand)]
+    Config(ConfigCommand),
+
+    /// Store the API key in the OS keyring
+    #[command(subcommand)]
+    Auth(AuthCommand),
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ChatArgs {
+    /// Message to send; `-` reads the whole prompt from stdin. Without one (and
+    /// with nothing piped in) an interactive prompt starts
+    pub message: Option<String>,
+
+    /// Model to use [default: the configured default model]
+    #[arg(short, long)]
+    pub model: Option<String>,
+
+    /// System prompt to use [default: the configured or built-in prompt]
+    #[arg(short, long)]
+    pub system: Option<String>,
+
+    /// Use a saved system prompt preset (see `prompts list`); with a session,
+    /// the session keeps using it
+    #[arg(long, value_name = "NAME", conflicts_with = "system")]
+    pub prompt_preset: Option<String>,
+
+    /// Answer as a configured agent, with its model, prompt and parameters
+    /// unless given here; with a session, the session keeps using it
+    #[arg(long, value_name = "NAME")]
+    pub agent: Option<String>,
+
+    /// Maximum tokens in the reply [default: the model's, else the
+    /// configured max_tokens]
+    #[arg(short = 'x', long)]
+    pub max_tokens: Option<i32>,
+
+    /// Sampling temperature (0.0-2.0) [default: the model's, else the
+    /// configured temperature]
+    #[arg(short, long)]
+    pub temperature: Option<f32>,
+
+    /// Inline a file's contents into the message (repeatable)
+    #[arg(short = 'f', long = "file", value_name = "PATH")]
+    pub files: Vec<PathBuf>,
+
+    /// Resume a saved session, sending its history as context
+    #[arg(short = 'i', long)]
+    pub session: Option<String>,
+
+    /// Continue the most recently updated session
+    #[arg(long = "continue", conflicts_with_all = ["session", "new_session"])]
+    pub continue_session: bool,
+
+    /// Start a new saved session with this title
+    #[arg(long, value_name = "TITLE", conflicts_with = "session")]
+    pub new_session: Option<String>,
+
+    /// Stream the reply as it arrives (default when stdout is a terminal)
+    #[arg(long, conflicts_with_all = ["no_stream", "logprobs"])]
+    pub stream: bool,
+
+    /// Wait for the complete reply before printing it
+    #[arg(long)]
+    pub no_stream: bool,
+
+    /// Print a token usage summary to stderr
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Request token logprobs and print them to stderr
+    #[arg(long)]
+    pub logprobs: bool,
+
+    /// Number of alternative tokens to return per position with --logprobs
+    #[arg(long, default_value_t = 0, requires = "logprobs")]
+    pub top_logprobs: u8,
+
+    /// Print the estimated cost to stderr
+    #[arg(long)]
+    pub show_cost: bool,
+
+    /// Print a single JSON object instead of text; implies --no-stream
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, conflicts_with = "stream")]
+    pub output: OutputFormat,
+
+    /// How to print the reply; `auto` styles markdown when stdout is a terminal
+    #[arg(long, value_enum, default_value_t = RenderMode::Auto)]
+    pub render: RenderMode,
+
+    /// Queue the message in a saved session (a new one unless --session or
+    /// --continue picks one) without sending it; `sessions flush` sends it
+    #[arg(long, conflicts_with_all = ["stream", "logprobs"])]
+    pub offline: bool,
+
+    /// Print the request that would be sent to the API (without the API
+    /// key) instead of sending it; nothing is saved
+    #[arg(long, conflicts_with_all = ["offline", "new_session"])]
+    pub dry_run: bool,
+
+    /// Send even when the request could cost more than cost_threshold_usd
+    #[arg(long)]
+    pub yes: bool,
+
+    /// Whether a temperature was chosen, with --temperature or the agent
+    #[arg(skip)]
+    pub temperature_given: bool,
+
+    /// Whether a system prompt was chosen, with --system or --prompt-preset,
+    /// before `apply_config` filled in the configured one
+    #[arg(skip)]
+    pub system_given: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ReplayArgs {
+    /// Session export to replay, as written by `sessions export --format json`
+    pub file: PathBuf,
+
+    /// Model to replay against [default: the session's model]
+    #[arg(short, long)]
+    pub model: Option<String>,
+
+    /// Maximum tokens per reply [default: the model's, else the configured
+    /// max_tokens]
+    #[arg(short = 'x', long)]
+    pub max_tokens: Option<i32>,
+
+    /// Sampling temperature (0.0-2.0) [default: the model's, else the
+    /// configured temperature]
+    #[arg(short, long)]
+    pub temperature: Option<f32>,
+
+    /// Exit with status 7 when any turn's similarity falls below this (0.0-1.0)
+    #[arg(long, value_name = "SCORE", value_parser = parse_similarity)]
+    pub fail_threshold: Option<f64>,
+
+    /// Replay turns even when they could cost more than cost_threshold_usd
+    #[arg(long)]
+    pub yes: bool,
+
+    /// Print the report as JSON instead of text
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+}
+
+#[cfg(feature = "server")]
+#[derive(Debug, Clone, Args)]
+pub struct RunArgs {
+    /// YAML script with `model`, `system_prompt`, `max_tokens`,
+    /// `temperature`, `vars` and the `turns` to send (see USAGE.md)
+    pub script: PathBuf,
+
+    /// Value for a `{{name}}` placeholder, overriding the environment and the
+    /// script's `vars` (repeatable)
+    #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var)]
+    pub vars: Vec<(String, String)>,
+
+    /// Model to use [default: the script's, else the configured default model]
+    #[arg(short, long)]
+    pub model: Option<String>,
+
+    /// Wait for each complete reply instead of streaming it (the default when
+    /// stdout is not a terminal)
+    #[arg(long)]
+    pub no_stream: bool,
+
+    /// Print the report as JSON instead of the replies and a summary
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+}
+
+#[cfg(feature = "server")]
+fn parse_var(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err("expected NAME=VALUE".to_string()),
+    }
+}
+
+fn parse_similarity(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(score) if (0.0..=1.0).contains(&score) => Ok(score),
+        Ok(_) => Err("must be between 0.0 and 1.0".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum RenderMode {
+    #[default]
+    Auto,
+    Markdown,
+    Plain,
+}
+
+impl RenderMode {
+    /// Whether a reply gets ANSI styling. Piped output and `NO_COLOR` always get
+    /// plain text; `auto` also needs the reply to look like markdown.
+    pub fn styles(self, is_terminal: bool, no_color: bool, looks_like_markdown: bool) -> bool {
+        if !is_terminal || no_color {
+            return false;
+        }
+        match self {
+            RenderMode::Auto => looks_like_markdown,
+            RenderMode::Markdown => true,
+            RenderMode::Plain => false,
+        }
+    }
+}
+
+impl Default for ChatArgs {
+    fn default() -> Self {
+        Self {
+            message: None,
+            model: None,
+            system: None,
+            prompt_preset: None,
+            agent: None,
+            max_tokens: None,
+            temperature: None,
+            files: Vec::new(),
+            session: None,
+            continue_session: false,
+            new_session: None,
+            stream: false,
+            no_stream: false,
+            verbose: false,
+            logprobs: false,
+            top_logprobs: 0,
+            show_cost: false,
+            output: OutputFormat::Text,
+            render: RenderMode::Auto,
+            offline: false,
+            dry_run: false,
+            yes: false,
+            temperature_given: false,
+            system_given: false,
+        }
+    }
+}
+
+impl ChatArgs {
+    /// Fills in the model, system prompt and generation parameters `agent`
+    /// sets, unless they were given on the command line. Runs before
+    /// `apply_config`, so the agent's model may be an alias.
+    pub fn apply_agent(&mut self, agent: &Agent) {
+        self.model.get_or_insert_with(|| agent.model.clone());
+        if let Some(prompt) = &agent.system_prompt {
+            self.system.get_or_insert_with(|| prompt.clone());
+        }
+        if let Some(max_tokens) = agent.max_tokens {
+            self.max_tokens.get_or_insert(max_tokens);
+        }
+        if let Some(temperature) = agent.temperature {
+            self.temperature.get_or_insert(temperature);
+        }
+    }
+
+    /// Fills in the model and system prompt from `config` unless they were
+    /// given on the command line, which wins over the config file and env.
+    /// Model aliases are resolved here. Generation parameters left unset
+    /// stay unset, so the model's own defaults can apply before the
+    /// configured ones.
+    pub fn apply_config(&mut self, config: &Config) -> anyhow::Result<()> {
+        let model = config
+            .resolve_model(self.model.as_deref())
+            .map_err(|e| UsageError(e.to_string()))?;
+        self.model = Some(model);
+        self.system_given = self.system.is_some();
+        self.system
+            .get_or_insert_with(|| config.system_prompt().to_string());
+        self.temperature_given = self.temperature.is_some();
+        Ok(())
+    }
+
+    pub fn model(&self) -> &str {
+        self.model.as_deref().unwrap_or(DEFAULT_MODEL)
+    }
+
+    pub fn system(&self) -> &str {
+        self.system.as_deref().unwrap_or(DEFAULT_SYSTEM_PROMPT)
+    }
+
+    /// Whether the message should join a saved session.
+    /// Offline messages are always stored, so `--offline` implies a session.
+    pub fn wants_session(&self) -> bool {
+        self.session.is_some()
+            || self.continue_session
+            || self.new_session.is_some()
+            || self.offline
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ModelsArgs {
+    /// Print a JSON array instead of one model per line
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct TuiArgs {
+    /// Session ID to resume
+    #[arg(short = 'i', long)]
+    pub session: Option<String>,
+
+    /// Model to use [default: the configured default model]
+    #[arg(short, long)]
+    pub model: Option<String>,
+
+    /// Start without checking the database and API key first
+    #[arg(long)]
+    pub skip_preflight: bool,
+
+    /// Start offline: messages are queued in the session database and sent
+    /// once you go back online (`o` in normal mode). Skips the API check
+    #[arg(long)]
+    pub offline: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ServeArgs {
+    /// Address to bind [default: SERVER_HOST or 127.0.0.1]
+    #[arg(short = 'H', long)]
+    pub host: Option<String>,
+
+    /// Port to listen on [default: SERVER_PORT or 3000]
+    #[arg(short, long)]
+    pub port: Option<u16>,
+
+    /// Start without checking the database and API key first, e.g. on
+    /// machines without network access
+    #[arg(long)]
+    pub skip_preflight: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct BenchArgs {
+    /// Messages in flight at once, each worker sending into its own session
+    #[arg(short, long, default_value_t = 8, value_parser = parse_count)]
+    pub concurrency: usize,
+
+    /// Messages to send in total
+    #[arg(short = 'n', long, default_value_t = 100, value_parser = parse_count)]
+    pub requests: usize,
+
+    /// Server to load [default: http://SERVER_HOST:SERVER_PORT]
+    #[arg(long, value_name = "URL", conflicts_with = "mock_upstream")]
+    pub target: Option<String>,
+
+    /// Start a server in-process, backed by a fake xAI API and a throwaway
+    /// database, so the server itself is measured (builds with the `mock`
+    /// feature only)
+    #[arg(long)]
+    pub mock_upstream: bool,
+
+    /// How long the fake xAI API takes to answer
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 200,
+        requires = "mock_upstream"
+    )]
+    pub delay_ms: u64,
+
+    /// Model to create the sessions with [default: the server's default model]
+    #[arg(short, long)]
+    pub model: Option<String>,
+
+    /// Message every request sends
+    #[arg(long, default_value = "Reply with one short sentence.")]
+    pub message: String,
+
+    /// Print the report as JSON instead of a table
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+}
+
+fn parse_count(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(count) => Ok(count),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SessionsCommand {
+    /// List sessions, most recently updated first
+    List {
+        /// Maximum number of sessions to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: i64,
+
+        /// Print a JSON array instead of a table
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+
+    /// Print a session's transcript
+    Show { id: String },
+
+    /// Delete a session and all of its messages
+    Delete { id: String },
+
+    /// Export a session with its messages
+    Export {
+        id: String,
+
+        #[arg(short, long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Keep the example messages the session's template seeded it with
+        #[arg(long)]
+        include_seeded: bool,
+    },
+
+    /// Import conversations exported from another chat tool as new sessions
+    Import {
+        file: PathBuf,
+
+        #[arg(short, long, value_enum)]
+        format: ImportFormat,
+    },
+
+    /// Export every session, with messages and usage, to a directory or a
+    /// .tar.gz archive
+    ExportAll {
+        /// A directory, or a file ending in .tar.gz or .tgz
+        path: PathBuf,
+
+        /// Only sessions updated on or after this date (YYYY-MM-DD or RFC 3339)
+        #[arg(long, value_parser = parse_since)]
+        since: Option<DateTime<Utc>>,
+    },
+
+    /// Export conversations as OpenAI fine-tuning JSONL, one example per
+    /// session or per exchange, redacted as configured
+    ExportFinetune {
+        /// Only sessions with a message annotated with this label; with
+        /// --per-turn, only replies that are
+        #[arg(long)]
+        tag: Option<String>,
+
+        /// Only sessions with a message rated up or down; with --per-turn,
+        /// only replies that are
+        #[arg(long, value_parser = parse_rating)]
+        rating: Option<Rating>,
+
+        /// Only sessions updated on or after this date (YYYY-MM-DD or RFC 3339)
+        #[arg(long, value_parser = parse_since)]
+        since: Option<DateTime<Utc>>,
+
+        /// Only sessions updated up to this date, inclusive, or before this
+        /// RFC 3339 time
+        #[arg(long, value_parser = parse_until)]
+        until: Option<DateTime<Utc>>,
+
+        /// One example per user message and its reply instead of per session
+        #[arg(long)]
+        per_turn: bool,
+
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+
+    /// Send messages queued while offline, oldest first, storing the replies
+    Flush,
+
+    /// Search saved messages line by line for a regular expression
+    Grep(GrepArgs),
+
+    /// Move every session on a model, typically one xAI retired, to another
+    MigrateModel {
+        /// The model to move sessions off
+        #[arg(long)]
+        from: String,
+
+        /// The model to move them to; aliases are resolved
+        #[arg(long)]
+        to: String,
+    },
+
+    /// Find sessions holding the same conversation; with --apply, merge each
+    /// group into its oldest session, keeping every message found in only one
+    Dedupe {
+        /// Merge the duplicates instead of only listing them
+        #[arg(long)]
+        apply: bool,
+
+        /// Share of two sessions' messages in common at which they count as
+        /// duplicates (0.0-1.0); sessions with the same user messages always do
+        #[arg(long, default_value_t = 0.8, value_parser = parse_similarity)]
+        similarity: f64,
+
+        /// Print the report as JSON instead of text
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct GrepArgs {
+    /// A regular expression (Rust `regex` syntax); plain text matches literally
+    pub pattern: String,
+
+    /// Match regardless of case
+    #[arg(short = 'i', long)]
+    pub ignore_case: bool,
+
+    /// Only messages with this role (user, assistant, system or tool)
+    #[arg(long, value_parser = parse_role)]
+    pub role: Option<MessageRole>,
+
+    /// Only this session
+    #[arg(long)]
+    pub session: Option<String>,
+
+    /// Lines of the message to show around each match
+    #[arg(short = 'C', long, default_value_t = 0)]
+    pub context: usize,
+
+    /// Print one JSON object per matching line instead of text
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+}
+
+fn parse_role(value: &str) -> Result<MessageRole, String> {
+    value
+        .parse()
+        .map_err(|e: crate::models::UnknownRole| e.to_string())
+}
+
+fn parse_rating(value: &str) -> Result<Rating, String> {
+    value.parse().map_err(|e: anyhow::Error| e.to_string())
+}
+
+#[derive(Debug, Subcommand)]
+pub enum PromptsCommand {
+    /// Save a preset, replacing one with the same name
+    Add {
+        name: String,
+
+        /// The prompt text; `-` or none reads it from stdin
+        body: Option<String>,
+
+        /// Use this preset when a session has none of its own
+        #[arg(long)]
+        default: bool,
+    },
+
+    /// List saved presets
+    List {
+        /// Print a JSON array instead of one preset per line
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+
+    /// Delete a preset; sessions using it fall back to the default
+    Rm { name: String },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SessionTemplatesCommand {
+    /// Save a template from a JSON file (`-` or none reads stdin), replacing
+    /// one with the same name: `{"name", "description", "system_prompt",
+    /// "messages": [{"role", "content"}]}`
+    Add { file: Option<PathBuf> },
+
+    /// List saved templates
+    List {
+        /// Print a JSON array instead of one template per line
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+
+    /// Print a template as JSON
+    Show { name: String },
+
+    /// Delete a template; sessions started from it keep their messages
+    Rm { name: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    Markdown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    /// `conversations.json` from a ChatGPT data export
+    Chatgpt,
+    /// `{"title", "messages": [{"role", "content", "timestamp"}]}`, or an
+    /// array of such objects
+    Generic,
+    /// A directory or .tar.gz written by `sessions export-all`
+    Archive,
+}
+
+/// A date means midnight UTC at its start.
+pub fn parse_since(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| "expected a date (YYYY-MM-DD) or an RFC 3339 time".to_string())
+}
+
+/// The end of an inclusive range: a date means the start of the day after,
+/// so everything on that day is before it.
+pub fn parse_until(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let next = date.succ_opt().ok_or("date out of range")?;
+        return Ok(next.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+    parse_since(value)
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Write a commented config file template
+    Init {
+        /// Replace an existing config file
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Print the effective configuration, with the API key redacted
+    Show,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AuthCommand {
+    /// Prompt for an API key, verify it and store it for the profile
+    Login,
+
+    /// Remove the profile's stored API key
+    Logout,
+
+    /// Show where the API key in use comes from
+    Status,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct MaintenanceArgs {
+    /// Delete sessions not updated in this many days
+    #[arg(long, value_name = "DAYS")]
+    pub prune_days: Option<u32>,
+
+    /// Report what would be pruned or removed without changing anything
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[cfg(feature = "server")]
+#[derive(Debug, Clone, Args)]
+pub struct ReportArgs {
+    /// Days to cover, ending today: day, week (7) or month (30)
+    #[arg(short, long, default_value_t = crate::report::ReportPeriod::Week)]
+    pub period: crate::report::ReportPeriod,
+
+    /// Print the report as markdown or JSON
+    #[arg(short, long, value_enum, default_value_t = ReportFormat::Markdown)]
+    pub format: ReportFormat,
+}
+
+#[cfg(feature = "server")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    #[default]
+    Markdown,
+    Json,
+}
+
+/// The flat flags from before subcommands existed. They are hidden from
+/// `--help` and still accepted for one release, with a deprecation warning.
+#[derive(Debug, Default, PartialEq, Args)]
+pub struct LegacyArgs {
+    #[arg(short, long, hide = true, conflicts_with = "server")]
+    pub terminal: bool,
+
+    #[arg(short, long, hide = true)]
+    pub server: bool,
+
+    #[arg(long, hide = true)]
+    pub port: Option<u16>,
+
+    #[arg(short = 'H', long, hide = true)]
+    pub host: Option<String>,
+
+    #[arg(short = 'i', long, hide = true)]
+    pub session: Option<String>,
+
+    #[arg(short, long, hide = true)]
+    pub model: Option<String>,
+
+    #[arg(short = 'g', long, hide = true)]
+    pub message: Option<String>,
+
+    #[arg(short = 'y', long, hide = true)]
+    pub system: Option<String>,
+
+    #[arg(short = 'x', long, hide = true)]
+    pub max_tokens: Option<i32>,
+
+    #[arg(short = 'p', long, hide = true)]
+    pub temperature: Option<f32>,
+
+    #[arg(short = 'f', long = "file", hide = true)]
+    pub files: Vec<PathBuf>,
+
+    #[arg(long = "continue", hide = true)]
+    pub continue_session: bool,
+
+    #[arg(long, hide = true)]
+    pub new_session: Option<String>,
+
+    #[arg(long, hide = true)]
+    pub stream: bool,
+
+    #[arg(long, hide = true)]
+    pub no_stream: bool,
+
+    #[arg(short, long, hide = true)]
+    pub verbose: bool,
+
+    #[arg(long, hide = true)]
+    pub logprobs: bool,
+
+    #[arg(long, hide = true)]
+    pub top_logprobs: Option<u8>,
+
+    #[arg(long, hide = true)]
+    pub show_cost: bool,
+}
+
+/// What a bare or legacy invocation resolves to.
+#[derive(Debug)]
+pub enum LegacyCommand {
+    #[cfg(feature = "terminal")]
+    Tui(TuiArgs),
+    #[cfg(feature = "server")]
+    Serve(ServeArgs),
+    /// Chat, falling back to the terminal UI when there is no message.
+    Chat(ChatArgs),
+}
+
+impl LegacyArgs {
+    pub fn is_used(&self) -> bool {
+        *self != Self::default()
+    }
+
+    pub fn into_command(self) -> LegacyCommand {
+        #[cfg(feature = "terminal")]
+        if self.terminal {
+            return LegacyCommand::Tui(TuiArgs {
+                session: self.session,
+                model: self.model,
+                skip_preflight: false,
+                offline: false,
+            });
+        }
+
+        #[cfg(feature = "server")]
+        if self.server {
+            return LegacyCommand::Serve(ServeArgs {
+                host: self.host,
+                port: self.port,
+                skip_preflight: false,
+            });
+        }
+
+        let defaults = ChatArgs::default();
+        LegacyCommand::Chat(ChatArgs {
+            message: self.message,
+            model: self.model,
+            system: self.system,
+            prompt_preset: None,
+            agent: None,
+            max_tokens: self.max_tokens,
+            temperature: self.temperature,
+            files: self.files,
+            session: self.session,
+            continue_session: self.continue_session,
+            new_session: self.new_session,
+            stream: self.stream,
+            no_stream: self.no_stream,
+            verbose: self.verbose,
+            logprobs: self.logprobs,
+            top_logprobs: self.top_logprobs.unwrap_or(defaults.top_logprobs),
+            show_cost: self.show_cost,
+            output: defaults.output,
+            render: defaults.render,
+            offline: false,
+            dry_run: false,
+            yes: false,
+            temperature_given: false,
+            system_given: false,
+        })
+    }
+}
+
+impl Command {
+    /// Whether the command prints JSON, in which case errors must be JSON too.
+    pub fn output(&self) -> OutputFormat {
+        match self {
+            Command::Chat(args) => args.output,
+            Command::Models(args) => args.output,
+            Command::Replay(args) => args.output,
+            #[cfg(feature = "server")]
+            Command::Sessions(SessionsCommand::List { output, .. }) => *output,
+            #[cfg(feature = "server")]
+            Command::Sessions(SessionsCommand::Grep(args)) => args.output,
+            #[cfg(feature = "server")]
+            Command::Sessions(SessionsCommand::Dedupe { output, .. }) => *output,
+            #[cfg(feature = "server")]
+            Command::Prompts(PromptsCommand::List { output }) => *output,
+            #[cfg(feature = "server")]
+            Command::SessionTemplates(SessionTemplatesCommand::List { output }) => *output,
+            #[cfg(feature = "server")]
+            Command::Bench(args) => args.output,
+            _ => OutputFormat::Text,
+        }
+    }
+}
+
+/// The `chat --output json` result.
+#[derive(Debug, Serialize)]
+pub struct ChatOutput {
+    pub content: String,
+    pub model: String,
+    pub finish_reason: Option<String>,
+    pub usage: Option<UsageStats>,
+    pub latency_ms: u64,
+    /// Secrets masked in the request before it was sent.
+    pub redactions: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<Cost>,
+    /// The most the request was projected to cost before it was sent,
+    /// unless `cost_guard` is off.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_cost: Option<CostEstimate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<Vec<TokenLogprob>>,
+    /// Side effects of post-processing the reply, such as files written.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub effects: Vec<String>,
+}
+
+/// Process exit codes, so scripts can tell failure modes apart. Argument
+/// errors reported by clap itself also exit with 2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Success = 0,
+    Failure = 1,
+    Usage = 2,
+    Auth = 3,
+    Upstream = 4,
+    RateLimited = 5,
+    ContextTooLong = 6,
+    Regression = 7,
+    /// A request over the cost threshold wasn't confirmed with `--yes`.
+    CostNotConfirmed = 8,
+}
+
+impl ExitStatus {
+    /// Classifies an error by the typed causes in its chain.
+    pub fn of(error: &anyhow::Error) -> Self {
+        if error.is::<UsageError>()
+            || error.chain().any(|cause| {
+                cause.is::<UnknownPreset>()
+                    || cause.is::<UnknownAgent>()
+                    || cause.is::<UnknownProvider>()
+            })
+        {
+            return ExitStatus::Usage;
+        }
+        if error.is::<ReplayRegression>() {
+            return ExitStatus::Regression;
+        }
+        if error.is::<CostNotConfirmed>() {
+            return ExitStatus::CostNotConfirmed;
+        }
+        #[cfg(feature = "server")]
+        if error.is::<crate::script::ScriptFailed>() {
+            return ExitStatus::Regression;
+        }
+        // The first problem found decides, as in `check`
+        if let Some(failed) = error.downcast_ref::<PreflightFailed>() {
+            return match failed.issues.first().map(|issue| issue.kind) {
+                Some(IssueKind::MissingApiKey | IssueKind::RejectedApiKey) => ExitStatus::Auth,
+                Some(IssueKind::Unreachable) => ExitStatus::Upstream,
+                _ => ExitStatus::Failure,
+            };
+        }
+        if error
+            .chain()
+            .any(|cause| cause.is::<MissingApiKey>() || cause.is::<InvalidApiKey>())
+        {
+            return ExitStatus::Auth;
+        }
+
+        if error.is::<CircuitOpen>() {
+            return ExitStatus::Upstream;
+        }
+
+        match XaiError::from_anyhow(error) {
+            Some(XaiError::Api { status: 401 | 403, .. }) => ExitStatus::Auth,
+            Some(XaiError::Api { status: 429, .. }) => ExitStatus::RateLimited,
+            Some(XaiError::Api { status: 413, .. }) => ExitStatus::ContextTooLong,
+            Some(XaiError::Api { status: 400, body }) if mentions_context_length(body) => {
+                ExitStatus::ContextTooLong
+            }
+            Some(XaiError::Api { .. } | XaiError::Timeout | XaiError::InvalidResponse { .. }) => {
+                ExitStatus::Upstream
+            }
+            Some(XaiError::ContextLengthExceeded { .. }) => ExitStatus::ContextTooLong,
+            Some(XaiError::ModelNotFound { .. }) => ExitStatus::Usage,
+            Some(XaiError::Cancelled) => ExitStatus::Failure,
+            None if error.chain().any(|cause| cause.is::<reqwest::Error>()) => {
+                ExitStatus::Upstream
+            }
+            None => ExitStatus::Failure,
+        }
+    }
+
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    /// A suggestion printed under the error in text mode.
+    pub fn hint(self) -> Option<&'static str> {
+        match self {
+            ExitStatus::Auth => Some(tr("cli.hint_auth")),
+            ExitStatus::Upstream => Some(tr("cli.hint_upstream")),
+            ExitStatus::RateLimited => Some(tr("cli.hint_rate_limited")),
+            ExitStatus::ContextTooLong => Some(tr("cli.hint_context_too_long")),
+            ExitStatus::Regression => Some(tr("cli.hint_regression")),
+            ExitStatus::CostNotConfirmed => Some(tr("cli.hint_cost")),
+            _ => None,
+        }
+    }
+}
+
+/// Invalid input that clap cannot catch, such as `--file` without a prompt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageError(pub String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+/// Machine-readable form of `error` for stderr in JSON mode. `kind` is one of
+/// `api`, `timeout`, `cancelled`, `circuit_open`, `model_not_found`,
+/// `context_length_exceeded`, `invalid_response`, `cost_not_confirmed` or
+/// `other`; API errors and `context_length_exceeded` also carry `status`,
+/// `circuit_open` carries `retry_in_secs`, `model_not_found` the
+/// `model` and `cost_not_confirmed` the `estimated_cost` and `threshold_usd`.
+pub fn error_json(error: &anyhow::Error) -> Value {
+    let mut body = json!({ "message": format!("{:#}", error) });
+    if let Some(open) = error.downcast_ref::<CircuitOpen>() {
+        body["kind"] = json!("circuit_open");
+        body["retry_in_secs"] = json!(open.retry_in_secs());
+        return json!({ "error": body });
+    }
+    if let Some(refused) = error.downcast_ref::<CostNotConfirmed>() {
+        body["kind"] = json!("cost_not_confirmed");
+        body["estimated_cost"] = json!(refused.estimate);
+        body["threshold_usd"] = json!(refused.threshold_usd);
+        return json!({ "error": body });
+    }
+    let kind = match XaiError::from_anyhow(error) {
+        Some(XaiError::Api { status, .. }) => {
+            body["status"] = json!(status);
+            "api"
+        }
+        Some(XaiError::ModelNotFound { model }) => {
+            body["model"] = json!(model);
+            "model_not_found"
+        }
+        Some(XaiError::ContextLengthExceeded { status, .. }) => {
+            body["status"] = json!(status);
+            "context_length_exceeded"
+        }
+        Some(XaiError::InvalidResponse { .. }) => "invalid_response",
+        Some(XaiError::Timeout) => "timeout",
+        Some(XaiError::Cancelled) => "cancelled",
+        None => "other",
+    };
+    body["kind"] = json!(kind);
+    json!({ "error": body })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preflight::PreflightIssue;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition_is_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_chat_subcommand_parses_focused_options() {
+        let cli =
+            Cli::try_parse_from(["grok", "chat", "hello", "-t", "0.2", "-f", "a.rs"]).unwrap();
+
+        match cli.command {
+            Some(Command::Chat(args)) => {
+                assert_eq!(args.message.as_deref(), Some("hello"));
+                assert_eq!(args.temperature, Some(0.2));
+                assert_eq!(args.max_tokens, None);
+                assert_eq!(args.files, vec![PathBuf::from("a.rs")]);
+                assert_eq!(args.model(), DEFAULT_MODEL);
+            }
+            other => panic!("expected chat, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_legacy_flags_map_to_chat() {
+        let cli = Cli::try_parse_from(["grok", "-g", "hi", "-p", "0.9", "-x", "100"]).unwrap();
+        assert!(cli.command.is_none());
+        assert!(cli.legacy.is_used());
+
+        match cli.legacy.into_command() {
+            LegacyCommand::Chat(args) => {
+                assert_eq!(args.message.as_deref(), Some("hi"));
+                assert_eq!(args.temperature, Some(0.9));
+                assert_eq!(args.max_tokens, Some(100));
+                assert_eq!(args.system(), DEFAULT_SYSTEM_PROMPT);
+            }
+            #[allow(unreachable_patterns)]
+            other => panic!("expected chat, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_flags_win_over_config_and_aliases_resolve() {
+        let config = Config {
+            temperature: 0.1,
+            max_tokens: 512,
+            system_prompt: "Be brief.".to_string(),
+            model_aliases: [("fast".to_string(), "grok-4-fast-non-reasoning".to_string())].into(),
+            ..Config::default()
+        };
+
+        let mut args = ChatArgs {
+            model: Some("fast".to_string()),
+            temperature: Some(1.2),
+            ..ChatArgs::default()
+        };
+        args.apply_config(&config).unwrap();
+        assert_eq!(args.model(), "grok-4-fast-non-reasoning");
+        assert_eq!(args.temperature, Some(1.2));
+        assert!(args.temperature_given);
+        // Left to the model's defaults, then the config's, when sent
+        assert_eq!(args.max_tokens, None);
+        assert_eq!(args.system(), "Be brief.");
+
+        let mut args = ChatArgs {
+            model: Some("slow".to_string()),
+            ..ChatArgs::default()
+        };
+        let error = args.apply_config(&config).unwrap_err();
+        assert!(error.to_string().contains("available aliases: fast"));
+        assert_eq!(ExitStatus::of(&error), ExitStatus::Usage);
+    }
+
+    #[test]
+    fn test_bare_invocation_is_not_legacy() {
+        let cli = Cli::try_parse_from(["grok"]).unwrap();
+        assert!(cli.command.is_none());
+        assert!(!cli.legacy.is_used());
+    }
+
+    #[test]
+    fn test_error_json_reports_api_status() {
+        let error = anyhow::Error::new(XaiError::Api {
+            status: 429,
+            body: "slow down".to_string(),
+        });
+        let body = error_json(&error);
+        assert_eq!(body["error"]["kind"], "api");
+        assert_eq!(body["error"]["status"], 429);
+
+        let body = error_json(&anyhow::anyhow!("boom").context("while chatting"));
+        assert_eq!(
+            body,
+            json!({"error": {"message": "while chatting: boom", "kind": "other"}})
+        );
+    }
+
+    #[test]
+    fn test_json_output_is_selected_per_command() {
+        let cli = Cli::try_parse_from(["grok", "chat", "hi", "--output", "json"]).unwrap();
+        assert_eq!(cli.command.unwrap().output(), OutputFormat::Json);
+
+        let cli = Cli::try_parse_from(["grok", "models"]).unwrap();
+        assert_eq!(cli.command.unwrap().output(), OutputFormat::Text);
+
+        assert!(Cli::try_parse_from(["grok", "chat", "--stream", "--output", "json"]).is_err());
+    }
+
+    #[test]
+    fn test_prompt_preset_replaces_the_system_prompt() {
+        let cli = Cli::try_parse_from(["grok", "chat", "hi", "--prompt-preset", "terse"]).unwrap();
+        let Some(Command::Chat(args)) = cli.command else {
+            panic!("expected chat");
+        };
+        assert_eq!(args.prompt_preset.as_deref(), Some("terse"));
+
+        assert!(Cli::try_parse_from([
+            "grok",
+            "chat",
+            "hi",
+            "--prompt-preset",
+            "terse",
+            "--system",
+            "Be brief."
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_exit_status_classifies_failures() {
+        let api = |status: u16, body: &str| {
+            anyhow::Error::new(XaiError::Api {
+                status,
+                body: body.to_string(),
+            })
+        };
+
+        assert_eq!(ExitStatus::of(&api(401, "")), ExitStatus::Auth);
+        assert_eq!(ExitStatus::of(&api(429, "")), ExitStatus::RateLimited);
+        assert_eq!(ExitStatus::of(&api(503, "")), ExitStatus::Upstream);
+        let open = anyhow::Error::new(CircuitOpen {
+            model: "grok-3".to_string(),
+            retry_in: std::time::Duration::from_millis(1500),
+        });
+        assert_eq!(ExitStatus::of(&open), ExitStatus::Upstream);
+        assert_eq!(error_json(&open)["error"]["retry_in_secs"], 2);
+        assert_eq!(
+            ExitStatus::of(&api(400, "This model's maximum prompt length is 131072")),
+            ExitStatus::ContextTooLong
+        );
+        assert_eq!(ExitStatus::of(&api(400, "bad temperature")), ExitStatus::Upstream);
+        assert_eq!(
+            ExitStatus::of(&anyhow::Error::new(MissingApiKey).context("loading config")),
+            ExitStatus::Auth
+        );
+        assert_eq!(
+            ExitStatus::of(&UsageError("--file needs a prompt".to_string()).into()),
+            ExitStatus::Usage
+        );
+        assert_eq!(
+            ExitStatus::of(
+                &anyhow::Error::new(UnknownPreset {
+                    name: "terse".to_string(),
+                    available: Vec::new(),
+                })
+                .context("choosing a prompt")
+            ),
+            ExitStatus::Usage
+        );
+        assert_eq!(
+            ExitStatus::of(&anyhow::Error::new(UnknownAgent {
+                name: "critic".to_string(),
+                available: vec!["reviewer".to_string()],
+            })),
+            ExitStatus::Usage
+        );
+        assert_eq!(
+            ExitStatus::of(&anyhow::Error::new(UnknownProvider {
+                model: "openai/gpt-4o".to_string(),
+                provider: "openai".to_string(),
+                available: vec!["xai".to_string()],
+            })),
+            ExitStatus::Usage
+        );
+        assert_eq!(
+            ExitStatus::of(
+                &ReplayRegression {
+                    min_similarity: 0.4,
+                    threshold: 0.8
+                }
+                .into()
+            ),
+            ExitStatus::Regression
+        );
+        let preflight = |kinds: &[IssueKind]| {
+            anyhow::Error::new(PreflightFailed {
+                issues: kinds
+                    .iter()
+                    .map(|&kind| PreflightIssue {
+                        kind,
+                        problem: String::new(),
+                        fix: String::new(),
+                    })
+                    .collect(),
+            })
+        };
+        assert_eq!(
+            ExitStatus::of(&preflight(&[IssueKind::RejectedApiKey])),
+            ExitStatus::Auth
+        );
+        assert_eq!(
+            ExitStatus::of(&preflight(&[IssueKind::Unreachable])),
+            ExitStatus::Upstream
+        );
+        assert_eq!(
+            ExitStatus::of(&preflight(&[IssueKind::Database, IssueKind::MissingApiKey])),
+            ExitStatus::Failure
+        );
+        assert_eq!(ExitStatus::of(&anyhow::anyhow!("boom")), ExitStatus::Failure);
+    }
+
+    #[test]
+    fn test_quiet_is_accepted_by_every_command() {
+        assert!(Cli::try_parse_from(["grok", "chat", "hi", "-q"]).unwrap().quiet);
+        assert!(Cli::try_parse_from(["grok", "models", "--quiet"]).unwrap().quiet);
+        assert!(Cli::try_parse_from(["grok", "-q", "-g", "hi"]).unwrap().quiet);
+    }
+
+    #[test]
+    fn test_render_mode_degrades_to_plain() {
+        assert!(RenderMode::Auto.styles(true, false, true));
+        assert!(!RenderMode::Auto.styles(true, false, false));
+        assert!(RenderMode::Markdown.styles(true, false, false));
+        assert!(!RenderMode::Markdown.styles(false, false, true));
+        assert!(!RenderMode::Markdown.styles(true, true, true));
+        assert!(!RenderMode::Plain.styles(true, false, true));
+    }
+
+    #[test]
+    fn test_conflicting_combinations_are_rejected() {
+        assert!(Cli::try_parse_from(["grok", "--terminal", "--server"]).is_err());
+        assert!(Cli::try_parse_from(["grok", "-g", "hi", "chat"]).is_err());
+        assert!(Cli::try_parse_from(["grok", "chat", "--continue", "-i", "abc"]).is_err());
+        assert!(Cli::try_parse_from(["grok", "chat", "--stream", "--no-stream"]).is_err());
+    }
+
+    #[test]
+    fn test_since_accepts_dates_and_times() {
+        assert_eq!(
+            parse_since("2025-03-01").unwrap().to_rfc3339(),
+            "2025-03-01T00:00:00+00:00"
+        );
+        assert_eq!(
+            parse_since("2025-03-01T12:00:00+02:00")
+                .unwrap()
+                .to_rfc3339(),
+            "2025-03-01T10:00:00+00:00"
+        );
+        assert!(parse_since("last tuesday").is_err());
+        assert!(
+            Cli::try_parse_from(["grok", "sessions", "export-all", "out", "--since", "03/01"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_until_includes_the_whole_day() {
+        assert_eq!(
+            parse_until("2025-03-01").unwrap().to_rfc3339(),
+            "2025-03-02T00:00:00+00:00"
+        );
+        assert_eq!(
+            parse_until("2025-03-01T12:00:00Z").unwrap().to_rfc3339(),
+            "2025-03-01T12:00:00+00:00"
+        );
+        assert!(
+            Cli::try_parse_from(["grok", "sessions", "export-finetune", "--rating", "meh"])
+                .is_err()
+        );
+    }
+}
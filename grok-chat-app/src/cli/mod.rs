@@ -0,0 +1,3 @@
+pub use self::cli::*;
+#[allow(clippy::module_inception)]
+mod cli;
@@ -0,0 +1,524 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::{is_offline, XaiError};
+
+/// The `[default.circuit_breaker]` section of `config.toml`. Unset keys keep
+/// their defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BreakerSettings {
+    /// Consecutive failures that open a model's breaker; 0 turns breakers off.
+    pub failures: u32,
+    /// Share of failed requests within the window that opens the breaker...
+    pub error_rate: f64,
+    /// ...once the window holds at least this many requests.
+    pub min_requests: u32,
+    pub window_secs: u64,
+    /// How long an open breaker fails requests before letting a probe through.
+    pub open_secs: u64,
+    /// Model to send to instead while a breaker is open.
+    pub fallback_model: Option<String>,
+}
+
+impl Default for BreakerSettings {
+    fn default() -> Self {
+        Self {
+            failures: 5,
+            error_rate: 0.5,
+            min_requests: 10,
+            window_secs: 60,
+            open_secs: 30,
+            fallback_model: None,
+        }
+    }
+}
+
+impl BreakerSettings {
+    pub fn validate(&self) -> Result<()> {
+        if !(self.error_rate > 0.0 && self.error_rate <= 1.0) {
+            bail!(
+                "circuit_breaker.error_rate must be above 0 and at most 1, got {}",
+                self.error_rate
+            );
+        }
+        if self.window_secs == 0 {
+            bail!("circuit_breaker.window_secs must be at least 1");
+        }
+        Ok(())
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.failures > 0
+    }
+
+    fn window(&self) -> Duration {
+        Duration::from_secs(self.window_secs)
+    }
+
+    fn open_for(&self) -> Duration {
+        Duration::from_secs(self.open_secs)
+    }
+}
+
+/// A model's breaker is open, so the request was not sent. Travels inside
+/// `anyhow::Error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircuitOpen {
+    pub model: String,
+    /// Until the next probe request is let through.
+    pub retry_in: Duration,
+}
+
+impl CircuitOpen {
+    pub fn retry_in_secs(&self) -> u64 {
+        whole_secs(self.retry_in)
+    }
+}
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Model '{}' is failing, so requests to it are paused; retry in {}s",
+            self.model,
+            self.retry_in_secs()
+        )
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    /// Requests fail fast (or go to the fallback model).
+    Open,
+    /// One probe request is in flight; its outcome closes or reopens the breaker.
+    HalfOpen,
+}
+
+/// How a request went, as far as the breaker is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    Failure,
+    /// Says nothing about the model's health.
+    Ignored,
+}
+
+impl Outcome {
+    /// Timeouts, rate limits and server errors count against the model. A
+    /// rejected request (other 4xx), a cancellation or an unreachable API is
+    /// not the model's fault.
+    pub fn of<T>(result: &Result<T>) -> Self {
+        let Err(error) = result else {
+            return Outcome::Success;
+        };
+        if is_offline(error) {
+            return Outcome::Ignored;
+        }
+        match XaiError::from_anyhow(error) {
+            Some(XaiError::Timeout) => Outcome::Failure,
+            Some(XaiError::Api { status, .. }) if *status == 408 || *status == 429 => {
+                Outcome::Failure
+            }
+            Some(XaiError::Api { status, .. }) if *status >= 500 => Outcome::Failure,
+            Some(_) => Outcome::Ignored,
+            None => Outcome::Failure,
+        }
+    }
+}
+
+/// Recent health of one model, as shown on `/stats` and in the TUI.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModelHealth {
+    pub model: String,
+    pub state: BreakerState,
+    /// Requests within the window.
+    pub requests: usize,
+    pub success_rate: Option<f64>,
+    /// Over successful requests within the window; streams count until the
+    /// first byte.
+    pub p95_latency_ms: Option<u64>,
+    pub consecutive_failures: u32,
+    /// Until an open breaker lets a probe through.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_in_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Closed,
+    Open { until: Instant },
+    HalfOpen { probe_started: Instant },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    at: Instant,
+    ok: bool,
+    latency: Duration,
+}
+
+#[derive(Debug)]
+struct Tracker {
+    state: State,
+    consecutive_failures: u32,
+    samples: VecDeque<Sample>,
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self {
+            state: State::Closed,
+            consecutive_failures: 0,
+            samples: VecDeque::new(),
+        }
+    }
+}
+
+impl Tracker {
+    fn prune(&mut self, now: Instant, window: Duration) {
+        while self
+            .samples
+            .front()
+            .is_some_and(|sample| now.saturating_duration_since(sample.at) > window)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    fn error_rate(&self) -> f64 {
+        let failed = self.samples.iter().filter(|sample| !sample.ok).count();
+        failed as f64 / self.samples.len().max(1) as f64
+    }
+}
+
+/// A circuit breaker per model, with the latency and success rate behind it.
+/// Clones share their state.
+#[derive(Debug, Clone, Default)]
+pub struct CircuitBreakers {
+    settings: Arc<BreakerSettings>,
+    models: Arc<Mutex<HashMap<String, Tracker>>>,
+}
+
+impl CircuitBreakers {
+    pub fn new(settings: BreakerSettings) -> Self {
+        Self {
+            settings: Arc::new(settings),
+            models: Arc::default(),
+        }
+    }
+
+    pub fn settings(&self) -> &BreakerSettings {
+        &self.settings
+    }
+
+    /// Lets a request to `model` through, unless its breaker is open. Once
+    /// `open_secs` have passed, one request goes through as a probe.
+    pub fn admit(&self, model: &str) -> Result<(), CircuitOpen> {
+        self.admit_at(model, Instant::now())
+    }
+
+    /// Reports how a request admitted for `model` went.
+    pub fn record(&self, model: &str, outcome: Outcome, latency: Duration) {
+        self.record_at(model, outcome, latency, Instant::now())
+    }
+
+    /// Every model requested so far, by name.
+    pub fn health(&self) -> Vec<ModelHealth> {
+        let now = Instant::now();
+        let mut models = self.models.lock().unwrap();
+        let mut health: Vec<ModelHealth> = models
+            .iter_mut()
+            .map(|(model, tracker)| self.snapshot(model, tracker, now))
+            .collect();
+        health.sort_by(|a, b| a.model.cmp(&b.model));
+        health
+    }
+
+    pub fn health_of(&self, model: &str) -> Option<ModelHealth> {
+        let now = Instant::now();
+        let mut models = self.models.lock().unwrap();
+        let tracker = models.get_mut(model)?;
+        Some(self.snapshot(model, tracker, now))
+    }
+
+    fn admit_at(&self, model: &str, now: Instant) -> Result<(), CircuitOpen> {
+        let mut models = self.models.lock().unwrap();
+        let Some(tracker) = models.get_mut(model) else {
+            return Ok(());
+        };
+        let open_for = self.settings.open_for();
+        let retry_in = match tracker.state {
+            State::Closed => return Ok(()),
+            State::Open { until } if now < until => until - now,
+            // A probe that never reported back (its caller gave up) stops
+            // blocking once it is as old as the open period
+            State::HalfOpen { probe_started }
+                if now.saturating_duration_since(probe_started) < open_for =>
+            {
+                open_for - now.saturating_duration_since(probe_started)
+            }
+            State::Open { .. } | State::HalfOpen { .. } => {
+                tracker.state = State::HalfOpen { probe_started: now };
+                return Ok(());
+            }
+        };
+        Err(CircuitOpen {
+            model: model.to_string(),
+            retry_in,
+        })
+    }
+
+    fn record_at(&self, model: &str, outcome: Outcome, latency: Duration, now: Instant) {
+        let settings = &self.settings;
+        let mut models = self.models.lock().unwrap();
+        let tracker = models.entry(model.to_string()).or_default();
+        tracker.prune(now, settings.window());
+
+        let ok = match outcome {
+            Outcome::Ignored => {
+                if let State::HalfOpen { .. } = tracker.state {
+                    // Let the next request probe instead
+                    tracker.state = State::Open { until: now };
+                }
+                return;
+            }
+            Outcome::Success => true,
+            Outcome::Failure => false,
+        };
+        tracker.samples.push_back(Sample {
+            at: now,
+            ok,
+            latency,
+        });
+
+        if ok {
+            tracker.consecutive_failures = 0;
+            if let State::HalfOpen { .. } = tracker.state {
+                // Failures from before the outage would reopen it at once
+                tracker.samples.retain(|sample| sample.ok);
+                tracker.state = State::Closed;
+            }
+            return;
+        }
+
+        tracker.consecutive_failures += 1;
+        let trip = match tracker.state {
+            State::HalfOpen { .. } => true,
+            State::Open { .. } => false,
+            State::Closed => {
+                settings.enabled()
+                    && (tracker.consecutive_failures >= settings.failures
+                        || (tracker.samples.len() >= settings.min_requests as usize
+                            && tracker.error_rate() > settings.error_rate))
+            }
+        };
+        if trip {
+            tracker.state = State::Open {
+                until: now + settings.open_for(),
+            };
+        }
+    }
+
+    fn snapshot(&self, model: &str, tracker: &mut Tracker, now: Instant) -> ModelHealth {
+        tracker.prune(now, self.settings.window());
+        let (state, retry_in) = match tracker.state {
+            State::Closed => (BreakerState::Closed, None),
+            State::Open { until } => (
+                BreakerState::Open,
+                Some(until.saturating_duration_since(now)),
+            ),
+            State::HalfOpen { .. } => (BreakerState::HalfOpen, None),
+        };
+        let requests = tracker.samples.len();
+        ModelHealth {
+            model: model.to_string(),
+            state,
+            requests,
+            success_rate: (requests > 0).then(|| 1.0 - tracker.error_rate()),
+            p95_latency_ms: p95(tracker).map(|latency| latency.as_millis() as u64),
+            consecutive_failures: tracker.consecutive_failures,
+            retry_in_secs: retry_in.map(whole_secs),
+        }
+    }
+}
+
+fn p95(tracker: &Tracker) -> Option<Duration> {
+    let mut latencies: Vec<Duration> = tracker
+        .samples
+        .iter()
+        .filter(|sample| sample.ok)
+        .map(|sample| sample.latency)
+        .collect();
+    if latencies.is_empty() {
+        return None;
+    }
+    latencies.sort();
+    let rank = (latencies.len() * 95).div_ceil(100);
+    Some(latencies[rank - 1])
+}
+
+fn whole_secs(duration: Duration) -> u64 {
+    duration.as_millis().div_ceil(1000) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODEL: &str = "grok-3";
+    const LATENCY: Duration = Duration::from_millis(100);
+
+    fn breakers() -> CircuitBreakers {
+        CircuitBreakers::new(BreakerSettings {
+            failures: 3,
+            open_secs: 30,
+            ..BreakerSettings::default()
+        })
+    }
+
+    fn state(breakers: &CircuitBreakers) -> BreakerState {
+        breakers.health_of(MODEL).unwrap().state
+    }
+
+    #[test]
+    fn test_breaker_opens_probes_and_closes_over_time() {
+        let breakers = breakers();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        for _ in 0..3 {
+            assert!(breakers.admit_at(MODEL, at(0)).is_ok());
+            breakers.record_at(MODEL, Outcome::Failure, LATENCY, at(0));
+        }
+        let open = breakers.admit_at(MODEL, at(10)).unwrap_err();
+        assert_eq!(open.retry_in, Duration::from_secs(20));
+        assert_eq!(state(&breakers), BreakerState::Open);
+
+        // Half-open: a single probe goes through, and a failed one reopens
+        assert!(breakers.admit_at(MODEL, at(30)).is_ok());
+        assert!(breakers.admit_at(MODEL, at(31)).is_err());
+        breakers.record_at(MODEL, Outcome::Failure, LATENCY, at(31));
+        assert!(breakers.admit_at(MODEL, at(60)).is_err());
+
+        assert!(breakers.admit_at(MODEL, at(61)).is_ok());
+        assert_eq!(state(&breakers), BreakerState::HalfOpen);
+        breakers.record_at(MODEL, Outcome::Success, LATENCY, at(62));
+        assert!(breakers.admit_at(MODEL, at(62)).is_ok());
+        let health = breakers.health_of(MODEL).unwrap();
+        assert_eq!(health.state, BreakerState::Closed);
+        assert_eq!(health.consecutive_failures, 0);
+
+        // A failure right after closing doesn't reopen it
+        breakers.record_at(MODEL, Outcome::Failure, LATENCY, at(63));
+        assert!(breakers.admit_at(MODEL, at(63)).is_ok());
+    }
+
+    #[test]
+    fn test_error_rate_within_window_opens_breaker() {
+        let breakers = CircuitBreakers::new(BreakerSettings {
+            min_requests: 4,
+            ..BreakerSettings::default()
+        });
+        let start = Instant::now();
+        for (secs, outcome) in [
+            (0, Outcome::Failure),
+            (1, Outcome::Success),
+            (2, Outcome::Failure),
+            (3, Outcome::Success),
+        ] {
+            breakers.record_at(MODEL, outcome, LATENCY, start + Duration::from_secs(secs));
+        }
+        assert!(breakers
+            .admit_at(MODEL, start + Duration::from_secs(4))
+            .is_ok());
+
+        // The oldest failure has left the window by now
+        breakers.record_at(
+            MODEL,
+            Outcome::Failure,
+            LATENCY,
+            start + Duration::from_secs(61),
+        );
+        assert!(breakers
+            .admit_at(MODEL, start + Duration::from_secs(62))
+            .is_ok());
+        breakers.record_at(
+            MODEL,
+            Outcome::Failure,
+            LATENCY,
+            start + Duration::from_secs(62),
+        );
+        assert!(breakers
+            .admit_at(MODEL, start + Duration::from_secs(63))
+            .is_err());
+    }
+
+    #[test]
+    fn test_abandoned_probe_and_ignored_outcomes_do_not_wedge_breaker() {
+        let breakers = breakers();
+        let start = Instant::now();
+        for _ in 0..3 {
+            breakers.record_at(MODEL, Outcome::Failure, LATENCY, start);
+        }
+        let probe = start + Duration::from_secs(30);
+        assert!(breakers.admit_at(MODEL, probe).is_ok());
+        breakers.record_at(MODEL, Outcome::Ignored, LATENCY, probe);
+        assert!(breakers.admit_at(MODEL, probe).is_ok());
+
+        // Never reported back: another probe once the open period has passed
+        assert!(breakers
+            .admit_at(MODEL, probe + Duration::from_secs(29))
+            .is_err());
+        assert!(breakers
+            .admit_at(MODEL, probe + Duration::from_secs(30))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_health_reports_success_rate_and_p95() {
+        let breakers = CircuitBreakers::new(BreakerSettings {
+            failures: 0,
+            ..BreakerSettings::default()
+        });
+        let now = Instant::now();
+        for millis in 1..=100 {
+            breakers.record_at(MODEL, Outcome::Success, Duration::from_millis(millis), now);
+        }
+        for _ in 0..100 {
+            breakers.record_at(MODEL, Outcome::Failure, LATENCY, now);
+        }
+        let health = breakers.health_of(MODEL).unwrap();
+        assert_eq!(health.requests, 200);
+        assert_eq!(health.success_rate, Some(0.5));
+        assert_eq!(health.p95_latency_ms, Some(95));
+        // Disabled breakers only track
+        assert_eq!(health.state, BreakerState::Closed);
+    }
+
+    #[test]
+    fn test_outcome_classification() {
+        let api = |status| {
+            Err::<(), _>(anyhow::Error::new(XaiError::Api {
+                status,
+                body: String::new(),
+            }))
+        };
+        assert_eq!(Outcome::of(&Ok(())), Outcome::Success);
+        assert_eq!(Outcome::of(&api(503)), Outcome::Failure);
+        assert_eq!(Outcome::of(&api(429)), Outcome::Failure);
+        assert_eq!(Outcome::of(&api(400)), Outcome::Ignored);
+        let cancelled: Result<()> = Err(XaiError::Cancelled.into());
+        assert_eq!(Outcome::of(&cancelled), Outcome::Ignored);
+        let timeout: Result<()> = Err(XaiError::Timeout.into());
+        assert_eq!(Outcome::of(&timeout), Outcome::Failure);
+    }
+}
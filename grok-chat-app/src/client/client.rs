@@ -1,73 +1,253 @@
 use anyhow::{anyhow, Result};
 use futures_util::StreamExt;
-use reqwest::{Client as HttpClient, Response};
+use reqwest::Client as HttpClient;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio_stream::Stream;
+use tokio_util::sync::CancellationToken;
 
-use crate::config::Config;
-use crate::models::{ApiChatRequest, ApiChatResponse, ApiMessage, Choice, Delta, UsageStats};
+use super::transport::{error_for_model, error_for_status, read_json};
+use super::{
+    closest_model, envelope_message, is_context_length_exceeded, Capabilities, CircuitBreakers,
+    ModelMigration, Outcome, ProviderSettings, Providers, RetiredModel, XaiError, DEFAULT_PROVIDER,
+};
+use crate::capabilities::{ModelProfiles, ParamWarning, RequestDefaults};
+use crate::config::{Config, DEFAULT_SYSTEM_PROMPT};
+use crate::context::{estimate_message_tokens, estimate_tokens, shed_oldest, SHED_ATTEMPTS};
+use crate::models::{
+    ApiChatRequest, ApiChatResponse, ApiMessage, CompletionStats, Conversation, StreamChunk,
+    TokenLogprob, UsageStats,
+};
+use crate::postprocess::{Pipeline, PostProcessor, ResponseContext};
+use crate::pricing::{CostEstimate, PricingTable};
+use crate::redact::Redactor;
 
-const XAI_API_BASE_URL: &str = "https://api.x.ai/v1";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 
+/// Sent after the partial reply when resuming a stream that was cut off.
+const RESUME_PROMPT: &str = "Your previous reply was cut off. Continue exactly where it stopped, \
+     without repeating anything or adding a preamble.";
+
+/// Wait before the first follow-up request, growing with each attempt.
+const RESUME_BACKOFF: Duration = Duration::from_millis(250);
+
+/// How long a fetched list of models is used before fetching it again.
+const MODELS_TTL: Duration = Duration::from_secs(600);
+
+/// A list of models, and when it was fetched.
+type FetchedModels = (Instant, Vec<String>);
+
+pub type ContentStream = Pin<Box<dyn Stream<Item = Result<StreamChunk>> + Send>>;
+
+/// The body of `GET /models`; only the ids are used.
+#[derive(Deserialize)]
+struct ModelList {
+    data: Vec<ModelEntry>,
+}
+
+#[derive(Deserialize)]
+struct ModelEntry {
+    id: String,
+}
+
+/// Thin wrapper around a single pooled `reqwest::Client`. Cloning is cheap and
+/// shares the underlying connection pool, so construct it once and pass it around.
+/// Talks to xAI or to any other OpenAI-compatible provider; see
+/// [`Providers`].
+#[derive(Clone)]
 pub struct XaiClient {
     client: HttpClient,
+    /// Empty for providers that take no key.
     api_key: String,
+    base_url: String,
+    request_timeout: Duration,
+    provider: String,
+    capabilities: Capabilities,
+    /// Configured models, listed when the provider can't list its own.
+    models: Vec<String>,
 }
 
 impl XaiClient {
     pub fn new(config: &Config) -> Self {
-        let client = HttpClient::new();
+        Self::connect(
+            config,
+            DEFAULT_PROVIDER,
+            config.xai_api_key(),
+            config.xai_base_url(),
+        )
+    }
+
+    /// A client for the provider configured as `name`.
+    pub fn for_provider(config: &Config, name: &str, settings: &ProviderSettings) -> Self {
+        let api_key = settings
+            .api_key_env
+            .as_deref()
+            .and_then(|var| std::env::var(var).ok())
+            .unwrap_or_default();
+        Self {
+            capabilities: settings.capabilities(),
+            models: settings.models.clone(),
+            ..Self::connect(config, name, &api_key, &settings.base_url)
+        }
+    }
+
+    fn connect(config: &Config, provider: &str, api_key: &str, base_url: &str) -> Self {
+        let client = HttpClient::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .pool_idle_timeout(Duration::from_secs(90))
+            .user_agent(concat!("grok-chat-app/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .unwrap_or_else(|_| HttpClient::new());
+
         Self {
             client,
-            api_key: config.xai_api_key().to_string(),
+            api_key: api_key.to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            request_timeout: config.request_timeout(),
+            provider: provider.to_string(),
+            capabilities: Capabilities::default(),
+            models: Vec::new(),
         }
     }
 
-    pub async fn list_models(&self) -> Result<Vec<String>> {
-        let response = self
+    pub fn provider(&self) -> &str {
+        &self.provider
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// `model` as the provider knows it, without the provider prefix.
+    fn upstream_model<'a>(&self, model: &'a str) -> &'a str {
+        model
+            .strip_prefix(self.provider.as_str())
+            .and_then(|model| model.strip_prefix('/'))
+            .unwrap_or(model)
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self
             .client
-            .get(&format!("{}/models", XAI_API_BASE_URL))
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .send()
-            .await?;
+            .request(method, format!("{}/{}", self.base_url, path))
+            .header("Content-Type", "application/json");
+        if self.api_key.is_empty() {
+            return request;
+        }
+        request.header("Authorization", format!("Bearer {}", self.api_key))
+    }
+
+    /// Error bodies from providers other than xAI are cut down to their
+    /// message, whichever envelope it came in. Errors are classified first,
+    /// from the whole body.
+    fn normalized(&self, error: anyhow::Error) -> anyhow::Error {
+        if self.provider == DEFAULT_PROVIDER {
+            return error;
+        }
+        let message = |body: String| envelope_message(&body).unwrap_or(body);
+        match error.downcast::<XaiError>() {
+            Ok(XaiError::Api { status, body }) => XaiError::Api {
+                status,
+                body: message(body),
+            }
+            .into(),
+            Ok(XaiError::ContextLengthExceeded { status, body }) => {
+                XaiError::ContextLengthExceeded {
+                    status,
+                    body: message(body),
+                }
+                .into()
+            }
+            Ok(error) => error.into(),
+            Err(error) => error,
+        }
+    }
 
-        if !response.status().is_success() {
-            return Err(anyhow!("Failed to list models: {}", response.status()));
+    /// Runs `request` under the configured timeout, abandoning it (and the
+    /// underlying connection) as soon as `cancel` fires.
+    async fn guarded<T>(
+        &self,
+        cancel: Option<&CancellationToken>,
+        request: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let bounded = async {
+            tokio::time::timeout(self.request_timeout, request)
+                .await
+                .map_err(|_| anyhow::Error::new(XaiError::Timeout))?
+        };
+
+        match cancel {
+            Some(token) => tokio::select! {
+                biased;
+                _ = token.cancelled() => Err(XaiError::Cancelled.into()),
+                result = bounded => result,
+            },
+            None => bounded.await,
         }
+    }
 
-        let models_response: Value = response.json().await?;
-        let models = models_response["data"]
-            .as_array()
-            .ok_or_else(|| anyhow!("Invalid response format for models"))?;
+    /// The provider's models. Other providers' ids carry their prefix, and
+    /// their configured models stand in for a list that can't be fetched.
+    pub async fn list_models(&self) -> Result<Vec<String>> {
+        let listed: Result<ModelList> = self
+            .guarded(None, async {
+                let response = self.request(reqwest::Method::GET, "models").send().await?;
 
-        let model_names: Vec<String> = models
-            .iter()
-            .filter_map(|model| {
-                let id = model["id"].as_str()?;
-                // Filter to text-based Grok models only
-                if id.contains("grok") && !id.contains("vision") && !id.contains("image") {
-                    Some(id.to_string())
-                } else {
-                    None
-                }
+                read_json(error_for_status(response).await?).await
             })
-            .collect();
+            .await
+            .map_err(|e| self.normalized(e));
 
-        Ok(model_names)
+        if self.provider == DEFAULT_PROVIDER {
+            let model_names: Vec<String> = listed?
+                .data
+                .into_iter()
+                .map(|model| model.id)
+                // Filter to text-based Grok models only
+                .filter(|id| id.contains("grok") && !id.contains("vision") && !id.contains("image"))
+                .collect();
+            return Ok(model_names);
+        }
+
+        let ids = match listed {
+            Ok(models) => models.data.into_iter().map(|model| model.id).collect(),
+            Err(_) if !self.models.is_empty() => self.models.clone(),
+            Err(e) => return Err(e),
+        };
+        Ok(ids
+            .into_iter()
+            .map(|id| format!("{}/{}", self.provider, id))
+            .collect())
     }
 
-    pub async fn chat_completion(
-        &self,
-        request: ApiChatRequest,
-    ) -> Result<ApiChatResponse> {
+    /// The JSON body posted for `request`, fitted to what the provider
+    /// supports.
+    pub fn request_body(&self, request: ApiChatRequest, stream: bool) -> Value {
+        // The prompt replaces any system messages leading the history
+        let messages = match request.system_prompt {
+            Some(system_prompt) => Conversation::new()
+                .system_prompt(system_prompt)
+                .history(request.messages)
+                .into_messages(),
+            None => request.messages,
+        };
+
         let mut request_body = json!({
-            "model": request.model,
-            "messages": request.messages,
-            "stream": request.stream.unwrap_or(false),
+            "model": self.upstream_model(&request.model),
+            "messages": messages,
+            "stream": stream,
         });
 
+        // Ask for a trailing usage event so streamed replies can be costed too
+        if stream && self.capabilities.usage {
+            request_body["stream_options"] = json!({"include_usage": true});
+        }
+
         if let Some(max_tokens) = request.max_tokens {
             request_body["max_tokens"] = json!(max_tokens);
         }
@@ -76,152 +256,542 @@ impl XaiClient {
             request_body["temperature"] = json!(temperature);
         }
 
-        if let Some(system_prompt) = request.system_prompt {
-            // Add system message to the beginning of messages
-            let messages_array = request_body["messages"].as_array_mut().unwrap();
-            messages_array.insert(
-                0,
-                json!({
-                    "role": "system",
-                    "content": system_prompt
-                }),
-            );
+        // Only sent when asked for, so ordinary requests stay byte-identical
+        if request.logprobs && self.capabilities.logprobs {
+            request_body["logprobs"] = json!(true);
+            if request.top_logprobs > 0 {
+                request_body["top_logprobs"] = json!(request.top_logprobs);
+            }
         }
 
-        let response = self
-            .client
-            .post(&format!("{}/chat/completions", XAI_API_BASE_URL))
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .json(&request_body)
-            .send()
-            .await?;
+        request_body
+    }
 
-        if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(anyhow!(
-                "API request failed with status {}: {}",
-                response.status(),
-                error_text
-            ));
-        }
+    pub async fn chat_completion(
+        &self,
+        request: ApiChatRequest,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<ApiChatResponse> {
+        let stream = request.stream.unwrap_or(false);
+        let model = request.model.clone();
+        let request_body = self.request_body(request, stream);
+
+        let mut response: ApiChatResponse = self
+            .guarded(cancel, async {
+                let response = self
+                    .request(reqwest::Method::POST, "chat/completions")
+                    .json(&request_body)
+                    .send()
+                    .await?;
 
-        let chat_response: ApiChatResponse = response.json().await?;
-        Ok(chat_response)
+                read_json(error_for_model(response, &model).await?).await
+            })
+            .await
+            .map_err(|e| self.normalized(e))?;
+
+        if response.usage.is_none() && !self.capabilities.usage {
+            let reply = response.get_content().unwrap_or_default();
+            response.usage = Some(estimated_usage(prompt_tokens(&request_body), &reply));
+        }
+        Ok(response)
     }
 
+    /// Streams content deltas, ending with a chunk that carries `finish_reason`
+    /// and `usage` when the API reports them. The timeout covers the wait for
+    /// response headers; once data flows, only `cancel` ends the stream early,
+    /// yielding a final `XaiError::Cancelled` item. A provider that can't
+    /// stream answers with the whole reply as one chunk.
     pub async fn chat_completion_stream(
         &self,
-        request: ApiChatRequest,
-    ) -> Result<impl Stream<Item = Result<String>>> {
-        let mut request_body = json!({
-            "model": request.model,
-            "messages": request.messages,
-            "stream": true,
-        });
+        mut request: ApiChatRequest,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<ContentStream> {
+        if !self.capabilities.streaming {
+            request.stream = Some(false);
+            let response = self.chat_completion(request, cancel).await?;
+            let chunk = StreamChunk {
+                content: response.get_content().unwrap_or_default(),
+                finish_reason: response.get_finish_reason(),
+                usage: response.usage,
+                ..StreamChunk::default()
+            };
+            return Ok(Box::pin(futures_util::stream::once(async { Ok(chunk) })));
+        }
 
-        if let Some(max_tokens) = request.max_tokens {
-            request_body["max_tokens"] = json!(max_tokens);
+        let model = request.model.clone();
+        let request_body = self.request_body(request, true);
+
+        let response = self
+            .guarded(cancel, async {
+                let response = self
+                    .request(reqwest::Method::POST, "chat/completions")
+                    .json(&request_body)
+                    .send()
+                    .await?;
+
+                error_for_model(response, &model).await
+            })
+            .await
+            .map_err(|e| self.normalized(e))?;
+
+        let content_stream = futures_util::stream::unfold(
+            Some((
+                response.bytes_stream(),
+                SseBuffer::default(),
+                VecDeque::new(),
+            )),
+            |state| async move {
+                let (mut bytes, mut buffer, mut ready) = state?;
+                loop {
+                    if let Some(chunk) = ready.pop_front() {
+                        return Some((Ok(chunk), Some((bytes, buffer, ready))));
+                    }
+                    match bytes.next().await {
+                        Some(Ok(data)) => ready.extend(
+                            buffer
+                                .push(&data)
+                                .iter()
+                                .filter_map(|event| parse_stream_event(event)),
+                        ),
+                        // A broken body ends the stream after reporting the error
+                        Some(Err(e)) => return Some((Err(e.into()), None)),
+                        None => return None,
+                    }
+                }
+            },
+        );
+        let mut content_stream: ContentStream = Box::pin(content_stream);
+        if !self.capabilities.usage {
+            content_stream = with_estimated_usage(content_stream, prompt_tokens(&request_body));
         }
 
-        if let Some(temperature) = request.temperature {
-            request_body["temperature"] = json!(temperature);
+        match cancel {
+            Some(token) => Ok(cancellable(content_stream, token.clone())),
+            None => Ok(content_stream),
         }
+    }
 
-        if let Some(system_prompt) = request.system_prompt {
-            let messages_array = request_body["messages"].as_array_mut().unwrap();
-            messages_array.insert(
-                0,
-                json!({
-                    "role": "system",
-                    "content": system_prompt
-                }),
-            );
+    /// `Ok(false)` when the API rejects the key. Network and other failures
+    /// are errors rather than a verdict on the key.
+    pub async fn validate_api_key(&self) -> Result<bool> {
+        match self.list_models().await {
+            Ok(_) => Ok(true),
+            Err(e) => match XaiError::from_anyhow(&e) {
+                Some(XaiError::Api {
+                    status: 401 | 403, ..
+                }) => Ok(false),
+                _ => Err(e),
+            },
         }
+    }
+}
 
-        let response = self
-            .client
-            .post(&format!("{}/chat/completions", XAI_API_BASE_URL))
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .json(&request_body)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(anyhow!(
-                "API request failed with status {}: {}",
-                response.status(),
-                error_text
-            ));
-        }
-
-        let stream = response.bytes_stream();
-        let content_stream = stream.filter_map(move |chunk| {
-            let chunk = chunk.ok()?;
-            let text = String::from_utf8_lossy(&chunk);
-
-            // Parse SSE-like stream format
-            let lines: Vec<&str> = text.split('\n').collect();
-            let mut content = String::new();
-
-            for line in lines {
-                if line.starts_with("data: ") {
-                    let data = &line[6..]; // Remove "data: " prefix
-                    if data == "[DONE]" {
-                        continue;
-                    }
+/// Prompt tokens of the messages in a request body, estimated as elsewhere.
+fn prompt_tokens(body: &Value) -> usize {
+    let messages: Vec<ApiMessage> =
+        serde_json::from_value(body["messages"].clone()).unwrap_or_default();
+    estimate_message_tokens(&messages)
+}
 
-                    if let Ok(chunk_data) = serde_json::from_str::<Value>(data) {
-                        if let Some(choices) = chunk_data["choices"].as_array() {
-                            for choice in choices {
-                                if let Some(delta) = choice["delta"].as_object() {
-                                    if let Some(delta_content) = delta["content"].as_str() {
-                                        content.push_str(delta_content);
-                                    }
-                                }
-                            }
-                        }
-                    }
+/// Usage for a provider that doesn't report it, estimated from the text.
+fn estimated_usage(prompt_tokens: usize, reply: &str) -> UsageStats {
+    let prompt_tokens = prompt_tokens as i32;
+    let completion_tokens = estimate_tokens(reply) as i32;
+    UsageStats {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens: prompt_tokens + completion_tokens,
+    }
+}
+
+/// Passes `stream` through and, if it ends without reporting usage, yields
+/// one more chunk with usage estimated from `prompt_tokens` and the reply.
+fn with_estimated_usage(stream: ContentStream, prompt_tokens: usize) -> ContentStream {
+    let state = (stream, String::new(), false);
+    Box::pin(futures_util::stream::unfold(
+        Some(state),
+        move |state| async move {
+            let (mut stream, mut reply, reported) = state?;
+            match stream.next().await {
+                Some(Ok(chunk)) => {
+                    reply.push_str(&chunk.content);
+                    let reported = reported || chunk.usage.is_some();
+                    Some((Ok(chunk), Some((stream, reply, reported))))
+                }
+                Some(Err(e)) => Some((Err(e), None)),
+                None if reported => None,
+                None => {
+                    let chunk = StreamChunk {
+                        usage: Some(estimated_usage(prompt_tokens, &reply)),
+                        ..StreamChunk::default()
+                    };
+                    Some((Ok(chunk), None))
                 }
             }
+        },
+    ))
+}
 
-            if content.is_empty() {
-                None
-            } else {
-                Some(Ok(content))
+/// Reassembles server-sent event lines that may be split across network reads.
+#[derive(Default)]
+struct SseBuffer {
+    pending: Vec<u8>,
+}
+
+impl SseBuffer {
+    /// Appends `bytes` and returns the payloads of every complete `data:` line.
+    fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(bytes);
+
+        let mut events = Vec::new();
+        while let Some(end) = self.pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if let Some(data) = line.trim_end().strip_prefix("data:") {
+                events.push(data.trim_start().to_string());
             }
-        });
+        }
+        events
+    }
+}
 
-        Ok(content_stream)
+/// Decodes one SSE payload; `[DONE]`, malformed and empty events yield `None`.
+fn parse_stream_event(data: &str) -> Option<StreamChunk> {
+    if data == "[DONE]" {
+        return None;
     }
 
-    pub async fn validate_api_key(&self) -> Result<bool> {
-        match self.list_models().await {
-            Ok(_) => Ok(true),
-            Err(_) => Ok(false),
+    let event: Value = serde_json::from_str(data).ok()?;
+    let mut chunk = StreamChunk::default();
+
+    if let Some(choices) = event["choices"].as_array() {
+        for choice in choices {
+            if let Some(delta_content) = choice["delta"]["content"].as_str() {
+                chunk.content.push_str(delta_content);
+            }
+            if let Some(reason) = choice["finish_reason"].as_str() {
+                chunk.finish_reason = Some(reason.to_string());
+            }
         }
     }
+
+    if !event["usage"].is_null() {
+        chunk.usage = serde_json::from_value(event["usage"].clone()).ok();
+    }
+
+    (!chunk.is_empty()).then_some(chunk)
 }
 
-pub struct ChatService {
+/// Follows a streamed reply and, if it fails after some content arrived,
+/// asks for the rest with up to `attempts` follow-up requests that carry the
+/// partial reply. Chunks after a resume say how many it took.
+struct Resume {
     client: XaiClient,
+    request: ApiChatRequest,
+    cancel: Option<CancellationToken>,
+    attempts: u32,
+    resumes: u32,
+    partial: String,
+    /// `None` once the reply has ended or failed for good.
+    stream: Option<ContentStream>,
+}
+
+impl Resume {
+    fn stream(self) -> ContentStream {
+        Box::pin(futures_util::stream::unfold(self, Self::next))
+    }
+
+    async fn next(mut self) -> Option<(Result<StreamChunk>, Self)> {
+        loop {
+            match self.stream.as_mut()?.next().await {
+                Some(Ok(mut chunk)) => {
+                    self.partial.push_str(&chunk.content);
+                    if self.resumes > 0 {
+                        chunk.resumes = Some(self.resumes);
+                    }
+                    return Some((Ok(chunk), self));
+                }
+                Some(Err(e)) if self.can_resume(&e) => {
+                    // A follow-up that fails to connect comes back as the
+                    // next error, which may be resumed in turn
+                    let stream = self.resume().await;
+                    self.stream = Some(stream);
+                }
+                Some(Err(e)) => {
+                    self.stream = None;
+                    return Some((Err(e), self));
+                }
+                None => return None,
+            }
+        }
+    }
+
+    /// Only a reply that got somewhere is resumed, and never a cancelled one.
+    fn can_resume(&self, error: &anyhow::Error) -> bool {
+        self.resumes < self.attempts
+            && !self.partial.is_empty()
+            && !matches!(XaiError::from_anyhow(error), Some(XaiError::Cancelled))
+    }
+
+    async fn resume(&mut self) -> ContentStream {
+        self.resumes += 1;
+        tokio::time::sleep(RESUME_BACKOFF * self.resumes).await;
+
+        let mut request = self.request.clone();
+        request
+            .messages
+            .push(ApiMessage::assistant(self.partial.clone()));
+        request.messages.push(ApiMessage::user(RESUME_PROMPT));
+        match self
+            .client
+            .chat_completion_stream(request, self.cancel.as_ref())
+            .await
+        {
+            Ok(stream) => stream,
+            Err(e) => Box::pin(futures_util::stream::once(async { Err(e) })),
+        }
+    }
+}
+
+/// Passes `stream` through and reports to `breakers` how it ended: a
+/// failure (or nothing, for a cancellation) if with an error, a success
+/// otherwise. A stream dropped before it ends reports nothing.
+fn reported(
+    stream: ContentStream,
+    breakers: CircuitBreakers,
+    model: String,
+    latency: Duration,
+) -> ContentStream {
+    let state = (stream, Some((breakers, model)));
+    Box::pin(futures_util::stream::unfold(
+        state,
+        move |(mut stream, mut report)| async move {
+            let item = stream.next().await;
+            let outcome = match &item {
+                Some(Ok(_)) => None,
+                Some(result) => Some(Outcome::of(result)),
+                None => Some(Outcome::Success),
+            };
+            if let Some(outcome) = outcome {
+                if let Some((breakers, model)) = report.take() {
+                    breakers.record(&model, outcome, latency);
+                }
+            }
+            item.map(|item| (item, (stream, report)))
+        },
+    ))
+}
+
+/// Passes `stream` through and, once it ends, yields one more chunk with
+/// the whole reply run through `pipeline`. A failed stream is not processed.
+fn post_processed(
+    stream: ContentStream,
+    pipeline: Pipeline,
+    ctx: ResponseContext,
+) -> ContentStream {
+    let state = (Some(stream), String::new());
+    Box::pin(futures_util::stream::unfold(
+        state,
+        move |(stream, mut reply)| {
+            let pipeline = pipeline.clone();
+            let ctx = ctx.clone();
+            async move {
+                let mut stream = stream?;
+                match stream.next().await {
+                    Some(Ok(chunk)) => {
+                        reply.push_str(&chunk.content);
+                        Some((Ok(chunk), (Some(stream), reply)))
+                    }
+                    Some(Err(e)) => Some((Err(e), (None, reply))),
+                    None => {
+                        let chunk = StreamChunk {
+                            processed: Some(pipeline.process(&reply, &ctx)),
+                            ..StreamChunk::default()
+                        };
+                        Some((Ok(chunk), (None, String::new())))
+                    }
+                }
+            }
+        },
+    ))
+}
+
+/// Ends `stream` with a single `XaiError::Cancelled` item once `token` fires,
+/// dropping the inner stream (and its HTTP body) immediately.
+fn cancellable(stream: ContentStream, token: CancellationToken) -> ContentStream {
+    Box::pin(futures_util::stream::unfold(
+        Some((stream, token)),
+        |state| async move {
+            let (mut stream, token) = state?;
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => Some((Err(XaiError::Cancelled.into()), None)),
+                item = stream.next() => item.map(|item| (item, Some((stream, token)))),
+            }
+        },
+    ))
+}
+
+#[derive(Clone)]
+pub struct ChatService {
+    providers: Providers,
     default_system_prompt: String,
+    redactor: Option<Arc<Redactor>>,
+    profiles: Arc<ModelProfiles>,
+    defaults: RequestDefaults,
+    resume_attempts: u32,
+    post_processors: Pipeline,
+    breakers: CircuitBreakers,
+    /// The last list of models fetched.
+    models: Arc<Mutex<Option<FetchedModels>>>,
 }
 
 impl ChatService {
-    pub fn new(config: &Config) -> Self {
+    /// Fails on a redaction pattern, profile override or post-processor name
+    /// `Config::load` would have rejected, as a hand-built config may carry
+    /// one.
+    pub fn new(config: &Config) -> Result<Self> {
+        let redactor = Redactor::from_config(config)?;
+        let profiles = ModelProfiles::with_overrides(config.model_profiles())?;
+        let post_processors = Pipeline::from_config(config)?;
+        Ok(Self::with_providers(Providers::new(config))
+            .with_system_prompt(config.system_prompt())
+            .with_redactor(redactor)
+            .with_profiles(profiles)
+            .with_defaults(RequestDefaults::from_config(config))
+            .with_resume_attempts(config.stream_resume_attempts())
+            .with_post_processors(post_processors)
+            .with_breakers(CircuitBreakers::new(config.circuit_breaker().clone())))
+    }
+
+    /// Sends every request to xAI through `client`.
+    pub fn with_client(client: XaiClient) -> Self {
+        Self::with_providers(Providers::single(client))
+    }
+
+    /// Sends each request to the provider its model names.
+    pub fn with_providers(providers: Providers) -> Self {
         Self {
-            client: XaiClient::new(config),
-            default_system_prompt: "You are Grok, a helpful and maximally truthful AI built by xAI, not based on any other companies and their models.".to_string(),
+            providers,
+            default_system_prompt: DEFAULT_SYSTEM_PROMPT.to_string(),
+            redactor: Some(Arc::new(Redactor::default())),
+            profiles: Arc::default(),
+            defaults: RequestDefaults::default(),
+            resume_attempts: 0,
+            post_processors: Pipeline::default(),
+            breakers: CircuitBreakers::default(),
+            models: Arc::default(),
+        }
+    }
+
+    /// `None` sends prompts unredacted.
+    pub fn with_redactor(mut self, redactor: Option<Redactor>) -> Self {
+        self.redactor = redactor.map(Arc::new);
+        self
+    }
+
+    pub fn with_system_prompt(mut self, system_prompt: impl Into<String>) -> Self {
+        self.default_system_prompt = system_prompt.into();
+        self
+    }
+
+    pub fn with_profiles(mut self, profiles: ModelProfiles) -> Self {
+        self.profiles = Arc::new(profiles);
+        self
+    }
+
+    /// The `temperature` and `max_tokens` of requests that leave them unset
+    /// and whose model's profile has no default; without, the API's apply.
+    pub fn with_defaults(mut self, defaults: RequestDefaults) -> Self {
+        self.defaults = defaults;
+        self
+    }
+
+    /// Follow-up requests allowed to finish a streamed reply that was cut
+    /// off; each one sends the prompt again, so the default is none.
+    pub fn with_resume_attempts(mut self, attempts: u32) -> Self {
+        self.resume_attempts = attempts;
+        self
+    }
+
+    /// Replaces the reply processors built from the config.
+    pub fn with_post_processors(mut self, pipeline: Pipeline) -> Self {
+        self.post_processors = pipeline;
+        self
+    }
+
+    /// Runs `processor` on replies after the configured ones.
+    pub fn with_post_processor(mut self, processor: impl PostProcessor + 'static) -> Self {
+        self.post_processors.push(processor);
+        self
+    }
+
+    pub fn with_breakers(mut self, breakers: CircuitBreakers) -> Self {
+        self.breakers = breakers;
+        self
+    }
+
+    /// Per-model breakers, with the latency and success rate behind them.
+    pub fn breakers(&self) -> &CircuitBreakers {
+        &self.breakers
+    }
+
+    pub fn profiles(&self) -> &ModelProfiles {
+        &self.profiles
+    }
+
+    /// The xAI client.
+    pub fn client(&self) -> &XaiClient {
+        self.providers.default_client()
+    }
+
+    pub fn providers(&self) -> &Providers {
+        &self.providers
+    }
+
+    /// Masks secrets in the messages and system prompt, returning how many
+    /// were found. `send_request` does this itself; call it first to report
+    /// the count.
+    pub fn redact(&self, request: &mut ApiChatRequest) -> usize {
+        let Some(redactor) = &self.redactor else {
+            return 0;
+        };
+        let prompt = request.system_prompt.iter_mut();
+        let contents = request
+            .messages
+            .iter_mut()
+            .map(|message| &mut message.content);
+        contents
+            .chain(prompt)
+            .map(|text| redactor.redact(text))
+            .sum()
+    }
+
+    /// Fits the request to its model's profile, filling in defaults,
+    /// dropping parameters the model rejects and clamping `max_tokens`.
+    /// `send_request` does this itself; call it first to show the warnings.
+    pub fn fit_to_model(&self, request: &mut ApiChatRequest) -> Vec<ParamWarning> {
+        self.profiles.fit(request, self.defaults)
+    }
+
+    /// `text` as it should be saved locally: redacted only when
+    /// `redact_stored` is configured.
+    pub fn stored_text(&self, mut text: String) -> String {
+        if let Some(redactor) = self.redactor.as_ref().filter(|r| r.redacts_stored()) {
+            redactor.redact(&mut text);
         }
+        text
     }
 
+    /// Sends `messages` to `model`. A `system_prompt` replaces any system
+    /// message leading `messages`; without one, the history's own system
+    /// message is kept, and the service's default prompt is used only when
+    /// there is none.
     pub async fn send_message(
         &self,
         messages: Vec<ApiMessage>,
         model: String,
+        system_prompt: Option<String>,
         max_tokens: Option<i32>,
         temperature: Option<f32>,
         stream: bool,
@@ -232,26 +802,302 @@ impl ChatService {
             max_tokens,
             temperature,
             stream: Some(stream),
-            system_prompt: Some(self.default_system_prompt.clone()),
+            system_prompt,
+            ..Default::default()
+        };
+
+        self.send_request(request, None).await
+    }
+
+    /// Sends a fully specified request, falling back to the service's default
+    /// system prompt when the request doesn't carry one. Replies go through
+    /// the post-processors: complete ones before they are returned, streamed
+    /// ones in a final chunk. A model whose breaker is open gets a
+    /// [`CircuitOpen`](super::CircuitOpen) error, or is swapped for the
+    /// configured fallback model. The request goes to the provider its model
+    /// names; see [`Providers`].
+    pub async fn send_request(
+        &self,
+        mut request: ApiChatRequest,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<ChatResponse> {
+        self.admit(&mut request)?;
+        let client = self.providers.route(&request.model)?;
+        self.prepare(&mut request);
+        let ctx = ResponseContext {
+            model: request.model.clone(),
+            streamed: request.stream.unwrap_or(false),
         };
 
-        if stream {
-            let content_stream = self.client.chat_completion_stream(request).await?;
+        if ctx.streamed {
+            let resend = (self.resume_attempts > 0).then(|| request.clone());
+            let started = Instant::now();
+            let result = client.chat_completion_stream(request, cancel).await;
+            let latency = started.elapsed();
+            if result.is_err() {
+                self.breakers
+                    .record(&ctx.model, Outcome::of(&result), latency);
+            }
+            let mut content_stream = result?;
+            if let Some(request) = resend {
+                content_stream = Resume {
+                    client: client.clone(),
+                    request,
+                    cancel: cancel.cloned(),
+                    attempts: self.resume_attempts,
+                    resumes: 0,
+                    partial: String::new(),
+                    stream: Some(content_stream),
+                }
+                .stream();
+            }
+            // Only how the stream ends, after any resumes, tells the breaker
+            // whether the model delivered
+            content_stream = reported(
+                content_stream,
+                self.breakers.clone(),
+                ctx.model.clone(),
+                latency,
+            );
+            if !self.post_processors.is_empty() {
+                content_stream = post_processed(content_stream, self.post_processors.clone(), ctx);
+            }
             Ok(ChatResponse::Stream(content_stream))
         } else {
-            let response = self.client.chat_completion(request).await?;
+            let started = Instant::now();
+            let result = client.chat_completion(request, cancel).await;
+            let latency = started.elapsed();
+            self.breakers
+                .record(&ctx.model, Outcome::of(&result), latency);
+            let mut response = result?;
+            response.latency_ms = Some(latency.as_millis() as u64);
+            self.post_process(&mut response, &ctx);
             Ok(ChatResponse::Complete(response))
         }
     }
 
+    /// What `send_request` would post for `request`, without sending it or
+    /// touching the circuit breakers. The API key travels in a header, so it
+    /// is never part of the result.
+    pub fn dry_run(&self, mut request: ApiChatRequest) -> DryRun {
+        let (redactions, warnings) = self.prepare(&mut request);
+        // Streamed requests go to the streaming call, which always streams
+        let stream = request.stream.unwrap_or(false);
+        // An unknown provider fails when sent; until then it's shown as xAI's
+        let client = self
+            .providers
+            .route(&request.model)
+            .unwrap_or_else(|_| self.providers.default_client());
+        let body = client.request_body(request, stream);
+        let messages: Vec<ApiMessage> =
+            serde_json::from_value(body["messages"].clone()).unwrap_or_default();
+        DryRun {
+            estimated_tokens: estimate_message_tokens(&messages),
+            body,
+            redactions,
+            warnings,
+            dropped: Vec::new(),
+        }
+    }
+
+    /// The most `request` could cost: its prompt tokens as it would be sent,
+    /// plus a reply of `max_tokens`.
+    pub fn estimate_cost(&self, request: &ApiChatRequest, pricing: &PricingTable) -> CostEstimate {
+        let prompt_tokens = self.dry_run(request.clone()).estimated_tokens;
+        let mut fitted = request.clone();
+        self.fit_to_model(&mut fitted);
+        CostEstimate::new(pricing, &request.model, prompt_tokens, fitted.max_tokens)
+    }
+
+    /// Everything done to a request before it is sent: the default system
+    /// prompt, redaction and fitting it to the model.
+    fn prepare(&self, request: &mut ApiChatRequest) -> (usize, Vec<ParamWarning>) {
+        // A history that already carries its own system message keeps it as-is
+        let has_system_message = request.messages.iter().any(ApiMessage::is_system);
+        if request.system_prompt.is_none() && !has_system_message {
+            request.system_prompt = Some(self.default_system_prompt.clone());
+        }
+        let redactions = self.redact(request);
+        let warnings = self.fit_to_model(request);
+        (redactions, warnings)
+    }
+
+    fn admit(&self, request: &mut ApiChatRequest) -> Result<()> {
+        let Err(open) = self.breakers.admit(&request.model) else {
+            return Ok(());
+        };
+        match &self.breakers.settings().fallback_model {
+            Some(fallback)
+                if *fallback != request.model && self.breakers.admit(fallback).is_ok() =>
+            {
+                request.model = fallback.clone();
+                Ok(())
+            }
+            _ => Err(open.into()),
+        }
+    }
+
+    fn post_process(&self, response: &mut ApiChatResponse, ctx: &ResponseContext) {
+        if self.post_processors.is_empty() {
+            return;
+        }
+        let Some(message) = response
+            .choices
+            .first_mut()
+            .and_then(|choice| choice.message.as_mut())
+        else {
+            return;
+        };
+        let processed = self.post_processors.process(&message.content, ctx);
+        message.content = processed.content;
+        response.effects = processed.effects;
+    }
+
+    /// Fetches the models every provider offers, xAI's first, remembering
+    /// them for [`cached_models`](Self::cached_models). Only xAI failing is
+    /// an error; other providers that fail are left out.
     pub async fn list_available_models(&self) -> Result<Vec<String>> {
-        self.client.list_models().await
+        let listed = futures_util::future::join_all(
+            self.providers
+                .clients()
+                .map(|client| async move { (client.provider(), client.list_models().await) }),
+        )
+        .await;
+        let mut models = Vec::new();
+        for (provider, result) in listed {
+            match result {
+                Ok(listed) => models.extend(listed),
+                Err(e) if provider == DEFAULT_PROVIDER => return Err(e),
+                Err(_) => {}
+            }
+        }
+        *self.models.lock().unwrap() = Some((Instant::now(), models.clone()));
+        Ok(models)
+    }
+
+    /// The models the API offers, fetched again once the last list is more
+    /// than [`MODELS_TTL`] old.
+    pub async fn cached_models(&self) -> Result<Vec<String>> {
+        if let Some((fetched, models)) = &*self.models.lock().unwrap() {
+            if fetched.elapsed() < MODELS_TTL {
+                return Ok(models.clone());
+            }
+        }
+        self.list_available_models().await
+    }
+
+    /// When `error` says the requested model doesn't exist: that model, and
+    /// the closest one the API still offers. An unreachable models list just
+    /// leaves out the suggestion.
+    pub async fn retired_model(&self, error: &anyhow::Error) -> Option<RetiredModel> {
+        let Some(XaiError::ModelNotFound { model }) = XaiError::from_anyhow(error) else {
+            return None;
+        };
+        let suggestion = self
+            .cached_models()
+            .await
+            .ok()
+            .and_then(|models| closest_model(model, &models).map(str::to_string));
+        Some(RetiredModel {
+            model: model.clone(),
+            suggestion,
+        })
+    }
+
+    /// Like [`send_request`](Self::send_request), but a request to a retired
+    /// model is sent again to its replacement when `config` migrates models
+    /// automatically, returning the migration made. Otherwise the error
+    /// carries a [`RetiredModel`] with the closest model still offered.
+    pub async fn send_request_migrating(
+        &self,
+        request: ApiChatRequest,
+        cancel: Option<&CancellationToken>,
+        config: &Config,
+    ) -> Result<(ChatResponse, Option<ModelMigration>)> {
+        let mut resend = request.clone();
+        let error = match self.send_request(request, cancel).await {
+            Ok(response) => return Ok((response, None)),
+            Err(error) => error,
+        };
+        let Some(retired) = self.retired_model(&error).await else {
+            return Err(error);
+        };
+        let Some(replacement) = config.model_replacement(&retired) else {
+            return Err(error.context(retired));
+        };
+        resend.model = replacement.clone();
+        let response = self.send_request(resend, cancel).await?;
+        Ok((
+            response,
+            Some(ModelMigration {
+                from: retired.model,
+                to: replacement,
+            }),
+        ))
+    }
+
+    /// Like [`send_request_migrating`](Self::send_request_migrating), but
+    /// when `shed` allows it a request rejected as longer than the model's
+    /// context window is sent again with its oldest history dropped (see
+    /// [`shed_oldest`]), up to [`SHED_ATTEMPTS`] times. The first `pinned`
+    /// messages after the system messages are kept. Also returns how many
+    /// messages were dropped.
+    pub async fn send_request_shedding(
+        &self,
+        mut request: ApiChatRequest,
+        cancel: Option<&CancellationToken>,
+        config: &Config,
+        pinned: usize,
+        shed: bool,
+    ) -> Result<(ChatResponse, Option<ModelMigration>, usize)> {
+        let mut dropped = 0;
+        let mut attempts = 0;
+        loop {
+            let error = match self
+                .send_request_migrating(request.clone(), cancel, config)
+                .await
+            {
+                Ok((response, migration)) => return Ok((response, migration, dropped)),
+                Err(error) => error,
+            };
+            if !shed || !is_context_length_exceeded(&error) || attempts == SHED_ATTEMPTS {
+                return Err(error);
+            }
+            match shed_oldest(&mut request.messages, pinned) {
+                0 => return Err(error),
+                removed => dropped += removed,
+            }
+            attempts += 1;
+        }
     }
 }
 
 pub enum ChatResponse {
     Complete(ApiChatResponse),
-    Stream(impl Stream<Item = Result<String>>),
+    Stream(ContentStream),
+}
+
+/// A request that was assembled but not sent; see [`ChatService::dry_run`].
+#[derive(Debug, Clone, Serialize)]
+pub struct DryRun {
+    /// The JSON body exactly as it would be posted to `/chat/completions`.
+    pub body: Value,
+    /// Prompt tokens of the messages in `body`, estimated as elsewhere.
+    pub estimated_tokens: usize,
+    /// Secrets masked in the request.
+    pub redactions: usize,
+    /// Parameters changed to suit the model.
+    pub warnings: Vec<ParamWarning>,
+    /// History the context budget left out, oldest first. Filled in by
+    /// whoever assembled the history.
+    pub dropped: Vec<ApiMessage>,
+}
+
+impl DryRun {
+    pub fn with_dropped(mut self, dropped: Vec<ApiMessage>) -> Self {
+        self.dropped = dropped;
+        self
+    }
 }
 
 impl ApiChatResponse {
@@ -272,11 +1118,36 @@ impl ApiChatResponse {
     pub fn get_usage(&self) -> Option<&UsageStats> {
         self.usage.as_ref()
     }
+
+    pub fn get_finish_reason(&self) -> Option<String> {
+        self.choices.first()?.finish_reason.clone()
+    }
+
+    /// What the usage ledger records about this response.
+    pub fn completion_stats(&self) -> CompletionStats {
+        CompletionStats {
+            latency_ms: self.latency_ms,
+            finish_reason: self.get_finish_reason(),
+        }
+    }
+
+    /// Token logprobs of the first choice, present only when the request set `logprobs`.
+    pub fn get_logprobs(&self) -> Option<&[TokenLogprob]> {
+        self.choices
+            .first()?
+            .logprobs
+            .as_ref()?
+            .content
+            .as_deref()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::capabilities::ProfileOverride;
+    use crate::client::mock::{MockReply, MockUpstream};
+    use crate::client::{BreakerSettings, BreakerState, CircuitOpen, UnknownProvider};
     use crate::config::Config;
 
     #[tokio::test]
@@ -284,13 +1155,122 @@ mod tests {
         let config = Config {
             xai_api_key: "test-key".to_string(),
             database_url: "sqlite:test.db".to_string(),
-            server_host: "127.0.0.1".to_string(),
-            server_port: 3000,
-            default_model: "grok-4-0709".to_string(),
+            ..Config::default()
         };
 
         let client = XaiClient::new(&config);
         assert_eq!(client.api_key, "test-key");
+        assert_eq!(client.base_url, "https://api.x.ai/v1");
+    }
+
+    fn user_message(content: &str) -> Vec<ApiMessage> {
+        vec![ApiMessage {
+            role: "user".to_string(),
+            content: content.to_string(),
+        }]
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_matches_what_is_sent() {
+        let upstream = MockUpstream::echo("pong").await;
+        let service = ChatService::new(&upstream.config()).unwrap();
+        let request = ApiChatRequest::builder()
+            .messages(user_message("my key is xai-AbCdEfGhIjKlMnOpQrStUvWxYz0123"))
+            .model("grok-4-0709")
+            .max_tokens(100)
+            .temperature(0.3)
+            .stream(false)
+            .build();
+
+        let dry_run = service.dry_run(request.clone());
+        service.send_request(request, None).await.unwrap();
+
+        assert_eq!(upstream.requests(), std::slice::from_ref(&dry_run.body));
+        assert_eq!(dry_run.redactions, 1);
+        // grok-4 takes no temperature
+        assert_eq!(dry_run.warnings[0].parameter, "temperature");
+        assert_eq!(dry_run.body["messages"][0]["role"], "system");
+        assert!(dry_run.estimated_tokens > 0);
+    }
+
+    #[tokio::test]
+    async fn test_repeated_requests_reuse_connection() {
+        let upstream = MockUpstream::echo("pong").await;
+        let service = ChatService::new(&upstream.config()).unwrap();
+
+        for _ in 0..3 {
+            let response = service
+                .send_message(user_message("ping"), "grok-3".to_string(), None, None, None, false)
+                .await
+                .unwrap();
+            match response {
+                ChatResponse::Complete(response) => {
+                    assert_eq!(response.get_content().unwrap(), "pong")
+                }
+                ChatResponse::Stream(_) => panic!("expected a complete response"),
+            }
+        }
+
+        // A cloned service shares the same pool as well.
+        let cloned = service.clone();
+        cloned
+            .send_message(user_message("ping"), "grok-3".to_string(), None, None, None, false)
+            .await
+            .unwrap();
+
+        assert_eq!(upstream.requests().len(), 4);
+        assert_eq!(upstream.connection_count(), 1);
+    }
+
+    #[test]
+    fn test_request_body_without_logprobs_is_unchanged() {
+        let request = ApiChatRequest {
+            messages: user_message("Hi"),
+            model: "grok-3".to_string(),
+            max_tokens: Some(16),
+            temperature: Some(0.5),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            XaiClient::new(&Config::default())
+                .request_body(request, false)
+                .to_string(),
+            r#"{"max_tokens":16,"messages":[{"content":"Hi","role":"user"}],"model":"grok-3","stream":false,"temperature":0.5}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_logprobs_round_trip() {
+        let upstream = MockUpstream::start(|_| {
+            let mut body = crate::client::mock::completion_body("Hi");
+            body["choices"][0]["logprobs"] = serde_json::json!({
+                "content": [{"token": "Hi", "logprob": -0.1, "top_logprobs": []}]
+            });
+            MockReply::Json(200, body)
+        })
+        .await;
+        let service = ChatService::new(&upstream.config()).unwrap();
+
+        let request = ApiChatRequest {
+            messages: user_message("Hello"),
+            model: "grok-3".to_string(),
+            logprobs: true,
+            top_logprobs: 2,
+            ..Default::default()
+        };
+        let response = match service.send_request(request, None).await.unwrap() {
+            ChatResponse::Complete(response) => response,
+            ChatResponse::Stream(_) => panic!("expected a complete response"),
+        };
+
+        let sent = &upstream.requests()[0];
+        assert_eq!(sent["logprobs"], true);
+        assert_eq!(sent["top_logprobs"], 2);
+
+        let logprobs = response.get_logprobs().unwrap();
+        assert_eq!(logprobs[0].token, "Hi");
+        assert_eq!(logprobs[0].logprob, -0.1);
     }
 
     #[test]
@@ -325,6 +1305,7 @@ mod tests {
             temperature: Some(0.7),
             stream: Some(false),
             system_prompt: Some("Custom prompt".to_string()),
+            ..Default::default()
         };
 
         let json = serde_json::to_string(&request).unwrap();
@@ -332,4 +1313,792 @@ mod tests {
         assert!(json.contains("Hello"));
         assert!(json.contains("Custom prompt"));
     }
+
+    #[tokio::test]
+    async fn test_history_system_message_is_not_duplicated() {
+        let upstream = MockUpstream::echo("ok").await;
+        let service = ChatService::new(&upstream.config())
+            .unwrap()
+            .with_system_prompt("default");
+
+        let mut messages = user_message("hi");
+        messages.insert(
+            0,
+            ApiMessage {
+                role: "system".to_string(),
+                content: "custom".to_string(),
+            },
+        );
+        let request = ApiChatRequest {
+            messages,
+            model: "grok-3".to_string(),
+            ..Default::default()
+        };
+        let explicit = ApiChatRequest {
+            messages: request.messages.clone(),
+            model: "grok-3".to_string(),
+            system_prompt: Some("explicit".to_string()),
+            ..Default::default()
+        };
+        service.send_request(request, None).await.unwrap();
+        service
+            .send_message(
+                user_message("hi"),
+                "grok-3".to_string(),
+                None,
+                None,
+                None,
+                false,
+            )
+            .await
+            .unwrap();
+        service.send_request(explicit, None).await.unwrap();
+
+        let requests = upstream.requests();
+        assert_eq!(requests[0]["messages"][0]["content"], "custom");
+        assert_eq!(requests[0]["messages"].as_array().unwrap().len(), 2);
+        assert_eq!(requests[1]["messages"][0]["content"], "default");
+        assert_eq!(requests[2]["messages"][0]["content"], "explicit");
+        assert_eq!(requests[2]["messages"].as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_fitted_to_the_model_profile() {
+        let upstream = MockUpstream::echo("ok").await;
+        let mut config = upstream.config();
+        config.model_profiles.insert(
+            "grok-3".to_string(),
+            ProfileOverride {
+                max_output_tokens: Some(100),
+                ..Default::default()
+            },
+        );
+        let service = ChatService::new(&config).unwrap();
+
+        for model in ["grok-4-0709", "grok-3"] {
+            service
+                .send_message(
+                    user_message("hi"),
+                    model.to_string(),
+                    None,
+                    Some(4096),
+                    Some(0.5),
+                    false,
+                )
+                .await
+                .unwrap();
+        }
+        let requests = upstream.requests();
+        assert!(requests[0].get("temperature").is_none());
+        assert_eq!(requests[0]["max_tokens"], 4096);
+        assert_eq!(requests[1]["temperature"], 0.5);
+        assert_eq!(requests[1]["max_tokens"], 100);
+    }
+
+    #[tokio::test]
+    async fn test_model_defaults_apply_before_the_configured_ones() {
+        let upstream = MockUpstream::echo("ok").await;
+        let mut config = upstream.config();
+        config.temperature = 0.75;
+        config.max_tokens = 2048;
+        config.model_profiles.insert(
+            "grok-3".to_string(),
+            ProfileOverride {
+                default_temperature: Some(0.25),
+                default_max_tokens: Some(300),
+                ..Default::default()
+            },
+        );
+        let service = ChatService::new(&config).unwrap();
+
+        for (model, temperature) in [("grok-3", None), ("grok-3", Some(1.5)), ("grok-2", None)] {
+            let request = ApiChatRequest {
+                messages: user_message("hi"),
+                model: model.to_string(),
+                temperature,
+                ..Default::default()
+            };
+            service.send_request(request, None).await.unwrap();
+        }
+        let requests = upstream.requests();
+        assert_eq!(requests[0]["temperature"], 0.25);
+        assert_eq!(requests[0]["max_tokens"], 300);
+        assert_eq!(requests[1]["temperature"], 1.5);
+        assert_eq!(requests[1]["max_tokens"], 300);
+        assert_eq!(requests[2]["temperature"], 0.75);
+        assert_eq!(requests[2]["max_tokens"], 2048);
+    }
+
+    #[tokio::test]
+    async fn test_outgoing_secrets_are_redacted() {
+        let upstream = MockUpstream::echo("ok").await;
+        let mut config = upstream.config();
+        let service = ChatService::new(&config).unwrap();
+
+        let mut request = ApiChatRequest {
+            messages: user_message("my key is xai-AbCdEfGhIjKlMnOpQrStUvWx, mail me@example.com"),
+            model: "grok-3".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(service.redact(&mut request), 2);
+        service.send_request(request, None).await.unwrap();
+        assert_eq!(
+            upstream.requests()[0]["messages"][1]["content"],
+            "my key is [REDACTED:api_key], mail [REDACTED:email]"
+        );
+
+        // Local history keeps the original unless redact_stored is set
+        let prompt = "ping me@example.com".to_string();
+        assert_eq!(service.stored_text(prompt.clone()), prompt);
+        config.redact_stored = true;
+        let service = ChatService::new(&config).unwrap();
+        assert_eq!(service.stored_text(prompt), "ping [REDACTED:email]");
+
+        config.redact = false;
+        let service = ChatService::new(&config).unwrap();
+        service
+            .send_message(
+                user_message("me@example.com"),
+                "grok-3".to_string(),
+                None,
+                None,
+                None,
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            upstream.requests()[1]["messages"][1]["content"],
+            "me@example.com"
+        );
+
+        // A bad pattern is an error, not the built-in rules in its place
+        config.redact = true;
+        config.redact_patterns = [("ticket".to_string(), "SEC-[".to_string())].into();
+        let error = ChatService::new(&config).err().unwrap();
+        assert!(error.to_string().contains("ticket"));
+    }
+
+    #[test]
+    fn test_unknown_post_processors_are_an_error() {
+        let config = Config {
+            post_processors: vec!["shout".to_string()],
+            ..Config::default()
+        };
+        let error = ChatService::new(&config).err().unwrap();
+        assert!(error.to_string().contains("Unknown post-processor 'shout'"));
+    }
+
+    #[tokio::test]
+    async fn test_send_message_system_prompt_combinations() {
+        let upstream = MockUpstream::echo("ok").await;
+        let service = ChatService::new(&upstream.config())
+            .unwrap()
+            .with_system_prompt("default");
+        let with_history_prompt = || {
+            let mut messages = user_message("hi");
+            messages.insert(0, ApiMessage::system("from history"));
+            messages
+        };
+
+        let cases = [
+            (user_message("hi"), Some("caller"), "caller"),
+            (user_message("hi"), None, "default"),
+            (with_history_prompt(), Some("caller"), "caller"),
+            (with_history_prompt(), None, "from history"),
+        ];
+        for (messages, system_prompt, _) in &cases {
+            service
+                .send_message(
+                    messages.clone(),
+                    "grok-3".to_string(),
+                    system_prompt.map(str::to_string),
+                    None,
+                    None,
+                    false,
+                )
+                .await
+                .unwrap();
+        }
+
+        let requests = upstream.requests();
+        for (request, (_, _, expected)) in requests.iter().zip(&cases) {
+            assert_eq!(
+                request["messages"],
+                json!([
+                    {"role": "system", "content": expected},
+                    {"role": "user", "content": "hi"},
+                ])
+            );
+        }
+    }
+
+    fn stream_request() -> ApiChatRequest {
+        ApiChatRequest {
+            messages: user_message("Count slowly"),
+            model: "grok-3".to_string(),
+            stream: Some(true),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_yields_deltas_then_usage() {
+        let upstream =
+            MockUpstream::start(|_| MockReply::stream(&["Hel", "lo"], Duration::ZERO)).await;
+        let client = XaiClient::new(&upstream.config());
+
+        let chunks: Vec<StreamChunk> = client
+            .chat_completion_stream(stream_request(), None)
+            .await
+            .unwrap()
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+
+        let content: String = chunks.iter().map(|chunk| chunk.content.as_str()).collect();
+        assert_eq!(content, "Hello");
+        let last = chunks.last().unwrap();
+        assert_eq!(last.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(last.usage.as_ref().unwrap().total_tokens, 2);
+        assert_eq!(
+            upstream.requests()[0]["stream_options"],
+            json!({"include_usage": true})
+        );
+    }
+
+    async fn collect_stream(service: &ChatService) -> (String, Vec<Result<StreamChunk>>) {
+        let Ok(ChatResponse::Stream(stream)) = service.send_request(stream_request(), None).await
+        else {
+            panic!("expected a stream");
+        };
+        let items: Vec<Result<StreamChunk>> = stream.collect().await;
+        let content = items
+            .iter()
+            .filter_map(|item| item.as_ref().ok())
+            .map(|chunk| chunk.content.as_str())
+            .collect();
+        (content, items)
+    }
+
+    #[tokio::test]
+    async fn test_cut_off_stream_is_resumed_with_the_partial_reply() {
+        let upstream = MockUpstream::start(|body| {
+            let messages = body["messages"].as_array().unwrap();
+            if messages.last().unwrap()["content"] == RESUME_PROMPT {
+                MockReply::stream(&["lo world"], Duration::ZERO)
+            } else {
+                MockReply::cut_off(&["Hel"])
+            }
+        })
+        .await;
+        let service = ChatService::new(&upstream.config())
+            .unwrap()
+            .with_resume_attempts(2);
+
+        let (content, items) = collect_stream(&service).await;
+
+        assert_eq!(content, "Hello world");
+        let chunks: Vec<StreamChunk> = items.into_iter().map(Result::unwrap).collect();
+        assert_eq!(chunks[0].resumes, None);
+        assert!(chunks[1..].iter().all(|chunk| chunk.resumes == Some(1)));
+        assert_eq!(
+            chunks.last().unwrap().usage.as_ref().unwrap().total_tokens,
+            2
+        );
+
+        let requests = upstream.requests();
+        assert_eq!(requests.len(), 2);
+        let resent = requests[1]["messages"].as_array().unwrap();
+        assert_eq!(resent[resent.len() - 3]["content"], "Count slowly");
+        assert_eq!(
+            resent[resent.len() - 2],
+            json!({"role": "assistant", "content": "Hel"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resuming_stops_after_the_configured_attempts() {
+        let upstream = MockUpstream::start(|_| MockReply::cut_off(&["a"])).await;
+        let service = ChatService::new(&upstream.config())
+            .unwrap()
+            .with_resume_attempts(2);
+
+        let (content, items) = collect_stream(&service).await;
+
+        assert_eq!(content, "aaa");
+        assert!(items.last().unwrap().is_err());
+        assert_eq!(upstream.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_cut_off_stream_is_not_resumed_by_default() {
+        let upstream = MockUpstream::start(|_| MockReply::cut_off(&["Hel"])).await;
+        let service = ChatService::new(&upstream.config()).unwrap();
+
+        let (content, items) = collect_stream(&service).await;
+
+        assert_eq!(content, "Hel");
+        assert!(items.last().unwrap().is_err());
+        assert_eq!(upstream.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stream_cut_off_after_resumes_counts_against_the_model() {
+        let upstream = MockUpstream::start(|_| MockReply::cut_off(&["a", "b"])).await;
+        let service = ChatService::new(&upstream.config())
+            .unwrap()
+            .with_resume_attempts(1);
+
+        let (content, items) = collect_stream(&service).await;
+
+        assert_eq!(content, "abab");
+        assert!(items.last().unwrap().is_err());
+        let health = service.breakers().health_of("grok-3").unwrap();
+        assert_eq!(health.requests, 1);
+        assert_eq!(health.success_rate, Some(0.0));
+
+        let upstream =
+            MockUpstream::start(|_| MockReply::stream(&["a", "b"], Duration::ZERO)).await;
+        let service = ChatService::new(&upstream.config()).unwrap();
+        collect_stream(&service).await;
+        let health = service.breakers().health_of("grok-3").unwrap();
+        assert_eq!(health.success_rate, Some(1.0));
+    }
+
+    #[test]
+    fn test_sse_buffer_reassembles_split_events() {
+        let mut buffer = SseBuffer::default();
+        assert!(buffer.push(b"data: {\"choices\":[{\"del").is_empty());
+        let events = buffer.push(b"ta\":{\"content\":\"hi\"}}]}\r\n\r\ndata: [DONE]\n\n");
+        assert_eq!(events.len(), 2);
+        assert_eq!(parse_stream_event(&events[0]).unwrap().content, "hi");
+        assert!(parse_stream_event(&events[1]).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_mid_stream_terminates_promptly() {
+        let upstream = MockUpstream::start(|_| {
+            MockReply::stream(&["one", "two", "three", "four"], Duration::from_secs(2))
+        })
+        .await;
+        let client = XaiClient::new(&upstream.config());
+        let token = CancellationToken::new();
+
+        let mut stream = client
+            .chat_completion_stream(stream_request(), Some(&token))
+            .await
+            .unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap().content, "one");
+
+        let started = Instant::now();
+        token.cancel();
+        let error = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(XaiError::from_anyhow(&error), Some(&XaiError::Cancelled));
+        assert!(stream.next().await.is_none());
+        assert!(started.elapsed() < Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_pending_completion() {
+        let upstream = MockUpstream::start(|_| {
+            MockReply::Delayed(Duration::from_secs(10), Box::new(MockReply::completion("late")))
+        })
+        .await;
+        let client = XaiClient::new(&upstream.config());
+        let token = CancellationToken::new();
+
+        let canceller = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            canceller.cancel();
+        });
+
+        let started = Instant::now();
+        let error = client
+            .chat_completion(stream_request(), Some(&token))
+            .await
+            .unwrap_err();
+        assert_eq!(XaiError::from_anyhow(&error), Some(&XaiError::Cancelled));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_is_enforced_by_client() {
+        let upstream = MockUpstream::start(|_| {
+            MockReply::Delayed(Duration::from_secs(10), Box::new(MockReply::completion("late")))
+        })
+        .await;
+        let config = Config {
+            request_timeout_secs: 1,
+            ..upstream.config()
+        };
+        let client = XaiClient::new(&config);
+
+        let error = client
+            .chat_completion(stream_request(), None)
+            .await
+            .unwrap_err();
+        assert_eq!(XaiError::from_anyhow(&error), Some(&XaiError::Timeout));
+    }
+
+    #[tokio::test]
+    async fn test_api_error_is_typed() {
+        let upstream =
+            MockUpstream::start(|_| MockReply::Json(429, serde_json::json!({"error": "slow down"})))
+                .await;
+        let client = XaiClient::new(&upstream.config());
+
+        let error = client
+            .chat_completion(stream_request(), None)
+            .await
+            .unwrap_err();
+        match XaiError::from_anyhow(&error) {
+            Some(XaiError::Api { status, body }) => {
+                assert_eq!(*status, 429);
+                assert!(body.contains("slow down"));
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validate_api_key_separates_rejection_from_outage() {
+        let upstream = MockUpstream::start(|_| {
+            MockReply::Json(401, serde_json::json!({"error": "invalid key"}))
+        })
+        .await;
+        let client = XaiClient::new(&upstream.config());
+        assert!(!client.validate_api_key().await.unwrap());
+
+        let upstream =
+            MockUpstream::start(|_| MockReply::Json(503, serde_json::json!({}))).await;
+        let client = XaiClient::new(&upstream.config());
+        assert!(client.validate_api_key().await.is_err());
+
+        let upstream = MockUpstream::start(|_| {
+            MockReply::Json(200, serde_json::json!({"data": [{"id": "grok-3"}]}))
+        })
+        .await;
+        let client = XaiClient::new(&upstream.config());
+        assert!(client.validate_api_key().await.unwrap());
+    }
+    #[tokio::test]
+    async fn test_open_breaker_fails_fast_or_falls_back() {
+        let upstream = MockUpstream::start(|body| match body["model"].as_str() {
+            Some("grok-3") => MockReply::Json(503, serde_json::json!({"error": "overloaded"})),
+            _ => MockReply::completion("from the backup"),
+        })
+        .await;
+        let settings = BreakerSettings {
+            failures: 2,
+            ..BreakerSettings::default()
+        };
+        let send = |service: ChatService| async move {
+            let messages = Conversation::new().user("hi").into_messages();
+            service
+                .send_message(messages, "grok-3".to_string(), None, None, None, false)
+                .await
+        };
+
+        let service = ChatService::with_client(XaiClient::new(&upstream.config()))
+            .with_breakers(CircuitBreakers::new(settings.clone()));
+        for _ in 0..2 {
+            assert!(send(service.clone()).await.is_err());
+        }
+        let error = send(service.clone()).await.err().unwrap();
+        assert_eq!(error.downcast_ref::<CircuitOpen>().unwrap().model, "grok-3");
+        assert_eq!(upstream.requests().len(), 2);
+        let health = service.breakers().health_of("grok-3").unwrap();
+        assert_eq!(health.state, BreakerState::Open);
+        assert_eq!(health.success_rate, Some(0.0));
+
+        let service = ChatService::with_client(XaiClient::new(&upstream.config())).with_breakers(
+            CircuitBreakers::new(BreakerSettings {
+                fallback_model: Some("grok-3-mini".to_string()),
+                ..settings
+            }),
+        );
+        for _ in 0..2 {
+            assert!(send(service.clone()).await.is_err());
+        }
+        let Ok(ChatResponse::Complete(response)) = send(service.clone()).await else {
+            panic!("expected the fallback model's reply");
+        };
+        assert_eq!(response.get_content().unwrap(), "from the backup");
+        assert_eq!(upstream.requests().last().unwrap()["model"], "grok-3-mini");
+    }
+
+    #[tokio::test]
+    async fn test_retired_model_is_suggested_or_migrated() {
+        let upstream = MockUpstream::start(|body| match body["model"].as_str() {
+            // Listing models is a bodiless GET
+            None => MockReply::Json(
+                200,
+                serde_json::json!({"data": [{"id": "grok-3"}, {"id": "grok-2-latest"}]}),
+            ),
+            Some("grok-2-1212") => MockReply::Json(
+                404,
+                serde_json::json!({"error": "The model grok-2-1212 does not exist"}),
+            ),
+            Some(model) => MockReply::completion(model),
+        })
+        .await;
+        let service = ChatService::with_client(XaiClient::new(&upstream.config()));
+        let request = || ApiChatRequest {
+            messages: user_message("hi"),
+            model: "grok-2-1212".to_string(),
+            stream: Some(false),
+            ..Default::default()
+        };
+
+        let error = service
+            .send_request_migrating(request(), None, &upstream.config())
+            .await
+            .err()
+            .unwrap();
+        let retired = error.downcast_ref::<RetiredModel>().unwrap();
+        assert_eq!(retired.suggestion.as_deref(), Some("grok-2-latest"));
+        assert!(matches!(
+            XaiError::from_anyhow(&error),
+            Some(XaiError::ModelNotFound { .. })
+        ));
+
+        let config = Config {
+            auto_migrate_models: true,
+            model_migrations: [("grok-2-1212".to_string(), "grok-3".to_string())].into(),
+            ..upstream.config()
+        };
+        let (response, migration) = service
+            .send_request_migrating(request(), None, &config)
+            .await
+            .unwrap();
+        let ChatResponse::Complete(response) = response else {
+            panic!("expected a complete reply");
+        };
+        assert_eq!(response.get_content().unwrap(), "grok-3");
+        assert_eq!(
+            migration,
+            Some(ModelMigration {
+                from: "grok-2-1212".to_string(),
+                to: "grok-3".to_string(),
+            })
+        );
+        // The models were listed once and cached
+        let listings = upstream.requests().iter().filter(|r| r.is_null()).count();
+        assert_eq!(listings, 1);
+    }
+
+    #[tokio::test]
+    async fn test_overlong_requests_are_resent_with_less_history() {
+        let upstream = MockUpstream::start(|body| {
+            match body["messages"].as_array().map_or(0, Vec::len) {
+                0..=10 => MockReply::completion("fits"),
+                _ => MockReply::Json(
+                    400,
+                    serde_json::json!({"error": "This model's maximum context length is 131072 tokens"}),
+                ),
+            }
+        })
+        .await;
+        let service = ChatService::with_client(XaiClient::new(&upstream.config()));
+        let mut messages = vec![ApiMessage::user("Use British spelling")];
+        for turn in 0..5 {
+            messages.push(ApiMessage::user(format!("question {}", turn)));
+            messages.push(ApiMessage::assistant(format!("answer {}", turn)));
+        }
+        messages.push(ApiMessage::user("latest"));
+        let request = ApiChatRequest {
+            messages,
+            model: "grok-3".to_string(),
+            stream: Some(false),
+            ..Default::default()
+        };
+
+        let error = service
+            .send_request_shedding(request.clone(), None, &upstream.config(), 1, false)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(
+            XaiError::from_anyhow(&error),
+            Some(XaiError::ContextLengthExceeded { status: 400, .. })
+        ));
+
+        let (response, _, dropped) = service
+            .send_request_shedding(request, None, &upstream.config(), 1, true)
+            .await
+            .unwrap();
+        let ChatResponse::Complete(response) = response else {
+            panic!("expected a complete reply");
+        };
+        assert_eq!(response.get_content().unwrap(), "fits");
+        assert_eq!(dropped, 4);
+
+        let requests = upstream.requests();
+        assert_eq!(requests.len(), 4);
+        let contents = |request: &Value| -> Vec<String> {
+            request["messages"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|m| m["content"].as_str().unwrap().to_string())
+                .collect()
+        };
+        let (first, last) = (contents(&requests[1]), contents(&requests[3]));
+        assert_eq!(first.len(), 13);
+        assert_eq!(last.len(), 9);
+        // The system prompt, the pinned message and the newest turns stay
+        assert_eq!(last[1..3], ["Use British spelling", "question 2"]);
+        assert_eq!(last[2..], first[first.len() - 7..]);
+    }
+
+    /// A config sending `<name>/...` models to each of `providers`, and
+    /// bare ones to `xai`.
+    fn with_providers(xai: &MockUpstream, providers: &[(&str, ProviderSettings)]) -> Config {
+        Config {
+            providers: providers
+                .iter()
+                .map(|(name, settings)| (name.to_string(), settings.clone()))
+                .collect(),
+            ..xai.config()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_go_to_the_provider_their_model_names() {
+        let xai = MockUpstream::echo("from xai").await;
+        let local = MockUpstream::echo("from local").await;
+        let config = with_providers(&xai, &[("local", local.provider())]);
+        let service = ChatService::new(&config).unwrap();
+        let send = |model: &str| {
+            service.send_message(
+                user_message("hi"),
+                model.to_string(),
+                None,
+                None,
+                None,
+                false,
+            )
+        };
+
+        let Ok(ChatResponse::Complete(response)) = send("local/llama3").await else {
+            panic!("expected a complete reply");
+        };
+        assert_eq!(response.get_content().unwrap(), "from local");
+        // The provider knows the model without its prefix
+        assert_eq!(local.requests()[0]["model"], "llama3");
+        let Ok(ChatResponse::Complete(response)) = send("grok-3").await else {
+            panic!("expected a complete reply");
+        };
+        assert_eq!(response.get_content().unwrap(), "from xai");
+        assert_eq!(xai.requests()[0]["model"], "grok-3");
+        send("xai/grok-3").await.unwrap();
+        assert_eq!(xai.requests()[1]["model"], "grok-3");
+
+        let error = send("openai/gpt-4o").await.err().unwrap();
+        assert!(error.is::<UnknownProvider>());
+        assert_eq!(xai.requests().len() + local.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_provider_quirks_are_normalized() {
+        let xai = MockUpstream::echo("unused").await;
+        // A llama.cpp-like server: no streaming, no usage, no logprobs
+        let local = MockUpstream::start(|body| {
+            if body["messages"].as_array().map_or(0, Vec::len) > 3 {
+                return MockReply::Json(
+                    400,
+                    json!({"error": {"code": 400, "message": "the request exceeds the available context size", "type": "exceed_context_size_error"}}),
+                );
+            }
+            let mut reply = crate::client::mock::completion_body("Hello there");
+            reply.as_object_mut().unwrap().remove("usage");
+            MockReply::Json(200, reply)
+        })
+        .await;
+        let settings = ProviderSettings {
+            streaming: false,
+            usage: false,
+            logprobs: false,
+            ..local.provider()
+        };
+        let service = ChatService::new(&with_providers(&xai, &[("local", settings)])).unwrap();
+        let request = ApiChatRequest {
+            model: "local/llama3".to_string(),
+            logprobs: true,
+            top_logprobs: 2,
+            ..stream_request()
+        };
+
+        // The stream is answered whole, with usage estimated
+        let Ok(ChatResponse::Stream(stream)) = service.send_request(request.clone(), None).await
+        else {
+            panic!("expected a stream");
+        };
+        let chunks: Vec<StreamChunk> = stream.map(|chunk| chunk.unwrap()).collect().await;
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].content, "Hello there");
+        assert_eq!(chunks[0].finish_reason.as_deref(), Some("stop"));
+        let usage = chunks[0].usage.as_ref().unwrap();
+        assert_eq!(usage.completion_tokens, 3);
+        assert!(usage.prompt_tokens > 0);
+        let sent = &local.requests()[0];
+        assert_eq!(sent["stream"], false);
+        assert!(sent.get("stream_options").is_none());
+        assert!(sent.get("logprobs").is_none());
+
+        // Errors are classified, then cut down to their message
+        let mut overlong = request;
+        overlong.stream = Some(false);
+        overlong.messages = (0..4)
+            .map(|turn| ApiMessage::user(turn.to_string()))
+            .collect();
+        let error = service.send_request(overlong, None).await.err().unwrap();
+        assert!(is_context_length_exceeded(&error));
+        let message = error.to_string();
+        assert!(
+            message.ends_with(": the request exceeds the available context size"),
+            "{}",
+            message
+        );
+    }
+
+    #[tokio::test]
+    async fn test_models_are_listed_across_providers() {
+        let xai = MockUpstream::start(|_| {
+            MockReply::Json(
+                200,
+                json!({"data": [{"id": "grok-3"}, {"id": "grok-2-vision"}]}),
+            )
+        })
+        .await;
+        let local =
+            MockUpstream::start(|_| MockReply::Json(200, json!({"data": [{"id": "llama3"}]})))
+                .await;
+        let down = MockUpstream::start(|_| MockReply::Json(503, json!({"error": "loading"}))).await;
+        let config = with_providers(
+            &xai,
+            &[
+                ("local", local.provider()),
+                (
+                    "backup",
+                    ProviderSettings {
+                        models: vec!["qwen".to_string()],
+                        ..down.provider()
+                    },
+                ),
+                ("spare", down.provider()),
+            ],
+        );
+
+        let models = ChatService::new(&config)
+            .unwrap()
+            .list_available_models()
+            .await
+            .unwrap();
+        // Only xAI's list is filtered; a provider that can't list its models
+        // offers the configured ones, or none
+        assert_eq!(models, ["grok-3", "backup/qwen", "local/llama3"]);
+    }
 }
@@ -0,0 +1,196 @@
+use std::fmt;
+
+/// Typed failures from the xAI client. They travel inside `anyhow::Error`, so
+/// callers that care can `downcast_ref::<XaiError>()`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum XaiError {
+    /// The caller's cancellation token fired before the request finished.
+    Cancelled,
+    /// No response within the configured request timeout.
+    Timeout,
+    /// The API answered with a non-success status.
+    Api { status: u16, body: String },
+    /// The API doesn't know the requested model, usually because xAI
+    /// retired it.
+    ModelNotFound { model: String },
+    /// The prompt is longer than the model's context window, which the
+    /// token estimate can undercount.
+    ContextLengthExceeded { status: u16, body: String },
+    /// The API answered with a body that doesn't parse: where parsing
+    /// failed, and the start of the body with secrets masked.
+    InvalidResponse { error: String, excerpt: String },
+}
+
+impl fmt::Display for XaiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XaiError::Cancelled => write!(f, "Request cancelled"),
+            XaiError::Timeout => write!(f, "Request timed out"),
+            XaiError::Api { status, body } => {
+                write!(f, "API request failed with status {}: {}", status, body)
+            }
+            XaiError::ModelNotFound { model } => {
+                write!(
+                    f,
+                    "Model '{}' is not available; it may have been retired",
+                    model
+                )
+            }
+            XaiError::ContextLengthExceeded { status, body } => {
+                write!(
+                    f,
+                    "The conversation is longer than the model's context window \
+                     (status {}): {}",
+                    status, body
+                )
+            }
+            XaiError::InvalidResponse { error, excerpt } => {
+                write!(
+                    f,
+                    "Unexpected response from the API ({}); body: {}",
+                    error, excerpt
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for XaiError {}
+
+impl XaiError {
+    /// Finds an `XaiError` anywhere in an anyhow error chain.
+    pub fn from_anyhow(error: &anyhow::Error) -> Option<&XaiError> {
+        error.chain().find_map(|cause| cause.downcast_ref::<XaiError>())
+    }
+
+    /// An `Api` error answering a request for `model` that says the model
+    /// doesn't exist becomes `ModelNotFound`; anything else is kept.
+    pub fn for_model(self, model: &str) -> Self {
+        match &self {
+            XaiError::Api { status, body } if says_model_not_found(*status, body) => {
+                XaiError::ModelNotFound {
+                    model: model.to_string(),
+                }
+            }
+            _ => self,
+        }
+    }
+
+    /// An `Api` error saying the prompt doesn't fit the model's context
+    /// window becomes `ContextLengthExceeded`; anything else is kept.
+    pub fn for_context_length(self) -> Self {
+        match self {
+            XaiError::Api { status, body }
+                if status == 413 || (status == 400 && mentions_context_length(&body)) =>
+            {
+                XaiError::ContextLengthExceeded { status, body }
+            }
+            _ => self,
+        }
+    }
+}
+
+/// Whether an error body is about the prompt being too long.
+pub fn mentions_context_length(body: &str) -> bool {
+    let body = body.to_lowercase();
+    [
+        "context length",
+        "context_length",
+        "context size",
+        "prompt length",
+        "too many tokens",
+    ]
+    .iter()
+    .any(|phrase| body.contains(phrase))
+}
+
+/// xAI answers an unknown model with 404, or with 400 and a message such as
+/// "The model grok-2 does not exist or your team does not have access to it".
+fn says_model_not_found(status: u16, body: &str) -> bool {
+    let body = body.to_lowercase();
+    let about_model = body.contains("model");
+    match status {
+        404 => about_model || body.is_empty(),
+        400 => about_model && (body.contains("does not exist") || body.contains("not found")),
+        _ => false,
+    }
+}
+
+/// True when the API could not be reached at all (no route, refused
+/// connection, failed DNS lookup), so nothing was sent and retrying later
+/// can succeed. Answers from the API, however unwelcome, are not offline.
+pub fn is_offline(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(reqwest::Error::is_connect)
+    })
+}
+
+/// True when the API rejected the conversation as longer than the model's
+/// context window.
+pub fn is_context_length_exceeded(error: &anyhow::Error) -> bool {
+    matches!(
+        XaiError::from_anyhow(error),
+        Some(XaiError::ContextLengthExceeded { .. })
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_model_answers_become_model_not_found() {
+        let api = |status, body: &str| XaiError::Api {
+            status,
+            body: body.to_string(),
+        };
+        let not_found = XaiError::ModelNotFound {
+            model: "grok-2".to_string(),
+        };
+
+        let retired = r#"{"error":"The model grok-2 does not exist or your team does not have access to it."}"#;
+        assert_eq!(api(404, retired).for_model("grok-2"), not_found);
+        assert_eq!(api(400, retired).for_model("grok-2"), not_found);
+        assert_eq!(api(404, "").for_model("grok-2"), not_found);
+
+        // Other failures, even about models, stay as they were
+        let invalid = r#"{"error":"Invalid value for temperature for model grok-2"}"#;
+        assert_eq!(api(400, invalid).for_model("grok-2"), api(400, invalid));
+        assert_eq!(
+            api(404, "no route").for_model("grok-2"),
+            api(404, "no route")
+        );
+        assert_eq!(api(500, retired).for_model("grok-2"), api(500, retired));
+    }
+
+    #[test]
+    fn test_overlong_prompts_become_context_length_exceeded() {
+        let api = |status, body: &str| XaiError::Api {
+            status,
+            body: body.to_string(),
+        };
+        let too_long = r#"{"error":"This model's maximum prompt length is 131072 but the request contains 140000 tokens."}"#;
+        assert_eq!(
+            api(400, too_long).for_context_length(),
+            XaiError::ContextLengthExceeded {
+                status: 400,
+                body: too_long.to_string()
+            }
+        );
+        assert!(matches!(
+            api(413, "").for_context_length(),
+            XaiError::ContextLengthExceeded { status: 413, .. }
+        ));
+        // llama.cpp's wording
+        let llama = r#"{"error":{"code":400,"message":"the request exceeds the available context size","type":"exceed_context_size_error"}}"#;
+        assert!(matches!(
+            api(400, llama).for_context_length(),
+            XaiError::ContextLengthExceeded { status: 400, .. }
+        ));
+        let invalid = r#"{"error":"Invalid value for temperature"}"#;
+        assert_eq!(api(400, invalid).for_context_length(), api(400, invalid));
+        assert_eq!(api(500, too_long).for_context_length(), api(500, too_long));
+    }
+}
@@ -0,0 +1,229 @@
+// Minimal in-process stand-in for the xAI HTTP API used by tests. It speaks just
+// enough HTTP/1.1 (keep-alive, Content-Length bodies) to exercise the real client.
+
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::client::ProviderSettings;
+use crate::config::Config;
+
+type Responder = dyn Fn(&Value) -> MockReply + Send + Sync;
+
+pub enum MockReply {
+    Json(u16, Value),
+    /// Server-sent events, one content delta per chunk, `interval` apart, then a
+    /// final event with `finish_reason` and usage.
+    Stream {
+        chunks: Vec<String>,
+        interval: Duration,
+    },
+    /// Server-sent events for `chunks`, after which the connection drops
+    /// before the body is complete.
+    CutOff(Vec<String>),
+    Delayed(Duration, Box<MockReply>),
+}
+
+impl MockReply {
+    pub fn completion(content: &str) -> Self {
+        MockReply::Json(200, completion_body(content))
+    }
+
+    pub fn stream(chunks: &[&str], interval: Duration) -> Self {
+        MockReply::Stream {
+            chunks: chunks.iter().map(|chunk| chunk.to_string()).collect(),
+            interval,
+        }
+    }
+
+    pub fn cut_off(chunks: &[&str]) -> Self {
+        MockReply::CutOff(chunks.iter().map(|chunk| chunk.to_string()).collect())
+    }
+}
+
+pub fn completion_body(content: &str) -> Value {
+    json!({
+        "id": "mock-completion",
+        "object": "chat.completion",
+        "created": 0,
+        "model": "mock-model",
+        "choices": [{
+            "index": 0,
+            "message": {"role": "assistant", "content": content},
+            "finish_reason": "stop"
+        }],
+        "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2}
+    })
+}
+
+pub struct MockUpstream {
+    addr: std::net::SocketAddr,
+    connections: Arc<AtomicUsize>,
+    requests: Arc<Mutex<Vec<Value>>>,
+}
+
+impl MockUpstream {
+    pub async fn start<F>(responder: F) -> Self
+    where
+        F: Fn(&Value) -> MockReply + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let responder: Arc<Responder> = Arc::new(responder);
+
+        let conn_counter = connections.clone();
+        let request_log = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                conn_counter.fetch_add(1, Ordering::SeqCst);
+                let responder = responder.clone();
+                let request_log = request_log.clone();
+                tokio::spawn(serve_connection(socket, responder, request_log));
+            }
+        });
+
+        Self {
+            addr,
+            connections,
+            requests,
+        }
+    }
+
+    /// Replies to every chat completion with the same assistant content.
+    pub async fn echo(content: &'static str) -> Self {
+        Self::start(move |_| MockReply::completion(content)).await
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}/v1", self.addr)
+    }
+
+    pub fn config(&self) -> Config {
+        Config {
+            xai_api_key: "test-key".to_string(),
+            xai_base_url: self.base_url(),
+            ..Config::default()
+        }
+    }
+
+    /// Settings for a provider other than xAI served by this upstream.
+    pub fn provider(&self) -> ProviderSettings {
+        ProviderSettings::new(self.base_url())
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// JSON bodies of every request received so far, in arrival order.
+    pub fn requests(&self) -> Vec<Value> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn serve_connection(
+    socket: TcpStream,
+    responder: Arc<Responder>,
+    request_log: Arc<Mutex<Vec<Value>>>,
+) {
+    let mut reader = BufReader::new(socket);
+
+    loop {
+        let mut content_length = 0usize;
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).await.unwrap_or(0) == 0 {
+            return;
+        }
+
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header).await.unwrap_or(0) == 0 {
+                return;
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+
+        let mut body = vec![0u8; content_length];
+        if reader.read_exact(&mut body).await.is_err() {
+            return;
+        }
+        let body: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+        request_log.lock().unwrap().push(body.clone());
+
+        if !write_reply(reader.get_mut(), responder(&body)).await {
+            return;
+        }
+    }
+}
+
+/// Returns whether the connection can be reused for another request.
+async fn write_reply(socket: &mut TcpStream, reply: MockReply) -> bool {
+    let mut reply = reply;
+    while let MockReply::Delayed(delay, inner) = reply {
+        tokio::time::sleep(delay).await;
+        reply = *inner;
+    }
+
+    match reply {
+        MockReply::Json(status, payload) => {
+            let payload = payload.to_string();
+            let response = format!(
+                "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: keep-alive\r\n\r\n{}",
+                status,
+                payload.len(),
+                payload
+            );
+            socket.write_all(response.as_bytes()).await.is_ok()
+        }
+        MockReply::Stream { chunks, interval } => {
+            let headers = "HTTP/1.1 200 Mock\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n";
+            if socket.write_all(headers.as_bytes()).await.is_err() {
+                return false;
+            }
+            for chunk in chunks {
+                let event = json!({"choices": [{"index": 0, "delta": {"content": chunk}}]});
+                let frame = format!("data: {}\n\n", event);
+                if socket.write_all(frame.as_bytes()).await.is_err() {
+                    return false;
+                }
+                let _ = socket.flush().await;
+                tokio::time::sleep(interval).await;
+            }
+            let done = json!({
+                "choices": [{"index": 0, "delta": {}, "finish_reason": "stop"}],
+                "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2}
+            });
+            let _ = socket
+                .write_all(format!("data: {}\n\ndata: [DONE]\n\n", done).as_bytes())
+                .await;
+            // Streams are delimited by closing the connection.
+            false
+        }
+        MockReply::CutOff(chunks) => {
+            // Promises more body than is sent, so the client sees an error
+            let headers = "HTTP/1.1 200 Mock\r\nContent-Type: text/event-stream\r\nContent-Length: 1000000\r\n\r\n";
+            let mut body = headers.to_string();
+            for chunk in chunks {
+                let event = json!({"choices": [{"index": 0, "delta": {"content": chunk}}]});
+                body.push_str(&format!("data: {}\n\n", event));
+            }
+            let _ = socket.write_all(body.as_bytes()).await;
+            let _ = socket.flush().await;
+            false
+        }
+        MockReply::Delayed(..) => unreachable!(),
+    }
+}
@@ -1,2 +1,14 @@
+pub use self::breaker::*;
 pub use self::client::*;
+pub use self::error::*;
+pub use self::provider::*;
+pub use self::retired::*;
+mod breaker;
+#[allow(clippy::module_inception)]
 mod client;
+mod error;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+mod provider;
+mod retired;
+mod transport;
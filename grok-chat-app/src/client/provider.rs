@@ -0,0 +1,287 @@
+//! Providers: the OpenAI-compatible APIs requests can go to, such as xAI,
+//! OpenAI or a local llama.cpp or vLLM server. Model ids name their provider
+//! as `<provider>/<model>`; bare ids go to xAI, configured as before with
+//! `api_key` and `base_url`, so nothing changes until a provider is added.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::XaiClient;
+use crate::config::Config;
+
+/// The provider of model ids that don't name one.
+pub const DEFAULT_PROVIDER: &str = "xai";
+
+/// A `[default.providers.<name>]` section of `config.toml`. Capabilities
+/// default to supported.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProviderSettings {
+    /// Up to and including the API version, such as `https://api.openai.com/v1`.
+    pub base_url: String,
+    /// The environment variable holding the API key. Without one, or when it
+    /// isn't set, requests carry no key, as local servers expect.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_env: Option<String>,
+    /// Models offered in the chat UI's model picker, without the prefix.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub models: Vec<String>,
+    #[serde(default = "supported")]
+    pub streaming: bool,
+    #[serde(default = "supported")]
+    pub usage: bool,
+    #[serde(default = "supported")]
+    pub logprobs: bool,
+}
+
+fn supported() -> bool {
+    true
+}
+
+impl ProviderSettings {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into(),
+            api_key_env: None,
+            models: Vec::new(),
+            streaming: true,
+            usage: true,
+            logprobs: true,
+        }
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            streaming: self.streaming,
+            usage: self.usage,
+            logprobs: self.logprobs,
+        }
+    }
+
+    /// Checks the section called `name` when the config loads.
+    pub fn validate(&self, name: &str) -> Result<()> {
+        if name == DEFAULT_PROVIDER {
+            bail!(
+                "providers.{}: xAI is configured with api_key and base_url",
+                name
+            );
+        }
+        if name.is_empty() || name.contains('/') || name.contains(char::is_whitespace) {
+            bail!(
+                "providers.{}: provider names can't be empty or hold '/' or spaces",
+                name
+            );
+        }
+        if !self.base_url.starts_with("http://") && !self.base_url.starts_with("https://") {
+            bail!(
+                "providers.{}.base_url: expected an http:// or https:// URL, got '{}'",
+                name,
+                self.base_url
+            );
+        }
+        Ok(())
+    }
+}
+
+/// What a provider's API supports beyond plain chat completions. Requests
+/// are adjusted to fit, so callers needn't know which provider they reach.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Streamed replies. Without them a streamed request is answered whole,
+    /// as a single chunk.
+    pub streaming: bool,
+    /// Token usage in replies, asked for in streams with `stream_options`.
+    /// Without it usage is estimated from the text.
+    pub usage: bool,
+    /// Token logprobs. Without them requests for logprobs are sent without.
+    pub logprobs: bool,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            streaming: true,
+            usage: true,
+            logprobs: true,
+        }
+    }
+}
+
+/// The provider a model id names, and the model as that provider knows it:
+/// `local/llama3` is `("local", "llama3")` and `grok-3` is `("xai", "grok-3")`.
+pub fn split_model(model: &str) -> (&str, &str) {
+    model.split_once('/').unwrap_or((DEFAULT_PROVIDER, model))
+}
+
+/// A model id naming a provider that isn't configured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownProvider {
+    pub model: String,
+    pub provider: String,
+    /// Names of the configured providers, sorted.
+    pub available: Vec<String>,
+}
+
+impl fmt::Display for UnknownProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Model '{}' names unknown provider '{}' (available: {}); add it under \
+             [default.providers.{}] in config.toml",
+            self.model,
+            self.provider,
+            self.available.join(", "),
+            self.provider
+        )
+    }
+}
+
+impl std::error::Error for UnknownProvider {}
+
+/// A client for each provider, by name. xAI is always there.
+#[derive(Clone)]
+pub struct Providers {
+    clients: BTreeMap<String, XaiClient>,
+}
+
+impl Providers {
+    pub fn new(config: &Config) -> Self {
+        let mut providers = Self::single(XaiClient::new(config));
+        for (name, settings) in config.providers() {
+            providers = providers.with(XaiClient::for_provider(config, name, settings));
+        }
+        providers
+    }
+
+    /// Only xAI, through `client`.
+    pub fn single(client: XaiClient) -> Self {
+        Self {
+            clients: BTreeMap::new(),
+        }
+        .with(client)
+    }
+
+    /// Adds `client`, replacing any client of the same provider.
+    pub fn with(mut self, client: XaiClient) -> Self {
+        self.clients.insert(client.provider().to_string(), client);
+        self
+    }
+
+    /// The client for the provider `model` names; an `UnknownProvider`
+    /// error when it names one that isn't configured.
+    pub fn route(&self, model: &str) -> Result<&XaiClient> {
+        let (provider, _) = split_model(model);
+        match self.clients.get(provider) {
+            Some(client) => Ok(client),
+            None => Err(UnknownProvider {
+                model: model.to_string(),
+                provider: provider.to_string(),
+                available: self.clients.keys().cloned().collect(),
+            }
+            .into()),
+        }
+    }
+
+    /// The xAI client.
+    pub fn default_client(&self) -> &XaiClient {
+        &self.clients[DEFAULT_PROVIDER]
+    }
+
+    /// Every client, xAI first and then the others by name.
+    pub fn clients(&self) -> impl Iterator<Item = &XaiClient> {
+        let default = self.default_client();
+        std::iter::once(default).chain(
+            self.clients
+                .values()
+                .filter(|client| client.provider() != DEFAULT_PROVIDER),
+        )
+    }
+}
+
+/// The message of an error body in any of the envelopes OpenAI-compatible
+/// servers use: `{"error": {"message": ..}}` (OpenAI, llama.cpp),
+/// `{"error": ".."}` (xAI, Ollama) or `{"message": ..}` (vLLM).
+pub fn envelope_message(body: &str) -> Option<String> {
+    let value: Value = serde_json::from_str(body).ok()?;
+    let message = match &value["error"] {
+        Value::String(message) => message,
+        error => error["message"]
+            .as_str()
+            .or_else(|| value["message"].as_str())?,
+    };
+    Some(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_ids_name_their_provider() {
+        assert_eq!(split_model("grok-3"), ("xai", "grok-3"));
+        assert_eq!(split_model("local/llama3"), ("local", "llama3"));
+        // Only the first slash separates the provider
+        assert_eq!(
+            split_model("router/meta/llama-3.1"),
+            ("router", "meta/llama-3.1")
+        );
+
+        let config = Config {
+            providers: BTreeMap::from([(
+                "local".to_string(),
+                ProviderSettings::new("http://localhost:8080/v1"),
+            )]),
+            ..Config::default()
+        };
+        let providers = Providers::new(&config);
+        assert_eq!(providers.route("grok-3").unwrap().provider(), "xai");
+        assert_eq!(providers.route("xai/grok-3").unwrap().provider(), "xai");
+        assert_eq!(providers.route("local/llama3").unwrap().provider(), "local");
+        let Err(error) = providers.route("openai/gpt-4o") else {
+            panic!("expected an unknown provider");
+        };
+        let unknown = error.downcast_ref::<UnknownProvider>().unwrap();
+        assert_eq!(unknown.available, ["local", "xai"]);
+    }
+
+    #[test]
+    fn test_provider_sections_are_checked() {
+        let settings = ProviderSettings::new("http://localhost:8080/v1");
+        assert!(settings.validate("local").is_ok());
+        assert!(settings.validate("xai").is_err());
+        assert!(settings.validate("my/local").is_err());
+        let error = ProviderSettings::new("localhost:8080")
+            .validate("local")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("providers.local.base_url"), "{}", error);
+    }
+
+    #[test]
+    fn test_error_envelopes_yield_their_message() {
+        let openai = r#"{"error": {"message": "Incorrect API key provided", "type": "invalid_request_error", "code": "invalid_api_key"}}"#;
+        let llama = r#"{"error": {"code": 400, "message": "the request exceeds the available context size", "type": "exceed_context_size_error"}}"#;
+        let ollama = r#"{"error": "model \"llama3\" not found, try pulling it first"}"#;
+        let vllm = r#"{"object": "error", "message": "The model `x` does not exist.", "type": "NotFoundError", "code": 404}"#;
+        assert_eq!(
+            envelope_message(openai).as_deref(),
+            Some("Incorrect API key provided")
+        );
+        assert_eq!(
+            envelope_message(llama).as_deref(),
+            Some("the request exceeds the available context size")
+        );
+        assert_eq!(
+            envelope_message(ollama).as_deref(),
+            Some("model \"llama3\" not found, try pulling it first")
+        );
+        assert_eq!(
+            envelope_message(vllm).as_deref(),
+            Some("The model `x` does not exist.")
+        );
+        assert_eq!(envelope_message("Bad Gateway"), None);
+    }
+}
@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A model the API no longer knows, and the closest one it still offers.
+/// Travels as context on the `ModelNotFound` error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetiredModel {
+    pub model: String,
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for RetiredModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Model '{}' is not available; it may have been retired",
+            self.model
+        )?;
+        match &self.suggestion {
+            Some(suggestion) => write!(f, " (try '{}')", suggestion),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A switch from a retired model to its replacement.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelMigration {
+    pub from: String,
+    pub to: String,
+}
+
+impl fmt::Display for ModelMigration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {}", self.from, self.to)
+    }
+}
+
+/// The model in `available` sharing the longest prefix with `retired`, so
+/// `grok-2-1212` leads to `grok-2-latest` before `grok-3`. Among equally
+/// close ones the shortest wins, then the newest by name. Nothing sharing
+/// even a first character counts as close.
+pub fn closest_model<'a>(retired: &str, available: &'a [String]) -> Option<&'a str> {
+    available
+        .iter()
+        .filter(|model| *model != retired)
+        .map(|model| (common_prefix(retired, model), model))
+        .filter(|(shared, _)| *shared > 0)
+        .max_by(|(a, a_model), (b, b_model)| {
+            a.cmp(b)
+                .then(b_model.len().cmp(&a_model.len()))
+                .then(a_model.cmp(b_model))
+        })
+        .map(|(_, model)| model.as_str())
+}
+
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.chars().zip(b.chars()).take_while(|(a, b)| a == b).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_closest_model_prefers_the_longest_shared_prefix() {
+        let available: Vec<String> = ["grok-3", "grok-3-mini", "grok-4-0709", "grok-2-latest"]
+            .map(str::to_string)
+            .into();
+
+        assert_eq!(
+            closest_model("grok-2-1212", &available),
+            Some("grok-2-latest")
+        );
+        assert_eq!(closest_model("grok-4", &available), Some("grok-4-0709"));
+        // "grok-3" and "grok-4-0709" share as much with "grok-beta"; shorter wins
+        assert_eq!(closest_model("grok-beta", &available[..3]), Some("grok-3"));
+        assert_eq!(closest_model("grok-3", &available[..1]), None);
+        assert_eq!(closest_model("llama", &available), None);
+    }
+}
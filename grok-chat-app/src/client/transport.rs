@@ -0,0 +1,125 @@
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+
+use super::XaiError;
+use crate::redact::Redactor;
+
+/// Characters of an unparseable body kept in an `InvalidResponse` error.
+const EXCERPT_CHARS: usize = 500;
+
+/// Passes a successful `response` through; any other status becomes an
+/// `XaiError::Api` carrying the body.
+pub(super) async fn error_for_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    Err(XaiError::Api {
+        status: status.as_u16(),
+        body,
+    }
+    .into())
+}
+
+/// Like `error_for_status`, for a request to `model`: an answer that the
+/// model doesn't exist is a `ModelNotFound` error, and one that the prompt
+/// is too long for it `ContextLengthExceeded`.
+pub(super) async fn error_for_model(
+    response: reqwest::Response,
+    model: &str,
+) -> Result<reqwest::Response> {
+    error_for_status(response)
+        .await
+        .map_err(|e| match e.downcast::<XaiError>() {
+            Ok(error) => error.for_model(model).for_context_length().into(),
+            Err(e) => e,
+        })
+}
+
+/// Reads the whole body of `response` and parses it as `T`.
+pub(super) async fn read_json<T: DeserializeOwned>(response: reqwest::Response) -> Result<T> {
+    let body = response.bytes().await?;
+    Ok(parse_json(&body)?)
+}
+
+/// Parses `body` as `T`. A body that doesn't fit is an `InvalidResponse`
+/// error naming the field it failed at, such as `choices[0].message`, with a
+/// redacted excerpt of the body.
+pub(super) fn parse_json<T: DeserializeOwned>(body: &[u8]) -> Result<T, XaiError> {
+    let deserializer = &mut serde_json::Deserializer::from_slice(body);
+    serde_path_to_error::deserialize(deserializer).map_err(|e| XaiError::InvalidResponse {
+        error: e.to_string(),
+        excerpt: excerpt(body),
+    })
+}
+
+/// The start of `body` as text, with secrets masked.
+fn excerpt(body: &[u8]) -> String {
+    let mut text = String::from_utf8_lossy(body).into_owned();
+    // Masked first, so a secret straddling the cut is still caught
+    Redactor::default().redact(&mut text);
+    if let Some((cut, _)) = text.char_indices().nth(EXCERPT_CHARS) {
+        text.truncate(cut);
+        text.push('…');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ApiChatResponse;
+
+    fn parse(fixture: &str) -> ApiChatResponse {
+        parse_json(fixture.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_captured_responses_parse() {
+        let response = parse(include_str!("fixtures/completion.json"));
+        assert_eq!(response.get_content().unwrap(), "Lisbon.");
+        assert_eq!(response.get_usage().unwrap().total_tokens, 40);
+        assert_eq!(response.get_finish_reason().as_deref(), Some("stop"));
+
+        let response = parse(include_str!("fixtures/completion_without_usage.json"));
+        assert_eq!(response.get_content().unwrap(), "Hi there!");
+        assert!(response.get_usage().is_none());
+
+        // A reply holding only a refusal or tool calls has no content
+        let response = parse(include_str!("fixtures/completion_null_content.json"));
+        assert_eq!(response.get_content().unwrap(), "");
+        assert_eq!(response.get_finish_reason().as_deref(), Some("tool_calls"));
+
+        let response = parse(include_str!("fixtures/completion_extra_fields.json"));
+        assert_eq!(response.get_content().unwrap(), "42");
+        assert_eq!(response.get_usage().unwrap().completion_tokens, 180);
+    }
+
+    #[test]
+    fn test_unexpected_shape_names_the_field_and_shows_the_body() {
+        let body = r#"{"id": "x", "choices": [{"message": {"role": "assistant", "content": 7}}], "api_key": "xai-AbCdEfGhIjKlMnOpQrStUvWxYz0123"}"#;
+        let Err(XaiError::InvalidResponse { error, excerpt }) =
+            parse_json::<ApiChatResponse>(body.as_bytes())
+        else {
+            panic!("expected an invalid response");
+        };
+        assert!(
+            error.starts_with("choices[0].message.content: "),
+            "{}",
+            error
+        );
+        assert!(excerpt.contains(r#""content": 7"#));
+        assert!(!excerpt.contains("xai-AbCdEf"));
+
+        let long = format!("<html>{}</html>", "x".repeat(2000));
+        let Err(XaiError::InvalidResponse { excerpt, .. }) =
+            parse_json::<ApiChatResponse>(long.as_bytes())
+        else {
+            panic!("expected an invalid response");
+        };
+        assert_eq!(excerpt.chars().count(), EXCERPT_CHARS + 1);
+        assert!(excerpt.ends_with('…'));
+    }
+}
@@ -1,46 +1,391 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::{
+    config_file_path, selected_profile, ConfigFile, ProfileSettings, Resolver, SecretSource,
+};
+use crate::agents::{Agent, AgentSettings, UnknownAgent};
+use crate::auth::{keyring_enabled, stored_api_key, ApiKeySource, DEFAULT_PROFILE};
+use crate::capabilities::{ModelProfiles, ProfileOverride};
+use crate::client::{BreakerSettings, ProviderSettings, RetiredModel};
+use crate::context::ContextRetry;
+use crate::i18n::Locale;
+use crate::postprocess::Pipeline;
+use crate::pricing::GuardMode;
+use crate::redact::{Redactor, NO_REDACT_VAR};
+
+pub const DEFAULT_XAI_BASE_URL: &str = "https://api.x.ai/v1";
+
+pub const DEFAULT_MODEL: &str = "grok-4-0709";
+
+pub const DEFAULT_SYSTEM_PROMPT: &str = "You are Grok, a helpful and maximally truthful AI built by xAI, not based on any other companies and their models.";
+
+pub const DEFAULT_MAX_TOKENS: i32 = 2048;
+
+pub const DEFAULT_TEMPERATURE: f32 = 0.7;
+
+/// `XAI_API_KEY` is not set. Travels inside `anyhow::Error` so the CLI can map
+/// it to its own exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissingApiKey;
+
+impl fmt::Display for MissingApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "XAI_API_KEY environment variable (or api_key in config.toml, or a key \
+             stored with `auth login`) is required"
+        )
+    }
+}
+
+impl std::error::Error for MissingApiKey {}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub xai_api_key: String,
+    pub xai_base_url: String,
     pub database_url: String,
     pub server_host: String,
     pub server_port: u16,
+    /// Reject every request to the HTTP server that would change something.
+    pub server_read_only: bool,
     pub default_model: String,
+    pub system_prompt: String,
+    pub max_tokens: i32,
+    pub temperature: f32,
+    /// Short names such as `fast` for full model names.
+    pub model_aliases: BTreeMap<String, String>,
+    /// Retry requests to a retired model with its replacement, and move
+    /// the session over to it.
+    pub auto_migrate_models: bool,
+    /// Replacements for retired models, by retired model. Others are
+    /// replaced with the closest model the API offers.
+    pub model_migrations: BTreeMap<String, String>,
+    pub request_timeout_secs: u64,
+    /// Follow-up requests allowed to finish a cut-off streamed reply; 0
+    /// leaves it cut off.
+    pub stream_resume_attempts: u32,
+    /// Longest a streamed session send may run, from sending to the last
+    /// chunk, before the server gives up on it.
+    pub stream_max_secs: u64,
+    /// Earlier replies kept when a reply is regenerated, per position.
+    pub kept_generations: u32,
+    /// Projected cost, in USD, above which requests are held back.
+    pub cost_threshold_usd: f64,
+    pub cost_guard: GuardMode,
+    /// Whether requests rejected as too long for the model are sent again
+    /// with less history.
+    pub context_retry: ContextRetry,
+    pub pricing_json: Option<String>,
+    /// Mask secrets in outgoing prompts; see `crate::redact`.
+    pub redact: bool,
+    pub redact_stored: bool,
+    /// Extra redaction rules, by kind.
+    pub redact_patterns: BTreeMap<String, String>,
+    /// Changes to the built-in model profiles, by model id prefix.
+    pub model_profiles: BTreeMap<String, ProfileOverride>,
+    /// Named agents; see `crate::agents`.
+    pub agents: BTreeMap<String, AgentSettings>,
+    /// OpenAI-compatible providers besides xAI, by name; see
+    /// `crate::client::Providers`.
+    pub providers: BTreeMap<String, ProviderSettings>,
+    /// Built-in reply processors to apply, in order; see `crate::postprocess`.
+    pub post_processors: Vec<String>,
+    /// File the first code block of each reply is written to.
+    pub extract_code_to: Option<PathBuf>,
+    /// When to stop sending to a failing model; see `crate::client::CircuitBreakers`.
+    pub circuit_breaker: BreakerSettings,
+    /// IANA name of the zone reports group days in; `None` for the system's.
+    pub timezone: Option<String>,
+    /// Where attached and generated media are stored; see `media_dir`.
+    pub media_dir: Option<PathBuf>,
+    #[serde(skip)]
+    pub api_key_source: ApiKeySource,
+    /// Where the references in each resolved value came from, by key; see
+    /// `crate::config::Resolver`.
+    #[serde(skip)]
+    pub secret_sources: BTreeMap<String, Vec<SecretSource>>,
 }
 
 impl Config {
+    /// Loads the configuration, failing if no API key was provided.
     pub fn from_env() -> Result<Self> {
-        let xai_api_key = env::var("XAI_API_KEY")
-            .map_err(|_| anyhow!("XAI_API_KEY environment variable is required"))?;
+        let config = Self::load()?;
+        if config.xai_api_key.is_empty() {
+            return Err(MissingApiKey.into());
+        }
+        Ok(config)
+    }
+
+    /// Merges the built-in defaults, `config.toml` (with the profile named by
+    /// `GROK_PROFILE` on top of `[default]`) and environment variables, each
+    /// overriding the last. The API key falls back to the OS keyring and is
+    /// left empty when none is found. References such as `${VAR}` and
+    /// `file:<path>` in string values are resolved, wherever the value came
+    /// from.
+    pub fn load() -> Result<Self> {
+        let profile = selected_profile();
+        let file = match config_file_path() {
+            Some(path) => ConfigFile::load(&path)?,
+            None => None,
+        };
+        let settings = match (file, &profile) {
+            (Some(file), profile) => file.settings(profile.as_deref())?,
+            (None, Some(name)) => {
+                bail!("Profile '{}' selected but there is no config file", name)
+            }
+            (None, None) => ProfileSettings::default(),
+        };
+        let defaults = Config::default();
+        let mut resolver = Resolver::default();
+
+        let (xai_api_key, api_key_source) = match (env::var("XAI_API_KEY"), settings.api_key) {
+            (Ok(key), _) => (resolver.resolve("api_key", &key)?, ApiKeySource::Env),
+            (Err(_), Some(key)) => (resolver.resolve("api_key", &key)?, ApiKeySource::ConfigFile),
+            // An unusable keyring just means there is no key from it
+            (Err(_), None) if keyring_enabled() => {
+                match stored_api_key(profile.as_deref().unwrap_or(DEFAULT_PROFILE)) {
+                    Ok(Some(key)) => (key, ApiKeySource::Keyring),
+                    _ => (String::new(), ApiKeySource::Missing),
+                }
+            }
+            (Err(_), None) => (String::new(), ApiKeySource::Missing),
+        };
+
+        let xai_base_url = resolver
+            .optional(
+                "base_url",
+                env::var("XAI_BASE_URL").ok().or(settings.base_url),
+            )?
+            .unwrap_or(defaults.xai_base_url);
+
+        let database_url = resolver
+            .optional(
+                "database_url",
+                env::var("DATABASE_URL").ok().or(settings.database_url),
+            )?
+            .unwrap_or(defaults.database_url);
+
+        let server_host = resolver
+            .optional(
+                "server_host",
+                env::var("SERVER_HOST").ok().or(settings.server_host),
+            )?
+            .unwrap_or(defaults.server_host);
+
+        let server_port =
+            env_or("SERVER_PORT", settings.server_port)?.unwrap_or(defaults.server_port);
+
+        let server_read_only = env_or("SERVER_READ_ONLY", settings.server_read_only)?
+            .unwrap_or(defaults.server_read_only);
+
+        let default_model = resolver
+            .optional(
+                "default_model",
+                env::var("DEFAULT_MODEL").ok().or(settings.default_model),
+            )?
+            .unwrap_or(defaults.default_model);
 
-        let database_url =
-            env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite:grok_chat.db".to_string());
+        let system_prompt = resolver
+            .optional(
+                "system_prompt",
+                env::var("DEFAULT_SYSTEM_PROMPT")
+                    .ok()
+                    .or(settings.system_prompt),
+            )?
+            .unwrap_or(defaults.system_prompt);
 
-        let server_host = env::var("SERVER_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+        let max_tokens =
+            env_or("DEFAULT_MAX_TOKENS", settings.max_tokens)?.unwrap_or(defaults.max_tokens);
 
-        let server_port = env::var("SERVER_PORT")
-            .unwrap_or_else(|_| "3000".to_string())
-            .parse::<u16>()
-            .map_err(|_| anyhow!("Invalid SERVER_PORT value"))?;
+        let temperature =
+            env_or("DEFAULT_TEMPERATURE", settings.temperature)?.unwrap_or(defaults.temperature);
 
-        let default_model = env::var("DEFAULT_MODEL").unwrap_or_else(|_| "grok-4-0709".to_string());
+        let model_aliases =
+            resolver.map("model_aliases", settings.model_aliases.unwrap_or_default())?;
 
-        Ok(Config {
+        let auto_migrate_models = env_or("AUTO_MIGRATE_MODELS", settings.auto_migrate_models)?
+            .unwrap_or(defaults.auto_migrate_models);
+        let model_migrations = resolver.map(
+            "model_migrations",
+            settings.model_migrations.unwrap_or_default(),
+        )?;
+
+        let request_timeout_secs = env_or("REQUEST_TIMEOUT_SECS", settings.request_timeout_secs)?
+            .unwrap_or(defaults.request_timeout_secs);
+
+        let stream_resume_attempts =
+            env_or("STREAM_RESUME_ATTEMPTS", settings.stream_resume_attempts)?
+                .unwrap_or(defaults.stream_resume_attempts);
+
+        let stream_max_secs = env_or("STREAM_MAX_SECS", settings.stream_max_secs)?
+            .unwrap_or(defaults.stream_max_secs);
+
+        let kept_generations = env_or("KEPT_GENERATIONS", settings.kept_generations)?
+            .unwrap_or(defaults.kept_generations);
+
+        let cost_threshold_usd = env_or("COST_THRESHOLD_USD", settings.cost_threshold_usd)?
+            .unwrap_or(defaults.cost_threshold_usd);
+        if cost_threshold_usd.is_nan() || cost_threshold_usd < 0.0 {
+            bail!("cost_threshold_usd must be zero or more");
+        }
+        let cost_guard = env_or("COST_GUARD", settings.cost_guard)?.unwrap_or(defaults.cost_guard);
+        let context_retry =
+            env_or("CONTEXT_RETRY", settings.context_retry)?.unwrap_or(defaults.context_retry);
+
+        let pricing_json = resolver.optional("pricing_json", env::var("PRICING_JSON").ok())?;
+
+        let redact = env::var_os(NO_REDACT_VAR).is_none() && settings.redact.unwrap_or(true);
+        let redact_stored = settings.redact_stored.unwrap_or(false);
+        let redact_patterns = settings.redact_patterns.unwrap_or_default();
+        // Reject bad patterns now rather than on the first message
+        Redactor::new(&redact_patterns)?;
+        let model_profiles = settings.model_profiles.unwrap_or_default();
+        let profiles = ModelProfiles::with_overrides(&model_profiles)?;
+        let mut agents = settings.agents.unwrap_or_default();
+        for (name, agent) in &mut agents {
+            let key = |field: &str| format!("agents.{}.{}", name, field);
+            agent.description = resolver.optional(&key("description"), agent.description.take())?;
+            agent.model = resolver.optional(&key("model"), agent.model.take())?;
+            agent.system_prompt =
+                resolver.optional(&key("system_prompt"), agent.system_prompt.take())?;
+        }
+        let mut providers = settings.providers.unwrap_or_default();
+        for (name, provider) in &mut providers {
+            provider.base_url =
+                resolver.resolve(&format!("providers.{}.base_url", name), &provider.base_url)?;
+            provider.validate(name)?;
+        }
+
+        let post_processors = match env::var("POST_PROCESSORS") {
+            Ok(names) => names
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect(),
+            Err(_) => settings.post_processors.unwrap_or_default(),
+        };
+        Pipeline::new(&post_processors, None)?;
+        let extract_code_to = resolver.path(
+            "extract_code_to",
+            env::var_os("EXTRACT_CODE_TO")
+                .map(PathBuf::from)
+                .or(settings.extract_code_to),
+        )?;
+        let circuit_breaker = settings.circuit_breaker.unwrap_or_default();
+        circuit_breaker.validate()?;
+        // Read by `Locale::detect`; checked here so `check` reports a typo
+        if let Some(locale) = &settings.locale {
+            locale.parse::<Locale>()?;
+        }
+        let timezone = resolver.optional(
+            "timezone",
+            env::var("GROK_TIMEZONE").ok().or(settings.timezone),
+        )?;
+        if let Some(name) = &timezone {
+            name.parse::<Tz>().map_err(|_| {
+                anyhow!(
+                    "Unknown timezone '{}'; use an IANA name such as Europe/Madrid",
+                    name
+                )
+            })?;
+        }
+
+        let media_dir = resolver.path(
+            "media_dir",
+            env::var_os("GROK_MEDIA_DIR")
+                .map(PathBuf::from)
+                .or(settings.media_dir),
+        )?;
+
+        let config = Config {
             xai_api_key,
+            xai_base_url,
             database_url,
             server_host,
             server_port,
+            server_read_only,
             default_model,
-        })
+            system_prompt,
+            max_tokens,
+            temperature,
+            model_aliases,
+            auto_migrate_models,
+            model_migrations,
+            request_timeout_secs,
+            stream_resume_attempts,
+            stream_max_secs,
+            kept_generations,
+            cost_threshold_usd,
+            cost_guard,
+            context_retry,
+            pricing_json,
+            redact,
+            redact_stored,
+            redact_patterns,
+            model_profiles,
+            agents,
+            providers,
+            post_processors,
+            extract_code_to,
+            circuit_breaker,
+            timezone,
+            media_dir,
+            api_key_source,
+            secret_sources: resolver.into_sources(),
+        };
+        // Agents name aliases, so they can only be checked once those are known
+        for name in config.agents.keys() {
+            config.agent(name)?.validate(&profiles)?;
+        }
+        Ok(config)
+    }
+
+    /// Maps an alias to its model, or `None` to the default model. Names that
+    /// look like plain words (`fast`) must be configured aliases; anything
+    /// else (`grok-3`, `local/llama3`) is passed through unchanged.
+    pub fn resolve_model(&self, name: Option<&str>) -> Result<String> {
+        let name = name.unwrap_or(&self.default_model);
+        if let Some(model) = self.model_aliases.get(name) {
+            return Ok(model.clone());
+        }
+
+        let alias_like = name.chars().all(|c| c.is_ascii_alphabetic() || c == '_');
+        if !alias_like {
+            return Ok(name.to_string());
+        }
+
+        let aliases: Vec<&str> = self.model_aliases.keys().map(String::as_str).collect();
+        if aliases.is_empty() {
+            bail!(
+                "Unknown model or alias '{}' (no aliases are configured; add them under \
+                 [default.model_aliases] in config.toml)",
+                name
+            );
+        }
+        bail!(
+            "Unknown model or alias '{}' (available aliases: {})",
+            name,
+            aliases.join(", ")
+        )
     }
 
     pub fn xai_api_key(&self) -> &str {
         &self.xai_api_key
     }
 
+    pub fn xai_base_url(&self) -> &str {
+        &self.xai_base_url
+    }
+
     pub fn database_url(&self) -> &str {
         &self.database_url
     }
@@ -53,69 +398,506 @@ impl Config {
         self.server_port
     }
 
+    pub fn server_read_only(&self) -> bool {
+        self.server_read_only
+    }
+
     pub fn default_model(&self) -> &str {
         &self.default_model
     }
+
+    /// The API key with all but its last four characters masked. Short keys
+    /// are masked entirely.
+    pub fn redacted_api_key(&self) -> String {
+        let key = &self.xai_api_key;
+        if key.is_empty() {
+            return "(not set)".to_string();
+        }
+        let tail = match key.char_indices().rev().nth(3) {
+            Some((start, _)) if key.chars().count() >= 12 => &key[start..],
+            _ => "",
+        };
+        format!("****{}", tail)
+    }
+
+    pub fn api_key_source(&self) -> ApiKeySource {
+        self.api_key_source
+    }
+
+    /// Where the references in each resolved value came from, by key.
+    pub fn secret_sources(&self) -> &BTreeMap<String, Vec<SecretSource>> {
+        &self.secret_sources
+    }
+
+    /// Whether the value of `key` came from the keyring or a file, and so
+    /// isn't shown.
+    pub fn is_secret(&self, key: &str) -> bool {
+        self.secret_sources
+            .get(key)
+            .is_some_and(|sources| sources.iter().any(SecretSource::is_hidden))
+    }
+
+    pub fn system_prompt(&self) -> &str {
+        &self.system_prompt
+    }
+
+    pub fn max_tokens(&self) -> i32 {
+        self.max_tokens
+    }
+
+    pub fn temperature(&self) -> f32 {
+        self.temperature
+    }
+
+    pub fn model_aliases(&self) -> &BTreeMap<String, String> {
+        &self.model_aliases
+    }
+
+    pub fn auto_migrate_models(&self) -> bool {
+        self.auto_migrate_models
+    }
+
+    pub fn model_migrations(&self) -> &BTreeMap<String, String> {
+        &self.model_migrations
+    }
+
+    /// The model to retry with in place of `retired` when models are
+    /// migrated automatically: its configured replacement, else the
+    /// suggested one. Replacements may be aliases.
+    pub fn model_replacement(&self, retired: &RetiredModel) -> Option<String> {
+        if !self.auto_migrate_models {
+            return None;
+        }
+        let replacement = self
+            .model_migrations
+            .get(&retired.model)
+            .or(retired.suggestion.as_ref())?;
+        self.resolve_model(Some(replacement)).ok()
+    }
+
+    pub fn request_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.request_timeout_secs)
+    }
+
+    pub fn stream_resume_attempts(&self) -> u32 {
+        self.stream_resume_attempts
+    }
+
+    pub fn stream_max(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.stream_max_secs)
+    }
+
+    pub fn kept_generations(&self) -> usize {
+        self.kept_generations as usize
+    }
+
+    pub fn cost_threshold_usd(&self) -> f64 {
+        self.cost_threshold_usd
+    }
+
+    pub fn cost_guard(&self) -> GuardMode {
+        self.cost_guard
+    }
+
+    pub fn context_retry(&self) -> ContextRetry {
+        self.context_retry
+    }
+
+    pub fn pricing_json(&self) -> Option<&str> {
+        self.pricing_json.as_deref()
+    }
+
+    pub fn redact(&self) -> bool {
+        self.redact
+    }
+
+    pub fn redact_stored(&self) -> bool {
+        self.redact_stored
+    }
+
+    pub fn redact_patterns(&self) -> &BTreeMap<String, String> {
+        &self.redact_patterns
+    }
+
+    pub fn model_profiles(&self) -> &BTreeMap<String, ProfileOverride> {
+        &self.model_profiles
+    }
+
+    /// The agent called `name`, with its model resolved; an `UnknownAgent`
+    /// error listing the configured ones when there is none.
+    pub fn agent(&self, name: &str) -> Result<Agent> {
+        let Some(settings) = self.agents.get(name) else {
+            return Err(UnknownAgent {
+                name: name.to_string(),
+                available: self.agents.keys().cloned().collect(),
+            }
+            .into());
+        };
+        let model = self
+            .resolve_model(settings.model.as_deref())
+            .map_err(|e| anyhow!("agents.{}: {}", name, e))?;
+        Ok(Agent::new(name, settings, model))
+    }
+
+    /// Every configured agent, by name.
+    pub fn agents(&self) -> Vec<Agent> {
+        self.agents
+            .keys()
+            .filter_map(|name| self.agent(name).ok())
+            .collect()
+    }
+
+    pub fn providers(&self) -> &BTreeMap<String, ProviderSettings> {
+        &self.providers
+    }
+
+    pub fn post_processors(&self) -> &[String] {
+        &self.post_processors
+    }
+
+    pub fn extract_code_to(&self) -> Option<&Path> {
+        self.extract_code_to.as_deref()
+    }
+
+    pub fn circuit_breaker(&self) -> &BreakerSettings {
+        &self.circuit_breaker
+    }
+
+    /// The zone reports group days in; `None` means the system's local time.
+    pub fn timezone(&self) -> Option<Tz> {
+        self.timezone.as_deref()?.parse().ok()
+    }
+
+    /// The media store's directory: the configured one, else `media` in the
+    /// per-user data directory (`~/.local/share/grok-chat/media` on Linux).
+    pub fn media_dir(&self) -> PathBuf {
+        self.media_dir.clone().unwrap_or_else(|| {
+            dirs::data_dir()
+                .map(|dir| dir.join("grok-chat"))
+                .unwrap_or_default()
+                .join("media")
+        })
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             xai_api_key: "".to_string(),
+            xai_base_url: DEFAULT_XAI_BASE_URL.to_string(),
             database_url: "sqlite:grok_chat.db".to_string(),
             server_host: "127.0.0.1".to_string(),
             server_port: 3000,
-            default_model: "grok-4-0709".to_string(),
+            server_read_only: false,
+            default_model: DEFAULT_MODEL.to_string(),
+            system_prompt: DEFAULT_SYSTEM_PROMPT.to_string(),
+            max_tokens: DEFAULT_MAX_TOKENS,
+            temperature: DEFAULT_TEMPERATURE,
+            model_aliases: BTreeMap::new(),
+            auto_migrate_models: false,
+            model_migrations: BTreeMap::new(),
+            request_timeout_secs: 60,
+            stream_resume_attempts: 0,
+            stream_max_secs: 600,
+            kept_generations: 5,
+            cost_threshold_usd: 0.50,
+            cost_guard: GuardMode::default(),
+            context_retry: ContextRetry::default(),
+            pricing_json: None,
+            redact: true,
+            redact_stored: false,
+            redact_patterns: BTreeMap::new(),
+            model_profiles: BTreeMap::new(),
+            agents: BTreeMap::new(),
+            providers: BTreeMap::new(),
+            post_processors: Vec::new(),
+            extract_code_to: None,
+            circuit_breaker: BreakerSettings::default(),
+            timezone: None,
+            media_dir: None,
+            api_key_source: ApiKeySource::Missing,
+            secret_sources: BTreeMap::new(),
         }
     }
 }
 
+/// The environment variable `name` parsed, else `fallback` from the file.
+fn env_or<T: FromStr>(name: &str, fallback: Option<T>) -> Result<Option<T>> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| anyhow!("Invalid {} value", name)),
+        Err(_) => Ok(fallback),
+    }
+}
+
 pub fn load_config() -> Result<Config> {
     Config::from_env()
 }
 
+/// Per-user directory for files such as the REPL history (`~/.config/grok-chat`
+/// on Linux), or `None` when the platform has no notion of one.
+pub fn config_dir() -> Option<std::path::PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("grok-chat"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::auth::NO_KEYRING_VAR;
+    use crate::config::{CONFIG_PATH_VAR, PROFILE_VAR};
     use std::env;
+    use std::sync::Mutex;
+
+    // Tests mutate process-wide env vars, so they must not run concurrently.
+    pub(crate) static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Keeps a config file or stored key on the developer's machine out of
+    /// the tests.
+    fn without_config_file() {
+        env::set_var(CONFIG_PATH_VAR, "/nonexistent/grok-chat/config.toml");
+        env::set_var(NO_KEYRING_VAR, "1");
+        env::remove_var(PROFILE_VAR);
+    }
 
     #[test]
     fn test_config_from_env() {
+        let _guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        without_config_file();
         env::set_var("XAI_API_KEY", "test-key");
         env::set_var("DATABASE_URL", "sqlite:test.db");
         env::set_var("SERVER_HOST", "localhost");
         env::set_var("SERVER_PORT", "8080");
+        env::set_var("SERVER_READ_ONLY", "true");
         env::set_var("DEFAULT_MODEL", "grok-3");
+        env::set_var("XAI_BASE_URL", "http://localhost:9999/v1");
+        env::set_var("REQUEST_TIMEOUT_SECS", "5");
 
         let config = Config::from_env().unwrap();
         assert_eq!(config.xai_api_key(), "test-key");
+        assert_eq!(config.api_key_source(), ApiKeySource::Env);
         assert_eq!(config.database_url(), "sqlite:test.db");
         assert_eq!(config.server_host(), "localhost");
         assert_eq!(config.server_port(), 8080);
+        assert!(config.server_read_only());
         assert_eq!(config.default_model(), "grok-3");
+        assert_eq!(config.xai_base_url(), "http://localhost:9999/v1");
+        assert_eq!(config.request_timeout().as_secs(), 5);
     }
 
     #[test]
     fn test_config_defaults() {
-        env::remove_var("XAI_API_KEY");
+        let _guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        without_config_file();
+        env::set_var("XAI_API_KEY", "test-key");
+        env::remove_var("XAI_BASE_URL");
+        env::remove_var("REQUEST_TIMEOUT_SECS");
         env::remove_var("DATABASE_URL");
         env::remove_var("SERVER_HOST");
         env::remove_var("SERVER_PORT");
+        env::remove_var("SERVER_READ_ONLY");
         env::remove_var("DEFAULT_MODEL");
 
         let config = Config::from_env().unwrap();
         assert_eq!(config.database_url(), "sqlite:grok_chat.db");
         assert_eq!(config.server_host(), "127.0.0.1");
         assert_eq!(config.server_port(), 3000);
+        assert!(!config.server_read_only());
         assert_eq!(config.default_model(), "grok-4-0709");
+        assert_eq!(config.xai_base_url(), DEFAULT_XAI_BASE_URL);
+        assert_eq!(config.request_timeout().as_secs(), 60);
     }
 
     #[test]
     fn test_config_missing_api_key() {
+        let _guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        without_config_file();
         env::remove_var("XAI_API_KEY");
 
         let result = Config::from_env();
         assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("XAI_API_KEY"));
+        let error = result.unwrap_err();
+        assert!(error.to_string().contains("XAI_API_KEY"));
+        assert!(error.is::<MissingApiKey>());
+    }
+
+    #[test]
+    fn test_model_aliases_resolve() {
+        let config = Config {
+            default_model: "fast".to_string(),
+            model_aliases: BTreeMap::from([
+                ("fast".to_string(), "grok-4-fast-non-reasoning".to_string()),
+                ("mini".to_string(), "grok-3-mini".to_string()),
+            ]),
+            ..Config::default()
+        };
+
+        assert_eq!(
+            config.resolve_model(None).unwrap(),
+            "grok-4-fast-non-reasoning"
+        );
+        assert_eq!(config.resolve_model(Some("mini")).unwrap(), "grok-3-mini");
+        assert_eq!(config.resolve_model(Some("grok-3")).unwrap(), "grok-3");
+
+        let error = config.resolve_model(Some("smart")).unwrap_err().to_string();
+        assert!(error.contains("'smart'") && error.contains("fast, mini"));
+    }
+
+    #[test]
+    fn test_api_key_redaction() {
+        let redacted = |key: &str| {
+            Config {
+                xai_api_key: key.to_string(),
+                ..Config::default()
+            }
+            .redacted_api_key()
+        };
+
+        assert_eq!(redacted("xai-abcdefgh12345678"), "****5678");
+        assert_eq!(redacted("short-key"), "****");
+        assert_eq!(redacted(""), "(not set)");
+    }
+
+    #[test]
+    fn test_env_wins_over_selected_profile() {
+        let _guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "[default]\napi_key = \"file-key\"\nserver_port = 4000\n\n\
+             [profiles.work]\ndefault_model = \"grok-3-mini\"\nsystem_prompt = \"Be brief.\"\n",
+        )
+        .unwrap();
+        env::set_var(CONFIG_PATH_VAR, &path);
+        env::set_var(PROFILE_VAR, "work");
+        env::remove_var("XAI_API_KEY");
+        env::remove_var("SERVER_PORT");
+        env::set_var("DEFAULT_MODEL", "grok-4-0709");
+
+        let config = Config::from_env().unwrap();
+        assert_eq!(config.xai_api_key(), "file-key");
+        assert_eq!(config.api_key_source(), ApiKeySource::ConfigFile);
+        assert_eq!(config.server_port(), 4000);
+        assert_eq!(config.default_model(), "grok-4-0709");
+        assert_eq!(config.system_prompt(), "Be brief.");
+
+        env::set_var(PROFILE_VAR, "home");
+        assert!(Config::from_env().is_err());
+
+        env::remove_var("DEFAULT_MODEL");
+        without_config_file();
+    }
+
+    #[test]
+    fn test_agents_load_from_the_config_file() {
+        let _guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let write = |agent: &str| {
+            std::fs::write(
+                &path,
+                format!(
+                    "[default]\napi_key = \"file-key\"\n\n\
+                     [default.model_aliases]\nsmart = \"grok-3\"\n\n\
+                     [default.agents.reviewer]\nmodel = \"smart\"\n{}",
+                    agent
+                ),
+            )
+            .unwrap();
+        };
+        env::set_var(CONFIG_PATH_VAR, &path);
+        env::remove_var(PROFILE_VAR);
+        env::remove_var("XAI_API_KEY");
+
+        write("system_prompt = \"Review the code.\"\ntemperature = 0.2\n");
+        let config = Config::from_env().unwrap();
+        let reviewer = config.agent("reviewer").unwrap();
+        assert_eq!(reviewer.model, "grok-3");
+        assert_eq!(reviewer.system_prompt.as_deref(), Some("Review the code."));
+        assert_eq!(config.agents(), vec![reviewer]);
+        let error = config.agent("writer").unwrap_err();
+        assert!(error.is::<UnknownAgent>());
+        assert!(error.to_string().contains("available: reviewer"));
+
+        write("temperature = 3.0\n");
+        let error = Config::from_env().unwrap_err().to_string();
+        assert!(error.contains("agents.reviewer"), "{}", error);
+
+        write("voice = \"calm\"\n");
+        assert!(Config::from_env().is_err());
+
+        without_config_file();
+    }
+
+    #[test]
+    fn test_references_resolve_in_file_and_env_values() {
+        let _guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        without_config_file();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(dir.path().join("xai"), "xai-from-file\n").unwrap();
+        let write = |api_key: &str| {
+            std::fs::write(
+                &path,
+                format!(
+                    "[default]\napi_key = \"{}\"\n\
+                     database_url = \"sqlite:${{GROK_TEST_DIR}}/chat.db\"\n\
+                     system_prompt = \"Quote prices in $$ and ${{x}}\"\n",
+                    api_key
+                ),
+            )
+            .unwrap();
+        };
+        env::set_var(CONFIG_PATH_VAR, &path);
+        env::remove_var("XAI_API_KEY");
+        env::remove_var("DATABASE_URL");
+        env::remove_var("DEFAULT_SYSTEM_PROMPT");
+        env::set_var("GROK_TEST_DIR", dir.path());
+        env::set_var("DEFAULT_MODEL", "${GROK_TEST_MODEL}");
+        env::set_var("GROK_TEST_MODEL", "grok-3");
+
+        write("file:${GROK_TEST_DIR}/xai");
+        let error = Config::from_env().unwrap_err().to_string();
+        assert!(error.starts_with("system_prompt: ${x}"), "{}", error);
+
+        std::fs::write(
+            &path,
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .replace("${x}", "$x"),
+        )
+        .unwrap();
+        let config = Config::from_env().unwrap();
+        assert_eq!(config.xai_api_key(), "xai-from-file");
+        let db = format!("sqlite:{}/chat.db", dir.path().display());
+        assert_eq!(config.database_url(), db);
+        assert_eq!(config.system_prompt(), "Quote prices in $ and $x");
+        assert_eq!(config.default_model(), "grok-3");
+        let sources = &config.secret_sources()["api_key"];
+        assert_eq!(sources[1], SecretSource::File(dir.path().join("xai")));
+        assert!(config.is_secret("api_key"));
+        assert!(!config.is_secret("database_url"));
+
+        write("keyring:grok-api-key");
+        let error = Config::from_env().unwrap_err().to_string();
+        assert!(
+            error.starts_with("api_key: keyring:grok-api-key: keyring lookups are disabled"),
+            "{}",
+            error
+        );
+        write("file:/nonexistent/xai");
+        let error = Config::from_env().unwrap_err().to_string();
+        assert!(
+            error.starts_with("api_key: file:/nonexistent/xai"),
+            "{}",
+            error
+        );
+
+        env::remove_var("GROK_TEST_DIR");
+        env::remove_var("GROK_TEST_MODEL");
+        env::remove_var("DEFAULT_MODEL");
+        without_config_file();
     }
 }
@@ -0,0 +1,394 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use super::config_dir;
+use crate::agents::AgentSettings;
+use crate::capabilities::ProfileOverride;
+use crate::client::{BreakerSettings, ProviderSettings};
+use crate::context::ContextRetry;
+use crate::pricing::GuardMode;
+
+/// Overrides the config file location (mainly for tests and scripts).
+pub const CONFIG_PATH_VAR: &str = "GROK_CONFIG";
+
+/// Selects a `[profiles.<name>]` section; `--profile` sets it for the process.
+pub const PROFILE_VAR: &str = "GROK_PROFILE";
+
+pub const CONFIG_TEMPLATE: &str = r#"# grok-chat configuration
+#
+# Values here sit between the built-in defaults and environment variables:
+# env vars (XAI_API_KEY, DEFAULT_MODEL, ...) win over this file, and command
+# line flags such as --model win over both.
+#
+# A running `serve` picks up saved changes to the default model, prompt and
+# parameters, model aliases, agents, kept_generations, the cost guard,
+# context_retry and timezone; other keys need a restart.
+#
+# String values may refer to secrets instead of holding them: "${VAR}" is
+# replaced with the environment variable ("$$" is a literal "$"), and a whole
+# value of "keyring:<name>" or "file:<path>" is read from the OS keyring or the
+# file, e.g. api_key = "keyring:grok-api-key".
+
+[default]
+# api_key = "xai-..."
+# base_url = "https://api.x.ai/v1"
+# default_model = "grok-4-0709"
+# system_prompt = "You are a concise assistant."
+# max_tokens = 2048
+# temperature = 0.7
+# database_url = "sqlite:grok_chat.db"
+# server_host = "127.0.0.1"
+# server_port = 3000
+# Serve history read-only: every POST, PUT, PATCH and DELETE gets a 403
+# server_read_only = false
+# request_timeout_secs = 60
+# Language of messages (en, es); otherwise taken from LC_ALL, LC_MESSAGES or LANG
+# locale = "es"
+# Zone `report` groups days in (IANA name); otherwise the system's local time
+# timezone = "Europe/Madrid"
+# Directory attached images are stored in, one file per distinct image
+# media_dir = "/home/me/.local/share/grok-chat/media"
+
+# Streamed replies cut off partway (a dropped connection) are picked up with up
+# to this many follow-up requests, each of which pays for the prompt again.
+# stream_resume_attempts = 0
+
+# Streamed replies from the server that take longer than this, thinking
+# included, are ended with an error event.
+# stream_max_secs = 600
+
+# Regenerating a reply keeps the replaced one for comparison (`%` in the chat
+# UI); older generations beyond this many per reply are deleted.
+# kept_generations = 5
+
+# Requests projected to cost more than this (prompt plus a reply of max_tokens,
+# at PRICING_JSON or built-in prices) need confirming with cost_guard =
+# "block": the chat UI asks, `chat` needs --yes and the server wants
+# confirm_cost. "warn" only warns, "off" skips the estimate.
+# cost_threshold_usd = 0.50
+# cost_guard = "block"
+
+# A conversation the API rejects as too long for the model (the token estimate
+# can undercount) is sent again with the oldest fifth of its unpinned history
+# dropped, at most twice: "auto" does so, "ask" asks first in the chat UI and
+# needs shed_context from API clients, "off" reports the error.
+# context_retry = "auto"
+
+# Secrets and emails in prompts are replaced with [REDACTED:<kind>] before they
+# are sent (turn off for one run with --no-redact). Local history keeps the
+# original text unless redact_stored is set.
+# redact = true
+# redact_stored = false
+
+# Applied in order to every reply before it is shown or saved: strip_thinking
+# drops <think>/<thinking> blocks, normalize_whitespace collapses blank lines.
+# Streamed replies are printed as they arrive and saved processed.
+# post_processors = ["strip_thinking", "normalize_whitespace"]
+# Write the first fenced code block of each reply to this file
+# extract_code_to = "reply.code"
+
+# Short names accepted anywhere a model is (`chat -m fast`, API requests, TUI)
+# [default.model_aliases]
+# fast = "grok-4-fast-non-reasoning"
+# mini = "grok-3-mini"
+
+# A request to a model the API no longer offers fails with the closest model
+# it does offer as a suggestion. With auto_migrate_models it is sent again to
+# the replacement below, else to that closest model, and the session moves
+# over to it.
+# auto_migrate_models = false
+# [default.model_migrations]
+# grok-2 = "grok-3"
+
+# A model that keeps failing (timeouts, rate limits, server errors) is skipped
+# for open_secs after `failures` failures in a row, or once more than
+# error_rate of at least min_requests requests in window_secs failed. Then one
+# probe request decides whether to resume. Meanwhile requests fail at once, or
+# go to fallback_model when one is set. failures = 0 turns this off.
+# [default.circuit_breaker]
+# failures = 5
+# error_rate = 0.5
+# min_requests = 10
+# window_secs = 60
+# open_secs = 30
+# fallback_model = "grok-3-mini"
+
+# Extra redaction rules: kind = "regular expression"
+# [default.redact_patterns]
+# ticket = "SEC-[0-9]+"
+
+# Adjust the built-in profile of models starting with a prefix: context_window,
+# max_output_tokens, default_max_tokens, default_temperature, and whether
+# temperature, logprobs, streaming, tools, vision and reasoning are supported
+# [default.model_profiles.grok-4]
+# max_output_tokens = 8000
+
+# Named agents, each with its own model, system prompt, parameters and tools.
+# Pick one with `chat --agent reviewer`, `/agent reviewer` in the chat UI or
+# "agent": "reviewer" in API requests; sessions can keep one as their default.
+# Checked against the model's profile when the config loads.
+# [default.agents.reviewer]
+# description = "Terse code review"
+# model = "grok-3"
+# system_prompt = "Review the code you are given. Be terse and specific."
+# max_tokens = 4000
+# temperature = 0.2
+# tools = ["read_file"]
+
+# Other OpenAI-compatible providers. Their models are named
+# "<provider>/<model>", such as "local/llama3"; bare names go to xAI. The API
+# key is read from the api_key_env variable; without one none is sent. Set
+# streaming, usage or logprobs to false when the provider lacks them: streamed
+# replies then arrive whole and usage is estimated. `models` are offered in the
+# chat UI's picker and listed when the provider can't list its own.
+# [default.providers.local]
+# base_url = "http://localhost:8080/v1"
+# models = ["llama3"]
+# usage = false
+#
+# [default.providers.openai]
+# base_url = "https://api.openai.com/v1"
+# api_key_env = "OPENAI_API_KEY"
+
+# Named profiles override [default]. Select one with `--profile work` or
+# GROK_PROFILE=work.
+#
+# [profiles.work]
+# api_key = "xai-..."
+# default_model = "fast"
+# database_url = "sqlite:/home/me/work/grok_chat.db"
+"#;
+
+/// Settings a config file section may provide. Every key is optional.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileSettings {
+    pub api_key: Option<String>,
+    pub base_url: Option<String>,
+    pub default_model: Option<String>,
+    pub system_prompt: Option<String>,
+    pub max_tokens: Option<i32>,
+    pub temperature: Option<f32>,
+    pub model_aliases: Option<BTreeMap<String, String>>,
+    pub auto_migrate_models: Option<bool>,
+    pub model_migrations: Option<BTreeMap<String, String>>,
+    pub database_url: Option<String>,
+    pub server_host: Option<String>,
+    pub server_port: Option<u16>,
+    pub server_read_only: Option<bool>,
+    pub request_timeout_secs: Option<u64>,
+    pub stream_resume_attempts: Option<u32>,
+    pub stream_max_secs: Option<u64>,
+    pub kept_generations: Option<u32>,
+    pub cost_threshold_usd: Option<f64>,
+    pub cost_guard: Option<GuardMode>,
+    pub context_retry: Option<ContextRetry>,
+    pub redact: Option<bool>,
+    pub redact_stored: Option<bool>,
+    pub redact_patterns: Option<BTreeMap<String, String>>,
+    pub model_profiles: Option<BTreeMap<String, ProfileOverride>>,
+    pub agents: Option<BTreeMap<String, AgentSettings>>,
+    pub providers: Option<BTreeMap<String, ProviderSettings>>,
+    pub post_processors: Option<Vec<String>>,
+    pub extract_code_to: Option<PathBuf>,
+    pub circuit_breaker: Option<BreakerSettings>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+    pub media_dir: Option<PathBuf>,
+}
+
+/// `config.toml`: a `[default]` section plus any number of named profiles.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(default)]
+    pub default: ProfileSettings,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ProfileSettings>,
+}
+
+impl ProfileSettings {
+    /// Layers `other` on top of `self`, keeping values `other` leaves unset.
+    /// Model aliases and migrations, redaction patterns, model profiles,
+    /// agents and providers are combined, with `other` winning on conflicts.
+    pub fn merge(self, other: ProfileSettings) -> Self {
+        Self {
+            api_key: other.api_key.or(self.api_key),
+            base_url: other.base_url.or(self.base_url),
+            default_model: other.default_model.or(self.default_model),
+            system_prompt: other.system_prompt.or(self.system_prompt),
+            max_tokens: other.max_tokens.or(self.max_tokens),
+            temperature: other.temperature.or(self.temperature),
+            model_aliases: merge_maps(self.model_aliases, other.model_aliases),
+            auto_migrate_models: other.auto_migrate_models.or(self.auto_migrate_models),
+            model_migrations: merge_maps(self.model_migrations, other.model_migrations),
+            database_url: other.database_url.or(self.database_url),
+            server_host: other.server_host.or(self.server_host),
+            server_port: other.server_port.or(self.server_port),
+            server_read_only: other.server_read_only.or(self.server_read_only),
+            request_timeout_secs: other.request_timeout_secs.or(self.request_timeout_secs),
+            stream_resume_attempts: other.stream_resume_attempts.or(self.stream_resume_attempts),
+            stream_max_secs: other.stream_max_secs.or(self.stream_max_secs),
+            kept_generations: other.kept_generations.or(self.kept_generations),
+            cost_threshold_usd: other.cost_threshold_usd.or(self.cost_threshold_usd),
+            cost_guard: other.cost_guard.or(self.cost_guard),
+            context_retry: other.context_retry.or(self.context_retry),
+            redact: other.redact.or(self.redact),
+            redact_stored: other.redact_stored.or(self.redact_stored),
+            redact_patterns: merge_maps(self.redact_patterns, other.redact_patterns),
+            model_profiles: merge_maps(self.model_profiles, other.model_profiles),
+            agents: merge_maps(self.agents, other.agents),
+            providers: merge_maps(self.providers, other.providers),
+            post_processors: other.post_processors.or(self.post_processors),
+            extract_code_to: other.extract_code_to.or(self.extract_code_to),
+            circuit_breaker: other.circuit_breaker.or(self.circuit_breaker),
+            locale: other.locale.or(self.locale),
+            timezone: other.timezone.or(self.timezone),
+            media_dir: other.media_dir.or(self.media_dir),
+        }
+    }
+}
+
+fn merge_maps<V>(
+    base: Option<BTreeMap<String, V>>,
+    overrides: Option<BTreeMap<String, V>>,
+) -> Option<BTreeMap<String, V>> {
+    match (base, overrides) {
+        (Some(mut base), Some(overrides)) => {
+            base.extend(overrides);
+            Some(base)
+        }
+        (base, overrides) => overrides.or(base),
+    }
+}
+
+impl ConfigFile {
+    pub fn parse(source: &str, path: &Path) -> Result<Self> {
+        toml::from_str(source).map_err(|e| {
+            let Some(span) = e.span() else {
+                return anyhow!("{}: {}", path.display(), e.message());
+            };
+            let line_number = source[..span.start].matches('\n').count() + 1;
+            let line = source.lines().nth(line_number - 1).unwrap_or_default();
+            match line.split_once('=') {
+                Some((key, _)) => anyhow!(
+                    "{}:{}: invalid key `{}`: {}",
+                    path.display(),
+                    line_number,
+                    key.trim(),
+                    e.message()
+                ),
+                None => anyhow!("{}:{}: {}", path.display(), line_number, e.message()),
+            }
+        })
+    }
+
+    /// Reads the file at `path`, or `None` if it does not exist.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        match std::fs::read_to_string(path) {
+            Ok(source) => Self::parse(&source, path).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// `[default]` with the named profile layered on top.
+    pub fn settings(&self, profile: Option<&str>) -> Result<ProfileSettings> {
+        let Some(name) = profile else {
+            return Ok(self.default.clone());
+        };
+        match self.profiles.get(name) {
+            Some(settings) => Ok(self.default.clone().merge(settings.clone())),
+            None => bail!(
+                "Profile '{}' is not defined (available: {})",
+                name,
+                self.profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+}
+
+/// Where `config.toml` is read from: `GROK_CONFIG`, else the user config dir.
+pub fn config_file_path() -> Option<PathBuf> {
+    match std::env::var_os(CONFIG_PATH_VAR) {
+        Some(path) => Some(PathBuf::from(path)),
+        None => config_dir().map(|dir| dir.join("config.toml")),
+    }
+}
+
+/// The profile selected through `GROK_PROFILE`, if any.
+pub fn selected_profile() -> Option<String> {
+    std::env::var(PROFILE_VAR)
+        .ok()
+        .filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_overrides_default_section() {
+        let file = ConfigFile::parse(
+            r#"
+            [default]
+            default_model = "grok-3"
+            server_port = 4000
+            post_processors = ["strip_thinking"]
+            circuit_breaker = { failures = 2 }
+            model_aliases = { fast = "grok-4-fast-non-reasoning", mini = "grok-3-mini" }
+
+            [profiles.work]
+            api_key = "work-key"
+            default_model = "grok-4-fast-reasoning"
+
+            [profiles.work.model_aliases]
+            fast = "grok-4-fast-reasoning"
+            "#,
+            Path::new("config.toml"),
+        )
+        .unwrap();
+
+        let work = file.settings(Some("work")).unwrap();
+        assert_eq!(work.api_key.as_deref(), Some("work-key"));
+        assert_eq!(work.default_model.as_deref(), Some("grok-4-fast-reasoning"));
+        assert_eq!(work.server_port, Some(4000));
+        assert_eq!(work.post_processors.unwrap(), ["strip_thinking"]);
+        let breaker = work.circuit_breaker.unwrap();
+        assert_eq!((breaker.failures, breaker.open_secs), (2, 30));
+        let aliases = work.model_aliases.unwrap();
+        assert_eq!(aliases["fast"], "grok-4-fast-reasoning");
+        assert_eq!(aliases["mini"], "grok-3-mini");
+
+        assert_eq!(file.settings(None).unwrap().api_key, None);
+        let error = file.settings(Some("home")).unwrap_err().to_string();
+        assert!(error.contains("'home'") && error.contains("work"));
+    }
+
+    #[test]
+    fn test_parse_errors_name_file_line_and_key() {
+        let error = ConfigFile::parse(
+            "[default]\nserver_port = \"eighty\"\n",
+            Path::new("/tmp/config.toml"),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.starts_with("/tmp/config.toml:2: invalid key `server_port`"));
+
+        let error = ConfigFile::parse(
+            "[profiles.work]\n\nmodel = \"grok-3\"\n",
+            Path::new("config.toml"),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.starts_with("config.toml:3: invalid key `model`: unknown field"));
+    }
+
+    #[test]
+    fn test_template_parses() {
+        let file = ConfigFile::parse(CONFIG_TEMPLATE, Path::new("template")).unwrap();
+        assert_eq!(file, ConfigFile::default());
+    }
+}
@@ -1,2 +1,7 @@
 pub use self::config::*;
+pub use self::file::*;
+pub use self::secrets::*;
+#[allow(clippy::module_inception)]
 mod config;
+mod file;
+mod secrets;
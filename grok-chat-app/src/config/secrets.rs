@@ -0,0 +1,154 @@
+//! References in config values, so a config file can be checked in without
+//! its secrets. `${VAR}` anywhere in a value is replaced with the environment
+//! variable, and `$$` with a single `$`. A whole value of `keyring:<name>` is
+//! the OS keyring entry stored under that name, and `file:<path>` the
+//! contents of the file, without the trailing newline. Values from the
+//! config file and from environment variables are resolved alike.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::auth::{keyring_enabled, stored_secret, NO_KEYRING_VAR};
+
+/// Where part of a resolved value came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretSource {
+    Env(String),
+    Keyring(String),
+    File(PathBuf),
+}
+
+impl SecretSource {
+    /// Whether the value is secret enough to keep out of `config show`.
+    /// Expanded environment variables are shown, like the rest of the config.
+    pub fn is_hidden(&self) -> bool {
+        !matches!(self, SecretSource::Env(_))
+    }
+}
+
+impl fmt::Display for SecretSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretSource::Env(name) => write!(f, "environment variable {}", name),
+            SecretSource::Keyring(name) => write!(f, "OS keyring entry '{}'", name),
+            SecretSource::File(path) => write!(f, "file {}", path.display()),
+        }
+    }
+}
+
+/// Resolves config values, recording the sources of each key that had
+/// references in it.
+#[derive(Debug, Default)]
+pub struct Resolver {
+    sources: BTreeMap<String, Vec<SecretSource>>,
+}
+
+impl Resolver {
+    /// `value` with its references resolved; errors name `key` and the
+    /// reference that could not be resolved.
+    pub fn resolve(&mut self, key: &str, value: &str) -> Result<String> {
+        let (resolved, sources) = resolve_value(value).map_err(|e| anyhow!("{}: {:#}", key, e))?;
+        if !sources.is_empty() {
+            self.sources.insert(key.to_string(), sources);
+        }
+        Ok(resolved)
+    }
+
+    pub fn optional(&mut self, key: &str, value: Option<String>) -> Result<Option<String>> {
+        value.map(|value| self.resolve(key, &value)).transpose()
+    }
+
+    /// Paths that aren't valid UTF-8 can't hold references and are kept.
+    pub fn path(&mut self, key: &str, value: Option<PathBuf>) -> Result<Option<PathBuf>> {
+        match value {
+            Some(path) => match path.to_str() {
+                Some(text) => self.resolve(key, text).map(|text| Some(text.into())),
+                None => Ok(Some(path)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Resolves the values of `map`, as `<key>.<name>`.
+    pub fn map(
+        &mut self,
+        key: &str,
+        map: BTreeMap<String, String>,
+    ) -> Result<BTreeMap<String, String>> {
+        map.into_iter()
+            .map(|(name, value)| {
+                let value = self.resolve(&format!("{}.{}", key, name), &value)?;
+                Ok((name, value))
+            })
+            .collect()
+    }
+
+    pub fn into_sources(self) -> BTreeMap<String, Vec<SecretSource>> {
+        self.sources
+    }
+}
+
+/// `value` with its references resolved, and where they came from.
+pub fn resolve_value(value: &str) -> Result<(String, Vec<SecretSource>)> {
+    let mut sources = Vec::new();
+    if let Some(name) = value.strip_prefix("keyring:") {
+        let name = expand(name, &mut sources)?;
+        let secret = keyring_secret(&name).with_context(|| format!("keyring:{}", name))?;
+        sources.push(SecretSource::Keyring(name));
+        return Ok((secret, sources));
+    }
+    if let Some(path) = value.strip_prefix("file:") {
+        let path = PathBuf::from(expand(path, &mut sources)?);
+        let secret =
+            std::fs::read_to_string(&path).with_context(|| format!("file:{}", path.display()))?;
+        sources.push(SecretSource::File(path));
+        return Ok((secret.trim_end_matches(['\n', '\r']).to_string(), sources));
+    }
+    let expanded = expand(value, &mut sources)?;
+    Ok((expanded, sources))
+}
+
+fn keyring_secret(name: &str) -> Result<String> {
+    if name.is_empty() {
+        bail!("no keyring entry named");
+    }
+    if !keyring_enabled() {
+        bail!("keyring lookups are disabled by {}", NO_KEYRING_VAR);
+    }
+    stored_secret(name)?.ok_or_else(|| anyhow!("nothing is stored under this name"))
+}
+
+/// Replaces `${VAR}` with the variable and `$$` with `$`. Any other `$` is
+/// kept as it is.
+fn expand(value: &str, sources: &mut Vec<SecretSource>) -> Result<String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(at) = rest.find('$') {
+        expanded.push_str(&rest[..at]);
+        let after = &rest[at + 1..];
+        if let Some(after) = after.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+        } else if let Some(after) = after.strip_prefix('{') {
+            let end = after
+                .find('}')
+                .ok_or_else(|| anyhow!("`${{` without a closing `}}`"))?;
+            let name = &after[..end];
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                bail!("${{{}}} is not an environment variable name", name);
+            }
+            let variable = std::env::var(name)
+                .map_err(|_| anyhow!("${{{}}}: environment variable {} is not set", name, name))?;
+            expanded.push_str(&variable);
+            sources.push(SecretSource::Env(name.to_string()));
+            rest = &after[end + 1..];
+        } else {
+            expanded.push('$');
+            rest = after;
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
@@ -0,0 +1,319 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::models::{ApiMessage, Message, MessageRole};
+
+/// Prompt tokens the interactive CLI keeps in its history before dropping old turns.
+pub const DEFAULT_TOKEN_BUDGET: usize = 32_000;
+
+/// Times a request rejected as too long for the model is sent again with
+/// less history; see [`shed_oldest`].
+pub const SHED_ATTEMPTS: usize = 2;
+
+/// What happens when the API rejects a conversation as longer than the
+/// model's context window, which the token estimate can undercount.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextRetry {
+    /// The error is reported.
+    Off,
+    /// Sent again with the oldest history dropped.
+    #[default]
+    Auto,
+    /// Sent again with less history once confirmed.
+    Ask,
+}
+
+impl FromStr for ContextRetry {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "off" => Ok(ContextRetry::Off),
+            "auto" => Ok(ContextRetry::Auto),
+            "ask" => Ok(ContextRetry::Ask),
+            _ => Err(anyhow!(
+                "Unknown context retry mode '{}' (available: off, auto, ask)",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ContextRetry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ContextRetry::Off => "off",
+            ContextRetry::Auto => "auto",
+            ContextRetry::Ask => "ask",
+        })
+    }
+}
+
+/// Per-message framing overhead (role markers and separators) in tokens.
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// Rough token count: about four characters per token for English text. Good
+/// enough to keep requests clear of the context limit without a tokenizer.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+pub fn estimate_message_tokens(messages: &[ApiMessage]) -> usize {
+    messages
+        .iter()
+        .map(|message| estimate_tokens(&message.content) + MESSAGE_OVERHEAD_TOKENS)
+        .sum()
+}
+
+/// Drops the oldest non-system messages until `messages` fits in `budget`
+/// tokens, returning how many were removed. System messages and the final
+/// message are always kept, and the history never starts with an orphaned
+/// assistant reply.
+pub fn fit_to_budget(messages: &mut Vec<ApiMessage>, budget: usize) -> usize {
+    trim_to_budget(messages, budget).len()
+}
+
+/// [`fit_to_budget`], returning the removed messages, oldest first.
+pub fn trim_to_budget(messages: &mut Vec<ApiMessage>, budget: usize) -> Vec<ApiMessage> {
+    let mut dropped = Vec::new();
+
+    while estimate_message_tokens(messages) > budget {
+        let Some(oldest) = oldest_droppable(messages) else {
+            break;
+        };
+        dropped.push(messages.remove(oldest));
+
+        // Keep user/assistant turns paired
+        if let Some(next) = oldest_droppable(messages) {
+            if messages[next].role == "assistant" {
+                dropped.push(messages.remove(next));
+            }
+        }
+    }
+
+    dropped
+}
+
+/// The history to send for a saved session. Pinned messages are budgeted
+/// first and placed right after any system messages, however old; the rest
+/// of `budget` goes to the most recent unpinned turns, trimmed as in
+/// [`fit_to_budget`]. Queued messages have not been sent yet and are left out.
+pub fn session_context(messages: &[Message], budget: usize) -> Vec<ApiMessage> {
+    session_context_dropping(messages, budget).0
+}
+
+/// [`session_context`], along with the turns the budget left out.
+pub fn session_context_dropping(
+    messages: &[Message],
+    budget: usize,
+) -> (Vec<ApiMessage>, Vec<ApiMessage>) {
+    let (pinned, rest): (Vec<&Message>, Vec<&Message>) = messages
+        .iter()
+        .filter(|message| !message.is_queued())
+        .partition(|message| message.pinned && message.role != MessageRole::System);
+    let pinned: Vec<ApiMessage> = pinned.into_iter().map(ApiMessage::from).collect();
+    let mut context: Vec<ApiMessage> = rest.into_iter().map(ApiMessage::from).collect();
+
+    let remaining = budget.saturating_sub(estimate_message_tokens(&pinned));
+    let dropped = trim_to_budget(&mut context, remaining);
+
+    let after_system = context.iter().take_while(|m| m.is_system()).count();
+    context.splice(after_system..after_system, pinned);
+    (context, dropped)
+}
+
+/// How many messages [`session_context`] places after the system messages
+/// because they are pinned.
+pub fn pinned_count(messages: &[Message]) -> usize {
+    messages
+        .iter()
+        .filter(|message| {
+            !message.is_queued() && message.pinned && message.role != MessageRole::System
+        })
+        .count()
+}
+
+/// Drops the oldest fifth (at least one) of the messages that may go, for a
+/// request the API rejected as too long: system messages, the `pinned` ones
+/// right after them and the final message stay. Returns how many were
+/// removed; turns stay paired as in [`fit_to_budget`].
+pub fn shed_oldest(messages: &mut Vec<ApiMessage>, pinned: usize) -> usize {
+    let kept = messages.iter().take_while(|m| m.is_system()).count() + pinned;
+    let droppable = |messages: &[ApiMessage]| {
+        oldest_droppable(messages.get(kept..).unwrap_or_default()).map(|index| kept + index)
+    };
+    let last = messages.len().saturating_sub(1);
+    let candidates = messages
+        .get(kept..last)
+        .unwrap_or_default()
+        .iter()
+        .filter(|m| !m.is_system())
+        .count();
+    let mut removed = 0;
+    while removed < candidates.div_ceil(5) {
+        let Some(oldest) = droppable(messages) else {
+            break;
+        };
+        messages.remove(oldest);
+        removed += 1;
+    }
+    // An assistant reply whose question went goes too
+    if removed > 0 {
+        if let Some(next) = droppable(messages) {
+            if messages[next].role == "assistant" {
+                messages.remove(next);
+                removed += 1;
+            }
+        }
+    }
+    removed
+}
+
+fn oldest_droppable(messages: &[ApiMessage]) -> Option<usize> {
+    let last = messages.len().checked_sub(1)?;
+    messages[..last]
+        .iter()
+        .position(|message| message.role != "system")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: &str, content: &str) -> ApiMessage {
+        ApiMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+        assert_eq!(
+            estimate_message_tokens(&[message("user", "abcd"), message("assistant", "")]),
+            9
+        );
+    }
+
+    #[test]
+    fn test_fit_to_budget_drops_oldest_turns_first() {
+        let long = "x".repeat(400); // 100 tokens + overhead
+        let mut messages = vec![
+            message("system", "be brief"),
+            message("user", &long),
+            message("assistant", &long),
+            message("user", "recent question"),
+            message("assistant", "recent answer"),
+            message("user", "follow-up"),
+        ];
+
+        let dropped = fit_to_budget(&mut messages, 50);
+
+        assert_eq!(dropped, 2);
+        let roles: Vec<&str> = messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["system", "user", "assistant", "user"]);
+        assert_eq!(messages[1].content, "recent question");
+    }
+
+    #[test]
+    fn test_session_context_puts_pinned_messages_first() {
+        let long = "x".repeat(400);
+        let mut style = Message::user("s".to_string(), "Use British spelling".to_string());
+        style.pinned = true;
+        let messages = vec![
+            Message::system("s".to_string(), "be brief".to_string()),
+            Message::user("s".to_string(), long.clone()),
+            Message::assistant("s".to_string(), long, None),
+            style,
+            Message::user("s".to_string(), "recent question".to_string()),
+            Message::assistant("s".to_string(), "recent answer".to_string(), None),
+        ];
+
+        let (context, dropped) = session_context_dropping(&messages, 60);
+        assert_eq!(dropped.len(), 2);
+        assert_eq!(dropped[0].role, "user");
+
+        let contents: Vec<&str> = context.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(
+            contents,
+            [
+                "be brief",
+                "Use British spelling",
+                "recent question",
+                "recent answer"
+            ]
+        );
+
+        // Pinned messages stay even when they use up the whole budget
+        let context = session_context(&messages, 10);
+        assert_eq!(context[1].content, "Use British spelling");
+        assert_eq!(context.last().unwrap().content, "recent answer");
+    }
+
+    #[test]
+    fn test_seeded_examples_count_but_the_seeded_prompt_stays() {
+        let template: crate::session_templates::SessionTemplate = serde_json::from_str(
+            r#"{"name": "review", "system_prompt": "Review code.",
+                "messages": [{"role": "user", "content": "example question"},
+                             {"role": "assistant", "content": "example answer"}]}"#,
+        )
+        .unwrap();
+        let mut messages = template.seed("s");
+        messages.push(Message::user("s".to_string(), "x".repeat(200)));
+
+        let (context, dropped) = session_context_dropping(&messages, 60);
+        let contents: Vec<&str> = context.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["Review code.", "x".repeat(200).as_str()]);
+        assert_eq!(dropped.len(), 2);
+    }
+
+    #[test]
+    fn test_shedding_keeps_pinned_and_recent_messages() {
+        let mut messages = vec![message("system", "be brief"), message("user", "pinned")];
+        for turn in 0..6 {
+            messages.push(message("user", &format!("question {}", turn)));
+            messages.push(message("assistant", &format!("answer {}", turn)));
+        }
+        messages.push(message("user", "latest"));
+
+        // 12 may go, so 3 do, and the answer left without its question too
+        assert_eq!(shed_oldest(&mut messages, 1), 4);
+        let contents: Vec<&str> = messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(
+            contents,
+            [
+                "be brief",
+                "pinned",
+                "question 2",
+                "answer 2",
+                "question 3",
+                "answer 3",
+                "question 4",
+                "answer 4",
+                "question 5",
+                "answer 5",
+                "latest"
+            ]
+        );
+
+        let mut last = vec![message("system", "be brief"), message("user", "latest")];
+        assert_eq!(shed_oldest(&mut last, 0), 0);
+    }
+
+    #[test]
+    fn test_fit_to_budget_keeps_system_and_latest_message() {
+        let mut messages = vec![
+            message("system", &"s".repeat(400)),
+            message("user", &"u".repeat(400)),
+        ];
+
+        assert_eq!(fit_to_budget(&mut messages, 10), 0);
+        assert_eq!(messages.len(), 2);
+    }
+}
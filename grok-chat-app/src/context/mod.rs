@@ -0,0 +1,3 @@
+pub use self::context::*;
+#[allow(clippy::module_inception)]
+mod context;
@@ -1,21 +1,151 @@
-use anyhow::{anyhow, Result};
+use anyhow::{bail, Context, Result};
 use chrono::{DateTime, Utc};
-use sqlx::SqlitePool;
+use futures_util::{Stream, StreamExt};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use sqlx::{Connection, Row, SqliteConnection, SqlitePool};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
 
+use crate::audit::{Actor, AuditEntry, AuditFilter, AuditOp, AuditRecord};
+use crate::client::DEFAULT_PROVIDER;
 use crate::config::Config;
-use crate::models::{ChatSession, Message, MessageRole};
+use crate::dedupe::message_key;
+use crate::media::StoredMedia;
+use crate::models::{
+    Annotation, ChatSession, CompletionStats, Message, MessageActivity, MessageRole, MessageStatus,
+    ModelUsage, Rating, RestoreReport, SessionExport, SessionShare, SessionSnapshot,
+    SnapshotRemoved, UsageRecord, UsageStats,
+};
+use crate::presets::{PromptPreset, UnknownPreset};
+use crate::session_templates::{SessionTemplate, UnknownSessionTemplate};
+use crate::templates::PromptTemplate;
 
+use super::{WriteTransaction, BUSY_TIMEOUT};
+
+/// Narrows `Database::scan_messages`; unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct MessageFilter {
+    pub session_id: Option<String>,
+    pub role: Option<MessageRole>,
+    /// Text the content contains, ignoring ASCII case.
+    pub containing: Option<String>,
+}
+
+/// Queries shorter than this only match titles and models in
+/// `Database::search_sessions`; a few letters would hit most messages.
+pub const MIN_CONTENT_QUERY: usize = 4;
+
+/// A session found by `Database::search_sessions`.
+#[derive(Debug, Clone)]
+pub struct SessionMatch {
+    pub session: ChatSession,
+    /// The best matching message, when the content matched.
+    pub hit: Option<ContentHit>,
+}
+
+/// What identifies one state of a session's export: it changes whenever the
+/// session is touched or a message is added, removed or regenerated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionVersion {
+    pub updated_at: DateTime<Utc>,
+    /// Messages in the conversation, superseded generations left out.
+    pub messages: i64,
+    pub last_message_id: i64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentHit {
+    pub message_id: i64,
+    pub snippet: Snippet,
+}
+
+/// Text around a match, with the matched words marked.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snippet {
+    pub text: String,
+    /// Byte ranges of `text` that matched.
+    pub highlights: Vec<Range<usize>>,
+}
+
+// Around matched words in the snippets SQLite writes; control characters,
+// so they never occur in what people type
+const SNIPPET_OPEN: char = '\u{2}';
+const SNIPPET_CLOSE: char = '\u{3}';
+
+impl Snippet {
+    fn from_marked(marked: &str) -> Self {
+        let mut snippet = Snippet::default();
+        let mut start = None;
+        for c in marked.chars() {
+            match c {
+                SNIPPET_OPEN => start = Some(snippet.text.len()),
+                SNIPPET_CLOSE => {
+                    if let Some(start) = start.take() {
+                        snippet.highlights.push(start..snippet.text.len());
+                    }
+                }
+                c => snippet.text.push(c),
+            }
+        }
+        snippet
+    }
+}
+
+/// The database may be shared with other processes, such as `serve` and the
+/// terminal UI: it is kept in WAL mode, so readers never wait for writers,
+/// and writes wait their turn (see [`WriteTransaction`]).
+#[derive(Clone)]
 pub struct Database {
     pool: SqlitePool,
+    /// A connection that never writes, for [`Database::data_version`].
+    watcher: Arc<Mutex<SqliteConnection>>,
 }
 
 impl Database {
     pub async fn new(config: &Config) -> Result<Self> {
-        let pool = SqlitePool::connect(config.database_url()).await?;
+        // Create the database file on first use rather than failing
+        let options = SqliteConnectOptions::from_str(config.database_url())?
+            .create_if_missing(true)
+            .busy_timeout(BUSY_TIMEOUT);
+        let watcher = Arc::new(Mutex::new(SqliteConnection::connect_with(&options).await?));
+        // The tables are made on a pool of their own, so that every
+        // connection of the real one opens after the search index exists.
+        // The real one connects before this closes, which keeps an
+        // in-memory database alive. WAL mode stays with the file, so only
+        // this pool switches to it
+        let setup = Self {
+            pool: SqlitePool::connect_with(options.clone().journal_mode(SqliteJournalMode::Wal))
+                .await?,
+            watcher: watcher.clone(),
+        };
+        setup.init_tables().await?;
+        let pool = SqlitePoolOptions::new()
+            .after_connect(|conn, _| Box::pin(async move { open_search_index(conn).await }))
+            .connect_with(options)
+            .await?;
+        setup.pool.close().await;
+
+        Ok(Self { pool, watcher })
+    }
+
+    /// A number that changes whenever a change to the database is
+    /// committed, by this process or another sharing the file. Cheap enough
+    /// to poll on every tick of an event loop.
+    pub async fn data_version(&self) -> Result<i64> {
+        let mut watcher = self.watcher.lock().await;
+        let version = sqlx::query_scalar("PRAGMA data_version")
+            .fetch_one(&mut *watcher)
+            .await?;
+        Ok(version)
+    }
 
-        let db = Self { pool };
-        db.init_tables().await?;
-        Ok(db)
+    /// Runs a trivial query to prove the connection works.
+    pub async fn ping(&self) -> Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
     }
 
     async fn init_tables(&self) -> Result<()> {
@@ -27,7 +157,8 @@ impl Database {
                 created_at TEXT NOT NULL,
                 updated_at TEXT NOT NULL,
                 model TEXT NOT NULL,
-                title TEXT
+                title TEXT,
+                import_hash TEXT
             )
             "#,
         )
@@ -45,6 +176,144 @@ impl Database {
                 timestamp TEXT NOT NULL,
                 model TEXT,
                 tokens_used INTEGER,
+                pinned INTEGER NOT NULL DEFAULT 0,
+                status TEXT NOT NULL DEFAULT 'sent',
+                FOREIGN KEY (session_id) REFERENCES chat_sessions (id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+        self.add_column_if_missing("messages", "pinned", "INTEGER NOT NULL DEFAULT 0")
+            .await?;
+        // Messages written offline wait as 'queued' until the outbox sends them
+        self.add_column_if_missing("messages", "status", "TEXT NOT NULL DEFAULT 'sent'")
+            .await?;
+        // Follow-up requests a cut-off streamed reply took, when it took any
+        self.add_column_if_missing("messages", "resumes", "INTEGER")
+            .await?;
+        // The regenerated reply that replaced this one; NULL for current replies
+        self.add_column_if_missing("messages", "superseded_by", "INTEGER")
+            .await?;
+        // Few-shot examples copied from a session template
+        self.add_column_if_missing("messages", "seeded", "INTEGER NOT NULL DEFAULT 0")
+            .await?;
+        // Content hash of sessions brought in by `sessions import`
+        self.add_column_if_missing("chat_sessions", "import_hash", "TEXT")
+            .await?;
+        // Prompt preset the session uses; NULL for the default prompt
+        self.add_column_if_missing("chat_sessions", "prompt_preset", "TEXT")
+            .await?;
+        // Agent answering the session's messages when they name none
+        self.add_column_if_missing("chat_sessions", "agent", "TEXT")
+            .await?;
+        // Agent that produced a reply; NULL for replies without one
+        self.add_column_if_missing("messages", "agent", "TEXT")
+            .await?;
+        // Provider the model id names, kept in step with the model
+        let provider = format!(
+            "TEXT GENERATED ALWAYS AS (CASE WHEN model IS NULL THEN NULL \
+             WHEN instr(model, '/') > 0 THEN substr(model, 1, instr(model, '/') - 1) \
+             ELSE '{}' END) VIRTUAL",
+            DEFAULT_PROVIDER
+        );
+        self.add_column_if_missing("chat_sessions", "provider", &provider)
+            .await?;
+        self.add_column_if_missing("messages", "provider", &provider)
+            .await?;
+
+        // Create prompts table (named system prompt presets)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS prompts (
+                name TEXT PRIMARY KEY,
+                body TEXT NOT NULL,
+                is_default INTEGER NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create prompt_templates table
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS prompt_templates (
+                name TEXT PRIMARY KEY,
+                description TEXT,
+                body TEXT NOT NULL,
+                model TEXT,
+                temperature REAL,
+                max_tokens INTEGER,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create session_templates table (messages are a JSON array)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS session_templates (
+                name TEXT PRIMARY KEY,
+                description TEXT,
+                system_prompt TEXT,
+                messages TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create session_shares table (read-only links, at most one per session)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS session_shares (
+                token TEXT PRIMARY KEY,
+                session_id TEXT NOT NULL UNIQUE,
+                created_at TEXT NOT NULL,
+                expires_at TEXT,
+                FOREIGN KEY (session_id) REFERENCES chat_sessions (id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create session_snapshots table (points a session can be restored to)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS session_snapshots (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                session_id TEXT NOT NULL,
+                label TEXT NOT NULL,
+                message_id INTEGER NOT NULL,
+                created_at TEXT NOT NULL,
+                removed_by INTEGER,
+                FOREIGN KEY (session_id) REFERENCES chat_sessions (id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create restored_messages table: messages a restore took out of a
+        // session, as JSON with their annotation and attachments, kept until
+        // the session is deleted
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS restored_messages (
+                message_id INTEGER PRIMARY KEY,
+                session_id TEXT NOT NULL,
+                snapshot_id INTEGER NOT NULL,
+                restored_at TEXT NOT NULL,
+                message TEXT NOT NULL,
                 FOREIGN KEY (session_id) REFERENCES chat_sessions (id) ON DELETE CASCADE
             )
             "#,
@@ -52,12 +321,134 @@ impl Database {
         .execute(&self.pool)
         .await?;
 
+        // Create usage_ledger table (one row per upstream completion)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS usage_ledger (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                session_id TEXT,
+                model TEXT NOT NULL,
+                prompt_tokens INTEGER NOT NULL,
+                completion_tokens INTEGER NOT NULL,
+                created_at TEXT NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+        // Upstream latency and finish reason, for `report`; NULL on older rows
+        self.add_column_if_missing("usage_ledger", "latency_ms", "INTEGER")
+            .await?;
+        self.add_column_if_missing("usage_ledger", "finish_reason", "TEXT")
+            .await?;
+
+        // Create attachments table; the bytes live in the media store, by hash
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS attachments (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                message_id INTEGER NOT NULL,
+                hash TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                mime_type TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                FOREIGN KEY (message_id) REFERENCES messages (id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create annotations table; at most one per message
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS annotations (
+                message_id INTEGER PRIMARY KEY,
+                rating TEXT,
+                note TEXT,
+                labels TEXT NOT NULL DEFAULT '[]',
+                updated_at TEXT NOT NULL,
+                FOREIGN KEY (message_id) REFERENCES messages (id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create the audit log. No foreign keys: entries outlive what they
+        // name, and the triggers keep them from being changed or removed
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp TEXT NOT NULL,
+                actor TEXT NOT NULL,
+                operation TEXT NOT NULL,
+                session_id TEXT,
+                message_id INTEGER,
+                detail TEXT,
+                request_id TEXT
+            );
+            CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+            BEGIN
+                SELECT RAISE(ABORT, 'The audit log is append-only');
+            END;
+            CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log
+            BEGIN
+                SELECT RAISE(ABORT, 'The audit log is append-only');
+            END;
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Full-text index of message contents, kept in step by triggers.
+        // Filled from the messages already there when it is first created
+        let indexed = sqlx::query("SELECT 1 FROM sqlite_master WHERE name = 'messages_fts'")
+            .fetch_optional(&self.pool)
+            .await?
+            .is_some();
+        sqlx::query(
+            r#"
+            CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts
+                USING fts5(content, content = 'messages', content_rowid = 'id');
+            CREATE TRIGGER IF NOT EXISTS messages_fts_insert AFTER INSERT ON messages
+            BEGIN
+                INSERT INTO messages_fts (rowid, content) VALUES (new.id, new.content);
+            END;
+            CREATE TRIGGER IF NOT EXISTS messages_fts_delete AFTER DELETE ON messages
+            BEGIN
+                INSERT INTO messages_fts (messages_fts, rowid, content)
+                VALUES ('delete', old.id, old.content);
+            END;
+            CREATE TRIGGER IF NOT EXISTS messages_fts_update AFTER UPDATE OF content ON messages
+            BEGIN
+                INSERT INTO messages_fts (messages_fts, rowid, content)
+                VALUES ('delete', old.id, old.content);
+                INSERT INTO messages_fts (rowid, content) VALUES (new.id, new.content);
+            END;
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+        if !indexed {
+            sqlx::query("INSERT INTO messages_fts (messages_fts) VALUES ('rebuild')")
+                .execute(&self.pool)
+                .await?;
+        }
+
         // Create indexes for better performance
         sqlx::query(
             r#"
+            CREATE INDEX IF NOT EXISTS idx_audit_log_timestamp ON audit_log(timestamp);
             CREATE INDEX IF NOT EXISTS idx_messages_session_id ON messages(session_id);
             CREATE INDEX IF NOT EXISTS idx_messages_timestamp ON messages(timestamp);
             CREATE INDEX IF NOT EXISTS idx_chat_sessions_created_at ON chat_sessions(created_at);
+            CREATE INDEX IF NOT EXISTS idx_usage_ledger_model ON usage_ledger(model);
+            CREATE INDEX IF NOT EXISTS idx_chat_sessions_import_hash ON chat_sessions(import_hash);
+            CREATE INDEX IF NOT EXISTS idx_attachments_message_id ON attachments(message_id);
+            CREATE INDEX IF NOT EXISTS idx_attachments_hash ON attachments(hash);
+            CREATE INDEX IF NOT EXISTS idx_session_snapshots_session_id ON session_snapshots(session_id);
             "#,
         )
         .execute(&self.pool)
@@ -66,13 +457,61 @@ impl Database {
         Ok(())
     }
 
+    /// Upgrades tables created by older versions, which `CREATE TABLE IF NOT
+    /// EXISTS` leaves alone.
+    async fn add_column_if_missing(
+        &self,
+        table: &str,
+        column: &str,
+        definition: &str,
+    ) -> Result<()> {
+        // table_info leaves out generated columns
+        let columns = sqlx::query(&format!("PRAGMA table_xinfo({})", table))
+            .fetch_all(&self.pool)
+            .await?;
+        if columns
+            .iter()
+            .any(|row| row.get::<String, _>("name") == column)
+        {
+            return Ok(());
+        }
+
+        sqlx::query(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
     pub async fn create_session(&self, mut session: ChatSession) -> Result<ChatSession> {
         session.update_timestamp();
+        Self::insert_session(&mut *self.pool.acquire().await?, &session).await?;
+        Ok(session)
+    }
+
+    /// [`Database::create_session`], logged as done by `actor` in the same
+    /// transaction.
+    pub async fn create_session_audited(
+        &self,
+        mut session: ChatSession,
+        actor: &Actor,
+    ) -> Result<ChatSession> {
+        session.update_timestamp();
+        let mut tx = WriteTransaction::begin(&self.pool).await?;
+        Self::insert_session(&mut tx, &session).await?;
+        let record = AuditRecord::new(AuditOp::SessionCreate).session(&session.id);
+        Self::store_audit(&mut tx, actor, &record).await?;
+        tx.commit().await?;
+        Ok(session)
+    }
 
+    async fn insert_session(conn: &mut SqliteConnection, session: &ChatSession) -> Result<()> {
         sqlx::query(
             r#"
-            INSERT INTO chat_sessions (id, created_at, updated_at, model, title)
-            VALUES (?, ?, ?, ?, ?)
+            INSERT INTO chat_sessions (id, created_at, updated_at, model, title, prompt_preset, agent)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
             "#,
         )
         .bind(&session.id)
@@ -80,16 +519,174 @@ impl Database {
         .bind(session.updated_at.to_rfc3339())
         .bind(&session.model)
         .bind(&session.title)
-        .execute(&self.pool)
+        .bind(&session.prompt_preset)
+        .bind(&session.agent)
+        .execute(&mut *conn)
         .await?;
+        Ok(())
+    }
 
-        Ok(session)
+    /// Stores an imported session with its messages and usage in one
+    /// transaction, keeping their timestamps as given.
+    pub async fn import_session(
+        &self,
+        session: &ChatSession,
+        messages: &[Message],
+        usage: &[UsageRecord],
+        import_hash: Option<&str>,
+    ) -> Result<()> {
+        let mut tx = WriteTransaction::begin(&self.pool).await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO chat_sessions
+                (id, created_at, updated_at, model, title, import_hash, prompt_preset, agent)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&session.id)
+        .bind(session.created_at.to_rfc3339())
+        .bind(session.updated_at.to_rfc3339())
+        .bind(&session.model)
+        .bind(&session.title)
+        .bind(import_hash)
+        .bind(&session.prompt_preset)
+        .bind(&session.agent)
+        .execute(&mut *tx)
+        .await?;
+
+        for message in messages {
+            if let MessageRole::Other(role) = &message.role {
+                bail!("Cannot save a message with unknown role '{}'", role);
+            }
+            let inserted = sqlx::query(
+                r#"
+                INSERT INTO messages
+                    (session_id, role, content, timestamp, model, tokens_used, pinned, status, resumes, seeded,
+                     agent)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(&session.id)
+            .bind(message.role.to_string())
+            .bind(&message.content)
+            .bind(message.timestamp.to_rfc3339())
+            .bind(&message.model)
+            .bind(message.tokens_used)
+            .bind(message.pinned)
+            .bind(message.status.as_str())
+            .bind(message.resumes)
+            .bind(message.seeded)
+            .bind(&message.agent)
+            .execute(&mut *tx)
+            .await?;
+            if let Some(annotation) = &message.annotation {
+                Self::store_annotation(&mut tx, inserted.last_insert_rowid(), annotation).await?;
+            }
+        }
+
+        for record in usage {
+            sqlx::query(
+                r#"
+                INSERT INTO usage_ledger
+                    (session_id, model, prompt_tokens, completion_tokens, created_at, latency_ms, finish_reason)
+                VALUES (?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(&session.id)
+            .bind(&record.model)
+            .bind(record.prompt_tokens)
+            .bind(record.completion_tokens)
+            .bind(record.created_at.to_rfc3339())
+            .bind(record.latency_ms.map(|ms| ms as i64))
+            .bind(&record.finish_reason)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Stores messages recovered from a crash journal, creating the session
+    /// if needed. Messages already stored (same role, timestamp and content)
+    /// are skipped, so an interrupted recovery can simply run again. Returns
+    /// how many messages were added.
+    pub async fn recover_session(
+        &self,
+        session: &ChatSession,
+        messages: &[Message],
+    ) -> Result<u64> {
+        let mut tx = WriteTransaction::begin(&self.pool).await?;
+
+        sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO chat_sessions
+                (id, created_at, updated_at, model, title, prompt_preset, agent)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&session.id)
+        .bind(session.created_at.to_rfc3339())
+        .bind(session.updated_at.to_rfc3339())
+        .bind(&session.model)
+        .bind(&session.title)
+        .bind(&session.prompt_preset)
+        .bind(&session.agent)
+        .execute(&mut *tx)
+        .await?;
+
+        let mut added = 0;
+        for message in messages {
+            if let MessageRole::Other(role) = &message.role {
+                bail!("Cannot save a message with unknown role '{}'", role);
+            }
+            let result = sqlx::query(
+                r#"
+                INSERT INTO messages
+                    (session_id, role, content, timestamp, model, tokens_used, pinned, status, resumes, seeded,
+                     agent)
+                SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11
+                WHERE NOT EXISTS (
+                    SELECT 1 FROM messages
+                    WHERE session_id = ?1 AND role = ?2 AND content = ?3 AND timestamp = ?4
+                )
+                "#,
+            )
+            .bind(&session.id)
+            .bind(message.role.to_string())
+            .bind(&message.content)
+            .bind(message.timestamp.to_rfc3339())
+            .bind(&message.model)
+            .bind(message.tokens_used)
+            .bind(message.pinned)
+            .bind(message.status.as_str())
+            .bind(message.resumes)
+            .bind(message.seeded)
+            .bind(&message.agent)
+            .execute(&mut *tx)
+            .await?;
+            added += result.rows_affected();
+        }
+
+        tx.commit().await?;
+        Ok(added)
+    }
+
+    /// The session an earlier import with this content hash created.
+    pub async fn find_import(&self, import_hash: &str) -> Result<Option<String>> {
+        let row = sqlx::query("SELECT id FROM chat_sessions WHERE import_hash = ?")
+            .bind(import_hash)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|row| row.get::<String, _>(0)))
     }
 
     pub async fn get_session(&self, session_id: &str) -> Result<Option<ChatSession>> {
         let row = sqlx::query(
             r#"
-            SELECT id, created_at, updated_at, model, title
+            SELECT id, created_at, updated_at, model, title, prompt_preset, agent
             FROM chat_sessions
             WHERE id = ?
             "#,
@@ -98,19 +695,7 @@ impl Database {
         .fetch_optional(&self.pool)
         .await?;
 
-        if let Some(row) = row {
-            Ok(Some(ChatSession {
-                id: row.get::<String, _>(0),
-                created_at: DateTime::parse_from_rfc3339(&row.get::<String, _>(1))?
-                    .with_timezone(&Utc),
-                updated_at: DateTime::parse_from_rfc3339(&row.get::<String, _>(2))?
-                    .with_timezone(&Utc),
-                model: row.get::<String, _>(3),
-                title: row.get::<Option<String>, _>(4),
-            }))
-        } else {
-            Ok(None)
-        }
+        row.as_ref().map(Self::session_from_row).transpose()
     }
 
     pub async fn list_sessions(&self, limit: Option<i64>, offset: Option<i64>) -> Result<Vec<ChatSession>> {
@@ -119,7 +704,7 @@ impl Database {
 
         let rows = sqlx::query(
             r#"
-            SELECT id, created_at, updated_at, model, title
+            SELECT id, created_at, updated_at, model, title, prompt_preset, agent
             FROM chat_sessions
             ORDER BY updated_at DESC
             LIMIT ? OFFSET ?
@@ -130,40 +715,123 @@ impl Database {
         .fetch_all(&self.pool)
         .await?;
 
-        let mut sessions = Vec::new();
-        for row in rows {
-            sessions.push(ChatSession {
-                id: row.get::<String, _>(0),
-                created_at: DateTime::parse_from_rfc3339(&row.get::<String, _>(1))?
-                    .with_timezone(&Utc),
-                updated_at: DateTime::parse_from_rfc3339(&row.get::<String, _>(2))?
-                    .with_timezone(&Utc),
-                model: row.get::<String, _>(3),
-                title: row.get::<Option<String>, _>(4),
-            });
-        }
+        rows.iter().map(Self::session_from_row).collect()
+    }
+
+    /// Every session, or those active since `since`, oldest first.
+    pub async fn sessions_updated_since(
+        &self,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<Vec<ChatSession>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, created_at, updated_at, model, title, prompt_preset, agent
+            FROM chat_sessions
+            WHERE ? IS NULL OR updated_at >= ?
+            ORDER BY created_at ASC
+            "#,
+        )
+        .bind(since.map(|since| since.to_rfc3339()))
+        .bind(since.map(|since| since.to_rfc3339()))
+        .fetch_all(&self.pool)
+        .await?;
 
-        Ok(sessions)
+        rows.iter().map(Self::session_from_row).collect()
     }
 
-    pub async fn update_session(&self, session_id: &str, title: Option<String>) -> Result<()> {
-        let updated_at = Utc::now().to_rfc3339();
+    /// Sessions whose last activity is older than `cutoff`, oldest first.
+    pub async fn sessions_updated_before(&self, cutoff: DateTime<Utc>) -> Result<Vec<ChatSession>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, created_at, updated_at, model, title, prompt_preset, agent
+            FROM chat_sessions
+            WHERE updated_at < ?
+            ORDER BY updated_at ASC
+            "#,
+        )
+        .bind(cutoff.to_rfc3339())
+        .fetch_all(&self.pool)
+        .await?;
 
-        if let Some(title) = title {
-            sqlx::query(
-                r#"
-                UPDATE chat_sessions
-                SET updated_at = ?, title = ?
-                WHERE id = ?
-                "#,
-            )
-            .bind(updated_at)
-            .bind(title)
+        rows.iter().map(Self::session_from_row).collect()
+    }
+
+    fn session_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<ChatSession> {
+        Ok(ChatSession {
+            id: row.get::<String, _>(0),
+            created_at: DateTime::parse_from_rfc3339(&row.get::<String, _>(1))?.with_timezone(&Utc),
+            updated_at: DateTime::parse_from_rfc3339(&row.get::<String, _>(2))?.with_timezone(&Utc),
+            model: row.get::<String, _>(3),
+            title: row.get::<Option<String>, _>(4),
+            prompt_preset: row.get::<Option<String>, _>(5),
+            agent: row.get::<Option<String>, _>(6),
+        })
+    }
+
+    /// Makes the session use the preset `name`, or the default prompt for `None`.
+    pub async fn set_session_prompt(&self, session_id: &str, name: Option<&str>) -> Result<()> {
+        sqlx::query("UPDATE chat_sessions SET prompt_preset = ? WHERE id = ?")
+            .bind(name)
             .bind(session_id)
             .execute(&self.pool)
             .await?;
-        } else {
-            sqlx::query(
+        Ok(())
+    }
+
+    /// Makes `name` the session's default agent, or clears it for `None`.
+    pub async fn set_session_agent(&self, session_id: &str, name: Option<&str>) -> Result<()> {
+        sqlx::query("UPDATE chat_sessions SET agent = ? WHERE id = ?")
+            .bind(name)
+            .bind(session_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// The preset a session's messages are sent with: its own, else the
+    /// default preset. `None` means the configured system prompt.
+    pub async fn session_prompt(&self, session_id: &str) -> Result<Option<PromptPreset>> {
+        let chosen = match self.get_session(session_id).await? {
+            Some(ChatSession {
+                prompt_preset: Some(name),
+                ..
+            }) => self.get_prompt(&name).await?,
+            _ => None,
+        };
+        if chosen.is_some() {
+            return Ok(chosen);
+        }
+        // A session template's system prompt wins over the default preset
+        let seeded = sqlx::query(
+            "SELECT 1 FROM messages WHERE session_id = ? AND role = 'system' AND seeded = 1",
+        )
+        .bind(session_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        if seeded.is_some() {
+            return Ok(None);
+        }
+        self.default_prompt().await
+    }
+
+    pub async fn update_session(&self, session_id: &str, title: Option<String>) -> Result<()> {
+        let updated_at = Utc::now().to_rfc3339();
+
+        if let Some(title) = title {
+            sqlx::query(
+                r#"
+                UPDATE chat_sessions
+                SET updated_at = ?, title = ?
+                WHERE id = ?
+                "#,
+            )
+            .bind(updated_at)
+            .bind(title)
+            .bind(session_id)
+            .execute(&self.pool)
+            .await?;
+        } else {
+            sqlx::query(
                 r#"
                 UPDATE chat_sessions
                 SET updated_at = ?
@@ -179,89 +847,1946 @@ impl Database {
         Ok(())
     }
 
-    pub async fn delete_session(&self, session_id: &str) -> Result<()> {
-        sqlx::query("DELETE FROM chat_sessions WHERE id = ?")
-            .bind(session_id)
-            .execute(&self.pool)
+    pub async fn delete_session(&self, session_id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM chat_sessions WHERE id = ?")
+            .bind(session_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Deletes a session and logs it as done by `actor` in the same
+    /// transaction, returning whether it existed. Nothing is logged for a
+    /// session that did not.
+    pub async fn delete_session_audited(&self, session_id: &str, actor: &Actor) -> Result<bool> {
+        let mut tx = WriteTransaction::begin(&self.pool).await?;
+        let deleted = sqlx::query("DELETE FROM chat_sessions WHERE id = ?")
+            .bind(session_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+            > 0;
+        if deleted {
+            let record = AuditRecord::new(AuditOp::SessionDelete).session(session_id);
+            Self::store_audit(&mut tx, actor, &record).await?;
+            tx.commit().await?;
+        }
+        Ok(deleted)
+    }
+
+    /// Moves the sessions on model `from` to model `to`: all of them, or
+    /// only `session_id`. Each move is logged as done by `actor`. Returns the
+    /// ids of the sessions moved.
+    pub async fn migrate_model(
+        &self,
+        from: &str,
+        to: &str,
+        session_id: Option<&str>,
+        actor: &Actor,
+    ) -> Result<Vec<String>> {
+        let mut tx = WriteTransaction::begin(&self.pool).await?;
+        // Updated and listed in one statement, so the transaction starts
+        // out writing
+        let migrated: Vec<String> = sqlx::query_scalar(
+            "UPDATE chat_sessions SET model = ? WHERE model = ? AND (? IS NULL OR id = ?) \
+             RETURNING id",
+        )
+        .bind(to)
+        .bind(from)
+        .bind(session_id)
+        .bind(session_id)
+        .fetch_all(&mut *tx)
+        .await?;
+        let detail = format!("{} -> {}", from, to);
+        for id in &migrated {
+            let record = AuditRecord::new(AuditOp::SessionMigrateModel)
+                .session(id)
+                .detail(detail.clone());
+            Self::store_audit(&mut tx, actor, &record).await?;
+        }
+        tx.commit().await?;
+        Ok(migrated)
+    }
+
+    pub async fn create_message(&self, mut message: Message) -> Result<Message> {
+        message.id = Self::insert_message(&mut *self.pool.acquire().await?, &message).await?;
+        Ok(message)
+    }
+
+    /// [`Database::create_message`], logged as done by `actor` in the same
+    /// transaction.
+    pub async fn create_message_audited(
+        &self,
+        mut message: Message,
+        actor: &Actor,
+    ) -> Result<Message> {
+        let mut tx = WriteTransaction::begin(&self.pool).await?;
+        message.id = Self::insert_message(&mut tx, &message).await?;
+        let record = AuditRecord::new(AuditOp::MessageCreate)
+            .session(&message.session_id)
+            .message(message.id)
+            .detail(message.role.to_string());
+        Self::store_audit(&mut tx, actor, &record).await?;
+        tx.commit().await?;
+        Ok(message)
+    }
+
+    /// Stores `message`, returning its new id.
+    async fn insert_message(conn: &mut SqliteConnection, message: &Message) -> Result<i64> {
+        // The read path rejects roles it cannot parse, so never store one
+        if let MessageRole::Other(role) = &message.role {
+            bail!("Cannot save a message with unknown role '{}'", role);
+        }
+
+        let result = sqlx::query(
+            r#"
+            INSERT INTO messages
+                (session_id, role, content, timestamp, model, tokens_used, pinned, status, resumes, seeded,
+                     agent)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&message.session_id)
+        .bind(message.role.to_string())
+        .bind(&message.content)
+        .bind(message.timestamp.to_rfc3339())
+        .bind(&message.model)
+        .bind(message.tokens_used)
+        .bind(message.pinned)
+        .bind(message.status.as_str())
+            .bind(message.resumes)
+            .bind(message.seeded)
+            .bind(&message.agent)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    /// The session's conversation, without replies replaced by regenerating.
+    pub async fn get_messages(&self, session_id: &str) -> Result<Vec<Message>> {
+        self.session_messages(session_id, false).await
+    }
+
+    /// Like `get_messages`, with the kept earlier generations of regenerated
+    /// replies as well, each just before the reply that replaced it.
+    pub async fn get_messages_with_superseded(&self, session_id: &str) -> Result<Vec<Message>> {
+        self.session_messages(session_id, true).await
+    }
+
+    async fn session_messages(
+        &self,
+        session_id: &str,
+        include_superseded: bool,
+    ) -> Result<Vec<Message>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, role, content, timestamp, model, tokens_used, pinned, status, resumes,
+                   superseded_by, seeded, agent
+            FROM messages
+            WHERE session_id = ? AND (? OR superseded_by IS NULL)
+            ORDER BY timestamp ASC, id ASC
+            "#,
+        )
+        .bind(session_id)
+        .bind(include_superseded)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut messages = rows
+            .iter()
+            .map(Self::message_from_row)
+            .collect::<Result<Vec<_>>>()?;
+
+        let rows = sqlx::query(
+            r#"
+            SELECT a.message_id, a.rating, a.note, a.labels
+            FROM annotations a JOIN messages m ON m.id = a.message_id
+            WHERE m.session_id = ?
+            "#,
+        )
+        .bind(session_id)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut annotations = rows
+            .iter()
+            .map(|row| Ok((row.get::<i64, _>(0), Self::annotation_from_row(row)?)))
+            .collect::<Result<HashMap<_, _>>>()?;
+        for message in &mut messages {
+            message.annotation = annotations.remove(&message.id);
+        }
+        Ok(messages)
+    }
+
+    /// Up to `limit` of the session's conversation after `after`, in the order
+    /// of `get_messages`, for reading a long session a page at a time. The
+    /// messages a session template seeded are left out unless
+    /// `include_seeded`.
+    pub async fn messages_page(
+        &self,
+        session_id: &str,
+        after: Option<&Message>,
+        limit: i64,
+        include_seeded: bool,
+    ) -> Result<Vec<Message>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, role, content, timestamp, model, tokens_used, pinned, status, resumes,
+                   superseded_by, seeded, agent
+            FROM messages
+            WHERE session_id = ?1 AND superseded_by IS NULL AND (?5 OR seeded = 0)
+              AND (?2 IS NULL OR timestamp > ?2 OR (timestamp = ?2 AND id > ?3))
+            ORDER BY timestamp ASC, id ASC
+            LIMIT ?4
+            "#,
+        )
+        .bind(session_id)
+        .bind(after.map(|message| message.timestamp.to_rfc3339()))
+        .bind(after.map_or(0, |message| message.id))
+        .bind(limit)
+        .bind(include_seeded)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut messages = rows
+            .iter()
+            .map(Self::message_from_row)
+            .collect::<Result<Vec<_>>>()?;
+        let (Some(first), Some(last)) = (
+            messages.iter().map(|m| m.id).min(),
+            messages.iter().map(|m| m.id).max(),
+        ) else {
+            return Ok(messages);
+        };
+
+        let rows = sqlx::query(
+            r#"
+            SELECT a.message_id, a.rating, a.note, a.labels
+            FROM annotations a JOIN messages m ON m.id = a.message_id
+            WHERE m.session_id = ? AND a.message_id BETWEEN ? AND ?
+            "#,
+        )
+        .bind(session_id)
+        .bind(first)
+        .bind(last)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut annotations = rows
+            .iter()
+            .map(|row| Ok((row.get::<i64, _>(0), Self::annotation_from_row(row)?)))
+            .collect::<Result<HashMap<_, _>>>()?;
+        for message in &mut messages {
+            message.annotation = annotations.remove(&message.id);
+        }
+        Ok(messages)
+    }
+
+    /// `None` for an unknown session.
+    pub async fn session_version(&self, session_id: &str) -> Result<Option<SessionVersion>> {
+        let row = sqlx::query(
+            r#"
+            SELECT s.updated_at, COUNT(m.id), COALESCE(MAX(m.id), 0)
+            FROM chat_sessions s
+            LEFT JOIN messages m ON m.session_id = s.id AND m.superseded_by IS NULL
+            WHERE s.id = ?
+            GROUP BY s.id
+            "#,
+        )
+        .bind(session_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        row.map(|row| {
+            Ok(SessionVersion {
+                updated_at: DateTime::parse_from_rfc3339(&row.get::<String, _>(0))?
+                    .with_timezone(&Utc),
+                messages: row.get(1),
+                last_message_id: row.get(2),
+            })
+        })
+        .transpose()
+    }
+
+    /// Stores `replacement` as the regenerated version of `previous`, in one
+    /// transaction. `previous` and the generations it had replaced point at
+    /// the new reply, and all but the newest `keep` of them are deleted.
+    pub async fn supersede_message(
+        &self,
+        previous: &Message,
+        mut replacement: Message,
+        keep: usize,
+        actor: &Actor,
+    ) -> Result<Message> {
+        if let MessageRole::Other(role) = &replacement.role {
+            bail!("Cannot save a message with unknown role '{}'", role);
+        }
+        let mut tx = WriteTransaction::begin(&self.pool).await?;
+
+        let result = sqlx::query(
+            r#"
+            INSERT INTO messages (session_id, role, content, timestamp, model, tokens_used, pinned, status, resumes)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&replacement.session_id)
+        .bind(replacement.role.to_string())
+        .bind(&replacement.content)
+        .bind(replacement.timestamp.to_rfc3339())
+        .bind(&replacement.model)
+        .bind(replacement.tokens_used)
+        .bind(replacement.pinned)
+        .bind(replacement.status.as_str())
+        .bind(replacement.resumes)
+        .execute(&mut *tx)
+        .await?;
+        replacement.id = result.last_insert_rowid();
+
+        sqlx::query("UPDATE messages SET superseded_by = ? WHERE id = ? OR superseded_by = ?")
+            .bind(replacement.id)
+            .bind(previous.id)
+            .bind(previous.id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM messages
+            WHERE superseded_by = ?1 AND id NOT IN (
+                SELECT id FROM messages WHERE superseded_by = ?1 ORDER BY id DESC LIMIT ?2
+            )
+            "#,
+        )
+        .bind(replacement.id)
+        .bind(keep as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        let record = AuditRecord::new(AuditOp::MessageCreate)
+            .session(&replacement.session_id)
+            .message(replacement.id)
+            .detail(format!("regenerated {}", previous.id));
+        Self::store_audit(&mut tx, actor, &record).await?;
+
+        tx.commit().await?;
+        Ok(replacement)
+    }
+
+    /// Makes `kept`, a generation that `replaced` superseded, the reply
+    /// again, in one transaction. `replaced` is kept as a generation rather
+    /// than deleted, and the other generations point at `kept` instead.
+    /// Returns false if `kept` is no longer stored as a generation of it.
+    pub async fn reinstate_message(
+        &self,
+        session_id: &str,
+        kept: i64,
+        replaced: i64,
+    ) -> Result<bool> {
+        let mut tx = WriteTransaction::begin(&self.pool).await?;
+
+        let found: Option<i64> = sqlx::query_scalar(
+            "SELECT id FROM messages WHERE session_id = ? AND id = ? AND superseded_by = ?",
+        )
+        .bind(session_id)
+        .bind(kept)
+        .bind(replaced)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if found.is_none() {
+            return Ok(false);
+        }
+        sqlx::query(
+            "UPDATE messages SET superseded_by = CASE WHEN id = ?1 THEN NULL ELSE ?1 END \
+             WHERE session_id = ?3 AND (id = ?2 OR superseded_by = ?2)",
+        )
+        .bind(kept)
+        .bind(replaced)
+        .bind(session_id)
+        .execute(&mut *tx)
+        .await?;
+        Self::touch_session(&mut tx, session_id).await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// The kept earlier generations of a reply, oldest first.
+    pub async fn previous_generations(
+        &self,
+        session_id: &str,
+        message_id: i64,
+    ) -> Result<Vec<Message>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, role, content, timestamp, model, tokens_used, pinned, status, resumes,
+                   superseded_by, seeded, agent
+            FROM messages
+            WHERE session_id = ? AND superseded_by = ?
+            ORDER BY id ASC
+            "#,
+        )
+        .bind(session_id)
+        .bind(message_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(Self::message_from_row).collect()
+    }
+
+    /// Messages containing `query` (ignoring ASCII case), newest first.
+    pub async fn search_messages(&self, query: &str, limit: i64) -> Result<Vec<Message>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, role, content, timestamp, model, tokens_used, pinned, status, resumes,
+                   superseded_by, seeded, agent
+            FROM messages
+            WHERE content LIKE ? ESCAPE '\' AND superseded_by IS NULL
+            ORDER BY timestamp DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(like_pattern(query))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(Self::message_from_row).collect()
+    }
+
+    /// Sessions whose title or model contains `query`, most recently updated
+    /// first, then those with a message matching it, best match first.
+    /// Content is only searched for queries of at least
+    /// [`MIN_CONTENT_QUERY`] characters; each word then matches words it
+    /// begins. A session found both ways keeps its place and gains the hit.
+    pub async fn search_sessions(&self, query: &str, limit: i64) -> Result<Vec<SessionMatch>> {
+        let query = query.trim();
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+        let rows = sqlx::query(
+            r#"
+            SELECT id, created_at, updated_at, model, title, prompt_preset, agent
+            FROM chat_sessions
+            WHERE title LIKE ?1 ESCAPE '\' OR model LIKE ?1 ESCAPE '\'
+            ORDER BY updated_at DESC
+            LIMIT ?2
+            "#,
+        )
+        .bind(like_pattern(query))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut matches = rows
+            .iter()
+            .map(|row| {
+                Ok(SessionMatch {
+                    session: Self::session_from_row(row)?,
+                    hit: None,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if query.chars().count() < MIN_CONTENT_QUERY {
+            return Ok(matches);
+        }
+
+        // Several hits per session are likely, so read more than `limit`
+        // to fill it with distinct sessions
+        let rows = sqlx::query(
+            r#"
+            SELECT s.id, s.created_at, s.updated_at, s.model, s.title, s.prompt_preset, s.agent,
+                   m.id, snippet(messages_fts, 0, char(2), char(3), '…', 12)
+            FROM messages_fts
+            JOIN messages m ON m.id = messages_fts.rowid
+            JOIN chat_sessions s ON s.id = m.session_id
+            WHERE messages_fts MATCH ? AND m.superseded_by IS NULL
+            ORDER BY messages_fts.rank
+            LIMIT ?
+            "#,
+        )
+        .bind(fts_query(query))
+        .bind(limit.saturating_mul(10))
+        .fetch_all(&self.pool)
+        .await?;
+        let mut seen: HashMap<String, usize> = matches
+            .iter()
+            .enumerate()
+            .map(|(index, found)| (found.session.id.clone(), index))
+            .collect();
+        for row in &rows {
+            let hit = ContentHit {
+                message_id: row.get(7),
+                snippet: Snippet::from_marked(&row.get::<String, _>(8)),
+            };
+            let session_id: String = row.get(0);
+            match seen.get(&session_id) {
+                Some(&index) => {
+                    matches[index].hit.get_or_insert(hit);
+                }
+                None if (matches.len() as i64) < limit => {
+                    seen.insert(session_id, matches.len());
+                    matches.push(SessionMatch {
+                        session: Self::session_from_row(row)?,
+                        hit: Some(hit),
+                    });
+                }
+                None => {}
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Messages passing `filter`, read as the stream is polled rather than
+    /// all at once. Sessions come most recently updated first, each with its
+    /// messages in order.
+    pub fn scan_messages(&self, filter: MessageFilter) -> impl Stream<Item = Result<Message>> + '_ {
+        sqlx::query(
+            r#"
+            SELECT m.id, m.session_id, m.role, m.content, m.timestamp, m.model, m.tokens_used,
+                   m.pinned, m.status, m.resumes, m.superseded_by,
+                   m.seeded, m.agent
+            FROM messages m
+            JOIN chat_sessions s ON s.id = m.session_id
+            WHERE m.superseded_by IS NULL
+              AND (?1 IS NULL OR m.session_id = ?1)
+              AND (?2 IS NULL OR m.role = ?2)
+              AND (?3 IS NULL OR m.content LIKE ?3 ESCAPE '\')
+            ORDER BY s.updated_at DESC, m.session_id, m.id
+            "#,
+        )
+        .bind(filter.session_id)
+        .bind(filter.role.map(|role| role.to_string()))
+        .bind(filter.containing.as_deref().map(like_pattern))
+        .fetch(&self.pool)
+        .map(|row| Self::message_from_row(&row?))
+    }
+
+    /// Pins or unpins a message, flipping its flag when `pinned` is `None`.
+    /// Returns the updated message, or `None` if the session has no such message.
+    pub async fn set_message_pinned(
+        &self,
+        session_id: &str,
+        message_id: i64,
+        pinned: Option<bool>,
+    ) -> Result<Option<Message>> {
+        let mut tx = WriteTransaction::begin(&self.pool).await?;
+        let row = sqlx::query(
+            r#"
+            UPDATE messages
+            SET pinned = COALESCE(?, NOT pinned)
+            WHERE session_id = ? AND id = ?
+            RETURNING id, session_id, role, content, timestamp, model, tokens_used, pinned, status, resumes,
+                      superseded_by, seeded, agent
+            "#,
+        )
+        .bind(pinned)
+        .bind(session_id)
+        .bind(message_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let message = Self::message_from_row(&row)?;
+        Self::touch_session(&mut tx, session_id).await?;
+        tx.commit().await?;
+        Ok(Some(message))
+    }
+
+    /// Marks the session as changed, for changes to its messages that leave
+    /// their ids and number alone, so export ETags change with them.
+    async fn touch_session(conn: &mut SqliteConnection, session_id: &str) -> Result<()> {
+        sqlx::query("UPDATE chat_sessions SET updated_at = ? WHERE id = ?")
+            .bind(Utc::now().to_rfc3339())
+            .bind(session_id)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Sessions with messages waiting to be sent, the longest-waiting first.
+    pub async fn sessions_with_queued(&self) -> Result<Vec<String>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT session_id
+            FROM messages
+            WHERE status = ?
+            GROUP BY session_id
+            ORDER BY MIN(id) ASC
+            "#,
+        )
+        .bind(MessageStatus::Queued.as_str())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(|row| row.get::<String, _>(0)).collect())
+    }
+
+    /// A session's queued messages in the order they were written.
+    pub async fn queued_messages(&self, session_id: &str) -> Result<Vec<Message>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, role, content, timestamp, model, tokens_used, pinned, status, resumes,
+                   superseded_by, seeded, agent
+            FROM messages
+            WHERE session_id = ? AND status = ?
+            ORDER BY id ASC
+            "#,
+        )
+        .bind(session_id)
+        .bind(MessageStatus::Queued.as_str())
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(Self::message_from_row).collect()
+    }
+
+    /// Marks a queued message sent, moving it to `sent_at` so the history
+    /// reads in the order things actually went upstream.
+    pub async fn mark_message_sent(&self, message_id: i64, sent_at: DateTime<Utc>) -> Result<()> {
+        sqlx::query("UPDATE messages SET status = ?, timestamp = ? WHERE id = ?")
+            .bind(MessageStatus::Sent.as_str())
+            .bind(sent_at.to_rfc3339())
+            .bind(message_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    fn message_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<Message> {
+        let id = row.get::<i64, _>(0);
+        let session_id = row.get::<String, _>(1);
+        let role = row
+            .get::<String, _>(2)
+            .parse::<MessageRole>()
+            .with_context(|| format!("Message {} in session '{}' is corrupt", id, session_id))?;
+        Ok(Message {
+            id,
+            session_id,
+            role,
+            content: row.get::<String, _>(3),
+            timestamp: DateTime::parse_from_rfc3339(&row.get::<String, _>(4))?.with_timezone(&Utc),
+            model: row.get::<Option<String>, _>(5),
+            tokens_used: row.get::<Option<i32>, _>(6),
+            pinned: row.get::<bool, _>(7),
+            status: row.get::<String, _>(8).parse()?,
+            resumes: row.get::<Option<u32>, _>(9),
+            superseded_by: row.get::<Option<i64>, _>(10),
+            seeded: row.get::<bool, _>(11),
+            annotation: None,
+            agent: row.get::<Option<String>, _>(12),
+        })
+    }
+
+    pub async fn export_session(&self, session_id: &str) -> Result<Option<SessionExport>> {
+        let Some(session) = self.get_session(session_id).await? else {
+            return Ok(None);
+        };
+        let messages = self.get_messages(session_id).await?;
+        Ok(Some(SessionExport { session, messages }))
+    }
+
+    /// Stores a share link, replacing the session's previous one.
+    pub async fn create_share(&self, share: &SessionShare) -> Result<()> {
+        let mut tx = WriteTransaction::begin(&self.pool).await?;
+
+        sqlx::query("DELETE FROM session_shares WHERE session_id = ?")
+            .bind(&share.session_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO session_shares (token, session_id, created_at, expires_at)
+            VALUES (?, ?, ?, ?)
+            "#,
+        )
+        .bind(&share.token)
+        .bind(&share.session_id)
+        .bind(share.created_at.to_rfc3339())
+        .bind(share.expires_at.map(|at| at.to_rfc3339()))
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Returns false if the session had no share link.
+    pub async fn revoke_share(&self, session_id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM session_shares WHERE session_id = ?")
+            .bind(session_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The session a share token grants access to, without the messages a
+    /// session template seeded. Unknown, revoked and expired tokens all give
+    /// `None`; expired ones are deleted on the way.
+    pub async fn shared_session(&self, token: &str) -> Result<Option<SessionExport>> {
+        let row = sqlx::query(
+            "SELECT token, session_id, created_at, expires_at FROM session_shares WHERE token = ?",
+        )
+        .bind(token)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let share = SessionShare {
+            token: row.get::<String, _>(0),
+            session_id: row.get::<String, _>(1),
+            created_at: DateTime::parse_from_rfc3339(&row.get::<String, _>(2))?.with_timezone(&Utc),
+            expires_at: row
+                .get::<Option<String>, _>(3)
+                .map(|at| DateTime::parse_from_rfc3339(&at).map(|at| at.with_timezone(&Utc)))
+                .transpose()?,
+        };
+        if share.is_expired_at(Utc::now()) {
+            sqlx::query("DELETE FROM session_shares WHERE token = ?")
+                .bind(token)
+                .execute(&self.pool)
+                .await?;
+            return Ok(None);
+        }
+
+        Ok(self
+            .export_session(&share.session_id)
+            .await?
+            .map(SessionExport::without_seeded))
+    }
+
+    /// Records the session's newest message as a snapshot named `label`,
+    /// logged as done by `actor`. `None` for an unknown session.
+    pub async fn create_snapshot(
+        &self,
+        session_id: &str,
+        label: &str,
+        actor: &Actor,
+    ) -> Result<Option<SessionSnapshot>> {
+        let created_at = Utc::now();
+        let mut tx = WriteTransaction::begin(&self.pool).await?;
+        let row = sqlx::query(
+            r#"
+            INSERT INTO session_snapshots (session_id, label, message_id, created_at)
+            SELECT s.id, ?2, COALESCE((SELECT MAX(id) FROM messages WHERE session_id = s.id), 0), ?3
+            FROM chat_sessions s WHERE s.id = ?1
+            RETURNING id, message_id
+            "#,
+        )
+        .bind(session_id)
+        .bind(label)
+        .bind(created_at.to_rfc3339())
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let snapshot = SessionSnapshot {
+            id: row.get(0),
+            session_id: session_id.to_string(),
+            label: label.to_string(),
+            message_id: row.get(1),
+            created_at,
+            removed_by: None,
+        };
+
+        let record = AuditRecord::new(AuditOp::SessionSnapshot)
+            .session(session_id)
+            .detail(format!("snapshot {} '{}'", snapshot.id, label));
+        Self::store_audit(&mut tx, actor, &record).await?;
+        tx.commit().await?;
+        Ok(Some(snapshot))
+    }
+
+    /// The session's snapshots, oldest first, removed ones included.
+    pub async fn snapshots(&self, session_id: &str) -> Result<Vec<SessionSnapshot>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, label, message_id, created_at, removed_by
+            FROM session_snapshots
+            WHERE session_id = ?
+            ORDER BY id ASC
+            "#,
+        )
+        .bind(session_id)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter().map(Self::snapshot_from_row).collect()
+    }
+
+    /// Takes the session back to snapshot `snapshot_id` in one transaction,
+    /// logged as done by `actor`. Messages written since move to
+    /// `restored_messages`, replies regenerated since get their earlier
+    /// generation back, and later snapshots are marked removed. `None` for a
+    /// snapshot the session doesn't have, and a [`SnapshotRemoved`] error for
+    /// one an earlier restore went back past.
+    pub async fn restore_snapshot(
+        &self,
+        session_id: &str,
+        snapshot_id: i64,
+        actor: &Actor,
+    ) -> Result<Option<RestoreReport>> {
+        let now = Utc::now().to_rfc3339();
+        let mut tx = WriteTransaction::begin(&self.pool).await?;
+        // Touching the session first starts the transaction out writing, and
+        // changes the version export ETags are made from
+        sqlx::query("UPDATE chat_sessions SET updated_at = ? WHERE id = ?")
+            .bind(&now)
+            .bind(session_id)
+            .execute(&mut *tx)
+            .await?;
+        let row = sqlx::query(
+            r#"
+            SELECT id, session_id, label, message_id, created_at, removed_by
+            FROM session_snapshots
+            WHERE id = ? AND session_id = ?
+            "#,
+        )
+        .bind(snapshot_id)
+        .bind(session_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let snapshot = Self::snapshot_from_row(&row)?;
+        if let Some(removed_by) = snapshot.removed_by {
+            return Err(SnapshotRemoved {
+                snapshot_id,
+                removed_by,
+            }
+            .into());
+        }
+        let point = snapshot.message_id;
+
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, role, content, timestamp, model, tokens_used, pinned, status, resumes,
+                   superseded_by, seeded, agent
+            FROM messages
+            WHERE session_id = ? AND id > ?
+            ORDER BY id ASC
+            "#,
+        )
+        .bind(session_id)
+        .bind(point)
+        .fetch_all(&mut *tx)
+        .await?;
+        let mut later = rows
+            .iter()
+            .map(Self::message_from_row)
+            .collect::<Result<Vec<_>>>()?;
+        let rows = sqlx::query(
+            r#"
+            SELECT a.message_id, a.rating, a.note, a.labels
+            FROM annotations a JOIN messages m ON m.id = a.message_id
+            WHERE m.session_id = ? AND m.id > ?
+            "#,
+        )
+        .bind(session_id)
+        .bind(point)
+        .fetch_all(&mut *tx)
+        .await?;
+        let mut annotations = rows
+            .iter()
+            .map(|row| Ok((row.get::<i64, _>(0), Self::annotation_from_row(row)?)))
+            .collect::<Result<HashMap<_, _>>>()?;
+        // Deleting the messages deletes their attachment rows with them, and
+        // the blobs would then go to garbage collection
+        let rows = sqlx::query(
+            r#"
+            SELECT a.hash, a.size, a.mime_type, a.message_id
+            FROM attachments a JOIN messages m ON m.id = a.message_id
+            WHERE m.session_id = ? AND m.id > ?
+            ORDER BY a.id
+            "#,
+        )
+        .bind(session_id)
+        .bind(point)
+        .fetch_all(&mut *tx)
+        .await?;
+        let mut attachments: HashMap<i64, Vec<StoredMedia>> = HashMap::new();
+        for row in &rows {
+            attachments
+                .entry(row.get(3))
+                .or_default()
+                .push(Self::media_from_row(row));
+        }
+        for message in &mut later {
+            message.annotation = annotations.remove(&message.id);
+            let mut archived = serde_json::to_value(&message)?;
+            if let Some(attachments) = attachments.remove(&message.id) {
+                archived["attachments"] = serde_json::to_value(attachments)?;
+            }
+            sqlx::query(
+                r#"
+                INSERT INTO restored_messages (message_id, session_id, snapshot_id, restored_at, message)
+                VALUES (?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(message.id)
+            .bind(session_id)
+            .bind(snapshot_id)
+            .bind(&now)
+            .bind(archived.to_string())
+            .execute(&mut *tx)
+            .await?;
+        }
+        sqlx::query("DELETE FROM messages WHERE session_id = ? AND id > ?")
+            .bind(session_id)
+            .bind(point)
+            .execute(&mut *tx)
+            .await?;
+
+        // Regenerating points every generation of a reply at the newest one,
+        // so of those left the newest is the reply again
+        let rows = sqlx::query(
+            "SELECT superseded_by, MAX(id) FROM messages \
+             WHERE session_id = ? AND superseded_by > ? GROUP BY superseded_by",
+        )
+        .bind(session_id)
+        .bind(point)
+        .fetch_all(&mut *tx)
+        .await?;
+        for row in rows {
+            let (replaced_by, newest) = (row.get::<i64, _>(0), row.get::<i64, _>(1));
+            sqlx::query(
+                "UPDATE messages SET superseded_by = CASE WHEN id = ?1 THEN NULL ELSE ?1 END \
+                 WHERE superseded_by = ?2",
+            )
+            .bind(newest)
+            .bind(replaced_by)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let removed_snapshots: Vec<i64> = sqlx::query_scalar(
+            "UPDATE session_snapshots SET removed_by = ? \
+             WHERE session_id = ? AND message_id > ? AND removed_by IS NULL RETURNING id",
+        )
+        .bind(snapshot_id)
+        .bind(session_id)
+        .bind(point)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let record = AuditRecord::new(AuditOp::SessionRestore)
+            .session(session_id)
+            .detail(format!(
+                "snapshot {} '{}', {} messages archived",
+                snapshot_id,
+                snapshot.label,
+                later.len()
+            ));
+        Self::store_audit(&mut tx, actor, &record).await?;
+        tx.commit().await?;
+        Ok(Some(RestoreReport {
+            snapshot,
+            archived: later.len(),
+            removed_snapshots,
+        }))
+    }
+
+    fn snapshot_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<SessionSnapshot> {
+        Ok(SessionSnapshot {
+            id: row.get(0),
+            session_id: row.get(1),
+            label: row.get(2),
+            message_id: row.get(3),
+            created_at: DateTime::parse_from_rfc3339(&row.get::<String, _>(4))?.with_timezone(&Utc),
+            removed_by: row.get(5),
+        })
+    }
+
+    pub async fn get_session_message_count(&self, session_id: &str) -> Result<i64> {
+        let row = sqlx::query(
+            "SELECT COUNT(*) as count FROM messages WHERE session_id = ? AND superseded_by IS NULL",
+        )
+        .bind(session_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.get::<i64, _>("count"))
+    }
+
+    pub async fn get_total_sessions(&self) -> Result<i64> {
+        let row = sqlx::query("SELECT COUNT(*) as count FROM chat_sessions")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(row.get::<i64, _>("count"))
+    }
+
+    pub async fn get_queued_message_count(&self) -> Result<i64> {
+        let row = sqlx::query("SELECT COUNT(*) as count FROM messages WHERE status = ?")
+            .bind(MessageStatus::Queued.as_str())
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(row.get::<i64, _>("count"))
+    }
+
+    pub async fn get_total_messages(&self) -> Result<i64> {
+        let row = sqlx::query("SELECT COUNT(*) as count FROM messages")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(row.get::<i64, _>("count"))
+    }
+
+    /// Records that `message_id` carries the stored blob `media`.
+    pub async fn add_attachment(&self, message_id: i64, media: &StoredMedia) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO attachments (message_id, hash, size, mime_type, created_at)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(message_id)
+        .bind(&media.hash)
+        .bind(media.size as i64)
+        .bind(&media.mime_type)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The message's attachments, in the order they were added.
+    pub async fn message_attachments(&self, message_id: i64) -> Result<Vec<StoredMedia>> {
+        let rows = sqlx::query(
+            "SELECT hash, size, mime_type FROM attachments WHERE message_id = ? ORDER BY id",
+        )
+        .bind(message_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.iter().map(Self::media_from_row).collect())
+    }
+
+    /// The attachments of every message in the session, by message id, each
+    /// message's in the order they were added.
+    pub async fn session_attachments(
+        &self,
+        session_id: &str,
+    ) -> Result<HashMap<i64, Vec<StoredMedia>>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT a.hash, a.size, a.mime_type, a.message_id
+            FROM attachments a JOIN messages m ON m.id = a.message_id
+            WHERE m.session_id = ?
+            ORDER BY a.id
+            "#,
+        )
+        .bind(session_id)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut attachments: HashMap<i64, Vec<StoredMedia>> = HashMap::new();
+        for row in &rows {
+            attachments
+                .entry(row.get(3))
+                .or_default()
+                .push(Self::media_from_row(row));
+        }
+        Ok(attachments)
+    }
+
+    /// Size and type of the blob with `hash`, if any message still has it.
+    pub async fn find_media(&self, hash: &str) -> Result<Option<StoredMedia>> {
+        let row =
+            sqlx::query("SELECT hash, size, mime_type FROM attachments WHERE hash = ? LIMIT 1")
+                .bind(hash)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(row.as_ref().map(Self::media_from_row))
+    }
+
+    /// Every hash an attachment refers to, including those of messages a
+    /// restore archived; the media store may drop the rest.
+    pub async fn media_hashes(&self) -> Result<HashSet<String>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT hash FROM attachments
+            UNION
+            SELECT json_extract(a.value, '$.hash') AS hash
+            FROM restored_messages r, json_each(r.message, '$.attachments') a
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.iter().map(|row| row.get("hash")).collect())
+    }
+
+    fn media_from_row(row: &sqlx::sqlite::SqliteRow) -> StoredMedia {
+        StoredMedia {
+            hash: row.get("hash"),
+            size: row.get::<i64, _>("size") as u64,
+            mime_type: row.get("mime_type"),
+        }
+    }
+
+    /// Replaces the annotation on message `message_id` of `session_id`; an
+    /// empty one removes it. False when the session has no such message.
+    pub async fn set_annotation(
+        &self,
+        session_id: &str,
+        message_id: i64,
+        annotation: &Annotation,
+    ) -> Result<bool> {
+        let mut tx = WriteTransaction::begin(&self.pool).await?;
+        let found = sqlx::query("SELECT 1 FROM messages WHERE id = ? AND session_id = ?")
+            .bind(message_id)
+            .bind(session_id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .is_some();
+        if !found {
+            return Ok(false);
+        }
+        Self::store_annotation(&mut tx, message_id, annotation).await?;
+        Self::touch_session(&mut tx, session_id).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// The annotation on message `message_id` of `session_id`, if any.
+    pub async fn message_annotation(
+        &self,
+        session_id: &str,
+        message_id: i64,
+    ) -> Result<Option<Annotation>> {
+        let row = sqlx::query(
+            r#"
+            SELECT a.message_id, a.rating, a.note, a.labels
+            FROM annotations a JOIN messages m ON m.id = a.message_id
+            WHERE m.id = ? AND m.session_id = ?
+            "#,
+        )
+        .bind(message_id)
+        .bind(session_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        row.as_ref().map(Self::annotation_from_row).transpose()
+    }
+
+    /// Sessions with annotated messages (only those rated `rating`, if
+    /// given), oldest first.
+    pub async fn annotated_sessions(&self, rating: Option<Rating>) -> Result<Vec<String>> {
+        let rating = rating.map(Rating::as_str);
+        let rows = sqlx::query(
+            r#"
+            SELECT m.session_id
+            FROM annotations a
+            JOIN messages m ON m.id = a.message_id
+            JOIN chat_sessions s ON s.id = m.session_id
+            WHERE ?1 IS NULL OR a.rating = ?1
+            GROUP BY m.session_id
+            ORDER BY MIN(s.created_at), m.session_id
+            "#,
+        )
+        .bind(rating)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    async fn store_annotation(
+        conn: &mut SqliteConnection,
+        message_id: i64,
+        annotation: &Annotation,
+    ) -> Result<()> {
+        if annotation.is_empty() {
+            sqlx::query("DELETE FROM annotations WHERE message_id = ?")
+                .bind(message_id)
+                .execute(&mut *conn)
+                .await?;
+            return Ok(());
+        }
+        sqlx::query(
+            r#"
+            INSERT INTO annotations (message_id, rating, note, labels, updated_at)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT (message_id) DO UPDATE SET
+                rating = excluded.rating,
+                note = excluded.note,
+                labels = excluded.labels,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(message_id)
+        .bind(annotation.rating.map(Rating::as_str))
+        .bind(&annotation.note)
+        .bind(serde_json::to_string(&annotation.labels)?)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Reads the rating, note and labels columns (1 to 3).
+    fn annotation_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<Annotation> {
+        Ok(Annotation {
+            rating: row
+                .get::<Option<String>, _>(1)
+                .map(|rating| rating.parse())
+                .transpose()?,
+            note: row.get(2),
+            labels: serde_json::from_str(&row.get::<String, _>(3))
+                .context("Invalid annotation labels")?,
+        })
+    }
+
+    /// Appends an entry to the audit log, on its own.
+    pub async fn record_audit(&self, actor: &Actor, record: &AuditRecord) -> Result<()> {
+        Self::store_audit(&mut *self.pool.acquire().await?, actor, record).await
+    }
+
+    async fn store_audit(
+        conn: &mut SqliteConnection,
+        actor: &Actor,
+        record: &AuditRecord,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO audit_log
+                (timestamp, actor, operation, session_id, message_id, detail, request_id)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(Utc::now().to_rfc3339())
+        .bind(&actor.name)
+        .bind(record.op.as_str())
+        .bind(&record.session_id)
+        .bind(record.message_id)
+        .bind(&record.detail)
+        .bind(&actor.request_id)
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Audit entries matching `filter`, newest first, a page at a time.
+    pub async fn audit_entries(
+        &self,
+        filter: &AuditFilter,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<AuditEntry>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, timestamp, actor, operation, session_id, message_id, detail, request_id
+            FROM audit_log
+            WHERE (?1 IS NULL OR timestamp >= ?1)
+              AND (?2 IS NULL OR actor = ?2)
+              AND (?3 IS NULL OR operation = ?3 OR operation LIKE ?3 || '.%')
+            ORDER BY id DESC
+            LIMIT ?4 OFFSET ?5
+            "#,
+        )
+        .bind(filter.since.map(|since| since.to_rfc3339()))
+        .bind(&filter.actor)
+        .bind(&filter.op)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(Self::audit_entry_from_row).collect()
+    }
+
+    /// The audit log from `since` on (all of it when `None`), oldest first.
+    pub async fn audit_log_since(&self, since: Option<DateTime<Utc>>) -> Result<Vec<AuditEntry>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, timestamp, actor, operation, session_id, message_id, detail, request_id
+            FROM audit_log
+            WHERE ?1 IS NULL OR timestamp >= ?1
+            ORDER BY id
+            "#,
+        )
+        .bind(since.map(|since| since.to_rfc3339()))
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(Self::audit_entry_from_row).collect()
+    }
+
+    fn audit_entry_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<AuditEntry> {
+        Ok(AuditEntry {
+            id: row.get(0),
+            timestamp: DateTime::parse_from_rfc3339(&row.get::<String, _>(1))?.with_timezone(&Utc),
+            actor: row.get(2),
+            operation: row.get(3),
+            session_id: row.get(4),
+            message_id: row.get(5),
+            detail: row.get(6),
+            request_id: row.get(7),
+        })
+    }
+
+    /// Reclaims space left behind by deleted rows.
+    pub async fn vacuum(&self) -> Result<()> {
+        sqlx::query("VACUUM").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn record_usage(
+        &self,
+        session_id: Option<&str>,
+        model: &str,
+        usage: &UsageStats,
+        stats: &CompletionStats,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO usage_ledger
+                (session_id, model, prompt_tokens, completion_tokens, created_at, latency_ms, finish_reason)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(session_id)
+        .bind(model)
+        .bind(usage.prompt_tokens)
+        .bind(usage.completion_tokens)
+        .bind(Utc::now().to_rfc3339())
+        .bind(stats.latency_ms.map(|ms| ms as i64))
+        .bind(&stats.finish_reason)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Merges the `duplicates` sessions into `keep` in one transaction and
+    /// returns how many messages moved. A duplicate's message moves when
+    /// `keep` doesn't already hold it, with repeats counted, so no message is
+    /// lost; the rest are deleted with the duplicate. Usage moves too, and
+    /// `keep` takes the earliest creation time, the latest update and, when
+    /// it has none, a duplicate's title.
+    pub async fn merge_sessions(
+        &self,
+        keep: &str,
+        duplicates: &[String],
+        actor: &Actor,
+    ) -> Result<usize> {
+        let mut tx = WriteTransaction::begin(&self.pool).await?;
+        let mut moved = 0;
+
+        for duplicate in duplicates {
+            // Ids of the kept session's messages, by content
+            let mut kept: HashMap<String, Vec<i64>> = HashMap::new();
+            let rows = sqlx::query("SELECT id, role, content FROM messages WHERE session_id = ?")
+                .bind(keep)
+                .fetch_all(&mut *tx)
+                .await?;
+            for row in &rows {
+                let key = message_key(&row.get::<String, _>(1), &row.get::<String, _>(2));
+                kept.entry(key).or_default().push(row.get::<i64, _>(0));
+            }
+
+            let rows = sqlx::query(
+                "SELECT id, role, content FROM messages WHERE session_id = ? ORDER BY timestamp, id",
+            )
+            .bind(duplicate)
+            .fetch_all(&mut *tx)
+            .await?;
+            for row in &rows {
+                let id = row.get::<i64, _>(0);
+                let key = message_key(&row.get::<String, _>(1), &row.get::<String, _>(2));
+                match kept.get_mut(&key).and_then(Vec::pop) {
+                    // Earlier generations of a dropped reply, attachments the
+                    // twin lacks and an annotation when it has none now
+                    // belong to the twin
+                    Some(twin) => {
+                        sqlx::query(
+                            "UPDATE messages SET superseded_by = ? WHERE superseded_by = ?",
+                        )
+                        .bind(twin)
+                        .bind(id)
+                        .execute(&mut *tx)
+                        .await?;
+                        sqlx::query(
+                            r#"
+                            UPDATE attachments SET message_id = ?1
+                            WHERE message_id = ?2
+                              AND hash NOT IN (SELECT hash FROM attachments WHERE message_id = ?1)
+                            "#,
+                        )
+                        .bind(twin)
+                        .bind(id)
+                        .execute(&mut *tx)
+                        .await?;
+                        sqlx::query(
+                            r#"
+                            INSERT OR IGNORE INTO annotations
+                                (message_id, rating, note, labels, updated_at)
+                            SELECT ?1, rating, note, labels, updated_at
+                            FROM annotations WHERE message_id = ?2
+                            "#,
+                        )
+                        .bind(twin)
+                        .bind(id)
+                        .execute(&mut *tx)
+                        .await?;
+                    }
+                    None => {
+                        sqlx::query("UPDATE messages SET session_id = ? WHERE id = ?")
+                            .bind(keep)
+                            .bind(id)
+                            .execute(&mut *tx)
+                            .await?;
+                        moved += 1;
+                    }
+                }
+            }
+
+            sqlx::query("UPDATE usage_ledger SET session_id = ? WHERE session_id = ?")
+                .bind(keep)
+                .bind(duplicate)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(
+                r#"
+                UPDATE chat_sessions
+                SET created_at = MIN(created_at, (SELECT created_at FROM chat_sessions WHERE id = ?1)),
+                    updated_at = MAX(updated_at, (SELECT updated_at FROM chat_sessions WHERE id = ?1)),
+                    title = COALESCE(title, (SELECT title FROM chat_sessions WHERE id = ?1))
+                WHERE id = ?2
+                "#,
+            )
+            .bind(duplicate)
+            .bind(keep)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query("DELETE FROM chat_sessions WHERE id = ?")
+                .bind(duplicate)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        let record = AuditRecord::new(AuditOp::MaintenanceDedupe)
+            .session(keep)
+            .detail(format!("merged {}", duplicates.join(", ")));
+        Self::store_audit(&mut tx, actor, &record).await?;
+        tx.commit().await?;
+        Ok(moved)
+    }
+
+    /// The ledger rows of one session, oldest first.
+    pub async fn usage_for_session(&self, session_id: &str) -> Result<Vec<UsageRecord>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT session_id, model, prompt_tokens, completion_tokens, created_at,
+                   latency_ms, finish_reason
+            FROM usage_ledger
+            WHERE session_id = ?
+            ORDER BY id ASC
+            "#,
+        )
+        .bind(session_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(Self::usage_from_row).collect()
+    }
+
+    /// The ledger rows written in `[from, to)`, oldest first.
+    pub async fn usage_between(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<UsageRecord>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT session_id, model, prompt_tokens, completion_tokens, created_at,
+                   latency_ms, finish_reason
+            FROM usage_ledger
+            WHERE created_at >= ? AND created_at < ?
+            ORDER BY created_at ASC
+            "#,
+        )
+        .bind(from.to_rfc3339())
+        .bind(to.to_rfc3339())
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(Self::usage_from_row).collect()
+    }
+
+    fn usage_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<UsageRecord> {
+        Ok(UsageRecord {
+            session_id: row.get::<Option<String>, _>(0),
+            model: row.get::<String, _>(1),
+            prompt_tokens: row.get::<i64, _>(2),
+            completion_tokens: row.get::<i64, _>(3),
+            created_at: DateTime::parse_from_rfc3339(&row.get::<String, _>(4))?.with_timezone(&Utc),
+            latency_ms: row.get::<Option<i64>, _>(5).map(|ms| ms as u64),
+            finish_reason: row.get::<Option<String>, _>(6),
+        })
+    }
+
+    /// Current (not superseded) messages written in `[from, to)`, oldest
+    /// first, with the title of their session.
+    pub async fn message_activity(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<MessageActivity>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT m.session_id, s.title, m.timestamp
+            FROM messages m
+            JOIN chat_sessions s ON s.id = m.session_id
+            WHERE m.timestamp >= ? AND m.timestamp < ? AND m.superseded_by IS NULL
+            ORDER BY m.timestamp ASC
+            "#,
+        )
+        .bind(from.to_rfc3339())
+        .bind(to.to_rfc3339())
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(MessageActivity {
+                    session_id: row.get::<String, _>(0),
+                    session_title: row.get::<Option<String>, _>(1),
+                    timestamp: DateTime::parse_from_rfc3339(&row.get::<String, _>(2))?
+                        .with_timezone(&Utc),
+                })
+            })
+            .collect()
+    }
+
+    pub async fn usage_by_model(&self) -> Result<Vec<ModelUsage>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT model, COUNT(*), SUM(prompt_tokens), SUM(completion_tokens)
+            FROM usage_ledger
+            GROUP BY model
+            ORDER BY model ASC
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| ModelUsage {
+                model: row.get::<String, _>(0),
+                requests: row.get::<i64, _>(1),
+                prompt_tokens: row.get::<i64, _>(2),
+                completion_tokens: row.get::<i64, _>(3),
+            })
+            .collect())
+    }
+
+    pub async fn create_template(&self, template: &PromptTemplate) -> Result<()> {
+        let now = Utc::now().to_rfc3339();
+
+        sqlx::query(
+            r#"
+            INSERT INTO prompt_templates
+                (name, description, body, model, temperature, max_tokens, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&template.name)
+        .bind(&template.description)
+        .bind(&template.body)
+        .bind(&template.model)
+        .bind(template.temperature)
+        .bind(template.max_tokens)
+        .bind(&now)
+        .bind(&now)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_template(&self, name: &str) -> Result<Option<PromptTemplate>> {
+        let row = sqlx::query(
+            r#"
+            SELECT name, description, body, model, temperature, max_tokens
+            FROM prompt_templates
+            WHERE name = ?
+            "#,
+        )
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| Self::template_from_row(&row)))
+    }
+
+    pub async fn list_templates(&self) -> Result<Vec<PromptTemplate>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT name, description, body, model, temperature, max_tokens
+            FROM prompt_templates
+            ORDER BY name ASC
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(Self::template_from_row).collect())
+    }
+
+    /// Replaces an existing template. Returns false if no template has that name.
+    pub async fn update_template(&self, name: &str, template: &PromptTemplate) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE prompt_templates
+            SET name = ?, description = ?, body = ?, model = ?, temperature = ?,
+                max_tokens = ?, updated_at = ?
+            WHERE name = ?
+            "#,
+        )
+        .bind(&template.name)
+        .bind(&template.description)
+        .bind(&template.body)
+        .bind(&template.model)
+        .bind(template.temperature)
+        .bind(template.max_tokens)
+        .bind(Utc::now().to_rfc3339())
+        .bind(name)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn delete_template(&self, name: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM prompt_templates WHERE name = ?")
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Saves a preset. Marking it the default unmarks the previous default.
+    /// Creates `session` already holding `template`'s seeded messages, in
+    /// one transaction, and returns the messages with their ids.
+    pub async fn create_seeded_session(
+        &self,
+        session: &ChatSession,
+        template: &SessionTemplate,
+        actor: &Actor,
+    ) -> Result<Vec<Message>> {
+        let mut tx = WriteTransaction::begin(&self.pool).await?;
+        Self::insert_session(&mut tx, session).await?;
+        let record = AuditRecord::new(AuditOp::SessionCreate)
+            .session(&session.id)
+            .detail(format!("template {}", template.name));
+        Self::store_audit(&mut tx, actor, &record).await?;
+
+        let mut messages = template.seed(&session.id);
+        for message in &mut messages {
+            let result = sqlx::query(
+                r#"
+                INSERT INTO messages (session_id, role, content, timestamp, seeded)
+                VALUES (?, ?, ?, ?, 1)
+                "#,
+            )
+            .bind(&message.session_id)
+            .bind(message.role.to_string())
+            .bind(&message.content)
+            .bind(message.timestamp.to_rfc3339())
+            .execute(&mut *tx)
+            .await?;
+            message.id = result.last_insert_rowid();
+        }
+
+        tx.commit().await?;
+        Ok(messages)
+    }
+
+    pub async fn create_session_template(&self, template: &SessionTemplate) -> Result<()> {
+        let now = Utc::now().to_rfc3339();
+        sqlx::query(
+            r#"
+            INSERT INTO session_templates
+                (name, description, system_prompt, messages, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&template.name)
+        .bind(&template.description)
+        .bind(&template.system_prompt)
+        .bind(serde_json::to_string(&template.messages)?)
+        .bind(&now)
+        .bind(&now)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_session_template(&self, name: &str) -> Result<Option<SessionTemplate>> {
+        let row = sqlx::query(
+            "SELECT name, description, system_prompt, messages FROM session_templates WHERE name = ?",
+        )
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.as_ref()
+            .map(Self::session_template_from_row)
+            .transpose()
+    }
+
+    /// The template named `name`, or an `UnknownSessionTemplate` error
+    /// listing the saved ones.
+    pub async fn resolve_session_template(&self, name: &str) -> Result<SessionTemplate> {
+        if let Some(template) = self.get_session_template(name).await? {
+            return Ok(template);
+        }
+        let available = self
+            .list_session_templates()
+            .await?
+            .into_iter()
+            .map(|template| template.name)
+            .collect();
+        Err(UnknownSessionTemplate {
+            name: name.to_string(),
+            available,
+        }
+        .into())
+    }
+
+    pub async fn list_session_templates(&self) -> Result<Vec<SessionTemplate>> {
+        let rows = sqlx::query(
+            "SELECT name, description, system_prompt, messages FROM session_templates ORDER BY name ASC",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(Self::session_template_from_row).collect()
+    }
+
+    /// Replaces an existing template; sessions already started from it keep
+    /// their copies. Returns false if no template has that name.
+    pub async fn update_session_template(
+        &self,
+        name: &str,
+        template: &SessionTemplate,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE session_templates
+            SET name = ?, description = ?, system_prompt = ?, messages = ?, updated_at = ?
+            WHERE name = ?
+            "#,
+        )
+        .bind(&template.name)
+        .bind(&template.description)
+        .bind(&template.system_prompt)
+        .bind(serde_json::to_string(&template.messages)?)
+        .bind(Utc::now().to_rfc3339())
+        .bind(name)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn delete_session_template(&self, name: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM session_templates WHERE name = ?")
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    fn session_template_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<SessionTemplate> {
+        let name = row.get::<String, _>(0);
+        let messages = serde_json::from_str(&row.get::<String, _>(3))
+            .with_context(|| format!("Session template '{}' is corrupt", name))?;
+        Ok(SessionTemplate {
+            name,
+            description: row.get::<Option<String>, _>(1),
+            system_prompt: row.get::<Option<String>, _>(2),
+            messages,
+        })
+    }
+
+    pub async fn create_prompt(&self, preset: &PromptPreset) -> Result<()> {
+        let now = Utc::now().to_rfc3339();
+        let mut tx = WriteTransaction::begin(&self.pool).await?;
+
+        if preset.is_default {
+            sqlx::query("UPDATE prompts SET is_default = 0")
+                .execute(&mut *tx)
+                .await?;
+        }
+        sqlx::query(
+            r#"
+            INSERT INTO prompts (name, body, is_default, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&preset.name)
+        .bind(&preset.body)
+        .bind(preset.is_default)
+        .bind(&now)
+        .bind(&now)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn get_prompt(&self, name: &str) -> Result<Option<PromptPreset>> {
+        let row = sqlx::query("SELECT name, body, is_default FROM prompts WHERE name = ?")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|row| Self::prompt_from_row(&row)))
+    }
+
+    /// The preset named `name`, or an `UnknownPreset` error listing the saved ones.
+    pub async fn resolve_prompt(&self, name: &str) -> Result<PromptPreset> {
+        if let Some(preset) = self.get_prompt(name).await? {
+            return Ok(preset);
+        }
+        let available = self
+            .list_prompts()
+            .await?
+            .into_iter()
+            .map(|preset| preset.name)
+            .collect();
+        Err(UnknownPreset {
+            name: name.to_string(),
+            available,
+        }
+        .into())
+    }
+
+    pub async fn default_prompt(&self) -> Result<Option<PromptPreset>> {
+        let row = sqlx::query("SELECT name, body, is_default FROM prompts WHERE is_default = 1")
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|row| Self::prompt_from_row(&row)))
+    }
+
+    pub async fn list_prompts(&self) -> Result<Vec<PromptPreset>> {
+        let rows = sqlx::query("SELECT name, body, is_default FROM prompts ORDER BY name ASC")
+            .fetch_all(&self.pool)
             .await?;
 
-        Ok(())
+        Ok(rows.iter().map(Self::prompt_from_row).collect())
     }
 
-    pub async fn create_message(&self, mut message: Message) -> Result<Message> {
+    /// Replaces an existing preset; sessions using it follow a rename.
+    /// Returns false if no preset has that name.
+    pub async fn update_prompt(&self, name: &str, preset: &PromptPreset) -> Result<bool> {
+        let mut tx = WriteTransaction::begin(&self.pool).await?;
+
+        if preset.is_default {
+            sqlx::query("UPDATE prompts SET is_default = 0")
+                .execute(&mut *tx)
+                .await?;
+        }
         let result = sqlx::query(
             r#"
-            INSERT INTO messages (session_id, role, content, timestamp, model, tokens_used)
-            VALUES (?, ?, ?, ?, ?, ?)
+            UPDATE prompts
+            SET name = ?, body = ?, is_default = ?, updated_at = ?
+            WHERE name = ?
             "#,
         )
-        .bind(&message.session_id)
-        .bind(message.role.to_string())
-        .bind(&message.content)
-        .bind(message.timestamp.to_rfc3339())
-        .bind(&message.model)
-        .bind(message.tokens_used)
-        .execute(&self.pool)
+        .bind(&preset.name)
+        .bind(&preset.body)
+        .bind(preset.is_default)
+        .bind(Utc::now().to_rfc3339())
+        .bind(name)
+        .execute(&mut *tx)
         .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        sqlx::query("UPDATE chat_sessions SET prompt_preset = ? WHERE prompt_preset = ?")
+            .bind(&preset.name)
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
 
-        message.id = result.last_insert_rowid();
-        Ok(message)
+        tx.commit().await?;
+        Ok(true)
     }
 
-    pub async fn get_messages(&self, session_id: &str) -> Result<Vec<Message>> {
-        let rows = sqlx::query(
-            r#"
-            SELECT id, session_id, role, content, timestamp, model, tokens_used
-            FROM messages
-            WHERE session_id = ?
-            ORDER BY timestamp ASC
-            "#,
-        )
-        .bind(session_id)
-        .fetch_all(&self.pool)
-        .await?;
+    /// Deletes a preset. Sessions using it go back to the default prompt;
+    /// returns how many did, or `None` if no preset has that name.
+    pub async fn delete_prompt(&self, name: &str) -> Result<Option<u64>> {
+        let mut tx = WriteTransaction::begin(&self.pool).await?;
 
-        let mut messages = Vec::new();
-        for row in rows {
-            messages.push(Message {
-                id: row.get::<i64, _>(0),
-                session_id: row.get::<String, _>(1),
-                role: MessageRole::from(row.get::<String, _>(2)),
-                content: row.get::<String, _>(3),
-                timestamp: DateTime::parse_from_rfc3339(&row.get::<String, _>(4))?
-                    .with_timezone(&Utc),
-                model: row.get::<Option<String>, _>(5),
-                tokens_used: row.get::<Option<i32>, _>(6),
-            });
+        let deleted = sqlx::query("DELETE FROM prompts WHERE name = ?")
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+        if deleted.rows_affected() == 0 {
+            return Ok(None);
         }
+        let reset =
+            sqlx::query("UPDATE chat_sessions SET prompt_preset = NULL WHERE prompt_preset = ?")
+                .bind(name)
+                .execute(&mut *tx)
+                .await?;
 
-        Ok(messages)
+        tx.commit().await?;
+        Ok(Some(reset.rows_affected()))
     }
 
-    pub async fn get_session_message_count(&self, session_id: &str) -> Result<i64> {
-        let row = sqlx::query("SELECT COUNT(*) as count FROM messages WHERE session_id = ?")
-            .bind(session_id)
-            .fetch_one(&self.pool)
-            .await?;
-
-        Ok(row.get::<i64, _>("count"))
+    fn prompt_from_row(row: &sqlx::sqlite::SqliteRow) -> PromptPreset {
+        PromptPreset {
+            name: row.get::<String, _>(0),
+            body: row.get::<String, _>(1),
+            is_default: row.get::<bool, _>(2),
+        }
     }
 
-    pub async fn get_total_sessions(&self) -> Result<i64> {
-        let row = sqlx::query("SELECT COUNT(*) as count FROM chat_sessions")
-            .fetch_one(&self.pool)
-            .await?;
-
-        Ok(row.get::<i64, _>("count"))
+    fn template_from_row(row: &sqlx::sqlite::SqliteRow) -> PromptTemplate {
+        PromptTemplate {
+            name: row.get::<String, _>(0),
+            description: row.get::<Option<String>, _>(1),
+            body: row.get::<String, _>(2),
+            model: row.get::<Option<String>, _>(3),
+            temperature: row.get::<Option<f32>, _>(4),
+            max_tokens: row.get::<Option<i32>, _>(5),
+        }
     }
+}
 
-    pub async fn get_total_messages(&self) -> Result<i64> {
-        let row = sqlx::query("SELECT COUNT(*) as count FROM messages")
-            .fetch_one(&self.pool)
-            .await?;
+/// A LIKE pattern matching text that contains `text` literally.
+fn like_pattern(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
 
-        Ok(row.get::<i64, _>("count"))
-    }
+/// Opens the full-text index on a new connection. Opening it reads its
+/// settings; left to the first insert in a transaction, that read would make
+/// the transaction fail at once rather than wait while another connection
+/// writes.
+async fn open_search_index(conn: &mut SqliteConnection) -> sqlx::Result<()> {
+    sqlx::query("SELECT 1 FROM messages_fts LIMIT 0")
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// `text` as an FTS5 query that matches messages holding every word, each
+/// as the start of a word. Quoting keeps FTS5 syntax in `text` literal.
+fn fts_query(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 pub async fn init() -> Result<Database> {
@@ -272,30 +2797,26 @@ pub async fn init() -> Result<Database> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempfile::tempdir;
+    use tempfile::{tempdir, TempDir};
 
-    async fn setup_test_db() -> Database {
+    // The TempDir must outlive the Database, so callers hold on to it.
+    async fn setup_test_db() -> (Database, TempDir) {
         let temp_dir = tempdir().unwrap();
         let db_path = temp_dir.path().join("test.db");
-        let database_url = format!("sqlite:{}", db_path.to_string_lossy());
-
-        // Override config for testing
-        std::env::set_var("DATABASE_URL", &database_url);
+        let database_url = format!("sqlite:{}?mode=rwc", db_path.to_string_lossy());
 
         let config = Config {
             xai_api_key: "test-key".to_string(),
             database_url,
-            server_host: "127.0.0.1".to_string(),
-            server_port: 3000,
-            default_model: "grok-4-0709".to_string(),
+            ..Config::default()
         };
 
-        Database::new(&config).await.unwrap()
+        (Database::new(&config).await.unwrap(), temp_dir)
     }
 
     #[tokio::test]
     async fn test_create_and_get_session() {
-        let db = setup_test_db().await;
+        let (db, _dir) = setup_test_db().await;
 
         let session = ChatSession::new("grok-4-0709".to_string(), Some("Test Session".to_string()));
         let created_session = db.create_session(session.clone()).await.unwrap();
@@ -311,7 +2832,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_create_and_get_messages() {
-        let db = setup_test_db().await;
+        let (db, _dir) = setup_test_db().await;
 
         let session = ChatSession::new("grok-4-0709".to_string(), None);
         db.create_session(session.clone()).await.unwrap();
@@ -337,9 +2858,213 @@ mod tests {
         assert_eq!(messages[1].model, Some("grok-4-0709".to_string()));
     }
 
+    #[tokio::test]
+    async fn test_sessions_and_messages_record_their_provider() {
+        let (db, _dir) = setup_test_db().await;
+        let session = ChatSession::new("local/llama3".to_string(), None);
+        db.create_session(session.clone()).await.unwrap();
+        for (content, model) in [
+            ("Hi", None),
+            ("Hello!", Some("local/llama3")),
+            ("Hey", Some("grok-3")),
+        ] {
+            let message = Message::assistant(session.id.clone(), content.to_string(), None);
+            db.create_message(Message {
+                model: model.map(str::to_string),
+                ..message
+            })
+            .await
+            .unwrap();
+        }
+
+        let provider: String =
+            sqlx::query_scalar("SELECT provider FROM chat_sessions WHERE id = ?")
+                .bind(&session.id)
+                .fetch_one(&db.pool)
+                .await
+                .unwrap();
+        assert_eq!(provider, "local");
+        let providers: Vec<Option<String>> =
+            sqlx::query_scalar("SELECT provider FROM messages WHERE session_id = ? ORDER BY id")
+                .bind(&session.id)
+                .fetch_all(&db.pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            providers,
+            [None, Some("local".to_string()), Some("xai".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recovering_a_session_twice_adds_nothing_new() {
+        let (db, _dir) = setup_test_db().await;
+        let session = ChatSession::new("grok-3".to_string(), None);
+        let messages = vec![
+            Message::user(session.id.clone(), "Hello".to_string()),
+            Message::assistant(session.id.clone(), "Hi!".to_string(), None),
+        ];
+        // Stored before the crash, while the journal still held it
+        db.create_session(session.clone()).await.unwrap();
+        db.create_message(messages[0].clone()).await.unwrap();
+
+        assert_eq!(db.recover_session(&session, &messages).await.unwrap(), 1);
+        assert_eq!(db.recover_session(&session, &messages).await.unwrap(), 0);
+        let stored = db.get_messages(&session.id).await.unwrap();
+        let contents: Vec<_> = stored.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["Hello", "Hi!"]);
+    }
+
+    #[tokio::test]
+    async fn test_pinning_toggles_and_survives_reload() {
+        let (db, dir) = setup_test_db().await;
+        let session = ChatSession::new("grok-3".to_string(), None);
+        db.create_session(session.clone()).await.unwrap();
+        let message = db
+            .create_message(Message::user(session.id.clone(), "style guide".to_string()))
+            .await
+            .unwrap();
+
+        let pinned = db
+            .set_message_pinned(&session.id, message.id, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(pinned.pinned);
+        let unchanged = db
+            .set_message_pinned(&session.id, message.id, Some(true))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(unchanged.pinned);
+        assert!(db
+            .set_message_pinned("other-session", message.id, None)
+            .await
+            .unwrap()
+            .is_none());
+
+        // Reopening runs the column upgrade again, which must be a no-op
+        drop(db);
+        let config = Config {
+            database_url: format!("sqlite:{}", dir.path().join("test.db").display()),
+            ..Config::default()
+        };
+        let db = Database::new(&config).await.unwrap();
+        assert!(db.get_messages(&session.id).await.unwrap()[0].pinned);
+    }
+
+    #[tokio::test]
+    async fn test_reinstating_a_replaced_reply_keeps_the_newer_one() {
+        let (db, _dir) = setup_test_db().await;
+        let session = ChatSession::new("grok-3".to_string(), None);
+        db.create_session(session.clone()).await.unwrap();
+        let reply = |text: &str| Message::assistant(session.id.clone(), text.to_string(), None);
+        let first = db.create_message(reply("Paris?")).await.unwrap();
+        let second = db
+            .supersede_message(&first, reply("Lisbon"), 5, &Actor::cli())
+            .await
+            .unwrap();
+        let third = db
+            .supersede_message(&second, reply("Lisbon, Portugal"), 5, &Actor::cli())
+            .await
+            .unwrap();
+
+        assert!(db
+            .reinstate_message(&session.id, second.id, third.id)
+            .await
+            .unwrap());
+        let live = db.get_messages(&session.id).await.unwrap();
+        assert_eq!(live.len(), 1);
+        assert_eq!(live[0].content, "Lisbon");
+        let generations = db.previous_generations(&session.id, second.id).await.unwrap();
+        let contents: Vec<_> = generations.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["Paris?", "Lisbon, Portugal"]);
+
+        // Redoing swaps back; a reply that is no longer a generation is refused
+        assert!(db
+            .reinstate_message(&session.id, third.id, second.id)
+            .await
+            .unwrap());
+        assert_eq!(db.get_messages(&session.id).await.unwrap()[0].id, third.id);
+        assert!(!db
+            .reinstate_message(&session.id, second.id, first.id)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_tables_from_older_versions_gain_new_columns() {
+        let dir = tempdir().unwrap();
+        let database_url = format!("sqlite:{}?mode=rwc", dir.path().join("old.db").display());
+        let pool = SqlitePool::connect(&database_url).await.unwrap();
+        sqlx::query(
+            "CREATE TABLE messages (id INTEGER PRIMARY KEY AUTOINCREMENT, session_id TEXT NOT NULL, \
+             role TEXT NOT NULL, content TEXT NOT NULL, timestamp TEXT NOT NULL, model TEXT, \
+             tokens_used INTEGER)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO messages (session_id, role, content, timestamp) \
+             VALUES ('s1', 'user', 'hi', '2025-01-01T00:00:00Z')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        pool.close().await;
+
+        let config = Config {
+            database_url,
+            ..Config::default()
+        };
+        let db = Database::new(&config).await.unwrap();
+        let messages = db.get_messages("s1").await.unwrap();
+        assert!(!messages[0].pinned);
+        assert_eq!(messages[0].status, MessageStatus::Sent);
+        assert_eq!(messages[0].resumes, None);
+        assert!(db.sessions_with_queued().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_roles_are_rejected_not_relabeled() {
+        let (db, _dir) = setup_test_db().await;
+
+        let session = ChatSession::new("grok-4-0709".to_string(), None);
+        db.create_session(session.clone()).await.unwrap();
+
+        let tool = Message::new(
+            session.id.clone(),
+            MessageRole::Tool,
+            "{}".to_string(),
+            None,
+        );
+        db.create_message(tool).await.unwrap();
+        assert_eq!(
+            db.get_messages(&session.id).await.unwrap()[0].role,
+            MessageRole::Tool
+        );
+
+        let other = Message::new(
+            session.id.clone(),
+            MessageRole::Other("developer".to_string()),
+            "hi".to_string(),
+            None,
+        );
+        assert!(db.create_message(other).await.is_err());
+
+        // A row written by something else must not come back as a user message
+        sqlx::query("UPDATE messages SET role = 'robot'")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let error = format!("{:#}", db.get_messages(&session.id).await.unwrap_err());
+        assert!(error.contains("is corrupt") && error.contains("unknown message role 'robot'"));
+    }
+
     #[tokio::test]
     async fn test_session_message_count() {
-        let db = setup_test_db().await;
+        let (db, _dir) = setup_test_db().await;
 
         let session = ChatSession::new("grok-4-0709".to_string(), None);
         db.create_session(session.clone()).await.unwrap();
@@ -354,9 +3079,93 @@ mod tests {
         assert_eq!(count, 1);
     }
 
+    #[tokio::test]
+    async fn test_search_messages_matches_literally() {
+        let (db, _dir) = setup_test_db().await;
+
+        let session = ChatSession::new("grok-3".to_string(), None);
+        db.create_session(session.clone()).await.unwrap();
+        for content in [
+            "Rust lifetimes",
+            "50% off",
+            "snake_case names",
+            "nothing here",
+        ] {
+            db.create_message(Message::user(session.id.clone(), content.to_string()))
+                .await
+                .unwrap();
+        }
+
+        let found = db.search_messages("RUST", 10).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].content, "Rust lifetimes");
+        // LIKE wildcards in the query are plain characters
+        assert_eq!(db.search_messages("%", 10).await.unwrap().len(), 1);
+        assert_eq!(db.search_messages("e_c", 10).await.unwrap().len(), 1);
+        assert_eq!(db.search_messages("e", 2).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_scan_messages_applies_filters_in_session_order() {
+        let (db, _dir) = setup_test_db().await;
+
+        let older = ChatSession::new("grok-3".to_string(), None);
+        db.create_session(older.clone()).await.unwrap();
+        db.create_message(Message::user(older.id.clone(), "Rust? 100%".to_string()))
+            .await
+            .unwrap();
+        let newer = ChatSession::new("grok-3".to_string(), None);
+        db.create_session(newer.clone()).await.unwrap();
+        db.create_message(Message::user(newer.id.clone(), "rust".to_string()))
+            .await
+            .unwrap();
+        db.create_message(Message::assistant(
+            newer.id.clone(),
+            "Rust it is".to_string(),
+            None,
+        ))
+        .await
+        .unwrap();
+
+        let scan = |filter: MessageFilter| {
+            let db = db.clone();
+            async move {
+                db.scan_messages(filter)
+                    .map(|message| message.unwrap().content)
+                    .collect::<Vec<_>>()
+                    .await
+            }
+        };
+        assert_eq!(
+            scan(MessageFilter::default()).await,
+            ["rust", "Rust it is", "Rust? 100%"]
+        );
+        let containing = |text: &str| MessageFilter {
+            containing: Some(text.to_string()),
+            ..MessageFilter::default()
+        };
+        assert_eq!(scan(containing("0%")).await, ["Rust? 100%"]);
+        assert_eq!(
+            scan(MessageFilter {
+                role: Some(MessageRole::Assistant),
+                ..containing("RUST")
+            })
+            .await,
+            ["Rust it is"]
+        );
+        assert_eq!(
+            scan(MessageFilter {
+                session_id: Some(older.id.clone()),
+                ..MessageFilter::default()
+            })
+            .await,
+            ["Rust? 100%"]
+        );
+    }
+
     #[tokio::test]
     async fn test_list_sessions() {
-        let db = setup_test_db().await;
+        let (db, _dir) = setup_test_db().await;
 
         let session1 = ChatSession::new("grok-4-0709".to_string(), Some("Session 1".to_string()));
         let session2 = ChatSession::new("grok-3".to_string(), Some("Session 2".to_string()));
@@ -371,4 +3180,705 @@ mod tests {
         assert_eq!(sessions[0].id, session2.id);
         assert_eq!(sessions[1].id, session1.id);
     }
+
+    #[tokio::test]
+    async fn test_prune_stale_sessions_with_messages() {
+        let (db, _dir) = setup_test_db().await;
+
+        let session = ChatSession::new("grok-3".to_string(), Some("Old".to_string()));
+        db.create_session(session.clone()).await.unwrap();
+        db.create_message(Message::user(session.id.clone(), "hi".to_string()))
+            .await
+            .unwrap();
+
+        let yesterday = Utc::now() - chrono::Duration::days(1);
+        assert!(db
+            .sessions_updated_before(yesterday)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let soon = Utc::now() + chrono::Duration::minutes(1);
+        let stale = db.sessions_updated_before(soon).await.unwrap();
+        assert_eq!(stale.len(), 1);
+
+        let export = db.export_session(&session.id).await.unwrap().unwrap();
+        assert_eq!(export.session.title.as_deref(), Some("Old"));
+        assert_eq!(export.messages.len(), 1);
+
+        db.delete_session(&stale[0].id).await.unwrap();
+        db.vacuum().await.unwrap();
+        assert!(db.export_session(&session.id).await.unwrap().is_none());
+        assert!(db.get_messages(&session.id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_template_crud() {
+        let (db, _dir) = setup_test_db().await;
+
+        let mut template = PromptTemplate::new("review", "Review {{code}}");
+        template.model = Some("grok-3".to_string());
+        db.create_template(&template).await.unwrap();
+        assert!(db.create_template(&template).await.is_err());
+
+        assert_eq!(
+            db.get_template("review").await.unwrap(),
+            Some(template.clone())
+        );
+
+        template.body = "Please review {{code}}".to_string();
+        assert!(db.update_template("review", &template).await.unwrap());
+        assert!(!db.update_template("missing", &template).await.unwrap());
+        assert_eq!(db.list_templates().await.unwrap(), vec![template]);
+
+        assert!(db.delete_template("review").await.unwrap());
+        assert!(!db.delete_template("review").await.unwrap());
+        assert_eq!(db.get_template("review").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_prompt_presets_keep_one_default() {
+        let (db, _dir) = setup_test_db().await;
+
+        let terse = PromptPreset {
+            is_default: true,
+            ..PromptPreset::new("terse", "Be brief.")
+        };
+        let eli5 = PromptPreset {
+            is_default: true,
+            ..PromptPreset::new("eli5", "Explain like I'm five.")
+        };
+        db.create_prompt(&terse).await.unwrap();
+        db.create_prompt(&eli5).await.unwrap();
+        assert!(db.create_prompt(&eli5).await.is_err());
+        assert_eq!(db.default_prompt().await.unwrap(), Some(eli5.clone()));
+        assert!(!db.get_prompt("terse").await.unwrap().unwrap().is_default);
+
+        assert!(db.update_prompt("terse", &terse).await.unwrap());
+        assert!(!db.update_prompt("missing", &terse).await.unwrap());
+        assert_eq!(db.default_prompt().await.unwrap(), Some(terse));
+
+        let error = db.resolve_prompt("reviewer").await.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<UnknownPreset>().unwrap().available,
+            vec!["eli5", "terse"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deleting_a_preset_in_use_falls_back_to_the_default() {
+        let (db, _dir) = setup_test_db().await;
+        let default = PromptPreset {
+            is_default: true,
+            ..PromptPreset::new("default", "Be helpful.")
+        };
+        db.create_prompt(&default).await.unwrap();
+        db.create_prompt(&PromptPreset::new("terse", "Be brief."))
+            .await
+            .unwrap();
+
+        let session = db
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        assert_eq!(
+            db.session_prompt(&session.id).await.unwrap(),
+            Some(default.clone())
+        );
+        db.set_session_prompt(&session.id, Some("terse"))
+            .await
+            .unwrap();
+        assert_eq!(
+            db.session_prompt(&session.id).await.unwrap().unwrap().name,
+            "terse"
+        );
+
+        assert_eq!(db.delete_prompt("terse").await.unwrap(), Some(1));
+        assert_eq!(db.delete_prompt("terse").await.unwrap(), None);
+        let session = db.get_session(&session.id).await.unwrap().unwrap();
+        assert_eq!(session.prompt_preset, None);
+        assert_eq!(db.session_prompt(&session.id).await.unwrap(), Some(default));
+    }
+
+    #[tokio::test]
+    async fn test_share_links_expire_and_revoke() {
+        let (db, _dir) = setup_test_db().await;
+        let session = db
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        let mut example = Message::user(session.id.clone(), "Example".to_string());
+        example.seeded = true;
+        db.create_message(example).await.unwrap();
+        db.create_message(Message::user(session.id.clone(), "Hi".to_string()))
+            .await
+            .unwrap();
+
+        let first = SessionShare::new(session.id.clone(), None);
+        db.create_share(&first).await.unwrap();
+        let shared = db.shared_session(&first.token).await.unwrap().unwrap();
+        assert_eq!(shared.session.id, session.id);
+        // Template examples aren't published with the session
+        assert_eq!(shared.messages.len(), 1);
+        assert_eq!(shared.messages[0].content, "Hi");
+
+        // A new link replaces the old one
+        let expired = SessionShare::new(
+            session.id.clone(),
+            Some(Utc::now() - chrono::Duration::minutes(1)),
+        );
+        db.create_share(&expired).await.unwrap();
+        assert!(db.shared_session(&first.token).await.unwrap().is_none());
+        assert!(db.shared_session(&expired.token).await.unwrap().is_none());
+        assert!(!db.revoke_share(&session.id).await.unwrap());
+
+        let current = SessionShare::new(
+            session.id.clone(),
+            Some(Utc::now() + chrono::Duration::hours(1)),
+        );
+        db.create_share(&current).await.unwrap();
+        assert!(db.shared_session(&current.token).await.unwrap().is_some());
+        assert!(db.revoke_share(&session.id).await.unwrap());
+        assert!(db.shared_session(&current.token).await.unwrap().is_none());
+
+        // Deleting the session takes its link with it
+        let last = SessionShare::new(session.id.clone(), None);
+        db.create_share(&last).await.unwrap();
+        db.delete_session(&session.id).await.unwrap();
+        assert!(!db.revoke_share(&session.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_seeded_sessions_keep_the_template_prompt_over_the_default() {
+        let (db, _dir) = setup_test_db().await;
+        let mut default = PromptPreset::new("terse", "Be brief.");
+        default.is_default = true;
+        db.create_prompt(&default).await.unwrap();
+        let template: SessionTemplate = serde_json::from_str(
+            r#"{"name": "review", "system_prompt": "Review code.",
+                "messages": [{"role": "user", "content": "x.unwrap()"},
+                             {"role": "assistant", "content": "Avoid unwrap."}]}"#,
+        )
+        .unwrap();
+        db.create_session_template(&template).await.unwrap();
+        assert_eq!(
+            db.resolve_session_template("review").await.unwrap(),
+            template
+        );
+        let error = db.resolve_session_template("nope").await.unwrap_err();
+        assert!(error.to_string().contains("available: review"));
+
+        let session = ChatSession::new("grok-3".to_string(), None);
+        let seeded = db
+            .create_seeded_session(&session, &template, &Actor::new("test"))
+            .await
+            .unwrap();
+        assert_eq!(seeded.len(), 3);
+        db.create_message(Message::user(session.id.clone(), "mine".to_string()))
+            .await
+            .unwrap();
+
+        let messages = db.get_messages(&session.id).await.unwrap();
+        let flags: Vec<bool> = messages.iter().map(|m| m.seeded).collect();
+        assert_eq!(flags, [true, true, true, false]);
+        assert_eq!(messages[0].content, "Review code.");
+        assert_eq!(db.session_prompt(&session.id).await.unwrap(), None);
+
+        // An explicitly chosen preset still applies
+        db.set_session_prompt(&session.id, Some("terse"))
+            .await
+            .unwrap();
+        assert_eq!(db.session_prompt(&session.id).await.unwrap(), Some(default));
+
+        assert!(db.delete_session_template("review").await.unwrap());
+        assert!(db.list_session_templates().await.unwrap().is_empty());
+        assert_eq!(db.get_messages(&session.id).await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_attachments_keep_only_a_hash_and_go_with_their_message() {
+        let (db, _dir) = setup_test_db().await;
+        let session = db
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        let first = db
+            .create_message(Message::user(session.id.clone(), "look".to_string()))
+            .await
+            .unwrap();
+        let second = db
+            .create_message(Message::user(session.id.clone(), "again".to_string()))
+            .await
+            .unwrap();
+        let media = StoredMedia {
+            hash: "ab".repeat(32),
+            size: 42,
+            mime_type: "image/png".to_string(),
+        };
+        db.add_attachment(first.id, &media).await.unwrap();
+        db.add_attachment(second.id, &media).await.unwrap();
+
+        assert_eq!(
+            db.message_attachments(first.id).await.unwrap(),
+            vec![media.clone()]
+        );
+        let attachments = db.session_attachments(&session.id).await.unwrap();
+        assert_eq!(attachments.len(), 2);
+        assert_eq!(attachments[&second.id], vec![media.clone()]);
+        assert_eq!(
+            db.find_media(&media.hash).await.unwrap(),
+            Some(media.clone())
+        );
+        assert_eq!(
+            db.media_hashes().await.unwrap(),
+            HashSet::from([media.hash.clone()])
+        );
+
+        db.delete_session(&session.id).await.unwrap();
+        assert!(db.media_hashes().await.unwrap().is_empty());
+        assert_eq!(db.find_media(&media.hash).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_annotations_survive_export_and_import() {
+        let (db, _dir) = setup_test_db().await;
+        let session = db
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        db.create_message(Message::user(session.id.clone(), "2+2?".to_string()))
+            .await
+            .unwrap();
+        let reply = db
+            .create_message(Message::assistant(
+                session.id.clone(),
+                "5".to_string(),
+                None,
+            ))
+            .await
+            .unwrap();
+        let annotation = Annotation {
+            rating: Some(Rating::Down),
+            note: Some("Wrong sum".to_string()),
+            labels: vec!["math".to_string()],
+        };
+        assert!(db
+            .set_annotation(&session.id, reply.id, &annotation)
+            .await
+            .unwrap());
+        assert!(!db
+            .set_annotation("elsewhere", reply.id, &annotation)
+            .await
+            .unwrap());
+
+        let export = db.export_session(&session.id).await.unwrap().unwrap();
+        assert_eq!(export.messages[0].annotation, None);
+        assert_eq!(export.messages[1].annotation, Some(annotation.clone()));
+        assert_eq!(
+            db.annotated_sessions(Some(Rating::Down)).await.unwrap(),
+            [session.id.as_str()]
+        );
+        assert!(db
+            .annotated_sessions(Some(Rating::Up))
+            .await
+            .unwrap()
+            .is_empty());
+
+        let copy = ChatSession::new("grok-3".to_string(), None);
+        db.import_session(&copy, &export.messages, &[], None)
+            .await
+            .unwrap();
+        let imported = db.get_messages(&copy.id).await.unwrap();
+        assert_eq!(imported[1].annotation, Some(annotation));
+
+        db.set_annotation(&session.id, reply.id, &Annotation::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            db.message_annotation(&session.id, reply.id).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_sessions_by_title_then_content() {
+        let (db, _dir) = setup_test_db().await;
+
+        let rust = db
+            .create_session(ChatSession::new(
+                "grok-3".to_string(),
+                Some("Rust lifetimes".to_string()),
+            ))
+            .await
+            .unwrap();
+        let cooking = db
+            .create_session(ChatSession::new(
+                "grok-3-mini".to_string(),
+                Some("Dinner".to_string()),
+            ))
+            .await
+            .unwrap();
+        db.create_message(Message::user(
+            cooking.id.clone(),
+            "How long should risotto rest?".to_string(),
+        ))
+        .await
+        .unwrap();
+        let hit = db
+            .create_message(Message::assistant(
+                cooking.id.clone(),
+                "Let the risotto rest for two minutes.".to_string(),
+                None,
+            ))
+            .await
+            .unwrap();
+        db.create_message(Message::user(
+            rust.id.clone(),
+            "Why does rustc talk about lifetimes?".to_string(),
+        ))
+        .await
+        .unwrap();
+
+        // Short queries only look at titles and models
+        let found = db.search_sessions("mini", 10).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].session.id, cooking.id);
+        assert!(found[0].hit.is_none());
+
+        let found = db.search_sessions("risotto two", 10).await.unwrap();
+        assert_eq!(found.len(), 1);
+        let content = found[0].hit.as_ref().unwrap();
+        assert_eq!(content.message_id, hit.id);
+        let marked: Vec<&str> = content
+            .snippet
+            .highlights
+            .iter()
+            .map(|range| &content.snippet.text[range.clone()])
+            .collect();
+        assert_eq!(marked, ["risotto", "two"]);
+
+        // A title match keeps its place and picks up the message
+        let found = db.search_sessions("lifetimes", 10).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].session.id, rust.id);
+        assert!(found[0].hit.is_some());
+
+        // Words are prefixes, and FTS syntax is taken literally
+        assert_eq!(db.search_sessions("risot", 10).await.unwrap().len(), 1);
+        assert!(db
+            .search_sessions("risotto\" OR (", 10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        db.delete_session(&cooking.id).await.unwrap();
+        assert!(db.search_sessions("risotto", 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_records_changes_and_refuses_edits() {
+        let (db, _dir) = setup_test_db().await;
+        let api = Actor::new("api:127.0.0.1").with_request_id(Some("req-1".to_string()));
+        let cli = Actor::new("cli:alice");
+
+        let session = db
+            .create_session_audited(ChatSession::new("grok-3".to_string(), None), &api)
+            .await
+            .unwrap();
+        let message = db
+            .create_message_audited(Message::user(session.id.clone(), "Hi".to_string()), &api)
+            .await
+            .unwrap();
+        assert!(db.delete_session_audited(&session.id, &cli).await.unwrap());
+        assert!(!db.delete_session_audited(&session.id, &cli).await.unwrap());
+
+        let entries = db
+            .audit_entries(&AuditFilter::default(), 10, 0)
+            .await
+            .unwrap();
+        let ops: Vec<&str> = entries.iter().map(|e| e.operation.as_str()).collect();
+        assert_eq!(ops, ["session.delete", "message.create", "session.create"]);
+        assert_eq!(entries[1].message_id, Some(message.id));
+        assert_eq!(entries[1].request_id.as_deref(), Some("req-1"));
+        assert_eq!(entries[2].session_id.as_deref(), Some(session.id.as_str()));
+
+        let filter = AuditFilter {
+            actor: Some("api:127.0.0.1".to_string()),
+            op: Some("session".to_string()),
+            ..AuditFilter::default()
+        };
+        let mine = db.audit_entries(&filter, 10, 0).await.unwrap();
+        assert_eq!(mine.len(), 1);
+        assert_eq!(mine[0].operation, "session.create");
+        let page = db
+            .audit_entries(&AuditFilter::default(), 1, 1)
+            .await
+            .unwrap();
+        assert_eq!(page[0].operation, "message.create");
+
+        for statement in [
+            "UPDATE audit_log SET actor = 'someone else'",
+            "DELETE FROM audit_log",
+        ] {
+            let error = sqlx::query(statement).execute(&db.pool).await.unwrap_err();
+            assert!(error.to_string().contains("append-only"), "{}", error);
+        }
+        assert_eq!(db.audit_log_since(None).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_restoring_a_snapshot_archives_later_messages() {
+        let (db, _dir) = setup_test_db().await;
+        let actor = Actor::new("test");
+        let session = db
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        let say = |role: MessageRole, text: &str| Message {
+            role,
+            ..Message::user(session.id.clone(), text.to_string())
+        };
+        db.create_message(say(MessageRole::User, "question"))
+            .await
+            .unwrap();
+        let first = db
+            .create_message(say(MessageRole::Assistant, "first answer"))
+            .await
+            .unwrap();
+        let second = db
+            .supersede_message(
+                &first,
+                say(MessageRole::Assistant, "second answer"),
+                5,
+                &actor,
+            )
+            .await
+            .unwrap();
+        let checkpoint = db
+            .create_snapshot(&session.id, "checkpoint", &actor)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(checkpoint.message_id, second.id);
+
+        // Regenerated again, then carried on with
+        db.supersede_message(
+            &second,
+            say(MessageRole::Assistant, "third answer"),
+            5,
+            &actor,
+        )
+        .await
+        .unwrap();
+        db.create_message(say(MessageRole::User, "follow-up"))
+            .await
+            .unwrap();
+        let later = db
+            .create_snapshot(&session.id, "later", &actor)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let report = db
+            .restore_snapshot(&session.id, checkpoint.id, &actor)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.archived, 2);
+        assert_eq!(report.removed_snapshots, [later.id]);
+        let contents = |messages: Vec<Message>| -> Vec<String> {
+            messages.into_iter().map(|m| m.content).collect()
+        };
+        assert_eq!(
+            contents(db.get_messages(&session.id).await.unwrap()),
+            ["question", "second answer"]
+        );
+        assert_eq!(
+            contents(
+                db.previous_generations(&session.id, second.id)
+                    .await
+                    .unwrap()
+            ),
+            ["first answer"]
+        );
+        let archived: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM restored_messages")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(archived, 2);
+
+        let error = db
+            .restore_snapshot(&session.id, later.id, &actor)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<SnapshotRemoved>(),
+            Some(&SnapshotRemoved {
+                snapshot_id: later.id,
+                removed_by: checkpoint.id,
+            })
+        );
+        assert!(db
+            .restore_snapshot("other-session", checkpoint.id, &actor)
+            .await
+            .unwrap()
+            .is_none());
+        let snapshots = db.snapshots(&session.id).await.unwrap();
+        assert_eq!(snapshots[1].removed_by, Some(checkpoint.id));
+
+        let ops: Vec<String> = db
+            .audit_entries(&AuditFilter::default(), 10, 0)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.operation)
+            .collect();
+        assert_eq!(ops[0], "session.restore");
+    }
+
+    #[tokio::test]
+    async fn test_restoring_keeps_the_attachments_of_archived_messages() {
+        let (db, _dir) = setup_test_db().await;
+        let actor = Actor::new("test");
+        let session = db
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        db.create_message(Message::user(session.id.clone(), "hello".to_string()))
+            .await
+            .unwrap();
+        let checkpoint = db
+            .create_snapshot(&session.id, "before the photo", &actor)
+            .await
+            .unwrap()
+            .unwrap();
+        let photo = db
+            .create_message(Message::user(session.id.clone(), "look".to_string()))
+            .await
+            .unwrap();
+        let media = StoredMedia {
+            hash: "cd".repeat(32),
+            size: 7,
+            mime_type: "image/jpeg".to_string(),
+        };
+        db.add_attachment(photo.id, &media).await.unwrap();
+
+        db.restore_snapshot(&session.id, checkpoint.id, &actor)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(db
+            .session_attachments(&session.id)
+            .await
+            .unwrap()
+            .is_empty());
+        let archived: String =
+            sqlx::query_scalar("SELECT message FROM restored_messages WHERE message_id = ?")
+                .bind(photo.id)
+                .fetch_one(&db.pool)
+                .await
+                .unwrap();
+        let archived: serde_json::Value = serde_json::from_str(&archived).unwrap();
+        assert_eq!(archived["content"], "look");
+        assert_eq!(
+            archived["attachments"],
+            serde_json::to_value([&media]).unwrap()
+        );
+        // Still referenced, so garbage collection leaves the blob alone
+        assert_eq!(
+            db.media_hashes().await.unwrap(),
+            HashSet::from([media.hash.clone()])
+        );
+
+        db.delete_session(&session.id).await.unwrap();
+        assert!(db.media_hashes().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_model_moves_sessions_on_the_retired_model() {
+        let (db, _dir) = setup_test_db().await;
+        let cli = Actor::new("cli:alice");
+        let mut ids = Vec::new();
+        for model in ["grok-2", "grok-2", "grok-3-mini"] {
+            let session = ChatSession::new(model.to_string(), None);
+            ids.push(db.create_session(session).await.unwrap().id);
+        }
+
+        let moved = db
+            .migrate_model("grok-2", "grok-3", Some(&ids[1]), &cli)
+            .await
+            .unwrap();
+        assert_eq!(moved, [ids[1].clone()]);
+        let moved = db
+            .migrate_model("grok-2", "grok-3", None, &cli)
+            .await
+            .unwrap();
+        assert_eq!(moved, [ids[0].clone()]);
+        assert!(db
+            .migrate_model("grok-2", "grok-3", None, &cli)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let mut models = Vec::new();
+        for id in &ids {
+            models.push(db.get_session(id).await.unwrap().unwrap().model);
+        }
+        assert_eq!(models, ["grok-3", "grok-3", "grok-3-mini"]);
+        let entries = db.audit_log_since(None).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries
+            .iter()
+            .all(|entry| entry.operation == "session.migrate_model"
+                && entry.detail.as_deref() == Some("grok-2 -> grok-3")));
+    }
+
+    #[tokio::test]
+    async fn test_usage_ledger_aggregates_by_model() {
+        let (db, _dir) = setup_test_db().await;
+
+        let usage = |prompt, completion| UsageStats {
+            prompt_tokens: prompt,
+            completion_tokens: completion,
+            total_tokens: prompt + completion,
+        };
+        db.record_usage(
+            Some("s1"),
+            "grok-3",
+            &usage(10, 5),
+            &CompletionStats::default(),
+        )
+        .await
+        .unwrap();
+        db.record_usage(None, "grok-3", &usage(20, 15), &CompletionStats::default())
+            .await
+            .unwrap();
+        db.record_usage(
+            Some("s2"),
+            "grok-3-mini",
+            &usage(1, 2),
+            &CompletionStats::default(),
+        )
+        .await
+        .unwrap();
+
+        let totals = db.usage_by_model().await.unwrap();
+        assert_eq!(
+            totals,
+            vec![
+                ModelUsage {
+                    model: "grok-3".to_string(),
+                    requests: 2,
+                    prompt_tokens: 30,
+                    completion_tokens: 20,
+                },
+                ModelUsage {
+                    model: "grok-3-mini".to_string(),
+                    requests: 1,
+                    prompt_tokens: 1,
+                    completion_tokens: 2,
+                },
+            ]
+        );
+    }
 }
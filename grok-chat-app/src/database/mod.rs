@@ -1,2 +1,5 @@
 pub use self::database::*;
+pub use self::transaction::*;
+#[allow(clippy::module_inception)]
 mod database;
+mod transaction;
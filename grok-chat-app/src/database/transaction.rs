@@ -0,0 +1,91 @@
+//! Write transactions that take SQLite's write lock when they begin. The
+//! server and the terminal UI can share one database file, and a deferred
+//! transaction that reads before it writes fails at once with `SQLITE_BUSY`
+//! when the other process wrote in between. Taken up front, the lock is
+//! waited for instead, for up to [`BUSY_TIMEOUT`] and a few more attempts.
+
+use anyhow::Result;
+use sqlx::pool::PoolConnection;
+use sqlx::{Sqlite, SqliteConnection, SqlitePool};
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+/// How long a statement waits for another connection's lock before failing
+/// with `SQLITE_BUSY`.
+pub const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Further attempts to begin a write transaction whose wait for the lock
+/// timed out, each after a longer pause.
+const BUSY_RETRIES: u32 = 3;
+const BUSY_BACKOFF: Duration = Duration::from_millis(50);
+
+/// A `BEGIN IMMEDIATE` transaction on a pooled connection. It derefs to the
+/// connection, like `sqlx::Transaction`, and is rolled back unless committed.
+pub struct WriteTransaction {
+    conn: Option<PoolConnection<Sqlite>>,
+}
+
+impl WriteTransaction {
+    pub async fn begin(pool: &SqlitePool) -> Result<Self> {
+        let mut conn = pool.acquire().await?;
+        let mut attempts = 0;
+        loop {
+            match sqlx::query("BEGIN IMMEDIATE").execute(&mut *conn).await {
+                Ok(_) => return Ok(Self { conn: Some(conn) }),
+                Err(e) if is_busy(&e) && attempts < BUSY_RETRIES => {
+                    attempts += 1;
+                    tokio::time::sleep(BUSY_BACKOFF * attempts).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    pub async fn commit(mut self) -> Result<()> {
+        let mut conn = self.conn.take().expect("open until committed");
+        match sqlx::query("COMMIT").execute(&mut *conn).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                // Still open, so it is rolled back on drop
+                self.conn = Some(conn);
+                Err(e.into())
+            }
+        }
+    }
+}
+
+impl Deref for WriteTransaction {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &SqliteConnection {
+        self.conn.as_ref().expect("open until committed")
+    }
+}
+
+impl DerefMut for WriteTransaction {
+    fn deref_mut(&mut self) -> &mut SqliteConnection {
+        self.conn.as_mut().expect("open until committed")
+    }
+}
+
+impl Drop for WriteTransaction {
+    fn drop(&mut self) {
+        // Closing the connection rolls the transaction back; returned to the
+        // pool instead, it would hand the next caller an open transaction
+        if let Some(conn) = self.conn.take() {
+            drop(conn.detach());
+        }
+    }
+}
+
+/// True when SQLite gave up waiting for another connection's lock.
+pub fn is_busy(error: &sqlx::Error) -> bool {
+    const SQLITE_BUSY: i32 = 5;
+    const SQLITE_LOCKED: i32 = 6;
+    let Some(error) = error.as_database_error() else {
+        return false;
+    };
+    // Extended result codes keep the primary code in the low byte
+    let code = error.code().and_then(|code| code.parse::<i32>().ok());
+    code.is_some_and(|code| matches!(code & 0xff, SQLITE_BUSY | SQLITE_LOCKED))
+}
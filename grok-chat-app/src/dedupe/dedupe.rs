@@ -0,0 +1,408 @@
+//! `sessions dedupe`: finds sessions holding the same conversation, as left
+//! behind by repeated imports or clients that retried creating a session, and
+//! merges each group into its oldest session.
+//!
+//! A session's fingerprint is a hash of its user messages in order, after
+//! normalizing case and whitespace; equal fingerprints are exact duplicates.
+//! Sessions that differ a little (a retried message, an extra reply) are
+//! caught by Jaccard similarity over their sets of messages.
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+
+use crate::audit::Actor;
+use crate::database::Database;
+use crate::models::{ChatSession, Message, MessageRole};
+
+/// Jaccard similarity at which two sessions count as duplicates.
+pub const DEFAULT_SIMILARITY: f64 = 0.8;
+
+/// Lowercases `content` and collapses its whitespace, so copies that only
+/// differ in formatting compare equal.
+pub fn normalize(content: &str) -> String {
+    content
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// What makes two messages the same message: role and normalized content.
+pub fn message_key(role: &str, content: &str) -> String {
+    format!("{}\n{}", role, normalize(content))
+}
+
+/// A session as it appears in a dedupe report.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SessionRef {
+    pub id: String,
+    pub title: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub messages: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Duplicate {
+    #[serde(flatten)]
+    pub session: SessionRef,
+    /// Same user messages in the same order as the kept session.
+    pub exact: bool,
+    /// Jaccard similarity of its messages to the kept session's.
+    pub similarity: f64,
+}
+
+/// Sessions that hold the same conversation; all merge into `keep`, the
+/// oldest.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DuplicateCluster {
+    pub keep: SessionRef,
+    pub duplicates: Vec<Duplicate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DedupeReport {
+    pub clusters: Vec<DuplicateCluster>,
+    /// Whether the clusters were merged, or only reported.
+    pub applied: bool,
+    /// Sessions merged away.
+    pub merged_sessions: usize,
+    /// Messages found only in a duplicate, moved into the kept session.
+    pub moved_messages: usize,
+}
+
+/// Rejects similarity thresholds outside (0, 1].
+pub fn check_similarity(similarity: f64) -> Result<()> {
+    if !(similarity > 0.0 && similarity <= 1.0) {
+        bail!(
+            "Similarity must be above 0 and at most 1, not {}",
+            similarity
+        );
+    }
+    Ok(())
+}
+
+/// Finds the duplicate clusters, merging them when `apply` is set. Each
+/// cluster merges in its own transaction, so an error leaves earlier merges
+/// done and the rest untouched.
+pub async fn dedupe_sessions(
+    database: &Database,
+    similarity: f64,
+    apply: bool,
+    actor: &Actor,
+) -> Result<DedupeReport> {
+    check_similarity(similarity)?;
+    let mut sessions = Vec::new();
+    for session in database.sessions_updated_since(None).await? {
+        let messages = database.get_messages(&session.id).await?;
+        sessions.push((session, messages));
+    }
+    let clusters = find_clusters(&sessions, similarity);
+
+    let (mut merged_sessions, mut moved_messages) = (0, 0);
+    if apply {
+        for cluster in &clusters {
+            let duplicates: Vec<String> = cluster
+                .duplicates
+                .iter()
+                .map(|duplicate| duplicate.session.id.clone())
+                .collect();
+            moved_messages += database
+                .merge_sessions(&cluster.keep.id, &duplicates, actor)
+                .await?;
+            merged_sessions += duplicates.len();
+        }
+    }
+    Ok(DedupeReport {
+        clusters,
+        applied: apply,
+        merged_sessions,
+        moved_messages,
+    })
+}
+
+struct Fingerprint {
+    /// Hash of the ordered, normalized user messages.
+    hash: String,
+    keys: HashSet<String>,
+}
+
+impl Fingerprint {
+    /// `None` for sessions without user messages, which have nothing to
+    /// compare. Messages seeded from a session template are shared by every
+    /// session started from it, so they are left out.
+    fn of(messages: &[Message]) -> Option<Self> {
+        let own: Vec<&Message> = messages.iter().filter(|m| !m.seeded).collect();
+        let mut hasher = Sha256::new();
+        let mut users = 0;
+        for message in own.iter().filter(|m| m.role == MessageRole::User) {
+            hasher.update(normalize(&message.content).as_bytes());
+            hasher.update([0]);
+            users += 1;
+        }
+        if users == 0 {
+            return None;
+        }
+        Some(Self {
+            hash: format!("{:x}", hasher.finalize()),
+            keys: own
+                .iter()
+                .map(|message| message_key(message.role.as_str(), &message.content))
+                .collect(),
+        })
+    }
+
+    fn similarity(&self, other: &Fingerprint) -> f64 {
+        let shared = self.keys.intersection(&other.keys).count();
+        let all = self.keys.union(&other.keys).count();
+        shared as f64 / all as f64
+    }
+}
+
+/// Groups sessions that are exact duplicates or at least `similarity` alike,
+/// directly or through another member. Clusters come oldest first.
+fn find_clusters(
+    sessions: &[(ChatSession, Vec<Message>)],
+    similarity: f64,
+) -> Vec<DuplicateCluster> {
+    let fingerprints: Vec<Option<Fingerprint>> = sessions
+        .iter()
+        .map(|(_, messages)| Fingerprint::of(messages))
+        .collect();
+
+    let mut parent: Vec<usize> = (0..sessions.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for (i, a) in fingerprints.iter().enumerate() {
+        let Some(a) = a else { continue };
+        for (j, b) in fingerprints.iter().enumerate().skip(i + 1) {
+            let Some(b) = b else { continue };
+            if a.hash == b.hash || a.similarity(b) >= similarity {
+                let (ri, rj) = (root(&mut parent, i), root(&mut parent, j));
+                parent[ri] = rj;
+            }
+        }
+    }
+
+    let mut groups: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for i in 0..sessions.len() {
+        let r = root(&mut parent, i);
+        groups.entry(r).or_default().push(i);
+    }
+
+    let session_ref = |i: usize| SessionRef {
+        id: sessions[i].0.id.clone(),
+        title: sessions[i].0.title.clone(),
+        created_at: sessions[i].0.created_at,
+        messages: sessions[i].1.len(),
+    };
+    let mut clusters: Vec<DuplicateCluster> = groups
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .map(|mut members| {
+            members.sort_by(|&a, &b| {
+                let (a, b) = (&sessions[a].0, &sessions[b].0);
+                (a.created_at, &a.id).cmp(&(b.created_at, &b.id))
+            });
+            let keep = members[0];
+            let kept = fingerprints[keep]
+                .as_ref()
+                .expect("only fingerprinted sessions cluster");
+            DuplicateCluster {
+                keep: session_ref(keep),
+                duplicates: members[1..]
+                    .iter()
+                    .map(|&i| {
+                        let fingerprint = fingerprints[i]
+                            .as_ref()
+                            .expect("only fingerprinted sessions cluster");
+                        Duplicate {
+                            session: session_ref(i),
+                            exact: fingerprint.hash == kept.hash,
+                            similarity: fingerprint.similarity(kept),
+                        }
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+    clusters.sort_by(|a, b| (a.keep.created_at, &a.keep.id).cmp(&(b.keep.created_at, &b.keep.id)));
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::models::{CompletionStats, UsageStats};
+    use chrono::Duration;
+
+    async fn database(dir: &std::path::Path) -> Database {
+        let config = Config {
+            database_url: format!("sqlite:{}?mode=rwc", dir.join("dedupe.db").display()),
+            ..Config::default()
+        };
+        Database::new(&config).await.unwrap()
+    }
+
+    /// Stores a session created `days_ago` with alternating user and
+    /// assistant messages.
+    async fn session(db: &Database, days_ago: i64, title: &str, turns: &[&str]) -> ChatSession {
+        let mut session = ChatSession::new("grok-3".to_string(), Some(title.to_string()));
+        session.created_at = Utc::now() - Duration::days(days_ago);
+        let messages: Vec<Message> = turns
+            .iter()
+            .enumerate()
+            .map(|(i, text)| {
+                let mut message = if i % 2 == 0 {
+                    Message::user(session.id.clone(), text.to_string())
+                } else {
+                    Message::assistant(session.id.clone(), text.to_string(), None)
+                };
+                message.timestamp = session.created_at + Duration::seconds(i as i64);
+                message
+            })
+            .collect();
+        db.import_session(&session, &messages, &[], None)
+            .await
+            .unwrap();
+        session
+    }
+
+    fn contents(messages: &[Message]) -> Vec<&str> {
+        messages.iter().map(|m| m.content.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_dedupe_reports_then_merges_without_losing_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = database(dir.path()).await;
+        let trip = [
+            "Plan a trip",
+            "Go to Porto",
+            "Where to eat?",
+            "Try a francesinha",
+            "Where to stay?",
+            "Near the river",
+            "How long?",
+            "Three days",
+        ];
+        let original = session(&db, 3, "Trip", &trip).await;
+        // Imported again, with different whitespace and one more exchange
+        let mut extended = trip.to_vec();
+        extended[0] = "plan a  trip";
+        extended.extend(["And to drink?", "Port, naturally"]);
+        let copy = session(&db, 2, "Trip (imported)", &extended).await;
+        // A retried send left the first exchange twice
+        let mut retried = trip[..2].to_vec();
+        retried.extend(trip);
+        let retried = session(&db, 1, "Trip", &retried).await;
+        let other = session(&db, 1, "Rust", &["Explain lifetimes", "They are scopes"]).await;
+        let usage = UsageStats {
+            prompt_tokens: 10,
+            completion_tokens: 5,
+            total_tokens: 15,
+        };
+        db.record_usage(
+            Some(&copy.id),
+            "grok-3",
+            &usage,
+            &CompletionStats::default(),
+        )
+        .await
+        .unwrap();
+
+        let report = dedupe_sessions(&db, DEFAULT_SIMILARITY, false, &Actor::new("test"))
+            .await
+            .unwrap();
+        assert!(!report.applied);
+        assert_eq!(report.clusters.len(), 1);
+        let cluster = &report.clusters[0];
+        assert_eq!(cluster.keep.id, original.id);
+        let duplicates: Vec<(&str, bool, f64)> = cluster
+            .duplicates
+            .iter()
+            .map(|d| (d.session.id.as_str(), d.exact, d.similarity))
+            .collect();
+        assert_eq!(
+            duplicates,
+            vec![
+                (copy.id.as_str(), false, 0.8),
+                (retried.id.as_str(), false, 1.0)
+            ]
+        );
+        // A dry run changes nothing
+        assert_eq!(db.sessions_updated_since(None).await.unwrap().len(), 4);
+
+        let report = dedupe_sessions(&db, DEFAULT_SIMILARITY, true, &Actor::new("test"))
+            .await
+            .unwrap();
+        assert_eq!(report.merged_sessions, 2);
+        // The extra exchange, and the second copy of the retried one
+        assert_eq!(report.moved_messages, 4);
+
+        let mut ids: Vec<String> = db
+            .sessions_updated_since(None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| s.id)
+            .collect();
+        ids.sort();
+        let mut expected = vec![original.id.clone(), other.id.clone()];
+        expected.sort();
+        assert_eq!(ids, expected);
+
+        let merged = db.get_messages(&original.id).await.unwrap();
+        let mut expected = trip.to_vec();
+        expected.extend([
+            "And to drink?",
+            "Port, naturally",
+            "Plan a trip",
+            "Go to Porto",
+        ]);
+        assert_eq!(contents(&merged), expected);
+        assert_eq!(db.usage_for_session(&original.id).await.unwrap().len(), 1);
+
+        // Nothing left to merge
+        let report = dedupe_sessions(&db, DEFAULT_SIMILARITY, true, &Actor::new("test"))
+            .await
+            .unwrap();
+        assert!(report.clusters.is_empty());
+    }
+
+    #[test]
+    fn test_sessions_without_user_messages_never_cluster() {
+        let a = ChatSession::new("grok-3".to_string(), None);
+        let b = ChatSession::new("grok-3".to_string(), None);
+        let sessions = vec![(a, Vec::new()), (b, Vec::new())];
+        assert!(find_clusters(&sessions, DEFAULT_SIMILARITY).is_empty());
+
+        // Nor do sessions holding only what their template seeded
+        let seeded = |session: ChatSession| {
+            let mut message = Message::user(session.id.clone(), "example".to_string());
+            message.seeded = true;
+            (session, vec![message])
+        };
+        let sessions = vec![
+            seeded(ChatSession::new("grok-3".to_string(), None)),
+            seeded(ChatSession::new("grok-3".to_string(), None)),
+        ];
+        assert!(find_clusters(&sessions, DEFAULT_SIMILARITY).is_empty());
+    }
+
+    #[test]
+    fn test_similarity_threshold_is_checked() {
+        assert!(check_similarity(0.8).is_ok());
+        assert!(check_similarity(1.0).is_ok());
+        assert!(check_similarity(0.0).is_err());
+        assert!(check_similarity(1.5).is_err());
+    }
+}
@@ -0,0 +1,3 @@
+pub use self::dedupe::*;
+#[allow(clippy::module_inception)]
+mod dedupe;
@@ -0,0 +1,91 @@
+//! Word-level differences between two versions of a reply, such as a reply
+//! and the regenerated one that replaced it.
+//!
+//! Text is split into words and the whitespace between them, so a diff
+//! reads as whole words added or removed. Consecutive words with the same
+//! fate are merged into one span.
+
+use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, TextDiff};
+
+/// A run of text both versions share, or that only one of them has.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "op", content = "text", rename_all = "snake_case")]
+pub enum DiffSpan {
+    Equal(String),
+    /// Only in the newer version.
+    Insert(String),
+    /// Only in the older version.
+    Delete(String),
+}
+
+impl DiffSpan {
+    pub fn text(&self) -> &str {
+        match self {
+            DiffSpan::Equal(text) | DiffSpan::Insert(text) | DiffSpan::Delete(text) => text,
+        }
+    }
+}
+
+/// The spans that turn `old` into `new`, in reading order. Concatenating
+/// the `Equal` and `Delete` spans gives `old`; `Equal` and `Insert`, `new`.
+pub fn word_diff(old: &str, new: &str) -> Vec<DiffSpan> {
+    let diff = TextDiff::from_words(old, new);
+    let mut spans: Vec<DiffSpan> = Vec::new();
+    for change in diff.iter_all_changes() {
+        let text = change.value();
+        match (spans.last_mut(), change.tag()) {
+            (Some(DiffSpan::Equal(last)), ChangeTag::Equal)
+            | (Some(DiffSpan::Insert(last)), ChangeTag::Insert)
+            | (Some(DiffSpan::Delete(last)), ChangeTag::Delete) => last.push_str(text),
+            (_, ChangeTag::Equal) => spans.push(DiffSpan::Equal(text.to_string())),
+            (_, ChangeTag::Insert) => spans.push(DiffSpan::Insert(text.to_string())),
+            (_, ChangeTag::Delete) => spans.push(DiffSpan::Delete(text.to_string())),
+        }
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn side(spans: &[DiffSpan], keep: fn(&DiffSpan) -> bool) -> String {
+        spans
+            .iter()
+            .filter(|span| keep(span))
+            .map(DiffSpan::text)
+            .collect()
+    }
+
+    #[test]
+    fn test_word_diff_marks_whole_words() {
+        let spans = word_diff("The sky is blue today.", "The sky is grey today.");
+        assert_eq!(
+            spans,
+            vec![
+                DiffSpan::Equal("The sky is ".into()),
+                DiffSpan::Delete("blue".into()),
+                DiffSpan::Insert("grey".into()),
+                DiffSpan::Equal(" today.".into()),
+            ]
+        );
+        assert!(word_diff("same", "same")
+            .iter()
+            .all(|span| matches!(span, DiffSpan::Equal(_))));
+    }
+
+    #[test]
+    fn test_word_diff_rebuilds_both_versions() {
+        let old = "First line.\nSecond line, kept.\n\nA removed paragraph.";
+        let new = "First line, changed.\nSecond line, kept.\n\nAn added ending!";
+        let spans = word_diff(old, new);
+        assert_eq!(side(&spans, |s| !matches!(s, DiffSpan::Insert(_))), old);
+        assert_eq!(side(&spans, |s| !matches!(s, DiffSpan::Delete(_))), new);
+
+        let json = serde_json::to_value(&spans[1]).unwrap();
+        assert_eq!(json["op"], "delete");
+        assert!(json["text"].is_string());
+    }
+}
@@ -0,0 +1,3 @@
+pub use self::diff::*;
+#[allow(clippy::module_inception)]
+mod diff;
@@ -0,0 +1,297 @@
+//! Session exports for `GET /sessions/:id/export`: the JSON `sessions export
+//! --format json` writes, produced a page of messages at a time so a long
+//! session is never held in memory whole. While the session is unchanged the
+//! bytes are the same on every request, so a download that broke off can
+//! resume from where it stopped with a `Range` request. As with `sessions
+//! export`, the messages a session template seeded are left out unless asked
+//! for.
+
+use anyhow::{bail, Result};
+use std::fmt;
+use std::io;
+use tokio::sync::mpsc;
+
+use crate::database::{Database, SessionVersion};
+use crate::models::{ChatSession, Message};
+
+/// Messages read per query.
+pub const EXPORT_PAGE: i64 = 500;
+
+/// The session changed while its export was being read, so the pieces no
+/// longer fit together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionChanged {
+    pub session_id: String,
+}
+
+impl fmt::Display for SessionChanged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Session {} changed during the export; download it again",
+            self.session_id
+        )
+    }
+}
+
+impl std::error::Error for SessionChanged {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Part {
+    Head,
+    Messages,
+    Done,
+}
+
+/// One session's export, read as it is written. The pieces joined are the
+/// same bytes as `serde_json::to_vec(&SessionExport)`.
+pub struct ExportChunks {
+    database: Database,
+    session: ChatSession,
+    version: SessionVersion,
+    /// The last message written, where the next page starts.
+    after: Option<Message>,
+    part: Part,
+    page: i64,
+    include_seeded: bool,
+}
+
+impl ExportChunks {
+    /// `None` for an unknown session. Seeded messages are written only with
+    /// `include_seeded`.
+    pub async fn open(
+        database: Database,
+        session_id: &str,
+        include_seeded: bool,
+    ) -> Result<Option<Self>> {
+        let Some(version) = database.session_version(session_id).await? else {
+            return Ok(None);
+        };
+        let Some(session) = database.get_session(session_id).await? else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            database,
+            session,
+            version,
+            after: None,
+            part: Part::Head,
+            page: EXPORT_PAGE,
+            include_seeded,
+        }))
+    }
+
+    /// A strong validator for the export, from the session's `updated_at`,
+    /// message count and newest message. Exports with and without the seeded
+    /// messages are different bytes, so they get different tags.
+    pub fn etag(&self) -> String {
+        format!(
+            "\"{}-{}-{}{}\"",
+            self.version.updated_at.timestamp_micros(),
+            self.version.messages,
+            self.version.last_message_id,
+            if self.include_seeded { "-seeded" } else { "" }
+        )
+    }
+
+    /// The next piece of the export, `None` once all of it is written. Fails
+    /// with `SessionChanged` when a page is read after the session changed.
+    pub async fn next(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut out = Vec::new();
+        match self.part {
+            Part::Head => {
+                out.extend_from_slice(b"{\"session\":");
+                serde_json::to_writer(&mut out, &self.session)?;
+                out.extend_from_slice(b",\"messages\":[");
+                self.part = Part::Messages;
+            }
+            Part::Messages => {
+                let page = self
+                    .database
+                    .messages_page(
+                        &self.session.id,
+                        self.after.as_ref(),
+                        self.page,
+                        self.include_seeded,
+                    )
+                    .await?;
+                if self.database.session_version(&self.session.id).await? != Some(self.version) {
+                    bail!(SessionChanged {
+                        session_id: self.session.id.clone(),
+                    });
+                }
+                for message in &page {
+                    if self.after.is_some() || !out.is_empty() {
+                        out.push(b',');
+                    }
+                    serde_json::to_writer(&mut out, message)?;
+                }
+                if (page.len() as i64) < self.page {
+                    out.extend_from_slice(b"]}");
+                    self.part = Part::Done;
+                }
+                if let Some(last) = page.into_iter().last() {
+                    self.after = Some(last);
+                }
+            }
+            Part::Done => return Ok(None),
+        }
+        Ok(Some(out))
+    }
+
+    /// How many bytes the whole export is. Reads all of it, a page at a time.
+    pub async fn byte_len(mut self) -> Result<u64> {
+        let mut total = 0;
+        while let Some(chunk) = self.next().await? {
+            total += chunk.len() as u64;
+        }
+        Ok(total)
+    }
+
+    /// Sends bytes `first..=last` of the export to `sender`. An error is sent
+    /// on as well, which cuts the download short rather than leaving a gap.
+    pub async fn send_range(
+        mut self,
+        first: u64,
+        last: u64,
+        sender: mpsc::Sender<io::Result<Vec<u8>>>,
+    ) {
+        let mut offset = 0;
+        loop {
+            let chunk = match self.next().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => return,
+                Err(e) => {
+                    let _ = sender.send(Err(io::Error::other(e.to_string()))).await;
+                    return;
+                }
+            };
+            let (from, to) = (offset, offset + chunk.len() as u64);
+            offset = to;
+            if to <= first {
+                continue;
+            }
+            let start = first.saturating_sub(from) as usize;
+            let end = ((last + 1).min(to) - from) as usize;
+            if sender.send(Ok(chunk[start..end].to_vec())).await.is_err() || to > last {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[tokio::test]
+    async fn test_export_pages_match_the_whole_export() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = Database::new(&Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            ..Config::default()
+        })
+        .await
+        .unwrap();
+        let session = database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        for i in 0..5 {
+            database
+                .create_message(Message::user(session.id.clone(), format!("message {}", i)))
+                .await
+                .unwrap();
+        }
+        let whole =
+            serde_json::to_vec(&database.export_session(&session.id).await.unwrap().unwrap())
+                .unwrap();
+
+        for page in [2, 5, 500] {
+            let mut chunks = ExportChunks::open(database.clone(), &session.id, false)
+                .await
+                .unwrap()
+                .unwrap();
+            chunks.page = page;
+            let mut written = Vec::new();
+            while let Some(chunk) = chunks.next().await.unwrap() {
+                written.extend(chunk);
+            }
+            assert_eq!(
+                String::from_utf8(written).unwrap(),
+                String::from_utf8(whole.clone()).unwrap()
+            );
+        }
+
+        let (sender, mut receiver) = mpsc::channel(8);
+        let chunks = ExportChunks::open(database.clone(), &session.id, false)
+            .await
+            .unwrap()
+            .unwrap();
+        chunks.send_range(20, 99, sender).await;
+        let mut sent = Vec::new();
+        while let Some(chunk) = receiver.recv().await {
+            sent.extend(chunk.unwrap());
+        }
+        assert_eq!(sent, whole[20..100]);
+
+        // A message added part way through spoils the rest
+        let mut chunks = ExportChunks::open(database.clone(), &session.id, false)
+            .await
+            .unwrap()
+            .unwrap();
+        chunks.page = 2;
+        chunks.next().await.unwrap();
+        chunks.next().await.unwrap();
+        database
+            .create_message(Message::user(session.id.clone(), "late".to_string()))
+            .await
+            .unwrap();
+        let error = chunks.next().await.unwrap_err();
+        assert!(error.is::<SessionChanged>());
+        assert!(ExportChunks::open(database, "missing", false)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_seeded_messages_are_exported_only_when_asked_for() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = Database::new(&Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            ..Config::default()
+        })
+        .await
+        .unwrap();
+        let session = database
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        let mut example = Message::user(session.id.clone(), "Example".to_string());
+        example.seeded = true;
+        database.create_message(example).await.unwrap();
+        database
+            .create_message(Message::user(session.id.clone(), "Own".to_string()))
+            .await
+            .unwrap();
+        let export = database.export_session(&session.id).await.unwrap().unwrap();
+
+        let mut etags = Vec::new();
+        for (include_seeded, expected) in [(false, export.clone().without_seeded()), (true, export)]
+        {
+            let mut chunks = ExportChunks::open(database.clone(), &session.id, include_seeded)
+                .await
+                .unwrap()
+                .unwrap();
+            etags.push(chunks.etag());
+            let mut written = Vec::new();
+            while let Some(chunk) = chunks.next().await.unwrap() {
+                written.extend(chunk);
+            }
+            assert_eq!(written, serde_json::to_vec(&expected).unwrap());
+        }
+        assert_ne!(etags[0], etags[1]);
+    }
+}
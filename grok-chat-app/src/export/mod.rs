@@ -0,0 +1,3 @@
+pub use self::export::*;
+#[allow(clippy::module_inception)]
+mod export;
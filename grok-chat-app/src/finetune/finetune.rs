@@ -0,0 +1,299 @@
+//! Sessions as fine-tuning data, in OpenAI's chat JSON Lines format: each
+//! line is `{"messages": [{"role", "content"}, ...]}`, holding a whole
+//! session or, per turn, one user message and the reply to it. Only the
+//! conversation itself goes in: system messages, the examples a session
+//! template seeded, queued messages and replaced replies are left out, and
+//! the text is redacted as configured.
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::context::estimate_message_tokens;
+use crate::database::Database;
+use crate::models::{Annotation, ApiMessage, ChatSession, Message, MessageRole, Rating};
+use crate::redact::Redactor;
+
+/// Which sessions, and which parts of them, become examples. Unset fields
+/// match everything.
+#[derive(Debug, Clone, Default)]
+pub struct FinetuneFilter {
+    /// Only sessions with a message annotated with this label; per turn,
+    /// only replies that are.
+    pub tag: Option<String>,
+    /// Only sessions with a message rated this way; per turn, only replies
+    /// that are.
+    pub rating: Option<Rating>,
+    /// Only sessions last active at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Only sessions last active before this time.
+    pub until: Option<DateTime<Utc>>,
+    /// One example per exchange rather than per session.
+    pub per_turn: bool,
+}
+
+impl FinetuneFilter {
+    fn matches(&self, annotation: Option<&Annotation>) -> bool {
+        if self.tag.is_none() && self.rating.is_none() {
+            return true;
+        }
+        let Some(annotation) = annotation else {
+            return false;
+        };
+        let tagged = self
+            .tag
+            .as_ref()
+            .is_none_or(|tag| annotation.labels.contains(tag));
+        tagged
+            && self
+                .rating
+                .is_none_or(|rating| annotation.rating == Some(rating))
+    }
+
+    fn in_range(&self, session: &ChatSession) -> bool {
+        self.until.is_none_or(|until| session.updated_at < until)
+    }
+}
+
+/// One line of the dataset.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FinetuneExample {
+    pub messages: Vec<ApiMessage>,
+}
+
+/// What went into a dataset.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FinetuneReport {
+    /// Sessions with at least one example.
+    pub sessions_included: usize,
+    /// Sessions in the date range that the filter or their contents left
+    /// without any.
+    pub sessions_skipped: usize,
+    pub examples: usize,
+    /// Estimated tokens of every example, as elsewhere.
+    pub tokens: usize,
+    /// Secrets and personal data masked.
+    pub redactions: usize,
+}
+
+/// The examples in a session's `messages`, in conversation order: the whole
+/// conversation as one, ending with its last reply, or each reply with the
+/// user message before it.
+pub fn session_examples(messages: &[Message], filter: &FinetuneFilter) -> Vec<FinetuneExample> {
+    let conversation: Vec<&Message> = messages
+        .iter()
+        .filter(|message| {
+            matches!(message.role, MessageRole::User | MessageRole::Assistant)
+                && !message.seeded
+                && !message.is_queued()
+                && message.superseded_by.is_none()
+        })
+        .collect();
+    let example = |messages: &[&Message]| FinetuneExample {
+        messages: messages
+            .iter()
+            .map(|message| ApiMessage::new(message.role.clone(), message.content.clone()))
+            .collect(),
+    };
+
+    if filter.per_turn {
+        return conversation
+            .windows(2)
+            .filter(|pair| {
+                pair[0].role == MessageRole::User
+                    && pair[1].role == MessageRole::Assistant
+                    && filter.matches(pair[1].annotation.as_ref())
+            })
+            .map(example)
+            .collect();
+    }
+
+    if !conversation
+        .iter()
+        .any(|message| filter.matches(message.annotation.as_ref()))
+    {
+        return Vec::new();
+    }
+    // A question never answered teaches nothing
+    let Some(last_reply) = conversation
+        .iter()
+        .rposition(|message| message.role == MessageRole::Assistant)
+    else {
+        return Vec::new();
+    };
+    let conversation = &conversation[..=last_reply];
+    if !conversation
+        .iter()
+        .any(|message| message.role == MessageRole::User)
+    {
+        return Vec::new();
+    }
+    vec![example(conversation)]
+}
+
+/// `example` as a line of JSON, newline included. The line is read back and
+/// compared, so nothing that fails to parse the same way ends up in a dataset.
+pub fn to_line(example: &FinetuneExample) -> Result<String> {
+    let line = serde_json::to_string(example)?;
+    if serde_json::from_str::<FinetuneExample>(&line)? != *example {
+        bail!("An example did not read back as written: {}", line);
+    }
+    Ok(line + "\n")
+}
+
+/// A dataset, read a session at a time so it never has to fit in memory.
+pub struct FinetuneExport {
+    database: Database,
+    filter: FinetuneFilter,
+    redactor: Option<Redactor>,
+    /// Sessions still to read, oldest first.
+    sessions: std::vec::IntoIter<ChatSession>,
+    report: FinetuneReport,
+}
+
+impl FinetuneExport {
+    /// `redactor` masks the text of every example; `None` leaves it as stored.
+    pub async fn open(
+        database: Database,
+        filter: FinetuneFilter,
+        redactor: Option<Redactor>,
+    ) -> Result<Self> {
+        let sessions: Vec<ChatSession> = database
+            .sessions_updated_since(filter.since)
+            .await?
+            .into_iter()
+            .filter(|session| filter.in_range(session))
+            .collect();
+        Ok(Self {
+            database,
+            filter,
+            redactor,
+            sessions: sessions.into_iter(),
+            report: FinetuneReport::default(),
+        })
+    }
+
+    /// The lines of the next session that has examples, `None` once every
+    /// session is read.
+    pub async fn next(&mut self) -> Result<Option<String>> {
+        for session in self.sessions.by_ref() {
+            let messages = self.database.get_messages(&session.id).await?;
+            let examples = session_examples(&messages, &self.filter);
+            if examples.is_empty() {
+                self.report.sessions_skipped += 1;
+                continue;
+            }
+
+            let mut lines = String::new();
+            for mut example in examples {
+                if let Some(redactor) = &self.redactor {
+                    for message in &mut example.messages {
+                        self.report.redactions += redactor.redact(&mut message.content);
+                    }
+                }
+                lines.push_str(&to_line(&example)?);
+                self.report.examples += 1;
+                self.report.tokens += estimate_message_tokens(&example.messages);
+            }
+            self.report.sessions_included += 1;
+            return Ok(Some(lines));
+        }
+        Ok(None)
+    }
+
+    /// What went in so far; all of it once `next` has returned `None`.
+    pub fn report(&self) -> &FinetuneReport {
+        &self.report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: i64, role: MessageRole, content: &str) -> Message {
+        let mut message = Message::new("s1".to_string(), role, content.to_string(), None);
+        message.id = id;
+        message
+    }
+
+    fn contents(example: &FinetuneExample) -> Vec<&str> {
+        example
+            .messages
+            .iter()
+            .map(|message| message.content.as_str())
+            .collect()
+    }
+
+    fn golden(mut message: Message) -> Message {
+        message.annotation = Some(Annotation {
+            rating: Some(Rating::Up),
+            labels: vec!["golden".to_string()],
+            ..Annotation::default()
+        });
+        message
+    }
+
+    #[test]
+    fn test_examples_hold_only_the_live_conversation() {
+        let mut seeded = message(2, MessageRole::User, "Example question");
+        seeded.seeded = true;
+        let mut replaced = message(4, MessageRole::Assistant, "Paris?");
+        replaced.superseded_by = Some(5);
+        let mut queued = message(7, MessageRole::User, "Still offline");
+        queued.status = crate::models::MessageStatus::Queued;
+        let messages = [
+            message(1, MessageRole::System, "Be brief."),
+            seeded,
+            message(3, MessageRole::User, "Capital of Portugal?"),
+            replaced,
+            golden(message(5, MessageRole::Assistant, "Lisbon")),
+            message(6, MessageRole::User, "Unanswered"),
+            queued,
+        ];
+
+        let whole = session_examples(&messages, &FinetuneFilter::default());
+        assert_eq!(whole.len(), 1);
+        assert_eq!(contents(&whole[0]), ["Capital of Portugal?", "Lisbon"]);
+        assert_eq!(whole[0].messages[1].role, "assistant");
+
+        let tagged = FinetuneFilter {
+            tag: Some("golden".to_string()),
+            ..FinetuneFilter::default()
+        };
+        assert_eq!(session_examples(&messages, &tagged), whole);
+        let rejected = FinetuneFilter {
+            rating: Some(Rating::Down),
+            ..FinetuneFilter::default()
+        };
+        assert!(session_examples(&messages, &rejected).is_empty());
+    }
+
+    #[test]
+    fn test_per_turn_examples_pair_replies_with_their_question() {
+        let messages = [
+            message(1, MessageRole::User, "One?"),
+            message(2, MessageRole::Assistant, "1"),
+            message(3, MessageRole::User, "Two?"),
+            golden(message(4, MessageRole::Assistant, "2")),
+        ];
+        let per_turn = FinetuneFilter {
+            per_turn: true,
+            ..FinetuneFilter::default()
+        };
+        let examples = session_examples(&messages, &per_turn);
+        assert_eq!(examples.len(), 2);
+        assert_eq!(contents(&examples[1]), ["Two?", "2"]);
+
+        let golden_turns = FinetuneFilter {
+            tag: Some("golden".to_string()),
+            ..per_turn
+        };
+        let examples = session_examples(&messages, &golden_turns);
+        assert_eq!(examples.len(), 1);
+        assert_eq!(
+            to_line(&examples[0]).unwrap(),
+            "{\"messages\":[{\"role\":\"user\",\"content\":\"Two?\"},{\"role\":\"assistant\",\"content\":\"2\"}]}\n"
+        );
+    }
+}
@@ -0,0 +1,3 @@
+pub use self::finetune::*;
+#[allow(clippy::module_inception)]
+mod finetune;
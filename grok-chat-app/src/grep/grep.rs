@@ -0,0 +1,289 @@
+//! `sessions grep`: finds lines of saved messages that match a pattern and
+//! prints them the way ripgrep does, grouped under their session.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+
+use crate::models::{Message, MessageRole};
+use crate::sanitize::Sanitizer;
+
+const MATCH_COLOR: &str = "\x1b[1;31m";
+const HEADER_COLOR: &str = "\x1b[35m";
+const LINE_NUMBER_COLOR: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+
+/// A compiled search pattern.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    regex: Regex,
+    literal: Option<String>,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<Self> {
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .with_context(|| format!("Invalid pattern '{}'", pattern))?;
+        // SQL LIKE ignores ASCII case only, so it can't stand in for a
+        // case-insensitive search for other letters
+        let plain = !pattern.is_empty() && regex::escape(pattern) == pattern;
+        let literal = (plain && (!ignore_case || pattern.is_ascii())).then(|| pattern.to_string());
+        Ok(Self { regex, literal })
+    }
+
+    /// Text every matching message contains, ignoring ASCII case, for the
+    /// database to narrow the scan with. `None` when every message has to
+    /// be checked.
+    pub fn literal(&self) -> Option<&str> {
+        self.literal.as_deref()
+    }
+
+    /// The lines of `message` that match, each with up to `context` lines
+    /// of the message on either side.
+    pub fn matches(
+        &self,
+        message: &Message,
+        session_title: Option<&str>,
+        context: usize,
+    ) -> Vec<LineMatch> {
+        let lines: Vec<&str> = message.content.lines().collect();
+        let mut matches = Vec::new();
+        for (index, line) in lines.iter().enumerate() {
+            let ranges: Vec<(usize, usize)> = self
+                .regex
+                .find_iter(line)
+                .map(|found| (found.start(), found.end()))
+                .collect();
+            if ranges.is_empty() {
+                continue;
+            }
+            let after_end = (index + 1 + context).min(lines.len());
+            matches.push(LineMatch {
+                session_id: message.session_id.clone(),
+                session_title: session_title.map(str::to_string),
+                message_id: message.id,
+                timestamp: message.timestamp,
+                role: message.role.clone(),
+                line_number: index + 1,
+                line: line.to_string(),
+                ranges,
+                before: lines[index.saturating_sub(context)..index]
+                    .iter()
+                    .map(|line| line.to_string())
+                    .collect(),
+                after: lines[index + 1..after_end]
+                    .iter()
+                    .map(|line| line.to_string())
+                    .collect(),
+            });
+        }
+        matches
+    }
+}
+
+/// One matching line; `--output json` prints one per line.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LineMatch {
+    pub session_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_title: Option<String>,
+    pub message_id: i64,
+    pub timestamp: DateTime<Utc>,
+    pub role: MessageRole,
+    /// Counted from 1 within the message.
+    pub line_number: usize,
+    pub line: String,
+    /// Byte offsets of each match within `line`.
+    pub ranges: Vec<(usize, usize)>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub before: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub after: Vec<String>,
+}
+
+/// How text results are printed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TextStyle {
+    /// Show control characters as placeholders; see `crate::sanitize`.
+    pub sanitize: bool,
+    /// Highlight matches (implies `sanitize`).
+    pub color: bool,
+    /// Print `--` between lines that aren't adjacent.
+    pub separators: bool,
+}
+
+impl TextStyle {
+    /// The line a session's matches are printed under.
+    pub fn session_header(&self, session_id: &str, title: Option<&str>) -> String {
+        let heading = format!("{} ({})", title.unwrap_or("(untitled)"), session_id);
+        if self.color {
+            format!("{}{}{}\n", HEADER_COLOR, self.clean(&heading), RESET)
+        } else {
+            format!("{}\n", self.clean(&heading))
+        }
+    }
+
+    /// The matches within one message, as `timestamp role:line:text`, with
+    /// context lines as `timestamp role-line-text`. Context lines shared by
+    /// neighbouring matches are printed once.
+    pub fn format_message(&self, matches: &[LineMatch]) -> String {
+        let mut out = String::new();
+        let mut printed = 0;
+        for (index, found) in matches.iter().enumerate() {
+            let prefix = format!(
+                "{} {}",
+                found.timestamp.format("%Y-%m-%d %H:%M"),
+                found.role
+            );
+            let first = found.line_number - found.before.len();
+            if self.separators && printed > 0 && first > printed + 1 {
+                out.push_str("--\n");
+            }
+            for (offset, line) in found.before.iter().enumerate() {
+                if first + offset > printed {
+                    out.push_str(&self.line(&prefix, '-', first + offset, line, &[]));
+                }
+            }
+            out.push_str(&self.line(&prefix, ':', found.line_number, &found.line, &found.ranges));
+            printed = found.line_number;
+
+            let next = matches.get(index + 1).map(|next| next.line_number);
+            for line in &found.after {
+                if next.is_some_and(|next| printed + 1 >= next) {
+                    break;
+                }
+                printed += 1;
+                out.push_str(&self.line(&prefix, '-', printed, line, &[]));
+            }
+        }
+        out
+    }
+
+    fn line(
+        &self,
+        prefix: &str,
+        separator: char,
+        number: usize,
+        text: &str,
+        ranges: &[(usize, usize)],
+    ) -> String {
+        if !self.color {
+            return format!(
+                "{}{}{}{}{}\n",
+                prefix,
+                separator,
+                number,
+                separator,
+                self.clean(text)
+            );
+        }
+
+        let mut sanitizer = Sanitizer::new();
+        let mut highlighted = String::new();
+        let mut end_of_last = 0;
+        for &(start, end) in ranges {
+            highlighted.push_str(&sanitizer.push(&text[end_of_last..start]));
+            highlighted.push_str(MATCH_COLOR);
+            highlighted.push_str(&sanitizer.push(&text[start..end]));
+            highlighted.push_str(RESET);
+            end_of_last = end;
+        }
+        highlighted.push_str(&sanitizer.push(&text[end_of_last..]));
+        highlighted.push_str(&sanitizer.finish());
+        format!(
+            "{}{}{}{}{}{}{}\n",
+            prefix, separator, LINE_NUMBER_COLOR, number, RESET, separator, highlighted
+        )
+    }
+
+    fn clean(&self, text: &str) -> String {
+        if self.sanitize || self.color {
+            let mut sanitizer = Sanitizer::new();
+            let mut cleaned = sanitizer.push(text);
+            cleaned.push_str(&sanitizer.finish());
+            cleaned
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(content: &str) -> Message {
+        let mut message = Message::user("s1".to_string(), content.to_string());
+        message.timestamp = DateTime::parse_from_rfc3339("2025-01-02T03:04:05Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        message
+    }
+
+    #[test]
+    fn test_only_plain_patterns_narrow_the_scan() {
+        assert_eq!(
+            Pattern::new("tokio", false).unwrap().literal(),
+            Some("tokio")
+        );
+        assert_eq!(Pattern::new("to.io", false).unwrap().literal(), None);
+        assert_eq!(Pattern::new("Grüße", true).unwrap().literal(), None);
+        assert_eq!(
+            Pattern::new("Grüße", false).unwrap().literal(),
+            Some("Grüße")
+        );
+        assert!(Pattern::new("(", false).is_err());
+    }
+
+    #[test]
+    fn test_matches_carry_ranges_and_context() {
+        let pattern = Pattern::new("rust", true).unwrap();
+        let matches = pattern.matches(&message("one\nRust and rust\nthree\nfour"), Some("T"), 1);
+
+        assert_eq!(matches.len(), 1);
+        let found = &matches[0];
+        assert_eq!(found.line_number, 2);
+        assert_eq!(found.ranges, [(0, 4), (9, 13)]);
+        assert_eq!(found.before, ["one"]);
+        assert_eq!(found.after, ["three"]);
+        assert_eq!(found.session_title.as_deref(), Some("T"));
+    }
+
+    #[test]
+    fn test_overlapping_context_is_printed_once() {
+        let pattern = Pattern::new("x", false).unwrap();
+        let content = "x1\na\nx2\nb\nc\nd\nx3";
+        let matches = pattern.matches(&message(content), None, 1);
+        let style = TextStyle {
+            separators: true,
+            ..TextStyle::default()
+        };
+
+        assert_eq!(
+            style.format_message(&matches),
+            "2025-01-02 03:04 user:1:x1\n\
+             2025-01-02 03:04 user-2-a\n\
+             2025-01-02 03:04 user:3:x2\n\
+             2025-01-02 03:04 user-4-b\n\
+             --\n\
+             2025-01-02 03:04 user-6-d\n\
+             2025-01-02 03:04 user:7:x3\n"
+        );
+    }
+
+    #[test]
+    fn test_color_highlights_matches_and_defuses_escapes() {
+        let pattern = Pattern::new("ab", false).unwrap();
+        let matches = pattern.matches(&message("\x1b[2Jab"), None, 0);
+        let style = TextStyle {
+            color: true,
+            ..TextStyle::default()
+        };
+
+        let text = style.format_message(&matches);
+        assert!(text.contains("␛[2J\x1b[1;31mab\x1b[0m"));
+    }
+}
@@ -0,0 +1,3 @@
+pub use self::grep::*;
+#[allow(clippy::module_inception)]
+mod grep;
@@ -0,0 +1,464 @@
+//! User scripts run as slash commands. An executable `cmd-<name>` in the
+//! hooks directory answers `/<name> args...` in the terminal UI and the
+//! interactive CLI.
+//!
+//! The script gets the arguments as its own, the session id in
+//! `GROK_SESSION_ID`, and on stdin a JSON object with `session_id`, `args`
+//! and the most recent `messages` (`role` and `content`). Its stdout is put
+//! in the input box when it exits 0, and sent as the next message when it
+//! exits 3. Any other exit is a failure, reported with its stderr. Scripts
+//! that run too long are killed, and output over the size limit is thrown
+//! away.
+//!
+//! Hooks run with the user's permissions, so each one is confirmed before
+//! its first run. The confirmation is tied to the script's content hash: an
+//! edited hook asks again.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+
+use crate::config::config_dir;
+use crate::i18n::{tr, tr_args};
+use crate::models::ApiMessage;
+
+/// File name prefix that makes a script in the hooks directory a command.
+pub const HOOK_PREFIX: &str = "cmd-";
+
+/// Set for the script to the current session's id, when there is a session.
+pub const SESSION_ID_VAR: &str = "GROK_SESSION_ID";
+
+/// How long a hook may run before it is killed.
+pub const HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Most stdout a hook may write, in bytes.
+pub const MAX_HOOK_OUTPUT: usize = 64 * 1024;
+
+/// Stderr kept for the failure message, in bytes.
+const MAX_HOOK_STDERR: usize = 4 * 1024;
+
+/// Most recent messages passed to a hook.
+pub const HOOK_MESSAGES: usize = 20;
+
+/// Exit code asking for the output to be sent rather than edited first.
+pub const SEND_EXIT_CODE: i32 = 3;
+
+/// Where hooks are kept (`~/.config/grok-chat/hooks` on Linux).
+pub fn hooks_dir() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("hooks"))
+}
+
+/// The hooks in a directory, and the record of which ones were confirmed.
+#[derive(Debug, Clone)]
+pub struct Hooks {
+    dir: PathBuf,
+    trust_file: PathBuf,
+}
+
+/// An executable found for a command name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hook {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// What a hook passes on, by its exit code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookOutput {
+    /// Exit 0: put in the input box to review.
+    Insert(String),
+    /// Exit 3: send as the next message.
+    Send(String),
+}
+
+/// Why a hook's output could not be used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookError {
+    TimedOut,
+    TooMuchOutput,
+    NotUtf8,
+    Failed { status: String, stderr: String },
+}
+
+impl fmt::Display for HookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            HookError::TimedOut => tr_args("hooks.timed_out", &[("secs", &HOOK_TIMEOUT.as_secs())]),
+            HookError::TooMuchOutput => tr_args(
+                "hooks.too_much_output",
+                &[("kib", &(MAX_HOOK_OUTPUT / 1024))],
+            ),
+            HookError::NotUtf8 => tr("hooks.not_utf8").to_string(),
+            HookError::Failed { status, stderr } => tr_args(
+                "hooks.failed",
+                &[("status", status), ("stderr", &stderr.trim())],
+            ),
+        };
+        f.write_str(&text)
+    }
+}
+
+impl std::error::Error for HookError {}
+
+/// What a hook reads on stdin.
+#[derive(Debug, Serialize)]
+struct HookInput<'a> {
+    session_id: Option<&'a str>,
+    args: &'a str,
+    messages: &'a [ApiMessage],
+}
+
+impl Hooks {
+    pub fn new(dir: PathBuf, trust_file: PathBuf) -> Self {
+        Self { dir, trust_file }
+    }
+
+    /// The user's hooks directory, with confirmations kept next to it.
+    pub fn from_config() -> Option<Self> {
+        let dir = hooks_dir()?;
+        let trust_file = config_dir()?.join("trusted-hooks.json");
+        Some(Self::new(dir, trust_file))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The hook answering `/<name>`, if there is an executable for it.
+    pub fn find(&self, name: &str) -> Option<Hook> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return None;
+        }
+        let path = self.dir.join(format!("{}{}", HOOK_PREFIX, name));
+        is_executable(&path).then(|| Hook {
+            name: name.to_string(),
+            path,
+        })
+    }
+
+    /// Names of the available hooks, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry
+                    .file_name()
+                    .to_str()?
+                    .strip_prefix(HOOK_PREFIX)?
+                    .to_string();
+                self.find(&name).map(|hook| hook.name)
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Whether `hook` was confirmed as it is now.
+    pub fn is_trusted(&self, hook: &Hook) -> Result<bool> {
+        let digest = content_digest(&hook.path)?;
+        Ok(self.trusted()?.get(&trust_key(&hook.path)) == Some(&digest))
+    }
+
+    /// Records that `hook`, as it is now, may run without asking.
+    pub fn trust(&self, hook: &Hook) -> Result<()> {
+        let mut trusted = self.trusted()?;
+        trusted.insert(trust_key(&hook.path), content_digest(&hook.path)?);
+        if let Some(parent) = self.trust_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.trust_file, serde_json::to_string_pretty(&trusted)?)
+            .with_context(|| format!("Failed to write {}", self.trust_file.display()))
+    }
+
+    /// Confirmed content hashes, by script path.
+    fn trusted(&self) -> Result<BTreeMap<String, String>> {
+        match fs::read_to_string(&self.trust_file) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("{} is corrupt", self.trust_file.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Hook {
+    /// Runs the hook with `args`, giving it the session id and the last
+    /// `HOOK_MESSAGES` of `messages`.
+    pub async fn run(
+        &self,
+        args: &str,
+        session_id: Option<&str>,
+        messages: &[ApiMessage],
+    ) -> Result<HookOutput> {
+        self.run_with_timeout(args, session_id, messages, HOOK_TIMEOUT)
+            .await
+    }
+
+    async fn run_with_timeout(
+        &self,
+        args: &str,
+        session_id: Option<&str>,
+        messages: &[ApiMessage],
+        timeout: Duration,
+    ) -> Result<HookOutput> {
+        let recent = &messages[messages.len().saturating_sub(HOOK_MESSAGES)..];
+        let input = serde_json::to_vec(&HookInput {
+            session_id,
+            args,
+            messages: recent,
+        })?;
+
+        let mut command = Command::new(&self.path);
+        command
+            .args(args.split_whitespace())
+            .current_dir(self.path.parent().unwrap_or(Path::new(".")))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        match session_id {
+            Some(id) => command.env(SESSION_ID_VAR, id),
+            None => command.env_remove(SESSION_ID_VAR),
+        };
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("Failed to run {}", self.path.display()))?;
+
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let finished = async {
+            // A hook that ignores stdin may close it before reading it all
+            let write = async {
+                let _ = stdin.write_all(&input).await;
+                drop(stdin);
+                Ok(())
+            };
+            let (_, stdout, stderr) =
+                tokio::try_join!(write, read_output(stdout), read_stderr(stderr),)?;
+            let status = child.wait().await?;
+            Ok::<_, anyhow::Error>((status, stdout, stderr))
+        };
+        // Dropping the child on timeout or error kills it
+        let (status, stdout, stderr) = tokio::time::timeout(timeout, finished)
+            .await
+            .map_err(|_| HookError::TimedOut)??;
+
+        let stdout = String::from_utf8(stdout).map_err(|_| HookError::NotUtf8)?;
+        match status.code() {
+            Some(0) => Ok(HookOutput::Insert(stdout)),
+            Some(SEND_EXIT_CODE) => Ok(HookOutput::Send(stdout)),
+            _ => Err(HookError::Failed {
+                status: describe_status(status),
+                stderr: String::from_utf8_lossy(&stderr).into_owned(),
+            }
+            .into()),
+        }
+    }
+}
+
+/// Reads stdout to the end, failing as soon as it outgrows `MAX_HOOK_OUTPUT`.
+async fn read_output(reader: impl AsyncRead + Unpin) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    reader
+        .take(MAX_HOOK_OUTPUT as u64 + 1)
+        .read_to_end(&mut output)
+        .await?;
+    if output.len() > MAX_HOOK_OUTPUT {
+        return Err(HookError::TooMuchOutput.into());
+    }
+    Ok(output)
+}
+
+/// Reads stderr to the end, keeping its start for the failure message.
+async fn read_stderr(reader: impl AsyncRead + Unpin) -> Result<Vec<u8>> {
+    let mut stderr = Vec::new();
+    let mut reader = reader.take(MAX_HOOK_STDERR as u64);
+    reader.read_to_end(&mut stderr).await?;
+    // Drain the rest so the hook isn't blocked writing it
+    tokio::io::copy(&mut reader.into_inner(), &mut tokio::io::sink()).await?;
+    Ok(stderr)
+}
+
+fn describe_status(status: ExitStatus) -> String {
+    match status.code() {
+        Some(code) => code.to_string(),
+        None => status.to_string(),
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+fn trust_key(path: &Path) -> String {
+    fs::canonicalize(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .display()
+        .to_string()
+}
+
+fn content_digest(path: &Path) -> Result<String> {
+    let content = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(Sha256::digest(content)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn write_hook(dir: &Path, name: &str, script: &str) -> PathBuf {
+        let path = dir.join(format!("{}{}", HOOK_PREFIX, name));
+        fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    fn hooks(dir: &Path) -> Hooks {
+        Hooks::new(dir.to_path_buf(), dir.join("trusted.json"))
+    }
+
+    #[test]
+    fn test_only_executable_hooks_with_plain_names_are_found() {
+        let dir = tempfile::tempdir().unwrap();
+        write_hook(dir.path(), "wc", "true");
+        fs::write(dir.path().join("cmd-notes"), "not executable").unwrap();
+        let hooks = hooks(dir.path());
+
+        assert_eq!(hooks.find("wc").unwrap().name, "wc");
+        assert!(hooks.find("notes").is_none());
+        assert!(hooks.find("missing").is_none());
+        assert!(hooks.find("../cmd-wc").is_none());
+        assert!(hooks.find("").is_none());
+        assert_eq!(hooks.names(), ["wc"]);
+    }
+
+    #[tokio::test]
+    async fn test_hooks_get_the_session_and_choose_insert_or_send() {
+        let dir = tempfile::tempdir().unwrap();
+        write_hook(
+            dir.path(),
+            "echo",
+            r#"printf '%s|%s|' "$GROK_SESSION_ID" "$2"; cat"#,
+        );
+        write_hook(dir.path(), "send", "echo hello; exit 3");
+        let hooks = hooks(dir.path());
+        let messages: Vec<ApiMessage> = (0..30)
+            .map(|n| ApiMessage::user(format!("message {}", n)))
+            .collect();
+
+        let HookOutput::Insert(output) = hooks
+            .find("echo")
+            .unwrap()
+            .run("one two", Some("session-1"), &messages)
+            .await
+            .unwrap()
+        else {
+            panic!("exit 0 should insert");
+        };
+        let (prefix, stdin) = output.split_at("session-1|two|".len());
+        assert_eq!(prefix, "session-1|two|");
+        let input: serde_json::Value = serde_json::from_str(stdin).unwrap();
+        assert_eq!(input["session_id"], "session-1");
+        assert_eq!(input["args"], "one two");
+        assert_eq!(input["messages"].as_array().unwrap().len(), HOOK_MESSAGES);
+        assert_eq!(
+            input["messages"][HOOK_MESSAGES - 1]["content"],
+            "message 29"
+        );
+
+        let output = hooks
+            .find("send")
+            .unwrap()
+            .run("", None, &[])
+            .await
+            .unwrap();
+        assert_eq!(output, HookOutput::Send("hello\n".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_failing_slow_and_noisy_hooks_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        write_hook(dir.path(), "fail", "echo 'no network' >&2; exit 1");
+        write_hook(dir.path(), "slow", "sleep 5");
+        write_hook(dir.path(), "noisy", "while :; do echo spam; done");
+        let hooks = hooks(dir.path());
+
+        let error = hooks
+            .find("fail")
+            .unwrap()
+            .run("", None, &[])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<HookError>(),
+            Some(&HookError::Failed {
+                status: "1".to_string(),
+                stderr: "no network\n".to_string()
+            })
+        );
+
+        let error = hooks
+            .find("slow")
+            .unwrap()
+            .run_with_timeout("", None, &[], Duration::from_millis(200))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<HookError>(),
+            Some(&HookError::TimedOut)
+        );
+
+        let error = hooks
+            .find("noisy")
+            .unwrap()
+            .run("", None, &[])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<HookError>(),
+            Some(&HookError::TooMuchOutput)
+        );
+    }
+
+    #[test]
+    fn test_trust_is_lost_when_a_hook_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_hook(dir.path(), "wc", "wc -w");
+        let hooks = hooks(dir.path());
+        let hook = hooks.find("wc").unwrap();
+
+        assert!(!hooks.is_trusted(&hook).unwrap());
+        hooks.trust(&hook).unwrap();
+        assert!(hooks.is_trusted(&hook).unwrap());
+
+        fs::write(&path, "#!/bin/sh\nrm -rf ~\n").unwrap();
+        assert!(!hooks.is_trusted(&hook).unwrap());
+    }
+}
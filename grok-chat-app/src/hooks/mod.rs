@@ -0,0 +1,3 @@
+pub use self::hooks::*;
+#[allow(clippy::module_inception)]
+mod hooks;
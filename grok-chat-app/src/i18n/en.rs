@@ -0,0 +1,328 @@
+//! English strings, the fallback for every other locale.
+
+pub(super) const STRINGS: &[(&str, &str)] = &[
+    // Terminal UI status bar
+    ("tui.ready", "Ready to chat! Type your message and press Enter to send."),
+    ("tui.normal_mode", "Press 'i' to insert, 'h' for help, 'q' to quit"),
+    ("tui.insert_mode", "Insert mode: Type your message and press Enter"),
+    ("tui.offline", "📴 Offline: messages are queued until you press 'o' again"),
+    ("tui.thinking", "🤔 Grok is thinking..."),
+    ("tui.sent", "✅ Message sent! Press 'i' to continue chatting."),
+    ("tui.sent_redacted_one", "✅ Message sent with 1 secret redacted. Press 'i' to continue chatting."),
+    ("tui.sent_redacted", "✅ Message sent with {count} secrets redacted. Press 'i' to continue chatting."),
+    ("tui.send_failed", "❌ Error occurred. Check your API key and try again."),
+    ("tui.reply_error", "❌ Error: {error}"),
+    ("tui.model_retired", "❌ Model {model} is no longer available. Press Esc then 'm' to pick another."),
+    ("tui.model_retired_try", "❌ Model {model} is no longer available; try {suggestion} (Esc then 'm')."),
+    ("tui.model_migrated", "🔀 Model {from} was retired; this session now uses {to}"),
+    ("tui.cost_confirm", "💰 {error}. Send anyway? y/N"),
+    ("tui.image_hint", "v opens it"),
+    ("tui.image_opened", "🖼 Opened {target}"),
+    ("tui.image_open_failed", "❌ Could not open {target}: {error}"),
+    ("tui.context_shed", "✂️ Left out the {count} oldest messages to fit the model's context window"),
+    ("tui.context_confirm", "✂️ The conversation is too long for the model. Send it again without the oldest messages? y/N"),
+    ("tui.snapshot_usage", "Usage: /snapshot <label>"),
+    ("tui.snapshot_taken", "📌 Snapshot '{label}' taken; /restore goes back to it"),
+    ("tui.snapshot_failed", "❌ Could not take a snapshot: {error}"),
+    ("tui.snapshots_none", "No snapshots of this session to restore; take one with /snapshot <label>"),
+    ("tui.snapshots_failed", "❌ Could not list snapshots: {error}"),
+    ("tui.snapshots_unsupported", "❌ Snapshots need database support; rebuild with --features server"),
+    ("tui.restored", "⏪ Restored '{label}'; {count} later messages moved to the archive"),
+    ("tui.restore_failed", "❌ Could not restore: {error}"),
+    ("tui.cost_declined", "💰 Not sent; the message is back in the input"),
+    ("tui.thinking_costly", "💰 This could cost up to {cost}, over the ${threshold} threshold. Grok is thinking..."),
+    ("tui.dry_run_usage", "Usage: /dryrun <message>"),
+    ("tui.dry_run_in_compare", "🧪 /dryrun isn't available in compare mode"),
+    ("tui.dry_run_done", "🧪 Dry run: nothing was sent"),
+    ("tui.compare_on", "⚖️ Compare mode: prompts go to both models. Tab picks the continuing pane, m its model, C leaves."),
+    ("tui.compare_off", "Compare mode off; continuing with {model}"),
+    ("tui.compare_needs_api", "📴 Compare mode needs the API; press 'o' to go online first"),
+    ("tui.compare_needs_api_insert", "📴 Compare mode needs the API; press Esc then 'o' to go online"),
+    ("tui.compare_continuing", "Continuing with {model} when compare mode ends"),
+    ("tui.compare_thinking", "🤔 Both models are thinking..."),
+    ("tui.compare_done", "✅ Both replies in. Tab picks the continuing pane; C leaves compare mode."),
+    ("tui.compare_save_failed", "❌ Could not save the replies: {error}"),
+    ("tui.queued", "📴 Offline: {count} message(s) queued. Press Esc then 'o' to send them now."),
+    ("tui.queue_failed", "❌ Could not queue the message: {error}"),
+    ("tui.queue_unsupported", "❌ Offline: queueing needs database support; rebuild with --features server"),
+    ("tui.online", "🌐 Online"),
+    ("tui.sending_queued", "📤 Sending queued messages..."),
+    ("tui.sent_queued", "🌐 Online: sent {count} queued message(s)"),
+    ("tui.still_offline", "📴 Still offline; queued messages will be retried"),
+    ("tui.send_queued_failed", "❌ Sending queued messages failed: {error}"),
+    ("tui.save_failed", "❌ Could not save the message: {error}"),
+    ("tui.journal_disabled", "⚠️ Crash journal disabled: {error}"),
+    ("tui.recovered", "♻️ Recovered {count} message(s) from a session that ended unexpectedly ({outcome})"),
+    ("tui.recovered_saved", "saved to the session database"),
+    ("tui.recovered_not_saved", "not saved: {error}"),
+    ("tui.recovered_save_failed", "♻️ Recovered {count} message(s), but could not save them: {error}"),
+    ("tui.new_session", "✨ New session created: {id}"),
+    ("tui.resumed", "🔄 Resumed session: {id}"),
+    ("tui.template_usage", "Usage: /template <name> key=value ..."),
+    ("tui.template_filled", "📝 Template '{name}' filled. Review and press Enter to send."),
+    ("tui.templates_unavailable", "Template store unavailable: database could not be opened"),
+    ("tui.templates_unsupported", "Templates need database support; rebuild with --features server"),
+    ("tui.template_not_found", "Template '{name}' not found"),
+    ("tui.no_database", "the session database could not be opened"),
+    ("tui.saving_unsupported", "saving needs database support; rebuild with --features server"),
+    ("tui.presets_unavailable", "❌ Prompt presets unavailable: database could not be opened"),
+    ("tui.presets_unsupported", "❌ Prompt presets need database support; rebuild with --features server"),
+    ("tui.default_prompt", "Using the default prompt; no presets are saved"),
+    ("tui.default_prompt_presets", "Using the default prompt. Presets: {presets}"),
+    ("tui.preset_chosen", "🎭 Using prompt preset '{name}'"),
+    ("tui.preset_save_failed", "❌ Could not save the preset: {error}"),
+    ("tui.preset_missing", "Prompt preset '{name}' no longer exists; using the default prompt"),
+    ("tui.no_agent", "No agent; none are configured under [default.agents] in config.toml"),
+    ("tui.no_agent_agents", "No agent. Agents: {agents}"),
+    ("tui.agent_chosen", "🤖 Agent '{name}' answers, with {model}"),
+    ("tui.agent_save_failed", "❌ Could not save the agent: {error}"),
+    ("tui.newfrom_usage", "Usage: /newfrom <session template>"),
+    ("tui.new_from_template", "✨ New session from '{name}' with {count} seeded message(s)"),
+    ("tui.template_session_failed", "❌ Could not create the session: {error}"),
+    ("tui.session_templates_unavailable", "❌ Session templates unavailable: database could not be opened"),
+    ("tui.session_templates_unsupported", "❌ Session templates need database support; rebuild with --features server"),
+    ("tui.model_changed", "Model changed to: {model}"),
+    ("tui.model_changed_profile", "Model changed to: {model} ({profile})"),
+    ("tui.provider_changed", "Provider {provider} · {status}"),
+    ("tui.pane_model", "Continuing pane now uses {model}"),
+    ("tui.select_first", "Select a message with ↑/↓ first"),
+    ("tui.pinned", "📌 Pinned: always sent as context"),
+    ("tui.unpinned", "Unpinned"),
+    ("tui.pin_save_failed", "❌ Could not save the pin: {error}"),
+    ("tui.rated_up", "👍 Rated a good reply"),
+    ("tui.rated_down", "👎 Rated a bad reply"),
+    ("tui.rating_cleared", "Rating removed"),
+    ("tui.noted", "📝 Note saved"),
+    ("tui.note_cleared", "Note and labels removed"),
+    ("tui.annotation_save_failed", "❌ Could not save the annotation: {error}"),
+    ("tui.regenerating", "🔁 Asking Grok again..."),
+    ("tui.regenerated", "🔁 Reply regenerated; press % to see what changed"),
+    ("tui.regenerate_nothing", "The last message is not a reply; there is nothing to regenerate"),
+    ("tui.regenerate_offline", "📴 Regenerating needs the API; press 'o' to go online first"),
+    ("tui.regenerate_failed", "❌ Could not regenerate the reply: {error}"),
+    ("tui.regenerate_save_failed", "❌ Could not save the regenerated reply: {error}"),
+    ("tui.diff_none", "This reply was never regenerated; press R on the last reply first"),
+    ("tui.nothing_to_undo", "Nothing to undo"),
+    ("tui.nothing_to_redo", "Nothing to redo"),
+    ("tui.undone", "↩️ Undone: {edit}"),
+    ("tui.redone", "↪️ Redone: {edit}"),
+    ("tui.undone_view", "↩️ Undone in this view: {edit} (stored sessions are unchanged)"),
+    ("tui.redone_view", "↪️ Redone in this view: {edit} (stored sessions are unchanged)"),
+    ("tui.edit_pin", "pin"),
+    ("tui.edit_new_session", "new session"),
+    ("tui.edit_leave_compare", "leaving compare mode"),
+    ("tui.edit_open_session", "opening a session"),
+    ("tui.edit_regenerate", "regenerating"),
+    ("tui.reply_not_stored", "⚠️ The earlier reply is no longer stored; only this view changed"),
+    ("tui.sessions_unavailable", "❌ Sessions unavailable: database could not be opened"),
+    ("tui.sessions_unsupported", "❌ The session list needs database support; rebuild with --features server"),
+    ("tui.sessions_failed", "❌ Could not list sessions: {error}"),
+    ("tui.search_failed", "❌ Message search failed: {error}"),
+    ("tui.session_changed", "🔄 The session was changed elsewhere; reloaded"),
+    ("tui.picker_empty", "No session matches"),
+    ("tui.picker_untitled", "(untitled)"),
+    // Terminal UI labels
+    ("tui.label_user", "You: "),
+    ("tui.label_assistant", "Grok: "),
+    ("tui.label_system", "System: "),
+    ("tui.label_tool", "Tool: "),
+    ("tui.label_pinned", "📌 pinned"),
+    ("tui.label_queued", "⏳ queued"),
+    ("tui.label_offline", "📴 OFFLINE"),
+    ("tui.label_model", "Model: "),
+    ("tui.label_cost", "Cost: "),
+    ("tui.title_chat", "💬 Chat"),
+    ("tui.title_pane_continuing", "💬 {model} (continuing)"),
+    ("tui.title_input", "Input"),
+    ("tui.title_status", "Status"),
+    ("tui.title_dry_run", "🧪 Dry run (↑/↓ PgUp/PgDn scroll, Esc closes)"),
+    ("tui.title_diff", "🔁 Changes since the previous reply (↑/↓ scroll, Esc closes)"),
+    ("tui.title_snapshots", "⏪ Snapshots (↑/↓ choose, Enter restores, Esc closes)"),
+    ("tui.title_picker", "📂 Sessions (type to search, ↑/↓ choose, Enter opens, Esc closes)"),
+    ("tui.breaker_open", "⛔ {model} paused {secs}s"),
+    ("tui.breaker_probing", "🟡 {model} probing"),
+    ("tui.pane_tokens", "{prompt} + {completion} tokens"),
+    ("tui.pane_waiting", "waiting for a prompt"),
+    ("tui.dry_run_summary", "Nothing was sent. About {tokens} prompt tokens."),
+    ("tui.dry_run_dropped", "{count} older message(s) left out to stay within the context budget"),
+    ("tui.dry_run_redacted", "{count} secret(s) redacted"),
+    // Terminal UI help overlay
+    ("help.title", "Help"),
+    ("help.normal_mode", "Normal Mode:"),
+    ("help.insert_mode", "Insert Mode:"),
+    ("help.insert", "Enter insert mode"),
+    ("help.quit", "Quit"),
+    ("help.help", "Toggle help"),
+    ("help.new_session", "Create new session"),
+    ("help.model", "Cycle model"),
+    ("help.offline", "Go offline, or back online to send queued messages"),
+    ("help.open_image", "Open the selected message's images in the system viewer"),
+    ("help.sessions", "Find and open a saved session"),
+    ("help.select", "Select a message"),
+    ("help.pin", "Pin or unpin the selected message"),
+    ("help.rate", "Rate the selected message good or bad"),
+    ("help.regenerate", "Regenerate the last reply"),
+    ("help.diff", "Show what regenerating changed in the selected reply"),
+    ("help.undo", "Undo or redo a pin, new or opened session, or compare exit"),
+    ("help.compare", "Compare two models side by side, or stop comparing"),
+    ("help.continuing", "Pick the compare pane that continues"),
+    ("help.send", "Send message"),
+    ("help.template", "Fill a template"),
+    ("help.prompt", "Use a system prompt preset, or the default"),
+    ("help.agent", "Answer with a configured agent, or none"),
+    ("help.newfrom", "Start a new session from a session template"),
+    ("help.note", "Note on the selected message; #words become labels"),
+    ("help.dry_run", "Show the request without sending it"),
+    ("help.snapshot", "Take a snapshot to roll back to"),
+    ("help.restore", "Roll the session back to a snapshot"),
+    ("help.hook", "Run the hook cmd-<name> with the arguments"),
+    ("help.normal", "Return to normal mode"),
+    ("help.type", "Type your message..."),
+    ("help.close", "Press any key to close help..."),
+    // Interactive prompts
+    ("prompt.recover", "⚠️  The terminal UI exited unexpectedly before saving {messages} message(s) in {sessions} session(s). Recover them? [Y/n] "),
+    ("prompt.yes", "y,yes"),
+    ("prompt.api_key", "xAI API key: "),
+    ("repl.banner", "🤖 Grok Chat (Interactive Mode)"),
+    ("repl.model", "Model: {model}"),
+    ("repl.model_switched", "🔄 Model: {model}"),
+    ("repl.model_switched_profile", "🔄 Model: {model} ({profile})"),
+    ("repl.instructions", "Type 'quit' or 'exit' to end the conversation, '/help' for commands."),
+    ("repl.user", "You: "),
+    ("repl.assistant", "Grok: "),
+    ("repl.reset", "🧹 Conversation reset"),
+    ("repl.system", "System prompt: {prompt}"),
+    ("repl.system_updated", "📝 System prompt updated"),
+    ("repl.help_reset", "/reset            Forget the conversation so far"),
+    ("repl.help_model", "/model [name]     Show or switch the model"),
+    ("repl.help_system", "/system [prompt]  Show or replace the system prompt"),
+    ("repl.help_hook", "/<name> [args]    Run the hook {dir}/cmd-<name>"),
+    ("repl.unknown_command", "❓ Unknown command '/{name}'; try /help"),
+    ("repl.cancelled", "(cancelled)"),
+    ("repl.cost_confirm", "💰 {error}. Send anyway? [y/N] "),
+    ("repl.cost_declined", "(not sent)"),
+    ("repl.dropped", "✂️  Dropped {count} old messages to stay within the context budget"),
+    ("repl.history_not_saved", "⚠️  Could not save input history: {error}"),
+    // Command line errors and hints
+    ("cli.error", "❌ Error: {error}"),
+    ("cli.error_quiet", "Error: {error}"),
+    ("cli.hint", "💡 {hint}"),
+    ("cli.hint_auth", "Store a key with `grok-chat-app auth login`, or export XAI_API_KEY=your_api_key_here"),
+    ("cli.hint_upstream", "Check your network connection and XAI_BASE_URL"),
+    ("cli.hint_rate_limited", "Rate limited by the API; wait a moment and retry"),
+    ("cli.hint_context_too_long", "Shorten the prompt or attachments, or start a new session"),
+    ("cli.hint_regression", "Compare the replies in the report above"),
+    ("cli.hint_cost", "Pass --yes to send it anyway, or raise cost_threshold_usd"),
+    ("cli.legacy_flags", "⚠️  Top-level flags are deprecated and will be removed in the next release; use the `chat`, `tui` and `serve` subcommands instead (see --help)"),
+    ("cli.input_truncated", "⚠️  Input exceeds {limit} KiB; {bytes} bytes were truncated"),
+    ("cli.json_needs_message", "--output json needs a message"),
+    ("cli.offline_needs_message", "--offline needs a message"),
+    ("cli.dry_run_needs_message", "--dry-run needs a message"),
+    ("cli.stdin_needs_prompt", "A message of `-` expects the prompt on stdin"),
+    ("cli.file_needs_prompt", "--file needs a prompt: pass a message or pipe one on stdin"),
+    ("cli.mock_needs_feature", "--mock-upstream needs a build with the `mock` feature"),
+    ("cli.offline_needs_server", "--offline stores messages in a session and needs the `server` feature"),
+    ("cli.preset_needs_server", "--prompt-preset reads saved presets and needs the `server` feature"),
+    ("cli.read_failed", "Failed to read {path}: {error}"),
+    ("cli.not_an_export", "{path} is not a JSON session export: {error}"),
+    ("cli.not_a_script", "{path} is not a valid script: {error}"),
+    ("cli.file_exists", "{path} already exists; pass --force to replace it"),
+    ("cli.no_api_key", "No API key entered"),
+    ("cli.empty_preset", "The prompt preset is empty"),
+    ("cli.session_not_found", "Session '{id}' not found"),
+    // Command line reports
+    ("cli.session", "🧵 Session: {id}"),
+    ("cli.usage", "📊 Usage: {prompt} prompt + {completion} completion = {total} tokens ({model})"),
+    ("cli.usage_unknown", "📊 Usage: not reported"),
+    ("cli.cost", "💰 Cost: {cost} ({prompt} prompt + {completion} completion tokens)"),
+    ("cli.cost_unknown", "💰 Cost: unknown (no usage reported)"),
+    ("cli.cost_estimated", "💰 Estimated before sending: up to {cost} ({prompt} prompt + {completion} reply tokens)"),
+    ("cli.cost_warning", "⚠️  This request could cost up to {cost}, over the ${threshold} threshold"),
+    ("cli.redacted", "🔒 Redacted {count} secret(s) before sending (--no-redact to send as typed)"),
+    ("cli.dry_run", "🧪 Dry run: nothing was sent. About {tokens} prompt tokens."),
+    ("cli.dry_run_dropped", "✂️  {count} older message(s) left out to stay within the context budget"),
+    ("cli.sent_queued", "📤 Sent {count} queued message(s) first"),
+    ("cli.reason_offline", "Offline"),
+    ("cli.reason_unreachable", "API unreachable"),
+    ("cli.queued", "📴 {reason}: message queued in session {id}"),
+    ("cli.queued_hint", "💡 Send it with `grok-chat-app sessions flush` once you are back online"),
+    ("cli.sessions_need_server", "⚠️  Sessions need the `server` feature; this message will not be saved"),
+    ("cli.no_matches", "No matches"),
+    ("cli.exported", "📦 Exported session {id} to {path}"),
+    ("cli.finetune_redacted", "🔒 Redacted {count} secret(s)"),
+    ("cli.resumed_one", "🔁 The reply was cut off and resumed (1 follow-up request)"),
+    ("cli.resumed", "🔁 The reply was cut off and resumed ({count} follow-up requests)"),
+    ("cli.no_logprobs", "(no logprobs returned by the model)"),
+    // Subcommand reports
+    ("check.config_failed", "❌ Configuration: {error}"),
+    ("check.config_loaded", "✅ Configuration loaded (API: {url})"),
+    ("check.api_ok", "✅ API key accepted ({count} models available)"),
+    ("check.api_failed", "❌ API: {error}"),
+    ("check.pricing_ok", "✅ Pricing table loaded"),
+    ("check.pricing_failed", "❌ Pricing: {error}"),
+    ("check.database_ok", "✅ Database {url} ({count} sessions)"),
+    ("check.database_failed", "❌ Database {url}: {error}"),
+    ("auth.stored", "🔑 API key stored in the OS keyring (profile '{profile}')"),
+    ("auth.removed", "🗑️  Removed the stored API key (profile '{profile}')"),
+    ("auth.none_stored", "No API key stored for profile '{profile}'"),
+    ("auth.status", "✅ API key from the {source} (profile '{profile}')"),
+    ("auth.keyring_disabled", "   Keyring lookups are disabled by {var}"),
+    ("auth.keyring_has_key", "   A key is stored in the OS keyring"),
+    ("auth.keyring_no_key", "   No key stored in the OS keyring"),
+    ("sessions.none", "No saved sessions"),
+    ("sessions.msgs", "msgs"),
+    ("sessions.untitled", "(untitled)"),
+    ("sessions.deleted", "🗑️  Deleted session {id}"),
+    ("sessions.imported", "📥 Imported {sessions} sessions ({messages} messages) from {path}"),
+    ("sessions.skipped_duplicates", "   Skipped {count} already imported"),
+    ("sessions.skipped_malformed", "   Skipped {count} malformed entries"),
+    ("sessions.nothing_queued", "📭 No queued messages"),
+    ("sessions.flushed", "📤 Sent {sent} queued message(s); {remaining} still queued"),
+    ("sessions.flush_failed", "   ❌ Session {id}: {error}"),
+    ("sessions.not_flushed", "{count} session(s) could not be flushed"),
+    ("sessions.migrated", "🔀 Moved {count} session(s) from {from} to {to}"),
+    ("sessions.archived", "📦 Exported {count} sessions to {path}"),
+    ("sessions.archive_skipped", "   Skipped {id}: {error}"),
+    ("sessions.finetune", "📦 Exported {examples} example(s) from {sessions} session(s), ~{tokens} tokens"),
+    ("sessions.finetune_written", "📦 Exported {examples} example(s) from {sessions} session(s), ~{tokens} tokens, to {path}"),
+    ("sessions.finetune_skipped", "   Skipped {count} session(s) with nothing matching"),
+    ("sessions.no_duplicates", "✨ No duplicate sessions"),
+    ("sessions.dedupe_keep", "{id}  {date}  {title} ({count} messages)"),
+    ("sessions.dedupe_duplicate", "  ↳ {id}  {date}  {title} ({count} messages, {likeness})"),
+    ("sessions.same_prompts", "same prompts"),
+    ("sessions.alike", "{percent}% alike"),
+    ("sessions.merged", "🧹 Merged {count} duplicate session(s) into {groups}, moving {moved} message(s) found only in them"),
+    ("sessions.duplicates_found", "🔍 {count} duplicate session(s) in {groups} group(s); run with --apply to merge them"),
+    ("prompts.saved", "📝 Saved prompt preset '{name}'"),
+    ("prompts.saved_default", "📝 Saved prompt preset '{name}' (default)"),
+    ("prompts.updated", "📝 Updated prompt preset '{name}'"),
+    ("prompts.updated_default", "📝 Updated prompt preset '{name}' (default)"),
+    ("prompts.none", "No saved prompt presets"),
+    ("prompts.not_found", "Prompt preset '{name}' not found"),
+    ("prompts.deleted", "🗑️  Deleted prompt preset {name}"),
+    ("prompts.sessions_reset", "⚠️  {count} session(s) used '{name}' and now get the default prompt"),
+    ("templates.invalid", "Invalid session template: {error}"),
+    ("templates.saved", "📝 Saved session template '{name}' ({count} example message(s))"),
+    ("templates.updated", "📝 Updated session template '{name}' ({count} example message(s))"),
+    ("templates.none", "No saved session templates"),
+    ("templates.not_found", "Session template '{name}' not found"),
+    ("templates.deleted", "🗑️  Deleted session template {name}"),
+    ("maintenance.pruned", "Pruned {count} session(s) not updated in {days} days"),
+    ("maintenance.would_prune", "Would prune {count} session(s) not updated in {days} days"),
+    ("maintenance.removed_media", "Removed {count} unreferenced media file(s), {kib} KiB"),
+    ("maintenance.would_remove_media", "Would remove {count} unreferenced media file(s), {kib} KiB"),
+    ("maintenance.compacted", "🧹 Database compacted"),
+    // Slash command hooks
+    ("hooks.timed_out", "stopped after {secs}s without finishing"),
+    ("hooks.too_much_output", "wrote more than {kib} KiB of output"),
+    ("hooks.not_utf8", "wrote output that is not UTF-8"),
+    ("hooks.failed", "exited with status {status}: {stderr}"),
+    ("hooks.error", "❌ /{name}: {error}"),
+    ("hooks.running", "⚙️ Running /{name}..."),
+    ("hooks.confirm", "⚠️  {path} has not run before, or changed since it last ran. It runs with your permissions. Run it? [y/N] "),
+    ("hooks.confirm_tui", "⚠️ {path} is new or changed and runs with your permissions. Run it? y/N"),
+    ("hooks.declined", "Hook not run"),
+    ("hooks.inserted", "/{name} filled the input; edit it or press Enter to send"),
+    ("hooks.empty", "/{name} printed nothing"),
+    ("hooks.available", "Hooks: {names}"),
+];
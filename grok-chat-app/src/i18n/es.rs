@@ -0,0 +1,328 @@
+//! Spanish strings.
+
+pub(super) const STRINGS: &[(&str, &str)] = &[
+    // Terminal UI status bar
+    ("tui.ready", "¡Listo para conversar! Escribe tu mensaje y pulsa Enter para enviarlo."),
+    ("tui.normal_mode", "Pulsa 'i' para escribir, 'h' para la ayuda, 'q' para salir"),
+    ("tui.insert_mode", "Modo de inserción: escribe tu mensaje y pulsa Enter"),
+    ("tui.offline", "📴 Sin conexión: los mensajes quedan en cola hasta que vuelvas a pulsar 'o'"),
+    ("tui.thinking", "🤔 Grok está pensando..."),
+    ("tui.sent", "✅ ¡Mensaje enviado! Pulsa 'i' para seguir conversando."),
+    ("tui.sent_redacted_one", "✅ Mensaje enviado con 1 secreto ocultado. Pulsa 'i' para seguir conversando."),
+    ("tui.sent_redacted", "✅ Mensaje enviado con {count} secretos ocultados. Pulsa 'i' para seguir conversando."),
+    ("tui.send_failed", "❌ Se produjo un error. Revisa tu clave de API y vuelve a intentarlo."),
+    ("tui.reply_error", "❌ Error: {error}"),
+    ("tui.model_retired", "❌ El modelo {model} ya no está disponible. Pulsa Esc y luego 'm' para elegir otro."),
+    ("tui.model_retired_try", "❌ El modelo {model} ya no está disponible; prueba {suggestion} (Esc y luego 'm')."),
+    ("tui.model_migrated", "🔀 El modelo {from} se retiró; esta sesión usa ahora {to}"),
+    ("tui.cost_confirm", "💰 {error}. ¿Enviarlo de todos modos? s/N"),
+    ("tui.image_hint", "v la abre"),
+    ("tui.image_opened", "🖼 Se abrió {target}"),
+    ("tui.image_open_failed", "❌ No se pudo abrir {target}: {error}"),
+    ("tui.context_shed", "✂️ Se omitieron los {count} mensajes más antiguos para caber en la ventana de contexto del modelo"),
+    ("tui.context_confirm", "✂️ La conversación es demasiado larga para el modelo. ¿Enviarla de nuevo sin los mensajes más antiguos? s/N"),
+    ("tui.snapshot_usage", "Uso: /snapshot <etiqueta>"),
+    ("tui.snapshot_taken", "📌 Instantánea '{label}' tomada; /restore vuelve a ella"),
+    ("tui.snapshot_failed", "❌ No se pudo tomar la instantánea: {error}"),
+    ("tui.snapshots_none", "No hay instantáneas de esta sesión que restaurar; toma una con /snapshot <etiqueta>"),
+    ("tui.snapshots_failed", "❌ No se pudieron listar las instantáneas: {error}"),
+    ("tui.snapshots_unsupported", "❌ Las instantáneas necesitan soporte de base de datos; recompila con --features server"),
+    ("tui.restored", "⏪ Se restauró '{label}'; {count} mensajes posteriores pasaron al archivo"),
+    ("tui.restore_failed", "❌ No se pudo restaurar: {error}"),
+    ("tui.cost_declined", "💰 No enviado; el mensaje vuelve a la entrada"),
+    ("tui.thinking_costly", "💰 Esto podría costar hasta {cost}, por encima del umbral de ${threshold}. Grok está pensando..."),
+    ("tui.dry_run_usage", "Uso: /dryrun <mensaje>"),
+    ("tui.dry_run_in_compare", "🧪 /dryrun no está disponible en el modo de comparación"),
+    ("tui.dry_run_done", "🧪 Simulación: no se envió nada"),
+    ("tui.compare_on", "⚖️ Modo de comparación: los mensajes van a ambos modelos. Tab elige el panel que continúa, m su modelo, C sale."),
+    ("tui.compare_off", "Modo de comparación desactivado; se continúa con {model}"),
+    ("tui.compare_needs_api", "📴 El modo de comparación necesita la API; pulsa 'o' para conectarte primero"),
+    ("tui.compare_needs_api_insert", "📴 El modo de comparación necesita la API; pulsa Esc y luego 'o' para conectarte"),
+    ("tui.compare_continuing", "Se continuará con {model} al salir del modo de comparación"),
+    ("tui.compare_thinking", "🤔 Ambos modelos están pensando..."),
+    ("tui.compare_done", "✅ Llegaron ambas respuestas. Tab elige el panel que continúa; C sale del modo de comparación."),
+    ("tui.compare_save_failed", "❌ No se pudieron guardar las respuestas: {error}"),
+    ("tui.queued", "📴 Sin conexión: {count} mensaje(s) en cola. Pulsa Esc y luego 'o' para enviarlos ahora."),
+    ("tui.queue_failed", "❌ No se pudo poner el mensaje en cola: {error}"),
+    ("tui.queue_unsupported", "❌ Sin conexión: la cola necesita la base de datos; recompila con --features server"),
+    ("tui.online", "🌐 Conectado"),
+    ("tui.sending_queued", "📤 Enviando los mensajes en cola..."),
+    ("tui.sent_queued", "🌐 Conectado: se enviaron {count} mensaje(s) en cola"),
+    ("tui.still_offline", "📴 Todavía sin conexión; los mensajes en cola se reintentarán"),
+    ("tui.send_queued_failed", "❌ Falló el envío de los mensajes en cola: {error}"),
+    ("tui.save_failed", "❌ No se pudo guardar el mensaje: {error}"),
+    ("tui.journal_disabled", "⚠️ Registro de recuperación desactivado: {error}"),
+    ("tui.recovered", "♻️ Se recuperaron {count} mensaje(s) de una sesión que terminó inesperadamente ({outcome})"),
+    ("tui.recovered_saved", "guardados en la base de datos de sesiones"),
+    ("tui.recovered_not_saved", "sin guardar: {error}"),
+    ("tui.recovered_save_failed", "♻️ Se recuperaron {count} mensaje(s), pero no se pudieron guardar: {error}"),
+    ("tui.new_session", "✨ Nueva sesión creada: {id}"),
+    ("tui.resumed", "🔄 Sesión reanudada: {id}"),
+    ("tui.template_usage", "Uso: /template <nombre> clave=valor ..."),
+    ("tui.template_filled", "📝 Plantilla '{name}' completada. Revísala y pulsa Enter para enviarla."),
+    ("tui.templates_unavailable", "Plantillas no disponibles: no se pudo abrir la base de datos"),
+    ("tui.templates_unsupported", "Las plantillas necesitan la base de datos; recompila con --features server"),
+    ("tui.template_not_found", "No se encontró la plantilla '{name}'"),
+    ("tui.no_database", "no se pudo abrir la base de datos de sesiones"),
+    ("tui.saving_unsupported", "guardar necesita la base de datos; recompila con --features server"),
+    ("tui.presets_unavailable", "❌ Ajustes de prompt no disponibles: no se pudo abrir la base de datos"),
+    ("tui.presets_unsupported", "❌ Los ajustes de prompt necesitan la base de datos; recompila con --features server"),
+    ("tui.default_prompt", "Se usa el prompt predeterminado; no hay ajustes guardados"),
+    ("tui.default_prompt_presets", "Se usa el prompt predeterminado. Ajustes: {presets}"),
+    ("tui.preset_chosen", "🎭 Se usa el ajuste de prompt '{name}'"),
+    ("tui.preset_save_failed", "❌ No se pudo guardar el ajuste: {error}"),
+    ("tui.preset_missing", "El ajuste de prompt '{name}' ya no existe; se usa el prompt predeterminado"),
+    ("tui.no_agent", "Sin agente; no hay ninguno configurado en [default.agents] de config.toml"),
+    ("tui.no_agent_agents", "Sin agente. Agentes: {agents}"),
+    ("tui.agent_chosen", "🤖 Responde el agente '{name}', con {model}"),
+    ("tui.agent_save_failed", "❌ No se pudo guardar el agente: {error}"),
+    ("tui.newfrom_usage", "Uso: /newfrom <plantilla de sesión>"),
+    ("tui.new_from_template", "✨ Nueva sesión desde '{name}' con {count} mensaje(s) sembrado(s)"),
+    ("tui.template_session_failed", "❌ No se pudo crear la sesión: {error}"),
+    ("tui.session_templates_unavailable", "❌ Plantillas de sesión no disponibles: no se pudo abrir la base de datos"),
+    ("tui.session_templates_unsupported", "❌ Las plantillas de sesión necesitan la base de datos; recompila con --features server"),
+    ("tui.model_changed", "Modelo cambiado a: {model}"),
+    ("tui.model_changed_profile", "Modelo cambiado a: {model} ({profile})"),
+    ("tui.provider_changed", "Proveedor {provider} · {status}"),
+    ("tui.pane_model", "El panel que continúa usa ahora {model}"),
+    ("tui.select_first", "Primero selecciona un mensaje con ↑/↓"),
+    ("tui.pinned", "📌 Fijado: siempre se envía como contexto"),
+    ("tui.unpinned", "Desfijado"),
+    ("tui.pin_save_failed", "❌ No se pudo guardar el fijado: {error}"),
+    ("tui.rated_up", "👍 Valorado como buena respuesta"),
+    ("tui.rated_down", "👎 Valorado como mala respuesta"),
+    ("tui.rating_cleared", "Valoración eliminada"),
+    ("tui.noted", "📝 Nota guardada"),
+    ("tui.note_cleared", "Nota y etiquetas eliminadas"),
+    ("tui.annotation_save_failed", "❌ No se pudo guardar la anotación: {error}"),
+    ("tui.regenerating", "🔁 Preguntando de nuevo a Grok..."),
+    ("tui.regenerated", "🔁 Respuesta regenerada; pulsa % para ver qué cambió"),
+    ("tui.regenerate_nothing", "El último mensaje no es una respuesta; no hay nada que regenerar"),
+    ("tui.regenerate_offline", "📴 Regenerar necesita la API; pulsa 'o' para conectarte primero"),
+    ("tui.regenerate_failed", "❌ No se pudo regenerar la respuesta: {error}"),
+    ("tui.regenerate_save_failed", "❌ No se pudo guardar la respuesta regenerada: {error}"),
+    ("tui.diff_none", "Esta respuesta nunca se regeneró; pulsa R sobre la última respuesta primero"),
+    ("tui.nothing_to_undo", "Nada que deshacer"),
+    ("tui.nothing_to_redo", "Nada que rehacer"),
+    ("tui.undone", "↩️ Deshecho: {edit}"),
+    ("tui.redone", "↪️ Rehecho: {edit}"),
+    ("tui.undone_view", "↩️ Deshecho en esta vista: {edit} (las sesiones guardadas no cambian)"),
+    ("tui.redone_view", "↪️ Rehecho en esta vista: {edit} (las sesiones guardadas no cambian)"),
+    ("tui.edit_pin", "fijado"),
+    ("tui.edit_new_session", "nueva sesión"),
+    ("tui.edit_leave_compare", "salida del modo de comparación"),
+    ("tui.edit_open_session", "apertura de una sesión"),
+    ("tui.edit_regenerate", "regeneración"),
+    ("tui.reply_not_stored", "⚠️ La respuesta anterior ya no está guardada; solo cambió esta vista"),
+    ("tui.sessions_unavailable", "❌ Sesiones no disponibles: no se pudo abrir la base de datos"),
+    ("tui.sessions_unsupported", "❌ La lista de sesiones necesita soporte de base de datos; recompila con --features server"),
+    ("tui.sessions_failed", "❌ No se pudieron listar las sesiones: {error}"),
+    ("tui.search_failed", "❌ La búsqueda en los mensajes falló: {error}"),
+    ("tui.session_changed", "🔄 La sesión cambió en otro lugar; se ha recargado"),
+    ("tui.picker_empty", "Ninguna sesión coincide"),
+    ("tui.picker_untitled", "(sin título)"),
+    // Terminal UI labels
+    ("tui.label_user", "Tú: "),
+    ("tui.label_assistant", "Grok: "),
+    ("tui.label_system", "Sistema: "),
+    ("tui.label_tool", "Herramienta: "),
+    ("tui.label_pinned", "📌 fijado"),
+    ("tui.label_queued", "⏳ en cola"),
+    ("tui.label_offline", "📴 SIN CONEXIÓN"),
+    ("tui.label_model", "Modelo: "),
+    ("tui.label_cost", "Coste: "),
+    ("tui.title_chat", "💬 Chat"),
+    ("tui.title_pane_continuing", "💬 {model} (continúa)"),
+    ("tui.title_input", "Entrada"),
+    ("tui.title_status", "Estado"),
+    ("tui.title_dry_run", "🧪 Simulación (↑/↓ RePág/AvPág desplazan, Esc cierra)"),
+    ("tui.title_diff", "🔁 Cambios desde la respuesta anterior (↑/↓ desplazan, Esc cierra)"),
+    ("tui.title_snapshots", "⏪ Instantáneas (↑/↓ eligen, Enter restaura, Esc cierra)"),
+    ("tui.title_picker", "📂 Sesiones (escribe para buscar, ↑/↓ eligen, Enter abre, Esc cierra)"),
+    ("tui.breaker_open", "⛔ {model} en pausa {secs}s"),
+    ("tui.breaker_probing", "🟡 {model} en prueba"),
+    ("tui.pane_tokens", "{prompt} + {completion} tokens"),
+    ("tui.pane_waiting", "esperando un mensaje"),
+    ("tui.dry_run_summary", "No se envió nada. Unos {tokens} tokens de entrada."),
+    ("tui.dry_run_dropped", "{count} mensaje(s) antiguos omitidos para no superar el presupuesto de contexto"),
+    ("tui.dry_run_redacted", "{count} secreto(s) ocultados"),
+    // Terminal UI help overlay
+    ("help.title", "Ayuda"),
+    ("help.normal_mode", "Modo normal:"),
+    ("help.insert_mode", "Modo de inserción:"),
+    ("help.insert", "Entrar en el modo de inserción"),
+    ("help.quit", "Salir"),
+    ("help.help", "Mostrar u ocultar la ayuda"),
+    ("help.new_session", "Crear una sesión nueva"),
+    ("help.model", "Cambiar de modelo"),
+    ("help.offline", "Desconectarse, o reconectarse para enviar los mensajes en cola"),
+    ("help.open_image", "Abrir las imágenes del mensaje seleccionado en el visor del sistema"),
+    ("help.sessions", "Buscar y abrir una sesión guardada"),
+    ("help.select", "Seleccionar un mensaje"),
+    ("help.pin", "Fijar o desfijar el mensaje seleccionado"),
+    ("help.rate", "Valorar el mensaje seleccionado como bueno o malo"),
+    ("help.regenerate", "Regenerar la última respuesta"),
+    ("help.diff", "Mostrar qué cambió al regenerar la respuesta seleccionada"),
+    ("help.undo", "Deshacer o rehacer un fijado, una sesión nueva o abierta, o la salida de la comparación"),
+    ("help.compare", "Comparar dos modelos lado a lado, o dejar de comparar"),
+    ("help.continuing", "Elegir el panel de comparación que continúa"),
+    ("help.send", "Enviar el mensaje"),
+    ("help.template", "Completar una plantilla"),
+    ("help.prompt", "Usar un ajuste de prompt de sistema, o el predeterminado"),
+    ("help.agent", "Responder con un agente configurado, o ninguno"),
+    ("help.newfrom", "Empezar una sesión nueva desde una plantilla de sesión"),
+    ("help.note", "Nota sobre el mensaje seleccionado; las #palabras son etiquetas"),
+    ("help.dry_run", "Mostrar la petición sin enviarla"),
+    ("help.snapshot", "Tomar una instantánea a la que volver"),
+    ("help.restore", "Devolver la sesión a una instantánea"),
+    ("help.hook", "Ejecutar el hook cmd-<nombre> con los argumentos"),
+    ("help.normal", "Volver al modo normal"),
+    ("help.type", "Escribe tu mensaje..."),
+    ("help.close", "Pulsa cualquier tecla para cerrar la ayuda..."),
+    // Interactive prompts
+    ("prompt.recover", "⚠️  La interfaz de terminal se cerró inesperadamente antes de guardar {messages} mensaje(s) en {sessions} sesión(es). ¿Recuperarlos? [S/n] "),
+    ("prompt.yes", "s,si,sí,y,yes"),
+    ("prompt.api_key", "Clave de API de xAI: "),
+    ("repl.banner", "🤖 Grok Chat (modo interactivo)"),
+    ("repl.model", "Modelo: {model}"),
+    ("repl.model_switched", "🔄 Modelo: {model}"),
+    ("repl.model_switched_profile", "🔄 Modelo: {model} ({profile})"),
+    ("repl.instructions", "Escribe 'quit' o 'exit' para terminar la conversación, '/help' para ver los comandos."),
+    ("repl.user", "Tú: "),
+    ("repl.assistant", "Grok: "),
+    ("repl.reset", "🧹 Conversación reiniciada"),
+    ("repl.system", "Prompt de sistema: {prompt}"),
+    ("repl.system_updated", "📝 Prompt de sistema actualizado"),
+    ("repl.help_reset", "/reset            Olvidar la conversación hasta ahora"),
+    ("repl.help_model", "/model [nombre]   Mostrar o cambiar el modelo"),
+    ("repl.help_system", "/system [prompt]  Mostrar o reemplazar el prompt de sistema"),
+    ("repl.help_hook", "/<nombre> [args]  Ejecutar el hook {dir}/cmd-<nombre>"),
+    ("repl.unknown_command", "❓ Comando desconocido '/{name}'; prueba /help"),
+    ("repl.cancelled", "(cancelado)"),
+    ("repl.cost_confirm", "💰 {error}. ¿Enviar de todos modos? [s/N] "),
+    ("repl.cost_declined", "(no enviado)"),
+    ("repl.dropped", "✂️  Se descartaron {count} mensajes antiguos para no superar el presupuesto de contexto"),
+    ("repl.history_not_saved", "⚠️  No se pudo guardar el historial de entrada: {error}"),
+    // Command line errors and hints
+    ("cli.error", "❌ Error: {error}"),
+    ("cli.error_quiet", "Error: {error}"),
+    ("cli.hint", "💡 {hint}"),
+    ("cli.hint_auth", "Guarda una clave con `grok-chat-app auth login`, o exporta XAI_API_KEY=tu_clave_de_api"),
+    ("cli.hint_upstream", "Revisa tu conexión de red y XAI_BASE_URL"),
+    ("cli.hint_rate_limited", "La API limitó la frecuencia de peticiones; espera un momento y reintenta"),
+    ("cli.hint_context_too_long", "Acorta el mensaje o los adjuntos, o empieza una sesión nueva"),
+    ("cli.hint_regression", "Compara las respuestas del informe anterior"),
+    ("cli.hint_cost", "Usa --yes para enviarlo de todos modos, o sube cost_threshold_usd"),
+    ("cli.legacy_flags", "⚠️  Las opciones de nivel superior están obsoletas y se eliminarán en la próxima versión; usa los subcomandos `chat`, `tui` y `serve` (consulta --help)"),
+    ("cli.input_truncated", "⚠️  La entrada supera {limit} KiB; se truncaron {bytes} bytes"),
+    ("cli.json_needs_message", "--output json necesita un mensaje"),
+    ("cli.offline_needs_message", "--offline necesita un mensaje"),
+    ("cli.dry_run_needs_message", "--dry-run necesita un mensaje"),
+    ("cli.stdin_needs_prompt", "Un mensaje `-` espera el texto por la entrada estándar"),
+    ("cli.file_needs_prompt", "--file necesita un mensaje: pásalo como argumento o por la entrada estándar"),
+    ("cli.mock_needs_feature", "--mock-upstream necesita una compilación con la característica `mock`"),
+    ("cli.offline_needs_server", "--offline guarda los mensajes en una sesión y necesita la característica `server`"),
+    ("cli.preset_needs_server", "--prompt-preset lee ajustes guardados y necesita la característica `server`"),
+    ("cli.read_failed", "No se pudo leer {path}: {error}"),
+    ("cli.not_an_export", "{path} no es una exportación de sesión en JSON: {error}"),
+    ("cli.not_a_script", "{path} no es un guion válido: {error}"),
+    ("cli.file_exists", "{path} ya existe; usa --force para reemplazarlo"),
+    ("cli.no_api_key", "No se introdujo ninguna clave de API"),
+    ("cli.empty_preset", "El ajuste de prompt está vacío"),
+    ("cli.session_not_found", "No se encontró la sesión '{id}'"),
+    // Informes de la línea de comandos
+    ("cli.session", "🧵 Sesión: {id}"),
+    ("cli.usage", "📊 Uso: {prompt} de prompt + {completion} de respuesta = {total} tokens ({model})"),
+    ("cli.usage_unknown", "📊 Uso: no informado"),
+    ("cli.cost", "💰 Coste: {cost} ({prompt} tokens de prompt + {completion} de respuesta)"),
+    ("cli.cost_unknown", "💰 Coste: desconocido (no se informó el uso)"),
+    ("cli.cost_estimated", "💰 Estimado antes de enviar: hasta {cost} ({prompt} tokens de prompt + {completion} de respuesta)"),
+    ("cli.cost_warning", "⚠️  Esta petición podría costar hasta {cost}, por encima del umbral de ${threshold}"),
+    ("cli.redacted", "🔒 Se ocultaron {count} secreto(s) antes de enviar (--no-redact para enviar tal cual)"),
+    ("cli.dry_run", "🧪 Simulación: no se envió nada. Unos {tokens} tokens de prompt."),
+    ("cli.dry_run_dropped", "✂️  Se dejaron fuera {count} mensaje(s) antiguo(s) para no superar el presupuesto de contexto"),
+    ("cli.sent_queued", "📤 Se enviaron antes {count} mensaje(s) en cola"),
+    ("cli.reason_offline", "Sin conexión"),
+    ("cli.reason_unreachable", "API inaccesible"),
+    ("cli.queued", "📴 {reason}: mensaje en cola en la sesión {id}"),
+    ("cli.queued_hint", "💡 Envíalo con `grok-chat-app sessions flush` cuando vuelvas a tener conexión"),
+    ("cli.sessions_need_server", "⚠️  Las sesiones necesitan la característica `server`; este mensaje no se guardará"),
+    ("cli.no_matches", "Sin coincidencias"),
+    ("cli.exported", "📦 Sesión {id} exportada a {path}"),
+    ("cli.finetune_redacted", "🔒 Se ocultaron {count} secreto(s)"),
+    ("cli.resumed_one", "🔁 La respuesta se cortó y se reanudó (1 petición adicional)"),
+    ("cli.resumed", "🔁 La respuesta se cortó y se reanudó ({count} peticiones adicionales)"),
+    ("cli.no_logprobs", "(el modelo no devolvió logprobs)"),
+    // Informes de los subcomandos
+    ("check.config_failed", "❌ Configuración: {error}"),
+    ("check.config_loaded", "✅ Configuración cargada (API: {url})"),
+    ("check.api_ok", "✅ Clave de API aceptada ({count} modelos disponibles)"),
+    ("check.api_failed", "❌ API: {error}"),
+    ("check.pricing_ok", "✅ Tabla de precios cargada"),
+    ("check.pricing_failed", "❌ Precios: {error}"),
+    ("check.database_ok", "✅ Base de datos {url} ({count} sesiones)"),
+    ("check.database_failed", "❌ Base de datos {url}: {error}"),
+    ("auth.stored", "🔑 Clave de API guardada en el llavero del sistema (perfil '{profile}')"),
+    ("auth.removed", "🗑️  Se eliminó la clave de API guardada (perfil '{profile}')"),
+    ("auth.none_stored", "No hay ninguna clave de API guardada para el perfil '{profile}'"),
+    ("auth.status", "✅ Clave de API de: {source} (perfil '{profile}')"),
+    ("auth.keyring_disabled", "   Las consultas al llavero están desactivadas por {var}"),
+    ("auth.keyring_has_key", "   Hay una clave guardada en el llavero del sistema"),
+    ("auth.keyring_no_key", "   No hay ninguna clave en el llavero del sistema"),
+    ("sessions.none", "No hay sesiones guardadas"),
+    ("sessions.msgs", "msjs"),
+    ("sessions.untitled", "(sin título)"),
+    ("sessions.deleted", "🗑️  Sesión {id} eliminada"),
+    ("sessions.imported", "📥 Se importaron {sessions} sesiones ({messages} mensajes) de {path}"),
+    ("sessions.skipped_duplicates", "   Se omitieron {count} ya importadas"),
+    ("sessions.skipped_malformed", "   Se omitieron {count} entradas mal formadas"),
+    ("sessions.nothing_queued", "📭 No hay mensajes en cola"),
+    ("sessions.flushed", "📤 Se enviaron {sent} mensaje(s) en cola; quedan {remaining} en cola"),
+    ("sessions.flush_failed", "   ❌ Sesión {id}: {error}"),
+    ("sessions.not_flushed", "No se pudieron vaciar {count} sesión(es)"),
+    ("sessions.migrated", "🔀 Se movieron {count} sesión(es) de {from} a {to}"),
+    ("sessions.archived", "📦 Se exportaron {count} sesiones a {path}"),
+    ("sessions.archive_skipped", "   Se omitió {id}: {error}"),
+    ("sessions.finetune", "📦 Se exportaron {examples} ejemplo(s) de {sessions} sesión(es), ~{tokens} tokens"),
+    ("sessions.finetune_written", "📦 Se exportaron {examples} ejemplo(s) de {sessions} sesión(es), ~{tokens} tokens, a {path}"),
+    ("sessions.finetune_skipped", "   Se omitieron {count} sesión(es) sin nada que coincida"),
+    ("sessions.no_duplicates", "✨ No hay sesiones duplicadas"),
+    ("sessions.dedupe_keep", "{id}  {date}  {title} ({count} mensajes)"),
+    ("sessions.dedupe_duplicate", "  ↳ {id}  {date}  {title} ({count} mensajes, {likeness})"),
+    ("sessions.same_prompts", "mismos mensajes"),
+    ("sessions.alike", "{percent}% parecida"),
+    ("sessions.merged", "🧹 Se fusionaron {count} sesión(es) duplicada(s) en {groups}, moviendo {moved} mensaje(s) que solo estaban en ellas"),
+    ("sessions.duplicates_found", "🔍 {count} sesión(es) duplicada(s) en {groups} grupo(s); ejecuta con --apply para fusionarlas"),
+    ("prompts.saved", "📝 Preajuste de prompt '{name}' guardado"),
+    ("prompts.saved_default", "📝 Preajuste de prompt '{name}' guardado (predeterminado)"),
+    ("prompts.updated", "📝 Preajuste de prompt '{name}' actualizado"),
+    ("prompts.updated_default", "📝 Preajuste de prompt '{name}' actualizado (predeterminado)"),
+    ("prompts.none", "No hay preajustes de prompt guardados"),
+    ("prompts.not_found", "No se encontró el preajuste de prompt '{name}'"),
+    ("prompts.deleted", "🗑️  Preajuste de prompt {name} eliminado"),
+    ("prompts.sessions_reset", "⚠️  {count} sesión(es) usaban '{name}' y ahora reciben el prompt predeterminado"),
+    ("templates.invalid", "Plantilla de sesión no válida: {error}"),
+    ("templates.saved", "📝 Plantilla de sesión '{name}' guardada ({count} mensaje(s) de ejemplo)"),
+    ("templates.updated", "📝 Plantilla de sesión '{name}' actualizada ({count} mensaje(s) de ejemplo)"),
+    ("templates.none", "No hay plantillas de sesión guardadas"),
+    ("templates.not_found", "No se encontró la plantilla de sesión '{name}'"),
+    ("templates.deleted", "🗑️  Plantilla de sesión {name} eliminada"),
+    ("maintenance.pruned", "Se eliminaron {count} sesión(es) sin actualizar en {days} días"),
+    ("maintenance.would_prune", "Se eliminarían {count} sesión(es) sin actualizar en {days} días"),
+    ("maintenance.removed_media", "Se eliminaron {count} archivo(s) multimedia sin referencias, {kib} KiB"),
+    ("maintenance.would_remove_media", "Se eliminarían {count} archivo(s) multimedia sin referencias, {kib} KiB"),
+    ("maintenance.compacted", "🧹 Base de datos compactada"),
+    // Hooks de comandos
+    ("hooks.timed_out", "se detuvo tras {secs}s sin terminar"),
+    ("hooks.too_much_output", "escribió más de {kib} KiB de salida"),
+    ("hooks.not_utf8", "escribió una salida que no es UTF-8"),
+    ("hooks.failed", "terminó con el estado {status}: {stderr}"),
+    ("hooks.error", "❌ /{name}: {error}"),
+    ("hooks.running", "⚙️ Ejecutando /{name}..."),
+    ("hooks.confirm", "⚠️  {path} no se ha ejecutado antes, o ha cambiado desde la última vez. Se ejecuta con tus permisos. ¿Ejecutarlo? [s/N] "),
+    ("hooks.confirm_tui", "⚠️ {path} es nuevo o ha cambiado y se ejecuta con tus permisos. ¿Ejecutarlo? s/N"),
+    ("hooks.declined", "No se ejecutó el hook"),
+    ("hooks.inserted", "/{name} rellenó la entrada; edítala o pulsa Enter para enviarla"),
+    ("hooks.empty", "/{name} no imprimió nada"),
+    ("hooks.available", "Hooks: {names}"),
+];
@@ -0,0 +1,198 @@
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use super::{en, es};
+use crate::config::{config_file_path, selected_profile, ConfigFile};
+
+/// Selects the language of messages; `--locale` sets it for the process.
+pub const LOCALE_VAR: &str = "GROK_LOCALE";
+
+/// A language the user-facing strings are translated into.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Locale {
+    #[default]
+    En,
+    Es,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Locale::En, Locale::Es];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Es => "es",
+        }
+    }
+
+    fn strings(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Locale::En => en::STRINGS,
+            Locale::Es => es::STRINGS,
+        }
+    }
+
+    /// The locale a language tag (`es`, `es-MX`) or POSIX locale name
+    /// (`es_ES.UTF-8`) asks for, when it is one there are strings for.
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let language = tag.split(['_', '-', '.', '@']).next()?.to_lowercase();
+        Locale::ALL
+            .into_iter()
+            .find(|locale| locale.code() == language)
+    }
+
+    /// `GROK_LOCALE`, then the config file's `locale`, then the POSIX locale
+    /// variables. Anything without strings, such as `LANG=C`, gets English.
+    pub fn detect() -> Self {
+        let configured = || {
+            let file = ConfigFile::load(&config_file_path()?).ok()??;
+            file.settings(selected_profile().as_deref()).ok()?.locale
+        };
+        let system = || {
+            ["LC_ALL", "LC_MESSAGES", "LANG"]
+                .into_iter()
+                .filter_map(|name| std::env::var(name).ok())
+                .find(|value| !value.is_empty())
+        };
+        std::env::var(LOCALE_VAR)
+            .ok()
+            .filter(|value| !value.is_empty())
+            .or_else(configured)
+            .or_else(system)
+            .and_then(|tag| Locale::from_tag(&tag))
+            .unwrap_or_default()
+    }
+}
+
+impl FromStr for Locale {
+    type Err = anyhow::Error;
+
+    fn from_str(tag: &str) -> Result<Self> {
+        Locale::from_tag(tag).ok_or_else(|| {
+            let available: Vec<&str> = Locale::ALL.iter().map(|locale| locale.code()).collect();
+            anyhow!(
+                "Unsupported locale '{}' (available: {})",
+                tag,
+                available.join(", ")
+            )
+        })
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+static LOCALE: OnceLock<Locale> = OnceLock::new();
+
+/// Sets the locale for the rest of the process; only the first call counts.
+pub fn set_locale(locale: Locale) {
+    let _ = LOCALE.set(locale);
+}
+
+/// The process locale, English until [`set_locale`] is called.
+pub fn locale() -> Locale {
+    LOCALE.get().copied().unwrap_or_default()
+}
+
+/// The string for `key` in `locale`, without falling back.
+pub fn lookup(locale: Locale, key: &str) -> Option<&'static str> {
+    locale
+        .strings()
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, text)| *text)
+}
+
+/// The string for `key` in the process locale, else in English. An unknown
+/// key is returned as is, so a missing string shows up rather than vanishing.
+pub fn tr(key: &'static str) -> &'static str {
+    lookup(locale(), key)
+        .or_else(|| lookup(Locale::En, key))
+        .unwrap_or(key)
+}
+
+/// [`tr`] with each `{name}` placeholder replaced by its value.
+pub fn tr_args(key: &'static str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    fill(tr(key), args)
+}
+
+fn fill(text: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    args.iter().fold(text.to_string(), |text, (name, value)| {
+        text.replace(&format!("{{{}}}", name), &value.to_string())
+    })
+}
+
+/// Whether `answer` is one of the locale's words for yes.
+pub fn is_affirmative(answer: &str) -> bool {
+    let answer = answer.trim().to_lowercase();
+    tr("prompt.yes").split(',').any(|yes| yes == answer)
+}
+
+/// The `{name}` placeholders in `text`, sorted.
+#[cfg(test)]
+fn placeholders(text: &str) -> Vec<&str> {
+    let mut names: Vec<&str> = text
+        .split('{')
+        .skip(1)
+        .filter_map(|rest| rest.split_once('}').map(|(name, _)| name))
+        .collect();
+    names.sort_unstable();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn test_every_locale_is_complete() {
+        let english: BTreeSet<&str> = en::STRINGS.iter().map(|(key, _)| *key).collect();
+        assert_eq!(english.len(), en::STRINGS.len(), "duplicate English keys");
+        for locale in Locale::ALL {
+            let keys: BTreeSet<&str> = locale.strings().iter().map(|(key, _)| *key).collect();
+            assert_eq!(
+                keys.len(),
+                locale.strings().len(),
+                "duplicate {} keys",
+                locale
+            );
+            assert_eq!(keys, english, "{} differs from English", locale);
+            for (key, text) in locale.strings() {
+                assert_eq!(
+                    placeholders(text),
+                    placeholders(lookup(Locale::En, key).unwrap()),
+                    "{} placeholders of {}",
+                    locale,
+                    key
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_locale_from_tags() {
+        assert_eq!(Locale::from_tag("es_ES.UTF-8"), Some(Locale::Es));
+        assert_eq!(Locale::from_tag("es-MX"), Some(Locale::Es));
+        assert_eq!(Locale::from_tag("EN"), Some(Locale::En));
+        assert_eq!(Locale::from_tag("C"), None);
+        assert_eq!(Locale::from_tag("de_DE"), None);
+        let error = "ja".parse::<Locale>().unwrap_err().to_string();
+        assert!(error.contains("'ja'") && error.contains("en, es"));
+    }
+
+    #[test]
+    fn test_missing_strings_fall_back() {
+        assert_eq!(tr("no.such.key"), "no.such.key");
+        assert_eq!(
+            fill("{count} of {total}", &[("count", &1), ("total", &"2")]),
+            "1 of 2"
+        );
+        assert_eq!(lookup(Locale::Es, "no.such.key"), None);
+    }
+}
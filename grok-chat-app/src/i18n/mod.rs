@@ -0,0 +1,5 @@
+pub use self::i18n::*;
+mod en;
+mod es;
+#[allow(clippy::module_inception)]
+mod i18n;
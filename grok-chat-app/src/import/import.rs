@@ -0,0 +1,450 @@
+//! Brings conversations exported from other chat tools into the session
+//! database, so they can be listed, searched and continued like any other.
+//!
+//! Each conversation becomes one session with the model `imported`, keeping
+//! its title, roles and timestamps. Conversations are fingerprinted by their
+//! roles and contents, so importing the same export twice adds nothing.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+
+use crate::cli::ImportFormat;
+use crate::database::Database;
+use crate::models::{Annotation, ChatSession, Message, MessageRole};
+
+/// The model recorded for imported sessions.
+pub const IMPORTED_MODEL: &str = "imported";
+
+/// One conversation read from an export, before it is stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedConversation {
+    pub title: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub messages: Vec<ImportedMessage>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedMessage {
+    pub role: MessageRole,
+    pub content: String,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub model: Option<String>,
+    /// Kept from our own exports; other tools have none.
+    pub annotation: Option<Annotation>,
+}
+
+/// The conversations found in an export, and how many entries (whole
+/// conversations or single messages) were too malformed to read.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParsedExport {
+    pub conversations: Vec<ImportedConversation>,
+    pub malformed: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub sessions_imported: usize,
+    pub messages_imported: usize,
+    /// Conversations already imported earlier.
+    pub duplicates: usize,
+    /// Conversations and messages skipped as malformed.
+    pub malformed: usize,
+    pub session_ids: Vec<String>,
+}
+
+/// Reads `json` in the given format. Only a document that is not JSON at all,
+/// or has the wrong overall shape, is an error; bad entries are counted.
+pub fn parse_export(format: ImportFormat, json: &str) -> Result<ParsedExport> {
+    if format == ImportFormat::Archive {
+        anyhow::bail!("Archives are read with crate::archive::read_archive");
+    }
+    let document: Value = serde_json::from_str(json).context("Import file is not valid JSON")?;
+    let entries = match (format, document) {
+        (_, Value::Array(entries)) => entries,
+        (ImportFormat::Generic, entry @ Value::Object(_)) => vec![entry],
+        (ImportFormat::Chatgpt, _) => {
+            anyhow::bail!("A ChatGPT export is a JSON array of conversations")
+        }
+        (_, _) => anyhow::bail!("Expected a conversation object or an array of them"),
+    };
+
+    let mut parsed = ParsedExport::default();
+    for entry in &entries {
+        let conversation = match format {
+            ImportFormat::Chatgpt => chatgpt_conversation(entry, &mut parsed.malformed),
+            ImportFormat::Generic => generic_conversation(entry, &mut parsed.malformed),
+            ImportFormat::Archive => unreachable!("archives are rejected above"),
+        };
+        match conversation {
+            Some(conversation) if !conversation.messages.is_empty() => {
+                parsed.conversations.push(conversation)
+            }
+            _ => parsed.malformed += 1,
+        }
+    }
+    Ok(parsed)
+}
+
+/// Stores every parsed conversation not imported before.
+pub async fn import_sessions(database: &Database, parsed: ParsedExport) -> Result<ImportReport> {
+    let mut report = ImportReport {
+        malformed: parsed.malformed,
+        ..ImportReport::default()
+    };
+    // Repeats within one file are duplicates too
+    let mut seen = HashSet::new();
+
+    for conversation in parsed.conversations {
+        let hash = content_hash(&conversation);
+        if !seen.insert(hash.clone()) || database.find_import(&hash).await?.is_some() {
+            report.duplicates += 1;
+            continue;
+        }
+
+        let (session, messages) = into_session(conversation);
+        database
+            .import_session(&session, &messages, &[], Some(&hash))
+            .await
+            .with_context(|| format!("Failed to import '{}'", session_title(&session)))?;
+        report.sessions_imported += 1;
+        report.messages_imported += messages.len();
+        report.session_ids.push(session.id);
+    }
+    Ok(report)
+}
+
+fn session_title(session: &ChatSession) -> &str {
+    session.title.as_deref().unwrap_or("(untitled)")
+}
+
+/// SHA-256 over every message's role and content, hex encoded.
+fn content_hash(conversation: &ImportedConversation) -> String {
+    let mut hasher = Sha256::new();
+    for message in &conversation.messages {
+        hasher.update(message.role.as_str());
+        hasher.update([0]);
+        hasher.update(&message.content);
+        hasher.update([0]);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Messages without a timestamp take the one before them (or the
+/// conversation's), so the stored order matches the export.
+fn into_session(conversation: ImportedConversation) -> (ChatSession, Vec<Message>) {
+    let mut session = ChatSession::new(IMPORTED_MODEL.to_string(), conversation.title);
+    let mut timestamp = conversation
+        .created_at
+        .or_else(|| conversation.messages.iter().find_map(|m| m.timestamp))
+        .unwrap_or(session.created_at);
+    session.created_at = timestamp;
+
+    let messages: Vec<Message> = conversation
+        .messages
+        .into_iter()
+        .map(|imported| {
+            timestamp = imported.timestamp.unwrap_or(timestamp);
+            let mut message = Message::new(
+                session.id.clone(),
+                imported.role,
+                imported.content,
+                imported.model,
+            );
+            message.timestamp = timestamp;
+            message.annotation = imported.annotation;
+            message
+        })
+        .collect();
+    session.updated_at = timestamp;
+    (session, messages)
+}
+
+/// Follows `current_node` up through the parents, which is the branch the
+/// user last saw; edited-away branches are left out. Hidden and empty
+/// messages (ChatGPT stores its own system prompt that way) are dropped
+/// without counting as malformed.
+fn chatgpt_conversation(entry: &Value, malformed: &mut usize) -> Option<ImportedConversation> {
+    let mapping = entry["mapping"].as_object()?;
+    let mut node_id = entry["current_node"].as_str()?;
+
+    let mut path = Vec::new();
+    // The mapping is a tree, but a corrupt one could loop
+    let mut visited = HashSet::new();
+    while visited.insert(node_id) {
+        let node = mapping.get(node_id)?;
+        path.push(node);
+        match node["parent"].as_str() {
+            Some(parent) => node_id = parent,
+            None => break,
+        }
+    }
+    path.reverse();
+
+    let mut messages = Vec::new();
+    for node in path {
+        let message = &node["message"];
+        if message.is_null() || message["metadata"]["is_visually_hidden_from_conversation"] == true
+        {
+            continue;
+        }
+        let Some(role) = message["author"]["role"]
+            .as_str()
+            .and_then(|role| role.parse::<MessageRole>().ok())
+        else {
+            *malformed += 1;
+            continue;
+        };
+        let content = message["content"]["parts"]
+            .as_array()
+            .map(|parts| {
+                parts
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .or_else(|| message["content"]["text"].as_str().map(str::to_string))
+            .unwrap_or_default();
+        if content.trim().is_empty() {
+            continue;
+        }
+        messages.push(ImportedMessage {
+            model: (role == MessageRole::Assistant)
+                .then(|| {
+                    message["metadata"]["model_slug"]
+                        .as_str()
+                        .map(str::to_string)
+                })
+                .flatten(),
+            role,
+            content,
+            timestamp: unix_time(&message["create_time"]),
+            annotation: None,
+        });
+    }
+
+    Some(ImportedConversation {
+        title: entry["title"].as_str().map(str::to_string),
+        created_at: unix_time(&entry["create_time"]),
+        messages,
+    })
+}
+
+fn generic_conversation(entry: &Value, malformed: &mut usize) -> Option<ImportedConversation> {
+    let mut messages = Vec::new();
+    for message in entry["messages"].as_array()? {
+        let role = message["role"]
+            .as_str()
+            .and_then(|role| role.parse::<MessageRole>().ok());
+        let (Some(role), Some(content)) = (role, message["content"].as_str()) else {
+            *malformed += 1;
+            continue;
+        };
+        messages.push(ImportedMessage {
+            role,
+            content: content.to_string(),
+            timestamp: timestamp(&message["timestamp"]),
+            model: message["model"].as_str().map(str::to_string),
+            annotation: serde_json::from_value(message["annotation"].clone()).unwrap_or_default(),
+        });
+    }
+
+    Some(ImportedConversation {
+        title: entry["title"].as_str().map(str::to_string),
+        created_at: timestamp(&entry["created_at"]),
+        messages,
+    })
+}
+
+/// An RFC 3339 string or Unix seconds.
+fn timestamp(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::String(text) => DateTime::parse_from_rfc3339(text)
+            .ok()
+            .map(|time| time.with_timezone(&Utc)),
+        _ => unix_time(value),
+    }
+}
+
+/// Seconds since the epoch, with a fraction as ChatGPT writes them.
+fn unix_time(value: &Value) -> Option<DateTime<Utc>> {
+    let seconds = value.as_f64()?;
+    DateTime::from_timestamp(
+        seconds.trunc() as i64,
+        (seconds.fract() * 1e9).round() as u32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use serde_json::json;
+
+    fn chatgpt_export() -> String {
+        json!([{
+            "title": "Rust lifetimes",
+            "create_time": 1_700_000_000.5,
+            "current_node": "c",
+            "mapping": {
+                "root": {"id": "root", "message": null, "parent": null, "children": ["sys"]},
+                "sys": {
+                    "id": "sys",
+                    "parent": "root",
+                    "message": {
+                        "author": {"role": "system"},
+                        "content": {"content_type": "text", "parts": [""]},
+                        "metadata": {"is_visually_hidden_from_conversation": true}
+                    }
+                },
+                "a": {
+                    "id": "a",
+                    "parent": "sys",
+                    "message": {
+                        "author": {"role": "user"},
+                        "create_time": 1_700_000_001.0,
+                        "content": {"content_type": "text", "parts": ["What is 'a?"]}
+                    }
+                },
+                // An answer the user regenerated; not on the current path
+                "old": {
+                    "id": "old",
+                    "parent": "a",
+                    "message": {
+                        "author": {"role": "assistant"},
+                        "content": {"content_type": "text", "parts": ["Outdated"]}
+                    }
+                },
+                "b": {
+                    "id": "b",
+                    "parent": "a",
+                    "message": {
+                        "author": {"role": "assistant"},
+                        "create_time": 1_700_000_002.0,
+                        "content": {"content_type": "text", "parts": ["A lifetime."]},
+                        "metadata": {"model_slug": "gpt-4o"}
+                    }
+                },
+                "c": {
+                    "id": "c",
+                    "parent": "b",
+                    "message": {
+                        "author": {"role": "narrator"},
+                        "content": {"content_type": "text", "parts": ["?"]}
+                    }
+                }
+            }
+        }, {
+            "title": "No current node",
+            "mapping": {}
+        }])
+        .to_string()
+    }
+
+    #[test]
+    fn test_chatgpt_follows_the_current_branch() {
+        let parsed = parse_export(ImportFormat::Chatgpt, &chatgpt_export()).unwrap();
+        // The unknown role and the conversation without a current node
+        assert_eq!(parsed.malformed, 2);
+        assert_eq!(parsed.conversations.len(), 1);
+
+        let conversation = &parsed.conversations[0];
+        assert_eq!(conversation.title.as_deref(), Some("Rust lifetimes"));
+        assert_eq!(
+            conversation.created_at.unwrap().timestamp_millis(),
+            1_700_000_000_500
+        );
+        let messages: Vec<(&MessageRole, &str)> = conversation
+            .messages
+            .iter()
+            .map(|m| (&m.role, m.content.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                (&MessageRole::User, "What is 'a?"),
+                (&MessageRole::Assistant, "A lifetime.")
+            ]
+        );
+        assert_eq!(conversation.messages[1].model.as_deref(), Some("gpt-4o"));
+    }
+
+    #[test]
+    fn test_generic_accepts_an_object_or_an_array() {
+        let one = json!({
+            "title": "Notes",
+            "messages": [
+                {"role": "user", "content": "hi", "timestamp": "2023-05-01T10:00:00Z"},
+                {"role": "assistant", "content": "hello", "timestamp": 1682935260},
+                {"role": "user"},
+                {"role": "wizard", "content": "?"}
+            ]
+        });
+        let parsed = parse_export(ImportFormat::Generic, &one.to_string()).unwrap();
+        assert_eq!(parsed.malformed, 2);
+        let messages = &parsed.conversations[0].messages;
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[1].timestamp.unwrap().to_rfc3339(),
+            "2023-05-01T10:01:00+00:00"
+        );
+
+        let many = json!([one, {"messages": []}, "nonsense"]);
+        let parsed = parse_export(ImportFormat::Generic, &many.to_string()).unwrap();
+        assert_eq!(parsed.conversations.len(), 1);
+        assert_eq!(parsed.malformed, 2 + 2);
+
+        assert!(parse_export(ImportFormat::Generic, "not json").is_err());
+        assert!(parse_export(ImportFormat::Chatgpt, "{}").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_import_preserves_history_and_skips_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            ..Config::default()
+        };
+        let database = Database::new(&config).await.unwrap();
+        let export = chatgpt_export();
+
+        let report = import_sessions(
+            &database,
+            parse_export(ImportFormat::Chatgpt, &export).unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.sessions_imported, 1);
+        assert_eq!(report.messages_imported, 2);
+        assert_eq!(report.malformed, 2);
+
+        let stored = database
+            .export_session(&report.session_ids[0])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.session.model, IMPORTED_MODEL);
+        assert_eq!(stored.session.title.as_deref(), Some("Rust lifetimes"));
+        assert_eq!(stored.session.updated_at.timestamp(), 1_700_000_002);
+        assert_eq!(stored.messages[0].timestamp.timestamp(), 1_700_000_001);
+        assert_eq!(stored.messages[1].model.as_deref(), Some("gpt-4o"));
+
+        let again = import_sessions(
+            &database,
+            parse_export(ImportFormat::Chatgpt, &export).unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(again.sessions_imported, 0);
+        assert_eq!(again.duplicates, 1);
+        assert_eq!(database.get_total_sessions().await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,3 @@
+pub use self::import::*;
+#[allow(clippy::module_inception)]
+mod import;
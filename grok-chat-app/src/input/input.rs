@@ -0,0 +1,142 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// Combined size budget for piped stdin and `--file` contents inlined into a prompt.
+pub const MAX_INPUT_BYTES: usize = 256 * 1024;
+
+/// A block of text (piped stdin or a file) appended to the prompt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub label: String,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComposedPrompt {
+    pub text: String,
+    /// Bytes dropped to stay within the size budget.
+    pub omitted_bytes: usize,
+}
+
+impl Attachment {
+    pub fn new(label: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            label: label.into(),
+            content: content.into(),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(Self::new(path.display().to_string(), content))
+    }
+}
+
+/// Appends each attachment to `message` as a fenced block. Attachments share a
+/// budget of `limit` bytes; anything past it is cut at a character boundary and
+/// replaced with a truncation notice the model can see.
+pub fn compose_prompt(message: &str, attachments: &[Attachment], limit: usize) -> ComposedPrompt {
+    let mut text = message.trim_end().to_string();
+    let mut remaining = limit;
+    let mut omitted_bytes = 0;
+
+    for attachment in attachments {
+        let content = attachment.content.trim_end_matches('\n');
+        let kept = floor_char_boundary(content, remaining);
+        remaining -= kept;
+
+        let fence = fence_for(content);
+        text.push_str(&format!(
+            "\n\n{}:\n{}\n{}\n{}",
+            attachment.label,
+            fence,
+            &content[..kept],
+            fence
+        ));
+
+        if kept < content.len() {
+            let dropped = content.len() - kept;
+            omitted_bytes += dropped;
+            text.push_str(&format!(
+                "\n[{} truncated: {} of {} bytes omitted]",
+                attachment.label,
+                dropped,
+                content.len()
+            ));
+        }
+    }
+
+    ComposedPrompt {
+        text,
+        omitted_bytes,
+    }
+}
+
+/// A backtick fence longer than any run of backticks inside `content`.
+fn fence_for(content: &str) -> String {
+    let longest_run = content.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat(longest_run.max(2) + 1)
+}
+
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    if index >= text.len() {
+        return text.len();
+    }
+    (0..=index)
+        .rev()
+        .find(|i| text.is_char_boundary(*i))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compose_wraps_attachments_in_fences() {
+        let prompt = compose_prompt(
+            "review this diff\n",
+            &[Attachment::new("stdin", "+ added\n- removed\n")],
+            MAX_INPUT_BYTES,
+        );
+
+        assert_eq!(
+            prompt.text,
+            "review this diff\n\nstdin:\n```\n+ added\n- removed\n```"
+        );
+        assert_eq!(prompt.omitted_bytes, 0);
+    }
+
+    #[test]
+    fn test_fence_outgrows_embedded_backticks() {
+        let prompt = compose_prompt(
+            "explain",
+            &[Attachment::new("README.md", "```rust\nfn main() {}\n```")],
+            MAX_INPUT_BYTES,
+        );
+
+        assert!(prompt.text.contains("README.md:\n````\n```rust"));
+        assert!(prompt.text.ends_with("```\n````"));
+    }
+
+    #[test]
+    fn test_budget_is_shared_and_truncation_is_noted() {
+        let prompt = compose_prompt(
+            "summarize",
+            &[
+                Attachment::new("a.txt", "0123456789"),
+                Attachment::new("b.txt", "héllo"),
+            ],
+            12,
+        );
+
+        // a.txt uses 10 bytes, leaving 2: "h" fits but "é" would split
+        assert!(prompt.text.contains("a.txt:\n```\n0123456789\n```"));
+        assert!(prompt.text.contains("b.txt:\n```\nh\n```"));
+        assert!(prompt
+            .text
+            .ends_with("[b.txt truncated: 5 of 6 bytes omitted]"));
+        assert_eq!(prompt.omitted_bytes, 5);
+    }
+}
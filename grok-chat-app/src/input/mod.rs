@@ -0,0 +1,3 @@
+pub use self::input::*;
+#[allow(clippy::module_inception)]
+mod input;
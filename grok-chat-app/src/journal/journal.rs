@@ -0,0 +1,309 @@
+//! Crash journal for the terminal UI. The UI keeps conversations in memory
+//! until something puts them in the database, so each new message and
+//! settings change is also appended to a small JSON-lines file and synced to
+//! disk. A session's entries are dropped once it is safely stored, and the
+//! file is deleted when the UI exits normally, so a journal that outlives its
+//! UI holds exactly what a crash or dropped connection would have lost.
+//!
+//! Each running UI has its own journal and holds a lock on it; a journal
+//! nobody holds a lock on was left behind by a UI that is gone.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::config::config_dir;
+use crate::models::Message;
+
+/// Where journals are kept (`~/.config/grok-chat/journal` on Linux).
+pub fn journal_dir() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("journal"))
+}
+
+/// One line of a journal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JournalEntry {
+    /// A message joined the session's conversation.
+    Message { message: Message },
+    /// The session's model or prompt preset changed.
+    Settings {
+        session_id: String,
+        model: String,
+        prompt_preset: Option<String>,
+    },
+}
+
+impl JournalEntry {
+    pub fn session_id(&self) -> &str {
+        match self {
+            JournalEntry::Message { message } => &message.session_id,
+            JournalEntry::Settings { session_id, .. } => session_id,
+        }
+    }
+}
+
+/// The running UI's journal, locked while it is open.
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    entries: Vec<JournalEntry>,
+}
+
+impl Journal {
+    /// Starts a new journal in `dir`.
+    pub fn create(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let path = dir.join(format!("{}.jsonl", Uuid::new_v4().simple()));
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create_new(true)
+            .open(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        file.try_lock()
+            .with_context(|| format!("Failed to lock {}", path.display()))?;
+        Ok(Self {
+            path,
+            file,
+            entries: Vec::new(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends `entry` and waits until it is on disk.
+    pub fn append(&mut self, entry: JournalEntry) -> Result<()> {
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Drops the session's entries once it is safely in the database.
+    pub fn forget(&mut self, session_id: &str) -> Result<()> {
+        let before = self.entries.len();
+        self.entries
+            .retain(|entry| entry.session_id() != session_id);
+        if self.entries.len() == before {
+            return Ok(());
+        }
+
+        // Rewritten in place: a fresh file would be unlocked until locked
+        // again, and another UI could take it for an orphan meanwhile
+        let mut lines = String::new();
+        for entry in &self.entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(lines.as_bytes())?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Deletes the journal when the UI exits normally.
+    pub fn remove(self) -> Result<()> {
+        fs::remove_file(&self.path)?;
+        Ok(())
+    }
+}
+
+/// A session's conversation as a journal left it.
+#[derive(Debug, Clone)]
+pub struct RecoveredSession {
+    pub session_id: String,
+    /// From the newest settings entry, else the newest reply.
+    pub model: Option<String>,
+    pub prompt_preset: Option<String>,
+    pub messages: Vec<Message>,
+}
+
+/// A journal left behind by a UI that did not exit normally. It stays
+/// locked, so no other UI recovers it at the same time, and stays on disk
+/// until discarded, so recovery can be retried if it is interrupted.
+pub struct OrphanedJournal {
+    path: PathBuf,
+    _file: File,
+    pub sessions: Vec<RecoveredSession>,
+}
+
+impl OrphanedJournal {
+    pub fn message_count(&self) -> usize {
+        self.sessions
+            .iter()
+            .map(|session| session.messages.len())
+            .sum()
+    }
+
+    /// Deletes the journal once its sessions are recovered or declined.
+    pub fn discard(self) -> Result<()> {
+        fs::remove_file(&self.path)?;
+        Ok(())
+    }
+}
+
+/// The journals in `dir` left behind by UIs that are gone. Journals with
+/// nothing to recover are deleted on the way.
+pub fn orphaned_journals(dir: &Path) -> Result<Vec<OrphanedJournal>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", dir.display())),
+    };
+
+    let mut orphans = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_none_or(|extension| extension != "jsonl")
+        {
+            continue;
+        }
+        let file = File::open(&path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            // A running UI's journal
+            Err(TryLockError::WouldBlock) => continue,
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("Failed to lock {}", path.display()))
+            }
+        }
+        let sessions = read_sessions(&file)?;
+        let orphan = OrphanedJournal {
+            path,
+            _file: file,
+            sessions,
+        };
+        if orphan.message_count() == 0 {
+            orphan.discard()?;
+        } else {
+            orphans.push(orphan);
+        }
+    }
+    Ok(orphans)
+}
+
+/// Groups a journal's entries by session, in the order sessions first
+/// appear. A line cut short by the crash is skipped.
+fn read_sessions(file: &File) -> Result<Vec<RecoveredSession>> {
+    let mut sessions: Vec<RecoveredSession> = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let Ok(entry) = serde_json::from_str::<JournalEntry>(&line?) else {
+            continue;
+        };
+        let session_id = entry.session_id().to_string();
+        let index = match sessions.iter().position(|s| s.session_id == session_id) {
+            Some(index) => index,
+            None => {
+                sessions.push(RecoveredSession {
+                    session_id,
+                    model: None,
+                    prompt_preset: None,
+                    messages: Vec::new(),
+                });
+                sessions.len() - 1
+            }
+        };
+        let session = &mut sessions[index];
+        match entry {
+            JournalEntry::Message { message } => {
+                if message.model.is_some() {
+                    session.model = message.model.clone();
+                }
+                session.messages.push(message);
+            }
+            JournalEntry::Settings {
+                model,
+                prompt_preset,
+                ..
+            } => {
+                session.model = Some(model);
+                session.prompt_preset = prompt_preset;
+            }
+        }
+    }
+    Ok(sessions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(session_id: &str, content: &str) -> JournalEntry {
+        JournalEntry::Message {
+            message: Message::user(session_id.to_string(), content.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_only_unlocked_journals_are_orphans() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut live = Journal::create(dir.path()).unwrap();
+        live.append(message("s1", "still running")).unwrap();
+
+        assert!(orphaned_journals(dir.path()).unwrap().is_empty());
+
+        // Closing without `remove` is what a crash leaves behind
+        drop(live);
+        let orphans = orphaned_journals(dir.path()).unwrap();
+        assert_eq!(orphans.len(), 1);
+        assert_eq!(orphans[0].sessions[0].messages[0].content, "still running");
+
+        // Held by whoever is recovering it
+        assert!(orphaned_journals(dir.path()).unwrap().is_empty());
+        orphans.into_iter().next().unwrap().discard().unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_forget_keeps_other_sessions_and_torn_lines_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut journal = Journal::create(dir.path()).unwrap();
+        journal.append(message("saved", "stored now")).unwrap();
+        journal.append(message("unsaved", "hello")).unwrap();
+        journal
+            .append(JournalEntry::Settings {
+                session_id: "unsaved".to_string(),
+                model: "grok-3".to_string(),
+                prompt_preset: Some("reviewer".to_string()),
+            })
+            .unwrap();
+        journal.forget("saved").unwrap();
+        journal.append(message("unsaved", "again")).unwrap();
+        let path = journal.path().to_path_buf();
+        drop(journal);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"event\":\"message\",\"mess").unwrap();
+
+        let orphans = orphaned_journals(dir.path()).unwrap();
+        let sessions = &orphans[0].sessions;
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].session_id, "unsaved");
+        assert_eq!(sessions[0].model.as_deref(), Some("grok-3"));
+        assert_eq!(sessions[0].prompt_preset.as_deref(), Some("reviewer"));
+        let contents: Vec<_> = sessions[0].messages.iter().map(|m| &m.content).collect();
+        assert_eq!(contents, ["hello", "again"]);
+    }
+
+    #[test]
+    fn test_journals_without_messages_are_cleaned_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut journal = Journal::create(dir.path()).unwrap();
+        journal.append(message("s1", "hi")).unwrap();
+        journal.forget("s1").unwrap();
+        drop(journal);
+
+        assert!(orphaned_journals(dir.path()).unwrap().is_empty());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,3 @@
+pub use self::journal::*;
+#[allow(clippy::module_inception)]
+mod journal;
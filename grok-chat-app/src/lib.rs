@@ -1,16 +1,96 @@
 // Library exports for the Grok Chat App
 
+pub mod agents;
+pub mod auth;
+pub mod capabilities;
+pub mod chat;
+pub mod cli;
+pub mod client;
 pub mod config;
+pub mod context;
+pub mod diff;
+pub mod hooks;
+pub mod i18n;
+pub mod input;
 pub mod models;
+pub mod postprocess;
+pub mod preflight;
+pub mod presets;
+pub mod pricing;
+pub mod redact;
+pub mod render;
+pub mod repl;
+pub mod replay;
+pub mod sanitize;
+pub mod session_templates;
+pub mod templates;
 
 #[cfg(feature = "server")]
-pub mod client;
+pub mod annotations;
+
+#[cfg(feature = "server")]
+pub mod archive;
+
+#[cfg(feature = "server")]
+pub mod audit;
+
+#[cfg(feature = "server")]
+pub mod bench;
 
 #[cfg(feature = "server")]
 pub mod database;
 
+#[cfg(feature = "server")]
+pub mod dedupe;
+
+#[cfg(feature = "server")]
+pub mod export;
+
+#[cfg(feature = "server")]
+pub mod finetune;
+
+#[cfg(feature = "server")]
+pub mod grep;
+
+#[cfg(feature = "server")]
+pub mod import;
+
+#[cfg(feature = "terminal")]
+pub mod journal;
+
+#[cfg(feature = "mcp")]
+pub mod mcp;
+
+#[cfg(feature = "server")]
+pub mod media;
+
+#[cfg(feature = "server")]
+pub mod outbox;
+
+#[cfg(feature = "server")]
+pub mod reload;
+
+#[cfg(feature = "server")]
+pub mod report;
+
+#[cfg(feature = "server")]
+pub mod script;
+
 #[cfg(feature = "terminal")]
 pub mod ui;
 
 #[cfg(feature = "server")]
-pub mod api;
\ No newline at end of file
+pub mod api;
+
+pub use chat::{GrokChat, Reply, ReplyStream, Session};
+
+/// The types most library users need: `use grok_chat_app::prelude::*;`
+pub mod prelude {
+    pub use crate::chat::{GrokChat, Reply, ReplyStream, Session};
+    pub use crate::client::{ChatService, XaiError};
+    pub use crate::config::Config;
+    pub use crate::models::{
+        ApiChatRequest, ApiMessage, ChatSession, Conversation, Message, MessageRole, MessageStatus,
+        StreamChunk, UsageStats,
+    };
+}
@@ -1,201 +1,2575 @@
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use clap::Parser;
-use std::io::{self, Write};
+use futures_util::StreamExt;
+use std::io::{self, IsTerminal, Read, Write};
+use std::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use grok_chat_app::auth::{
+    delete_api_key, keyring_enabled, store_api_key, stored_api_key, ApiKeySource, InvalidApiKey,
+    DEFAULT_PROFILE, NO_KEYRING_VAR,
+};
+use grok_chat_app::capabilities::ParamWarning;
+use grok_chat_app::cli::{
+    error_json, AuthCommand, ChatArgs, ChatOutput, Cli, Command, ConfigCommand, ExitStatus,
+    LegacyCommand, ModelsArgs, OutputFormat, ReplayArgs, UsageError,
+};
+use grok_chat_app::client::{is_offline, ChatResponse, ChatService, DryRun, XaiError};
+use grok_chat_app::config::{
+    config_file_path, selected_profile, Config, MissingApiKey, CONFIG_TEMPLATE, PROFILE_VAR,
+};
+#[cfg(feature = "server")]
+use grok_chat_app::context::session_context_dropping;
+use grok_chat_app::context::{fit_to_budget, DEFAULT_TOKEN_BUDGET};
+use grok_chat_app::hooks::{Hook, HookOutput, Hooks};
+use grok_chat_app::i18n::{is_affirmative, set_locale, tr, tr_args, Locale, LOCALE_VAR};
+use grok_chat_app::input::{compose_prompt, Attachment, ComposedPrompt, MAX_INPUT_BYTES};
+use grok_chat_app::models::{
+    ApiChatRequest, ApiChatResponse, ApiMessage, CompletionStats, Conversation, Message,
+    SessionExport, UsageStats,
+};
+use grok_chat_app::preflight::PreflightFailed;
+use grok_chat_app::presets::PromptPreset;
+use grok_chat_app::pricing::{
+    Cost, CostEstimate, CostGuard, CostNotConfirmed, PricingTable, Verdict,
+};
+use grok_chat_app::redact::NO_REDACT_VAR;
+use grok_chat_app::render::{looks_like_markdown, no_color, render_markdown, MarkdownRenderer};
+use grok_chat_app::repl::{join_continuations, line_editor, save_history, LineEditor};
+use grok_chat_app::replay::{replay, ReplayOptions, ReplayReport};
+use grok_chat_app::sanitize::{sanitize, Sanitizer};
+use rustyline::error::ReadlineError;
+
+#[cfg(feature = "server")]
+use anyhow::bail;
+#[cfg(feature = "server")]
+use grok_chat_app::archive::{export_archive, import_archive, read_archive, ArchiveWriter};
+#[cfg(feature = "server")]
+use grok_chat_app::audit::{Actor, AuditOp, AuditRecord};
+#[cfg(feature = "server")]
+use grok_chat_app::bench::{run_bench, BenchOptions};
+#[cfg(feature = "server")]
+use grok_chat_app::cli::{
+    BenchArgs, ExportFormat, GrepArgs, ImportFormat, MaintenanceArgs, PromptsCommand, ReportArgs,
+    ReportFormat, RunArgs, ServeArgs, SessionTemplatesCommand, SessionsCommand,
+};
+#[cfg(feature = "server")]
+use grok_chat_app::database::{Database, MessageFilter};
+#[cfg(feature = "server")]
+use grok_chat_app::dedupe::{dedupe_sessions, DedupeReport};
+#[cfg(feature = "server")]
+use grok_chat_app::finetune::{FinetuneExport, FinetuneFilter};
+#[cfg(feature = "server")]
+use grok_chat_app::grep::{Pattern, TextStyle};
+#[cfg(feature = "server")]
+use grok_chat_app::import::{import_sessions, parse_export};
+#[cfg(feature = "server")]
+use grok_chat_app::media::MediaStore;
+#[cfg(feature = "server")]
+use grok_chat_app::models::{ChatSession, SessionSummary};
+#[cfg(feature = "server")]
+use grok_chat_app::outbox::Outbox;
+#[cfg(feature = "server")]
+use grok_chat_app::redact::Redactor;
+#[cfg(feature = "server")]
+use grok_chat_app::report::{usage_report, ReportZone};
+#[cfg(feature = "server")]
+use grok_chat_app::script::{run_script, Script, ScriptEvent, ScriptOptions, ScriptReport};
+#[cfg(feature = "server")]
+use grok_chat_app::session_templates::SessionTemplate;
+#[cfg(feature = "server")]
+use std::collections::HashMap;
 
 #[cfg(feature = "terminal")]
 use grok_chat_app::ui::run_terminal_chat;
 
-#[derive(Parser)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    /// Run in terminal mode (requires terminal feature)
-    #[arg(short, long)]
-    terminal: bool,
+#[tokio::main]
+async fn main() {
+    // Load environment variables
+    dotenvy::dotenv().ok();
 
-    /// Run HTTP API server
-    #[arg(short, long)]
-    server: bool,
+    let cli = Cli::parse();
+    let quiet = cli.quiet;
+    let output = cli
+        .command
+        .as_ref()
+        .map_or(OutputFormat::Text, Command::output);
 
-    /// Port for HTTP server
-    #[arg(short, long, default_value = "3000")]
-    port: u16,
+    if let Err(e) = run(cli).await {
+        let status = ExitStatus::of(&e);
+        if output == OutputFormat::Json {
+            eprintln!("{}", error_json(&e));
+        } else if quiet {
+            eprintln!(
+                "{}",
+                tr_args("cli.error_quiet", &[("error", &format!("{:#}", e))])
+            );
+        } else {
+            eprintln!(
+                "{}",
+                tr_args("cli.error", &[("error", &format!("{:#}", e))])
+            );
+            // A preflight report carries its own fixes
+            if let Some(hint) = status.hint().filter(|_| !e.is::<PreflightFailed>()) {
+                eprintln!("{}", tr_args("cli.hint", &[("hint", &hint)]));
+            }
+        }
+        std::process::exit(status.code());
+    }
+}
 
-    /// Host for HTTP server
-    #[arg(short = 'H', long, default_value = "127.0.0.1")]
-    host: String,
+async fn run(cli: Cli) -> Result<()> {
+    let quiet = cli.quiet;
 
-    /// Session ID to resume (terminal mode)
-    #[arg(short = 'i', long)]
-    session: Option<String>,
+    // `Config::load` reads the profile from the environment; publish the flag
+    // there before anything loads the configuration
+    if let Some(profile) = &cli.profile {
+        std::env::set_var(PROFILE_VAR, profile);
+    }
+    if cli.no_redact {
+        std::env::set_var(NO_REDACT_VAR, "1");
+    }
+    if let Some(locale) = cli.locale {
+        std::env::set_var(LOCALE_VAR, locale.code());
+    }
+    set_locale(Locale::detect());
 
-    /// Model to use
-    #[arg(short, long, default_value = "grok-4-0709")]
-    model: String,
+    let Some(command) = cli.command else {
+        if cli.legacy.is_used() && !quiet {
+            eprintln!("{}", tr("cli.legacy_flags"));
+        }
 
-    /// Message to send (if not provided, will enter interactive mode)
-    #[arg(short = 'g', long)]
-    message: Option<String>,
+        // The terminal UI and server report a missing key in their preflight
+        return match cli.legacy.into_command() {
+            #[cfg(feature = "terminal")]
+            LegacyCommand::Tui(args) => {
+                run_terminal_chat(args.session, args.model, args.skip_preflight, args.offline).await
+            }
+            #[cfg(feature = "server")]
+            LegacyCommand::Serve(args) => serve(args).await,
+            LegacyCommand::Chat(args) => {
+                require_api_key()?;
+                run_chat(args, true, quiet).await
+            }
+        };
+    };
 
-    /// System prompt to use
-    #[arg(
-        short = 'y',
-        long,
-        default_value = "You are Grok, a helpful and maximally truthful AI built by xAI, not based on any other companies and their models."
-    )]
-    system: String,
+    // `check` reports a missing key itself, `tui` and `serve` in their
+    // preflight; `config`, `auth` and `prompts` work without one, as does
+    // `mcp` until a message is sent
+    let exempt = match command {
+        Command::Check | Command::Config(_) | Command::Auth(_) => true,
+        #[cfg(feature = "server")]
+        Command::Prompts(_) | Command::SessionTemplates(_) => true,
+        #[cfg(feature = "mcp")]
+        Command::Mcp => true,
+        #[cfg(feature = "terminal")]
+        Command::Tui(_) => true,
+        #[cfg(feature = "server")]
+        Command::Serve(_) => true,
+        #[cfg(feature = "server")]
+        Command::Bench(_) | Command::Report(_) => true,
+        _ => false,
+    };
+    if !exempt {
+        require_api_key()?;
+    }
 
-    /// Maximum tokens
-    #[arg(short = 'x', long, default_value = "2048")]
-    max_tokens: i32,
+    match command {
+        Command::Chat(args) => run_chat(args, false, quiet).await,
+        #[cfg(feature = "terminal")]
+        Command::Tui(args) => {
+            run_terminal_chat(args.session, args.model, args.skip_preflight, args.offline).await
+        }
+        #[cfg(feature = "server")]
+        Command::Serve(args) => serve(args).await,
+        #[cfg(feature = "server")]
+        Command::Sessions(command) => run_sessions(command).await,
+        #[cfg(feature = "server")]
+        Command::Prompts(command) => run_prompts(command).await,
+        #[cfg(feature = "server")]
+        Command::SessionTemplates(command) => run_session_templates(command).await,
+        Command::Models(args) => list_models(args).await,
+        Command::Replay(args) => run_replay(args, quiet).await,
+        #[cfg(feature = "server")]
+        Command::Run(args) => run_script_file(args, quiet).await,
+        Command::Check => run_check().await,
+        #[cfg(feature = "server")]
+        Command::Maintenance(args) => run_maintenance(args).await,
+        #[cfg(feature = "server")]
+        Command::Bench(args) => bench(args, quiet).await,
+        #[cfg(feature = "server")]
+        Command::Report(args) => run_report(args).await,
+        #[cfg(feature = "mcp")]
+        Command::Mcp => grok_chat_app::mcp::run_stdio().await,
+        Command::Config(command) => run_config(command),
+        Command::Auth(command) => run_auth(command).await,
+    }
+}
 
-    /// Temperature
-    #[arg(short = 'p', long, default_value = "0.7")]
-    temperature: f32,
+/// Fails early, before any UI starts, when no API key is configured.
+fn require_api_key() -> Result<()> {
+    Config::from_env().map(drop)
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Load environment variables
-    dotenvy::dotenv().ok();
+/// Sends one message, or reads messages line by line when there is no prompt.
+/// A bare invocation (`tui_fallback`) opens the terminal UI instead. `quiet`
+/// keeps stderr free of notes and banners.
+#[cfg_attr(not(feature = "terminal"), allow(unused_variables))]
+async fn run_chat(mut args: ChatArgs, tui_fallback: bool, quiet: bool) -> Result<()> {
+    // JSON output is quiet by definition
+    let quiet = quiet || args.output == OutputFormat::Json;
+    let prompt = read_prompt(&args)?;
 
-    let args = Args::parse();
+    #[cfg(feature = "terminal")]
+    if tui_fallback && prompt.is_none() && !args.dry_run {
+        return run_terminal_chat(args.session, args.model, false, false).await;
+    }
 
-    // Check for API key
-    if std::env::var("XAI_API_KEY").is_err() {
-        eprintln!("❌ Error: XAI_API_KEY environment variable is required");
-        eprintln!("💡 Please set your xAI API key:");
-        eprintln!("   export XAI_API_KEY=your_api_key_here");
-        std::process::exit(1);
+    if let Some(prompt) = &prompt {
+        if prompt.omitted_bytes > 0 && !quiet {
+            eprintln!(
+                "{}",
+                tr_args(
+                    "cli.input_truncated",
+                    &[
+                        ("limit", &(MAX_INPUT_BYTES / 1024)),
+                        ("bytes", &prompt.omitted_bytes)
+                    ]
+                )
+            );
+        }
     }
 
-    #[cfg(feature = "terminal")]
-    if args.terminal || (!args.server && args.message.is_none()) {
-        // Run terminal interface
-        return run_terminal_chat(args.session, args.model).await;
+    // One service (and therefore one connection pool) for the whole process
+    let config = Config::from_env()?;
+    if let Some(name) = &args.prompt_preset {
+        args.system = Some(find_preset(&config, name).await?.body);
+    }
+    args.agent = session_agent(&config, &args).await?;
+    if let Some(name) = &args.agent {
+        args.apply_agent(&config.agent(name)?);
     }
+    args.apply_config(&config)?;
+    let chat_service = ChatService::new(&config)?.with_system_prompt(args.system());
 
-    #[cfg(feature = "server")]
-    if args.server {
-        // Run HTTP API server
-        return grok_chat_app::api::run_server(args.host, args.port).await;
-    }
-
-    if let Some(message) = args.message {
-        // Single message mode
-        let response = send_message(
-            &args.model,
-            &args.system,
-            &message,
-            args.max_tokens,
-            args.temperature,
-        )
-        .await?;
-        println!("{}", response);
-    } else {
-        // Interactive mode (fallback)
-        println!("🤖 Grok Chat (Interactive Mode)");
-        println!("Model: {}", args.model);
-        println!("Type 'quit' or 'exit' to end the conversation.");
-        println!();
+    match prompt {
+        Some(prompt) => send_single(&chat_service, &config, &args, prompt.text, quiet).await,
+        None if args.output == OutputFormat::Json => {
+            Err(UsageError(tr("cli.json_needs_message").to_string()).into())
+        }
+        None if args.offline => Err(UsageError(tr("cli.offline_needs_message").to_string()).into()),
+        None if args.dry_run => Err(UsageError(tr("cli.dry_run_needs_message").to_string()).into()),
+        None => run_interactive(&chat_service, &config, &args, quiet).await,
+    }
+}
 
-        loop {
-            print!("You: ");
-            io::stdout().flush()?;
+async fn send_single(
+    chat_service: &ChatService,
+    config: &Config,
+    args: &ChatArgs,
+    message: String,
+    quiet: bool,
+) -> Result<()> {
+    // Logprobs and JSON output both need the complete response
+    let json = args.output == OutputFormat::Json;
+    let stream =
+        !json && (args.stream || (!args.no_stream && !args.logprobs && io::stdout().is_terminal()));
+    let session = CliSession::open(args, config, chat_service, quiet).await?;
+    if let Some(session) = session.as_ref().filter(|_| !args.dry_run) {
+        if args.offline {
+            return queue_offline(session, message, args, tr("cli.reason_offline"), quiet).await;
+        }
+        // Anything queued earlier in the session goes out first, in order
+        match session.flush_queued().await {
+            Ok(0) => {}
+            Ok(sent) if !quiet => eprintln!("{}", tr_args("cli.sent_queued", &[("count", &sent)])),
+            Ok(_) => {}
+            Err(e) if is_offline(&e) => {
+                return queue_offline(session, message, args, tr("cli.reason_unreachable"), quiet)
+                    .await;
+            }
+            Err(e) => return Err(e.context("Failed to send the messages queued in this session")),
+        }
+    }
+    let (history, dropped) = match &session {
+        Some(session) => session.history().await?,
+        None => (Vec::new(), Vec::new()),
+    };
+    // A session's own preset stands in for the configured prompt
+    let system = session
+        .as_ref()
+        .and_then(CliSession::system_prompt)
+        .unwrap_or(args.system());
+    let messages = Conversation::new()
+        .system_prompt(system)
+        .history(history)
+        .user(message.clone())
+        .into_messages();
 
-            let mut input = String::new();
-            std::io::stdin().read_line(&mut input)?;
-            let input = input.trim();
+    let mut request = ApiChatRequest::builder()
+        .messages(messages)
+        .model(args.model())
+        .stream(stream)
+        .logprobs(args.logprobs)
+        .top_logprobs(args.top_logprobs)
+        .build();
+    request.max_tokens = args.max_tokens;
+    request.temperature = args.temperature;
+    if args.dry_run {
+        let dry_run = chat_service.dry_run(request).with_dropped(dropped);
+        return print_dry_run(&dry_run, args, quiet);
+    }
+    let redactions = chat_service.redact(&mut request);
+    if redactions > 0 && !quiet {
+        print_redactions(redactions);
+    }
+    let warnings = chat_service.fit_to_model(&mut request);
+    if !quiet {
+        print_param_warnings(&warnings, args.temperature_given);
+    }
+    let estimated_cost = guard_cost(chat_service, config, &request, args.yes, quiet)?;
 
-            if input == "quit" || input == "exit" {
-                break;
+    let started = Instant::now();
+    let sent = async {
+        if stream {
+            stream_reply(chat_service, request, styles(args, None)).await
+        } else {
+            let response = complete(chat_service, request).await?;
+            let reply = response.get_content()?;
+            if !json {
+                print_reply(&reply, styles(args, Some(&reply)));
+                if args.logprobs {
+                    print_logprobs(&response);
+                }
             }
+            Ok(Sent {
+                reply,
+                usage: response.get_usage().cloned(),
+                finish_reason: response.get_finish_reason(),
+                effects: response.effects.clone(),
+                response: Some(response),
+                resumes: None,
+                cancelled: false,
+            })
+        }
+    };
+    let sent = match (sent.await, &session) {
+        (Err(e), Some(session)) if is_offline(&e) => {
+            return queue_offline(session, message, args, tr("cli.reason_unreachable"), quiet)
+                .await;
+        }
+        (result, _) => result?,
+    };
+    let Sent {
+        reply,
+        usage,
+        finish_reason,
+        response,
+        resumes,
+        effects,
+        cancelled,
+    } = sent;
+    let latency = started.elapsed();
+    if let Some(resumes) = resumes.filter(|_| !quiet) {
+        let notice = match resumes {
+            1 => tr("cli.resumed_one").to_string(),
+            _ => tr_args("cli.resumed", &[("count", &resumes)]),
+        };
+        eprintln!("{}", notice);
+    }
+    if !quiet {
+        print_effects(&effects);
+    }
+
+    // Cancelled before any of the reply came, the turn is left unsaved
+    if let Some(session) = session
+        .as_ref()
+        .filter(|_| !(cancelled && reply.is_empty()))
+    {
+        session
+            .save_turn(
+                chat_service.stored_text(message),
+                reply.clone(),
+                args.model(),
+                usage.as_ref(),
+                CompletionStats {
+                    latency_ms: Some(latency.as_millis() as u64),
+                    finish_reason: finish_reason.clone(),
+                },
+                resumes,
+            )
+            .await?;
+    }
+
+    if json {
+        let cost = if args.show_cost || estimated_cost.is_some() {
+            let pricing = PricingTable::load(config)?;
+            Some(usage.as_ref().map_or(Cost::Unknown, |usage| {
+                pricing.estimate_cost(usage, args.model())
+            }))
+        } else {
+            None
+        };
+        let output = ChatOutput {
+            content: reply,
+            model: response.as_ref().map_or_else(
+                || args.model().to_string(),
+                |response| response.model.clone(),
+            ),
+            finish_reason,
+            usage,
+            latency_ms: latency.as_millis() as u64,
+            redactions,
+            session_id: session.map(|session| session.id),
+            cost,
+            estimated_cost,
+            logprobs: response
+                .as_ref()
+                .and_then(|r| r.get_logprobs())
+                .map(<[_]>::to_vec),
+            effects,
+        };
+        println!("{}", serde_json::to_string(&output)?);
+        return Ok(());
+    }
 
-            if input.is_empty() {
+    if let Some(session) = session.as_ref().filter(|_| !quiet) {
+        eprintln!("{}", tr_args("cli.session", &[("id", &session.id)]));
+    }
+
+    if args.verbose {
+        match &usage {
+            Some(usage) => eprintln!(
+                "{}",
+                tr_args(
+                    "cli.usage",
+                    &[
+                        ("prompt", &usage.prompt_tokens),
+                        ("completion", &usage.completion_tokens),
+                        ("total", &usage.total_tokens),
+                        ("model", &args.model()),
+                    ],
+                )
+            ),
+            None => eprintln!("{}", tr("cli.usage_unknown")),
+        }
+    }
+
+    if args.show_cost {
+        let pricing = PricingTable::load(config)?;
+        match &usage {
+            Some(usage) => eprintln!(
+                "{}",
+                tr_args(
+                    "cli.cost",
+                    &[
+                        ("cost", &pricing.estimate_cost(usage, args.model())),
+                        ("prompt", &usage.prompt_tokens),
+                        ("completion", &usage.completion_tokens),
+                    ],
+                )
+            ),
+            None => eprintln!("{}", tr("cli.cost_unknown")),
+        }
+        if let Some(estimate) = &estimated_cost {
+            eprintln!(
+                "{}",
+                tr_args(
+                    "cli.cost_estimated",
+                    &[
+                        ("cost", &estimate.cost),
+                        ("prompt", &estimate.prompt_tokens),
+                        ("completion", &estimate.completion_tokens),
+                    ],
+                )
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Stores the prompt in its session as queued instead of sending it. JSON
+/// output is the stored message, whose `status` is `queued`.
+async fn queue_offline(
+    session: &CliSession,
+    message: String,
+    args: &ChatArgs,
+    reason: &str,
+    quiet: bool,
+) -> Result<()> {
+    let queued = session.queue(message, args.model()).await?;
+    if args.output == OutputFormat::Json {
+        println!("{}", serde_json::to_string(&queued)?);
+    } else if !quiet {
+        eprintln!(
+            "{}",
+            tr_args("cli.queued", &[("reason", &reason), ("id", &session.id)])
+        );
+        eprintln!("{}", tr("cli.queued_hint"));
+    }
+    Ok(())
+}
+
+/// Line-by-line chat that keeps the conversation as context, trimming the
+/// oldest turns once the history outgrows the token budget.
+async fn run_interactive(
+    chat_service: &ChatService,
+    config: &Config,
+    args: &ChatArgs,
+    quiet: bool,
+) -> Result<()> {
+    let mut model = args.model().to_string();
+    let mut history = Conversation::new()
+        .system_prompt(args.system())
+        .into_messages();
+
+    if !quiet {
+        println!("{}", tr("repl.banner"));
+        println!("{}", tr_args("repl.model", &[("model", &model)]));
+        println!("{}", tr("repl.instructions"));
+        println!();
+    }
+
+    let mut editor = line_editor()?;
+    let hooks = Hooks::from_config();
+    // Hook output waiting in the next line for review
+    let mut pending_input: Option<String> = None;
+
+    loop {
+        let line = match pending_input.take() {
+            Some(text) => editor.readline_with_initial(tr("repl.user"), (&text, "")),
+            None => editor.readline(tr("repl.user")),
+        };
+        let line = match line {
+            Ok(line) => line,
+            // Ctrl-C abandons the current line, Ctrl-D ends the session
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let input = join_continuations(line.trim());
+        let input = input.as_str();
+        if !input.is_empty() {
+            let _ = editor.add_history_entry(input);
+        }
+
+        if input == "quit" || input == "exit" {
+            break;
+        }
+
+        if input.is_empty() {
+            continue;
+        }
+
+        let mut hook_message = None;
+        if let Some(command) = input.strip_prefix('/') {
+            let (name, value) = command
+                .split_once(char::is_whitespace)
+                .map_or((command, ""), |(name, value)| (name, value.trim()));
+
+            match (name, value) {
+                ("reset", _) => {
+                    history.truncate(1);
+                    println!("{}", tr("repl.reset"));
+                }
+                ("model", "") => println!("{}", tr_args("repl.model", &[("model", &model)])),
+                ("model", name) => match config.resolve_model(Some(name)) {
+                    Ok(resolved) => {
+                        model = resolved;
+                        match chat_service.profiles().profile_for(&model) {
+                            Some(profile) => println!(
+                                "{}",
+                                tr_args(
+                                    "repl.model_switched_profile",
+                                    &[("model", &model), ("profile", &profile.summary())]
+                                )
+                            ),
+                            None => {
+                                println!("{}", tr_args("repl.model_switched", &[("model", &model)]))
+                            }
+                        }
+                    }
+                    Err(e) => println!("❌ {}", e),
+                },
+                ("system", "") => println!(
+                    "{}",
+                    tr_args("repl.system", &[("prompt", &history[0].content)])
+                ),
+                ("system", prompt) => {
+                    history[0] = ApiMessage::system(prompt);
+                    println!("{}", tr("repl.system_updated"));
+                }
+                ("help", _) => {
+                    for key in ["repl.help_reset", "repl.help_model", "repl.help_system"] {
+                        println!("{}", tr(key));
+                    }
+                    if let Some(hooks) = &hooks {
+                        let dir = hooks.dir().display();
+                        println!("{}", tr_args("repl.help_hook", &[("dir", &dir)]));
+                        let names = hooks.names();
+                        if !names.is_empty() {
+                            let names = names.join(", ");
+                            println!("{}", tr_args("hooks.available", &[("names", &names)]));
+                        }
+                    }
+                }
+                _ => match hooks
+                    .as_ref()
+                    .and_then(|hooks| Some((hooks, hooks.find(name)?)))
+                {
+                    Some((hooks, hook)) => {
+                        match run_repl_hook(&mut editor, hooks, &hook, value, &history[1..]).await {
+                            Some(HookOutput::Insert(text)) => {
+                                pending_input = Some(text.trim_end().to_string())
+                            }
+                            Some(HookOutput::Send(text)) => hook_message = Some(text),
+                            None => {}
+                        }
+                    }
+                    None => println!("{}", tr_args("repl.unknown_command", &[("name", &name)])),
+                },
+            }
+            if hook_message.is_none() {
+                println!();
                 continue;
             }
+        }
+        let input = match hook_message.as_deref() {
+            Some(message) => {
+                // Show what the hook sends on the user's behalf
+                println!("{}{}", tr("repl.user"), message.trim());
+                message.trim()
+            }
+            None => input,
+        };
+
+        history.push(ApiMessage::user(input));
+        let dropped = fit_to_budget(&mut history, DEFAULT_TOKEN_BUDGET);
+        if dropped > 0 {
+            println!("{}", tr_args("repl.dropped", &[("count", &dropped)]));
+        }
+
+        let mut request = ApiChatRequest::builder()
+            .messages(history.clone())
+            .model(&model)
+            .stream(false)
+            .build();
+        request.max_tokens = args.max_tokens;
+        request.temperature = args.temperature;
+        let redactions = chat_service.redact(&mut request);
+        if redactions > 0 {
+            if !quiet {
+                print_redactions(redactions);
+            }
+            // Keep the masked text so later turns don't count it again
+            history.clone_from(&request.messages);
+        }
+        if !quiet {
+            print_param_warnings(
+                &chat_service.fit_to_model(&mut request),
+                args.temperature_given,
+            );
+        }
+
+        match guard_cost(chat_service, config, &request, args.yes, quiet) {
+            Ok(_) => {}
+            Err(e) if e.is::<CostNotConfirmed>() => {
+                let prompt = tr_args("repl.cost_confirm", &[("error", &e)]);
+                let answer = editor.readline(&prompt).unwrap_or_default();
+                if !is_affirmative(&answer) {
+                    history.pop();
+                    println!("{}", tr("repl.cost_declined"));
+                    println!();
+                    continue;
+                }
+            }
+            Err(e) => return Err(e),
+        }
+
+        print!("{}", tr("repl.assistant"));
+        io::stdout().flush()?;
+
+        // Ctrl-C while waiting abandons the request but keeps the session
+        let reply = tokio::select! {
+            result = complete(chat_service, request) => {
+                result.and_then(|response| Ok((response.get_content()?, response.effects)))
+            }
+            _ = tokio::signal::ctrl_c() => Err(XaiError::Cancelled.into()),
+        };
+
+        match reply {
+            Ok((response, effects)) => {
+                print_reply(&response, styles(args, Some(&response)));
+                if !quiet {
+                    print_effects(&effects);
+                }
+                history.push(ApiMessage::assistant(response));
+            }
+            Err(e) if is_cancelled(&e) => {
+                history.pop();
+                println!("{}", tr("repl.cancelled"));
+            }
+            Err(e) => {
+                // Forget the failed turn so the history keeps alternating
+                history.pop();
+                eprintln!(
+                    "{}",
+                    tr_args("cli.error", &[("error", &format!("{:#}", e))])
+                );
+                if let Some(hint) = ExitStatus::of(&e).hint().filter(|_| !quiet) {
+                    eprintln!("{}", tr_args("cli.hint", &[("hint", &hint)]));
+                }
+            }
+        }
+
+        println!();
+    }
+
+    if let Err(e) = save_history(&mut editor) {
+        eprintln!("{}", tr_args("repl.history_not_saved", &[("error", &e)]));
+    }
+
+    Ok(())
+}
+
+/// Runs a hook from the interactive CLI, asking first when it is new or has
+/// changed. Problems are printed; `None` leaves nothing to do.
+async fn run_repl_hook(
+    editor: &mut LineEditor,
+    hooks: &Hooks,
+    hook: &Hook,
+    args: &str,
+    messages: &[ApiMessage],
+) -> Option<HookOutput> {
+    let report = |e: anyhow::Error| {
+        let error = format!("{:#}", e);
+        eprintln!(
+            "{}",
+            tr_args("hooks.error", &[("name", &hook.name), ("error", &error)])
+        );
+    };
+    match hooks.is_trusted(hook) {
+        Ok(true) => {}
+        Ok(false) => {
+            let prompt = tr_args("hooks.confirm", &[("path", &hook.path.display())]);
+            let answer = editor.readline(&prompt).unwrap_or_default();
+            if !is_affirmative(&answer) {
+                println!("{}", tr("hooks.declined"));
+                return None;
+            }
+            if let Err(e) = hooks.trust(hook) {
+                report(e);
+                return None;
+            }
+        }
+        Err(e) => {
+            report(e);
+            return None;
+        }
+    }
+    match hook.run(args, None, messages).await {
+        Ok(output) => {
+            let (HookOutput::Insert(text) | HookOutput::Send(text)) = &output;
+            if text.trim().is_empty() {
+                println!("{}", tr_args("hooks.empty", &[("name", &hook.name)]));
+                return None;
+            }
+            Some(output)
+        }
+        Err(e) => {
+            report(e);
+            None
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+async fn serve(args: ServeArgs) -> Result<()> {
+    // The key is checked by the server's preflight
+    let config = Config::load()?;
+    let host = args
+        .host
+        .unwrap_or_else(|| config.server_host().to_string());
+    let port = args.port.unwrap_or(config.server_port());
+    grok_chat_app::api::run_server(host, port, args.skip_preflight).await
+}
+
+/// Load-tests a running server, or one started in-process against a fake
+/// xAI API with `--mock-upstream`.
+#[cfg(feature = "server")]
+async fn bench(args: BenchArgs, quiet: bool) -> Result<()> {
+    #[cfg(feature = "mock")]
+    let mock = match args.mock_upstream {
+        true => Some(
+            grok_chat_app::bench::MockTarget::start(std::time::Duration::from_millis(
+                args.delay_ms,
+            ))
+            .await?,
+        ),
+        false => None,
+    };
+    #[cfg(feature = "mock")]
+    let mock_url = mock.as_ref().map(|mock| mock.url().to_string());
+    #[cfg(not(feature = "mock"))]
+    let mock_url: Option<String> = match args.mock_upstream {
+        true => return Err(UsageError(tr("cli.mock_needs_feature").to_string()).into()),
+        false => None,
+    };
+
+    let target = match mock_url.or(args.target) {
+        Some(target) => target,
+        None => {
+            let config = Config::load()?;
+            format!("http://{}:{}", config.server_host(), config.server_port())
+        }
+    };
+    let options = BenchOptions {
+        target,
+        concurrency: args.concurrency,
+        requests: args.requests,
+        model: args.model,
+        message: args.message,
+    };
+
+    if !quiet && args.output == OutputFormat::Text {
+        eprintln!(
+            "⏱️  Sending {} messages to {} from {} workers...",
+            options.requests, options.target, options.concurrency
+        );
+    }
+    let report = run_bench(&options).await?;
+
+    match args.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string(&report)?),
+        OutputFormat::Text => print!("{}", report.table()),
+    }
+    Ok(())
+}
+
+/// Prints usage over the period ending today, grouped by day in the
+/// configured timezone. Needs only the database, not an API key.
+#[cfg(feature = "server")]
+async fn run_report(args: ReportArgs) -> Result<()> {
+    let config = Config::load()?;
+    let database = Database::new(&config).await?;
+    let pricing = PricingTable::load(&config)?;
+    let report = usage_report(
+        &database,
+        &pricing,
+        args.period,
+        ReportZone::from_config(&config),
+        chrono::Utc::now(),
+    )
+    .await?;
+
+    match args.format {
+        ReportFormat::Json => println!("{}", serde_json::to_string(&report)?),
+        ReportFormat::Markdown => print!("{}", report.markdown()),
+    }
+    Ok(())
+}
+
+async fn list_models(args: ModelsArgs) -> Result<()> {
+    let config = Config::from_env()?;
+    let models = ChatService::new(&config)?.list_available_models().await?;
+
+    match args.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string(&models)?),
+        OutputFormat::Text => {
+            for model in models {
+                println!("{}", model);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replays an export and prints the comparison. Falling below
+/// `--fail-threshold` is an error, reported after the full report.
+async fn run_replay(args: ReplayArgs, quiet: bool) -> Result<()> {
+    let config = Config::from_env()?;
+    let json = std::fs::read_to_string(&args.file).map_err(|e| {
+        UsageError(tr_args(
+            "cli.read_failed",
+            &[("path", &args.file.display()), ("error", &e)],
+        ))
+    })?;
+    let export: SessionExport = serde_json::from_str(&json).map_err(|e| {
+        UsageError(tr_args(
+            "cli.not_an_export",
+            &[("path", &args.file.display()), ("error", &e)],
+        ))
+    })?;
+
+    let model = args.model.as_deref().unwrap_or(&export.session.model);
+    let options = ReplayOptions {
+        model: config
+            .resolve_model(Some(model))
+            .map_err(|e| UsageError(e.to_string()))?,
+        system_prompt: config.system_prompt().to_string(),
+        max_tokens: args.max_tokens,
+        temperature: args.temperature,
+        fail_threshold: args.fail_threshold,
+        cost_guard: Some(CostGuard::from_config(&config)),
+        confirm_cost: args.yes,
+    };
+    let pricing = PricingTable::load(&config)?;
+
+    if !quiet && args.output == OutputFormat::Text {
+        eprintln!(
+            "🔁 Replaying session {} against {}...",
+            export.session.id, options.model
+        );
+    }
+    let report = replay(&ChatService::new(&config)?, &pricing, &export, &options).await?;
+
+    match args.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string(&report)?),
+        OutputFormat::Text => print_replay_report(&report),
+    }
+
+    match report.regression() {
+        Some(regression) => Err(regression.into()),
+        None => Ok(()),
+    }
+}
+
+fn print_replay_report(report: &ReplayReport) {
+    for turn in &report.turns {
+        println!("── Turn {} ──", turn.turn);
+        println!("Prompt:   {}", turn.prompt);
+        println!(
+            "Original: {}",
+            turn.original.as_deref().unwrap_or("(no reply recorded)")
+        );
+        println!("Replayed: {}", turn.replayed);
+        let similarity = turn
+            .similarity
+            .map_or("n/a".to_string(), |score| format!("{:.2}", score));
+        println!(
+            "Similarity {} · tokens {} → {} ({:+}) · cost {} → {}",
+            similarity,
+            turn.original_tokens,
+            turn.replayed_tokens,
+            turn.token_delta,
+            turn.original_cost,
+            turn.replayed_cost
+        );
+        println!();
+    }
+
+    println!(
+        "{} → {}: {} turns, mean similarity {:.2}, min {:.2}, tokens {:+}",
+        report.original_model,
+        report.model,
+        report.turns.len(),
+        report.mean_similarity,
+        report.min_similarity,
+        report.token_delta
+    );
+    if let Some(delta) = report.cost_delta_usd {
+        println!("Cost delta: {:+.4} USD", delta);
+    }
+}
+
+/// Plays a script into a new session, printing each reply as it arrives.
+/// Missed expectations are an error, reported after the summary.
+#[cfg(feature = "server")]
+async fn run_script_file(args: RunArgs, quiet: bool) -> Result<()> {
+    let config = Config::from_env()?;
+    let yaml = std::fs::read_to_string(&args.script).map_err(|e| {
+        UsageError(tr_args(
+            "cli.read_failed",
+            &[("path", &args.script.display()), ("error", &e)],
+        ))
+    })?;
+    let vars: HashMap<String, String> = args.vars.into_iter().collect();
+    let mut script = Script::from_yaml(&yaml)
+        .and_then(|script| script.interpolate(&vars, |name| std::env::var(name).ok()))
+        .map_err(|e| {
+            UsageError(tr_args(
+                "cli.not_a_script",
+                &[("path", &args.script.display()), ("error", &e)],
+            ))
+        })?;
+    if script.title.is_none() {
+        script.title = args
+            .script
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned());
+    }
+
+    let text = args.output == OutputFormat::Text;
+    let model = args.model.as_deref().or(script.model.as_deref());
+    let options = ScriptOptions {
+        model: config
+            .resolve_model(model)
+            .map_err(|e| UsageError(e.to_string()))?,
+        system_prompt: script
+            .system_prompt
+            .clone()
+            .unwrap_or_else(|| config.system_prompt().to_string()),
+        max_tokens: script.max_tokens,
+        temperature: script.temperature,
+        stream: text && !args.no_stream && io::stdout().is_terminal(),
+    };
+    let database = Database::new(&config)
+        .await
+        .context("Failed to open the session database")?;
+
+    if !quiet && text {
+        eprintln!(
+            "▶️  Running {} ({} turns) against {}...",
+            args.script.display(),
+            script.turns.len(),
+            options.model
+        );
+    }
+    let mut stdout = io::stdout();
+    let mut sanitizer = stdout.is_terminal().then(Sanitizer::new);
+    let report = run_script(
+        &ChatService::new(&config)?,
+        &database,
+        &script,
+        &options,
+        |event| {
+            if !text {
+                return;
+            }
+            match event {
+                ScriptEvent::Turn { turn, prompt } => {
+                    println!("── Turn {} ──", turn);
+                    println!("> {}", prompt);
+                }
+                ScriptEvent::Delta(delta) => {
+                    match sanitizer.as_mut() {
+                        Some(sanitizer) => print!("{}", sanitizer.push(delta)),
+                        None => print!("{}", delta),
+                    }
+                    let _ = stdout.flush();
+                }
+                ScriptEvent::Checked(_) => {
+                    let rest = sanitizer
+                        .as_mut()
+                        .map(Sanitizer::finish)
+                        .unwrap_or_default();
+                    println!("{}", rest);
+                    println!();
+                }
+            }
+        },
+    )
+    .await?;
 
-            print!("Grok: ");
-            io::stdout().flush()?;
+    match args.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string(&report)?),
+        OutputFormat::Text => print_script_summary(&report),
+    }
 
-            match send_message(
-                &args.model,
-                &args.system,
-                input,
-                args.max_tokens,
-                args.temperature,
+    match report.failure() {
+        Some(failure) => Err(failure.into()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(feature = "server")]
+fn print_script_summary(report: &ScriptReport) {
+    for turn in &report.turns {
+        if turn.passed {
+            println!("✅ Turn {}", turn.turn);
+        } else {
+            let missing: Vec<String> = turn
+                .missing
+                .iter()
+                .map(|expected| format!("{:?}", expected))
+                .collect();
+            println!("❌ Turn {}: missing {}", turn.turn, missing.join(", "));
+        }
+    }
+    let passed = report.turns.iter().filter(|turn| turn.passed).count();
+    println!(
+        "{}/{} turns passed · session {}",
+        passed,
+        report.turns.len(),
+        report.session_id
+    );
+}
+
+/// Checks each dependency in turn, exiting non-zero if any of them fails.
+async fn run_check() -> Result<()> {
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}", tr_args("check.config_failed", &[("error", &e)]));
+            return Err(e.context("Health check failed"));
+        }
+    };
+    println!(
+        "{}",
+        tr_args("check.config_loaded", &[("url", &config.xai_base_url())])
+    );
+
+    // The first failure decides the exit code
+    let mut failure = None;
+
+    match ChatService::new(&config)?.list_available_models().await {
+        Ok(models) => println!("{}", tr_args("check.api_ok", &[("count", &models.len())])),
+        Err(e) => {
+            println!("{}", tr_args("check.api_failed", &[("error", &e)]));
+            failure.get_or_insert(e);
+        }
+    }
+
+    match PricingTable::load(&config) {
+        Ok(_) => println!("{}", tr("check.pricing_ok")),
+        Err(e) => {
+            println!(
+                "{}",
+                tr_args("check.pricing_failed", &[("error", &format!("{:#}", e))])
+            );
+            failure.get_or_insert(e);
+        }
+    }
+
+    #[cfg(feature = "server")]
+    match Database::new(&config).await {
+        Ok(database) => println!(
+            "{}",
+            tr_args(
+                "check.database_ok",
+                &[
+                    ("url", &config.database_url()),
+                    ("count", &database.get_total_sessions().await?),
+                ],
             )
-            .await
+        ),
+        Err(e) => {
+            println!(
+                "{}",
+                tr_args(
+                    "check.database_failed",
+                    &[("url", &config.database_url()), ("error", &e)],
+                )
+            );
+            failure.get_or_insert(e);
+        }
+    }
+
+    match failure {
+        Some(e) => Err(e.context("Health check failed")),
+        None => Ok(()),
+    }
+}
+
+fn run_config(command: ConfigCommand) -> Result<()> {
+    let path = config_file_path()
+        .ok_or_else(|| anyhow!("No config directory on this platform; set GROK_CONFIG"))?;
+
+    match command {
+        ConfigCommand::Init { force } => {
+            if path.exists() && !force {
+                return Err(
+                    UsageError(tr_args("cli.file_exists", &[("path", &path.display())])).into(),
+                );
+            }
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create {}", dir.display()))?;
+            }
+            std::fs::write(&path, CONFIG_TEMPLATE)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            println!("📝 Wrote {}", path.display());
+        }
+        ConfigCommand::Show => {
+            let config = Config::load()?;
+            let found = if path.exists() { "" } else { " (not found)" };
+            println!("# Config file: {}{}", path.display(), found);
+            println!(
+                "# Profile: {}",
+                selected_profile().as_deref().unwrap_or("default")
+            );
+            for (key, sources) in config.secret_sources() {
+                let sources: Vec<String> = sources.iter().map(ToString::to_string).collect();
+                println!("# {} from {}", key, sources.join(", "));
+            }
+            // Values read from the keyring or a file stay out of the output
+            let shown = |key: &str, value: &str| match config.is_secret(key) {
+                true => "\"(secret)\"".to_string(),
+                false => format!("{:?}", value),
+            };
+            println!("api_key = {:?}", config.redacted_api_key());
+            println!("base_url = {}", shown("base_url", config.xai_base_url()));
+            println!(
+                "default_model = {}",
+                shown("default_model", config.default_model())
+            );
+            println!(
+                "system_prompt = {}",
+                shown("system_prompt", config.system_prompt())
+            );
+            println!("max_tokens = {}", config.max_tokens());
+            println!("temperature = {}", config.temperature());
+            println!(
+                "database_url = {}",
+                shown("database_url", config.database_url())
+            );
+            println!(
+                "server_host = {}",
+                shown("server_host", config.server_host())
+            );
+            println!("server_port = {}", config.server_port());
+            println!(
+                "request_timeout_secs = {}",
+                config.request_timeout().as_secs()
+            );
+            println!(
+                "stream_resume_attempts = {}",
+                config.stream_resume_attempts()
+            );
+            println!("stream_max_secs = {}", config.stream_max().as_secs());
+            println!("kept_generations = {}", config.kept_generations());
+            println!("auto_migrate_models = {}", config.auto_migrate_models());
+            println!("cost_threshold_usd = {}", config.cost_threshold_usd());
+            println!("cost_guard = {:?}", config.cost_guard().to_string());
+            println!("context_retry = {:?}", config.context_retry().to_string());
+            println!("redact = {}", config.redact());
+            println!("redact_stored = {}", config.redact_stored());
+            println!("post_processors = {:?}", config.post_processors());
+            if let Some(path) = config.extract_code_to() {
+                let path = path.display().to_string();
+                println!("extract_code_to = {}", shown("extract_code_to", &path));
+            }
+            if let Some(timezone) = config.timezone() {
+                println!("timezone = {:?}", timezone.name());
+            }
+            let media_dir = config.media_dir().display().to_string();
+            println!("media_dir = {}", shown("media_dir", &media_dir));
+            let breaker = config.circuit_breaker();
+            println!();
+            println!("[circuit_breaker]");
+            println!("failures = {}", breaker.failures);
+            println!("error_rate = {}", breaker.error_rate);
+            println!("min_requests = {}", breaker.min_requests);
+            println!("window_secs = {}", breaker.window_secs);
+            println!("open_secs = {}", breaker.open_secs);
+            if let Some(model) = &breaker.fallback_model {
+                println!("fallback_model = {:?}", model);
+            }
+            if !config.model_aliases().is_empty() {
+                println!();
+                println!("[model_aliases]");
+                for (alias, model) in config.model_aliases() {
+                    let key = format!("model_aliases.{}", alias);
+                    println!("{} = {}", alias, shown(&key, model));
+                }
+            }
+            if !config.model_migrations().is_empty() {
+                println!();
+                println!("[model_migrations]");
+                for (retired, model) in config.model_migrations() {
+                    let key = format!("model_migrations.{}", retired);
+                    println!("{} = {}", retired, shown(&key, model));
+                }
+            }
+            if !config.redact_patterns().is_empty() {
+                println!();
+                println!("[redact_patterns]");
+                for (kind, pattern) in config.redact_patterns() {
+                    println!("{} = {:?}", kind, pattern);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_auth(command: AuthCommand) -> Result<()> {
+    let profile = selected_profile().unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+
+    match command {
+        AuthCommand::Login => {
+            let key = read_api_key()?;
+
+            // Validate against the configured base URL, with the new key
+            let config = Config {
+                xai_api_key: key.clone(),
+                ..Config::load()?
+            };
+            if !ChatService::new(&config)?
+                .client()
+                .validate_api_key()
+                .await?
             {
-                Ok(response) => {
-                    println!("{}", response);
+                return Err(InvalidApiKey.into());
+            }
+
+            store_api_key(&profile, &key).context(
+                "The key is valid but could not be stored; keep using XAI_API_KEY \
+                 or api_key in config.toml",
+            )?;
+            println!("{}", tr_args("auth.stored", &[("profile", &profile)]));
+        }
+        AuthCommand::Logout => {
+            if delete_api_key(&profile)? {
+                println!("{}", tr_args("auth.removed", &[("profile", &profile)]));
+            } else {
+                println!("{}", tr_args("auth.none_stored", &[("profile", &profile)]));
+            }
+        }
+        AuthCommand::Status => {
+            let config = Config::load()?;
+            if config.api_key_source() == ApiKeySource::Missing {
+                return Err(MissingApiKey.into());
+            }
+            println!(
+                "{}",
+                tr_args(
+                    "auth.status",
+                    &[("source", &config.api_key_source()), ("profile", &profile)],
+                )
+            );
+
+            if !keyring_enabled() {
+                println!(
+                    "{}",
+                    tr_args("auth.keyring_disabled", &[("var", &NO_KEYRING_VAR)])
+                );
+            } else {
+                match stored_api_key(&profile) {
+                    Ok(Some(_)) => println!("{}", tr("auth.keyring_has_key")),
+                    Ok(None) => println!("{}", tr("auth.keyring_no_key")),
+                    Err(e) => eprintln!("⚠️  {:#}", e),
                 }
-                Err(e) => {
-                    eprintln!("❌ Error: {}", e);
-                    eprintln!("💡 Make sure your XAI_API_KEY is set correctly in the .env file");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Prompts for the key without echo on a terminal, or reads one line from
+/// piped stdin.
+fn read_api_key() -> Result<String> {
+    let key = if io::stdin().is_terminal() {
+        rpassword::prompt_password(tr("prompt.api_key"))?
+    } else {
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        line
+    };
+
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(UsageError(tr("cli.no_api_key").to_string()).into());
+    }
+    Ok(key.to_string())
+}
+
+/// Prints matches as they are found, one message at a time.
+#[cfg(feature = "server")]
+async fn grep_sessions(database: &Database, args: GrepArgs) -> Result<()> {
+    let pattern = Pattern::new(&args.pattern, args.ignore_case)
+        .map_err(|e| UsageError(format!("{:#}", e)))?;
+    if let Some(id) = &args.session {
+        if database.get_session(id).await?.is_none() {
+            bail!(tr_args("cli.session_not_found", &[("id", &id)]));
+        }
+    }
+    let json = args.output == OutputFormat::Json;
+    let terminal = io::stdout().is_terminal();
+    let style = TextStyle {
+        sanitize: terminal,
+        color: terminal && !no_color(),
+        separators: args.context > 0,
+    };
+
+    let filter = MessageFilter {
+        session_id: args.session,
+        role: args.role,
+        containing: pattern.literal().map(str::to_string),
+    };
+    let mut messages = database.scan_messages(filter);
+    let mut out = io::BufWriter::new(io::stdout());
+    // Messages arrive grouped by session; the title is looked up on its first match
+    let mut current: Option<(String, Option<String>)> = None;
+    let mut found = false;
+    let mut first_in_session = true;
+    while let Some(message) = messages.next().await {
+        let message = message?;
+        let title = match &current {
+            Some((id, title)) if *id == message.session_id => title.clone(),
+            _ => {
+                let title = database
+                    .get_session(&message.session_id)
+                    .await?
+                    .and_then(|session| session.title);
+                current = Some((message.session_id.clone(), title.clone()));
+                first_in_session = true;
+                title
+            }
+        };
+        let matches = pattern.matches(&message, title.as_deref(), args.context);
+        if matches.is_empty() {
+            continue;
+        }
+
+        if json {
+            for found in &matches {
+                writeln!(out, "{}", serde_json::to_string(found)?)?;
+            }
+        } else {
+            if first_in_session {
+                if found {
+                    writeln!(out)?;
                 }
+                write!(
+                    out,
+                    "{}",
+                    style.session_header(&message.session_id, title.as_deref())
+                )?;
+            } else if style.separators {
+                writeln!(out, "--")?;
             }
+            write!(out, "{}", style.format_message(&matches))?;
+        }
+        first_in_session = false;
+        found = true;
+    }
+    out.flush()?;
 
-            println!();
+    if !found && !json {
+        eprintln!("{}", tr("cli.no_matches"));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+async fn run_sessions(command: SessionsCommand) -> Result<()> {
+    let config = Config::from_env()?;
+    let database = Database::new(&config).await?;
+
+    match command {
+        SessionsCommand::List { limit, output } => {
+            let mut summaries = Vec::new();
+            for session in database.list_sessions(Some(limit), None).await? {
+                let message_count = database.get_session_message_count(&session.id).await?;
+                summaries.push(SessionSummary {
+                    session,
+                    message_count,
+                });
+            }
+
+            if output == OutputFormat::Json {
+                println!("{}", serde_json::to_string(&summaries)?);
+                return Ok(());
+            }
+
+            if summaries.is_empty() {
+                println!("{}", tr("sessions.none"));
+            }
+            for summary in summaries {
+                println!(
+                    "{}  {}  {:>4} {}  {:<16}  {}",
+                    summary.session.id,
+                    summary.session.updated_at.format("%Y-%m-%d %H:%M"),
+                    summary.message_count,
+                    tr("sessions.msgs"),
+                    summary.session.model,
+                    summary
+                        .session
+                        .title
+                        .as_deref()
+                        .unwrap_or(tr("sessions.untitled"))
+                );
+            }
+        }
+        SessionsCommand::Show { id } => {
+            let markdown = find_export(&database, &id).await?.to_markdown();
+            if io::stdout().is_terminal() {
+                print!("{}", sanitize(&markdown));
+            } else {
+                print!("{}", markdown);
+            }
+        }
+        SessionsCommand::Delete { id } => {
+            if database.get_session(&id).await?.is_none() {
+                bail!(tr_args("cli.session_not_found", &[("id", &id)]));
+            }
+            database.delete_session_audited(&id, &Actor::cli()).await?;
+            println!("{}", tr_args("sessions.deleted", &[("id", &id)]));
+        }
+        SessionsCommand::Export {
+            id,
+            format,
+            output,
+            include_seeded,
+        } => {
+            let mut export = find_export(&database, &id).await?;
+            if !include_seeded {
+                export = export.without_seeded();
+            }
+            let text = match format {
+                ExportFormat::Json => serde_json::to_string_pretty(&export)?,
+                ExportFormat::Markdown => export.to_markdown(),
+            };
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, text)
+                        .with_context(|| format!("Failed to write {}", path.display()))?;
+                    eprintln!(
+                        "{}",
+                        tr_args("cli.exported", &[("id", &id), ("path", &path.display())])
+                    );
+                }
+                None => println!("{}", text),
+            }
+        }
+        SessionsCommand::Import { file, format } => {
+            let report = if format == ImportFormat::Archive {
+                let parsed = read_archive(&file)
+                    .map_err(|e| UsageError(format!("{}: {:#}", file.display(), e)))?;
+                import_archive(&database, parsed).await?
+            } else {
+                let json = std::fs::read_to_string(&file)
+                    .with_context(|| format!("Failed to read {}", file.display()))?;
+                let parsed = parse_export(format, &json)
+                    .map_err(|e| UsageError(format!("{}: {:#}", file.display(), e)))?;
+                import_sessions(&database, parsed).await?
+            };
+            println!(
+                "{}",
+                tr_args(
+                    "sessions.imported",
+                    &[
+                        ("sessions", &report.sessions_imported),
+                        ("messages", &report.messages_imported),
+                        ("path", &file.display()),
+                    ],
+                )
+            );
+            if report.duplicates > 0 {
+                println!(
+                    "{}",
+                    tr_args(
+                        "sessions.skipped_duplicates",
+                        &[("count", &report.duplicates)]
+                    )
+                );
+            }
+            if report.malformed > 0 {
+                println!(
+                    "{}",
+                    tr_args(
+                        "sessions.skipped_malformed",
+                        &[("count", &report.malformed)]
+                    )
+                );
+            }
+        }
+        SessionsCommand::Flush => {
+            let outbox = Outbox::new(database.clone(), ChatService::new(&config)?);
+            if outbox.queued_count().await? == 0 {
+                println!("{}", tr("sessions.nothing_queued"));
+                return Ok(());
+            }
+            let report = outbox.flush().await?;
+            println!(
+                "{}",
+                tr_args(
+                    "sessions.flushed",
+                    &[("sent", &report.sent), ("remaining", &report.remaining)],
+                )
+            );
+            for failure in &report.failed {
+                println!(
+                    "{}",
+                    tr_args(
+                        "sessions.flush_failed",
+                        &[("id", &failure.session_id), ("error", &failure.error)],
+                    )
+                );
+            }
+            if !report.failed.is_empty() {
+                bail!(tr_args(
+                    "sessions.not_flushed",
+                    &[("count", &report.failed.len())]
+                ));
+            }
+        }
+        SessionsCommand::Grep(args) => grep_sessions(&database, args).await?,
+        SessionsCommand::Dedupe {
+            apply,
+            similarity,
+            output,
+        } => {
+            let report = dedupe_sessions(&database, similarity, apply, &Actor::cli()).await?;
+            match output {
+                OutputFormat::Json => println!("{}", serde_json::to_string(&report)?),
+                OutputFormat::Text => print_dedupe_report(&report),
+            }
+        }
+        SessionsCommand::MigrateModel { from, to } => {
+            let to = config.resolve_model(Some(&to))?;
+            let migrated = database
+                .migrate_model(&from, &to, None, &Actor::cli())
+                .await?;
+            println!(
+                "{}",
+                tr_args(
+                    "sessions.migrated",
+                    &[("count", &migrated.len()), ("from", &from), ("to", &to)],
+                )
+            );
+        }
+        SessionsCommand::ExportAll { path, since } => {
+            let index = export_archive(&database, since, ArchiveWriter::create(&path)?).await?;
+            println!(
+                "{}",
+                tr_args(
+                    "sessions.archived",
+                    &[("count", &index.sessions.len()), ("path", &path.display())],
+                )
+            );
+            for skipped in &index.skipped {
+                println!(
+                    "{}",
+                    tr_args(
+                        "sessions.archive_skipped",
+                        &[("id", &skipped.id), ("error", &skipped.error)],
+                    )
+                );
+            }
+        }
+        SessionsCommand::ExportFinetune {
+            tag,
+            rating,
+            since,
+            until,
+            per_turn,
+            out,
+        } => {
+            let filter = FinetuneFilter {
+                tag,
+                rating,
+                since,
+                until,
+                per_turn,
+            };
+            let redactor = Redactor::from_config(&config)?;
+            let mut export = FinetuneExport::open(database.clone(), filter, redactor).await?;
+            let mut writer: Box<dyn Write + Send> = match &out {
+                Some(path) => Box::new(io::BufWriter::new(
+                    std::fs::File::create(path)
+                        .with_context(|| format!("Failed to write {}", path.display()))?,
+                )),
+                None => Box::new(io::stdout()),
+            };
+            while let Some(lines) = export.next().await? {
+                writer.write_all(lines.as_bytes())?;
+            }
+            writer.flush()?;
+
+            // On stderr, so stdout stays a clean dataset
+            let report = export.report();
+            let path = out.map(|path| path.display().to_string());
+            eprintln!(
+                "{}",
+                tr_args(
+                    if path.is_some() {
+                        "sessions.finetune_written"
+                    } else {
+                        "sessions.finetune"
+                    },
+                    &[
+                        ("examples", &report.examples),
+                        ("sessions", &report.sessions_included),
+                        ("tokens", &report.tokens),
+                        ("path", &path.unwrap_or_default()),
+                    ],
+                )
+            );
+            if report.sessions_skipped > 0 {
+                eprintln!(
+                    "{}",
+                    tr_args(
+                        "sessions.finetune_skipped",
+                        &[("count", &report.sessions_skipped)],
+                    )
+                );
+            }
+            if report.redactions > 0 {
+                eprintln!(
+                    "{}",
+                    tr_args("cli.finetune_redacted", &[("count", &report.redactions)])
+                );
+            }
         }
     }
 
     Ok(())
 }
 
-async fn send_message(
-    model: &str,
-    system_prompt: &str,
-    message: &str,
-    max_tokens: i32,
-    temperature: f32,
-) -> Result<String> {
-    use tokio::time::{timeout, Duration};
+#[cfg(feature = "server")]
+fn print_dedupe_report(report: &DedupeReport) {
+    if report.clusters.is_empty() {
+        println!("{}", tr("sessions.no_duplicates"));
+        return;
+    }
+    for cluster in &report.clusters {
+        let keep = &cluster.keep;
+        println!(
+            "{}",
+            tr_args(
+                "sessions.dedupe_keep",
+                &[
+                    ("id", &keep.id),
+                    ("date", &keep.created_at.format("%Y-%m-%d")),
+                    (
+                        "title",
+                        &keep.title.as_deref().unwrap_or(tr("sessions.untitled"))
+                    ),
+                    ("count", &keep.messages),
+                ],
+            )
+        );
+        for duplicate in &cluster.duplicates {
+            let session = &duplicate.session;
+            let likeness = if duplicate.exact {
+                tr("sessions.same_prompts").to_string()
+            } else {
+                tr_args(
+                    "sessions.alike",
+                    &[("percent", &format!("{:.0}", duplicate.similarity * 100.0))],
+                )
+            };
+            println!(
+                "{}",
+                tr_args(
+                    "sessions.dedupe_duplicate",
+                    &[
+                        ("id", &session.id),
+                        ("date", &session.created_at.format("%Y-%m-%d")),
+                        (
+                            "title",
+                            &session.title.as_deref().unwrap_or(tr("sessions.untitled")),
+                        ),
+                        ("count", &session.messages),
+                        ("likeness", &likeness),
+                    ],
+                )
+            );
+        }
+    }
+    let duplicates: usize = report.clusters.iter().map(|c| c.duplicates.len()).sum();
+    if report.applied {
+        println!(
+            "{}",
+            tr_args(
+                "sessions.merged",
+                &[
+                    ("count", &report.merged_sessions),
+                    ("groups", &report.clusters.len()),
+                    ("moved", &report.moved_messages),
+                ],
+            )
+        );
+    } else {
+        println!(
+            "{}",
+            tr_args(
+                "sessions.duplicates_found",
+                &[("count", &duplicates), ("groups", &report.clusters.len())],
+            )
+        );
+    }
+}
 
-    let api_key = std::env::var("XAI_API_KEY")
-        .map_err(|_| anyhow::anyhow!("❌ XAI_API_KEY environment variable is required"))?;
+#[cfg(feature = "server")]
+async fn run_prompts(command: PromptsCommand) -> Result<()> {
+    let config = Config::load()?;
+    let database = Database::new(&config).await?;
 
-    let client = reqwest::Client::new();
+    match command {
+        PromptsCommand::Add {
+            name,
+            body,
+            default,
+        } => {
+            let body = match body {
+                Some(body) if body != "-" => body,
+                _ => {
+                    let mut body = String::new();
+                    io::stdin()
+                        .read_to_string(&mut body)
+                        .context("Failed to read the prompt from stdin")?;
+                    body.trim_end().to_string()
+                }
+            };
+            if body.trim().is_empty() {
+                return Err(UsageError(tr("cli.empty_preset").to_string()).into());
+            }
 
-    let request_body = serde_json::json!({
-        "messages": [
-            {
-                "role": "system",
-                "content": system_prompt
-            },
-            {
-                "role": "user",
-                "content": message
-            }
-        ],
-        "model": model,
-        "max_tokens": max_tokens,
-        "temperature": temperature,
-        "stream": false
+            let mut preset = PromptPreset::new(name, body);
+            preset.is_default = default;
+            let replaced = database.update_prompt(&preset.name, &preset).await?;
+            if !replaced {
+                database.create_prompt(&preset).await?;
+            }
+            let key = match (replaced, default) {
+                (false, false) => "prompts.saved",
+                (false, true) => "prompts.saved_default",
+                (true, false) => "prompts.updated",
+                (true, true) => "prompts.updated_default",
+            };
+            println!("{}", tr_args(key, &[("name", &preset.name)]));
+        }
+        PromptsCommand::List { output } => {
+            let presets = database.list_prompts().await?;
+            if output == OutputFormat::Json {
+                println!("{}", serde_json::to_string(&presets)?);
+                return Ok(());
+            }
+
+            if presets.is_empty() {
+                println!("{}", tr("prompts.none"));
+            }
+            for preset in presets {
+                let first_line = preset.body.lines().next().unwrap_or_default();
+                println!(
+                    "{:<16} {} {}",
+                    preset.name,
+                    if preset.is_default { "*" } else { " " },
+                    first_line
+                );
+            }
+        }
+        PromptsCommand::Rm { name } => {
+            // An unknown name fails listing the saved presets
+            database.resolve_prompt(&name).await?;
+            let Some(reset) = database.delete_prompt(&name).await? else {
+                bail!(tr_args("prompts.not_found", &[("name", &name)]));
+            };
+            println!("{}", tr_args("prompts.deleted", &[("name", &name)]));
+            if reset > 0 {
+                eprintln!(
+                    "{}",
+                    tr_args(
+                        "prompts.sessions_reset",
+                        &[("count", &reset), ("name", &name)]
+                    )
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+async fn run_session_templates(command: SessionTemplatesCommand) -> Result<()> {
+    let config = Config::load()?;
+    let database = Database::new(&config).await?;
+
+    match command {
+        SessionTemplatesCommand::Add { file } => {
+            let json = match file {
+                Some(path) if path.as_os_str() != "-" => std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?,
+                _ => {
+                    let mut json = String::new();
+                    io::stdin()
+                        .read_to_string(&mut json)
+                        .context("Failed to read the template from stdin")?;
+                    json
+                }
+            };
+            let template: SessionTemplate = serde_json::from_str(&json)
+                .map_err(|e| UsageError(tr_args("templates.invalid", &[("error", &e)])))?;
+            template.validate().map_err(|e| UsageError(e.to_string()))?;
+
+            let replaced = database
+                .update_session_template(&template.name, &template)
+                .await?;
+            if !replaced {
+                database.create_session_template(&template).await?;
+            }
+            println!(
+                "{}",
+                tr_args(
+                    if replaced {
+                        "templates.updated"
+                    } else {
+                        "templates.saved"
+                    },
+                    &[
+                        ("name", &template.name),
+                        ("count", &template.messages.len()),
+                    ],
+                )
+            );
+        }
+        SessionTemplatesCommand::List { output } => {
+            let templates = database.list_session_templates().await?;
+            if output == OutputFormat::Json {
+                println!("{}", serde_json::to_string(&templates)?);
+                return Ok(());
+            }
+
+            if templates.is_empty() {
+                println!("{}", tr("templates.none"));
+            }
+            for template in templates {
+                println!(
+                    "{:<16} {:>2} {}  {}",
+                    template.name,
+                    template.messages.len(),
+                    tr("sessions.msgs"),
+                    template.description.as_deref().unwrap_or_default()
+                );
+            }
+        }
+        SessionTemplatesCommand::Show { name } => {
+            let template = database.resolve_session_template(&name).await?;
+            println!("{}", serde_json::to_string_pretty(&template)?);
+        }
+        SessionTemplatesCommand::Rm { name } => {
+            if !database.delete_session_template(&name).await? {
+                bail!(tr_args("templates.not_found", &[("name", &name)]));
+            }
+            println!("{}", tr_args("templates.deleted", &[("name", &name)]));
+        }
+    }
+
+    Ok(())
+}
+
+/// The preset named by `--prompt-preset`.
+#[cfg(feature = "server")]
+async fn find_preset(config: &Config, name: &str) -> Result<PromptPreset> {
+    let database = Database::new(config)
+        .await
+        .context("Failed to open the session database")?;
+    database.resolve_prompt(name).await
+}
+
+#[cfg(not(feature = "server"))]
+async fn find_preset(_config: &Config, _name: &str) -> Result<PromptPreset> {
+    Err(UsageError(tr("cli.preset_needs_server").to_string()).into())
+}
+
+/// The agent to answer with: `--agent`, else the default agent of the
+/// session being resumed.
+#[cfg(feature = "server")]
+async fn session_agent(config: &Config, args: &ChatArgs) -> Result<Option<String>> {
+    if args.agent.is_some() || !(args.session.is_some() || args.continue_session) {
+        return Ok(args.agent.clone());
+    }
+    let database = Database::new(config)
+        .await
+        .context("Failed to open the session database")?;
+    let session = match &args.session {
+        Some(id) => database.get_session(id).await?,
+        None => database.list_sessions(Some(1), None).await?.pop(),
+    };
+    Ok(session.and_then(|session| session.agent))
+}
+
+#[cfg(not(feature = "server"))]
+async fn session_agent(_config: &Config, args: &ChatArgs) -> Result<Option<String>> {
+    Ok(args.agent.clone())
+}
+
+#[cfg(feature = "server")]
+async fn find_export(database: &Database, id: &str) -> Result<SessionExport> {
+    database
+        .export_session(id)
+        .await?
+        .ok_or_else(|| anyhow!(tr_args("cli.session_not_found", &[("id", &id)])))
+}
+
+#[cfg(feature = "server")]
+async fn run_maintenance(args: MaintenanceArgs) -> Result<()> {
+    let config = Config::from_env()?;
+    let database = Database::new(&config).await?;
+    let actor = Actor::cli();
+
+    if let Some(days) = args.prune_days {
+        let cutoff = chrono::Utc::now() - chrono::Duration::days(i64::from(days));
+        let stale = database.sessions_updated_before(cutoff).await?;
+
+        for session in &stale {
+            println!(
+                "  {}  {}  {}",
+                session.id,
+                session.updated_at.format("%Y-%m-%d"),
+                session.title.as_deref().unwrap_or(tr("sessions.untitled"))
+            );
+            if !args.dry_run {
+                database.delete_session_audited(&session.id, &actor).await?;
+            }
+        }
+        if !args.dry_run {
+            let record = AuditRecord::new(AuditOp::MaintenancePrune).detail(format!(
+                "{} session(s) not updated in {} days",
+                stale.len(),
+                days
+            ));
+            database.record_audit(&actor, &record).await?;
+        }
+        println!(
+            "{}",
+            tr_args(
+                if args.dry_run {
+                    "maintenance.would_prune"
+                } else {
+                    "maintenance.pruned"
+                },
+                &[("count", &stale.len()), ("days", &days)],
+            )
+        );
+    }
+
+    // After pruning, so the images of pruned sessions go too
+    let store = MediaStore::new(config.media_dir());
+    let garbage = store
+        .collect_garbage(&database.media_hashes().await?, args.dry_run)
+        .await?;
+    if garbage.files > 0 {
+        println!(
+            "{}",
+            tr_args(
+                if args.dry_run {
+                    "maintenance.would_remove_media"
+                } else {
+                    "maintenance.removed_media"
+                },
+                &[
+                    ("count", &garbage.files),
+                    ("kib", &garbage.bytes.div_ceil(1024))
+                ],
+            )
+        );
+        if !args.dry_run {
+            let record = AuditRecord::new(AuditOp::MaintenanceMedia).detail(format!(
+                "{} file(s), {} bytes",
+                garbage.files, garbage.bytes
+            ));
+            database.record_audit(&actor, &record).await?;
+        }
+    }
+
+    if !args.dry_run {
+        database.vacuum().await?;
+        database
+            .record_audit(&actor, &AuditRecord::new(AuditOp::MaintenanceVacuum))
+            .await?;
+        println!("{}", tr("maintenance.compacted"));
+    }
+
+    Ok(())
+}
+
+/// Builds the single-message prompt from `--message`, `--file` and piped stdin.
+/// Returns `None` when there is nothing to send, selecting interactive mode.
+fn read_prompt(args: &ChatArgs) -> Result<Option<ComposedPrompt>> {
+    let stdin = if io::stdin().is_terminal() {
+        None
+    } else {
+        let mut piped = String::new();
+        io::stdin().read_to_string(&mut piped)?;
+        Some(piped).filter(|piped| !piped.trim().is_empty())
+    };
+
+    let mut attachments = Vec::new();
+    let message = match args.message.as_deref() {
+        Some("-") => stdin.ok_or_else(|| UsageError(tr("cli.stdin_needs_prompt").to_string()))?,
+        Some(message) => {
+            if let Some(piped) = stdin {
+                attachments.push(Attachment::new("stdin", piped));
+            }
+            message.to_string()
+        }
+        None => match stdin {
+            Some(piped) => piped,
+            None if args.files.is_empty() => return Ok(None),
+            None => return Err(UsageError(tr("cli.file_needs_prompt").to_string()).into()),
+        },
+    };
+
+    for path in &args.files {
+        attachments.push(Attachment::from_file(path)?);
+    }
+
+    Ok(Some(compose_prompt(
+        &message,
+        &attachments,
+        MAX_INPUT_BYTES,
+    )))
+}
+
+async fn complete(chat_service: &ChatService, request: ApiChatRequest) -> Result<ApiChatResponse> {
+    match chat_service.send_request(request, None).await? {
+        ChatResponse::Complete(response) => Ok(response),
+        ChatResponse::Stream(_) => Err(anyhow::anyhow!("Unexpected streaming response")),
+    }
+}
+
+/// Whether replies should be styled as markdown. While streaming the reply is
+/// not known yet (`None`) and lines are styled as they complete.
+fn styles(args: &ChatArgs, reply: Option<&str>) -> bool {
+    args.render.styles(
+        io::stdout().is_terminal(),
+        no_color(),
+        reply.is_none_or(looks_like_markdown),
+    )
+}
+
+/// Prints a reply; on a terminal its control characters are made visible
+/// first, while piped output stays byte-for-byte.
+fn print_reply(reply: &str, styled: bool) {
+    let reply = if io::stdout().is_terminal() {
+        sanitize(reply)
+    } else {
+        reply.to_string()
+    };
+    if styled {
+        println!("{}", render_markdown(&reply));
+    } else {
+        println!("{}", reply);
+    }
+}
+
+/// A reply as `send_single` received it.
+struct Sent {
+    /// The reply as it is stored, after post-processing.
+    reply: String,
+    usage: Option<UsageStats>,
+    finish_reason: Option<String>,
+    /// The complete response, when the reply was not streamed.
+    response: Option<ApiChatResponse>,
+    /// How many times a streamed reply was resumed.
+    resumes: Option<u32>,
+    /// Side effects of post-processing, such as files written.
+    effects: Vec<String>,
+    /// Whether Ctrl-C stopped the reply, leaving it partial or empty.
+    cancelled: bool,
+}
+
+/// Prints deltas as they arrive, flushing after each one. When `styled`, text
+/// is printed one rendered line at a time instead. Ctrl-C cancels the request
+/// but keeps whatever was already printed and is not treated as an error.
+/// Post-processors change the stored reply, not what was printed.
+async fn stream_reply(
+    chat_service: &ChatService,
+    request: ApiChatRequest,
+    styled: bool,
+) -> Result<Sent> {
+    let cancel = CancellationToken::new();
+    let on_interrupt = cancel.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            on_interrupt.cancel();
+        }
     });
 
-    // Add timeout to prevent hanging
-    let response = timeout(
-        Duration::from_secs(60), // 60 second timeout
-        client
-            .post("https://api.x.ai/v1/chat/completions")
-            .header("Authorization", format!("Bearer {}", api_key))
-            .header("Content-Type", "application/json")
-            .json(&request_body)
-            .send()
-    ).await??;
+    let mut stream = match chat_service.send_request(request, Some(&cancel)).await {
+        Ok(ChatResponse::Stream(stream)) => stream,
+        Ok(ChatResponse::Complete(response)) => {
+            let reply = response.get_content()?;
+            print_reply(&reply, styled);
+            return Ok(Sent {
+                reply,
+                usage: response.get_usage().cloned(),
+                finish_reason: response.get_finish_reason(),
+                effects: response.effects.clone(),
+                response: Some(response),
+                resumes: None,
+                cancelled: false,
+            });
+        }
+        Err(e) if is_cancelled(&e) => {
+            return Ok(Sent {
+                reply: String::new(),
+                usage: None,
+                finish_reason: None,
+                response: None,
+                resumes: None,
+                effects: Vec::new(),
+                cancelled: true,
+            })
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut stdout = io::stdout();
+    let mut output = StreamOutput {
+        sanitizer: stdout.is_terminal().then(Sanitizer::new),
+        renderer: styled.then(MarkdownRenderer::new),
+    };
+    let mut reply = String::new();
+    let mut usage = None;
+    let mut finish_reason = None;
+    let mut resumes = None;
+    let mut effects = Vec::new();
+    let mut cancelled = false;
+    while let Some(chunk) = stream.next().await {
+        match chunk {
+            Ok(chunk) => {
+                print!("{}", output.push(&chunk.content));
+                stdout.flush()?;
+                reply.push_str(&chunk.content);
+                if chunk.usage.is_some() {
+                    usage = chunk.usage;
+                }
+                if chunk.finish_reason.is_some() {
+                    finish_reason = chunk.finish_reason;
+                }
+                resumes = chunk.resumes.or(resumes);
+                if let Some(processed) = chunk.processed {
+                    reply = processed.content;
+                    effects = processed.effects;
+                }
+            }
+            Err(e) if is_cancelled(&e) => {
+                cancelled = true;
+                break;
+            }
+            Err(e) => {
+                println!("{}", output.finish());
+                return Err(e);
+            }
+        }
+    }
+    println!("{}", output.finish());
+
+    Ok(Sent {
+        reply,
+        usage,
+        finish_reason,
+        response: None,
+        resumes,
+        effects,
+        cancelled,
+    })
+}
+
+fn print_effects(effects: &[String]) {
+    for effect in effects {
+        eprintln!("📎 {}", effect);
+    }
+}
+
+/// What a streamed reply passes through before it is printed: the sanitizer
+/// on a terminal, then the markdown renderer when styled.
+struct StreamOutput {
+    sanitizer: Option<Sanitizer>,
+    renderer: Option<MarkdownRenderer>,
+}
+
+impl StreamOutput {
+    fn push(&mut self, delta: &str) -> String {
+        let delta = match self.sanitizer.as_mut() {
+            Some(sanitizer) => sanitizer.push(delta),
+            None => delta.to_string(),
+        };
+        self.push_rendered(&delta)
+    }
+
+    fn finish(&mut self) -> String {
+        let rest = self
+            .sanitizer
+            .as_mut()
+            .map(Sanitizer::finish)
+            .unwrap_or_default();
+        let mut out = self.push_rendered(&rest);
+        if let Some(renderer) = self.renderer.as_mut() {
+            out.push_str(&renderer.finish());
+        }
+        out
+    }
+
+    fn push_rendered(&mut self, text: &str) -> String {
+        match self.renderer.as_mut() {
+            Some(renderer) => renderer.push(text),
+            None => text.to_string(),
+        }
+    }
+}
+
+/// The saved conversation a single message belongs to, selected with
+/// `--session`, `--continue` or `--new-session`.
+#[cfg(feature = "server")]
+struct CliSession {
+    database: Database,
+    outbox: Outbox,
+    id: String,
+    /// The session's preset, unless --system or --prompt-preset chose one.
+    system_prompt: Option<String>,
+    /// Recorded on replies.
+    agent: Option<String>,
+}
+
+#[cfg(feature = "server")]
+impl CliSession {
+    async fn open(
+        args: &ChatArgs,
+        config: &Config,
+        chat_service: &ChatService,
+        _quiet: bool,
+    ) -> Result<Option<Self>> {
+        if !args.wants_session() {
+            return Ok(None);
+        }
+
+        let database = Database::new(config)
+            .await
+            .context("Failed to open the session database")?;
+
+        let id = if let Some(title) = &args.new_session {
+            let session = ChatSession::new(args.model().to_string(), Some(title.clone()));
+            database.create_session(session).await?.id
+        } else if let Some(id) = &args.session {
+            match database.get_session(id).await? {
+                Some(session) => session.id,
+                None => bail!(tr_args("cli.session_not_found", &[("id", &id)])),
+            }
+        } else if args.continue_session {
+            match database.list_sessions(Some(1), None).await?.pop() {
+                Some(session) => session.id,
+                None => bail!("No session to continue; start one with --new-session"),
+            }
+        } else {
+            // --offline on its own keeps the message in a fresh session
+            let session = ChatSession::new(args.model().to_string(), None);
+            database.create_session(session).await?.id
+        };
+
+        // The session keeps using a preset chosen for it
+        if let Some(name) = &args.prompt_preset {
+            database.set_session_prompt(&id, Some(name)).await?;
+        }
+        if let Some(name) = &args.agent {
+            database.set_session_agent(&id, Some(name)).await?;
+        }
+        let system_prompt = if args.system_given {
+            None
+        } else {
+            database
+                .session_prompt(&id)
+                .await?
+                .map(|preset| preset.body)
+        };
+
+        let outbox = Outbox::new(database.clone(), chat_service.clone());
+        Ok(Some(Self {
+            database,
+            outbox,
+            id,
+            system_prompt,
+            agent: args.agent.clone(),
+        }))
+    }
+
+    fn system_prompt(&self) -> Option<&str> {
+        self.system_prompt.as_deref()
+    }
+
+    /// Sends messages queued earlier in this session, returning how many.
+    async fn flush_queued(&self) -> Result<usize> {
+        Ok(self.outbox.flush_session(&self.id).await?.len())
+    }
+
+    async fn queue(&self, prompt: String, model: &str) -> Result<Message> {
+        self.outbox
+            .queue(&self.id, prompt, Some(model.to_string()))
+            .await
+    }
+
+    /// The context to send, and the older turns the budget left out.
+    async fn history(&self) -> Result<(Vec<ApiMessage>, Vec<ApiMessage>)> {
+        let messages = self.database.get_messages(&self.id).await?;
+        Ok(session_context_dropping(&messages, DEFAULT_TOKEN_BUDGET))
+    }
+
+    async fn save_turn(
+        &self,
+        prompt: String,
+        reply: String,
+        model: &str,
+        usage: Option<&UsageStats>,
+        stats: CompletionStats,
+        resumes: Option<u32>,
+    ) -> Result<()> {
+        self.database
+            .create_message(Message::user(self.id.clone(), prompt))
+            .await?;
+        let mut reply = Message::assistant(self.id.clone(), reply, Some(model.to_string()));
+        reply.resumes = resumes;
+        reply.agent = self.agent.clone();
+        self.database.create_message(reply).await?;
+        if let Some(usage) = usage {
+            self.database
+                .record_usage(Some(&self.id), model, usage, &stats)
+                .await?;
+        }
 
-    if !response.status().is_success() {
-        let status = response.status();
-        let error_text = response.text().await.unwrap_or_default();
-        return Err(anyhow::anyhow!("❌ API Error ({}): {}", status, error_text));
+        // Keeps --continue pointed at the conversation used last
+        self.database.update_session(&self.id, None).await
     }
+}
 
-    let response_json: serde_json::Value = response.json().await?;
+/// Without the `server` feature there is no database, so messages stay stateless.
+#[cfg(not(feature = "server"))]
+struct CliSession {
+    id: String,
+}
 
-    let content = response_json["choices"]
-        .get(0)
-        .and_then(|choice| choice["message"]["content"].as_str())
-        .ok_or_else(|| anyhow::anyhow!("❌ No response content found in API response"))?;
+#[cfg(not(feature = "server"))]
+impl CliSession {
+    async fn open(
+        args: &ChatArgs,
+        _config: &Config,
+        _chat_service: &ChatService,
+        quiet: bool,
+    ) -> Result<Option<Self>> {
+        if args.offline {
+            return Err(UsageError(tr("cli.offline_needs_server").to_string()).into());
+        }
+        if args.wants_session() && !quiet {
+            eprintln!("{}", tr("cli.sessions_need_server"));
+        }
+        Ok(None)
+    }
+
+    fn system_prompt(&self) -> Option<&str> {
+        None
+    }
+
+    async fn history(&self) -> Result<(Vec<ApiMessage>, Vec<ApiMessage>)> {
+        Ok((Vec::new(), Vec::new()))
+    }
+
+    async fn flush_queued(&self) -> Result<usize> {
+        Ok(0)
+    }
 
-    Ok(content.to_string())
+    async fn queue(&self, _prompt: String, _model: &str) -> Result<Message> {
+        unreachable!("sessions are never opened without the `server` feature")
+    }
+
+    async fn save_turn(
+        &self,
+        _prompt: String,
+        _reply: String,
+        _model: &str,
+        _usage: Option<&UsageStats>,
+        _stats: CompletionStats,
+        _resumes: Option<u32>,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A temperature that came from the config rather than --temperature is
+/// dropped without a note; reasoning models would otherwise warn every time.
+fn print_param_warnings(warnings: &[ParamWarning], explicit_temperature: bool) {
+    for warning in warnings {
+        if warning.parameter != "temperature" || explicit_temperature {
+            eprintln!("⚠️  {}", warning);
+        }
+    }
+}
+
+/// Prints the body a `--dry-run` would have sent, or with `--output json` the
+/// whole report. Text output notes the rest on stderr.
+fn print_dry_run(dry_run: &DryRun, args: &ChatArgs, quiet: bool) -> Result<()> {
+    if args.output == OutputFormat::Json {
+        println!("{}", serde_json::to_string(dry_run)?);
+        return Ok(());
+    }
+    println!("{}", serde_json::to_string_pretty(&dry_run.body)?);
+    if !quiet {
+        eprintln!(
+            "{}",
+            tr_args("cli.dry_run", &[("tokens", &dry_run.estimated_tokens)])
+        );
+        if !dry_run.dropped.is_empty() {
+            eprintln!(
+                "{}",
+                tr_args("cli.dry_run_dropped", &[("count", &dry_run.dropped.len())])
+            );
+        }
+        if dry_run.redactions > 0 {
+            print_redactions(dry_run.redactions);
+        }
+        print_param_warnings(&dry_run.warnings, args.temperature_given);
+    }
+    Ok(())
+}
+
+/// Projects what `request` could cost and applies the cost guard: over the
+/// threshold, `warn` mode warns on stderr and `block` mode fails with
+/// `CostNotConfirmed` unless `confirmed`. `None` when the guard is off.
+fn guard_cost(
+    chat_service: &ChatService,
+    config: &Config,
+    request: &ApiChatRequest,
+    confirmed: bool,
+    quiet: bool,
+) -> Result<Option<CostEstimate>> {
+    let guard = CostGuard::from_config(config);
+    if guard.is_off() {
+        return Ok(None);
+    }
+    let estimate = chat_service.estimate_cost(request, &PricingTable::load(config)?);
+    if guard.admit(&estimate, confirmed)? == Verdict::Warn && !quiet {
+        eprintln!(
+            "{}",
+            tr_args(
+                "cli.cost_warning",
+                &[
+                    ("cost", &estimate.cost),
+                    ("threshold", &format!("{:.2}", guard.threshold_usd)),
+                ],
+            )
+        );
+    }
+    Ok(Some(estimate))
+}
+
+fn print_redactions(count: usize) {
+    eprintln!("{}", tr_args("cli.redacted", &[("count", &count)]));
+}
+
+fn is_cancelled(error: &anyhow::Error) -> bool {
+    XaiError::from_anyhow(error) == Some(&XaiError::Cancelled)
+}
+
+fn print_logprobs(response: &ApiChatResponse) {
+    let Some(logprobs) = response.get_logprobs() else {
+        eprintln!("{}", tr("cli.no_logprobs"));
+        return;
+    };
+
+    for token in logprobs {
+        let alternatives: Vec<String> = token
+            .top_logprobs
+            .iter()
+            .map(|alt| format!("{:?} {:.4}", alt.token, alt.logprob))
+            .collect();
+
+        if alternatives.is_empty() {
+            eprintln!("{:?}\t{:.4}", token.token, token.logprob);
+        } else {
+            eprintln!(
+                "{:?}\t{:.4}\t[{}]",
+                token.token,
+                token.logprob,
+                alternatives.join(", ")
+            );
+        }
+    }
 }
@@ -0,0 +1,903 @@
+//! `grok-chat mcp`: a Model Context Protocol server on stdin/stdout, so
+//! MCP-capable editors can read saved chat history and talk to Grok.
+//!
+//! Messages are JSON-RPC 2.0, one per line. Sessions are exposed both as
+//! resources (`grok://sessions` and `grok://sessions/{id}`) and as tools;
+//! `send_message` goes through [`ChatService`] and is saved like any other
+//! exchange. Stdout carries only protocol messages.
+
+use anyhow::{anyhow, bail, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::client::{ChatResponse, ChatService};
+use crate::config::{Config, MissingApiKey};
+use crate::context::{session_context, DEFAULT_TOKEN_BUDGET};
+use crate::database::Database;
+use crate::models::{ApiChatRequest, ChatSession, Conversation, Message};
+use crate::pricing::{CostGuard, PricingTable};
+
+/// Newest first. A client asking for another version is offered the newest.
+pub const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+const SESSIONS_URI: &str = "grok://sessions";
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 200;
+
+/// JSON-RPC error codes.
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+/// MCP's code for a `resources/read` of an unknown URI.
+pub const RESOURCE_NOT_FOUND: i64 = -32002;
+
+/// A request that fails as a whole. Failures inside a tool are reported in
+/// its result instead, with `isError` set, so the model can see them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::new(INTERNAL_ERROR, format!("{:#}", e))
+    }
+}
+
+pub struct McpServer {
+    database: Database,
+    chat_service: ChatService,
+    config: Config,
+    initialized: bool,
+}
+
+/// Serves MCP on stdin/stdout until the client closes stdin.
+pub async fn run_stdio() -> Result<()> {
+    // Listing and searching history works without an API key; only
+    // `send_message` needs one
+    let config = Config::load()?;
+    let database = Database::new(&config).await?;
+    McpServer::new(config, database)?
+        .serve(tokio::io::stdin(), tokio::io::stdout())
+        .await
+}
+
+impl McpServer {
+    pub fn new(config: Config, database: Database) -> Result<Self> {
+        Ok(Self {
+            chat_service: ChatService::new(&config)?,
+            database,
+            config,
+            initialized: false,
+        })
+    }
+
+    /// Answers each line read from `reader` on `writer` until `reader` ends.
+    /// Requests are handled one at a time, in order.
+    pub async fn serve<R, W>(&mut self, reader: R, mut writer: W) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(response) = self.handle_line(&line).await {
+                let mut bytes = serde_json::to_vec(&response)?;
+                bytes.push(b'\n');
+                writer.write_all(&bytes).await?;
+                writer.flush().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The response to one message; notifications and client responses get
+    /// none.
+    pub async fn handle_line(&mut self, line: &str) -> Option<Value> {
+        let message: Value = match serde_json::from_str(line) {
+            Ok(message) => message,
+            Err(e) => return Some(error_response(Value::Null, PARSE_ERROR, e.to_string())),
+        };
+        if message.is_array() {
+            return Some(error_response(
+                Value::Null,
+                INVALID_REQUEST,
+                "Batches are not supported",
+            ));
+        }
+
+        let id = message.get("id").cloned();
+        let method = message.get("method").and_then(Value::as_str);
+        let (Some(method), true) = (method, message["jsonrpc"] == "2.0") else {
+            // A response to a request we never send, or junk
+            return match id {
+                Some(id) if message.get("result").is_none() && message.get("error").is_none() => {
+                    Some(error_response(
+                        id,
+                        INVALID_REQUEST,
+                        "Not a JSON-RPC 2.0 request",
+                    ))
+                }
+                _ => None,
+            };
+        };
+
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        let Some(id) = id else {
+            // `notifications/initialized`, `notifications/cancelled` and the
+            // like need no action
+            return None;
+        };
+        let response = match self.dispatch(method, params).await {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(e) => error_response(id, e.code, e.message),
+        };
+        Some(response)
+    }
+
+    async fn dispatch(&mut self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "initialize" => return Ok(self.initialize(&params)),
+            "ping" => return Ok(json!({})),
+            _ if !self.initialized => {
+                return Err(RpcError::new(
+                    INVALID_REQUEST,
+                    "The session has not been initialized",
+                ));
+            }
+            _ => {}
+        }
+
+        match method {
+            "tools/list" => Ok(json!({ "tools": tool_definitions() })),
+            "tools/call" => {
+                let call: ToolCall = parse_params(params)?;
+                self.call_tool(&call.name, call.arguments).await
+            }
+            "resources/list" => self.list_resources().await,
+            "resources/templates/list" => Ok(json!({
+                "resourceTemplates": [{
+                    "uriTemplate": format!("{}/{{session_id}}", SESSIONS_URI),
+                    "name": "session",
+                    "description": "A saved session and its messages",
+                    "mimeType": "application/json",
+                }]
+            })),
+            "resources/read" => {
+                let read: ReadResource = parse_params(params)?;
+                self.read_resource(&read.uri).await
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method '{}'", method),
+            )),
+        }
+    }
+
+    fn initialize(&mut self, params: &Value) -> Value {
+        let requested = params["protocolVersion"].as_str();
+        let version = PROTOCOL_VERSIONS
+            .iter()
+            .find(|version| Some(**version) == requested)
+            .unwrap_or(&PROTOCOL_VERSIONS[0]);
+        self.initialized = true;
+        json!({
+            "protocolVersion": version,
+            "capabilities": {
+                "tools": { "listChanged": false },
+                "resources": { "subscribe": false, "listChanged": false },
+            },
+            "serverInfo": {
+                "name": "grok-chat",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+        })
+    }
+
+    async fn call_tool(&self, name: &str, arguments: Value) -> Result<Value, RpcError> {
+        let result = match name {
+            "list_sessions" => {
+                let args: LimitArgs = parse_params(arguments)?;
+                self.session_summaries(args.limit()).await
+            }
+            "get_session_messages" => {
+                let args: SessionArgs = parse_params(arguments)?;
+                self.session_messages(&args.session_id).await
+            }
+            "search_messages" => {
+                let args: SearchArgs = parse_params(arguments)?;
+                let limit = args.limit.clamp(1, MAX_LIMIT);
+                self.database
+                    .search_messages(&args.query, limit)
+                    .await
+                    .map(|messages| json!(messages))
+            }
+            "send_message" => {
+                let args: SendArgs = parse_params(arguments)?;
+                self.send_message(args).await
+            }
+            _ => {
+                return Err(RpcError::new(
+                    INVALID_PARAMS,
+                    format!("Unknown tool '{}'", name),
+                ))
+            }
+        };
+
+        Ok(match result {
+            Ok(value) => json!({
+                "content": [{ "type": "text", "text": pretty(&value) }],
+                "isError": false,
+            }),
+            Err(e) => json!({
+                "content": [{ "type": "text", "text": format!("{:#}", e) }],
+                "isError": true,
+            }),
+        })
+    }
+
+    async fn session_summaries(&self, limit: i64) -> Result<Value> {
+        let mut summaries = Vec::new();
+        for session in self.database.list_sessions(Some(limit), None).await? {
+            let messages = self.database.get_session_message_count(&session.id).await?;
+            summaries.push(json!({
+                "id": session.id,
+                "title": session.title,
+                "model": session.model,
+                "updated_at": session.updated_at,
+                "messages": messages,
+            }));
+        }
+        Ok(json!(summaries))
+    }
+
+    async fn session_messages(&self, session_id: &str) -> Result<Value> {
+        let export = self
+            .database
+            .export_session(session_id)
+            .await?
+            .ok_or_else(|| anyhow!("Session '{}' not found", session_id))?;
+        Ok(json!(export))
+    }
+
+    /// Sends `message` in a session (a new one without `session_id`) and
+    /// saves both sides, as the HTTP API does, once the reply is in: a failed
+    /// send leaves the session as it was. Model aliases are resolved. A
+    /// message that could cost more than the cost threshold is refused unless
+    /// `confirm_cost` is set.
+    async fn send_message(&self, args: SendArgs) -> Result<Value> {
+        if self.config.xai_api_key().is_empty() {
+            return Err(MissingApiKey.into());
+        }
+        let session = match &args.session_id {
+            Some(id) => Some(
+                self.database
+                    .get_session(id)
+                    .await?
+                    .ok_or_else(|| anyhow!("Session '{}' not found", id))?,
+            ),
+            None => None,
+        };
+        let model = self.config.resolve_model(
+            args.model
+                .as_deref()
+                .or(session.as_ref().map(|session| session.model.as_str())),
+        )?;
+        let (system_prompt, history) = match &session {
+            Some(session) => (
+                self.database
+                    .session_prompt(&session.id)
+                    .await?
+                    .map(|preset| preset.body),
+                self.database.get_messages(&session.id).await?,
+            ),
+            None => (None, Vec::new()),
+        };
+        let messages = Conversation::new()
+            .history(session_context(&history, DEFAULT_TOKEN_BUDGET))
+            .user(args.message.clone())
+            .into_messages();
+        let mut request = ApiChatRequest::builder()
+            .messages(messages)
+            .model(&model)
+            .stream(false)
+            .build();
+        request.system_prompt = system_prompt;
+        let guard = CostGuard::from_config(&self.config);
+        if !guard.is_off() {
+            let estimate = self
+                .chat_service
+                .estimate_cost(&request, &PricingTable::load(&self.config)?);
+            guard
+                .admit(&estimate, args.confirm_cost)
+                .map_err(|e| anyhow!("{}; set confirm_cost to send it anyway", e))?;
+        }
+
+        let ChatResponse::Complete(response) =
+            self.chat_service.send_request(request, None).await?
+        else {
+            bail!("Expected a complete response");
+        };
+        let reply = response.get_content()?;
+
+        let session = match session {
+            Some(session) => session,
+            None => {
+                self.database
+                    .create_session(ChatSession::new(model.clone(), None))
+                    .await?
+            }
+        };
+        self.database
+            .create_message(Message::user(
+                session.id.clone(),
+                self.chat_service.stored_text(args.message),
+            ))
+            .await?;
+
+        if let Some(usage) = response.get_usage() {
+            self.database
+                .record_usage(
+                    Some(&session.id),
+                    &model,
+                    usage,
+                    &response.completion_stats(),
+                )
+                .await?;
+        }
+        self.database
+            .create_message(Message::assistant(
+                session.id.clone(),
+                reply.clone(),
+                Some(model),
+            ))
+            .await?;
+        self.database.update_session(&session.id, None).await?;
+
+        Ok(json!({ "session_id": session.id, "reply": reply }))
+    }
+
+    async fn list_resources(&self) -> Result<Value, RpcError> {
+        let mut resources = vec![json!({
+            "uri": SESSIONS_URI,
+            "name": "sessions",
+            "description": "The most recently updated sessions",
+            "mimeType": "application/json",
+        })];
+        for session in self
+            .database
+            .list_sessions(Some(DEFAULT_LIMIT), None)
+            .await?
+        {
+            resources.push(json!({
+                "uri": format!("{}/{}", SESSIONS_URI, session.id),
+                "name": session.title.as_deref().unwrap_or(&session.id),
+                "mimeType": "application/json",
+            }));
+        }
+        Ok(json!({ "resources": resources }))
+    }
+
+    async fn read_resource(&self, uri: &str) -> Result<Value, RpcError> {
+        let contents = if uri == SESSIONS_URI {
+            self.session_summaries(DEFAULT_LIMIT).await?
+        } else {
+            let export = match uri
+                .strip_prefix(SESSIONS_URI)
+                .and_then(|id| id.strip_prefix('/'))
+            {
+                Some(id) => self.database.export_session(id).await?,
+                None => None,
+            };
+            json!(export.ok_or_else(|| {
+                RpcError::new(RESOURCE_NOT_FOUND, format!("Resource not found: {}", uri))
+            })?)
+        };
+        Ok(json!({
+            "contents": [{
+                "uri": uri,
+                "mimeType": "application/json",
+                "text": pretty(&contents),
+            }]
+        }))
+    }
+}
+
+fn error_response(id: Value, code: i64, message: impl Into<String>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message.into() },
+    })
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    // Tools without arguments may be called with none at all
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_default()
+}
+
+#[derive(Deserialize)]
+struct ToolCall {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+#[derive(Deserialize)]
+struct ReadResource {
+    uri: String,
+}
+
+#[derive(Deserialize)]
+struct LimitArgs {
+    limit: Option<i64>,
+}
+
+impl LimitArgs {
+    fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+}
+
+#[derive(Deserialize)]
+struct SessionArgs {
+    session_id: String,
+}
+
+#[derive(Deserialize)]
+struct SearchArgs {
+    query: String,
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+fn default_limit() -> i64 {
+    DEFAULT_LIMIT
+}
+
+#[derive(Deserialize)]
+struct SendArgs {
+    message: String,
+    session_id: Option<String>,
+    model: Option<String>,
+    #[serde(default)]
+    confirm_cost: bool,
+}
+
+fn tool_definitions() -> Value {
+    json!([
+        {
+            "name": "list_sessions",
+            "description": "List saved Grok chat sessions, most recently updated first",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "limit": { "type": "integer", "minimum": 1, "maximum": MAX_LIMIT },
+                },
+            },
+        },
+        {
+            "name": "get_session_messages",
+            "description": "Get a session and all of its messages",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "session_id": { "type": "string" } },
+                "required": ["session_id"],
+            },
+        },
+        {
+            "name": "search_messages",
+            "description": "Find messages containing some text, newest first",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string" },
+                    "limit": { "type": "integer", "minimum": 1, "maximum": MAX_LIMIT },
+                },
+                "required": ["query"],
+            },
+        },
+        {
+            "name": "send_message",
+            "description": "Send a message to Grok in a session (a new one when session_id \
+                            is omitted) and return the reply; both are saved",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "message": { "type": "string" },
+                    "session_id": { "type": "string" },
+                    "model": { "type": "string" },
+                    "confirm_cost": {
+                        "type": "boolean",
+                        "description": "Send even when it could cost more than the \
+                                        configured cost threshold",
+                    },
+                },
+                "required": ["message"],
+            },
+        },
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock::MockUpstream;
+    use tokio::io::{DuplexStream, Lines};
+    use tokio::task::JoinHandle;
+
+    /// A client on the other end of in-memory pipes, one per direction so
+    /// that dropping `writer` closes the server's input.
+    struct Client {
+        writer: DuplexStream,
+        lines: Lines<BufReader<DuplexStream>>,
+        server: JoinHandle<Result<()>>,
+        next_id: i64,
+    }
+
+    impl Client {
+        async fn start(config: Config) -> (Self, Database) {
+            let database = Database::new(&config).await.unwrap();
+            let (writer, server_read) = tokio::io::duplex(64 * 1024);
+            let (server_write, read) = tokio::io::duplex(64 * 1024);
+            let mut mcp = McpServer::new(config, database.clone()).unwrap();
+            let server = tokio::spawn(async move { mcp.serve(server_read, server_write).await });
+            let client = Self {
+                writer,
+                lines: BufReader::new(read).lines(),
+                server,
+                next_id: 0,
+            };
+            (client, database)
+        }
+
+        async fn send(&mut self, line: &str) {
+            self.writer.write_all(line.as_bytes()).await.unwrap();
+            self.writer.write_all(b"\n").await.unwrap();
+        }
+
+        async fn receive(&mut self) -> Value {
+            let line = self.lines.next_line().await.unwrap().unwrap();
+            serde_json::from_str(&line).unwrap()
+        }
+
+        async fn request(&mut self, method: &str, params: Value) -> Value {
+            self.next_id += 1;
+            let request = json!({
+                "jsonrpc": "2.0",
+                "id": self.next_id,
+                "method": method,
+                "params": params,
+            });
+            self.send(&request.to_string()).await;
+            let response = self.receive().await;
+            assert_eq!(response["id"], self.next_id);
+            response
+        }
+
+        async fn initialize(&mut self) {
+            let response = self
+                .request("initialize", json!({ "protocolVersion": "2025-03-26" }))
+                .await;
+            assert_eq!(response["result"]["protocolVersion"], "2025-03-26");
+            self.send(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#)
+                .await;
+        }
+
+        async fn call(&mut self, tool: &str, arguments: Value) -> Value {
+            self.request(
+                "tools/call",
+                json!({ "name": tool, "arguments": arguments }),
+            )
+            .await["result"]
+                .clone()
+        }
+    }
+
+    fn config_in(dir: &std::path::Path, base: Config) -> Config {
+        Config {
+            database_url: format!("sqlite:{}?mode=rwc", dir.join("chat.db").display()),
+            ..base
+        }
+    }
+
+    /// The JSON carried in a tool result's text content.
+    fn tool_json(result: &Value) -> Value {
+        assert_eq!(result["isError"], false, "{}", result);
+        serde_json::from_str(result["content"][0]["text"].as_str().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_initialize_negotiates_and_gates_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut client, _) = Client::start(config_in(dir.path(), Config::default())).await;
+
+        let early = client.request("tools/list", json!({})).await;
+        assert_eq!(early["error"]["code"], INVALID_REQUEST);
+        assert_eq!(client.request("ping", json!({})).await["result"], json!({}));
+
+        let response = client
+            .request("initialize", json!({ "protocolVersion": "1999-01-01" }))
+            .await;
+        let result = &response["result"];
+        assert_eq!(result["protocolVersion"], PROTOCOL_VERSIONS[0]);
+        assert_eq!(result["serverInfo"]["name"], "grok-chat");
+        assert!(result["capabilities"]["tools"].is_object());
+        assert!(result["capabilities"]["resources"].is_object());
+
+        // Notifications get no reply: the next line answers the next request
+        client
+            .send(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#)
+            .await;
+        let tools = client.request("tools/list", Value::Null).await;
+        let names: Vec<&str> = tools["result"]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tool| tool["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "list_sessions",
+                "get_session_messages",
+                "search_messages",
+                "send_message"
+            ]
+        );
+
+        // Closing stdin ends the server cleanly
+        drop(client.writer);
+        client.server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_protocol_errors_are_mapped() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut client, _) = Client::start(config_in(dir.path(), Config::default())).await;
+        client.initialize().await;
+
+        client.send("{not json").await;
+        let parse = client.receive().await;
+        assert_eq!(parse["error"]["code"], PARSE_ERROR);
+        assert_eq!(parse["id"], Value::Null);
+
+        client
+            .send(r#"[{"jsonrpc":"2.0","id":1,"method":"ping"}]"#)
+            .await;
+        assert_eq!(client.receive().await["error"]["code"], INVALID_REQUEST);
+
+        client.send(r#"{"id":7,"method":"ping"}"#).await;
+        assert_eq!(client.receive().await["error"]["code"], INVALID_REQUEST);
+
+        let unknown = client.request("sessions/delete", json!({})).await;
+        assert_eq!(unknown["error"]["code"], METHOD_NOT_FOUND);
+
+        let tool = client
+            .request("tools/call", json!({ "name": "rm_rf", "arguments": {} }))
+            .await;
+        assert_eq!(tool["error"]["code"], INVALID_PARAMS);
+
+        let missing = client
+            .request("tools/call", json!({ "name": "search_messages" }))
+            .await;
+        assert_eq!(missing["error"]["code"], INVALID_PARAMS);
+
+        let resource = client
+            .request("resources/read", json!({ "uri": "grok://sessions/nope" }))
+            .await;
+        assert_eq!(resource["error"]["code"], RESOURCE_NOT_FOUND);
+
+        // A tool that runs but fails reports it in its result
+        let result = client
+            .call("get_session_messages", json!({ "session_id": "nope" }))
+            .await;
+        assert_eq!(result["isError"], true);
+        assert!(result["content"][0]["text"]
+            .as_str()
+            .unwrap()
+            .contains("not found"));
+    }
+
+    #[tokio::test]
+    async fn test_history_tools_and_resources() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut client, database) = Client::start(config_in(dir.path(), Config::default())).await;
+        let session = database
+            .create_session(ChatSession::new(
+                "grok-3".to_string(),
+                Some("Lisbon".into()),
+            ))
+            .await
+            .unwrap();
+        for content in ["Where should I eat in Lisbon?", "Try the pastéis de nata"] {
+            database
+                .create_message(Message::user(session.id.clone(), content.to_string()))
+                .await
+                .unwrap();
+        }
+        client.initialize().await;
+
+        let sessions = tool_json(&client.call("list_sessions", json!({})).await);
+        assert_eq!(sessions[0]["id"], session.id.as_str());
+        assert_eq!(sessions[0]["messages"], 2);
+
+        let export = tool_json(
+            &client
+                .call("get_session_messages", json!({ "session_id": session.id }))
+                .await,
+        );
+        assert_eq!(export["session"]["title"], "Lisbon");
+        assert_eq!(export["messages"].as_array().unwrap().len(), 2);
+
+        let found = tool_json(
+            &client
+                .call("search_messages", json!({ "query": "pastéis" }))
+                .await,
+        );
+        assert_eq!(found.as_array().unwrap().len(), 1);
+        assert_eq!(found[0]["session_id"], session.id.as_str());
+
+        let listed = client.request("resources/list", json!({})).await;
+        let uris: Vec<&str> = listed["result"]["resources"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|resource| resource["uri"].as_str().unwrap())
+            .collect();
+        let session_uri = format!("grok://sessions/{}", session.id);
+        assert_eq!(uris, ["grok://sessions", session_uri.as_str()]);
+
+        let read = client
+            .request("resources/read", json!({ "uri": session_uri }))
+            .await;
+        let text = read["result"]["contents"][0]["text"].as_str().unwrap();
+        assert!(text.contains("pastéis de nata"));
+    }
+
+    #[tokio::test]
+    async fn test_send_message_proxies_and_saves_the_exchange() {
+        let upstream = MockUpstream::echo("Bom dia!").await;
+        let dir = tempfile::tempdir().unwrap();
+        let (mut client, database) = Client::start(config_in(dir.path(), upstream.config())).await;
+        client.initialize().await;
+
+        let sent = tool_json(
+            &client
+                .call("send_message", json!({ "message": "Say good morning" }))
+                .await,
+        );
+        assert_eq!(sent["reply"], "Bom dia!");
+        let session_id = sent["session_id"].as_str().unwrap();
+
+        let again = tool_json(
+            &client
+                .call(
+                    "send_message",
+                    json!({ "message": "Again", "session_id": session_id }),
+                )
+                .await,
+        );
+        assert_eq!(again["session_id"], session_id);
+
+        let messages = database.get_messages(session_id).await.unwrap();
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[1].content, "Bom dia!");
+        // The second request carried the first exchange
+        let second = &upstream.requests()[1]["messages"];
+        assert!(second.to_string().contains("Say good morning"));
+    }
+
+    #[tokio::test]
+    async fn test_send_message_resolves_aliases_and_saves_only_answered_messages() {
+        use crate::client::mock::MockReply;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = AtomicUsize::new(0);
+        let upstream = MockUpstream::start(move |_| match calls.fetch_add(1, Ordering::SeqCst) {
+            1 => MockReply::completion("Bom dia!"),
+            _ => MockReply::Json(400, json!({ "error": { "message": "Rejected" } })),
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            model_aliases: [("fast".to_string(), "grok-3-mini".to_string())].into(),
+            ..upstream.config()
+        };
+        let (mut client, database) = Client::start(config_in(dir.path(), config)).await;
+        client.initialize().await;
+
+        let arguments = json!({ "message": "Say good morning", "model": "fast" });
+        let failed = client.call("send_message", arguments.clone()).await;
+        assert_eq!(failed["isError"], true);
+        assert!(database.list_sessions(None, None).await.unwrap().is_empty());
+
+        let sent = tool_json(&client.call("send_message", arguments).await);
+        let session_id = sent["session_id"].as_str().unwrap();
+        let session = database.get_session(session_id).await.unwrap().unwrap();
+        assert_eq!(session.model, "grok-3-mini");
+        assert_eq!(upstream.requests()[1]["model"], "grok-3-mini");
+
+        let failed = client
+            .call(
+                "send_message",
+                json!({ "message": "Again", "session_id": session_id }),
+            )
+            .await;
+        assert_eq!(failed["isError"], true);
+        assert_eq!(database.get_messages(session_id).await.unwrap().len(), 2);
+
+        let unknown = client
+            .call("send_message", json!({ "message": "hi", "model": "slow" }))
+            .await;
+        assert_eq!(unknown["isError"], true);
+        assert_eq!(upstream.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_send_message_over_the_cost_threshold_needs_confirming() {
+        let upstream = MockUpstream::echo("Bom dia!").await;
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            cost_threshold_usd: 0.000_001,
+            ..upstream.config()
+        };
+        let (mut client, database) = Client::start(config_in(dir.path(), config)).await;
+        client.initialize().await;
+
+        let arguments = json!({ "message": "Say good morning", "model": "grok-4-0709" });
+        let result = client.call("send_message", arguments.clone()).await;
+        assert_eq!(result["isError"], true);
+        assert!(result["content"][0]["text"]
+            .as_str()
+            .unwrap()
+            .contains("confirm_cost"));
+        assert!(upstream.requests().is_empty());
+        assert!(database.list_sessions(None, None).await.unwrap().is_empty());
+
+        let mut confirmed = arguments;
+        confirmed["confirm_cost"] = json!(true);
+        let sent = tool_json(&client.call("send_message", confirmed).await);
+        assert_eq!(sent["reply"], "Bom dia!");
+        let session_id = sent["session_id"].as_str().unwrap();
+        assert_eq!(database.get_messages(session_id).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_send_message_without_a_key_is_a_tool_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut client, _) = Client::start(config_in(dir.path(), Config::default())).await;
+        client.initialize().await;
+
+        let result = client
+            .call("send_message", json!({ "message": "hi" }))
+            .await;
+        assert_eq!(result["isError"], true);
+        assert!(result["content"][0]["text"]
+            .as_str()
+            .unwrap()
+            .contains("XAI_API_KEY"));
+    }
+}
@@ -0,0 +1,3 @@
+pub use self::mcp::*;
+#[allow(clippy::module_inception)]
+mod mcp;
@@ -0,0 +1,263 @@
+//! Content-addressed storage for attached and generated images. Blobs live
+//! as plain files under the configured media directory, named by the SHA-256
+//! of their bytes, so the database only records a hash, size and MIME type
+//! per attachment, and the same image attached twice is stored once.
+//!
+//! Files are written, or touched when already there, before the attachment
+//! rows that reference them, so garbage collection leaves recent files alone
+//! for [`GC_GRACE`].
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+/// How old an unreferenced file must be before garbage collection removes
+/// it; younger ones may belong to an attachment still being saved.
+pub const GC_GRACE: Duration = Duration::from_secs(60 * 60);
+
+/// What the database keeps about a stored blob.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredMedia {
+    /// Hex SHA-256 of the bytes.
+    pub hash: String,
+    pub size: u64,
+    pub mime_type: String,
+}
+
+/// What [`MediaStore::collect_garbage`] removed, or would remove.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct GarbageReport {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Whether `hash` looks like one of ours: 64 lowercase hex digits. Anything
+/// else is refused before it gets near a path.
+pub fn is_media_hash(hash: &str) -> bool {
+    hash.len() == 64
+        && hash
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+#[derive(Debug, Clone)]
+pub struct MediaStore {
+    root: PathBuf,
+}
+
+impl MediaStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Where the blob with `hash` lives (`<root>/ab/abcdef…`), or `None` for
+    /// something that isn't a hash.
+    pub fn path(&self, hash: &str) -> Option<PathBuf> {
+        is_media_hash(hash).then(|| self.root.join(&hash[..2]).join(hash))
+    }
+
+    /// Stores `bytes`, unless a file with the same content is already there,
+    /// in which case it is touched: like a new file, it is then safe from
+    /// garbage collection until the attachment referencing it is saved. The
+    /// file is written under a temporary name and renamed into place, so
+    /// readers never see a partial blob.
+    pub async fn put(&self, bytes: &[u8], mime_type: &str) -> Result<StoredMedia> {
+        let hash = format!("{:x}", Sha256::digest(bytes));
+        let media = StoredMedia {
+            hash,
+            size: bytes.len() as u64,
+            mime_type: mime_type.to_string(),
+        };
+        let path = self.path(&media.hash).expect("SHA-256 hex is a media hash");
+        match touch(&path).await {
+            Ok(()) => return Ok(media),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to touch {}", path.display())),
+        }
+
+        let dir = path.parent().expect("blobs sit in a fan-out directory");
+        fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        let temp = dir.join(format!(".{}.tmp", Uuid::new_v4().simple()));
+        let written = async {
+            let mut file = File::create(&temp).await?;
+            file.write_all(bytes).await?;
+            file.sync_all().await?;
+            fs::rename(&temp, &path).await
+        }
+        .await;
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temp).await;
+            return Err(e).with_context(|| format!("Failed to write {}", path.display()));
+        }
+        Ok(media)
+    }
+
+    /// Opens the blob with `hash` for streaming, with its size; `None` when
+    /// there is no such blob.
+    pub async fn open(&self, hash: &str) -> Result<Option<(File, u64)>> {
+        let Some(path) = self.path(hash) else {
+            return Ok(None);
+        };
+        match File::open(&path).await {
+            Ok(file) => {
+                let size = file.metadata().await?.len();
+                Ok(Some((file, size)))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to open {}", path.display())),
+        }
+    }
+
+    /// Removes files no attachment references (`referenced` holds the hashes
+    /// still in use), along with temporary files left by interrupted writes.
+    /// Files younger than [`GC_GRACE`] are kept. With `dry_run`, only counts
+    /// what would go.
+    pub async fn collect_garbage(
+        &self,
+        referenced: &HashSet<String>,
+        dry_run: bool,
+    ) -> Result<GarbageReport> {
+        let mut report = GarbageReport::default();
+        let mut fanout = match fs::read_dir(&self.root).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(report),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", self.root.display()))
+            }
+        };
+        let cutoff = SystemTime::now() - GC_GRACE;
+
+        while let Some(dir) = fanout.next_entry().await? {
+            if !dir.file_type().await?.is_dir() {
+                continue;
+            }
+            let mut files = fs::read_dir(dir.path()).await?;
+            while let Some(file) = files.next_entry().await? {
+                let name = file.file_name();
+                let name = name.to_string_lossy();
+                let stray = name.starts_with('.') && name.ends_with(".tmp");
+                if !stray && (!is_media_hash(&name) || referenced.contains(name.as_ref())) {
+                    continue;
+                }
+                let metadata = file.metadata().await?;
+                if metadata.modified()? > cutoff {
+                    continue;
+                }
+                if !dry_run {
+                    fs::remove_file(file.path()).await?;
+                }
+                report.files += 1;
+                report.bytes += metadata.len();
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// Sets the file's modification time to now.
+async fn touch(path: &Path) -> std::io::Result<()> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        std::fs::File::options()
+            .append(true)
+            .open(path)?
+            .set_modified(SystemTime::now())
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_same_bytes_are_stored_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MediaStore::new(dir.path());
+
+        let first = store.put(b"\x89PNG fake", "image/png").await.unwrap();
+        let second = store.put(b"\x89PNG fake", "image/png").await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first.size, 9);
+        assert!(is_media_hash(&first.hash));
+
+        let fanout = dir.path().join(&first.hash[..2]);
+        assert_eq!(std::fs::read_dir(&fanout).unwrap().count(), 1);
+        let (_, size) = store.open(&first.hash).await.unwrap().unwrap();
+        assert_eq!(size, 9);
+        assert!(store.open(&"0".repeat(64)).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_only_hashes_become_paths() {
+        let store = MediaStore::new("/media");
+        let hash = "ab".repeat(32);
+        assert_eq!(
+            store.path(&hash),
+            Some(PathBuf::from("/media/ab").join(&hash))
+        );
+        assert_eq!(store.path("../../etc/passwd"), None);
+        assert_eq!(store.path(&"AB".repeat(32)), None);
+    }
+
+    #[tokio::test]
+    async fn test_garbage_collection_keeps_referenced_and_recent_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MediaStore::new(dir.path());
+        let kept = store.put(b"kept", "image/png").await.unwrap();
+        let orphan = store.put(b"orphan", "image/png").await.unwrap();
+        let fresh = store.put(b"fresh", "image/png").await.unwrap();
+
+        let old = SystemTime::now() - GC_GRACE * 2;
+        for media in [&kept, &orphan] {
+            std::fs::File::options()
+                .append(true)
+                .open(store.path(&media.hash).unwrap())
+                .unwrap()
+                .set_modified(old)
+                .unwrap();
+        }
+        let referenced = HashSet::from([kept.hash.clone()]);
+
+        let report = store.collect_garbage(&referenced, true).await.unwrap();
+        assert_eq!(report, GarbageReport { files: 1, bytes: 6 });
+        assert!(store.path(&orphan.hash).unwrap().exists());
+
+        store.collect_garbage(&referenced, false).await.unwrap();
+        assert!(store.path(&kept.hash).unwrap().exists());
+        assert!(!store.path(&orphan.hash).unwrap().exists());
+        assert!(store.path(&fresh.hash).unwrap().exists());
+    }
+
+    #[tokio::test]
+    async fn test_storing_an_old_blob_again_keeps_it_from_collection() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MediaStore::new(dir.path());
+        let media = store.put(b"again", "image/png").await.unwrap();
+        let path = store.path(&media.hash).unwrap();
+        std::fs::File::options()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - GC_GRACE * 2)
+            .unwrap();
+
+        // Attached again, with its row not saved yet
+        store.put(b"again", "image/png").await.unwrap();
+        let report = store.collect_garbage(&HashSet::new(), false).await.unwrap();
+        assert_eq!(report.files, 0);
+        assert!(path.exists());
+    }
+}
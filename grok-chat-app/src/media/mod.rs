@@ -0,0 +1,3 @@
+pub use self::media::*;
+#[allow(clippy::module_inception)]
+mod media;
@@ -1,2 +1,3 @@
 pub use self::models::*;
+#[allow(clippy::module_inception)]
 mod models;
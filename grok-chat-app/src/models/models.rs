@@ -1,20 +1,28 @@
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::str::FromStr;
 use uuid::Uuid;
 
-#[cfg(feature = "server")]
-use sqlx::FromRow;
+use crate::postprocess::ProcessedResponse;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ChatSession {
     pub id: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub model: String,
     pub title: Option<String>,
+    /// Name of the prompt preset the session's messages are sent with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_preset: Option<String>,
+    /// Agent that answers messages which don't name one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Message {
     pub id: i64,
     pub session_id: String,
@@ -23,51 +31,252 @@ pub struct Message {
     pub timestamp: DateTime<Utc>,
     pub model: Option<String>,
     pub tokens_used: Option<i32>,
+    /// Always sent as context, right after the system prompt, however old.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    /// Only written when the message is still waiting to be sent.
+    #[serde(default, skip_serializing_if = "MessageStatus::is_sent")]
+    pub status: MessageStatus,
+    /// How many times the reply's stream was cut off and resumed; absent
+    /// when it never was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resumes: Option<u32>,
+    /// The regenerated reply that replaced this one. Replaced replies are
+    /// kept for comparison but are no longer part of the conversation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub superseded_by: Option<i64>,
+    /// Put there by the session template the session started from rather
+    /// than typed or generated; still sent as context.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub seeded: bool,
+    /// A rating, note or labels added for evaluation. Exported and imported
+    /// with the message, never sent upstream.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotation: Option<Annotation>,
+    /// The agent that produced the reply; see `crate::agents`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+/// Whether a message has been through the API. Messages written offline stay
+/// `Queued`, without a reply, until the outbox sends them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum MessageStatus {
+    #[default]
+    Sent,
+    Queued,
+}
+
+impl MessageStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MessageStatus::Sent => "sent",
+            MessageStatus::Queued => "queued",
+        }
+    }
+
+    pub fn is_sent(&self) -> bool {
+        *self == MessageStatus::Sent
+    }
+}
+
+impl FromStr for MessageStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sent" => Ok(MessageStatus::Sent),
+            "queued" => Ok(MessageStatus::Queued),
+            _ => Err(anyhow::anyhow!("unknown message status '{}'", s)),
+        }
+    }
+}
+
+/// A thumbs up or down on a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Rating {
+    Up,
+    Down,
+}
+
+impl Rating {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Rating::Up => "up",
+            Rating::Down => "down",
+        }
+    }
+}
+
+impl FromStr for Rating {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "up" => Ok(Rating::Up),
+            "down" => Ok(Rating::Down),
+            _ => Err(anyhow::anyhow!("unknown rating '{}' (use up or down)", s)),
+        }
+    }
+}
+
+/// What someone collecting good and bad examples said about a message.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Annotation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rating: Option<Rating>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
+}
+
+impl Annotation {
+    /// Whether there is nothing left worth storing.
+    pub fn is_empty(&self) -> bool {
+        self.rating.is_none() && self.note.is_none() && self.labels.is_empty()
+    }
+
+    /// Labels trimmed, without blanks or repeats, in their first order; a
+    /// blank note is no note.
+    pub fn normalized(mut self) -> Self {
+        let mut labels: Vec<String> = Vec::new();
+        for label in self.labels {
+            let label = label.trim();
+            if !label.is_empty() && !labels.iter().any(|l| l == label) {
+                labels.push(label.to_string());
+            }
+        }
+        self.labels = labels;
+        self.note = self
+            .note
+            .map(|note| note.trim().to_string())
+            .filter(|note| !note.is_empty());
+        self
+    }
+}
+
+/// Who wrote a message. Parsing with [`FromStr`] is strict; serde keeps roles
+/// it does not know in [`MessageRole::Other`] so JSON round-trips losslessly.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum MessageRole {
-    #[serde(rename = "user")]
     User,
-    #[serde(rename = "assistant")]
     Assistant,
-    #[serde(rename = "system")]
     System,
+    Tool,
+    Other(String),
 }
 
-impl std::fmt::Display for MessageRole {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl MessageRole {
+    pub fn as_str(&self) -> &str {
         match self {
-            MessageRole::User => write!(f, "user"),
-            MessageRole::Assistant => write!(f, "assistant"),
-            MessageRole::System => write!(f, "system"),
+            MessageRole::User => "user",
+            MessageRole::Assistant => "assistant",
+            MessageRole::System => "system",
+            MessageRole::Tool => "tool",
+            MessageRole::Other(role) => role,
         }
     }
-}
 
-impl From<String> for MessageRole {
-    fn from(s: String) -> Self {
-        match s.as_str() {
-            "user" => MessageRole::User,
-            "assistant" => MessageRole::Assistant,
-            "system" => MessageRole::System,
-            _ => MessageRole::User, // Default fallback
+    /// The role as a heading in exported transcripts.
+    pub fn speaker(&self) -> &str {
+        match self {
+            MessageRole::User => "User",
+            MessageRole::Assistant => "Assistant",
+            MessageRole::System => "System",
+            MessageRole::Tool => "Tool",
+            MessageRole::Other(role) => role,
         }
     }
 }
 
-impl From<&str> for MessageRole {
-    fn from(s: &str) -> Self {
+impl std::fmt::Display for MessageRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A role string that is not one of `user`, `assistant`, `system` or `tool`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownRole(pub String);
+
+impl std::fmt::Display for UnknownRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown message role '{}'", self.0)
+    }
+}
+
+impl std::error::Error for UnknownRole {}
+
+impl FromStr for MessageRole {
+    type Err = UnknownRole;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            "user" => MessageRole::User,
-            "assistant" => MessageRole::Assistant,
-            "system" => MessageRole::System,
-            _ => MessageRole::User, // Default fallback
+            "user" => Ok(MessageRole::User),
+            "assistant" => Ok(MessageRole::Assistant),
+            "system" => Ok(MessageRole::System),
+            "tool" => Ok(MessageRole::Tool),
+            _ => Err(UnknownRole(s.to_string())),
         }
     }
 }
 
+impl TryFrom<&str> for MessageRole {
+    type Error = UnknownRole;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl TryFrom<String> for MessageRole {
+    type Error = UnknownRole;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Serialize for MessageRole {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for MessageRole {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let role = String::deserialize(deserializer)?;
+        Ok(role.parse().unwrap_or(MessageRole::Other(role)))
+    }
+}
+
+/// Any string is accepted, so the schema is a plain string with the known
+/// roles as examples rather than a closed enum.
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for MessageRole {
+    fn schema_name() -> String {
+        "MessageRole".to_string()
+    }
+
+    fn json_schema(generator: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        let mut schema = String::json_schema(generator).into_object();
+        let metadata = schema.metadata();
+        metadata.description = Some("Author of a message".to_string());
+        metadata.examples = ["user", "assistant", "system", "tool"]
+            .map(serde_json::Value::from)
+            .to_vec();
+        schema.into()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ChatRequest {
     pub message: String,
     pub model: String,
@@ -76,29 +285,380 @@ pub struct ChatRequest {
     pub max_tokens: Option<i32>,
     pub temperature: Option<f32>,
     pub stream: Option<bool>,
+    #[serde(default)]
+    pub logprobs: Option<bool>,
+    #[serde(default)]
+    pub top_logprobs: Option<u8>,
+    /// Answer with the request that would go upstream instead of sending it.
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Send even when the request could cost more than the cost threshold.
+    #[serde(default)]
+    pub confirm_cost: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ChatResponse {
     pub session_id: String,
     pub message: Message,
     pub usage: Option<UsageStats>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Token counts; ones the API leaves out count as 0.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
 pub struct UsageStats {
     pub prompt_tokens: i32,
     pub completion_tokens: i32,
     pub total_tokens: i32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Aggregated token usage for one model, as recorded in the usage ledger.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ModelUsage {
+    pub model: String,
+    pub requests: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+}
+
+/// One row of the usage ledger: the tokens of a single upstream completion.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct UsageRecord {
+    pub session_id: Option<String>,
+    pub model: String,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub created_at: DateTime<Utc>,
+    /// How long the upstream took to answer; missing on rows from older
+    /// versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    /// Why the reply ended, such as `stop` or `length` (cut off).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+}
+
+/// What the usage ledger keeps about a completion besides its tokens.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompletionStats {
+    pub latency_ms: Option<u64>,
+    pub finish_reason: Option<String>,
+}
+
+/// A stored message, for reports: when it was written and in which session.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageActivity {
+    pub session_id: String,
+    pub session_title: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// One row of `sessions list`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SessionSummary {
+    #[serde(flatten)]
+    pub session: ChatSession,
+    pub message_count: i64,
+}
+
+/// A session together with its full transcript, as written by `sessions export`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SessionExport {
+    pub session: ChatSession,
+    pub messages: Vec<Message>,
+}
+
+impl SessionExport {
+    /// Leaves out the messages a session template put there, which
+    /// `sessions export` does unless asked not to.
+    pub fn without_seeded(mut self) -> Self {
+        self.messages.retain(|message| !message.seeded);
+        self
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!(
+            "# {}\n\n- Session: {}\n- Model: {}\n- Created: {}\n",
+            self.session.title.as_deref().unwrap_or("Untitled session"),
+            self.session.id,
+            self.session.model,
+            self.session.created_at.format("%Y-%m-%d %H:%M UTC")
+        );
+
+        for message in &self.messages {
+            out.push_str(&format!(
+                "\n## {} ({}{}{}{})\n\n{}\n",
+                message.role.speaker(),
+                message.timestamp.format("%Y-%m-%d %H:%M"),
+                if message.pinned { ", pinned" } else { "" },
+                if message.is_queued() { ", queued" } else { "" },
+                if message.seeded {
+                    ", from template"
+                } else {
+                    ""
+                },
+                message.content.trim_end()
+            ));
+        }
+
+        out
+    }
+
+    /// A standalone page for reading the session in a browser. Message text
+    /// is shown as written, not rendered as markdown.
+    pub fn to_html(&self) -> String {
+        let title = escape_html(self.session.title.as_deref().unwrap_or("Untitled session"));
+        let mut out = format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <meta name="robots" content="noindex">
+    <title>{title}</title>
+    <style>
+        body {{
+            font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif;
+            max-width: 800px;
+            margin: 0 auto;
+            padding: 20px;
+            background: #f5f5f5;
+        }}
+        .message {{
+            background: white;
+            padding: 15px;
+            margin: 10px 0;
+            border-radius: 4px;
+            border-left: 4px solid #ccc;
+        }}
+        .user {{ border-left-color: #007acc; }}
+        .assistant {{ border-left-color: #2e8b57; }}
+        .meta {{ color: #666; font-size: 0.9em; }}
+        .content {{ white-space: pre-wrap; }}
+    </style>
+</head>
+<body>
+    <h1>{title}</h1>
+    <p class="meta">{model} · {created} · read-only</p>
+"#,
+            title = title,
+            model = escape_html(&self.session.model),
+            created = self.session.created_at.format("%Y-%m-%d %H:%M UTC"),
+        );
+
+        for message in &self.messages {
+            out.push_str(&format!(
+                "    <div class=\"message {}\">\n        <div class=\"meta\">{} · {}{}</div>\n        <div class=\"content\">{}</div>\n    </div>\n",
+                escape_html(message.role.as_str()),
+                escape_html(message.role.speaker()),
+                message.timestamp.format("%Y-%m-%d %H:%M"),
+                if message.pinned { " · pinned" } else { "" },
+                escape_html(message.content.trim_end())
+            ));
+        }
+
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// A read-only link to a session. Whoever holds the token can read the
+/// session and nothing else.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SessionShare {
+    pub token: String,
+    pub session_id: String,
+    pub created_at: DateTime<Utc>,
+    /// The link stops working at this time; `None` lasts until revoked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl SessionShare {
+    pub fn new(session_id: impl Into<String>, expires_at: Option<DateTime<Utc>>) -> Self {
+        Self {
+            // v4 UUIDs come from the OS random number generator
+            token: Uuid::new_v4().simple().to_string(),
+            session_id: session_id.into(),
+            created_at: Utc::now(),
+            expires_at,
+        }
+    }
+
+    pub fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// A labelled point a session can be restored to: its messages up to and
+/// including `message_id`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SessionSnapshot {
+    pub id: i64,
+    pub session_id: String,
+    pub label: String,
+    /// The newest message when the snapshot was taken; 0 for an empty
+    /// session.
+    pub message_id: i64,
+    pub created_at: DateTime<Utc>,
+    /// The snapshot whose restore took this one's later messages away, so
+    /// it can no longer be restored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub removed_by: Option<i64>,
+}
+
+/// What restoring a snapshot did.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RestoreReport {
+    pub snapshot: SessionSnapshot,
+    /// Messages written after the snapshot, now moved to the archive.
+    pub archived: usize,
+    /// Later snapshots that went with them.
+    pub removed_snapshots: Vec<i64>,
+}
+
+/// A snapshot an earlier restore went back past.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotRemoved {
+    pub snapshot_id: i64,
+    pub removed_by: i64,
+}
+
+impl std::fmt::Display for SnapshotRemoved {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Snapshot {} can no longer be restored: restoring snapshot {} removed its messages",
+            self.snapshot_id, self.removed_by
+        )
+    }
+}
+
+impl std::error::Error for SnapshotRemoved {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ApiMessage {
     pub role: String,
+    /// Empty when the API sends `null`, as for replies that only call tools.
+    #[serde(deserialize_with = "null_as_default")]
     pub content: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Reads `null` as the type's default, for fields the API sometimes nulls.
+fn null_as_default<'de, D: Deserializer<'de>, T: Default + Deserialize<'de>>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+impl ApiMessage {
+    pub fn new(role: MessageRole, content: impl Into<String>) -> Self {
+        Self {
+            role: role.to_string(),
+            content: content.into(),
+        }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new(MessageRole::User, content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new(MessageRole::Assistant, content)
+    }
+
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new(MessageRole::System, content)
+    }
+
+    pub fn is_system(&self) -> bool {
+        self.role == "system"
+    }
+}
+
+impl From<&Message> for ApiMessage {
+    fn from(message: &Message) -> Self {
+        Self::new(message.role.clone(), message.content.clone())
+    }
+}
+
+impl From<Message> for ApiMessage {
+    fn from(message: Message) -> Self {
+        Self::new(message.role, message.content)
+    }
+}
+
+/// Assembles the messages of one request: a system prompt, the prior history
+/// and the new user message, in that order.
+///
+/// The result never starts with more than one system message. An explicit
+/// [`system_prompt`](Self::system_prompt) replaces any system messages leading
+/// the history; without one, the last of them is kept.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Conversation {
+    system_prompt: Option<String>,
+    messages: Vec<ApiMessage>,
+}
+
+impl Conversation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn system_prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.system_prompt = Some(prompt.into());
+        self
+    }
+
+    /// Appends earlier messages, stored [`Message`]s or [`ApiMessage`]s alike.
+    pub fn history<M: Into<ApiMessage>>(mut self, messages: impl IntoIterator<Item = M>) -> Self {
+        self.messages.extend(messages.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn user(mut self, content: impl Into<String>) -> Self {
+        self.messages.push(ApiMessage::user(content));
+        self
+    }
+
+    pub fn into_messages(self) -> Vec<ApiMessage> {
+        let mut messages = self.messages;
+        let leading = messages.iter().take_while(|m| m.is_system()).count();
+        let inherited = messages.drain(..leading).next_back();
+        let system = self.system_prompt.map(ApiMessage::system).or(inherited);
+
+        system.into_iter().chain(messages).collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ApiChatRequest {
     pub messages: Vec<ApiMessage>,
     pub model: String,
@@ -106,32 +666,189 @@ pub struct ApiChatRequest {
     pub temperature: Option<f32>,
     pub stream: Option<bool>,
     pub system_prompt: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub logprobs: bool,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub top_logprobs: u8,
+}
+
+fn is_zero(value: &u8) -> bool {
+    *value == 0
+}
+
+impl ApiChatRequest {
+    /// Starts a request; prefer this over a struct literal, which breaks
+    /// whenever a field is added.
+    pub fn builder() -> ApiChatRequestBuilder {
+        ApiChatRequestBuilder::default()
+    }
 }
 
+/// Builds an [`ApiChatRequest`]. Unset options are left out of the request so
+/// the API applies its own defaults.
+#[derive(Debug, Default)]
+pub struct ApiChatRequestBuilder {
+    request: ApiChatRequest,
+}
+
+impl ApiChatRequestBuilder {
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.request.model = model.into();
+        self
+    }
+
+    pub fn message(mut self, message: impl Into<ApiMessage>) -> Self {
+        self.request.messages.push(message.into());
+        self
+    }
+
+    pub fn messages<M: Into<ApiMessage>>(mut self, messages: impl IntoIterator<Item = M>) -> Self {
+        self.request
+            .messages
+            .extend(messages.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn user(self, content: impl Into<String>) -> Self {
+        self.message(ApiMessage::user(content))
+    }
+
+    pub fn system_prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.request.system_prompt = Some(prompt.into());
+        self
+    }
+
+    pub fn max_tokens(mut self, max_tokens: i32) -> Self {
+        self.request.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.request.temperature = Some(temperature);
+        self
+    }
+
+    pub fn stream(mut self, stream: bool) -> Self {
+        self.request.stream = Some(stream);
+        self
+    }
+
+    pub fn logprobs(mut self, logprobs: bool) -> Self {
+        self.request.logprobs = logprobs;
+        self
+    }
+
+    /// Alternatives returned per token position; needs `logprobs(true)`.
+    pub fn top_logprobs(mut self, top_logprobs: u8) -> Self {
+        self.request.top_logprobs = top_logprobs;
+        self
+    }
+
+    pub fn build(self) -> ApiChatRequest {
+        self.request
+    }
+}
+
+/// A completion as the API returns it. Fields the API leaves out or adds are
+/// tolerated, so a change on its side doesn't break parsing.
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ApiChatResponse {
+    #[serde(default)]
     pub id: String,
+    #[serde(default)]
     pub object: String,
+    #[serde(default)]
     pub created: i64,
+    #[serde(default)]
     pub model: String,
+    #[serde(default)]
     pub choices: Vec<Choice>,
+    #[serde(default)]
     pub usage: Option<UsageStats>,
+    /// Side effects of post-processing the reply, such as files written;
+    /// filled in locally, never by the API.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub effects: Vec<String>,
+    /// How long the upstream took to answer; filled in locally.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Choice {
+    #[serde(default)]
     pub index: i32,
     pub message: Option<ApiMessage>,
     pub delta: Option<Delta>,
     pub finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<ChoiceLogprobs>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ChoiceLogprobs {
+    #[serde(default)]
+    pub content: Option<Vec<TokenLogprob>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<Vec<u8>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Delta {
     pub role: Option<String>,
     pub content: Option<String>,
 }
 
+/// One decoded server-sent event from a streaming completion. The final event
+/// usually carries only `finish_reason` and/or `usage`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct StreamChunk {
+    pub content: String,
+    pub finish_reason: Option<String>,
+    pub usage: Option<UsageStats>,
+    /// Set on every chunk after the stream was cut off and resumed: how many
+    /// follow-up requests the reply has taken so far.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resumes: Option<u32>,
+    /// On a chunk of its own after the last one when post-processors are
+    /// configured: the whole reply as it should be stored, replacing the
+    /// concatenated chunks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processed: Option<ProcessedResponse>,
+}
+
+impl StreamChunk {
+    pub fn is_empty(&self) -> bool {
+        self.content.is_empty()
+            && self.finish_reason.is_none()
+            && self.usage.is_none()
+            && self.processed.is_none()
+    }
+}
+
 impl ChatSession {
     pub fn new(model: String, title: Option<String>) -> Self {
         let now = Utc::now();
@@ -141,6 +858,8 @@ impl ChatSession {
             updated_at: now,
             model,
             title,
+            prompt_preset: None,
+            agent: None,
         }
     }
 
@@ -164,6 +883,13 @@ impl Message {
             timestamp: Utc::now(),
             model,
             tokens_used: None,
+            pinned: false,
+            status: MessageStatus::Sent,
+            resumes: None,
+            superseded_by: None,
+            seeded: false,
+            annotation: None,
+            agent: None,
         }
     }
 
@@ -178,12 +904,74 @@ impl Message {
     pub fn system(session_id: String, content: String) -> Self {
         Self::new(session_id, MessageRole::System, content, None)
     }
+
+    /// A user message written while offline, to be sent later.
+    pub fn queued(session_id: String, content: String) -> Self {
+        Self {
+            status: MessageStatus::Queued,
+            ..Self::user(session_id, content)
+        }
+    }
+
+    pub fn is_queued(&self) -> bool {
+        self.status == MessageStatus::Queued
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_session_export_markdown() {
+        let mut session = ChatSession::new("grok-3".to_string(), Some("Trip".to_string()));
+        session.created_at = DateTime::parse_from_rfc3339("2025-01-02T03:04:05Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut question = Message::user(session.id.clone(), "Where to?\n".to_string());
+        question.timestamp = session.created_at;
+        let mut answer = Message::assistant(session.id.clone(), "Lisbon".to_string(), None);
+        answer.timestamp = session.created_at;
+
+        let mut export = SessionExport {
+            session: session.clone(),
+            messages: vec![question, answer],
+        };
+
+        assert_eq!(
+            export.to_markdown(),
+            format!(
+                "# Trip\n\n- Session: {}\n- Model: grok-3\n- Created: 2025-01-02 03:04 UTC\n\
+                 \n## User (2025-01-02 03:04)\n\nWhere to?\n\
+                 \n## Assistant (2025-01-02 03:04)\n\nLisbon\n",
+                session.id
+            )
+        );
+
+        export.messages[1].pinned = true;
+        assert!(export
+            .to_markdown()
+            .ends_with("\n## Assistant (2025-01-02 03:04, pinned)\n\nLisbon\n"));
+    }
+
+    #[test]
+    fn test_session_export_html_escapes_text() {
+        let session = ChatSession::new("grok-3".to_string(), Some("<b>Trip</b>".to_string()));
+        let question = Message::user(
+            session.id.clone(),
+            "<script>alert('hi')</script> & more".to_string(),
+        );
+        let export = SessionExport {
+            session,
+            messages: vec![question],
+        };
+
+        let html = export.to_html();
+        assert!(html.contains("<title>&lt;b&gt;Trip&lt;/b&gt;</title>"));
+        assert!(html.contains("&lt;script&gt;alert(&#39;hi&#39;)&lt;/script&gt; &amp; more"));
+        assert!(!html.contains("<script>"));
+    }
+
     #[test]
     fn test_message_role_display() {
         assert_eq!(MessageRole::User.to_string(), "user");
@@ -193,10 +981,29 @@ mod tests {
 
     #[test]
     fn test_message_role_from_string() {
-        assert_eq!(MessageRole::from("user"), MessageRole::User);
-        assert_eq!(MessageRole::from("assistant"), MessageRole::Assistant);
-        assert_eq!(MessageRole::from("system"), MessageRole::System);
-        assert_eq!(MessageRole::from("unknown"), MessageRole::User); // Default fallback
+        assert_eq!("user".parse(), Ok(MessageRole::User));
+        assert_eq!("assistant".parse(), Ok(MessageRole::Assistant));
+        assert_eq!("system".parse(), Ok(MessageRole::System));
+        assert_eq!(MessageRole::try_from("tool"), Ok(MessageRole::Tool));
+
+        // Unknown roles used to silently become `User`
+        assert_eq!(
+            MessageRole::try_from("unknown".to_string()),
+            Err(UnknownRole("unknown".to_string()))
+        );
+        assert!("User".parse::<MessageRole>().is_err());
+    }
+
+    #[test]
+    fn test_message_role_serde_is_lossless() {
+        for json in [r#""user""#, r#""tool""#, r#""developer""#] {
+            let role: MessageRole = serde_json::from_str(json).unwrap();
+            assert_eq!(serde_json::to_string(&role).unwrap(), json);
+        }
+        assert_eq!(
+            serde_json::from_str::<MessageRole>(r#""developer""#).unwrap(),
+            MessageRole::Other("developer".to_string())
+        );
     }
 
     #[test]
@@ -227,4 +1034,210 @@ mod tests {
         assert!(json.contains("user"));
         assert!(json.contains("Hello"));
     }
+
+    #[test]
+    fn test_message_converts_to_api_message() {
+        let stored = Message::assistant("s1".to_string(), "Lisbon".to_string(), None);
+        assert_eq!(ApiMessage::from(&stored), ApiMessage::assistant("Lisbon"));
+        assert_eq!(ApiMessage::from(stored), ApiMessage::assistant("Lisbon"));
+    }
+
+    #[test]
+    fn test_conversation_orders_prompt_history_and_message() {
+        let history = vec![
+            Message::user("s1".to_string(), "Where to?".to_string()),
+            Message::assistant("s1".to_string(), "Lisbon".to_string(), None),
+        ];
+
+        let messages = Conversation::new()
+            .history(&history)
+            .system_prompt("Be brief.")
+            .user("And after?")
+            .into_messages();
+
+        assert_eq!(
+            messages,
+            vec![
+                ApiMessage::system("Be brief."),
+                ApiMessage::user("Where to?"),
+                ApiMessage::assistant("Lisbon"),
+                ApiMessage::user("And after?"),
+            ]
+        );
+        assert_eq!(Conversation::new().into_messages(), vec![]);
+    }
+
+    #[test]
+    fn test_conversation_keeps_a_single_system_prompt() {
+        let history = vec![
+            ApiMessage::system("stored"),
+            ApiMessage::system("stored again"),
+            ApiMessage::user("hi"),
+            ApiMessage::system("mid-conversation note"),
+        ];
+
+        let messages = Conversation::new()
+            .system_prompt("explicit")
+            .history(history.clone())
+            .into_messages();
+        assert_eq!(
+            messages,
+            vec![
+                ApiMessage::system("explicit"),
+                ApiMessage::user("hi"),
+                ApiMessage::system("mid-conversation note"),
+            ]
+        );
+
+        let messages = Conversation::new().history(history).into_messages();
+        assert_eq!(messages[0], ApiMessage::system("stored again"));
+        assert_eq!(messages.iter().filter(|m| m.is_system()).count(), 2);
+    }
+
+    #[test]
+    fn test_api_chat_request_omits_unset_logprobs() {
+        let request = ApiChatRequest {
+            messages: vec![ApiMessage {
+                role: "user".to_string(),
+                content: "Hi".to_string(),
+            }],
+            model: "grok-3".to_string(),
+            max_tokens: Some(16),
+            temperature: Some(0.5),
+            stream: Some(false),
+            system_prompt: None,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            r#"{"messages":[{"role":"user","content":"Hi"}],"model":"grok-3","max_tokens":16,"temperature":0.5,"stream":false,"system_prompt":null}"#
+        );
+
+        let request = ApiChatRequest {
+            logprobs: true,
+            top_logprobs: 3,
+            ..request
+        };
+        let json = serde_json::to_string(&request).unwrap();
+        assert!(json.ends_with(r#""system_prompt":null,"logprobs":true,"top_logprobs":3}"#));
+    }
+
+    #[test]
+    fn test_builder_serializes_like_a_struct_literal() {
+        let request = ApiChatRequest::builder()
+            .model("grok-3")
+            .user("Hi")
+            .max_tokens(16)
+            .temperature(0.5)
+            .stream(false)
+            .build();
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            r#"{"messages":[{"role":"user","content":"Hi"}],"model":"grok-3","max_tokens":16,"temperature":0.5,"stream":false,"system_prompt":null}"#
+        );
+
+        let request = ApiChatRequest::builder()
+            .messages([ApiMessage::system("Be brief."), ApiMessage::user("Hi")])
+            .model("grok-3")
+            .system_prompt("Terse")
+            .logprobs(true)
+            .top_logprobs(3)
+            .build();
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            r#"{"messages":[{"role":"system","content":"Be brief."},{"role":"user","content":"Hi"}],"model":"grok-3","max_tokens":null,"temperature":null,"stream":null,"system_prompt":"Terse","logprobs":true,"top_logprobs":3}"#
+        );
+    }
+
+    #[test]
+    fn test_stored_types_serialize_unchanged() {
+        let timestamp = DateTime::parse_from_rfc3339("2025-01-02T03:04:05Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let session = ChatSession {
+            id: "s1".to_string(),
+            created_at: timestamp,
+            updated_at: timestamp,
+            model: "grok-3".to_string(),
+            title: None,
+            prompt_preset: None,
+            agent: None,
+        };
+        let message = Message {
+            id: 7,
+            session_id: "s1".to_string(),
+            role: MessageRole::Assistant,
+            content: "Lisbon".to_string(),
+            timestamp,
+            model: Some("grok-3".to_string()),
+            tokens_used: None,
+            pinned: false,
+            status: MessageStatus::Sent,
+            resumes: None,
+            superseded_by: None,
+            seeded: false,
+            annotation: None,
+            agent: None,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&session).unwrap(),
+            r#"{"id":"s1","created_at":"2025-01-02T03:04:05Z","updated_at":"2025-01-02T03:04:05Z","model":"grok-3","title":null}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            r#"{"id":7,"session_id":"s1","role":"assistant","content":"Lisbon","timestamp":"2025-01-02T03:04:05Z","model":"grok-3","tokens_used":null}"#
+        );
+
+        let pinned = Message {
+            pinned: true,
+            ..message
+        };
+        assert!(serde_json::to_string(&pinned)
+            .unwrap()
+            .ends_with(r#""tokens_used":null,"pinned":true}"#));
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn test_json_schema_matches_serde_shape() {
+        let schema = serde_json::to_value(schemars::schema_for!(ApiChatRequest)).unwrap();
+        assert_eq!(schema["required"], serde_json::json!(["messages", "model"]));
+        assert_eq!(
+            schema["definitions"]["ApiMessage"]["required"],
+            serde_json::json!(["content", "role"])
+        );
+
+        let schema = serde_json::to_value(schemars::schema_for!(Message)).unwrap();
+        assert_eq!(schema["properties"]["timestamp"]["format"], "date-time");
+        assert_eq!(schema["definitions"]["MessageRole"]["type"], "string");
+    }
+
+    #[test]
+    fn test_choice_logprobs_deserialization() {
+        let choice: Choice = serde_json::from_str(
+            r#"{
+                "index": 0,
+                "message": {"role": "assistant", "content": "Hi"},
+                "finish_reason": "stop",
+                "logprobs": {"content": [{
+                    "token": "Hi",
+                    "logprob": -0.25,
+                    "bytes": [72, 105],
+                    "top_logprobs": [
+                        {"token": "Hi", "logprob": -0.25},
+                        {"token": "Hello", "logprob": -1.5}
+                    ]
+                }]}
+            }"#,
+        )
+        .unwrap();
+
+        let tokens = choice.logprobs.unwrap().content.unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].token, "Hi");
+        assert_eq!(tokens[0].logprob, -0.25);
+        assert_eq!(tokens[0].top_logprobs[1].token, "Hello");
+    }
 }
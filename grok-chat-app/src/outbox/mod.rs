@@ -0,0 +1,3 @@
+pub use self::outbox::*;
+#[allow(clippy::module_inception)]
+mod outbox;
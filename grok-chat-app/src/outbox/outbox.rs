@@ -0,0 +1,271 @@
+//! Messages written while offline. They are stored in their session with the
+//! status `queued` and no upstream call is made; a flush later sends them one
+//! at a time, oldest first, and stores each reply right after its prompt.
+//!
+//! Queues are per session and strictly ordered: a message is only sent once
+//! every message queued before it in the same session has its reply. Messages
+//! queued from several devices into one database simply go in stored order.
+
+use anyhow::{bail, Result};
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::client::{is_offline, ChatResponse, ChatService};
+use crate::context::{session_context, DEFAULT_TOKEN_BUDGET};
+use crate::database::Database;
+use crate::models::{Conversation, Message};
+
+/// What a flush sent and what it left queued.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FlushReport {
+    pub sent: usize,
+    /// Messages still queued afterwards, in any session.
+    pub remaining: usize,
+    /// Sessions whose queue stopped on an error; later messages in them wait.
+    pub failed: Vec<FlushFailure>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FlushFailure {
+    pub session_id: String,
+    pub error: String,
+}
+
+/// Queues messages and sends them once the API is reachable again. Cheap to
+/// clone.
+#[derive(Clone)]
+pub struct Outbox {
+    database: Database,
+    chat_service: ChatService,
+}
+
+impl Outbox {
+    pub fn new(database: Database, chat_service: ChatService) -> Self {
+        Self {
+            database,
+            chat_service,
+        }
+    }
+
+    /// Stores `text` in the session without sending it. `model` is used when
+    /// it is sent; `None` means the session's model.
+    pub async fn queue(
+        &self,
+        session_id: &str,
+        text: String,
+        model: Option<String>,
+    ) -> Result<Message> {
+        let mut message =
+            Message::queued(session_id.to_string(), self.chat_service.stored_text(text));
+        message.model = model;
+        let message = self.database.create_message(message).await?;
+        self.database.update_session(session_id, None).await?;
+        Ok(message)
+    }
+
+    /// Sends the session's queued messages in order and returns the stored
+    /// replies. Stops at the first failure, leaving that message and the ones
+    /// after it queued; [`is_offline`] tells whether retrying later may help.
+    pub async fn flush_session(&self, session_id: &str) -> Result<Vec<Message>> {
+        let Some(session) = self.database.get_session(session_id).await? else {
+            return Ok(Vec::new());
+        };
+
+        let system_prompt = self
+            .database
+            .session_prompt(session_id)
+            .await?
+            .map(|preset| preset.body);
+
+        let mut replies = Vec::new();
+        for queued in self.database.queued_messages(session_id).await? {
+            let history = self.database.get_messages(session_id).await?;
+            let messages = Conversation::new()
+                .history(session_context(&history, DEFAULT_TOKEN_BUDGET))
+                .user(queued.content.clone())
+                .into_messages();
+            let model = queued
+                .model
+                .clone()
+                .unwrap_or_else(|| session.model.clone());
+
+            let response = match self
+                .chat_service
+                .send_message(
+                    messages,
+                    model.clone(),
+                    system_prompt.clone(),
+                    None,
+                    None,
+                    false,
+                )
+                .await?
+            {
+                ChatResponse::Complete(response) => response,
+                ChatResponse::Stream(_) => bail!("Unexpected streaming response"),
+            };
+            let content = response.get_content()?;
+
+            self.database
+                .mark_message_sent(queued.id, Utc::now())
+                .await?;
+            let reply = self
+                .database
+                .create_message(Message::assistant(
+                    session_id.to_string(),
+                    content,
+                    Some(model.clone()),
+                ))
+                .await?;
+            if let Some(usage) = response.get_usage() {
+                self.database
+                    .record_usage(
+                        Some(session_id),
+                        &model,
+                        usage,
+                        &response.completion_stats(),
+                    )
+                    .await?;
+            }
+            self.database.update_session(session_id, None).await?;
+            replies.push(reply);
+        }
+        Ok(replies)
+    }
+
+    /// Flushes every session with queued messages, the longest-waiting first.
+    /// A session that fails is skipped and reported; being offline ends the
+    /// whole flush with that error.
+    pub async fn flush(&self) -> Result<FlushReport> {
+        let mut report = FlushReport::default();
+        let queued = self.queued_count().await?;
+        for session_id in self.database.sessions_with_queued().await? {
+            match self.flush_session(&session_id).await {
+                Ok(_) => {}
+                Err(e) if is_offline(&e) => {
+                    let remaining = self.queued_count().await?;
+                    return Err(e.context(format!(
+                        "API unreachable; {} message(s) still queued",
+                        remaining
+                    )));
+                }
+                Err(e) => report.failed.push(FlushFailure {
+                    session_id,
+                    error: format!("{:#}", e),
+                }),
+            }
+        }
+        report.remaining = self.queued_count().await?;
+        // Counted as a difference so sessions that stopped part-way count too
+        report.sent = queued.saturating_sub(report.remaining);
+        Ok(report)
+    }
+
+    /// Messages waiting to be sent, across all sessions.
+    pub async fn queued_count(&self) -> Result<usize> {
+        Ok(self.database.get_queued_message_count().await? as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock::{MockReply, MockUpstream};
+    use crate::config::Config;
+    use crate::models::{ChatSession, MessageRole, MessageStatus};
+
+    async fn outbox(config: &Config, dir: &std::path::Path) -> (Outbox, Database) {
+        let config = Config {
+            database_url: format!("sqlite:{}?mode=rwc", dir.join("chat.db").display()),
+            ..config.clone()
+        };
+        let database = Database::new(&config).await.unwrap();
+        let outbox = Outbox::new(database.clone(), ChatService::new(&config).unwrap());
+        (outbox, database)
+    }
+
+    #[tokio::test]
+    async fn test_flush_sends_queued_messages_in_order() {
+        let upstream = MockUpstream::start(|request| {
+            let prompt = request["messages"].as_array().unwrap().last().unwrap()["content"]
+                .as_str()
+                .unwrap()
+                .to_string();
+            MockReply::completion(&format!("re: {}", prompt))
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let (outbox, db) = outbox(&upstream.config(), dir.path()).await;
+        let session = db
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        outbox.queue(&session.id, "one".into(), None).await.unwrap();
+        outbox
+            .queue(&session.id, "two".into(), Some("grok-3-mini".into()))
+            .await
+            .unwrap();
+        assert!(upstream.requests().is_empty());
+        assert_eq!(outbox.queued_count().await.unwrap(), 2);
+
+        let report = outbox.flush().await.unwrap();
+        assert_eq!((report.sent, report.remaining), (2, 0));
+
+        let requests = upstream.requests();
+        assert_eq!(requests[0]["model"], "grok-3");
+        assert_eq!(requests[1]["model"], "grok-3-mini");
+        // The second prompt goes out with the first exchange as context
+        let contents: Vec<&str> = requests[1]["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|message| message["content"].as_str())
+            .collect();
+        assert!(contents.ends_with(&["one", "re: one", "two"]));
+
+        let stored = db.get_messages(&session.id).await.unwrap();
+        let turns: Vec<(&MessageRole, &str, MessageStatus)> = stored
+            .iter()
+            .map(|m| (&m.role, m.content.as_str(), m.status))
+            .collect();
+        assert_eq!(
+            turns,
+            [
+                (&MessageRole::User, "one", MessageStatus::Sent),
+                (&MessageRole::Assistant, "re: one", MessageStatus::Sent),
+                (&MessageRole::User, "two", MessageStatus::Sent),
+                (&MessageRole::Assistant, "re: two", MessageStatus::Sent),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_flush_while_offline_keeps_the_queue() {
+        // A port nothing listens on refuses the connection
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = Config {
+            xai_api_key: "test-key".to_string(),
+            xai_base_url: format!("http://{}/v1", listener.local_addr().unwrap()),
+            ..Config::default()
+        };
+        drop(listener);
+        let dir = tempfile::tempdir().unwrap();
+        let (outbox, db) = outbox(&config, dir.path()).await;
+        let session = db
+            .create_session(ChatSession::new("grok-3".to_string(), None))
+            .await
+            .unwrap();
+        outbox
+            .queue(&session.id, "draft".into(), None)
+            .await
+            .unwrap();
+
+        let error = outbox.flush_session(&session.id).await.unwrap_err();
+        assert!(is_offline(&error));
+        let error = outbox.flush().await.unwrap_err();
+        assert!(is_offline(&error));
+        assert!(error.to_string().contains("1 message(s) still queued"));
+        assert_eq!(outbox.queued_count().await.unwrap(), 1);
+        assert!(db.get_messages(&session.id).await.unwrap()[0].is_queued());
+    }
+}
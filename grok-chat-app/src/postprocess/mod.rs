@@ -0,0 +1,3 @@
+pub use self::postprocess::*;
+#[allow(clippy::module_inception)]
+mod postprocess;
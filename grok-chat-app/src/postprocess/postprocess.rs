@@ -0,0 +1,276 @@
+//! Transformations applied to assistant replies before they are stored or
+//! shown, such as stripping `<thinking>` blocks.
+//!
+//! A [`Pipeline`] runs its processors in order, each on the previous one's
+//! output. [`ChatService`](crate::client::ChatService) applies it to complete
+//! replies and, for streamed ones, once the stream has ended.
+
+use anyhow::{bail, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::Config;
+
+/// Names accepted in the `post_processors` setting, in no particular order.
+pub const BUILTIN_PROCESSORS: [&str; 2] = ["strip_thinking", "normalize_whitespace"];
+
+/// What a processor knows about the reply it is given.
+#[derive(Debug, Clone, Default)]
+pub struct ResponseContext {
+    pub model: String,
+    /// Whether the reply was streamed, in which case the raw text has already
+    /// been shown.
+    pub streamed: bool,
+}
+
+/// A reply after processing, with a note for each side effect (a file
+/// written, say) for the caller to report.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ProcessedResponse {
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub effects: Vec<String>,
+}
+
+impl ProcessedResponse {
+    pub fn unchanged(content: &str) -> Self {
+        Self {
+            content: content.to_string(),
+            effects: Vec::new(),
+        }
+    }
+}
+
+pub trait PostProcessor: Send + Sync {
+    fn process(&self, content: &str, ctx: &ResponseContext) -> ProcessedResponse;
+}
+
+/// Processors run in order on every reply. Cheap to clone.
+#[derive(Clone, Default)]
+pub struct Pipeline {
+    processors: Vec<Arc<dyn PostProcessor>>,
+}
+
+impl Pipeline {
+    /// The built-in processors named in `names` (see [`BUILTIN_PROCESSORS`]),
+    /// followed by a [`CodeBlockExtractor`] when `extract_code_to` is set, so
+    /// it sees the reply without thinking blocks.
+    pub fn new(names: &[String], extract_code_to: Option<&Path>) -> Result<Self> {
+        let mut pipeline = Self::default();
+        for name in names {
+            match name.as_str() {
+                "strip_thinking" => pipeline.push(ThinkTagStripper::default()),
+                "normalize_whitespace" => pipeline.push(WhitespaceNormalizer),
+                _ => bail!(
+                    "Unknown post-processor '{}' (available: {})",
+                    name,
+                    BUILTIN_PROCESSORS.join(", ")
+                ),
+            }
+        }
+        if let Some(path) = extract_code_to {
+            pipeline.push(CodeBlockExtractor::new(path));
+        }
+        Ok(pipeline)
+    }
+
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Self::new(config.post_processors(), config.extract_code_to())
+    }
+
+    /// Adds `processor` after the ones already in the pipeline.
+    pub fn push(&mut self, processor: impl PostProcessor + 'static) {
+        self.processors.push(Arc::new(processor));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.processors.is_empty()
+    }
+
+    /// Runs every processor, collecting their side effects in order.
+    pub fn process(&self, content: &str, ctx: &ResponseContext) -> ProcessedResponse {
+        let mut processed = ProcessedResponse::unchanged(content);
+        for processor in &self.processors {
+            let step = processor.process(&processed.content, ctx);
+            processed.content = step.content;
+            processed.effects.extend(step.effects);
+        }
+        processed
+    }
+}
+
+/// Removes `<think>` and `<thinking>` blocks some models put before their
+/// answer. An unclosed block is left alone rather than eating the reply.
+#[derive(Debug, Clone)]
+pub struct ThinkTagStripper {
+    pattern: Regex,
+}
+
+impl Default for ThinkTagStripper {
+    fn default() -> Self {
+        Self {
+            pattern: Regex::new(r"(?is)<think>.*?</think>|<thinking>.*?</thinking>")
+                .expect("think tag pattern compiles"),
+        }
+    }
+}
+
+impl PostProcessor for ThinkTagStripper {
+    fn process(&self, content: &str, _ctx: &ResponseContext) -> ProcessedResponse {
+        if !self.pattern.is_match(content) {
+            return ProcessedResponse::unchanged(content);
+        }
+        let stripped = self.pattern.replace_all(content, "");
+        ProcessedResponse::unchanged(stripped.trim_start())
+    }
+}
+
+/// Collapses runs of blank lines into one and drops blank lines around the
+/// reply. Fenced code blocks are kept as they are.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WhitespaceNormalizer;
+
+impl PostProcessor for WhitespaceNormalizer {
+    fn process(&self, content: &str, _ctx: &ResponseContext) -> ProcessedResponse {
+        let mut lines: Vec<&str> = Vec::new();
+        let mut in_code = false;
+        for line in content.lines() {
+            if line.trim_start().starts_with("```") {
+                in_code = !in_code;
+            }
+            let blank = line.trim().is_empty();
+            let after_blank = lines.last().is_none_or(|last| last.trim().is_empty());
+            if blank && after_blank && !in_code {
+                continue;
+            }
+            lines.push(if blank && !in_code { "" } else { line });
+        }
+        while lines.last().is_some_and(|line| line.trim().is_empty()) {
+            lines.pop();
+        }
+        ProcessedResponse::unchanged(&lines.join("\n"))
+    }
+}
+
+/// Writes the first fenced code block of a reply to a file, replacing it.
+/// The reply itself is unchanged.
+#[derive(Debug, Clone)]
+pub struct CodeBlockExtractor {
+    path: PathBuf,
+}
+
+impl CodeBlockExtractor {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl PostProcessor for CodeBlockExtractor {
+    fn process(&self, content: &str, _ctx: &ResponseContext) -> ProcessedResponse {
+        let mut processed = ProcessedResponse::unchanged(content);
+        let Some(code) = first_code_block(content) else {
+            return processed;
+        };
+        let effect = match std::fs::write(&self.path, &code) {
+            Ok(()) => {
+                let lines = code.lines().count();
+                format!(
+                    "Wrote the first code block ({} line{}) to {}",
+                    lines,
+                    if lines == 1 { "" } else { "s" },
+                    self.path.display()
+                )
+            }
+            Err(e) => format!(
+                "Could not write the first code block to {}: {}",
+                self.path.display(),
+                e
+            ),
+        };
+        processed.effects.push(effect);
+        processed
+    }
+}
+
+/// The body of the first ``` fenced block, with a trailing newline. A block
+/// the reply never closes runs to its end.
+fn first_code_block(content: &str) -> Option<String> {
+    let mut lines = content.lines();
+    lines.find(|line| line.trim_start().starts_with("```"))?;
+    let mut code = String::new();
+    for line in lines {
+        if line.trim_start().starts_with("```") {
+            break;
+        }
+        code.push_str(line);
+        code.push('\n');
+    }
+    Some(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(processor: &dyn PostProcessor, content: &str) -> ProcessedResponse {
+        processor.process(content, &ResponseContext::default())
+    }
+
+    #[test]
+    fn test_think_tags_are_stripped() {
+        let stripper = ThinkTagStripper::default();
+        assert_eq!(
+            run(&stripper, "<thinking>\nhmm\n</thinking>\n\nParis.").content,
+            "Paris."
+        );
+        assert_eq!(run(&stripper, "<THINK>a</THINK>b").content, "b");
+        // Unclosed blocks are kept so nothing is lost
+        assert_eq!(run(&stripper, "<think>cut off").content, "<think>cut off");
+    }
+
+    #[test]
+    fn test_blank_lines_collapse_outside_code() {
+        let content = "\n\nOne\n\n\n\nTwo\n```\na\n\n\nb\n```\n\n\n";
+        assert_eq!(
+            run(&WhitespaceNormalizer, content).content,
+            "One\n\nTwo\n```\na\n\n\nb\n```"
+        );
+    }
+
+    #[test]
+    fn test_code_block_is_extracted_and_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.rs");
+        let extractor = CodeBlockExtractor::new(&path);
+
+        let content = "Here:\n```rust\nfn main() {}\n```\n```\nsecond\n```";
+        let processed = run(&extractor, content);
+        assert_eq!(processed.content, content);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "fn main() {}\n");
+        assert_eq!(processed.effects.len(), 1);
+        assert!(processed.effects[0].contains("(1 line)"));
+
+        assert!(run(&extractor, "No code here").effects.is_empty());
+    }
+
+    #[test]
+    fn test_pipeline_runs_in_order_and_rejects_unknown_names() {
+        let names = ["strip_thinking", "normalize_whitespace"].map(String::from);
+        let pipeline = Pipeline::new(&names, None).unwrap();
+        let processed = pipeline.process(
+            "<think>plan</think>\n\n\nA\n\n\n\nB\n",
+            &ResponseContext::default(),
+        );
+        assert_eq!(processed.content, "A\n\nB");
+
+        let error = Pipeline::new(&["shout".to_string()], None)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("available: strip_thinking, normalize_whitespace"));
+        assert!(Pipeline::new(&[], None).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,3 @@
+pub use self::preflight::*;
+#[allow(clippy::module_inception)]
+mod preflight;
@@ -0,0 +1,384 @@
+//! Startup checks for the long-running modes (`serve` and `tui`), so a bad
+//! database path or API key is reported once, in plain words and with a fix,
+//! instead of surfacing later as a raw sqlx or reqwest error.
+
+use anyhow::Result;
+use std::fmt;
+#[cfg(feature = "server")]
+use std::path::{Path, PathBuf};
+
+use crate::client::XaiClient;
+use crate::config::{Config, MissingApiKey};
+#[cfg(feature = "server")]
+use crate::database::Database;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueKind {
+    /// The directory that should hold the database is missing or read-only.
+    DatabaseDirectory,
+    /// The database could not be opened or queried.
+    Database,
+    MissingApiKey,
+    /// The API answered 401 or 403 for the configured key.
+    RejectedApiKey,
+    /// The key could not be checked, usually because the API is unreachable.
+    Unreachable,
+}
+
+impl IssueKind {
+    fn component(self) -> &'static str {
+        match self {
+            IssueKind::DatabaseDirectory | IssueKind::Database => "Database",
+            IssueKind::MissingApiKey | IssueKind::RejectedApiKey => "API key",
+            IssueKind::Unreachable => "API",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreflightIssue {
+    pub kind: IssueKind,
+    pub problem: String,
+    pub fix: String,
+}
+
+impl PreflightIssue {
+    fn new(kind: IssueKind, problem: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            kind,
+            problem: problem.into(),
+            fix: fix.into(),
+        }
+    }
+}
+
+/// Everything the preflight found wrong, in the order it was checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreflightFailed {
+    pub issues: Vec<PreflightIssue>,
+}
+
+impl fmt::Display for PreflightFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Startup checks failed")?;
+        for issue in &self.issues {
+            write!(
+                f,
+                "\n  • {}: {}\n    Fix: {}",
+                issue.kind.component(),
+                issue.problem,
+                issue.fix
+            )?;
+        }
+        write!(f, "\n  (pass --skip-preflight to start anyway)")
+    }
+}
+
+impl std::error::Error for PreflightFailed {}
+
+/// Loads the configuration for a long-running mode and, unless
+/// `skip_preflight` is set, checks it with [`preflight`] first.
+pub async fn startup_config(skip_preflight: bool) -> Result<Config> {
+    if skip_preflight {
+        return Config::from_env();
+    }
+    let config = Config::load()?;
+    preflight(&config).await?;
+    Ok(config)
+}
+
+/// Checks that the database can be created and queried (when built with the
+/// `server` feature) and that the API key is present and accepted.
+pub async fn preflight(config: &Config) -> Result<(), PreflightFailed> {
+    let mut issues = Vec::new();
+    #[cfg(feature = "server")]
+    issues.extend(check_database(config).await);
+    issues.extend(check_api_key(config).await);
+
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(PreflightFailed { issues })
+    }
+}
+
+#[cfg(feature = "server")]
+async fn check_database(config: &Config) -> Option<PreflightIssue> {
+    let url = config.database_url();
+
+    if let Some(path) = database_file(url) {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let problem = if !dir.exists() {
+            Some("does not exist")
+        } else if !dir.is_dir() {
+            Some("is not a directory")
+        } else if !writable(&dir) {
+            Some("is not writable")
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            return Some(PreflightIssue::new(
+                IssueKind::DatabaseDirectory,
+                format!("{} (from {}) {}", dir.display(), url, problem),
+                format!(
+                    "create it with `mkdir -p {}` and make sure you can write to it, \
+                     or point DATABASE_URL at a directory you own",
+                    dir.display()
+                ),
+            ));
+        }
+    }
+
+    let opened = async { Database::new(config).await?.ping().await }.await;
+    opened.err().map(|e| {
+        PreflightIssue::new(
+            IssueKind::Database,
+            format!("cannot open {}: {:#}", url, e),
+            "check that the file is a SQLite database you can write to, \
+             or move it aside to start with an empty one",
+        )
+    })
+}
+
+/// The file behind a `sqlite:` URL, or `None` for in-memory databases and
+/// URLs that are not SQLite (opening those reports the problem).
+#[cfg(feature = "server")]
+fn database_file(url: &str) -> Option<PathBuf> {
+    let rest = url
+        .strip_prefix("sqlite://")
+        .or_else(|| url.strip_prefix("sqlite:"))?;
+    let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+    if path.is_empty() || path == ":memory:" || query.split('&').any(|p| p == "mode=memory") {
+        return None;
+    }
+    Some(PathBuf::from(path))
+}
+
+/// Whether a file can be created in `dir`. A directory without any write
+/// permission bits counts as read-only even for users who could bypass them.
+#[cfg(feature = "server")]
+fn writable(dir: &Path) -> bool {
+    let read_only = std::fs::metadata(dir).map_or(true, |meta| meta.permissions().readonly());
+    if read_only {
+        return false;
+    }
+    let probe = dir.join(format!(".grok-preflight-{}", std::process::id()));
+    match std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+    {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+async fn check_api_key(config: &Config) -> Option<PreflightIssue> {
+    if config.xai_api_key.is_empty() {
+        return Some(PreflightIssue::new(
+            IssueKind::MissingApiKey,
+            MissingApiKey.to_string(),
+            "store one with `grok-chat-app auth login`, or export XAI_API_KEY=your_api_key_here",
+        ));
+    }
+
+    match XaiClient::new(config).validate_api_key().await {
+        Ok(true) => None,
+        Ok(false) => Some(PreflightIssue::new(
+            IssueKind::RejectedApiKey,
+            format!(
+                "the key from the {} was rejected by {}",
+                config.api_key_source(),
+                config.xai_base_url()
+            ),
+            "replace it with `grok-chat-app auth login` or a new XAI_API_KEY \
+             (keys are managed at https://console.x.ai)",
+        )),
+        Err(e) => Some(PreflightIssue::new(
+            IssueKind::Unreachable,
+            format!(
+                "could not check the key with {}: {:#}",
+                config.xai_base_url(),
+                e
+            ),
+            "check your network connection and XAI_BASE_URL, \
+             or pass --skip-preflight on machines without access",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock::{MockReply, MockUpstream};
+
+    async fn accepting_upstream() -> MockUpstream {
+        MockUpstream::start(|_| {
+            MockReply::Json(200, serde_json::json!({"data": [{"id": "grok-3"}]}))
+        })
+        .await
+    }
+
+    fn kinds(result: Result<(), PreflightFailed>) -> Vec<IssueKind> {
+        result
+            .unwrap_err()
+            .issues
+            .iter()
+            .map(|issue| issue.kind)
+            .collect()
+    }
+
+    #[cfg(feature = "server")]
+    fn with_database(upstream: &MockUpstream, path: &Path) -> Config {
+        Config {
+            database_url: format!("sqlite:{}", path.display()),
+            ..upstream.config()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_passes_and_creates_the_database() {
+        let upstream = accepting_upstream().await;
+        #[cfg(feature = "server")]
+        {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("chat.db");
+            preflight(&with_database(&upstream, &path)).await.unwrap();
+            assert!(path.exists());
+        }
+        #[cfg(not(feature = "server"))]
+        preflight(&upstream.config()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_missing_api_key() {
+        let upstream = accepting_upstream().await;
+        let config = Config {
+            xai_api_key: String::new(),
+            database_url: "sqlite::memory:".to_string(),
+            ..upstream.config()
+        };
+        assert_eq!(kinds(preflight(&config).await), [IssueKind::MissingApiKey]);
+        // No point asking the API about a key that is not there
+        assert!(upstream.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rejected_api_key() {
+        let upstream = MockUpstream::start(|_| {
+            MockReply::Json(401, serde_json::json!({"error": "invalid key"}))
+        })
+        .await;
+        let config = Config {
+            database_url: "sqlite::memory:".to_string(),
+            ..upstream.config()
+        };
+        assert_eq!(kinds(preflight(&config).await), [IssueKind::RejectedApiKey]);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_api() {
+        // Nothing listens on a port that was just released
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let config = Config {
+            xai_api_key: "test-key".to_string(),
+            xai_base_url: format!("http://127.0.0.1:{}", port),
+            database_url: "sqlite::memory:".to_string(),
+            ..Config::default()
+        };
+        assert_eq!(kinds(preflight(&config).await), [IssueKind::Unreachable]);
+    }
+
+    #[cfg(feature = "server")]
+    #[tokio::test]
+    async fn test_missing_database_directory() {
+        let upstream = accepting_upstream().await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing").join("chat.db");
+        let error = preflight(&with_database(&upstream, &path))
+            .await
+            .unwrap_err();
+        assert_eq!(error.issues[0].kind, IssueKind::DatabaseDirectory);
+        assert!(error.issues[0].problem.contains("does not exist"));
+        assert!(error.to_string().contains("mkdir -p"));
+    }
+
+    #[cfg(all(feature = "server", unix))]
+    #[tokio::test]
+    async fn test_read_only_database_directory() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let upstream = accepting_upstream().await;
+        let dir = tempfile::tempdir().unwrap();
+        let locked = dir.path().join("locked");
+        std::fs::create_dir(&locked).unwrap();
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o555)).unwrap();
+
+        let result = preflight(&with_database(&upstream, &locked.join("chat.db"))).await;
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let error = result.unwrap_err();
+        assert_eq!(error.issues[0].kind, IssueKind::DatabaseDirectory);
+        assert!(error.issues[0].problem.contains("is not writable"));
+    }
+
+    #[cfg(feature = "server")]
+    #[tokio::test]
+    async fn test_unreadable_database_file() {
+        let upstream = accepting_upstream().await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chat.db");
+        std::fs::write(&path, "this is not a SQLite database, just some text").unwrap();
+
+        let error = preflight(&with_database(&upstream, &path))
+            .await
+            .unwrap_err();
+        assert_eq!(error.issues[0].kind, IssueKind::Database);
+        assert!(error.issues[0].problem.contains("cannot open"));
+    }
+
+    #[cfg(feature = "server")]
+    #[tokio::test]
+    async fn test_reports_every_problem_at_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            xai_api_key: String::new(),
+            database_url: format!("sqlite:{}/missing/chat.db", dir.path().display()),
+            ..Config::default()
+        };
+        let report = preflight(&config).await.unwrap_err();
+        assert_eq!(
+            report.issues.iter().map(|i| i.kind).collect::<Vec<_>>(),
+            [IssueKind::DatabaseDirectory, IssueKind::MissingApiKey]
+        );
+        let text = report.to_string();
+        assert!(text.starts_with("Startup checks failed\n  • Database: "));
+        assert!(text.contains("\n  • API key: "));
+        assert!(text.ends_with("--skip-preflight to start anyway)"));
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn test_database_file_from_url() {
+        assert_eq!(
+            database_file("sqlite:data/chat.db?mode=rwc"),
+            Some(PathBuf::from("data/chat.db"))
+        );
+        assert_eq!(
+            database_file("sqlite:///var/lib/chat.db"),
+            Some(PathBuf::from("/var/lib/chat.db"))
+        );
+        assert_eq!(database_file("sqlite::memory:"), None);
+        assert_eq!(database_file("sqlite:chat.db?mode=memory"), None);
+        assert_eq!(database_file("postgres://localhost/chat"), None);
+    }
+}
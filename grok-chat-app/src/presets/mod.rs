@@ -0,0 +1,3 @@
+pub use self::presets::*;
+#[allow(clippy::module_inception)]
+mod presets;
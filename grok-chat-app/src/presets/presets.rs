@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A named system prompt, such as "terse code reviewer", used in place of the
+/// configured one. Sessions remember the preset they use; the one marked
+/// `is_default` applies to sessions without one.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PromptPreset {
+    pub name: String,
+    pub body: String,
+    #[serde(default)]
+    pub is_default: bool,
+}
+
+impl PromptPreset {
+    pub fn new(name: impl Into<String>, body: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            body: body.into(),
+            is_default: false,
+        }
+    }
+}
+
+/// A preset name that matches no saved preset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownPreset {
+    pub name: String,
+    /// Names of the saved presets, sorted.
+    pub available: Vec<String>,
+}
+
+impl fmt::Display for UnknownPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.available.is_empty() {
+            return write!(
+                f,
+                "Unknown prompt preset '{}' (no presets are saved; add one with `prompts add`)",
+                self.name
+            );
+        }
+        write!(
+            f,
+            "Unknown prompt preset '{}' (available: {})",
+            self.name,
+            self.available.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownPreset {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_preset_lists_available_names() {
+        let error = UnknownPreset {
+            name: "eli5".to_string(),
+            available: vec!["reviewer".to_string(), "terse".to_string()],
+        };
+        assert_eq!(
+            error.to_string(),
+            "Unknown prompt preset 'eli5' (available: reviewer, terse)"
+        );
+
+        let none_saved = UnknownPreset {
+            available: Vec::new(),
+            ..error
+        };
+        assert!(none_saved.to_string().contains("no presets are saved"));
+    }
+
+    #[test]
+    fn test_is_default_is_optional_in_json() {
+        let preset: PromptPreset =
+            serde_json::from_str(r#"{"name": "terse", "body": "Be brief."}"#).unwrap();
+        assert_eq!(preset, PromptPreset::new("terse", "Be brief."));
+    }
+}
@@ -0,0 +1,189 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use super::{Cost, PricingTable};
+use crate::config::Config;
+
+/// What happens to a request projected to cost more than the threshold.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GuardMode {
+    /// Sent, with a warning.
+    Warn,
+    /// Sent only once confirmed.
+    #[default]
+    Block,
+    /// Sent without an estimate.
+    Off,
+}
+
+impl FromStr for GuardMode {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "warn" => Ok(GuardMode::Warn),
+            "block" => Ok(GuardMode::Block),
+            "off" => Ok(GuardMode::Off),
+            _ => Err(anyhow!(
+                "Unknown cost guard mode '{}' (available: warn, block, off)",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for GuardMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            GuardMode::Warn => "warn",
+            GuardMode::Block => "block",
+            GuardMode::Off => "off",
+        })
+    }
+}
+
+/// What a request will cost at most, worked out before it is sent: its
+/// estimated prompt tokens plus a reply as long as `max_tokens` allows.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CostEstimate {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub cost: Cost,
+}
+
+impl CostEstimate {
+    pub fn new(
+        pricing: &PricingTable,
+        model: &str,
+        prompt_tokens: usize,
+        max_tokens: Option<i32>,
+    ) -> Self {
+        let completion_tokens = max_tokens.unwrap_or(0).max(0) as usize;
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            cost: pricing.cost_for_tokens(prompt_tokens as i64, completion_tokens as i64, model),
+        }
+    }
+}
+
+/// How an estimate compares with the threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Within the threshold, unpriced, or the guard is off.
+    Send,
+    /// Over the threshold; send, but say so.
+    Warn,
+    /// Over the threshold; send only once confirmed.
+    Confirm,
+}
+
+/// Checks projected request costs against `cost_threshold_usd`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostGuard {
+    pub threshold_usd: f64,
+    pub mode: GuardMode,
+}
+
+impl CostGuard {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            threshold_usd: config.cost_threshold_usd(),
+            mode: config.cost_guard(),
+        }
+    }
+
+    pub fn is_off(&self) -> bool {
+        self.mode == GuardMode::Off
+    }
+
+    /// Models without a price are never held back; their cost is unknown.
+    pub fn check(&self, estimate: &CostEstimate) -> Verdict {
+        let over = estimate
+            .cost
+            .total_usd()
+            .is_some_and(|usd| usd > self.threshold_usd);
+        match self.mode {
+            _ if !over => Verdict::Send,
+            GuardMode::Warn => Verdict::Warn,
+            GuardMode::Block => Verdict::Confirm,
+            GuardMode::Off => Verdict::Send,
+        }
+    }
+
+    /// `Err(CostNotConfirmed)` when `estimate` needs confirming and
+    /// `confirmed` isn't set; the verdict otherwise.
+    pub fn admit(
+        &self,
+        estimate: &CostEstimate,
+        confirmed: bool,
+    ) -> Result<Verdict, CostNotConfirmed> {
+        match self.check(estimate) {
+            Verdict::Confirm if !confirmed => Err(CostNotConfirmed {
+                estimate: *estimate,
+                threshold_usd: self.threshold_usd,
+            }),
+            verdict => Ok(verdict),
+        }
+    }
+}
+
+/// A request over the cost threshold that was not confirmed, so it was not
+/// sent. Travels inside `anyhow::Error`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostNotConfirmed {
+    pub estimate: CostEstimate,
+    pub threshold_usd: f64,
+}
+
+impl fmt::Display for CostNotConfirmed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "This request could cost up to {} ({} prompt + {} reply tokens), over the ${:.2} threshold",
+            self.estimate.cost,
+            self.estimate.prompt_tokens,
+            self.estimate.completion_tokens,
+            self.threshold_usd
+        )
+    }
+}
+
+impl std::error::Error for CostNotConfirmed {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guard_holds_back_requests_over_the_threshold() {
+        let pricing = PricingTable::default();
+        // grok-4: $3 in, $15 out per million tokens
+        let small = CostEstimate::new(&pricing, "grok-4-0709", 1_000, Some(2_000));
+        let large = CostEstimate::new(&pricing, "grok-4-0709", 100_000, Some(20_000));
+        assert_eq!(large.cost.total_usd(), Some(0.6));
+        let unpriced = CostEstimate::new(&pricing, "llama", 1_000_000, None);
+
+        let guard = |mode| CostGuard {
+            threshold_usd: 0.5,
+            mode,
+        };
+        assert_eq!(guard(GuardMode::Block).check(&small), Verdict::Send);
+        assert_eq!(guard(GuardMode::Block).check(&large), Verdict::Confirm);
+        assert_eq!(guard(GuardMode::Block).check(&unpriced), Verdict::Send);
+        assert_eq!(guard(GuardMode::Warn).check(&large), Verdict::Warn);
+        assert_eq!(guard(GuardMode::Off).check(&large), Verdict::Send);
+
+        let refused = guard(GuardMode::Block).admit(&large, false).unwrap_err();
+        assert_eq!(refused.estimate, large);
+        assert_eq!(
+            guard(GuardMode::Block).admit(&large, true),
+            Ok(Verdict::Confirm)
+        );
+        assert_eq!("WARN".parse::<GuardMode>().unwrap(), GuardMode::Warn);
+        assert!("ask".parse::<GuardMode>().is_err());
+    }
+}
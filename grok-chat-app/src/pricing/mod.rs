@@ -0,0 +1,5 @@
+pub use self::guard::*;
+pub use self::pricing::*;
+mod guard;
+#[allow(clippy::module_inception)]
+mod pricing;
@@ -0,0 +1,250 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::config::Config;
+use crate::models::UsageStats;
+
+/// USD per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+// Published list prices; override with PRICING_JSON when they change.
+const BUILTIN_PRICES: &[(&str, f64, f64)] = &[
+    ("grok-4-0709", 3.00, 15.00),
+    ("grok-4-fast-reasoning", 0.20, 0.50),
+    ("grok-4-fast-non-reasoning", 0.20, 0.50),
+    ("grok-4", 3.00, 15.00),
+    ("grok-code-fast-1", 0.20, 1.50),
+    ("grok-3-mini", 0.30, 0.50),
+    ("grok-3", 3.00, 15.00),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "pricing", rename_all = "snake_case")]
+pub enum Cost {
+    Known {
+        input_usd: f64,
+        output_usd: f64,
+        total_usd: f64,
+    },
+    Unknown,
+}
+
+impl Cost {
+    pub fn zero() -> Self {
+        Cost::Known {
+            input_usd: 0.0,
+            output_usd: 0.0,
+            total_usd: 0.0,
+        }
+    }
+
+    pub fn total_usd(&self) -> Option<f64> {
+        match self {
+            Cost::Known { total_usd, .. } => Some(*total_usd),
+            Cost::Unknown => None,
+        }
+    }
+}
+
+/// Unknown pricing is contagious: a running total that includes an unpriced
+/// request can't claim to be accurate.
+impl std::ops::Add for Cost {
+    type Output = Cost;
+
+    fn add(self, other: Cost) -> Cost {
+        match (self, other) {
+            (
+                Cost::Known {
+                    input_usd: a_in,
+                    output_usd: a_out,
+                    ..
+                },
+                Cost::Known {
+                    input_usd: b_in,
+                    output_usd: b_out,
+                    ..
+                },
+            ) => Cost::Known {
+                input_usd: a_in + b_in,
+                output_usd: a_out + b_out,
+                total_usd: a_in + b_in + a_out + b_out,
+            },
+            _ => Cost::Unknown,
+        }
+    }
+}
+
+impl std::fmt::Display for Cost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Cost::Known { total_usd, .. } => write!(f, "${:.4}", total_usd),
+            Cost::Unknown => write!(f, "unknown pricing"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PricingTable {
+    prices: HashMap<String, ModelPrice>,
+}
+
+impl Default for PricingTable {
+    fn default() -> Self {
+        let prices = BUILTIN_PRICES
+            .iter()
+            .map(|(model, input, output)| {
+                (
+                    model.to_string(),
+                    ModelPrice {
+                        input_per_million: *input,
+                        output_per_million: *output,
+                    },
+                )
+            })
+            .collect();
+        Self { prices }
+    }
+}
+
+impl PricingTable {
+    /// Built-in prices, with entries from the `PRICING_JSON` file (if configured)
+    /// added or replacing them.
+    pub fn load(config: &Config) -> Result<Self> {
+        let mut table = Self::default();
+        if let Some(path) = config.pricing_json() {
+            let contents = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read pricing file {}", path))?;
+            table
+                .merge_json(&contents)
+                .with_context(|| format!("Invalid pricing file {}", path))?;
+        }
+        Ok(table)
+    }
+
+    /// Expects `{"model-id": {"input_per_million": 3.0, "output_per_million": 15.0}}`.
+    pub fn merge_json(&mut self, json: &str) -> Result<()> {
+        let overrides: HashMap<String, ModelPrice> = serde_json::from_str(json)?;
+        for (model, price) in overrides {
+            if price.input_per_million < 0.0 || price.output_per_million < 0.0 {
+                return Err(anyhow!("Negative price for model '{}'", model));
+            }
+            self.prices.insert(model, price);
+        }
+        Ok(())
+    }
+
+    /// Exact match first, then the longest known prefix (so dated or suffixed
+    /// ids like `grok-3-mini-beta` still resolve).
+    pub fn price_for(&self, model: &str) -> Option<ModelPrice> {
+        if let Some(price) = self.prices.get(model) {
+            return Some(*price);
+        }
+
+        self.prices
+            .iter()
+            .filter(|(known, _)| model.starts_with(known.as_str()))
+            .max_by_key(|(known, _)| known.len())
+            .map(|(_, price)| *price)
+    }
+
+    pub fn estimate_cost(&self, usage: &UsageStats, model: &str) -> Cost {
+        self.cost_for_tokens(
+            usage.prompt_tokens.max(0) as i64,
+            usage.completion_tokens.max(0) as i64,
+            model,
+        )
+    }
+
+    pub fn cost_for_tokens(&self, prompt_tokens: i64, completion_tokens: i64, model: &str) -> Cost {
+        match self.price_for(model) {
+            Some(price) => {
+                let input_usd = prompt_tokens as f64 * price.input_per_million / 1_000_000.0;
+                let output_usd = completion_tokens as f64 * price.output_per_million / 1_000_000.0;
+                Cost::Known {
+                    input_usd,
+                    output_usd,
+                    total_usd: input_usd + output_usd,
+                }
+            }
+            None => Cost::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(prompt: i32, completion: i32) -> UsageStats {
+        UsageStats {
+            prompt_tokens: prompt,
+            completion_tokens: completion,
+            total_tokens: prompt + completion,
+        }
+    }
+
+    #[test]
+    fn test_estimate_known_model() {
+        let table = PricingTable::default();
+        let cost = table.estimate_cost(&usage(1_000_000, 100_000), "grok-4-0709");
+        assert_eq!(
+            cost,
+            Cost::Known {
+                input_usd: 3.0,
+                output_usd: 1.5,
+                total_usd: 4.5
+            }
+        );
+        assert_eq!(cost.to_string(), "$4.5000");
+    }
+
+    #[test]
+    fn test_prefix_match_prefers_longest() {
+        let table = PricingTable::default();
+        let mini = table.price_for("grok-3-mini-beta").unwrap();
+        assert_eq!(mini.input_per_million, 0.30);
+        let full = table.price_for("grok-3-beta").unwrap();
+        assert_eq!(full.input_per_million, 3.00);
+    }
+
+    #[test]
+    fn test_unknown_model_is_not_free() {
+        let table = PricingTable::default();
+        let cost = table.estimate_cost(&usage(10, 10), "llama-3");
+        assert_eq!(cost, Cost::Unknown);
+        assert_eq!(cost.to_string(), "unknown pricing");
+        assert_eq!(cost.total_usd(), None);
+    }
+
+    #[test]
+    fn test_cost_addition() {
+        let table = PricingTable::default();
+        let a = table.estimate_cost(&usage(1_000_000, 0), "grok-3");
+        let b = table.estimate_cost(&usage(0, 1_000_000), "grok-3");
+        assert_eq!((Cost::zero() + a + b).total_usd(), Some(18.0));
+        assert_eq!(a + Cost::Unknown, Cost::Unknown);
+    }
+
+    #[test]
+    fn test_json_overrides() {
+        let mut table = PricingTable::default();
+        table
+            .merge_json(
+                r#"{"grok-3": {"input_per_million": 1.0, "output_per_million": 2.0},
+                    "custom": {"input_per_million": 0.5, "output_per_million": 0.5}}"#,
+            )
+            .unwrap();
+        assert_eq!(table.price_for("grok-3").unwrap().output_per_million, 2.0);
+        assert!(table.price_for("custom").is_some());
+
+        assert!(table.merge_json("not json").is_err());
+        assert!(table
+            .merge_json(r#"{"x": {"input_per_million": -1, "output_per_million": 0}}"#)
+            .is_err());
+    }
+}
@@ -0,0 +1,3 @@
+pub use self::redact::*;
+#[allow(clippy::module_inception)]
+mod redact;
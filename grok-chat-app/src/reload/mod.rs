@@ -0,0 +1,3 @@
+pub use self::reload::*;
+#[allow(clippy::module_inception)]
+mod reload;
@@ -0,0 +1,347 @@
+//! Applying a changed configuration to a running server. Handlers read the
+//! configuration and pricing table through [`ArcSwap`]s, so a reload takes
+//! effect from the next request on, without a restart or dropped sessions.
+//!
+//! Only some settings change this way. The rest (the bind address, the
+//! database, the API connection and what the chat service was built with)
+//! keep their old values and are reported as needing a restart.
+
+use anyhow::Result;
+use arc_swap::ArcSwap;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::audit::{Actor, AuditOp, AuditRecord};
+use crate::config::Config;
+use crate::database::Database;
+use crate::pricing::PricingTable;
+
+/// How long the watcher lets a burst of file events settle before reloading;
+/// editors often write a file in several steps.
+const SETTLE: Duration = Duration::from_millis(250);
+
+/// What a reload changed, by config file key.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReloadReport {
+    /// Settings now in effect with their new values.
+    pub reloaded: Vec<&'static str>,
+    /// Changed settings that keep their old values until the server restarts.
+    pub restart_required: Vec<&'static str>,
+}
+
+impl fmt::Display for ReloadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.reloaded.is_empty() && self.restart_required.is_empty() {
+            return write!(f, "no changes");
+        }
+        if !self.reloaded.is_empty() {
+            write!(f, "reloaded {}", self.reloaded.join(", "))?;
+        }
+        if !self.restart_required.is_empty() {
+            if !self.reloaded.is_empty() {
+                write!(f, "; ")?;
+            }
+            write!(f, "restart to apply {}", self.restart_required.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// A rejected configuration; the running one stays active.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InvalidConfig {
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for InvalidConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration: {}", self.errors.join("; "))
+    }
+}
+
+impl std::error::Error for InvalidConfig {}
+
+/// Problems with the reloadable settings that `Config::load` lets through.
+pub fn validate(config: &Config) -> Vec<String> {
+    let mut errors = Vec::new();
+    if config.max_tokens() <= 0 {
+        errors.push(format!(
+            "max_tokens must be positive, not {}",
+            config.max_tokens()
+        ));
+    }
+    if !(0.0..=2.0).contains(&config.temperature()) {
+        errors.push(format!(
+            "temperature must be between 0 and 2, not {}",
+            config.temperature()
+        ));
+    }
+    if let Err(e) = config.resolve_model(None) {
+        errors.push(format!("default_model: {}", e));
+    }
+    errors
+}
+
+/// Swaps the reloadable settings of `loaded` into `config`, and a pricing
+/// table re-read from its file into `pricing`. Settings that need a restart
+/// keep their running values. Nothing changes when `loaded` failed or is
+/// invalid.
+pub fn apply(
+    config: &ArcSwap<Config>,
+    pricing: &ArcSwap<PricingTable>,
+    loaded: Result<Config>,
+) -> std::result::Result<ReloadReport, InvalidConfig> {
+    let new = loaded.map_err(|e| InvalidConfig {
+        errors: vec![format!("{:#}", e)],
+    })?;
+    let mut errors = validate(&new);
+    let current = config.load_full();
+    // The pricing file is only ever the one the server started with
+    let table = match PricingTable::load(&current) {
+        Ok(table) => Some(table),
+        Err(e) => {
+            errors.push(format!("{:#}", e));
+            None
+        }
+    };
+    if !errors.is_empty() {
+        return Err(InvalidConfig { errors });
+    }
+
+    let mut report = changes(&current, &new);
+    if let Some(table) = table {
+        if table != **pricing.load() {
+            report.reloaded.push("pricing");
+            pricing.store(Arc::new(table));
+        }
+    }
+    let mut merged = (*current).clone();
+    merged.default_model = new.default_model;
+    merged.system_prompt = new.system_prompt;
+    merged.max_tokens = new.max_tokens;
+    merged.temperature = new.temperature;
+    merged.model_aliases = new.model_aliases;
+    merged.auto_migrate_models = new.auto_migrate_models;
+    merged.model_migrations = new.model_migrations;
+    merged.kept_generations = new.kept_generations;
+    merged.cost_threshold_usd = new.cost_threshold_usd;
+    merged.cost_guard = new.cost_guard;
+    merged.context_retry = new.context_retry;
+    merged.stream_max_secs = new.stream_max_secs;
+    merged.agents = new.agents;
+    merged.timezone = new.timezone;
+    merged.server_read_only = new.server_read_only;
+    config.store(Arc::new(merged));
+    Ok(report)
+}
+
+/// The settings that differ between `old` and `new`, split by whether a
+/// reload applies them.
+fn changes(old: &Config, new: &Config) -> ReloadReport {
+    let reloadable = [
+        ("default_model", old.default_model != new.default_model),
+        ("system_prompt", old.system_prompt != new.system_prompt),
+        ("max_tokens", old.max_tokens != new.max_tokens),
+        ("temperature", old.temperature != new.temperature),
+        ("model_aliases", old.model_aliases != new.model_aliases),
+        (
+            "auto_migrate_models",
+            old.auto_migrate_models != new.auto_migrate_models,
+        ),
+        (
+            "model_migrations",
+            old.model_migrations != new.model_migrations,
+        ),
+        (
+            "kept_generations",
+            old.kept_generations != new.kept_generations,
+        ),
+        (
+            "cost_threshold_usd",
+            old.cost_threshold_usd != new.cost_threshold_usd,
+        ),
+        ("cost_guard", old.cost_guard != new.cost_guard),
+        ("context_retry", old.context_retry != new.context_retry),
+        (
+            "stream_max_secs",
+            old.stream_max_secs != new.stream_max_secs,
+        ),
+        ("agents", old.agents != new.agents),
+        ("timezone", old.timezone != new.timezone),
+        (
+            "server_read_only",
+            old.server_read_only != new.server_read_only,
+        ),
+    ];
+    let fixed = [
+        ("api_key", old.xai_api_key != new.xai_api_key),
+        ("base_url", old.xai_base_url != new.xai_base_url),
+        ("database_url", old.database_url != new.database_url),
+        ("server_host", old.server_host != new.server_host),
+        ("server_port", old.server_port != new.server_port),
+        (
+            "request_timeout_secs",
+            old.request_timeout_secs != new.request_timeout_secs,
+        ),
+        (
+            "stream_resume_attempts",
+            old.stream_resume_attempts != new.stream_resume_attempts,
+        ),
+        ("redact", old.redact != new.redact),
+        ("redact_stored", old.redact_stored != new.redact_stored),
+        (
+            "redact_patterns",
+            old.redact_patterns != new.redact_patterns,
+        ),
+        ("model_profiles", old.model_profiles != new.model_profiles),
+        (
+            "post_processors",
+            old.post_processors != new.post_processors,
+        ),
+        (
+            "extract_code_to",
+            old.extract_code_to != new.extract_code_to,
+        ),
+        (
+            "circuit_breaker",
+            old.circuit_breaker != new.circuit_breaker,
+        ),
+        ("media_dir", old.media_dir != new.media_dir),
+        ("providers", old.providers != new.providers),
+    ];
+    let changed = |settings: &[(&'static str, bool)]| {
+        settings
+            .iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(key, _)| *key)
+            .collect()
+    };
+    ReloadReport {
+        reloaded: changed(&reloadable),
+        restart_required: changed(&fixed),
+    }
+}
+
+/// Reloads whenever the config file at `path` is written, replaced or
+/// created, printing what changed and noting it in the audit log. Watching
+/// stops when the returned watcher is dropped.
+pub fn watch(
+    path: PathBuf,
+    config: Arc<ArcSwap<Config>>,
+    pricing: Arc<ArcSwap<PricingTable>>,
+    database: Database,
+) -> Result<RecommendedWatcher> {
+    // The directory rather than the file: editors that save by renaming a new
+    // file into place would otherwise leave us watching the old one
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name = path.file_name().map(|name| name.to_os_string());
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else { return };
+        let ours = event
+            .paths
+            .iter()
+            .any(|p| p.file_name().map(|n| n.to_os_string()) == name);
+        if ours && !event.kind.is_access() {
+            let _ = tx.send(());
+        }
+    })?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+    tokio::spawn(async move {
+        while rx.recv().await.is_some() {
+            tokio::time::sleep(SETTLE).await;
+            while rx.try_recv().is_ok() {}
+            let outcome = apply(&config, &pricing, Config::load());
+            if let Ok(report) = &outcome {
+                if *report != ReloadReport::default() {
+                    let record = AuditRecord::new(AuditOp::ConfigReload).detail(report.to_string());
+                    if let Err(e) = database.record_audit(&Actor::new("watcher"), &record).await {
+                        eprintln!("Failed to write the audit log: {}", e);
+                    }
+                }
+            }
+            report_reload(&path, outcome);
+        }
+    });
+    Ok(watcher)
+}
+
+fn report_reload(path: &Path, outcome: std::result::Result<ReloadReport, InvalidConfig>) {
+    match outcome {
+        Ok(report) if report == ReloadReport::default() => {}
+        Ok(report) => {
+            println!("🔄 {} changed: {}", path.display(), report);
+        }
+        Err(e) => {
+            eprintln!("❌ {}; keeping the running configuration", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    fn live() -> (ArcSwap<Config>, ArcSwap<PricingTable>) {
+        (
+            ArcSwap::from_pointee(Config::default()),
+            ArcSwap::from_pointee(PricingTable::default()),
+        )
+    }
+
+    #[test]
+    fn test_reload_swaps_reloadable_settings_and_reports_the_rest() {
+        let (config, pricing) = live();
+        let new = Config {
+            default_model: "grok-3-mini".to_string(),
+            temperature: 0.2,
+            stream_max_secs: 30,
+            database_url: "sqlite:other.db".to_string(),
+            server_port: 8080,
+            ..Config::default()
+        };
+
+        let report = apply(&config, &pricing, Ok(new)).unwrap();
+        assert_eq!(
+            report.reloaded,
+            ["default_model", "temperature", "stream_max_secs"]
+        );
+        assert_eq!(report.restart_required, ["database_url", "server_port"]);
+
+        let running = config.load();
+        assert_eq!(running.default_model(), "grok-3-mini");
+        assert_eq!(running.temperature(), 0.2);
+        assert_eq!(running.stream_max(), Duration::from_secs(30));
+        assert_eq!(running.database_url(), Config::default().database_url());
+        assert_eq!(running.server_port(), 3000);
+    }
+
+    #[test]
+    fn test_invalid_configs_keep_the_running_one() {
+        let (config, pricing) = live();
+        let new = Config {
+            default_model: "fast".to_string(),
+            temperature: 3.5,
+            ..Config::default()
+        };
+
+        let invalid = apply(&config, &pricing, Ok(new)).unwrap_err();
+        assert_eq!(invalid.errors.len(), 2, "{:?}", invalid.errors);
+        assert!(invalid.errors[0].contains("temperature"));
+        assert!(invalid.errors[1].contains("Unknown model or alias 'fast'"));
+        assert_eq!(config.load().temperature(), Config::default().temperature());
+
+        let unreadable = apply(&config, &pricing, Err(anyhow!("bad toml"))).unwrap_err();
+        assert_eq!(unreadable.errors, ["bad toml"]);
+    }
+}
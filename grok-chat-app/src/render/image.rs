@@ -0,0 +1,145 @@
+//! Images in replies, which the terminal can't show: markdown images and
+//! bare image links are found so the terminal UI can stand a placeholder in
+//! for them and open them in the system viewer.
+
+/// File endings a bare link needs to count as an image.
+const IMAGE_EXTENSIONS: &[&str] = &[".png", ".jpg", ".jpeg", ".gif", ".webp", ".svg", ".bmp"];
+
+/// An image a message refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageRef {
+    /// The markdown alt text; empty for bare links.
+    pub alt: String,
+    /// A URL or a path.
+    pub target: String,
+}
+
+impl ImageRef {
+    /// The last path segment of the target, without any query string.
+    pub fn file_name(&self) -> &str {
+        let path = self.target.split(['?', '#']).next().unwrap_or_default();
+        path.rsplit('/')
+            .find(|segment| !segment.is_empty())
+            .unwrap_or(path)
+    }
+}
+
+/// The images `line` shows: `![alt](target)` ones, then bare links ending
+/// in an image file extension.
+pub fn find_images(line: &str) -> Vec<ImageRef> {
+    let mut images = Vec::new();
+    let mut rest = line;
+    while let Some(start) = rest.find("![") {
+        let after = &rest[start + 2..];
+        let parsed = after.split_once("](").and_then(|(alt, tail)| {
+            let end = tail.find(')')?;
+            // A title after the target: ![alt](url "title")
+            let target = tail[..end].split_whitespace().next()?;
+            Some((alt, target, &tail[end + 1..]))
+        });
+        match parsed {
+            Some((alt, target, tail)) if !alt.contains(']') => {
+                images.push(ImageRef {
+                    alt: alt.to_string(),
+                    target: target.to_string(),
+                });
+                rest = tail;
+            }
+            _ => rest = after,
+        }
+    }
+
+    for word in line.split_whitespace() {
+        let word = word.trim_matches(|c: char| matches!(c, '<' | '>' | '(' | ')' | ',' | '"'));
+        let lower = word.to_ascii_lowercase();
+        let path = lower.split(['?', '#']).next().unwrap_or_default();
+        let linked = lower.starts_with("http://") || lower.starts_with("https://");
+        if linked
+            && IMAGE_EXTENSIONS.iter().any(|ext| path.ends_with(ext))
+            && !images.iter().any(|image| image.target == word)
+            && !word.contains("](")
+        {
+            images.push(ImageRef {
+                alt: String::new(),
+                target: word.to_string(),
+            });
+        }
+    }
+    images
+}
+
+/// Width and height from the header of a PNG, GIF or JPEG file; `None` for
+/// other formats or a header cut short.
+pub fn image_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let be16 = |at: usize| Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32);
+    let le16 = |at: usize| Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32);
+    let be32 = |at: usize| Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?));
+
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some((be32(16)?, be32(20)?));
+    }
+    if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        return Some((le16(6)?, le16(8)?));
+    }
+    if bytes.starts_with(&[0xFF, 0xD8]) {
+        // Walk the segments to the frame header
+        let mut at = 2;
+        while *bytes.get(at)? == 0xFF {
+            let marker = *bytes.get(at + 1)?;
+            let is_frame = matches!(marker, 0xC0..=0xCF) && !matches!(marker, 0xC4 | 0xC8 | 0xCC);
+            if is_frame {
+                return Some((be16(at + 7)?, be16(at + 5)?));
+            }
+            at += 2 + be16(at + 2)? as usize;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_images_are_found_in_markdown_and_bare_links() {
+        let images = find_images(
+            "See ![a chart](https://x.ai/c.png \"Chart\") and https://x.ai/photo.JPG?s=2, \
+             not https://x.ai/page or [a link](https://x.ai/d.png).",
+        );
+        assert_eq!(
+            images,
+            [
+                ImageRef {
+                    alt: "a chart".to_string(),
+                    target: "https://x.ai/c.png".to_string(),
+                },
+                ImageRef {
+                    alt: String::new(),
+                    target: "https://x.ai/photo.JPG?s=2".to_string(),
+                },
+            ]
+        );
+        assert_eq!(images[1].file_name(), "photo.JPG");
+        assert!(find_images("an ![unclosed](image").is_empty());
+    }
+
+    #[test]
+    fn test_dimensions_come_from_image_headers() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend(640u32.to_be_bytes());
+        png.extend(480u32.to_be_bytes());
+        assert_eq!(image_dimensions(&png), Some((640, 480)));
+
+        let gif = b"GIF89a\x20\x03\x58\x02";
+        assert_eq!(image_dimensions(gif), Some((800, 600)));
+
+        // An APP0 segment, then the baseline frame header
+        let jpeg = [
+            0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, 0xFF, 0xC0, 0x00, 0x11, 0x08, 0x01,
+            0x2C, 0x01, 0x90,
+        ];
+        assert_eq!(image_dimensions(&jpeg), Some((400, 300)));
+        assert_eq!(image_dimensions(b"plain text"), None);
+        assert_eq!(image_dimensions(&png[..18]), None);
+    }
+}
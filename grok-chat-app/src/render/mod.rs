@@ -0,0 +1,7 @@
+pub use self::image::*;
+pub use self::render::*;
+pub use self::table::*;
+mod image;
+#[allow(clippy::module_inception)]
+mod render;
+mod table;
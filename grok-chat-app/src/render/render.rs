@@ -0,0 +1,277 @@
+//! A small markdown-to-ANSI renderer for replies printed by the plain CLI.
+//!
+//! It works line by line so streamed replies can be styled as each line
+//! completes. Only the constructs models commonly emit are handled: headings,
+//! bullets, block quotes, rules, code fences and bold/italic/code spans.
+
+const BOLD: &str = "\x1b[1m";
+const ITALIC: &str = "\x1b[3m";
+const UNDERLINE: &str = "\x1b[4m";
+const DIM: &str = "\x1b[2m";
+const CYAN: &str = "\x1b[36m";
+const BOLD_OFF: &str = "\x1b[22m";
+const ITALIC_OFF: &str = "\x1b[23m";
+const COLOR_OFF: &str = "\x1b[39m";
+const RESET: &str = "\x1b[0m";
+
+/// Whether the user opted out of colors (https://no-color.org).
+pub fn no_color() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+/// A cheap check for markdown syntax, so plain prose is printed untouched.
+pub fn looks_like_markdown(text: &str) -> bool {
+    text.contains("**")
+        || text.contains('`')
+        || text.lines().any(|line| {
+            let line = line.trim_start();
+            heading_level(line).is_some()
+                || list_marker(line).is_some()
+                || line.starts_with("> ")
+                || line.starts_with("~~~")
+        })
+}
+
+/// Renders a complete reply.
+pub fn render_markdown(text: &str) -> String {
+    let mut renderer = MarkdownRenderer::new();
+    let mut rendered = renderer.push(text);
+    rendered.push_str(&renderer.finish());
+    rendered
+}
+
+/// Renders streamed text one completed line at a time, remembering whether it
+/// is inside a code fence between lines.
+#[derive(Debug, Default)]
+pub struct MarkdownRenderer {
+    pending: String,
+    fence: Option<String>,
+}
+
+impl MarkdownRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds a delta and returns the styled form of every line it completes,
+    /// each terminated by a newline. A trailing partial line is held back.
+    pub fn push(&mut self, delta: &str) -> String {
+        self.pending.push_str(delta);
+
+        let mut rendered = String::new();
+        while let Some(end) = self.pending.find('\n') {
+            let line: String = self.pending.drain(..=end).collect();
+            rendered.push_str(&self.render_line(line.trim_end_matches(['\n', '\r'])));
+            rendered.push('\n');
+        }
+        rendered
+    }
+
+    /// Renders whatever follows the last newline.
+    pub fn finish(&mut self) -> String {
+        if self.pending.is_empty() {
+            return String::new();
+        }
+        let line = std::mem::take(&mut self.pending);
+        self.render_line(&line)
+    }
+
+    fn render_line(&mut self, line: &str) -> String {
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+
+        if let Some(fence) = &self.fence {
+            if closes_fence(trimmed, fence) {
+                self.fence = None;
+                return format!("{}└─{}", DIM, RESET);
+            }
+            return format!("{}│ {}{}", DIM, line, RESET);
+        }
+
+        if let Some((fence, language)) = opens_fence(trimmed) {
+            self.fence = Some(fence);
+            let label = if language.is_empty() {
+                "code"
+            } else {
+                language
+            };
+            return format!("{}┌─ {}{}", DIM, label, RESET);
+        }
+
+        if let Some(level) = heading_level(trimmed) {
+            let title = render_inline(trimmed[level..].trim());
+            let underline = if level == 1 { UNDERLINE } else { "" };
+            return format!("{}{}{}{}", BOLD, underline, title, RESET);
+        }
+
+        if is_rule(trimmed) {
+            return format!("{}{}{}", DIM, "─".repeat(40), RESET);
+        }
+
+        if let Some(quote) = trimmed.strip_prefix('>') {
+            return format!(
+                "{}{}│{} {}",
+                indent,
+                DIM,
+                RESET,
+                render_inline(quote.trim_start())
+            );
+        }
+
+        if let Some(marker) = list_marker(trimmed) {
+            let item = render_inline(&trimmed[marker.len()..]);
+            return match marker {
+                "- " | "* " | "+ " if indent.is_empty() => format!("  • {}", item),
+                "- " | "* " | "+ " => format!("  {}◦ {}", indent, item),
+                number => format!("  {}{}{}", indent, number, item),
+            };
+        }
+
+        render_inline(line)
+    }
+}
+
+fn heading_level(line: &str) -> Option<usize> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    let valid = (1..=6).contains(&level) && line[level..].starts_with(' ');
+    valid.then_some(level)
+}
+
+/// The bullet or number (with its trailing space) that starts a list item.
+fn list_marker(line: &str) -> Option<&str> {
+    if ["- ", "* ", "+ "].iter().any(|bullet| line.starts_with(bullet)) {
+        return Some(&line[..2]);
+    }
+
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    let rest = &line[digits..];
+    (digits > 0 && (rest.starts_with(". ") || rest.starts_with(") ")))
+        .then(|| &line[..digits + 2])
+}
+
+fn is_rule(line: &str) -> bool {
+    let marks: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    marks.len() >= 3
+        && ['-', '*', '_']
+            .iter()
+            .any(|mark| marks.chars().all(|c| c == *mark))
+}
+
+/// The fence string and language label of a line opening a code block.
+fn opens_fence(line: &str) -> Option<(String, &str)> {
+    let mark = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let length = line.chars().take_while(|c| *c == mark).count();
+    let info = line[length..].trim();
+    if length < 3 || (mark == '`' && info.contains('`')) {
+        return None;
+    }
+    Some((line[..length].to_string(), info))
+}
+
+fn closes_fence(line: &str, fence: &str) -> bool {
+    let mark = fence.chars().next().unwrap_or('`');
+    let length = line.chars().take_while(|c| *c == mark).count();
+    length >= fence.len() && line[length..].trim().is_empty()
+}
+
+/// Styles `code`, **bold**/__bold__ and *italic*/_italic_ spans. Underscores
+/// only count at word boundaries so snake_case names survive.
+fn render_inline(text: &str) -> String {
+    let mut rendered = String::with_capacity(text.len());
+    let mut index = 0;
+
+    'outer: while index < text.len() {
+        let rest = &text[index..];
+        for marker in ["`", "**", "__", "*", "_"] {
+            if let Some(inner) = span(text, index, marker) {
+                let styled = match marker {
+                    "`" => format!("{}{}{}", CYAN, inner, COLOR_OFF),
+                    "**" | "__" => format!("{}{}{}", BOLD, render_inline(inner), BOLD_OFF),
+                    _ => format!("{}{}{}", ITALIC, render_inline(inner), ITALIC_OFF),
+                };
+                rendered.push_str(&styled);
+                index += inner.len() + 2 * marker.len();
+                continue 'outer;
+            }
+        }
+
+        let next = rest.chars().next().map_or(1, char::len_utf8);
+        rendered.push_str(&rest[..next]);
+        index += next;
+    }
+
+    rendered
+}
+
+/// The contents of a `marker`-delimited span starting at `index`, if any.
+fn span<'a>(text: &'a str, index: usize, marker: &str) -> Option<&'a str> {
+    let rest = text[index..].strip_prefix(marker)?;
+    let end = rest.find(marker)?;
+    let inner = &rest[..end];
+    let after = rest[end + marker.len()..].chars().next();
+
+    if inner.is_empty() {
+        return None;
+    }
+    if marker == "`" {
+        return Some(inner);
+    }
+    if inner.starts_with(char::is_whitespace) || inner.ends_with(char::is_whitespace) {
+        return None;
+    }
+    if marker.starts_with('_') {
+        let before = text[..index].chars().next_back();
+        if before.is_some_and(char::is_alphanumeric) || after.is_some_and(char::is_alphanumeric) {
+            return None;
+        }
+    }
+    Some(inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inline_spans_are_styled() {
+        assert_eq!(
+            render_inline("a **bold** and *soft* `x_y`"),
+            "a \x1b[1mbold\x1b[22m and \x1b[3msoft\x1b[23m \x1b[36mx_y\x1b[39m"
+        );
+        // snake_case and stray asterisks are left alone
+        assert_eq!(render_inline("call my_long_name * 2"), "call my_long_name * 2");
+    }
+
+    #[test]
+    fn test_blocks_render_with_indentation_and_fences() {
+        let rendered = render_markdown("# Title\n- one\n  - two\n```rust\nlet x = *y;\n```\n");
+
+        assert_eq!(
+            rendered,
+            "\x1b[1m\x1b[4mTitle\x1b[0m\n  \
+             • one\n    \
+             ◦ two\n\
+             \x1b[2m┌─ rust\x1b[0m\n\
+             \x1b[2m│ let x = *y;\x1b[0m\n\
+             \x1b[2m└─\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn test_streamed_lines_render_as_they_complete() {
+        let mut renderer = MarkdownRenderer::new();
+
+        assert_eq!(renderer.push("## He"), "");
+        assert_eq!(renderer.push("llo\nmore "), "\x1b[1mHello\x1b[0m\n");
+        assert_eq!(renderer.push("**text"), "");
+        assert_eq!(renderer.finish(), "more **text");
+        assert_eq!(renderer.finish(), "");
+    }
+
+    #[test]
+    fn test_markdown_detection() {
+        assert!(looks_like_markdown("Steps:\n1. install\n2. run"));
+        assert!(looks_like_markdown("Use `cargo test`."));
+        assert!(!looks_like_markdown("Paris is the capital of France."));
+    }
+}
@@ -0,0 +1,263 @@
+//! GitHub-style markdown tables, laid out with box-drawing characters for
+//! the terminal UI. A table too wide for the terminal falls back to one
+//! `header: value` line per cell, wrapped, so nothing is cut off.
+
+/// How a column's cells line up, from the colons of the delimiter row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    pub headers: Vec<String>,
+    pub alignments: Vec<Alignment>,
+    /// As many cells as there are headers: short rows are padded with empty
+    /// cells and long ones cut, as GitHub does.
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    /// The table starting at `lines[0]`, and how many lines it spans. A table
+    /// needs a header row followed by a delimiter row with as many cells.
+    pub fn parse(lines: &[&str]) -> Option<(Table, usize)> {
+        let headers = split_row(lines.first()?)?;
+        let alignments = split_row(lines.get(1)?)?
+            .iter()
+            .map(|cell| delimiter_alignment(cell))
+            .collect::<Option<Vec<_>>>()?;
+        if alignments.len() != headers.len() {
+            return None;
+        }
+
+        let mut rows = Vec::new();
+        for line in &lines[2..] {
+            let Some(mut row) = split_row(line) else {
+                break;
+            };
+            row.resize(headers.len(), String::new());
+            rows.push(row);
+        }
+        let spanned = rows.len() + 2;
+        Some((
+            Table {
+                headers,
+                alignments,
+                rows,
+            },
+            spanned,
+        ))
+    }
+
+    /// The table in at most `width` columns: boxed with aligned columns when
+    /// it fits, or each row as wrapped `header: value` lines when it doesn't.
+    pub fn render(&self, width: usize) -> Vec<String> {
+        let widths: Vec<usize> = (0..self.headers.len())
+            .map(|column| {
+                std::iter::once(&self.headers)
+                    .chain(&self.rows)
+                    .map(|row| row[column].chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        // "│ " before each cell, " " after it, and the closing "│"
+        let boxed = widths.iter().map(|w| w + 3).sum::<usize>() + 1;
+        if boxed > width {
+            return self.render_narrow(width);
+        }
+
+        let rule = |left: &str, middle: &str, right: &str| {
+            let segments: Vec<String> = widths.iter().map(|w| "─".repeat(w + 2)).collect();
+            format!("{}{}{}", left, segments.join(middle), right)
+        };
+        let line = |cells: &[String], header: bool| {
+            let cells: Vec<String> = cells
+                .iter()
+                .zip(&widths)
+                .zip(&self.alignments)
+                .map(|((cell, &width), &alignment)| {
+                    let alignment = if header { Alignment::Left } else { alignment };
+                    format!(" {} ", pad(cell, width, alignment))
+                })
+                .collect();
+            format!("│{}│", cells.join("│"))
+        };
+
+        let mut lines = vec![rule("┌", "┬", "┐"), line(&self.headers, true)];
+        lines.push(rule("├", "┼", "┤"));
+        lines.extend(self.rows.iter().map(|row| line(row, false)));
+        lines.push(rule("└", "┴", "┘"));
+        lines
+    }
+
+    fn render_narrow(&self, width: usize) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (index, row) in self.rows.iter().enumerate() {
+            if index > 0 {
+                lines.push("─".repeat(width.min(20)));
+            }
+            for (header, cell) in self.headers.iter().zip(row) {
+                lines.extend(wrap(&format!("{}: {}", header, cell), width));
+            }
+        }
+        if self.rows.is_empty() {
+            lines.extend(wrap(&self.headers.join(" · "), width));
+        }
+        lines
+    }
+}
+
+/// The cells of a table row: the line split at pipes, leaving out the outer
+/// ones and any escaped as `\|`. `None` for a line without a pipe.
+pub fn split_row(line: &str) -> Option<Vec<String>> {
+    let line = line.trim();
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut pipes = 0;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'|') => {
+                cell.push('|');
+                chars.next();
+            }
+            '|' => {
+                pipes += 1;
+                cells.push(std::mem::take(&mut cell));
+            }
+            c => cell.push(c),
+        }
+    }
+    if pipes == 0 {
+        return None;
+    }
+    cells.push(cell);
+    if line.starts_with('|') {
+        cells.remove(0);
+    }
+    if line.ends_with('|') && !line.ends_with("\\|") {
+        cells.pop();
+    }
+    Some(cells.iter().map(|cell| cell.trim().to_string()).collect())
+}
+
+/// The alignment a delimiter cell such as `:---:` asks for, or `None` for
+/// a cell that isn't one.
+fn delimiter_alignment(cell: &str) -> Option<Alignment> {
+    let dashes = cell.trim_start_matches(':').trim_end_matches(':');
+    if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+        return None;
+    }
+    Some(match (cell.starts_with(':'), cell.ends_with(':')) {
+        (true, true) => Alignment::Center,
+        (false, true) => Alignment::Right,
+        _ => Alignment::Left,
+    })
+}
+
+fn pad(text: &str, width: usize, alignment: Alignment) -> String {
+    let gap = width.saturating_sub(text.chars().count());
+    let (before, after) = match alignment {
+        Alignment::Left => (0, gap),
+        Alignment::Right => (gap, 0),
+        Alignment::Center => (gap / 2, gap - gap / 2),
+    };
+    format!("{}{}{}", " ".repeat(before), text, " ".repeat(after))
+}
+
+/// Breaks `text` into lines of at most `width` characters, at spaces where
+/// it can.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split(' ') {
+        let fits = line.chars().count() + 1 + word.chars().count() <= width;
+        if !line.is_empty() && !fits {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+        while line.chars().count() > width {
+            let head: String = line.chars().take(width).collect();
+            line = line.chars().skip(width).collect();
+            lines.push(head);
+        }
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tables_parse_ragged_rows_and_escaped_pipes() {
+        let lines = [
+            "| Flag | Meaning | Default |",
+            "|:-----|:-------:|--------:|",
+            "| `-a` | a \\| b |",
+            "| `-b` | both | yes | extra |",
+            "not a table",
+        ];
+        let (table, spanned) = Table::parse(&lines).unwrap();
+        assert_eq!(spanned, 4);
+        assert_eq!(table.headers, ["Flag", "Meaning", "Default"]);
+        assert_eq!(
+            table.alignments,
+            [Alignment::Left, Alignment::Center, Alignment::Right]
+        );
+        assert_eq!(table.rows[0], ["`-a`", "a | b", ""]);
+        assert_eq!(table.rows[1], ["`-b`", "both", "yes"]);
+
+        // Rows without outer pipes, and a delimiter row that doesn't match
+        let (table, _) = Table::parse(&["a | b", "--- | ---", "1 | 2"]).unwrap();
+        assert_eq!(table.rows, [["1", "2"]]);
+        assert!(Table::parse(&["| a | b |", "| --- |", "| 1 | 2 |"]).is_none());
+        assert!(Table::parse(&["| a | b |", "| x | y |"]).is_none());
+        assert!(Table::parse(&["just text", "---"]).is_none());
+    }
+
+    #[test]
+    fn test_tables_render_boxed_or_wrapped_when_narrow() {
+        let (table, _) = Table::parse(&[
+            "| Model | Price |",
+            "|-------|------:|",
+            "| grok-3 | $3 |",
+            "| grok-3-mini | $0.30 |",
+        ])
+        .unwrap();
+
+        assert_eq!(
+            table.render(80),
+            [
+                "┌─────────────┬───────┐",
+                "│ Model       │ Price │",
+                "├─────────────┼───────┤",
+                "│ grok-3      │    $3 │",
+                "│ grok-3-mini │ $0.30 │",
+                "└─────────────┴───────┘",
+            ]
+        );
+        assert_eq!(
+            table.render(12),
+            [
+                "Model:",
+                "grok-3",
+                "Price: $3",
+                "────────────",
+                "Model:",
+                "grok-3-mini",
+                "Price: $0.30",
+            ]
+        );
+    }
+}
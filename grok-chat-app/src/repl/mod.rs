@@ -0,0 +1,3 @@
+pub use self::repl::*;
+#[allow(clippy::module_inception)]
+mod repl;
@@ -0,0 +1,80 @@
+use rustyline::history::FileHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Completer, Editor, Helper, Highlighter, Hinter};
+use std::path::PathBuf;
+
+use crate::config::config_dir;
+
+pub type LineEditor = Editor<ReplHelper, FileHistory>;
+
+/// Keeps reading lines while the input is unfinished: a trailing backslash or
+/// an unclosed ``` fence continues on the next line.
+#[derive(Completer, Helper, Highlighter, Hinter)]
+pub struct ReplHelper;
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if is_incomplete(ctx.input()) {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+pub fn is_incomplete(input: &str) -> bool {
+    let open_fences = input
+        .lines()
+        .filter(|line| line.trim_start().starts_with("```"))
+        .count();
+    input.ends_with('\\') || open_fences % 2 == 1
+}
+
+/// Removes the backslashes that marked continued lines.
+pub fn join_continuations(input: &str) -> String {
+    input.replace("\\\n", "\n")
+}
+
+pub fn history_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("history.txt"))
+}
+
+/// Creates a line editor with the persistent history loaded (if there is any).
+pub fn line_editor() -> rustyline::Result<LineEditor> {
+    let mut editor = LineEditor::new()?;
+    editor.set_helper(Some(ReplHelper));
+    if let Some(path) = history_path() {
+        let _ = editor.load_history(&path);
+    }
+    Ok(editor)
+}
+
+/// Writes the history back, creating the config directory on first use.
+pub fn save_history(editor: &mut LineEditor) -> anyhow::Result<()> {
+    if let Some(path) = history_path() {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        editor.save_history(&path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_incomplete_input() {
+        assert!(!is_incomplete("hello"));
+        assert!(is_incomplete("first line \\"));
+        assert!(is_incomplete("look at this:\n```rust\nfn main() {}"));
+        assert!(!is_incomplete("look at this:\n```rust\nfn main() {}\n```"));
+    }
+
+    #[test]
+    fn test_join_continuations() {
+        assert_eq!(join_continuations("one \\\ntwo"), "one \ntwo");
+        assert_eq!(join_continuations("a\\b"), "a\\b");
+    }
+}
@@ -0,0 +1,3 @@
+pub use self::replay::*;
+#[allow(clippy::module_inception)]
+mod replay;
@@ -0,0 +1,403 @@
+//! Replays an exported session against a model and compares the new replies
+//! with the recorded ones, to catch behavior changes in prompt chains.
+//!
+//! User turns are re-sent in order, one request at a time, and each new reply
+//! becomes the context for the next turn, so the replay follows the path the
+//! new model would actually take.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::client::{ChatResponse, ChatService};
+use crate::context::{estimate_message_tokens, estimate_tokens};
+use crate::models::{
+    ApiChatRequest, ApiMessage, Conversation, Message, MessageRole, SessionExport, UsageStats,
+};
+use crate::pricing::{Cost, CostGuard, PricingTable};
+
+/// How to replay; the CLI and HTTP API fill the defaults in from `Config`,
+/// except for sampling, left to the service when not given.
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+    pub model: String,
+    /// Used when the export does not start with its own system message.
+    pub system_prompt: String,
+    pub max_tokens: Option<i32>,
+    pub temperature: Option<f32>,
+    /// The replay fails when any turn scores below this similarity.
+    pub fail_threshold: Option<f64>,
+    /// Checked before each turn is sent: a turn over the threshold stops the
+    /// replay with `CostNotConfirmed` unless `confirm_cost`. `None` sends
+    /// every turn.
+    pub cost_guard: Option<CostGuard>,
+    pub confirm_cost: bool,
+}
+
+/// One user turn and the replies it got before and now.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayTurn {
+    /// 1-based position among the user turns.
+    pub turn: usize,
+    pub prompt: String,
+    /// `None` when the export has no reply to this prompt; such turns are
+    /// replayed for context but not scored.
+    pub original: Option<String>,
+    pub replayed: String,
+    pub similarity: Option<f64>,
+    /// Completion tokens. Estimated from the text when the export lacks them.
+    pub original_tokens: i64,
+    pub replayed_tokens: i64,
+    pub token_delta: i64,
+    /// Both costs assume the replayed prompt size, so the delta reflects reply
+    /// length and model price.
+    pub original_cost: Cost,
+    pub replayed_cost: Cost,
+    pub cost_delta_usd: Option<f64>,
+    pub usage: Option<UsageStats>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayReport {
+    pub session_id: String,
+    pub original_model: String,
+    pub model: String,
+    pub turns: Vec<ReplayTurn>,
+    /// Over scored turns; 1.0 when there are none.
+    pub mean_similarity: f64,
+    pub min_similarity: f64,
+    pub token_delta: i64,
+    pub cost_delta_usd: Option<f64>,
+    pub fail_threshold: Option<f64>,
+    pub passed: bool,
+}
+
+/// A replay whose similarity fell below `--fail-threshold`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplayRegression {
+    pub min_similarity: f64,
+    pub threshold: f64,
+}
+
+impl fmt::Display for ReplayRegression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Replay similarity {:.2} is below the threshold {:.2}",
+            self.min_similarity, self.threshold
+        )
+    }
+}
+
+impl std::error::Error for ReplayRegression {}
+
+impl ReplayReport {
+    /// The regression to report when the replay did not pass.
+    pub fn regression(&self) -> Option<ReplayRegression> {
+        match self.fail_threshold {
+            Some(threshold) if !self.passed => Some(ReplayRegression {
+                min_similarity: self.min_similarity,
+                threshold,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// 1.0 for identical texts down to 0.0 for nothing in common: one minus the
+/// word-level edit distance divided by the longer text's word count.
+/// Whitespace and line wrapping differences are ignored.
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<&str> = a.split_whitespace().collect();
+    let b: Vec<&str> = b.split_whitespace().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - levenshtein(&a, &b) as f64 / longest as f64
+}
+
+fn levenshtein<T: PartialEq>(a: &[T], b: &[T]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, left) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, right) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(left != right);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
+
+/// Re-sends every user turn of `export` and builds the comparison report.
+pub async fn replay(
+    service: &ChatService,
+    pricing: &PricingTable,
+    export: &SessionExport,
+    options: &ReplayOptions,
+) -> Result<ReplayReport> {
+    if !export
+        .messages
+        .iter()
+        .any(|message| message.role == MessageRole::User)
+    {
+        bail!(
+            "Session '{}' has no user messages to replay",
+            export.session.id
+        );
+    }
+
+    let has_system_prompt = export
+        .messages
+        .first()
+        .is_some_and(|message| message.role == MessageRole::System);
+    let original_model = &export.session.model;
+
+    let mut history: Vec<ApiMessage> = Vec::new();
+    let mut turns = Vec::new();
+    let mut messages = export.messages.iter().peekable();
+
+    while let Some(message) = messages.next() {
+        if message.role != MessageRole::User {
+            history.push(message.into());
+            continue;
+        }
+        let original = messages.next_if(|next| next.role == MessageRole::Assistant);
+        history.push(message.into());
+
+        let mut conversation = Conversation::new();
+        if !has_system_prompt {
+            conversation = conversation.system_prompt(&options.system_prompt);
+        }
+        let request_messages = conversation.history(history.clone()).into_messages();
+        let estimated_prompt_tokens = estimate_message_tokens(&request_messages) as i64;
+
+        let request = ApiChatRequest {
+            max_tokens: options.max_tokens,
+            temperature: options.temperature,
+            ..ApiChatRequest::builder()
+                .messages(request_messages)
+                .model(&options.model)
+                .stream(false)
+                .build()
+        };
+        if let Some(guard) = &options.cost_guard {
+            guard.admit(
+                &service.estimate_cost(&request, pricing),
+                options.confirm_cost,
+            )?;
+        }
+        let response = match service.send_request(request, None).await? {
+            ChatResponse::Complete(response) => response,
+            ChatResponse::Stream(_) => bail!("Expected a complete response"),
+        };
+        let replayed = response.get_content()?;
+        let usage = response.get_usage().cloned();
+
+        turns.push(compare_turn(
+            turns.len() + 1,
+            message,
+            original,
+            replayed.clone(),
+            usage,
+            estimated_prompt_tokens,
+            (pricing, original_model, &options.model),
+        ));
+        history.push(ApiMessage::assistant(replayed));
+    }
+
+    Ok(summarize(export, options, turns))
+}
+
+fn compare_turn(
+    turn: usize,
+    prompt: &Message,
+    original: Option<&Message>,
+    replayed: String,
+    usage: Option<UsageStats>,
+    estimated_prompt_tokens: i64,
+    (pricing, original_model, model): (&PricingTable, &str, &str),
+) -> ReplayTurn {
+    let original_tokens = original.map_or(0, |message| match message.tokens_used {
+        Some(tokens) => i64::from(tokens),
+        None => estimate_tokens(&message.content) as i64,
+    });
+    let (prompt_tokens, replayed_tokens) = match &usage {
+        Some(usage) => (
+            i64::from(usage.prompt_tokens),
+            i64::from(usage.completion_tokens),
+        ),
+        None => (estimated_prompt_tokens, estimate_tokens(&replayed) as i64),
+    };
+
+    let original_cost = pricing.cost_for_tokens(prompt_tokens, original_tokens, original_model);
+    let replayed_cost = pricing.cost_for_tokens(prompt_tokens, replayed_tokens, model);
+    let cost_delta_usd = replayed_cost
+        .total_usd()
+        .zip(original_cost.total_usd())
+        .map(|(replayed, original)| replayed - original);
+
+    ReplayTurn {
+        turn,
+        prompt: prompt.content.clone(),
+        similarity: original.map(|message| similarity(&message.content, &replayed)),
+        original: original.map(|message| message.content.clone()),
+        replayed,
+        original_tokens,
+        replayed_tokens,
+        token_delta: replayed_tokens - original_tokens,
+        original_cost,
+        replayed_cost,
+        cost_delta_usd,
+        usage,
+    }
+}
+
+fn summarize(
+    export: &SessionExport,
+    options: &ReplayOptions,
+    turns: Vec<ReplayTurn>,
+) -> ReplayReport {
+    let scores: Vec<f64> = turns.iter().filter_map(|turn| turn.similarity).collect();
+    let (mean_similarity, min_similarity) = if scores.is_empty() {
+        (1.0, 1.0)
+    } else {
+        (
+            scores.iter().sum::<f64>() / scores.len() as f64,
+            scores.iter().copied().fold(f64::INFINITY, f64::min),
+        )
+    };
+    let cost_delta_usd = turns
+        .iter()
+        .map(|turn| turn.cost_delta_usd)
+        .sum::<Option<f64>>();
+
+    ReplayReport {
+        session_id: export.session.id.clone(),
+        original_model: export.session.model.clone(),
+        model: options.model.clone(),
+        token_delta: turns.iter().map(|turn| turn.token_delta).sum(),
+        turns,
+        mean_similarity,
+        min_similarity,
+        cost_delta_usd,
+        fail_threshold: options.fail_threshold,
+        passed: options
+            .fail_threshold
+            .is_none_or(|threshold| min_similarity >= threshold),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock::{MockReply, MockUpstream};
+    use crate::models::ChatSession;
+    use crate::pricing::{CostNotConfirmed, GuardMode};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_similarity_is_normalized_word_edit_distance() {
+        assert_eq!(similarity("the cat sat", "the  cat\nsat"), 1.0);
+        assert_eq!(similarity("", ""), 1.0);
+        assert_eq!(similarity("a b c d", "a b x d"), 0.75);
+        assert_eq!(similarity("a b", "a b c d"), 0.5);
+        assert_eq!(similarity("one", ""), 0.0);
+    }
+
+    fn export() -> SessionExport {
+        let session = ChatSession::new("grok-3".to_string(), None);
+        let id = session.id.clone();
+        let mut reply = Message::assistant(id.clone(), "Paris is the capital".to_string(), None);
+        reply.tokens_used = Some(4);
+        SessionExport {
+            session,
+            messages: vec![
+                Message::system(id.clone(), "Be brief.".to_string()),
+                Message::user(id.clone(), "Capital of France?".to_string()),
+                reply,
+                Message::user(id.clone(), "And of Spain?".to_string()),
+                Message::assistant(id.clone(), "Madrid".to_string(), None),
+                Message::user(id, "Thanks".to_string()),
+            ],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_pairs_turns_and_threads_new_replies() {
+        let turn = AtomicUsize::new(0);
+        let upstream = MockUpstream::start(move |_| {
+            let reply = ["Paris is the capital", "It is Madrid", "You're welcome"];
+            MockReply::completion(reply[turn.fetch_add(1, Ordering::SeqCst)])
+        })
+        .await;
+        let service = ChatService::new(&upstream.config()).unwrap();
+        let options = ReplayOptions {
+            model: "grok-3-mini".to_string(),
+            system_prompt: "unused".to_string(),
+            max_tokens: Some(64),
+            temperature: Some(0.0),
+            fail_threshold: Some(0.5),
+            cost_guard: None,
+            confirm_cost: false,
+        };
+
+        let report = replay(&service, &PricingTable::default(), &export(), &options)
+            .await
+            .unwrap();
+
+        assert_eq!(report.turns.len(), 3);
+        assert_eq!(report.turns[0].similarity, Some(1.0));
+        assert_eq!(report.turns[0].token_delta, 1 - 4);
+        assert_eq!(report.turns[1].original.as_deref(), Some("Madrid"));
+        assert!((report.turns[1].similarity.unwrap() - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(report.turns[2].similarity, None);
+        assert_eq!(report.min_similarity, report.turns[1].similarity.unwrap());
+        assert!(!report.passed);
+        assert_eq!(report.regression().unwrap().threshold, 0.5);
+        assert!(report.cost_delta_usd.is_some());
+
+        // The export's system prompt is kept and new replies become context
+        let requests = upstream.requests();
+        let second = requests[1]["messages"].as_array().unwrap();
+        assert_eq!(second[0]["content"], "Be brief.");
+        assert_eq!(second[2]["content"], "Paris is the capital");
+        assert_eq!(second.len(), 4);
+        assert_eq!(requests[1]["model"], "grok-3-mini");
+        assert_eq!(requests[1]["temperature"], 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_replay_stops_at_a_turn_over_the_cost_threshold() {
+        let upstream = MockUpstream::echo("Paris").await;
+        let service = ChatService::new(&upstream.config()).unwrap();
+        let mut options = ReplayOptions {
+            model: "grok-4-0709".to_string(),
+            system_prompt: "unused".to_string(),
+            max_tokens: Some(64),
+            temperature: None,
+            fail_threshold: None,
+            cost_guard: Some(CostGuard {
+                threshold_usd: 0.0,
+                mode: GuardMode::Block,
+            }),
+            confirm_cost: false,
+        };
+
+        let error = replay(&service, &PricingTable::default(), &export(), &options)
+            .await
+            .unwrap_err();
+        assert!(error.is::<CostNotConfirmed>());
+        assert!(upstream.requests().is_empty());
+
+        options.confirm_cost = true;
+        let report = replay(&service, &PricingTable::default(), &export(), &options)
+            .await
+            .unwrap();
+        assert_eq!(report.turns.len(), 3);
+    }
+}
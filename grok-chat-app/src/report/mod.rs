@@ -0,0 +1,3 @@
+pub use self::report::*;
+#[allow(clippy::module_inception)]
+mod report;
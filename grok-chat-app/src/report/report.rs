@@ -0,0 +1,625 @@
+//! `report`: what the last day, week or month of chatting used, from the
+//! usage ledger and the messages table: tokens and estimated cost per model,
+//! the busiest sessions, upstream latency, how often replies were cut off,
+//! and a day-by-day table. Rows are stored in UTC; days are grouped in the
+//! configured timezone, or the system's local time.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Days, Local, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::config::Config;
+use crate::database::Database;
+use crate::models::{MessageActivity, UsageRecord};
+use crate::pricing::{Cost, PricingTable};
+
+/// Sessions listed under "Busiest sessions".
+pub const BUSIEST_SESSIONS: usize = 5;
+
+/// Levels of the day-by-day sparkline, lowest first.
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// How far back a report looks, counting today.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportPeriod {
+    Day,
+    #[default]
+    Week,
+    Month,
+}
+
+impl ReportPeriod {
+    /// Local days covered, today included.
+    pub fn days(self) -> u64 {
+        match self {
+            ReportPeriod::Day => 1,
+            ReportPeriod::Week => 7,
+            ReportPeriod::Month => 30,
+        }
+    }
+}
+
+impl FromStr for ReportPeriod {
+    type Err = anyhow::Error;
+
+    fn from_str(period: &str) -> Result<Self> {
+        match period.to_ascii_lowercase().as_str() {
+            "day" => Ok(ReportPeriod::Day),
+            "week" => Ok(ReportPeriod::Week),
+            "month" => Ok(ReportPeriod::Month),
+            _ => Err(anyhow!(
+                "Unknown period '{}' (available: day, week, month)",
+                period
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ReportPeriod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportPeriod::Day => write!(f, "day"),
+            ReportPeriod::Week => write!(f, "week"),
+            ReportPeriod::Month => write!(f, "month"),
+        }
+    }
+}
+
+/// The timezone whose days a report is grouped by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportZone {
+    /// The system's local time (which honours `TZ`).
+    Local,
+    Named(Tz),
+}
+
+impl ReportZone {
+    /// The configured `timezone`, or the system's.
+    pub fn from_config(config: &Config) -> Self {
+        config
+            .timezone()
+            .map_or(ReportZone::Local, ReportZone::Named)
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            ReportZone::Local => "local",
+            ReportZone::Named(tz) => tz.name(),
+        }
+    }
+
+    /// The local day `at` falls on.
+    pub fn date_of(&self, at: DateTime<Utc>) -> NaiveDate {
+        match self {
+            ReportZone::Local => at.with_timezone(&Local).date_naive(),
+            ReportZone::Named(tz) => at.with_timezone(tz).date_naive(),
+        }
+    }
+
+    /// When the local day `date` begins.
+    pub fn start_of(&self, date: NaiveDate) -> DateTime<Utc> {
+        match self {
+            ReportZone::Local => midnight(&Local, date),
+            ReportZone::Named(tz) => midnight(tz, date),
+        }
+    }
+}
+
+/// The first instant of `date` in `zone`. Where a DST change skips midnight,
+/// the day begins at the first hour that exists.
+fn midnight<Z: TimeZone>(zone: &Z, date: NaiveDate) -> DateTime<Utc> {
+    (0..24)
+        .filter_map(|hour| {
+            let time = NaiveTime::from_hms_opt(hour, 0, 0)?;
+            zone.from_local_datetime(&date.and_time(time)).earliest()
+        })
+        .next()
+        .map(|start| start.with_timezone(&Utc))
+        .unwrap_or_else(|| date.and_time(NaiveTime::MIN).and_utc())
+}
+
+/// Usage over a period, ready to print as markdown or serialize.
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageReport {
+    pub period: ReportPeriod,
+    pub timezone: String,
+    /// First local day covered.
+    pub from: NaiveDate,
+    /// Last local day covered (today).
+    pub to: NaiveDate,
+    pub totals: ReportTotals,
+    /// Most tokens first.
+    pub models: Vec<ModelReport>,
+    /// Most messages first, at most [`BUSIEST_SESSIONS`].
+    pub busiest_sessions: Vec<SessionReport>,
+    /// Over the completions that recorded a latency; `None` without any.
+    pub average_latency_ms: Option<u64>,
+    /// Share of completions cut off at `max_tokens` (finish reason `length`),
+    /// among those that recorded a finish reason; `None` without any.
+    pub truncation_rate: Option<f64>,
+    /// Every day of the period, oldest first, including quiet ones.
+    pub days: Vec<DayReport>,
+    /// Tokens per day, one character each.
+    pub sparkline: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReportTotals {
+    pub requests: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub messages: i64,
+    pub cost: Cost,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModelReport {
+    pub model: String,
+    pub requests: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub cost: Cost,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SessionReport {
+    pub session_id: String,
+    pub title: Option<String>,
+    pub messages: i64,
+    pub tokens: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DayReport {
+    pub date: NaiveDate,
+    pub requests: i64,
+    pub messages: i64,
+    pub tokens: i64,
+    pub cost: Cost,
+}
+
+/// Builds the report for the `period` ending on the local day of `now`.
+pub async fn usage_report(
+    database: &Database,
+    pricing: &PricingTable,
+    period: ReportPeriod,
+    zone: ReportZone,
+    now: DateTime<Utc>,
+) -> Result<UsageReport> {
+    let to = zone.date_of(now);
+    let from = to - Days::new(period.days() - 1);
+    let start = zone.start_of(from);
+    let end = zone.start_of(to + Days::new(1));
+
+    let usage = database.usage_between(start, end).await?;
+    let messages = database.message_activity(start, end).await?;
+    Ok(UsageReport::build(
+        period, zone, from, to, &usage, &messages, pricing,
+    ))
+}
+
+impl UsageReport {
+    fn build(
+        period: ReportPeriod,
+        zone: ReportZone,
+        from: NaiveDate,
+        to: NaiveDate,
+        usage: &[UsageRecord],
+        messages: &[MessageActivity],
+        pricing: &PricingTable,
+    ) -> Self {
+        let mut days: BTreeMap<NaiveDate, DayReport> = from
+            .iter_days()
+            .take_while(|date| *date <= to)
+            .map(|date| {
+                let day = DayReport {
+                    date,
+                    requests: 0,
+                    messages: 0,
+                    tokens: 0,
+                    cost: Cost::zero(),
+                };
+                (date, day)
+            })
+            .collect();
+        let mut models: BTreeMap<&str, ModelReport> = BTreeMap::new();
+        let mut sessions: HashMap<&str, SessionReport> = HashMap::new();
+        let (mut latency_total, mut latency_count) = (0u64, 0u64);
+        let (mut truncated, mut finished) = (0u64, 0u64);
+
+        for record in usage {
+            let cost = pricing.cost_for_tokens(
+                record.prompt_tokens,
+                record.completion_tokens,
+                &record.model,
+            );
+            let tokens = record.prompt_tokens + record.completion_tokens;
+
+            let model = models.entry(&record.model).or_insert_with(|| ModelReport {
+                model: record.model.clone(),
+                requests: 0,
+                prompt_tokens: 0,
+                completion_tokens: 0,
+                cost: Cost::zero(),
+            });
+            model.requests += 1;
+            model.prompt_tokens += record.prompt_tokens;
+            model.completion_tokens += record.completion_tokens;
+            model.cost = model.cost + cost;
+
+            if let Some(day) = days.get_mut(&zone.date_of(record.created_at)) {
+                day.requests += 1;
+                day.tokens += tokens;
+                day.cost = day.cost + cost;
+            }
+            if let Some(session_id) = &record.session_id {
+                session_entry(&mut sessions, session_id, None).tokens += tokens;
+            }
+            if let Some(latency) = record.latency_ms {
+                latency_total += latency;
+                latency_count += 1;
+            }
+            if let Some(reason) = &record.finish_reason {
+                finished += 1;
+                if reason == "length" {
+                    truncated += 1;
+                }
+            }
+        }
+
+        for message in messages {
+            if let Some(day) = days.get_mut(&zone.date_of(message.timestamp)) {
+                day.messages += 1;
+            }
+            session_entry(
+                &mut sessions,
+                &message.session_id,
+                message.session_title.as_deref(),
+            )
+            .messages += 1;
+        }
+
+        let mut models: Vec<ModelReport> = models.into_values().collect();
+        models.sort_by_key(|model| -(model.prompt_tokens + model.completion_tokens));
+        let mut busiest_sessions: Vec<SessionReport> = sessions.into_values().collect();
+        busiest_sessions.sort_by(|a, b| {
+            (b.messages, b.tokens, &a.session_id).cmp(&(a.messages, a.tokens, &b.session_id))
+        });
+        busiest_sessions.truncate(BUSIEST_SESSIONS);
+
+        let days: Vec<DayReport> = days.into_values().collect();
+        let totals = ReportTotals {
+            requests: models.iter().map(|model| model.requests).sum(),
+            prompt_tokens: models.iter().map(|model| model.prompt_tokens).sum(),
+            completion_tokens: models.iter().map(|model| model.completion_tokens).sum(),
+            messages: days.iter().map(|day| day.messages).sum(),
+            cost: models
+                .iter()
+                .fold(Cost::zero(), |total, model| total + model.cost),
+        };
+        let tokens: Vec<i64> = days.iter().map(|day| day.tokens).collect();
+
+        Self {
+            period,
+            timezone: zone.name().to_string(),
+            from,
+            to,
+            totals,
+            models,
+            busiest_sessions,
+            average_latency_ms: (latency_count > 0).then(|| latency_total / latency_count),
+            truncation_rate: (finished > 0).then(|| truncated as f64 / finished as f64),
+            sparkline: sparkline(&tokens),
+            days,
+        }
+    }
+
+    /// The report as markdown, for `--format markdown`.
+    pub fn markdown(&self) -> String {
+        let mut out = format!(
+            "# Usage report: {} to {} ({})\n\n",
+            self.from, self.to, self.timezone
+        );
+        let totals = &self.totals;
+        out.push_str(&format!(
+            "- Requests: {}\n- Tokens: {} ({} prompt + {} completion)\n- Estimated cost: {}\n- Messages: {}\n",
+            totals.requests,
+            totals.prompt_tokens + totals.completion_tokens,
+            totals.prompt_tokens,
+            totals.completion_tokens,
+            totals.cost,
+            totals.messages
+        ));
+        out.push_str(&format!(
+            "- Average latency: {}\n- Truncated replies: {}\n",
+            self.average_latency_ms
+                .map_or_else(|| "n/a".to_string(), |ms| format!("{} ms", ms)),
+            self.truncation_rate
+                .map_or_else(|| "n/a".to_string(), |rate| format!("{:.1}%", rate * 100.0))
+        ));
+
+        out.push_str("\n## Models\n\n");
+        out.push_str("| Model | Requests | Prompt tokens | Completion tokens | Cost |\n");
+        out.push_str("|---|--:|--:|--:|--:|\n");
+        for model in &self.models {
+            out.push_str(&format!(
+                "| {} | {} | {} | {} | {} |\n",
+                model.model,
+                model.requests,
+                model.prompt_tokens,
+                model.completion_tokens,
+                model.cost
+            ));
+        }
+
+        out.push_str("\n## Busiest sessions\n\n");
+        out.push_str("| Session | Title | Messages | Tokens |\n");
+        out.push_str("|---|---|--:|--:|\n");
+        for session in &self.busiest_sessions {
+            out.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                session.session_id,
+                session.title.as_deref().unwrap_or("").replace('|', "\\|"),
+                session.messages,
+                session.tokens
+            ));
+        }
+
+        out.push_str(&format!("\n## Days\n\nTokens: {}\n\n", self.sparkline));
+        out.push_str("| Day | Requests | Messages | Tokens | Cost | |\n");
+        out.push_str("|---|--:|--:|--:|--:|---|\n");
+        for (day, spark) in self.days.iter().zip(self.sparkline.chars()) {
+            out.push_str(&format!(
+                "| {} | {} | {} | {} | {} | {} |\n",
+                day.date.format("%a %Y-%m-%d"),
+                day.requests,
+                day.messages,
+                day.tokens,
+                day.cost,
+                spark
+            ));
+        }
+        out
+    }
+}
+
+/// The session's row, created on first sight; messages bring the title.
+fn session_entry<'m, 'a>(
+    sessions: &'m mut HashMap<&'a str, SessionReport>,
+    session_id: &'a str,
+    title: Option<&str>,
+) -> &'m mut SessionReport {
+    let session = sessions.entry(session_id).or_insert_with(|| SessionReport {
+        session_id: session_id.to_string(),
+        title: None,
+        messages: 0,
+        tokens: 0,
+    });
+    if session.title.is_none() {
+        session.title = title.map(str::to_string);
+    }
+    session
+}
+
+/// One character per value, scaled to the largest; zero gets the lowest
+/// level and anything above zero at least the next.
+fn sparkline(values: &[i64]) -> String {
+    let max = values.iter().copied().max().unwrap_or(0).max(1);
+    values
+        .iter()
+        .map(|&value| {
+            let level = ((value.max(0) * 7 + max - 1) / max) as usize;
+            SPARKS[level.min(7)]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ChatSession, Message};
+    use chrono::Duration;
+    use std::path::Path;
+
+    const NEW_YORK: ReportZone = ReportZone::Named(chrono_tz::America::New_York);
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn now() -> DateTime<Utc> {
+        // 08:00 in New York
+        at("2026-10-15T12:00:00Z")
+    }
+
+    fn date(ymd: &str) -> NaiveDate {
+        ymd.parse().unwrap()
+    }
+
+    /// Appends one exchange at `sent` and its ledger row.
+    fn exchange(
+        session: &ChatSession,
+        sent: DateTime<Utc>,
+        tokens: (i64, i64),
+        latency_ms: Option<u64>,
+        finish_reason: Option<&str>,
+        messages: &mut Vec<Message>,
+        usage: &mut Vec<UsageRecord>,
+    ) {
+        let mut prompt = Message::user(session.id.clone(), "question".into());
+        prompt.timestamp = sent;
+        let mut reply = Message::assistant(session.id.clone(), "answer".into(), None);
+        reply.timestamp = sent + Duration::seconds(1);
+        messages.extend([prompt, reply]);
+        usage.push(UsageRecord {
+            session_id: Some(session.id.clone()),
+            model: session.model.clone(),
+            prompt_tokens: tokens.0,
+            completion_tokens: tokens.1,
+            created_at: sent + Duration::seconds(1),
+            latency_ms,
+            finish_reason: finish_reason.map(str::to_string),
+        });
+    }
+
+    /// Five weeks of a daily stand-up session, plus a launch session busy
+    /// over the last three days and once late on the evening of Oct 14 in
+    /// New York (already Oct 15 in UTC), from before latency was recorded.
+    async fn seeded(dir: &Path) -> (Database, ChatSession, ChatSession) {
+        let config = Config {
+            database_url: format!("sqlite:{}?mode=rwc", dir.join("report.db").display()),
+            ..Config::default()
+        };
+        let db = Database::new(&config).await.unwrap();
+
+        let standup = ChatSession::new("grok-3-mini".to_string(), Some("Standup".into()));
+        let (mut messages, mut usage) = (Vec::new(), Vec::new());
+        for days_ago in 0..35 {
+            exchange(
+                &standup,
+                at("2026-10-15T11:00:00Z") - Duration::days(days_ago),
+                (100, 50),
+                Some(400),
+                Some("stop"),
+                &mut messages,
+                &mut usage,
+            );
+        }
+        db.import_session(&standup, &messages, &usage, None)
+            .await
+            .unwrap();
+
+        let launch = ChatSession::new("grok-3".to_string(), Some("Launch | plan".into()));
+        let (mut messages, mut usage) = (Vec::new(), Vec::new());
+        for day in ["2026-10-13", "2026-10-14", "2026-10-15"] {
+            for (hour, finish) in [(10, "length"), (10, "stop"), (11, "stop")] {
+                exchange(
+                    &launch,
+                    at(&format!("{}T{}:{:02}:00Z", day, hour, messages.len())),
+                    (1000, 500),
+                    Some(1000),
+                    Some(finish),
+                    &mut messages,
+                    &mut usage,
+                );
+            }
+        }
+        exchange(
+            &launch,
+            at("2026-10-15T02:00:00Z"),
+            (10, 10),
+            None,
+            None,
+            &mut messages,
+            &mut usage,
+        );
+        db.import_session(&launch, &messages, &usage, None)
+            .await
+            .unwrap();
+
+        (db, standup, launch)
+    }
+
+    #[tokio::test]
+    async fn test_week_report_groups_by_local_day() {
+        let dir = tempfile::tempdir().unwrap();
+        let (db, standup, launch) = seeded(dir.path()).await;
+        let pricing = PricingTable::default();
+
+        let report = usage_report(&db, &pricing, ReportPeriod::Week, NEW_YORK, now())
+            .await
+            .unwrap();
+        assert_eq!(report.timezone, "America/New_York");
+        assert_eq!(
+            (report.from, report.to),
+            (date("2026-10-09"), date("2026-10-15"))
+        );
+        assert_eq!(report.days.len(), 7);
+        assert_eq!(report.totals.requests, 7 + 10);
+        assert_eq!(report.totals.messages, 2 * 17);
+
+        assert_eq!(report.models[0].model, "grok-3");
+        assert_eq!(report.models[0].prompt_tokens, 9 * 1000 + 10);
+        assert_eq!(report.models[1].model, "grok-3-mini");
+        assert_eq!(report.models[1].requests, 7);
+        let expected = report.models[0].cost + report.models[1].cost;
+        assert_eq!(report.totals.cost, expected);
+
+        let busiest: Vec<(&str, i64)> = report
+            .busiest_sessions
+            .iter()
+            .map(|session| (session.session_id.as_str(), session.messages))
+            .collect();
+        assert_eq!(
+            busiest,
+            vec![(launch.id.as_str(), 20), (standup.id.as_str(), 14)]
+        );
+
+        // The old row has neither latency nor finish reason
+        assert_eq!(report.average_latency_ms, Some((7 * 400 + 9 * 1000) / 16));
+        assert_eq!(report.truncation_rate, Some(3.0 / 16.0));
+
+        // 22:00 on Oct 14 in New York is Oct 15 in UTC
+        let oct_14 = &report.days[5];
+        assert_eq!(oct_14.date, date("2026-10-14"));
+        assert_eq!((oct_14.requests, oct_14.tokens), (5, 150 + 3 * 1500 + 20));
+        let utc = ReportZone::Named(chrono_tz::UTC);
+        let report = usage_report(&db, &pricing, ReportPeriod::Week, utc, now())
+            .await
+            .unwrap();
+        assert_eq!(report.days[5].requests, 4);
+        assert_eq!(report.days[6].requests, 5);
+    }
+
+    #[tokio::test]
+    async fn test_month_and_day_reports_cover_their_days() {
+        let dir = tempfile::tempdir().unwrap();
+        let (db, standup, _) = seeded(dir.path()).await;
+        let pricing = PricingTable::default();
+
+        let month = usage_report(&db, &pricing, ReportPeriod::Month, NEW_YORK, now())
+            .await
+            .unwrap();
+        assert_eq!(month.from, date("2026-09-16"));
+        assert_eq!(month.days.len(), 30);
+        assert!(month.days.iter().all(|day| day.requests >= 1));
+        assert_eq!(month.totals.requests, 30 + 10);
+        assert_eq!(month.busiest_sessions[0].session_id, standup.id);
+        assert_eq!(month.busiest_sessions[0].tokens, 30 * 150);
+        assert_eq!(month.sparkline, format!("{}███", "▂".repeat(27)));
+
+        let day = usage_report(&db, &pricing, ReportPeriod::Day, NEW_YORK, now())
+            .await
+            .unwrap();
+        assert_eq!(day.days.len(), 1);
+        assert_eq!(day.totals.requests, 4);
+
+        let markdown = month.markdown();
+        assert!(markdown.starts_with("# Usage report: 2026-09-16 to 2026-10-15 (America/New_York)"));
+        assert!(markdown.contains("| Thu 2026-10-15 | 4 | 8 | 4650 |"));
+        assert!(markdown.contains("Launch \\| plan"));
+        assert!(markdown.contains("- Truncated replies: "));
+    }
+
+    #[test]
+    fn test_sparkline_scales_to_the_busiest_day() {
+        assert_eq!(sparkline(&[0, 1, 50, 100]), "▁▂▅█");
+        assert_eq!(sparkline(&[0, 0]), "▁▁");
+    }
+
+    #[test]
+    fn test_period_parses_case_insensitively() {
+        assert_eq!(
+            "Month".parse::<ReportPeriod>().unwrap(),
+            ReportPeriod::Month
+        );
+        assert!("year".parse::<ReportPeriod>().is_err());
+    }
+}
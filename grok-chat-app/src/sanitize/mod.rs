@@ -0,0 +1,3 @@
+pub use self::sanitize::*;
+#[allow(clippy::module_inception)]
+mod sanitize;
@@ -0,0 +1,166 @@
+//! Makes model output safe to show on a terminal.
+//!
+//! Replies (and documents pasted into them) can carry escape sequences that
+//! recolor the screen, move the cursor or, through OSC sequences, reach the
+//! terminal itself (OSC 52 writes the clipboard). Control characters are
+//! shown as visible placeholders instead: ESC becomes `␛`, so `\x1b[31m`
+//! prints as `␛[31m` and does nothing. Tabs become spaces and absurdly long
+//! lines are cut short.
+
+/// Characters kept per line; the rest is replaced by a count.
+pub const MAX_LINE_CHARS: usize = 4000;
+
+const TAB_WIDTH: usize = 4;
+
+/// Sanitizes a complete text.
+pub fn sanitize(text: &str) -> String {
+    let mut sanitizer = Sanitizer::new();
+    let mut sanitized = sanitizer.push(text);
+    sanitized.push_str(&sanitizer.finish());
+    sanitized
+}
+
+/// Sanitizes streamed text delta by delta. It keeps the column between
+/// deltas and holds back a trailing `\r` until it knows whether a `\n`
+/// follows.
+#[derive(Debug, Default)]
+pub struct Sanitizer {
+    column: usize,
+    /// Characters cut from the current line.
+    dropped: usize,
+    pending_cr: bool,
+}
+
+impl Sanitizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, delta: &str) -> String {
+        let mut out = String::with_capacity(delta.len());
+        for c in delta.chars() {
+            if std::mem::take(&mut self.pending_cr) && c != '\n' {
+                self.emit(&mut out, '\u{240d}');
+            }
+            match c {
+                '\r' => self.pending_cr = true,
+                '\n' => {
+                    self.end_line(&mut out);
+                    out.push('\n');
+                }
+                '\t' => {
+                    for _ in 0..TAB_WIDTH - self.column % TAB_WIDTH {
+                        self.emit(&mut out, ' ');
+                    }
+                }
+                c => self.emit(&mut out, c),
+            }
+        }
+        out
+    }
+
+    /// Flushes a held-back `\r` and notes a cut on the last line.
+    pub fn finish(&mut self) -> String {
+        let mut out = String::new();
+        if std::mem::take(&mut self.pending_cr) {
+            self.emit(&mut out, '\u{240d}');
+        }
+        self.end_line(&mut out);
+        out
+    }
+
+    fn emit(&mut self, out: &mut String, c: char) {
+        if self.column >= MAX_LINE_CHARS {
+            self.dropped += 1;
+            return;
+        }
+        self.column += 1;
+        match c {
+            // C0 controls map onto the Control Pictures block: ESC is ␛
+            '\0'..='\x1f' => out.push(char::from_u32(0x2400 + c as u32).unwrap()),
+            '\x7f' => out.push('\u{2421}'),
+            // C1 controls have no pictures; 0x9b alone starts a CSI sequence
+            '\u{80}'..='\u{9f}' => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    fn end_line(&mut self, out: &mut String) {
+        if self.dropped > 0 {
+            out.push_str(&format!(" … [{} more characters]", self.dropped));
+        }
+        self.column = 0;
+        self.dropped = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_sequences_become_visible() {
+        assert_eq!(sanitize("\x1b[31mred\x1b[0m"), "␛[31mred␛[0m");
+        assert_eq!(sanitize("a\x1b[2J\x1b[Hb"), "a␛[2J␛[Hb");
+        assert_eq!(sanitize("bell\x07 nul\0 del\x7f"), "bell␇ nul␀ del␡");
+        // 8-bit CSI and OSC introducers
+        assert_eq!(sanitize("\u{9b}31m \u{9d}0;x"), "\\u{9b}31m \\u{9d}0;x");
+        assert_eq!(sanitize("plain text, ünïcode ✓"), "plain text, ünïcode ✓");
+    }
+
+    #[test]
+    fn test_osc_sequences_cannot_reach_the_terminal() {
+        for attack in [
+            // OSC 52: write "rm -rf ~" to the clipboard, BEL and ST terminated
+            "\x1b]52;c;cm0gLXJmIH4=\x07",
+            "\x1b]52;c;cm0gLXJmIH4=\x1b\\",
+            // Set the window title, then report it back as input
+            "\x1b]0;evil\x07\x1b[21t",
+            // Hyperlink whose text hides the target
+            "\x1b]8;;https://evil.example\x1b\\docs\x1b]8;;\x1b\\",
+        ] {
+            let safe = sanitize(attack);
+            assert!(
+                !safe.chars().any(|c| c.is_control()),
+                "{:?} -> {:?}",
+                attack,
+                safe
+            );
+        }
+        assert_eq!(
+            sanitize("\x1b]52;c;cm0gLXJmIH4=\x07"),
+            "␛]52;c;cm0gLXJmIH4=␇"
+        );
+    }
+
+    #[test]
+    fn test_tabs_and_carriage_returns() {
+        assert_eq!(sanitize("a\tb\n\tc"), "a   b\n    c");
+        assert_eq!(sanitize("one\r\ntwo\r\n"), "one\ntwo\n");
+        // A lone carriage return would overwrite the line it ends
+        assert_eq!(sanitize("safe\rEVIL"), "safe␍EVIL");
+        assert_eq!(sanitize("end\r"), "end␍");
+    }
+
+    #[test]
+    fn test_long_lines_are_cut() {
+        let line = "x".repeat(MAX_LINE_CHARS + 10);
+        let safe = sanitize(&format!("{}\nnext", line));
+        let (first, rest) = safe.split_once('\n').unwrap();
+        assert_eq!(first.chars().filter(|c| *c == 'x').count(), MAX_LINE_CHARS);
+        assert!(first.ends_with(" … [10 more characters]"));
+        assert_eq!(rest, "next");
+    }
+
+    #[test]
+    fn test_streamed_deltas_match_whole_text() {
+        let text = "a\tb\x1b[1m\r\nc\rd\t\x07";
+        let mut sanitizer = Sanitizer::new();
+        let mut streamed: String = text
+            .chars()
+            .map(|c| sanitizer.push(&c.to_string()))
+            .collect();
+        streamed.push_str(&sanitizer.finish());
+        assert_eq!(streamed, sanitize(text));
+    }
+}
@@ -0,0 +1,3 @@
+pub use self::script::*;
+#[allow(clippy::module_inception)]
+mod script;
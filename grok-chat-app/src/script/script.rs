@@ -0,0 +1,487 @@
+//! Scripted conversations: a YAML file that fixes the model, system prompt and
+//! parameters, and lists the user turns to send in order, each optionally
+//! with substrings its reply must contain. `grok-chat run` plays one into a
+//! new saved session, for repeatable demos and prompt tests.
+//!
+//! ```yaml
+//! title: Rivers
+//! model: grok-3
+//! system_prompt: Answer in one sentence.
+//! temperature: 0
+//! vars:
+//!   country: Portugal
+//! turns:
+//!   - user: Name a river in {{country}}.
+//!     expect: Douro
+//!   - user: Where does it rise?
+//!     expect: [Spain]
+//! ```
+
+use anyhow::{bail, Context, Result};
+use futures_util::StreamExt;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Instant;
+
+use crate::client::{ChatResponse, ChatService};
+use crate::database::Database;
+use crate::models::{
+    ApiChatRequest, ApiMessage, ChatSession, CompletionStats, Message, MessageRole, UsageStats,
+};
+use crate::templates::PromptTemplate;
+
+/// A parsed script. Text fields may use `{{name}}` placeholders until
+/// [`Script::interpolate`] fills them in.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Script {
+    /// Title of the session the run is saved in.
+    pub title: Option<String>,
+    pub model: Option<String>,
+    pub system_prompt: Option<String>,
+    pub max_tokens: Option<i32>,
+    pub temperature: Option<f32>,
+    /// Placeholder values used when neither `--var` nor the environment sets
+    /// them.
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+    pub turns: Vec<ScriptTurn>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptTurn {
+    /// The message sent as the user.
+    pub user: String,
+    /// Substrings the reply must contain, matched case-sensitively; a single
+    /// string or a list.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub expect: Vec<String>,
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(one) => vec![one],
+        OneOrMany::Many(many) => many,
+    })
+}
+
+impl Script {
+    pub fn from_yaml(yaml: &str) -> Result<Self> {
+        let script: Script = serde_yaml::from_str(yaml)?;
+        if script.turns.is_empty() {
+            bail!("The script has no turns");
+        }
+        Ok(script)
+    }
+
+    /// Fills in every placeholder, looking each name up in `vars` (from
+    /// `--var`), then `env`, then the script's own `vars`. Any name found in
+    /// none of them is an error.
+    pub fn interpolate(
+        mut self,
+        vars: &HashMap<String, String>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self> {
+        let defaults = std::mem::take(&mut self.vars);
+        let lookup = |name: &str| {
+            vars.get(name)
+                .cloned()
+                .or_else(|| env(name))
+                .or_else(|| defaults.get(name).cloned())
+        };
+
+        if let Some(title) = &self.title {
+            self.title = Some(fill("title", title, &lookup)?);
+        }
+        if let Some(prompt) = &self.system_prompt {
+            self.system_prompt = Some(fill("system_prompt", prompt, &lookup)?);
+        }
+        for (i, turn) in self.turns.iter_mut().enumerate() {
+            let name = format!("turn {}", i + 1);
+            turn.user = fill(&name, &turn.user, &lookup)?;
+            for expected in &mut turn.expect {
+                *expected = fill(&name, expected, &lookup)?;
+            }
+        }
+        self.vars = defaults;
+        Ok(self)
+    }
+}
+
+fn fill(field: &str, text: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String> {
+    let template = PromptTemplate::new(field, text);
+    let mut values = HashMap::new();
+    let mut missing = Vec::new();
+    for name in template.placeholders() {
+        match lookup(&name) {
+            Some(value) => {
+                values.insert(name, value);
+            }
+            None => missing.push(name),
+        }
+    }
+    if !missing.is_empty() {
+        bail!(
+            "{} uses undefined variables: {}; set them with --var, in the environment or under `vars`",
+            field,
+            missing.join(", ")
+        );
+    }
+    Ok(template.render(&values)?.text)
+}
+
+/// How to run a script; the CLI fills in what the script leaves out from
+/// `Config`, except for sampling, left to the service.
+#[derive(Debug, Clone)]
+pub struct ScriptOptions {
+    pub model: String,
+    pub system_prompt: String,
+    pub max_tokens: Option<i32>,
+    pub temperature: Option<f32>,
+    /// Request streamed replies, reported delta by delta.
+    pub stream: bool,
+}
+
+/// Progress of a run, in order, for printing as it happens.
+#[derive(Debug)]
+pub enum ScriptEvent<'a> {
+    /// A turn's message is about to be sent.
+    Turn { turn: usize, prompt: &'a str },
+    /// Reply text as it arrives: every delta when streaming, else the whole
+    /// reply at once.
+    Delta(&'a str),
+    /// The reply is complete and its expectations checked.
+    Checked(&'a TurnOutcome),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TurnOutcome {
+    /// 1-based position in the script.
+    pub turn: usize,
+    pub prompt: String,
+    pub reply: String,
+    pub expected: Vec<String>,
+    /// The expected substrings the reply lacks.
+    pub missing: Vec<String>,
+    pub passed: bool,
+    pub usage: Option<UsageStats>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScriptReport {
+    pub session_id: String,
+    pub model: String,
+    pub turns: Vec<TurnOutcome>,
+    pub passed: bool,
+}
+
+impl ScriptReport {
+    /// The failure to report when any turn missed an expectation.
+    pub fn failure(&self) -> Option<ScriptFailed> {
+        let failed: Vec<usize> = self
+            .turns
+            .iter()
+            .filter(|turn| !turn.passed)
+            .map(|turn| turn.turn)
+            .collect();
+        (!failed.is_empty()).then_some(ScriptFailed {
+            failed,
+            total: self.turns.len(),
+        })
+    }
+}
+
+/// A run in which some replies lacked an expected substring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptFailed {
+    /// The failing turns, 1-based.
+    pub failed: Vec<usize>,
+    pub total: usize,
+}
+
+impl fmt::Display for ScriptFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let turns: Vec<String> = self.failed.iter().map(ToString::to_string).collect();
+        write!(
+            f,
+            "{} of {} turns failed their expectations (turn {})",
+            self.failed.len(),
+            self.total,
+            turns.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for ScriptFailed {}
+
+/// Sends the script's turns one at a time in a new session, saving each
+/// exchange as it completes. Every reply is the context for the next turn.
+/// A failed expectation does not stop the run; a failed request does, after
+/// the turns before it were saved.
+pub async fn run_script(
+    service: &ChatService,
+    database: &Database,
+    script: &Script,
+    options: &ScriptOptions,
+    mut on_event: impl FnMut(ScriptEvent<'_>),
+) -> Result<ScriptReport> {
+    let session = database
+        .create_session(ChatSession::new(
+            options.model.clone(),
+            script.title.clone(),
+        ))
+        .await?;
+    // Only a prompt the script chose is part of the session
+    if let Some(prompt) = &script.system_prompt {
+        database
+            .create_message(Message::new(
+                session.id.clone(),
+                MessageRole::System,
+                prompt.clone(),
+                None,
+            ))
+            .await?;
+    }
+
+    let mut history = vec![ApiMessage::system(&options.system_prompt)];
+    let mut turns = Vec::new();
+    for (i, turn) in script.turns.iter().enumerate() {
+        on_event(ScriptEvent::Turn {
+            turn: i + 1,
+            prompt: &turn.user,
+        });
+        history.push(ApiMessage::user(&turn.user));
+
+        let request = ApiChatRequest {
+            max_tokens: options.max_tokens,
+            temperature: options.temperature,
+            ..ApiChatRequest::builder()
+                .messages(history.clone())
+                .model(&options.model)
+                .stream(options.stream)
+                .build()
+        };
+        let started = Instant::now();
+        let mut reply = String::new();
+        let mut usage = None;
+        let mut finish_reason = None;
+        match service
+            .send_request(request, None)
+            .await
+            .with_context(|| format!("Turn {} failed", i + 1))?
+        {
+            ChatResponse::Complete(response) => {
+                reply = response.get_content()?;
+                usage = response.get_usage().cloned();
+                finish_reason = response.get_finish_reason();
+                on_event(ScriptEvent::Delta(&reply));
+            }
+            ChatResponse::Stream(mut stream) => {
+                while let Some(chunk) = stream.next().await {
+                    let chunk = chunk.with_context(|| format!("Turn {} failed", i + 1))?;
+                    on_event(ScriptEvent::Delta(&chunk.content));
+                    reply.push_str(&chunk.content);
+                    usage = chunk.usage.or(usage);
+                    finish_reason = chunk.finish_reason.or(finish_reason);
+                    if let Some(processed) = chunk.processed {
+                        reply = processed.content;
+                    }
+                }
+            }
+        }
+        let stats = CompletionStats {
+            latency_ms: Some(started.elapsed().as_millis() as u64),
+            finish_reason,
+        };
+
+        database
+            .create_message(Message::user(session.id.clone(), turn.user.clone()))
+            .await?;
+        database
+            .create_message(Message::assistant(
+                session.id.clone(),
+                reply.clone(),
+                Some(options.model.clone()),
+            ))
+            .await?;
+        if let Some(usage) = &usage {
+            database
+                .record_usage(Some(&session.id), &options.model, usage, &stats)
+                .await?;
+        }
+
+        let missing: Vec<String> = turn
+            .expect
+            .iter()
+            .filter(|expected| !reply.contains(expected.as_str()))
+            .cloned()
+            .collect();
+        let outcome = TurnOutcome {
+            turn: i + 1,
+            prompt: turn.user.clone(),
+            reply: reply.clone(),
+            expected: turn.expect.clone(),
+            passed: missing.is_empty(),
+            missing,
+            usage,
+        };
+        on_event(ScriptEvent::Checked(&outcome));
+        turns.push(outcome);
+        history.push(ApiMessage::assistant(reply));
+    }
+    database.update_session(&session.id, None).await?;
+
+    Ok(ScriptReport {
+        session_id: session.id,
+        model: options.model.clone(),
+        passed: turns.iter().all(|turn| turn.passed),
+        turns,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock::{MockReply, MockUpstream};
+    use crate::config::Config;
+    use std::time::Duration;
+
+    const SCRIPT: &str = r#"
+title: "{{topic}} quiz"
+system_prompt: You answer questions about {{topic}}.
+temperature: 0
+vars:
+  topic: rivers
+  country: Spain
+turns:
+  - user: Name a river in {{country}}.
+    expect: Douro
+  - user: Where does it rise?
+    expect: [Spain, mountains]
+"#;
+
+    #[test]
+    fn test_placeholders_prefer_cli_vars_then_environment_then_defaults() {
+        let vars = HashMap::from([("country".to_string(), "Portugal".to_string())]);
+        let env = |name: &str| (name == "topic" || name == "country").then(|| "geography".into());
+        let script = Script::from_yaml(SCRIPT)
+            .unwrap()
+            .interpolate(&vars, env)
+            .unwrap();
+
+        assert_eq!(script.title.as_deref(), Some("geography quiz"));
+        assert_eq!(script.turns[0].user, "Name a river in Portugal.");
+        assert_eq!(script.turns[0].expect, ["Douro"]);
+        assert_eq!(script.turns[1].expect, ["Spain", "mountains"]);
+
+        let defaults = Script::from_yaml(SCRIPT)
+            .unwrap()
+            .interpolate(&HashMap::new(), |_| None)
+            .unwrap();
+        assert_eq!(defaults.turns[0].user, "Name a river in Spain.");
+
+        let undefined = Script::from_yaml("turns:\n  - user: Hi {{name}}\n")
+            .unwrap()
+            .interpolate(&HashMap::new(), |_| None)
+            .unwrap_err();
+        assert!(undefined
+            .to_string()
+            .starts_with("turn 1 uses undefined variables: name"));
+        assert!(Script::from_yaml("turns: []").is_err());
+        assert!(Script::from_yaml("turns:\n  - usr: typo\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_streams_saves_the_session_and_checks_each_turn() {
+        let upstream = MockUpstream::start(|body| {
+            let turns = body["messages"].as_array().unwrap().len();
+            let reply: &[&str] = if turns == 2 {
+                &["The ", "Douro."]
+            } else {
+                &["In ", "Spain."]
+            };
+            MockReply::stream(reply, Duration::ZERO)
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            database_url: format!("sqlite:{}", dir.path().join("chat.db").display()),
+            ..upstream.config()
+        };
+        let database = Database::new(&config).await.unwrap();
+        let script = Script::from_yaml(SCRIPT)
+            .unwrap()
+            .interpolate(&HashMap::new(), |_| None)
+            .unwrap();
+        let options = ScriptOptions {
+            model: "grok-3".to_string(),
+            system_prompt: script.system_prompt.clone().unwrap(),
+            max_tokens: Some(100),
+            temperature: Some(0.0),
+            stream: true,
+        };
+
+        let mut printed = String::new();
+        let report = run_script(
+            &ChatService::new(&config).unwrap(),
+            &database,
+            &script,
+            &options,
+            |event| match event {
+                ScriptEvent::Turn { turn, .. } => printed.push_str(&format!("[{}] ", turn)),
+                ScriptEvent::Delta(delta) => printed.push_str(delta),
+                ScriptEvent::Checked(outcome) => {
+                    printed.push_str(if outcome.passed { " ok " } else { " FAIL " })
+                }
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(printed, "[1] The Douro. ok [2] In Spain. FAIL ");
+        assert!(!report.passed);
+        assert_eq!(report.turns[1].missing, ["mountains"]);
+        let failure = report.failure().unwrap();
+        assert_eq!(failure.failed, [2]);
+        assert_eq!(
+            failure.to_string(),
+            "1 of 2 turns failed their expectations (turn 2)"
+        );
+
+        // The second request carried the first exchange as context
+        let requests = upstream.requests();
+        assert_eq!(requests[1]["messages"][2]["content"], "The Douro.");
+
+        let session = database
+            .get_session(&report.session_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.title.as_deref(), Some("rivers quiz"));
+        let stored: Vec<String> = database
+            .get_messages(&report.session_id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|message| message.content)
+            .collect();
+        assert_eq!(
+            stored,
+            [
+                "You answer questions about rivers.",
+                "Name a river in Spain.",
+                "The Douro.",
+                "Where does it rise?",
+                "In Spain.",
+            ]
+        );
+    }
+}
@@ -0,0 +1,3 @@
+pub use self::session_templates::*;
+#[allow(clippy::module_inception)]
+mod session_templates;
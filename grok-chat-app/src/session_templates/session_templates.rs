@@ -0,0 +1,168 @@
+use anyhow::{bail, Result};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::models::{Message, MessageRole};
+
+/// A conversation scaffold new sessions can start from: a system prompt and
+/// a few example exchanges, such as a "code review" session that opens with
+/// a sample diff and the kind of review wanted.
+///
+/// The messages are copied into each session marked `seeded`. They are sent
+/// as context like any other, but are shown dimmed and left out of
+/// `sessions export` unless asked for.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SessionTemplate {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub system_prompt: Option<String>,
+    /// User and assistant turns, in order.
+    #[serde(default)]
+    pub messages: Vec<SeedMessage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SeedMessage {
+    pub role: MessageRole,
+    pub content: String,
+}
+
+impl SessionTemplate {
+    /// Rejects templates that would seed nothing, or seed roles other than
+    /// user and assistant turns; the system prompt has its own field.
+    pub fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            bail!("A session template needs a name");
+        }
+        if self.system_prompt.as_deref().is_none_or(str::is_empty) && self.messages.is_empty() {
+            bail!(
+                "Session template '{}' has neither a system prompt nor messages",
+                self.name
+            );
+        }
+        if let Some(seed) = self
+            .messages
+            .iter()
+            .find(|seed| !matches!(seed.role, MessageRole::User | MessageRole::Assistant))
+        {
+            bail!(
+                "Session template '{}' can only seed user and assistant messages, not {}",
+                self.name,
+                seed.role
+            );
+        }
+        Ok(())
+    }
+
+    /// The messages a new session with `session_id` starts with: the system
+    /// prompt, if any, then the examples, all marked seeded. Timestamps are a
+    /// millisecond apart, ending just before now, so the seeds keep their
+    /// order ahead of the first real message.
+    pub fn seed(&self, session_id: &str) -> Vec<Message> {
+        let system = self
+            .system_prompt
+            .iter()
+            .map(|prompt| (MessageRole::System, prompt));
+        let examples = self
+            .messages
+            .iter()
+            .map(|seed| (seed.role.clone(), &seed.content));
+        let count = self.messages.len() + usize::from(self.system_prompt.is_some());
+        let start = Utc::now() - Duration::milliseconds(count as i64);
+
+        system
+            .chain(examples)
+            .enumerate()
+            .map(|(i, (role, content))| {
+                let mut message = Message::new(session_id.to_string(), role, content.clone(), None);
+                message.timestamp = start + Duration::milliseconds(i as i64);
+                message.seeded = true;
+                message
+            })
+            .collect()
+    }
+}
+
+/// A template name that matches no saved session template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownSessionTemplate {
+    pub name: String,
+    /// Names of the saved templates, sorted.
+    pub available: Vec<String>,
+}
+
+impl fmt::Display for UnknownSessionTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.available.is_empty() {
+            return write!(
+                f,
+                "Unknown session template '{}' (none are saved; add one with `session-templates add`)",
+                self.name
+            );
+        }
+        write!(
+            f,
+            "Unknown session template '{}' (available: {})",
+            self.name,
+            self.available.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownSessionTemplate {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn review() -> SessionTemplate {
+        serde_json::from_str(
+            r#"{
+                "name": "review",
+                "system_prompt": "You review Rust code.",
+                "messages": [
+                    {"role": "user", "content": "fn f() { x.unwrap() }"},
+                    {"role": "assistant", "content": "Handle the error instead of unwrapping."}
+                ]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_seed_puts_the_system_prompt_first_and_marks_everything() {
+        let messages = review().seed("s1");
+
+        let roles: Vec<&MessageRole> = messages.iter().map(|m| &m.role).collect();
+        assert_eq!(
+            roles,
+            [
+                &MessageRole::System,
+                &MessageRole::User,
+                &MessageRole::Assistant
+            ]
+        );
+        assert!(messages.iter().all(|m| m.seeded && m.session_id == "s1"));
+        assert!(messages.windows(2).all(|w| w[0].timestamp < w[1].timestamp));
+    }
+
+    #[test]
+    fn test_validate_rejects_empty_templates_and_stray_roles() {
+        review().validate().unwrap();
+
+        let empty = SessionTemplate {
+            system_prompt: None,
+            messages: Vec::new(),
+            ..review()
+        };
+        assert!(empty.validate().is_err());
+
+        let mut system_turn = review();
+        system_turn.messages[0].role = MessageRole::System;
+        let error = system_turn.validate().unwrap_err().to_string();
+        assert!(error.contains("not system"), "{}", error);
+    }
+}
@@ -0,0 +1,3 @@
+pub use self::templates::*;
+#[allow(clippy::module_inception)]
+mod templates;
@@ -0,0 +1,270 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+/// A reusable prompt skeleton with `{{variable}}` placeholders.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PromptTemplate {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub body: String,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub max_tokens: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct RenderedTemplate {
+    pub text: String,
+    pub warnings: Vec<String>,
+}
+
+impl PromptTemplate {
+    pub fn new(name: impl Into<String>, body: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            description: None,
+            body: body.into(),
+            model: None,
+            temperature: None,
+            max_tokens: None,
+        }
+    }
+
+    /// Placeholder names in order of first appearance, without duplicates.
+    pub fn placeholders(&self) -> Vec<String> {
+        let mut seen = BTreeSet::new();
+        let mut names = Vec::new();
+        for segment in parse(&self.body) {
+            if let Segment::Placeholder(name) = segment {
+                if seen.insert(name) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names
+    }
+
+    /// Fills every placeholder from `variables`. Unfilled placeholders are an
+    /// error listing all of them; unused variables only produce warnings.
+    pub fn render(&self, variables: &HashMap<String, String>) -> Result<RenderedTemplate> {
+        let placeholders = self.placeholders();
+        let missing: Vec<&str> = placeholders
+            .iter()
+            .filter(|name| !variables.contains_key(*name))
+            .map(String::as_str)
+            .collect();
+
+        if !missing.is_empty() {
+            return Err(anyhow!(
+                "Template '{}' is missing values for: {}",
+                self.name,
+                missing.join(", ")
+            ));
+        }
+
+        let mut text = String::with_capacity(self.body.len());
+        for segment in parse(&self.body) {
+            match segment {
+                Segment::Text(literal) => text.push_str(literal),
+                Segment::Placeholder(name) => text.push_str(&variables[name]),
+            }
+        }
+
+        let mut unused: Vec<&String> = variables
+            .keys()
+            .filter(|key| !placeholders.contains(key))
+            .collect();
+        unused.sort();
+        let warnings = unused
+            .into_iter()
+            .map(|key| format!("Ignoring unused variable '{}'", key))
+            .collect();
+
+        Ok(RenderedTemplate { text, warnings })
+    }
+}
+
+enum Segment<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+fn is_placeholder_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+/// Splits a template body into literal text and `{{ name }}` placeholders.
+/// Braces that don't enclose a valid name are kept as literal text.
+fn parse(body: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = body;
+
+    while let Some(start) = rest.find("{{") {
+        let after_open = &rest[start + 2..];
+        let Some(end) = after_open.find("}}") else {
+            break;
+        };
+
+        let name = after_open[..end].trim();
+        if is_placeholder_name(name) {
+            if start > 0 {
+                segments.push(Segment::Text(&rest[..start]));
+            }
+            segments.push(Segment::Placeholder(name));
+        } else {
+            segments.push(Segment::Text(&rest[..start + 2 + end + 2]));
+        }
+        rest = &after_open[end + 2..];
+    }
+
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest));
+    }
+    segments
+}
+
+/// Parses `key=value` pairs as typed in a slash command. Values may be wrapped
+/// in double quotes to include spaces.
+pub fn parse_assignments(input: &str) -> Result<HashMap<String, String>> {
+    let mut variables = HashMap::new();
+    let mut chars = input.chars().peekable();
+
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut key = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == '=' || c.is_whitespace() {
+                break;
+            }
+            key.push(c);
+            chars.next();
+        }
+        if chars.next() != Some('=') || key.is_empty() {
+            return Err(anyhow!("Expected key=value, got '{}'", key));
+        }
+
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            let mut closed = false;
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => {
+                        closed = true;
+                        break;
+                    }
+                    '\\' => {
+                        if let Some(escaped) = chars.next() {
+                            value.push(escaped);
+                        }
+                    }
+                    _ => value.push(c),
+                }
+            }
+            if !closed {
+                return Err(anyhow!("Unterminated quote in value for '{}'", key));
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                value.push(c);
+                chars.next();
+            }
+        }
+
+        variables.insert(key, value);
+    }
+
+    Ok(variables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_render_substitutes_placeholders() {
+        let template = PromptTemplate::new(
+            "review",
+            "Review this {{lang}} code:\n{{ code }}\n({{lang}})",
+        );
+        let rendered = template
+            .render(&vars(&[("lang", "Rust"), ("code", "fn main() {}")]))
+            .unwrap();
+        assert_eq!(
+            rendered.text,
+            "Review this Rust code:\nfn main() {}\n(Rust)"
+        );
+        assert!(rendered.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_render_lists_all_missing_placeholders() {
+        let template = PromptTemplate::new("t", "{{a}} {{b}} {{c}}");
+        let error = template.render(&vars(&[("b", "x")])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Template 't' is missing values for: a, c"
+        );
+    }
+
+    #[test]
+    fn test_render_warns_about_extra_variables() {
+        let template = PromptTemplate::new("t", "Hi {{name}}");
+        let rendered = template
+            .render(&vars(&[("name", "Ada"), ("zeta", "1"), ("alpha", "2")]))
+            .unwrap();
+        assert_eq!(rendered.text, "Hi Ada");
+        assert_eq!(
+            rendered.warnings,
+            vec![
+                "Ignoring unused variable 'alpha'".to_string(),
+                "Ignoring unused variable 'zeta'".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_non_placeholder_braces_are_literal() {
+        let template = PromptTemplate::new("t", "{{ not valid! }} {{x}} {{");
+        assert_eq!(template.placeholders(), vec!["x".to_string()]);
+        let rendered = template.render(&vars(&[("x", "1")])).unwrap();
+        assert_eq!(rendered.text, "{{ not valid! }} 1 {{");
+    }
+
+    #[test]
+    fn test_parse_assignments() {
+        let parsed =
+            parse_assignments(r#"lang=rust  topic="error handling" q="say \"hi\"""#).unwrap();
+        assert_eq!(parsed["lang"], "rust");
+        assert_eq!(parsed["topic"], "error handling");
+        assert_eq!(parsed["q"], "say \"hi\"");
+
+        assert!(parse_assignments("novalue").is_err());
+        assert!(parse_assignments("k=\"open").is_err());
+        assert!(parse_assignments("   ").unwrap().is_empty());
+    }
+}
@@ -1,2 +1,7 @@
+#[cfg(feature = "server")]
+pub use self::picker::*;
 pub use self::ui::*;
+#[cfg(feature = "server")]
+mod picker;
+#[allow(clippy::module_inception)]
 mod ui;
@@ -0,0 +1,305 @@
+//! The session picker behind `l`: every saved session, narrowed as you type.
+//! Titles and models are matched fuzzily in memory on each keystroke; once
+//! typing pauses, longer queries also search message contents in the
+//! database, whose hits bring a snippet and the message to open at.
+
+use std::time::{Duration, Instant};
+
+use crate::database::{ContentHit, SessionMatch, MIN_CONTENT_QUERY};
+use crate::models::ChatSession;
+
+/// How long typing must pause before the message contents are searched.
+pub const SEARCH_DEBOUNCE: Duration = Duration::from_millis(150);
+
+/// Content matches shown at most.
+pub const CONTENT_RESULTS: i64 = 50;
+
+pub struct SessionPicker {
+    /// Most recently updated first.
+    sessions: Vec<ChatSession>,
+    query: String,
+    typed_at: Option<Instant>,
+    /// The last content search and what it found. Kept while a newer one
+    /// waits for typing to pause, so results don't flicker.
+    searched: String,
+    found: Vec<SessionMatch>,
+    selected: usize,
+}
+
+/// One row of the picker.
+#[derive(Debug)]
+pub struct PickerEntry<'a> {
+    pub session: &'a ChatSession,
+    /// Char positions of the query in the title and the model.
+    pub title_matches: Vec<usize>,
+    pub model_matches: Vec<usize>,
+    pub hit: Option<&'a ContentHit>,
+}
+
+impl SessionPicker {
+    pub fn new(sessions: Vec<ChatSession>) -> Self {
+        Self {
+            sessions,
+            query: String::new(),
+            typed_at: None,
+            searched: String::new(),
+            found: Vec::new(),
+            selected: 0,
+        }
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.query.push(c);
+        self.typed(Instant::now());
+    }
+
+    pub fn pop(&mut self) {
+        if self.query.pop().is_some() {
+            self.typed(Instant::now());
+        }
+    }
+
+    fn typed(&mut self, at: Instant) {
+        self.typed_at = Some(at);
+        self.selected = 0;
+        if self.query.trim().chars().count() < MIN_CONTENT_QUERY {
+            self.searched.clear();
+            self.found.clear();
+        }
+    }
+
+    /// The query to search message contents for, once typing has paused
+    /// for [`SEARCH_DEBOUNCE`] and it differs from the last one searched.
+    pub fn due_search(&self, now: Instant) -> Option<String> {
+        let query = self.query.trim();
+        let paused = self
+            .typed_at
+            .is_some_and(|at| now.duration_since(at) >= SEARCH_DEBOUNCE);
+        (paused && query.chars().count() >= MIN_CONTENT_QUERY && query != self.searched)
+            .then(|| query.to_string())
+    }
+
+    /// Takes the results of searching for `query`, unless the query has
+    /// been cut short below the content search length since.
+    pub fn found(&mut self, query: String, found: Vec<SessionMatch>) {
+        if self.query.trim().chars().count() >= MIN_CONTENT_QUERY {
+            self.searched = query;
+            self.found = found;
+        }
+    }
+
+    /// Sessions whose title or model fuzzily matches the query, closer
+    /// matches first, then those only the content search found.
+    pub fn entries(&self) -> Vec<PickerEntry<'_>> {
+        let hit_for = |id: &str| {
+            self.found
+                .iter()
+                .find(|found| found.session.id == id)
+                .and_then(|found| found.hit.as_ref())
+        };
+        let mut ranked: Vec<(usize, PickerEntry)> = self
+            .sessions
+            .iter()
+            .filter_map(|session| {
+                let title = session.title.as_deref().unwrap_or_default();
+                let title_match = fuzzy_match(&self.query, title);
+                let model_match = fuzzy_match(&self.query, &session.model);
+                let hit = hit_for(&session.id);
+                let gaps = title_match
+                    .iter()
+                    .chain(&model_match)
+                    .map(|positions| gaps(positions))
+                    .min();
+                let gaps = match (gaps, hit) {
+                    (Some(gaps), _) => gaps,
+                    (None, Some(_)) => usize::MAX,
+                    (None, None) => return None,
+                };
+                Some((
+                    gaps,
+                    PickerEntry {
+                        session,
+                        title_matches: title_match.unwrap_or_default(),
+                        model_matches: model_match.unwrap_or_default(),
+                        hit,
+                    },
+                ))
+            })
+            .collect();
+        // Stable, so equally close matches stay most recent first
+        ranked.sort_by_key(|(gaps, _)| *gaps);
+
+        let mut entries: Vec<PickerEntry> = ranked.into_iter().map(|(_, entry)| entry).collect();
+        for found in &self.found {
+            if !entries
+                .iter()
+                .any(|entry| entry.session.id == found.session.id)
+            {
+                entries.push(PickerEntry {
+                    session: &found.session,
+                    title_matches: Vec::new(),
+                    model_matches: Vec::new(),
+                    hit: found.hit.as_ref(),
+                });
+            }
+        }
+        entries
+    }
+
+    /// Swaps in a newer list of the saved sessions, keeping the query and,
+    /// as far as it still exists, the selection.
+    pub fn refresh(&mut self, sessions: Vec<ChatSession>) {
+        let chosen = self.choice().map(|(session_id, _)| session_id);
+        self.sessions = sessions;
+        let entries = self.entries();
+        self.selected = chosen
+            .and_then(|id| entries.iter().position(|entry| entry.session.id == id))
+            .unwrap_or(0);
+    }
+
+    pub fn move_selection(&mut self, step: isize) {
+        let count = self.entries().len();
+        if count == 0 {
+            self.selected = 0;
+            return;
+        }
+        self.selected = self.selected.saturating_add_signed(step).min(count - 1);
+    }
+
+    /// The session to open and the message to open it at.
+    pub fn choice(&self) -> Option<(String, Option<i64>)> {
+        self.entries().get(self.selected).map(|entry| {
+            (
+                entry.session.id.clone(),
+                entry.hit.map(|hit| hit.message_id),
+            )
+        })
+    }
+}
+
+/// The char positions in `text` where the characters of `query` appear in
+/// order, ignoring case and spaces in the query; `None` when they don't.
+/// An empty query matches everything.
+pub fn fuzzy_match(query: &str, text: &str) -> Option<Vec<usize>> {
+    let mut wanted = query
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .peekable();
+    let mut positions = Vec::new();
+    for (index, c) in text.chars().enumerate() {
+        let Some(next) = wanted.peek() else { break };
+        if c.to_lowercase().eq(std::iter::once(*next)) {
+            positions.push(index);
+            wanted.next();
+        }
+    }
+    wanted.peek().is_none().then_some(positions)
+}
+
+/// How scattered a match is: 0 when the query appears as written.
+fn gaps(positions: &[usize]) -> usize {
+    positions.windows(2).map(|pair| pair[1] - pair[0] - 1).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Snippet;
+
+    fn session(title: &str, model: &str) -> ChatSession {
+        ChatSession::new(model.to_string(), Some(title.to_string()))
+    }
+
+    #[test]
+    fn test_fuzzy_match_finds_characters_in_order() {
+        assert_eq!(fuzzy_match("rl", "Rust lifetimes"), Some(vec![0, 5]));
+        assert_eq!(
+            fuzzy_match("RUST L", "rust lifetimes"),
+            Some(vec![0, 1, 2, 3, 5])
+        );
+        assert_eq!(fuzzy_match("", "anything"), Some(vec![]));
+        assert_eq!(fuzzy_match("tsur", "Rust"), None);
+    }
+
+    #[test]
+    fn test_refreshing_keeps_the_query_and_selection() {
+        let rust = session("Rust lifetimes", "grok-3-mini");
+        let grocery = session("Grocery list", "grok-3");
+        let mut picker = SessionPicker::new(vec![rust.clone(), grocery.clone()]);
+        picker.push('r');
+        picker.move_selection(1);
+        assert_eq!(picker.choice().unwrap().0, grocery.id);
+
+        // A session started elsewhere comes first, as the most recent
+        let new = session("Road trip", "grok-3");
+        picker.refresh(vec![new.clone(), rust, grocery.clone()]);
+        assert_eq!(picker.query(), "r");
+        assert_eq!(picker.entries().len(), 3);
+        assert_eq!(picker.choice().unwrap().0, grocery.id);
+
+        picker.refresh(vec![new.clone()]);
+        assert_eq!(picker.choice().unwrap().0, new.id);
+    }
+
+    #[test]
+    fn test_entries_rank_close_matches_and_add_content_hits() {
+        let sessions = vec![
+            session("Rust lifetimes", "grok-3-mini"),
+            session("Grocery list", "grok-3"),
+            session("Trip planning", "grok-3"),
+        ];
+        let trip = sessions[2].clone();
+        let mut picker = SessionPicker::new(sessions);
+
+        for c in "lis".chars() {
+            picker.push(c);
+        }
+        let titles: Vec<_> = picker
+            .entries()
+            .iter()
+            .map(|entry| entry.session.title.clone().unwrap())
+            .collect();
+        // "Grocery list" holds it as written, "Rust lifetimes" scattered
+        assert_eq!(titles, ["Grocery list", "Rust lifetimes"]);
+        assert_eq!(picker.due_search(Instant::now()), None);
+
+        picker.push('t');
+        let later = Instant::now() + SEARCH_DEBOUNCE;
+        assert_eq!(picker.due_search(later), Some("list".to_string()));
+        let hit = ContentHit {
+            message_id: 7,
+            snippet: Snippet {
+                text: "a packing list".to_string(),
+                highlights: std::iter::once(10..14).collect(),
+            },
+        };
+        picker.found(
+            "list".to_string(),
+            vec![SessionMatch {
+                session: trip.clone(),
+                hit: Some(hit),
+            }],
+        );
+        assert_eq!(picker.due_search(later), None);
+
+        let entries = picker.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].session.id, trip.id);
+        picker.move_selection(5);
+        assert_eq!(picker.choice(), Some((trip.id.clone(), Some(7))));
+
+        // Dropping below the content search length forgets its results
+        picker.pop();
+        assert!(picker.entries().iter().all(|entry| entry.hit.is_none()));
+        assert_eq!(picker.selected(), 0);
+    }
+}
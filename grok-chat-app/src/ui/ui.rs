@@ -1,6 +1,6 @@
 use anyhow::Result;
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
@@ -9,31 +9,280 @@ use ratatui::{
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Wrap},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap},
     Frame,
 };
-use std::io::{self, Stdout};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Stdout, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
+use futures_util::stream::{self, BoxStream, StreamExt};
+
+use crate::agents::Agent;
+use crate::client::{
+    is_context_length_exceeded, is_offline, split_model, BreakerState, ChatResponse, ChatService,
+    DryRun, ModelHealth, ModelMigration, RetiredModel, DEFAULT_PROVIDER,
+};
 use crate::config::Config;
-use crate::models::{ApiMessage, Message, MessageRole};
+use crate::context::{
+    pinned_count, session_context, session_context_dropping, ContextRetry, DEFAULT_TOKEN_BUDGET,
+};
+use crate::diff::{word_diff, DiffSpan};
+use crate::hooks::{Hook, HookOutput, Hooks};
+use crate::i18n::{is_affirmative, tr, tr_args};
+use crate::journal::{
+    journal_dir, orphaned_journals, Journal, JournalEntry, OrphanedJournal, RecoveredSession,
+};
+use crate::models::{
+    Annotation, ApiChatRequest, ApiMessage, Conversation, Message, MessageRole, Rating,
+    StreamChunk, UsageStats,
+};
+use crate::postprocess::ProcessedResponse;
+use crate::preflight::startup_config;
+use crate::pricing::{Cost, CostGuard, PricingTable, Verdict};
+use crate::render::{find_images, Table};
+use crate::sanitize::sanitize;
+use crate::templates::{parse_assignments, PromptTemplate};
+
+#[cfg(feature = "server")]
+use super::{SessionPicker, CONTENT_RESULTS};
+#[cfg(feature = "server")]
+use crate::audit::Actor;
+#[cfg(feature = "server")]
+use crate::database::{Database, Snippet};
+#[cfg(feature = "server")]
+use crate::media::{MediaStore, StoredMedia};
+#[cfg(feature = "server")]
+use crate::models::{ChatSession, SessionSnapshot};
+#[cfg(feature = "server")]
+use crate::outbox::Outbox;
+
+/// How often queued messages are retried after the API became unreachable.
+const OFFLINE_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How many edits `u` can take back.
+const UNDO_LIMIT: usize = 50;
+
+/// Normal mode keys in the help overlay, with the string keys of their
+/// descriptions.
+const NORMAL_MODE_KEYS: &[(&str, &str)] = &[
+    ("i", "help.insert"),
+    ("q", "help.quit"),
+    ("h", "help.help"),
+    ("c", "help.new_session"),
+    ("m", "help.model"),
+    ("o", "help.offline"),
+    ("l", "help.sessions"),
+    ("↑/↓", "help.select"),
+    ("v", "help.open_image"),
+    ("P", "help.pin"),
+    ("+ / -", "help.rate"),
+    ("R", "help.regenerate"),
+    ("%", "help.diff"),
+    ("u / Ctrl-r", "help.undo"),
+    ("C", "help.compare"),
+    ("Tab", "help.continuing"),
+];
+
+/// Insert mode keys and slash commands in the help overlay.
+const INSERT_MODE_KEYS: &[(&str, &str)] = &[
+    ("Enter", "help.send"),
+    ("/template <name> k=v", "help.template"),
+    ("/prompt [name]", "help.prompt"),
+    ("/agent [name]", "help.agent"),
+    ("/newfrom <template>", "help.newfrom"),
+    ("/note <text> #label", "help.note"),
+    ("/dryrun <message>", "help.dry_run"),
+    ("/snapshot <label>", "help.snapshot"),
+    ("/restore", "help.restore"),
+    ("/<name> args", "help.hook"),
+    ("Esc", "help.normal"),
+];
+
+/// Lines PgUp and PgDn scroll a popup by.
+const POPUP_PAGE: u16 = 10;
 
 type AppTerminal = ratatui::Terminal<CrosstermBackend<Stdout>>;
 
 pub struct ChatUI {
     terminal: AppTerminal,
-    api_key: String,
+    chat_service: ChatService,
+    #[cfg(feature = "server")]
+    database: Option<Database>,
+    #[cfg(feature = "server")]
+    outbox: Option<Outbox>,
+    /// Where new messages and settings go until they are in the database, so
+    /// an unexpected exit doesn't lose them; see `crate::journal`.
+    journal: Option<Journal>,
     current_session_id: Option<String>,
+    /// Messages are queued rather than sent while set.
+    offline: bool,
+    /// Offline because the API was unreachable rather than by choice, so
+    /// queued messages are retried every `OFFLINE_RETRY_INTERVAL`.
+    retry_offline: bool,
+    last_retry: Instant,
     messages: Vec<Message>,
+    /// Message under the cursor in normal mode, for pinning.
+    selected_message: Option<usize>,
     input_buffer: String,
     input_mode: InputMode,
     available_models: Vec<String>,
     selected_model: String,
     status_message: String,
     show_help: bool,
-    system_prompt: String,
-    max_tokens: i32,
-    temperature: f32,
+    /// The request shown by `/dryrun`, or the changes shown by `%`.
+    popup: Option<Popup>,
+    /// Open while choosing a saved session with `l`.
+    #[cfg(feature = "server")]
+    picker: Option<SessionPicker>,
+    /// Open while choosing a snapshot to go back to with `/restore`.
+    #[cfg(feature = "server")]
+    restore_picker: Option<SnapshotChoice>,
+    pricing: PricingTable,
+    session_cost: Cost,
+    /// Preset chosen with `/prompt`; `None` uses the default preset, if any.
+    #[cfg(feature = "server")]
+    prompt_preset: Option<String>,
+    /// Agent chosen with `/agent`, or the session's default one.
+    agent: Option<Agent>,
+    /// Set while two models answer side by side (`C`).
+    compare: Option<Compare>,
+    /// Edits to the conversation, for `u` and `Ctrl-r`.
+    history: UndoStack<Edit>,
+    /// Replies replaced with `R`, oldest first, by the index of the reply
+    /// that replaced them.
+    generations: HashMap<usize, Vec<String>>,
+    kept_generations: usize,
+    /// Decides where requests to a retired model go instead.
+    config: Config,
+    /// Scripts answering other slash commands; see `crate::hooks`.
+    hooks: Option<Hooks>,
+    /// A new or changed hook, with its arguments, waiting for `y` to run.
+    confirm_hook: Option<(Hook, String)>,
+    /// A message over the cost threshold, waiting for `y` to send.
+    confirm_cost: Option<String>,
+    /// Why the last message was rejected as too long for the model, while
+    /// `y` to send it again with less history is awaited.
+    confirm_shed: Option<String>,
+    /// Images attached to the loaded session's messages, by message id.
+    attachments: HashMap<i64, Vec<Attachment>>,
+    /// The database's `data_version` when last looked at, to notice writes
+    /// by another process such as `serve`.
+    #[cfg(feature = "server")]
+    data_version: Option<i64>,
+}
+
+/// An image attached to a message, shown as a placeholder that `v` opens.
+#[derive(Debug, Clone)]
+struct Attachment {
+    path: PathBuf,
+    mime_type: String,
+    size: u64,
+    dimensions: Option<(u32, u32)>,
+}
+
+/// A scrollable popup over the conversation; `Esc` closes it.
+struct Popup {
+    title: &'static str,
+    lines: Vec<Line<'static>>,
+    scroll: u16,
+}
+
+/// The snapshots `/restore` offers, newest first.
+#[cfg(feature = "server")]
+struct SnapshotChoice {
+    snapshots: Vec<SessionSnapshot>,
+    selected: usize,
+}
+
+/// A change to the conversation that can be taken back. Applying an edit
+/// returns its inverse.
+enum Edit {
+    /// The pin on the message at this index was flipped.
+    Pin(usize),
+    /// The thread was replaced, by `c` or by leaving compare mode.
+    Thread(Box<Thread>),
+    /// The reply at this index was regenerated; holds the one it replaced.
+    Reply(usize, Box<Message>),
+}
+
+/// The parts of the conversation a replaced thread took with it.
+struct Thread {
+    /// What replaced it, for the undo and redo status, as a string key.
+    reason: &'static str,
+    session_id: Option<String>,
+    messages: Vec<Message>,
+    generations: HashMap<usize, Vec<String>>,
+    model: String,
+    cost: Cost,
+    compare: Option<Compare>,
+}
+
+impl Edit {
+    fn describe(&self) -> &'static str {
+        match self {
+            Edit::Pin(_) => tr("tui.edit_pin"),
+            Edit::Thread(thread) => tr(thread.reason),
+            Edit::Reply(..) => tr("tui.edit_regenerate"),
+        }
+    }
+}
+
+/// Done and undone edits. The oldest are dropped past `UNDO_LIMIT`, and
+/// recording a new edit forgets what was undone.
+struct UndoStack<T> {
+    done: VecDeque<T>,
+    undone: Vec<T>,
+}
+
+impl<T> UndoStack<T> {
+    fn new() -> Self {
+        Self {
+            done: VecDeque::new(),
+            undone: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, edit: T) {
+        self.undone.clear();
+        self.push_done(edit);
+    }
+
+    fn push_done(&mut self, edit: T) {
+        if self.done.len() == UNDO_LIMIT {
+            self.done.pop_front();
+        }
+        self.done.push_back(edit);
+    }
+}
+
+/// Two models answering the same prompts, each with its own thread.
+struct Compare {
+    panes: [ComparePane; 2],
+    /// The pane whose thread carries on when compare mode ends (`Tab`).
+    continuing: usize,
+}
+
+struct ComparePane {
+    model: String,
+    messages: Vec<Message>,
+    /// Usage and time to the last chunk of the newest reply.
+    usage: Option<UsageStats>,
+    latency: Option<Duration>,
+}
+
+impl ComparePane {
+    fn new(model: String, messages: Vec<Message>) -> Self {
+        Self {
+            model,
+            messages,
+            usage: None,
+            latency: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -43,37 +292,91 @@ enum InputMode {
 }
 
 impl ChatUI {
-    pub async fn new() -> Result<Self> {
+    /// `model` (a name or alias) replaces the configured default model.
+    /// `offline` starts with sending switched off.
+    pub async fn new(model: Option<&str>, offline: bool) -> Result<Self> {
         let config = Config::from_env()?;
-        let api_key = config.xai_api_key().to_string();
-
-        let terminal = setup_terminal()?;
+        let selected_model = config.resolve_model(model)?;
+        let chat_service = ChatService::new(&config)?;
+        let pricing = PricingTable::load(&config)?;
+        #[cfg(feature = "server")]
+        let database = Database::new(&config).await.ok();
+        #[cfg(feature = "server")]
+        let outbox = database
+            .clone()
+            .map(|database| Outbox::new(database, chat_service.clone()));
+        let journal = journal_dir().and_then(|dir| Journal::create(&dir).ok());
 
-        let available_models = vec![
+        let mut available_models = vec![
             "grok-4-0709".to_string(),
             "grok-4-fast-reasoning".to_string(),
             "grok-4-fast-non-reasoning".to_string(),
             "grok-3".to_string(),
             "grok-3-mini".to_string(),
         ];
+        // Other providers' configured, aliased and selected models join the
+        // picker so cycling reaches them
+        let provider_models = config.providers().iter().flat_map(|(name, provider)| {
+            provider
+                .models
+                .iter()
+                .map(move |model| format!("{}/{}", name, model))
+        });
+        let extra_models = provider_models
+            .chain(config.model_aliases().values().cloned())
+            .chain([selected_model.clone()])
+            .collect::<Vec<_>>();
+        for model in extra_models {
+            if !available_models.contains(&model) {
+                available_models.push(model);
+            }
+        }
+        group_by_provider(&mut available_models);
 
-        let selected_model = config.default_model().to_string();
-        let system_prompt = "You are Grok, a helpful and maximally truthful AI built by xAI, not based on any other companies and their models.".to_string();
+        let terminal = setup_terminal()?;
 
         Ok(Self {
             terminal,
-            api_key,
+            chat_service,
+            #[cfg(feature = "server")]
+            database,
+            #[cfg(feature = "server")]
+            outbox,
+            journal,
             current_session_id: None,
+            offline,
+            retry_offline: false,
+            last_retry: Instant::now(),
             messages: Vec::new(),
+            selected_message: None,
             input_buffer: String::new(),
             input_mode: InputMode::Insert,
             available_models,
             selected_model,
-            status_message: "Ready to chat! Type your message and press Enter to send.".to_string(),
+            status_message: tr("tui.ready").to_string(),
             show_help: false,
-            system_prompt,
-            max_tokens: 2048,
-            temperature: 0.7,
+            popup: None,
+            #[cfg(feature = "server")]
+            picker: None,
+            #[cfg(feature = "server")]
+            restore_picker: None,
+            pricing,
+            session_cost: Cost::zero(),
+            #[cfg(feature = "server")]
+            prompt_preset: None,
+            agent: None,
+            compare: None,
+            history: UndoStack::new(),
+            generations: HashMap::new(),
+            kept_generations: config.kept_generations(),
+            config,
+            hooks: Hooks::from_config(),
+            confirm_hook: None,
+            confirm_cost: None,
+            confirm_shed: None,
+            attachments: HashMap::new(),
+            #[cfg(feature = "server")]
+            data_version: None,
         })
     }
 
@@ -81,19 +384,82 @@ impl ChatUI {
         self.render()?;
 
         loop {
+            if self.retry_offline && self.last_retry.elapsed() >= OFFLINE_RETRY_INTERVAL {
+                self.go_online().await;
+                self.render()?;
+            }
+            if self.search_sessions().await {
+                self.render()?;
+            }
+            if self.follow_database().await {
+                self.render()?;
+            }
+
             if crossterm::event::poll(std::time::Duration::from_millis(100))? {
                 if let Event::Key(key) = event::read()? {
+                    // Confirmations and overlays take keys before the modes do
+                    if self.overlay_key(key.code).await? {
+                        self.render()?;
+                        continue;
+                    }
                     match self.input_mode {
                         InputMode::Insert => match key.code {
-                            KeyCode::Enter => {
-                                if !self.input_buffer.trim().is_empty() {
-                                    self.send_message().await?;
+                            KeyCode::Enter if !self.input_buffer.trim().is_empty() => {
+                                if let Some(args) = slash_argument(&self.input_buffer, "/template")
+                                {
+                                    let args = args.to_string();
+                                    self.apply_template(&args).await;
+                                } else if let Some(name) =
+                                    slash_argument(&self.input_buffer, "/prompt")
+                                {
+                                    let name = name.trim().to_string();
+                                    self.input_buffer.clear();
+                                    self.choose_prompt(&name).await;
+                                } else if let Some(name) =
+                                    slash_argument(&self.input_buffer, "/agent")
+                                {
+                                    let name = name.trim().to_string();
+                                    self.input_buffer.clear();
+                                    self.choose_agent(&name).await;
+                                } else if let Some(name) =
+                                    slash_argument(&self.input_buffer, "/newfrom")
+                                {
+                                    let name = name.trim().to_string();
+                                    self.input_buffer.clear();
+                                    self.new_session_from(&name).await?;
+                                } else if let Some(text) =
+                                    slash_argument(&self.input_buffer, "/note")
+                                {
+                                    let text = text.to_string();
+                                    self.input_buffer.clear();
+                                    self.note(&text).await;
+                                } else if let Some(message) =
+                                    slash_argument(&self.input_buffer, "/dryrun")
+                                {
+                                    let message = message.trim().to_string();
+                                    self.input_buffer.clear();
+                                    self.show_dry_run(message).await;
+                                } else if let Some(label) =
+                                    slash_argument(&self.input_buffer, "/snapshot")
+                                {
+                                    let label = label.trim().to_string();
+                                    self.input_buffer.clear();
+                                    self.take_snapshot(&label).await;
+                                } else if slash_argument(&self.input_buffer, "/restore").is_some() {
+                                    self.input_buffer.clear();
+                                    self.open_restore().await;
+                                } else if let Some((hook, args)) = self.find_hook() {
+                                    self.input_buffer.clear();
+                                    self.start_hook(hook, args).await?;
+                                } else if self.compare.is_some() {
+                                    self.send_compare().await?;
+                                } else {
+                                    self.send_message(false).await?;
                                 }
                             }
                             KeyCode::Esc => {
                                 self.input_mode = InputMode::Normal;
-                                self.status_message =
-                                    "Press 'i' to insert, 'h' for help, 'q' to quit".to_string();
+                                self.status_message = tr("tui.normal_mode").to_string();
                             }
                             KeyCode::Backspace => {
                                 self.input_buffer.pop();
@@ -113,8 +479,7 @@ impl ChatUI {
                             KeyCode::Char('q') => break,
                             KeyCode::Char('i') => {
                                 self.input_mode = InputMode::Insert;
-                                self.status_message =
-                                    "Insert mode: Type your message and press Enter".to_string();
+                                self.status_message = tr("tui.insert_mode").to_string();
                             }
                             KeyCode::Char('h') => {
                                 self.show_help = !self.show_help;
@@ -125,8 +490,28 @@ impl ChatUI {
                             KeyCode::Char('m') => {
                                 self.cycle_model();
                             }
-                            KeyCode::Char('l') => {
-                                self.load_session_list()?;
+                            KeyCode::Char('C') => self.toggle_compare(),
+                            KeyCode::Tab if self.compare.is_some() => self.switch_continuing(),
+                            KeyCode::Char('o') if self.offline => self.go_online().await,
+                            KeyCode::Char('o') => {
+                                self.offline = true;
+                                self.retry_offline = false;
+                                self.status_message = tr("tui.offline").to_string();
+                            }
+                            KeyCode::Char('l') => self.open_picker().await,
+                            // Selection and pins belong to the single thread
+                            _ if self.compare.is_some() => {}
+                            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+                            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+                            KeyCode::Char('v') => self.open_images(),
+                            KeyCode::Char('P') => self.toggle_pin().await,
+                            KeyCode::Char('+') => self.rate(Rating::Up).await,
+                            KeyCode::Char('-') => self.rate(Rating::Down).await,
+                            KeyCode::Char('R') => self.regenerate().await?,
+                            KeyCode::Char('%') => self.show_diff().await,
+                            KeyCode::Char('u') => self.undo().await,
+                            KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                self.redo().await
                             }
                             _ => {}
                         },
@@ -136,136 +521,2013 @@ impl ChatUI {
             }
         }
 
+        // A normal exit leaves nothing to recover
+        if let Some(journal) = self.journal.take() {
+            let _ = journal.remove();
+        }
         Ok(())
     }
 
-    async fn send_message(&mut self) -> Result<()> {
+    /// Hands `code` to whatever is waiting on a key ahead of the input
+    /// modes: a confirmation, a popup, or a picker. Returns whether one took it.
+    async fn overlay_key(&mut self, code: KeyCode) -> Result<bool> {
+        if let Some((hook, args)) = self.confirm_hook.take() {
+            self.confirm_and_run_hook(hook, args, code).await?;
+        } else if let Some(message) = self.confirm_cost.take() {
+            self.confirm_and_send(message, code).await?;
+        } else if let Some(error) = self.confirm_shed.take() {
+            self.confirm_and_shed(error, code).await?;
+        } else if self.popup.is_some() {
+            self.scroll_popup(code);
+        } else if self.picking() {
+            self.pick_session(code).await;
+        } else if self.choosing_snapshot() {
+            self.pick_snapshot(code).await;
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Sends the input. One that could cost more than the cost threshold
+    /// waits for a `y` first in `block` mode, unless `confirmed`.
+    async fn send_message(&mut self, confirmed: bool) -> Result<()> {
         let user_message = self.input_buffer.clone();
         self.input_buffer.clear();
 
         // Add user message to UI immediately
         let session_id = self
             .current_session_id
-            .clone()
-            .unwrap_or_else(|| Uuid::new_v4().to_string());
-        let user_msg = Message::user(session_id.clone(), user_message.clone());
-        self.messages.push(user_msg);
+            .get_or_insert_with(|| Uuid::new_v4().to_string())
+            .clone();
+        if self.offline {
+            self.queue_message(session_id, user_message).await;
+            return Ok(());
+        }
+        let user_msg = Message::user(
+            session_id.clone(),
+            self.chat_service.stored_text(user_message.clone()),
+        );
+        self.messages.push(user_msg.clone());
+
+        // Prepare messages for API: pinned messages first, then recent history
+        let api_messages = Conversation::new()
+            .history(session_context(&self.messages, DEFAULT_TOKEN_BUDGET))
+            .into_messages();
+        let (system_prompt, fallback) = self.preset_prompt().await;
+
+        let guard = CostGuard::from_config(&self.config);
+        let mut status = tr("tui.thinking").to_string();
+        if !guard.is_off() {
+            let request = self.grok_request(api_messages.clone(), system_prompt.clone());
+            let estimate = self.chat_service.estimate_cost(&request, &self.pricing);
+            match guard.admit(&estimate, confirmed) {
+                Err(e) => {
+                    self.messages.pop();
+                    self.status_message = tr_args("tui.cost_confirm", &[("error", &e)]);
+                    self.confirm_cost = Some(user_message);
+                    return Ok(());
+                }
+                Ok(Verdict::Warn) => {
+                    status = tr_args(
+                        "tui.thinking_costly",
+                        &[
+                            ("cost", &estimate.cost),
+                            ("threshold", &format!("{:.2}", guard.threshold_usd)),
+                        ],
+                    );
+                }
+                Ok(_) => {}
+            }
+        }
+        self.append_journal(JournalEntry::Message { message: user_msg });
 
         // Show that we're processing
-        self.status_message = "🤔 Grok is thinking...".to_string();
+        self.status_message = status;
         self.render()?;
 
-        // Prepare messages for API (including conversation history)
-        let api_messages: Vec<ApiMessage> = self
-            .messages
-            .iter()
-            .map(|msg| ApiMessage {
-                role: msg.role.to_string(),
-                content: msg.content.clone(),
-            })
-            .collect();
+        let shed = self.config.context_retry() == ContextRetry::Auto;
+        self.finish_send(
+            session_id,
+            user_message,
+            api_messages,
+            system_prompt,
+            fallback,
+            shed,
+        )
+        .await
+    }
+
+    /// Sends `api_messages`, ending with the input just added to the
+    /// conversation, and adds the reply. With `shed`, a conversation too long
+    /// for the model is sent again with less history; in `ask` mode, `y` is
+    /// awaited for that instead.
+    async fn finish_send(
+        &mut self,
+        session_id: String,
+        user_message: String,
+        api_messages: Vec<ApiMessage>,
+        system_prompt: Option<String>,
+        fallback: Option<String>,
+        shed: bool,
+    ) -> Result<()> {
+        let pinned = pinned_count(&self.messages);
+        match self
+            .send_to_grok_api(api_messages, system_prompt, pinned, shed)
+            .await
+        {
+            Ok((response_content, usage, redactions, effects, migration, dropped)) => {
+                if let Some(migration) = &migration {
+                    self.migrate_model(migration).await;
+                }
+                if let Some(usage) = usage {
+                    self.session_cost = self.session_cost
+                        + self.pricing.estimate_cost(&usage, &self.selected_model);
+                }
 
-        // Send to API using direct HTTP client (similar to main.rs)
-        match self.send_to_grok_api(api_messages).await {
-            Ok(response_content) => {
                 // Add assistant response to UI
-                let assistant_msg = Message::assistant(
+                let mut assistant_msg = Message::assistant(
                     session_id,
                     response_content.clone(),
                     Some(self.selected_model.clone()),
                 );
-                self.messages.push(assistant_msg);
-
-                self.status_message = "✅ Message sent! Press 'i' to continue chatting.".to_string();
+                assistant_msg.agent = self.agent.as_ref().map(|agent| agent.name.clone());
+                self.messages.push(assistant_msg.clone());
+                self.append_journal(JournalEntry::Message {
+                    message: assistant_msg,
+                });
+                self.store_last_exchange().await;
+
+                self.status_message = match (redactions, fallback) {
+                    (_, Some(warning)) => format!("⚠️ {}", warning),
+                    _ if !effects.is_empty() => format!("📎 {}", effects.join("; ")),
+                    (0, None) => tr("tui.sent").to_string(),
+                    (1, None) => tr("tui.sent_redacted_one").to_string(),
+                    (n, None) => tr_args("tui.sent_redacted", &[("count", &n)]),
+                };
+                if dropped > 0 {
+                    self.status_message = tr_args("tui.context_shed", &[("count", &dropped)]);
+                }
+                if let Some(migration) = migration {
+                    self.status_message = tr_args(
+                        "tui.model_migrated",
+                        &[("from", &migration.from), ("to", &migration.to)],
+                    );
+                }
+            }
+            Err(e) if is_offline(&e) => {
+                // Nothing reached the API, so the message waits in the outbox
+                self.messages.pop();
+                self.offline = true;
+                self.retry_offline = true;
+                self.last_retry = Instant::now();
+                self.queue_message(session_id, user_message).await;
+            }
+            Err(e)
+                if !shed
+                    && self.config.context_retry() == ContextRetry::Ask
+                    && is_context_length_exceeded(&e) =>
+            {
+                self.status_message = tr("tui.context_confirm").to_string();
+                self.confirm_shed = Some(e.to_string());
             }
             Err(e) => {
                 // Show error in UI
                 let error_msg = Message::assistant(
                     session_id,
-                    format!("❌ Error: {}", e),
+                    tr_args("tui.reply_error", &[("error", &e)]),
                     Some("error".to_string()),
                 );
                 self.messages.push(error_msg);
-                self.status_message = "❌ Error occurred. Check your API key and try again.".to_string();
+                self.status_message = match e.downcast_ref::<RetiredModel>() {
+                    Some(retired) => retired_status(retired),
+                    None => tr("tui.send_failed").to_string(),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the last message of the conversation, rejected as too long for
+    /// the model, again with less history on a `y`; otherwise the error
+    /// stands as the reply.
+    async fn confirm_and_shed(&mut self, error: String, key: KeyCode) -> Result<()> {
+        let Some(last) = self.messages.last().cloned() else {
+            return Ok(());
+        };
+        if !matches!(key, KeyCode::Char(c) if is_affirmative(&c.to_string())) {
+            self.messages.push(Message::assistant(
+                last.session_id,
+                tr_args("tui.reply_error", &[("error", &error)]),
+                Some("error".to_string()),
+            ));
+            self.status_message = tr("tui.send_failed").to_string();
+            return Ok(());
+        }
+
+        let api_messages = Conversation::new()
+            .history(session_context(&self.messages, DEFAULT_TOKEN_BUDGET))
+            .into_messages();
+        let (system_prompt, fallback) = self.preset_prompt().await;
+        self.status_message = tr("tui.thinking").to_string();
+        self.render()?;
+        self.finish_send(
+            last.session_id,
+            last.content,
+            api_messages,
+            system_prompt,
+            fallback,
+            true,
+        )
+        .await
+    }
+
+    /// Returns the reply, its usage, how many secrets were redacted, the
+    /// side effects of post-processing the reply, the migration made if the
+    /// model was retired and how many messages were dropped to fit the
+    /// model's context window.
+    async fn send_to_grok_api(
+        &self,
+        messages: Vec<ApiMessage>,
+        system_prompt: Option<String>,
+        pinned: usize,
+        shed: bool,
+    ) -> Result<(
+        String,
+        Option<UsageStats>,
+        usize,
+        Vec<String>,
+        Option<ModelMigration>,
+        usize,
+    )> {
+        let mut request = self.grok_request(messages, system_prompt);
+        let redactions = self.chat_service.redact(&mut request);
+        let (response, migration, dropped) = self
+            .chat_service
+            .send_request_shedding(request, None, &self.config, pinned, shed)
+            .await?;
+
+        match response {
+            ChatResponse::Complete(response) => Ok((
+                response.get_content()?,
+                response.get_usage().cloned(),
+                redactions,
+                response.effects,
+                migration,
+                dropped,
+            )),
+            ChatResponse::Stream(_) => Err(anyhow::anyhow!("Unexpected streaming response")),
+        }
+    }
+
+    fn grok_request(
+        &self,
+        messages: Vec<ApiMessage>,
+        system_prompt: Option<String>,
+    ) -> ApiChatRequest {
+        let mut request = ApiChatRequest::builder()
+            .messages(messages)
+            .model(&self.selected_model)
+            .stream(false)
+            .build();
+        request.system_prompt = system_prompt;
+        if let Some(agent) = &self.agent {
+            agent.apply(&mut request);
+            // The agent's model was selected when it was chosen; one cycled
+            // to since wins
+            request.model = self.selected_model.clone();
+        }
+        request
+    }
+
+    /// Opens a popup with the request `message` would be sent as, leaving
+    /// the conversation as it was.
+    async fn show_dry_run(&mut self, message: String) {
+        if message.is_empty() {
+            self.status_message = tr("tui.dry_run_usage").to_string();
+            return;
+        }
+        if self.compare.is_some() {
+            self.status_message = tr("tui.dry_run_in_compare").to_string();
+            return;
+        }
+        let session_id = self.current_session_id.clone().unwrap_or_default();
+        let mut messages = self.messages.clone();
+        messages.push(Message::user(
+            session_id,
+            self.chat_service.stored_text(message),
+        ));
+        let (history, dropped) = session_context_dropping(&messages, DEFAULT_TOKEN_BUDGET);
+        let (system_prompt, _) = self.preset_prompt().await;
+        let request = self.grok_request(
+            Conversation::new().history(history).into_messages(),
+            system_prompt,
+        );
+        let dry_run = self.chat_service.dry_run(request).with_dropped(dropped);
+        self.popup = Some(Popup {
+            title: tr("tui.title_dry_run"),
+            lines: dry_run_text(&dry_run)
+                .lines()
+                .map(|line| Line::from(line.to_string()))
+                .collect(),
+            scroll: 0,
+        });
+        self.status_message = tr("tui.dry_run_done").to_string();
+    }
+
+    fn scroll_popup(&mut self, key: KeyCode) {
+        let Some(popup) = self.popup.as_mut() else {
+            return;
+        };
+        let last = u16::try_from(popup.lines.len().saturating_sub(1)).unwrap_or(u16::MAX);
+        let scroll = popup.scroll;
+        popup.scroll = match key {
+            KeyCode::Up | KeyCode::Char('k') => scroll.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => scroll.saturating_add(1),
+            KeyCode::PageUp => scroll.saturating_sub(POPUP_PAGE),
+            KeyCode::PageDown => scroll.saturating_add(POPUP_PAGE),
+            KeyCode::Home => 0,
+            KeyCode::End => last,
+            KeyCode::Esc | KeyCode::Char('q') => {
+                self.popup = None;
+                return;
+            }
+            _ => scroll,
+        }
+        .min(last);
+    }
+
+    /// The hook a `/<name> args` input names, with its arguments.
+    fn find_hook(&self) -> Option<(Hook, String)> {
+        let command = self.input_buffer.trim().strip_prefix('/')?;
+        let (name, args) = command
+            .split_once(char::is_whitespace)
+            .unwrap_or((command, ""));
+        let hook = self.hooks.as_ref()?.find(name)?;
+        Some((hook, args.trim().to_string()))
+    }
+
+    /// Runs `hook`, or asks for a `y` first when it is new or has changed.
+    async fn start_hook(&mut self, hook: Hook, args: String) -> Result<()> {
+        let Some(hooks) = &self.hooks else {
+            return Ok(());
+        };
+        match hooks.is_trusted(&hook) {
+            Ok(true) => self.run_hook(hook, args).await,
+            Ok(false) => {
+                self.status_message =
+                    tr_args("hooks.confirm_tui", &[("path", &hook.path.display())]);
+                self.confirm_hook = Some((hook, args));
+                Ok(())
+            }
+            Err(e) => {
+                self.status_message = hook_error(&hook, &e);
+                Ok(())
+            }
+        }
+    }
+
+    /// Sends `message`, held back over the cost threshold, on a `y`; puts it
+    /// back in the input otherwise.
+    async fn confirm_and_send(&mut self, message: String, key: KeyCode) -> Result<()> {
+        self.input_buffer = message;
+        if matches!(key, KeyCode::Char(c) if is_affirmative(&c.to_string())) {
+            self.send_message(true).await
+        } else {
+            self.status_message = tr("tui.cost_declined").to_string();
+            Ok(())
+        }
+    }
+
+    async fn confirm_and_run_hook(&mut self, hook: Hook, args: String, key: KeyCode) -> Result<()> {
+        let confirmed = matches!(key, KeyCode::Char(c) if is_affirmative(&c.to_string()));
+        let Some(hooks) = self.hooks.as_ref().filter(|_| confirmed) else {
+            self.status_message = tr("hooks.declined").to_string();
+            return Ok(());
+        };
+        match hooks.trust(&hook) {
+            Ok(()) => self.run_hook(hook, args).await,
+            Err(e) => {
+                self.status_message = hook_error(&hook, &e);
+                Ok(())
+            }
+        }
+    }
+
+    /// Puts the hook's output in the input box, or sends it when the hook
+    /// asks for that.
+    async fn run_hook(&mut self, hook: Hook, args: String) -> Result<()> {
+        self.status_message = tr_args("hooks.running", &[("name", &hook.name)]);
+        self.render()?;
+
+        let messages: Vec<ApiMessage> = self
+            .messages
+            .iter()
+            .filter(|message| !is_error_placeholder(message))
+            .map(ApiMessage::from)
+            .collect();
+        let output = hook
+            .run(&args, self.current_session_id.as_deref(), &messages)
+            .await;
+        match output {
+            Ok(HookOutput::Insert(text) | HookOutput::Send(text)) if text.trim().is_empty() => {
+                self.status_message = tr_args("hooks.empty", &[("name", &hook.name)]);
+            }
+            Ok(HookOutput::Insert(text)) => {
+                self.input_buffer = text.trim_end().to_string();
+                self.status_message = tr_args("hooks.inserted", &[("name", &hook.name)]);
+            }
+            Ok(HookOutput::Send(text)) => {
+                self.input_buffer = text.trim().to_string();
+                if self.compare.is_some() {
+                    self.send_compare().await?;
+                } else {
+                    self.send_message(false).await?;
+                }
+            }
+            Err(e) => self.status_message = hook_error(&hook, &e),
+        }
+        Ok(())
+    }
+
+    /// Asks for the last reply again with the same history. The replaced
+    /// reply is kept, up to `kept_generations` of them, so `%` can show what
+    /// changed; a failed reply is simply replaced.
+    async fn regenerate(&mut self) -> Result<()> {
+        if self.offline {
+            self.status_message = tr("tui.regenerate_offline").to_string();
+            return Ok(());
+        }
+        let Some(index) = self
+            .messages
+            .len()
+            .checked_sub(1)
+            .filter(|index| self.messages[*index].role == MessageRole::Assistant)
+        else {
+            self.status_message = tr("tui.regenerate_nothing").to_string();
+            return Ok(());
+        };
+
+        self.status_message = tr("tui.regenerating").to_string();
+        self.render()?;
+
+        let api_messages = Conversation::new()
+            .history(session_context(
+                &self.messages[..index],
+                DEFAULT_TOKEN_BUDGET,
+            ))
+            .into_messages();
+        let (system_prompt, _) = self.preset_prompt().await;
+        let pinned = pinned_count(&self.messages[..index]);
+        let shed = self.config.context_retry() == ContextRetry::Auto;
+        match self
+            .send_to_grok_api(api_messages, system_prompt, pinned, shed)
+            .await
+        {
+            Ok((content, usage, _, _, migration, _)) => {
+                if let Some(migration) = &migration {
+                    self.migrate_model(migration).await;
+                }
+                if let Some(usage) = usage {
+                    self.session_cost = self.session_cost
+                        + self.pricing.estimate_cost(&usage, &self.selected_model);
+                }
+                let mut reply = Message::assistant(
+                    self.messages[index].session_id.clone(),
+                    content,
+                    Some(self.selected_model.clone()),
+                );
+                reply.agent = self.agent.as_ref().map(|agent| agent.name.clone());
+                let previous = std::mem::replace(&mut self.messages[index], reply);
+                self.selected_message = Some(index);
+                self.status_message = match migration {
+                    Some(migration) => tr_args(
+                        "tui.model_migrated",
+                        &[("from", &migration.from), ("to", &migration.to)],
+                    ),
+                    None => tr("tui.regenerated").to_string(),
+                };
+                if !is_error_placeholder(&previous) {
+                    let kept = self.generations.entry(index).or_default();
+                    kept.push(previous.content.clone());
+                    kept.drain(..kept.len().saturating_sub(self.kept_generations));
+                    self.store_regenerated(index, &previous).await;
+                    self.history.record(Edit::Reply(index, Box::new(previous)));
+                }
+            }
+            Err(e) => {
+                self.status_message = tr_args("tui.regenerate_failed", &[("error", &e)]);
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the reply at `index` in place of `previous` if that one is in
+    /// the database.
+    #[cfg(feature = "server")]
+    async fn store_regenerated(&mut self, index: usize, previous: &Message) {
+        let Some(database) = &self.database else {
+            return;
+        };
+        if previous.id == 0 {
+            return;
+        }
+        match database
+            .supersede_message(
+                previous,
+                self.messages[index].clone(),
+                self.kept_generations,
+                &Actor::cli(),
+            )
+            .await
+        {
+            Ok(saved) => self.messages[index].id = saved.id,
+            Err(e) => self.status_message = tr_args("tui.regenerate_save_failed", &[("error", &e)]),
+        }
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn store_regenerated(&mut self, _index: usize, _previous: &Message) {}
+
+    /// Opens a popup with the word-level changes between the selected (or
+    /// last) reply and the generation it replaced.
+    async fn show_diff(&mut self) {
+        let Some(index) = self
+            .selected_message
+            .or(self.messages.len().checked_sub(1))
+            .filter(|index| *index < self.messages.len())
+        else {
+            self.status_message = tr("tui.select_first").to_string();
+            return;
+        };
+        let previous = match self.generations.get(&index).and_then(|kept| kept.last()) {
+            Some(previous) => Some(previous.clone()),
+            None => self.stored_generation(index).await,
+        };
+        let Some(previous) = previous else {
+            self.status_message = tr("tui.diff_none").to_string();
+            return;
+        };
+        self.popup = Some(Popup {
+            title: tr("tui.title_diff"),
+            lines: diff_lines(&word_diff(&previous, &self.messages[index].content)),
+            scroll: 0,
+        });
+    }
+
+    /// The newest earlier generation of the reply at `index` kept in the
+    /// database, for sessions regenerated elsewhere.
+    #[cfg(feature = "server")]
+    async fn stored_generation(&self, index: usize) -> Option<String> {
+        let (Some(database), Some(message)) = (&self.database, self.messages.get(index)) else {
+            return None;
+        };
+        if message.id == 0 {
+            return None;
+        }
+        let mut kept = database
+            .previous_generations(&message.session_id, message.id)
+            .await
+            .ok()?;
+        kept.pop().map(|previous| previous.content)
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn stored_generation(&self, _index: usize) -> Option<String> {
+        None
+    }
+
+    /// Enters compare mode with the current model on the left and the next
+    /// one in the picker on the right, or leaves it keeping the continuing
+    /// pane's thread and model.
+    fn toggle_compare(&mut self) {
+        if let Some(mut compare) = self.compare.take() {
+            let kept = &mut compare.panes[compare.continuing];
+            let messages = std::mem::replace(&mut self.messages, kept.messages.clone());
+            let model = std::mem::replace(&mut self.selected_model, kept.model.clone());
+            self.history.record(Edit::Thread(Box::new(Thread {
+                reason: "tui.edit_leave_compare",
+                session_id: self.current_session_id.clone(),
+                messages,
+                // The kept pane's thread starts with the same messages
+                generations: self.generations.clone(),
+                model,
+                cost: self.session_cost,
+                compare: Some(compare),
+            })));
+            self.selected_message = None;
+            self.status_message = tr_args("tui.compare_off", &[("model", &self.selected_model)]);
+            return;
+        }
+        if self.offline {
+            self.status_message = tr("tui.compare_needs_api").to_string();
+            return;
+        }
+
+        let partner = next_model(&self.available_models, &self.selected_model);
+        self.compare = Some(Compare {
+            panes: [
+                ComparePane::new(self.selected_model.clone(), self.messages.clone()),
+                ComparePane::new(partner, self.messages.clone()),
+            ],
+            continuing: 0,
+        });
+        self.selected_message = None;
+        self.status_message = tr("tui.compare_on").to_string();
+    }
+
+    fn switch_continuing(&mut self) {
+        if let Some(compare) = self.compare.as_mut() {
+            compare.continuing = 1 - compare.continuing;
+            self.status_message = tr_args(
+                "tui.compare_continuing",
+                &[("model", &compare.panes[compare.continuing].model)],
+            );
+        }
+    }
+
+    /// Sends the prompt to both panes' models at once and streams each reply
+    /// into its pane.
+    async fn send_compare(&mut self) -> Result<()> {
+        if self.offline {
+            self.status_message = tr("tui.compare_needs_api_insert").to_string();
+            return Ok(());
+        }
+        let text = std::mem::take(&mut self.input_buffer);
+        let session_id = self
+            .current_session_id
+            .get_or_insert_with(|| Uuid::new_v4().to_string())
+            .clone();
+        let stored = self.chat_service.stored_text(text);
+        let (system_prompt, fallback) = self.preset_prompt().await;
+        let Some(compare) = self.compare.as_mut() else {
+            return Ok(());
+        };
+
+        let requests = compare.panes.each_mut().map(|pane| {
+            pane.messages
+                .push(Message::user(session_id.clone(), stored.clone()));
+            let mut request = ApiChatRequest::builder()
+                .messages(
+                    Conversation::new()
+                        .history(session_context(&pane.messages, DEFAULT_TOKEN_BUDGET))
+                        .into_messages(),
+                )
+                .model(&pane.model)
+                .stream(true)
+                .build();
+            request.system_prompt = system_prompt.clone();
+            pane.messages.push(Message::assistant(
+                session_id.clone(),
+                String::new(),
+                Some(pane.model.clone()),
+            ));
+            pane.usage = None;
+            pane.latency = None;
+            request
+        });
+
+        self.status_message = tr("tui.compare_thinking").to_string();
+        self.render()?;
+
+        let started = Instant::now();
+        let mut effects = Vec::new();
+        let [left, right] = requests;
+        let (left, right) = tokio::join!(
+            self.chat_service.send_request(left, None),
+            self.chat_service.send_request(right, None)
+        );
+        let mut chunks = stream::select_all(
+            [left, right]
+                .into_iter()
+                .enumerate()
+                .map(|(pane, response)| reply_chunks(response).map(move |chunk| (pane, chunk))),
+        );
+        while let Some((index, chunk)) = chunks.next().await {
+            let Some(compare) = self.compare.as_mut() else {
+                break;
+            };
+            let pane = &mut compare.panes[index];
+            pane.latency = Some(started.elapsed());
+            let reply = pane.messages.last_mut().expect("reply placeholder");
+            match chunk {
+                Ok(chunk) => {
+                    reply.content.push_str(&chunk.content);
+                    reply.resumes = chunk.resumes.or(reply.resumes);
+                    if chunk.usage.is_some() {
+                        pane.usage = chunk.usage;
+                    }
+                    if let Some(processed) = chunk.processed {
+                        reply.content = processed.content;
+                        effects.extend(processed.effects);
+                    }
+                }
+                Err(e) => {
+                    reply.content = tr_args("tui.reply_error", &[("error", &e)]);
+                    reply.model = Some("error".to_string());
+                }
+            }
+            self.render()?;
+        }
+
+        if let Some(compare) = &self.compare {
+            for pane in &compare.panes {
+                if let Some(usage) = &pane.usage {
+                    self.session_cost =
+                        self.session_cost + self.pricing.estimate_cost(usage, &pane.model);
+                }
+            }
+        }
+        let exchange = self
+            .compare
+            .as_ref()
+            .map(compare_exchange)
+            .unwrap_or_default();
+        for message in exchange {
+            self.append_journal(JournalEntry::Message { message });
+        }
+        self.store_compare_exchange(&session_id).await;
+        self.status_message = match fallback {
+            Some(warning) => format!("⚠️ {}", warning),
+            None if !effects.is_empty() => format!("📎 {}", effects.join("; ")),
+            None => tr("tui.compare_done").to_string(),
+        };
+        Ok(())
+    }
+
+    /// Stores the conversation so far under `session_id` unless the session
+    /// is already in the database, giving the shown messages their ids.
+    #[cfg(feature = "server")]
+    async fn save_session(&mut self, database: &Database, session_id: &str) -> Result<()> {
+        if database.get_session(session_id).await?.is_some() {
+            return Ok(());
+        }
+        let mut session = ChatSession::new(self.selected_model.clone(), None);
+        session.id = session_id.to_string();
+        session.prompt_preset = self.prompt_preset.clone();
+        session.agent = self.agent.as_ref().map(|agent| agent.name.clone());
+        database.create_session(session).await?;
+        // Without their ids the next tick would take these rows for another
+        // process's writes and reload over them
+        for message in self.messages.iter_mut() {
+            if !is_error_placeholder(message) {
+                message.id = database.create_message(message.clone()).await?.id;
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the newest compare prompt once, followed by the continuing
+    /// pane's reply tagged with its model.
+    #[cfg(feature = "server")]
+    async fn store_compare_exchange(&mut self, session_id: &str) {
+        let Some(database) = self.database.clone() else {
+            return;
+        };
+
+        let mut result = self.save_session(&database, session_id).await;
+        let exchange = self
+            .compare
+            .as_ref()
+            .map(compare_exchange)
+            .unwrap_or_default();
+        for message in exchange {
+            if result.is_err() {
+                break;
+            }
+            result = database.create_message(message).await.map(drop);
+        }
+        match result {
+            Ok(()) => {
+                let _ = database.update_session(session_id, None).await;
+                self.forget_journaled(session_id);
+            }
+            Err(e) => self.status_message = tr_args("tui.compare_save_failed", &[("error", &e)]),
+        }
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn store_compare_exchange(&mut self, _session_id: &str) {}
+
+    /// Stores `text` as a queued message in the session database, putting it
+    /// back in the input box if there is nowhere to keep it.
+    #[cfg(feature = "server")]
+    async fn queue_message(&mut self, session_id: String, text: String) {
+        match self.queue_in_outbox(&session_id, text.clone()).await {
+            Ok(queued) => {
+                self.messages.push(queued);
+                self.forget_journaled(&session_id);
+                let waiting = self.messages.iter().filter(|m| m.is_queued()).count();
+                self.status_message = tr_args("tui.queued", &[("count", &waiting)]);
+            }
+            Err(e) => {
+                self.input_buffer = text;
+                self.status_message = tr_args("tui.queue_failed", &[("error", &e)]);
+            }
+        }
+    }
+
+    #[cfg(feature = "server")]
+    async fn queue_in_outbox(&mut self, session_id: &str, text: String) -> Result<Message> {
+        let (Some(database), Some(outbox)) = (self.database.clone(), self.outbox.clone()) else {
+            anyhow::bail!(tr("tui.no_database"));
+        };
+        // The first queued message saves the conversation so far, which the
+        // flush sends as context
+        self.save_session(&database, session_id).await?;
+        outbox
+            .queue(session_id, text, Some(self.selected_model.clone()))
+            .await
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn queue_message(&mut self, _session_id: String, text: String) {
+        self.input_buffer = text;
+        self.status_message = tr("tui.queue_unsupported").to_string();
+    }
+
+    /// Sends the session's queued messages. Going online fails quietly back
+    /// to offline while the API is still unreachable.
+    #[cfg(feature = "server")]
+    async fn go_online(&mut self) {
+        self.last_retry = Instant::now();
+        let (Some(outbox), Some(database), Some(session_id)) = (
+            self.outbox.clone(),
+            self.database.clone(),
+            self.current_session_id.clone(),
+        ) else {
+            self.offline = false;
+            self.retry_offline = false;
+            self.status_message = tr("tui.online").to_string();
+            return;
+        };
+
+        self.status_message = tr("tui.sending_queued").to_string();
+        let _ = self.render();
+        let result = outbox.flush_session(&session_id).await;
+        // Whatever went out is stored, so the database has the current view
+        if let Ok(messages) = database.get_messages(&session_id).await {
+            if !messages.is_empty() {
+                self.messages = messages;
+                self.selected_message = None;
+                self.forget_journaled(&session_id);
+            }
+        }
+        match result {
+            Ok(replies) => {
+                self.offline = false;
+                self.retry_offline = false;
+                self.status_message = match replies.len() {
+                    0 => tr("tui.online").to_string(),
+                    n => tr_args("tui.sent_queued", &[("count", &n)]),
+                };
+            }
+            Err(e) if is_offline(&e) => {
+                self.offline = true;
+                self.retry_offline = true;
+                self.status_message = tr("tui.still_offline").to_string();
+            }
+            Err(e) => {
+                self.offline = false;
+                self.retry_offline = false;
+                self.status_message = tr_args("tui.send_queued_failed", &[("error", &e)]);
+            }
+        }
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn go_online(&mut self) {
+        self.offline = false;
+        self.retry_offline = false;
+        self.status_message = tr("tui.online").to_string();
+    }
+
+    /// Once a session is in the database (because something was queued in
+    /// it), later exchanges are saved there too so its history stays whole.
+    #[cfg(feature = "server")]
+    async fn store_last_exchange(&mut self) {
+        let (Some(database), Some(session_id)) =
+            (self.database.clone(), self.current_session_id.clone())
+        else {
+            return;
+        };
+        if !matches!(database.get_session(&session_id).await, Ok(Some(_))) {
+            return;
+        }
+        // Stored ids let later pins and undos reach the saved rows
+        let start = self.messages.len().saturating_sub(2);
+        for message in &mut self.messages[start..] {
+            match database.create_message(message.clone()).await {
+                Ok(saved) => message.id = saved.id,
+                Err(e) => {
+                    self.status_message = tr_args("tui.save_failed", &[("error", &e)]);
+                    return;
+                }
+            }
+        }
+        let _ = database.update_session(&session_id, None).await;
+        self.forget_journaled(&session_id);
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn store_last_exchange(&mut self) {}
+
+    /// Appends to the crash journal. A journal that fails to write is given
+    /// up on; the conversation carries on without one.
+    fn append_journal(&mut self, entry: JournalEntry) {
+        let Some(journal) = self.journal.as_mut() else {
+            return;
+        };
+        if let Err(e) = journal.append(entry) {
+            self.journal = None;
+            self.status_message = tr_args("tui.journal_disabled", &[("error", &e)]);
+        }
+    }
+
+    /// Continues with the replacement for a retired model, moving the
+    /// current session over to it in the database too.
+    async fn migrate_model(&mut self, migration: &ModelMigration) {
+        self.selected_model = migration.to.clone();
+        if !self.available_models.contains(&migration.to) {
+            self.available_models.push(migration.to.clone());
+            group_by_provider(&mut self.available_models);
+        }
+        self.journal_settings();
+        #[cfg(feature = "server")]
+        if let (Some(database), Some(session_id)) = (&self.database, &self.current_session_id) {
+            let _ = database
+                .migrate_model(
+                    &migration.from,
+                    &migration.to,
+                    Some(session_id),
+                    &Actor::cli(),
+                )
+                .await;
+        }
+    }
+
+    /// Journals the current session's model and preset after either changes.
+    fn journal_settings(&mut self) {
+        let Some(session_id) = self.current_session_id.clone() else {
+            return;
+        };
+        #[cfg(feature = "server")]
+        let prompt_preset = self.prompt_preset.clone();
+        #[cfg(not(feature = "server"))]
+        let prompt_preset = None;
+        self.append_journal(JournalEntry::Settings {
+            session_id,
+            model: self.selected_model.clone(),
+            prompt_preset,
+        });
+    }
+
+    /// Drops a session from the crash journal once it is in the database.
+    #[cfg(feature = "server")]
+    fn forget_journaled(&mut self, session_id: &str) {
+        if let Some(journal) = self.journal.as_mut() {
+            if journal.forget(session_id).is_err() {
+                // Left as it is, the journal may offer messages that are
+                // already saved; recovery skips those
+                self.journal = None;
+            }
+        }
+    }
+
+    /// Brings back conversations from the journals of UIs that exited
+    /// unexpectedly. They are saved to the session database where there is
+    /// one, and the newest is shown. Otherwise they go into this UI's journal,
+    /// so another crash does not lose them either.
+    async fn recover(&mut self, orphans: Vec<OrphanedJournal>) {
+        let count: usize = orphans.iter().map(|o| o.message_count()).sum();
+        let sessions: Vec<RecoveredSession> = orphans
+            .iter()
+            .flat_map(|orphan| orphan.sessions.iter().cloned())
+            .collect();
+        let Some(newest) = sessions.last().cloned() else {
+            return;
+        };
+        let saved = self.save_recovered(&sessions).await;
+
+        self.current_session_id = Some(newest.session_id.clone());
+        self.messages = newest.messages;
+        self.selected_message = None;
+        if let Some(model) = newest.model {
+            self.selected_model = model;
+        }
+        #[cfg(feature = "server")]
+        {
+            self.prompt_preset = newest.prompt_preset;
+        }
+
+        let outcome = match saved {
+            Ok(()) => {
+                // Picks up the stored ids, for pins
+                self.load_session(&newest.session_id).await;
+                tr("tui.recovered_saved").to_string()
+            }
+            Err(e) => {
+                for session in sessions {
+                    if let Some(model) = session.model {
+                        self.append_journal(JournalEntry::Settings {
+                            session_id: session.session_id,
+                            model,
+                            prompt_preset: session.prompt_preset,
+                        });
+                    }
+                    for message in session.messages {
+                        self.append_journal(JournalEntry::Message { message });
+                    }
+                }
+                if self.journal.is_none() {
+                    // Kept for the next start to try again
+                    self.status_message = tr_args(
+                        "tui.recovered_save_failed",
+                        &[("count", &count), ("error", &e)],
+                    );
+                    return;
+                }
+                tr_args("tui.recovered_not_saved", &[("error", &e)])
+            }
+        };
+        for orphan in orphans {
+            let _ = orphan.discard();
+        }
+        self.status_message = tr_args("tui.recovered", &[("count", &count), ("outcome", &outcome)]);
+    }
+
+    #[cfg(feature = "server")]
+    async fn save_recovered(&self, sessions: &[RecoveredSession]) -> Result<()> {
+        let Some(database) = &self.database else {
+            anyhow::bail!(tr("tui.no_database"));
+        };
+        for recovered in sessions {
+            let model = recovered
+                .model
+                .clone()
+                .unwrap_or_else(|| self.selected_model.clone());
+            let mut session = ChatSession::new(model, None);
+            session.id = recovered.session_id.clone();
+            session.prompt_preset = recovered.prompt_preset.clone();
+            database
+                .recover_session(&session, &recovered.messages)
+                .await?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn save_recovered(&self, _sessions: &[RecoveredSession]) -> Result<()> {
+        anyhow::bail!(tr("tui.saving_unsupported"))
+    }
+
+    /// Loads a saved session's messages, queued ones included, its preset
+    /// and its agent.
+    #[cfg(feature = "server")]
+    async fn load_session(&mut self, session_id: &str) {
+        if let Some(database) = &self.database {
+            if let Ok(messages) = database.get_messages(session_id).await {
+                self.messages = messages;
+            }
+            if let Ok(attachments) = database.session_attachments(session_id).await {
+                let store = MediaStore::new(self.config.media_dir());
+                self.attachments = attachments
+                    .into_iter()
+                    .map(|(id, media)| {
+                        let images = media
+                            .iter()
+                            .filter_map(|media| Attachment::stored(&store, media))
+                            .collect();
+                        (id, images)
+                    })
+                    .collect();
+            }
+            if let Ok(Some(session)) = database.get_session(session_id).await {
+                self.prompt_preset = session.prompt_preset;
+                // An agent since removed from the config is dropped
+                self.agent = session.agent.and_then(|name| self.config.agent(&name).ok());
+                if let Some(agent) = &self.agent {
+                    self.selected_model = agent.model.clone();
+                }
+            }
+        }
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn load_session(&mut self, _session_id: &str) {}
+
+    async fn create_new_session(&mut self) -> Result<()> {
+        let session_id = Uuid::new_v4().to_string();
+        let previous = self.current_session_id.replace(session_id.clone());
+        self.history.record(Edit::Thread(Box::new(Thread {
+            reason: "tui.edit_new_session",
+            session_id: previous,
+            messages: std::mem::take(&mut self.messages),
+            generations: std::mem::take(&mut self.generations),
+            model: self.selected_model.clone(),
+            cost: std::mem::replace(&mut self.session_cost, Cost::zero()),
+            compare: self.compare.take(),
+        })));
+        self.selected_message = None;
+        self.status_message = tr_args("tui.new_session", &[("id", &session_id)]);
+        Ok(())
+    }
+
+    /// Handles `/newfrom <template>`: starts a new session holding a session
+    /// template's system prompt and example exchanges. It is saved right
+    /// away, so the seeded messages are in the database before the first send.
+    #[cfg(feature = "server")]
+    async fn new_session_from(&mut self, name: &str) -> Result<()> {
+        if name.is_empty() {
+            self.status_message = tr("tui.newfrom_usage").to_string();
+            return Ok(());
+        }
+        let Some(database) = self.database.clone() else {
+            self.status_message = tr("tui.session_templates_unavailable").to_string();
+            return Ok(());
+        };
+        let template = match database.resolve_session_template(name).await {
+            Ok(template) => template,
+            Err(e) => {
+                self.status_message = format!("❌ {}", e);
+                return Ok(());
+            }
+        };
+
+        self.create_new_session().await?;
+        // The template's own system prompt replaces any chosen preset
+        if template.system_prompt.is_some() {
+            self.prompt_preset = None;
+        }
+        let mut session = ChatSession::new(self.selected_model.clone(), None);
+        session.id = self
+            .current_session_id
+            .clone()
+            .expect("a new session was just started");
+        session.prompt_preset = self.prompt_preset.clone();
+        match database
+            .create_seeded_session(&session, &template, &Actor::cli())
+            .await
+        {
+            Ok(messages) => {
+                self.messages = messages;
+                self.status_message = tr_args(
+                    "tui.new_from_template",
+                    &[("name", &template.name), ("count", &self.messages.len())],
+                );
+            }
+            Err(e) => {
+                self.status_message = tr_args("tui.template_session_failed", &[("error", &e)]);
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn new_session_from(&mut self, _name: &str) -> Result<()> {
+        self.status_message = tr("tui.session_templates_unsupported").to_string();
+        Ok(())
+    }
+
+    /// Handles `/template <name> key=value ...` by replacing the input buffer
+    /// with the rendered text so it can be reviewed before sending.
+    async fn apply_template(&mut self, args: &str) {
+        let args = args.trim();
+        let (name, assignments) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        if name.is_empty() {
+            self.status_message = tr("tui.template_usage").to_string();
+            return;
+        }
+
+        let rendered = match parse_assignments(assignments) {
+            Ok(variables) => match self.load_template(name).await {
+                Ok(template) => template.render(&variables),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+
+        match rendered {
+            Ok(rendered) => {
+                self.input_buffer = rendered.text;
+                self.status_message = if rendered.warnings.is_empty() {
+                    tr_args("tui.template_filled", &[("name", &name)])
+                } else {
+                    format!("⚠️ {}", rendered.warnings.join("; "))
+                };
+            }
+            Err(e) => {
+                self.status_message = format!("❌ {}", e);
+            }
+        }
+    }
+
+    #[cfg(feature = "server")]
+    async fn load_template(&self, name: &str) -> Result<PromptTemplate> {
+        let database = self
+            .database
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!(tr("tui.templates_unavailable")))?;
+
+        database
+            .get_template(name)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!(tr_args("tui.template_not_found", &[("name", &name)])))
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn load_template(&self, _name: &str) -> Result<PromptTemplate> {
+        Err(anyhow::anyhow!(tr("tui.templates_unsupported")))
+    }
+
+    /// Handles `/prompt <name>`, which sends the session's messages with a
+    /// saved preset's system prompt. A bare `/prompt` goes back to the default
+    /// and lists the presets.
+    #[cfg(feature = "server")]
+    async fn choose_prompt(&mut self, name: &str) {
+        let Some(database) = self.database.clone() else {
+            self.status_message = tr("tui.presets_unavailable").to_string();
+            return;
+        };
+
+        if name.is_empty() {
+            self.prompt_preset = None;
+            let available = match database.list_prompts().await {
+                Ok(presets) => presets.into_iter().map(|p| p.name).collect::<Vec<_>>(),
+                Err(e) => {
+                    self.status_message = format!("❌ {}", e);
+                    return;
+                }
+            };
+            self.status_message = if available.is_empty() {
+                tr("tui.default_prompt").to_string()
+            } else {
+                tr_args(
+                    "tui.default_prompt_presets",
+                    &[("presets", &available.join(", "))],
+                )
+            };
+        } else {
+            match database.resolve_prompt(name).await {
+                Ok(preset) => {
+                    self.status_message = tr_args("tui.preset_chosen", &[("name", &preset.name)]);
+                    self.prompt_preset = Some(preset.name);
+                }
+                Err(e) => {
+                    self.status_message = format!("❌ {}", e);
+                    return;
+                }
+            }
+        }
+
+        self.journal_settings();
+
+        // A saved session keeps the choice
+        if let Some(session_id) = &self.current_session_id {
+            if matches!(database.get_session(session_id).await, Ok(Some(_))) {
+                if let Err(e) = database
+                    .set_session_prompt(session_id, self.prompt_preset.as_deref())
+                    .await
+                {
+                    self.status_message = tr_args("tui.preset_save_failed", &[("error", &e)]);
+                }
+            }
+        }
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn choose_prompt(&mut self, _name: &str) {
+        self.status_message = tr("tui.presets_unsupported").to_string();
+    }
+
+    /// Handles `/agent <name>`, which answers with a configured agent's model,
+    /// system prompt and parameters. A bare `/agent` goes back to none and
+    /// lists the agents.
+    async fn choose_agent(&mut self, name: &str) {
+        if name.is_empty() {
+            self.agent = None;
+            let available: Vec<String> = self
+                .config
+                .agents()
+                .into_iter()
+                .map(|agent| agent.name)
+                .collect();
+            self.status_message = if available.is_empty() {
+                tr("tui.no_agent").to_string()
+            } else {
+                tr_args("tui.no_agent_agents", &[("agents", &available.join(", "))])
+            };
+        } else {
+            match self.config.agent(name) {
+                Ok(agent) => {
+                    self.status_message = tr_args(
+                        "tui.agent_chosen",
+                        &[("name", &agent.name), ("model", &agent.model)],
+                    );
+                    self.selected_model = agent.model.clone();
+                    self.agent = Some(agent);
+                }
+                Err(e) => {
+                    self.status_message = format!("❌ {}", e);
+                    return;
+                }
+            }
+        }
+        self.journal_settings();
+        self.save_agent().await;
+    }
+
+    /// A saved session keeps the chosen agent as its default.
+    #[cfg(feature = "server")]
+    async fn save_agent(&mut self) {
+        let (Some(database), Some(session_id)) = (&self.database, &self.current_session_id) else {
+            return;
+        };
+        if !matches!(database.get_session(session_id).await, Ok(Some(_))) {
+            return;
+        }
+        let name = self.agent.as_ref().map(|agent| agent.name.as_str());
+        if let Err(e) = database.set_session_agent(session_id, name).await {
+            self.status_message = tr_args("tui.agent_save_failed", &[("error", &e)]);
+        }
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn save_agent(&mut self) {}
+
+    /// The system prompt of the chosen preset, or of the default preset unless
+    /// the session has a seeded one, and a warning when the chosen one was
+    /// deleted in the meantime.
+    #[cfg(feature = "server")]
+    async fn preset_prompt(&mut self) -> (Option<String>, Option<String>) {
+        let Some(database) = &self.database else {
+            return (None, None);
+        };
+        let mut fallback = None;
+        if let Some(name) = &self.prompt_preset {
+            if let Ok(Some(preset)) = database.get_prompt(name).await {
+                return (Some(preset.body), None);
+            }
+            fallback = Some(tr_args("tui.preset_missing", &[("name", name)]));
+            self.prompt_preset = None;
+        }
+        // A session template's system prompt wins over the default preset
+        if self
+            .messages
+            .iter()
+            .any(|m| m.seeded && m.role == MessageRole::System)
+        {
+            return (None, fallback);
+        }
+        let body = database
+            .default_prompt()
+            .await
+            .ok()
+            .flatten()
+            .map(|preset| preset.body);
+        (body, fallback)
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn preset_prompt(&mut self) -> (Option<String>, Option<String>) {
+        (None, None)
+    }
+
+    fn cycle_model(&mut self) {
+        if let Some(compare) = self.compare.as_mut() {
+            let pane = &mut compare.panes[compare.continuing];
+            pane.model = next_model(&self.available_models, &pane.model);
+            self.status_message = tr_args("tui.pane_model", &[("model", &pane.model)]);
+            return;
+        }
+        let next = next_model(&self.available_models, &self.selected_model);
+        let previous = std::mem::replace(&mut self.selected_model, next);
+        self.status_message = match self
+            .chat_service
+            .profiles()
+            .profile_for(&self.selected_model)
+        {
+            Some(profile) => tr_args(
+                "tui.model_changed_profile",
+                &[
+                    ("model", &self.selected_model),
+                    ("profile", &profile.summary()),
+                ],
+            ),
+            None => tr_args("tui.model_changed", &[("model", &self.selected_model)]),
+        };
+        let provider = split_model(&self.selected_model).0;
+        if provider != split_model(&previous).0 {
+            self.status_message = tr_args(
+                "tui.provider_changed",
+                &[("provider", &provider), ("status", &self.status_message)],
+            );
+        }
+        self.journal_settings();
+    }
+
+    /// Moves the cursor by `step` messages, starting from the newest.
+    fn move_selection(&mut self, step: isize) {
+        let Some(last) = self.messages.len().checked_sub(1) else {
+            return;
+        };
+        self.selected_message = Some(match self.selected_message {
+            Some(index) => index.saturating_add_signed(step).min(last),
+            None => last,
+        });
+    }
+
+    /// What `v` opens for the selected message: its attached images, then
+    /// the web images its text shows or links to.
+    fn selected_images(&self) -> Vec<String> {
+        let Some(message) = self
+            .selected_message
+            .and_then(|index| self.messages.get(index))
+        else {
+            return Vec::new();
+        };
+        let attached = self
+            .attachments
+            .get(&message.id)
+            .into_iter()
+            .flatten()
+            .map(|attachment| attachment.path.display().to_string());
+        let linked = message
+            .content
+            .lines()
+            .flat_map(find_images)
+            .map(|image| image.target)
+            .filter(|target| target.starts_with("http://") || target.starts_with("https://"));
+        attached.chain(linked).collect()
+    }
+
+    /// Opens the selected message's images in the system viewer.
+    fn open_images(&mut self) {
+        for target in self.selected_images() {
+            let shown = sanitize(&target);
+            if let Err(e) = open_externally(&target) {
+                self.status_message = tr_args(
+                    "tui.image_open_failed",
+                    &[("target", &shown), ("error", &e)],
+                );
+                return;
+            }
+            self.status_message = tr_args("tui.image_opened", &[("target", &shown)]);
+        }
+    }
+
+    async fn toggle_pin(&mut self) {
+        let Some(index) = self
+            .selected_message
+            .filter(|index| *index < self.messages.len())
+        else {
+            self.status_message = tr("tui.select_first").to_string();
+            return;
+        };
+        let edit = self.apply_edit(Edit::Pin(index)).await;
+        self.history.record(edit);
+        self.status_message = if self.messages[index].pinned {
+            tr("tui.pinned").to_string()
+        } else {
+            tr("tui.unpinned").to_string()
+        };
+    }
+
+    /// Rates the selected message; the same rating again takes it back.
+    async fn rate(&mut self, rating: Rating) {
+        let Some(index) = self
+            .selected_message
+            .filter(|index| *index < self.messages.len())
+        else {
+            self.status_message = tr("tui.select_first").to_string();
+            return;
+        };
+        let mut annotation = self.messages[index].annotation.clone().unwrap_or_default();
+        annotation.rating = (annotation.rating != Some(rating)).then_some(rating);
+        self.status_message = match annotation.rating {
+            Some(Rating::Up) => tr("tui.rated_up"),
+            Some(Rating::Down) => tr("tui.rated_down"),
+            None => tr("tui.rating_cleared"),
+        }
+        .to_string();
+        self.annotate(index, annotation).await;
+    }
+
+    /// Handles `/note <text>`: words starting with `#` label the selected
+    /// message and the rest is its note. A bare `/note` removes both.
+    async fn note(&mut self, text: &str) {
+        let Some(index) = self
+            .selected_message
+            .filter(|index| *index < self.messages.len())
+        else {
+            self.status_message = tr("tui.select_first").to_string();
+            return;
+        };
+        let (note, labels) = parse_note(text);
+        let mut annotation = self.messages[index].annotation.clone().unwrap_or_default();
+        self.status_message = if note.is_none() && labels.is_empty() {
+            tr("tui.note_cleared").to_string()
+        } else {
+            tr("tui.noted").to_string()
+        };
+        annotation.note = note;
+        annotation.labels = labels;
+        self.annotate(index, annotation).await;
+    }
+
+    async fn annotate(&mut self, index: usize, annotation: Annotation) {
+        let annotation = annotation.normalized();
+        self.messages[index].annotation = (!annotation.is_empty()).then_some(annotation);
+        self.store_annotation(index).await;
+    }
+
+    /// Saves the annotation on the message at `index` if the message is in
+    /// the database.
+    #[cfg(feature = "server")]
+    async fn store_annotation(&mut self, index: usize) {
+        let (Some(database), Some(message)) = (&self.database, self.messages.get(index)) else {
+            return;
+        };
+        if message.id == 0 {
+            return;
+        }
+        let annotation = message.annotation.clone().unwrap_or_default();
+        if let Err(e) = database
+            .set_annotation(&message.session_id, message.id, &annotation)
+            .await
+        {
+            self.status_message = tr_args("tui.annotation_save_failed", &[("error", &e)]);
+        }
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn store_annotation(&mut self, _index: usize) {}
+
+    async fn undo(&mut self) {
+        let Some(edit) = self.history.done.pop_back() else {
+            self.status_message = tr("tui.nothing_to_undo").to_string();
+            return;
+        };
+        let inverse = self.apply_edit(edit).await;
+        let key = match inverse {
+            Edit::Thread(_) => "tui.undone_view",
+            _ => "tui.undone",
+        };
+        self.status_message = tr_args(key, &[("edit", &inverse.describe())]);
+        self.history.undone.push(inverse);
+    }
+
+    async fn redo(&mut self) {
+        let Some(edit) = self.history.undone.pop() else {
+            self.status_message = tr("tui.nothing_to_redo").to_string();
+            return;
+        };
+        let inverse = self.apply_edit(edit).await;
+        let key = match inverse {
+            Edit::Thread(_) => "tui.redone_view",
+            _ => "tui.redone",
+        };
+        self.status_message = tr_args(key, &[("edit", &inverse.describe())]);
+        self.history.push_done(inverse);
+    }
+
+    /// Applies `edit` to the conversation and returns the edit that reverses it.
+    async fn apply_edit(&mut self, edit: Edit) -> Edit {
+        match edit {
+            Edit::Pin(index) => {
+                if let Some(message) = self.messages.get_mut(index) {
+                    message.pinned = !message.pinned;
+                }
+                self.store_pin(index).await;
+                Edit::Pin(index)
+            }
+            Edit::Thread(mut thread) => {
+                // Only the view changes: sessions stay stored as they were, and
+                // the next message goes to whichever one is now current.
+                // Swapping the parts back and forth undoes and redoes alike
+                std::mem::swap(&mut self.current_session_id, &mut thread.session_id);
+                std::mem::swap(&mut self.messages, &mut thread.messages);
+                std::mem::swap(&mut self.generations, &mut thread.generations);
+                std::mem::swap(&mut self.selected_model, &mut thread.model);
+                std::mem::swap(&mut self.session_cost, &mut thread.cost);
+                std::mem::swap(&mut self.compare, &mut thread.compare);
+                self.selected_message = None;
+                Edit::Thread(thread)
+            }
+            Edit::Reply(index, mut reply) => {
+                if let Some(current) = self.messages.get_mut(index) {
+                    std::mem::swap(current, &mut reply);
+                    // What `%` compares against is now the reply swapped out
+                    if let Some(last) = self
+                        .generations
+                        .get_mut(&index)
+                        .and_then(|kept| kept.last_mut())
+                    {
+                        *last = reply.content.clone();
+                    }
+                    self.store_reinstated(index, &reply).await;
+                    self.selected_message = Some(index);
+                }
+                Edit::Reply(index, reply)
+            }
+        }
+    }
+
+    /// Makes the reply at `index` the stored reply again in place of
+    /// `replaced`, which stays stored as a generation; the upstream call
+    /// for it was already paid for.
+    #[cfg(feature = "server")]
+    async fn store_reinstated(&mut self, index: usize, replaced: &Message) {
+        let (Some(database), Some(reply)) = (&self.database, self.messages.get(index)) else {
+            return;
+        };
+        if reply.id == 0 || replaced.id == 0 {
+            return;
+        }
+        match database
+            .reinstate_message(&reply.session_id, reply.id, replaced.id)
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => self.status_message = tr("tui.reply_not_stored").to_string(),
+            Err(e) => self.status_message = tr_args("tui.regenerate_save_failed", &[("error", &e)]),
+        }
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn store_reinstated(&mut self, _index: usize, _replaced: &Message) {}
+
+    /// Saves the pin on the message at `index` if the message is in the
+    /// database.
+    #[cfg(feature = "server")]
+    async fn store_pin(&mut self, index: usize) {
+        let (Some(database), Some(message)) = (&self.database, self.messages.get(index)) else {
+            return;
+        };
+        if message.id == 0 {
+            return;
+        }
+        if let Err(e) = database
+            .set_message_pinned(&message.session_id, message.id, Some(message.pinned))
+            .await
+        {
+            self.status_message = tr_args("tui.pin_save_failed", &[("error", &e)]);
+        }
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn store_pin(&mut self, _index: usize) {}
+
+    /// Opens the session picker over every saved session.
+    #[cfg(feature = "server")]
+    async fn open_picker(&mut self) {
+        let Some(database) = &self.database else {
+            self.status_message = tr("tui.sessions_unavailable").to_string();
+            return;
+        };
+        match database.sessions_updated_since(None).await {
+            Ok(mut sessions) => {
+                sessions.reverse();
+                self.picker = Some(SessionPicker::new(sessions));
+            }
+            Err(e) => {
+                self.status_message = tr_args("tui.sessions_failed", &[("error", &e)]);
+            }
+        }
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn open_picker(&mut self) {
+        self.status_message = tr("tui.sessions_unsupported").to_string();
+    }
+
+    #[cfg(feature = "server")]
+    fn picking(&self) -> bool {
+        self.picker.is_some()
+    }
+
+    #[cfg(not(feature = "server"))]
+    fn picking(&self) -> bool {
+        false
+    }
+
+    /// A key pressed while the picker is open: typing narrows the list,
+    /// Enter opens the chosen session and Esc closes the picker.
+    #[cfg(feature = "server")]
+    async fn pick_session(&mut self, key: KeyCode) {
+        let Some(picker) = self.picker.as_mut() else {
+            return;
+        };
+        match key {
+            KeyCode::Esc => self.picker = None,
+            KeyCode::Enter => {
+                if let Some((session_id, message_id)) = picker.choice() {
+                    self.picker = None;
+                    self.open_session(session_id, message_id).await;
+                }
+            }
+            KeyCode::Up => picker.move_selection(-1),
+            KeyCode::Down => picker.move_selection(1),
+            KeyCode::Backspace => picker.pop(),
+            KeyCode::Char(c) => picker.push(c),
+            _ => {}
+        }
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn pick_session(&mut self, _key: KeyCode) {}
+
+    /// Handles `/snapshot <label>`: saves the session if it isn't yet, then
+    /// records where it stands for `/restore` to go back to.
+    #[cfg(feature = "server")]
+    async fn take_snapshot(&mut self, label: &str) {
+        if label.is_empty() {
+            self.status_message = tr("tui.snapshot_usage").to_string();
+            return;
+        }
+        let Some(database) = self.database.clone() else {
+            self.status_message = tr("tui.sessions_unavailable").to_string();
+            return;
+        };
+        let session_id = self
+            .current_session_id
+            .get_or_insert_with(|| Uuid::new_v4().to_string())
+            .clone();
+        let unsaved = self.messages.iter().any(|message| message.id == 0);
+        let taken = async {
+            self.save_session(&database, &session_id).await?;
+            database
+                .create_snapshot(&session_id, label, &Actor::cli())
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("session {} is not saved", session_id))
+        };
+        match taken.await {
+            Ok(snapshot) => {
+                // Freshly saved messages get their ids, which pins and
+                // restores go by
+                if unsaved {
+                    if let Ok(messages) = database.get_messages(&session_id).await {
+                        self.messages = messages;
+                        self.selected_message = None;
+                    }
+                    self.forget_journaled(&session_id);
+                }
+                self.status_message = tr_args("tui.snapshot_taken", &[("label", &snapshot.label)]);
+            }
+            Err(e) => {
+                self.status_message = tr_args("tui.snapshot_failed", &[("error", &e)]);
+            }
+        }
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn take_snapshot(&mut self, _label: &str) {
+        self.status_message = tr("tui.snapshots_unsupported").to_string();
+    }
+
+    /// Handles `/restore`: opens a list of the session's snapshots that can
+    /// still be restored.
+    #[cfg(feature = "server")]
+    async fn open_restore(&mut self) {
+        let (Some(database), Some(session_id)) = (&self.database, &self.current_session_id) else {
+            self.status_message = tr("tui.snapshots_none").to_string();
+            return;
+        };
+        match database.snapshots(session_id).await {
+            Ok(snapshots) => {
+                let snapshots: Vec<SessionSnapshot> = snapshots
+                    .into_iter()
+                    .rev()
+                    .filter(|snapshot| snapshot.removed_by.is_none())
+                    .collect();
+                if snapshots.is_empty() {
+                    self.status_message = tr("tui.snapshots_none").to_string();
+                } else {
+                    self.restore_picker = Some(SnapshotChoice {
+                        snapshots,
+                        selected: 0,
+                    });
+                }
+            }
+            Err(e) => {
+                self.status_message = tr_args("tui.snapshots_failed", &[("error", &e)]);
+            }
+        }
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn open_restore(&mut self) {
+        self.status_message = tr("tui.snapshots_unsupported").to_string();
+    }
+
+    #[cfg(feature = "server")]
+    fn choosing_snapshot(&self) -> bool {
+        self.restore_picker.is_some()
+    }
+
+    #[cfg(not(feature = "server"))]
+    fn choosing_snapshot(&self) -> bool {
+        false
+    }
+
+    /// A key pressed while choosing a snapshot: Enter restores the chosen
+    /// one and Esc closes the list.
+    #[cfg(feature = "server")]
+    async fn pick_snapshot(&mut self, key: KeyCode) {
+        let Some(choice) = self.restore_picker.as_mut() else {
+            return;
+        };
+        match key {
+            KeyCode::Esc => self.restore_picker = None,
+            KeyCode::Up => choice.selected = choice.selected.saturating_sub(1),
+            KeyCode::Down => {
+                choice.selected = (choice.selected + 1).min(choice.snapshots.len() - 1);
+            }
+            KeyCode::Enter => {
+                let snapshot = choice.snapshots.swap_remove(choice.selected);
+                self.restore_picker = None;
+                self.restore_snapshot(snapshot).await;
+            }
+            _ => {}
+        }
+    }
+
+    #[cfg(not(feature = "server"))]
+    async fn pick_snapshot(&mut self, _key: KeyCode) {}
+
+    /// Takes the session back to `snapshot` and reloads it. Undo history and
+    /// replaced replies point into the conversation as it was, so they go.
+    #[cfg(feature = "server")]
+    async fn restore_snapshot(&mut self, snapshot: SessionSnapshot) {
+        let Some(database) = self.database.clone() else {
+            return;
+        };
+        let restored = database
+            .restore_snapshot(&snapshot.session_id, snapshot.id, &Actor::cli())
+            .await;
+        match restored {
+            Ok(Some(report)) => {
+                self.history = UndoStack::new();
+                self.generations.clear();
+                self.selected_message = None;
+                self.load_session(&snapshot.session_id).await;
+                self.status_message = tr_args(
+                    "tui.restored",
+                    &[("label", &snapshot.label), ("count", &report.archived)],
+                );
+            }
+            Ok(None) => self.status_message = tr("tui.snapshots_none").to_string(),
+            Err(e) => {
+                self.status_message = tr_args("tui.restore_failed", &[("error", &e)]);
             }
         }
-        Ok(())
     }
 
-    async fn send_to_grok_api(&self, messages: Vec<ApiMessage>) -> Result<String> {
-        use reqwest::Client;
-        use tokio::time::{timeout, Duration};
-
-        let client = Client::new();
-
-        let request_body = serde_json::json!({
-            "messages": messages,
-            "model": self.selected_model,
-            "max_tokens": self.max_tokens,
-            "temperature": self.temperature,
-            "stream": false
-        });
-
-        // Add timeout to prevent hanging
-        let response = timeout(
-            Duration::from_secs(60), // 60 second timeout
-            client
-                .post("https://api.x.ai/v1/chat/completions")
-                .header("Authorization", format!("Bearer {}", self.api_key))
-                .header("Content-Type", "application/json")
-                .json(&request_body)
-                .send()
-        ).await??;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(anyhow::anyhow!("❌ API Error ({}): {}", status, error_text));
+    /// Searches message contents for the picker once typing has paused.
+    /// Returns whether there are new results to show.
+    #[cfg(feature = "server")]
+    async fn search_sessions(&mut self) -> bool {
+        let (Some(picker), Some(database)) = (self.picker.as_mut(), &self.database) else {
+            return false;
+        };
+        let Some(query) = picker.due_search(Instant::now()) else {
+            return false;
+        };
+        match database.search_sessions(&query, CONTENT_RESULTS).await {
+            Ok(found) => picker.found(query, found),
+            Err(e) => {
+                // Keep the title matches; don't retry the same query
+                picker.found(query, Vec::new());
+                self.status_message = tr_args("tui.search_failed", &[("error", &e)]);
+            }
         }
-
-        let response_json: serde_json::Value = response.json().await?;
-
-        let content = response_json["choices"]
-            .get(0)
-            .and_then(|choice| choice["message"]["content"].as_str())
-            .ok_or_else(|| anyhow::anyhow!("❌ No response content found in API response"))?;
-
-        Ok(content.to_string())
+        true
     }
 
-    async fn create_new_session(&mut self) -> Result<()> {
-        let session_id = Uuid::new_v4().to_string();
-        self.current_session_id = Some(session_id.clone());
-        self.messages.clear();
-        self.status_message = format!("✨ New session created: {}", session_id);
-        Ok(())
+    #[cfg(not(feature = "server"))]
+    async fn search_sessions(&mut self) -> bool {
+        false
     }
 
-    fn cycle_model(&mut self) {
-        let current_index = self
-            .available_models
-            .iter()
-            .position(|m| m == &self.selected_model)
-            .unwrap_or(0);
+    /// Picks up what was written to the database since the last tick, by
+    /// another process sharing it or by this one: an open session picker
+    /// gets the current list, and the conversation is reloaded when its
+    /// stored messages no longer match the ones shown. Returns whether
+    /// anything changed on screen.
+    #[cfg(feature = "server")]
+    async fn follow_database(&mut self) -> bool {
+        let Some(database) = self.database.clone() else {
+            return false;
+        };
+        let Ok(version) = database.data_version().await else {
+            return false;
+        };
+        if self
+            .data_version
+            .replace(version)
+            .is_none_or(|seen| seen == version)
+        {
+            return false;
+        }
+
+        let mut changed = false;
+        if let Some(picker) = self.picker.as_mut() {
+            if let Ok(mut sessions) = database.sessions_updated_since(None).await {
+                sessions.reverse();
+                picker.refresh(sessions);
+                changed = true;
+            }
+        }
+        // Side by side replies are stored without their ids; leave them be
+        let Some(session_id) = self
+            .current_session_id
+            .clone()
+            .filter(|_| self.compare.is_none())
+        else {
+            return changed;
+        };
+        match database.get_messages(&session_id).await {
+            Ok(stored) if changed_elsewhere(&self.messages, &stored) => {
+                let selected = self
+                    .selected_message
+                    .and_then(|index| self.messages.get(index))
+                    .map(|message| message.id);
+                self.load_session(&session_id).await;
+                self.selected_message = selected
+                    .and_then(|id| self.messages.iter().position(|message| message.id == id));
+                self.status_message = tr("tui.session_changed").to_string();
+                true
+            }
+            _ => changed,
+        }
+    }
 
-        let next_index = (current_index + 1) % self.available_models.len();
-        self.selected_model = self.available_models[next_index].clone();
-        self.status_message = format!("Model changed to: {}", self.selected_model);
+    #[cfg(not(feature = "server"))]
+    async fn follow_database(&mut self) -> bool {
+        false
     }
 
-    fn load_session_list(&mut self) -> Result<()> {
-        // This would load existing sessions - simplified for now
-        self.status_message = "Session list loading not implemented yet.".to_string();
-        Ok(())
+    /// Switches to a saved session, selecting `message_id` if given. Like
+    /// `c`, the thread left behind comes back with `u`.
+    #[cfg(feature = "server")]
+    async fn open_session(&mut self, session_id: String, message_id: Option<i64>) {
+        let previous = self.current_session_id.replace(session_id.clone());
+        self.history.record(Edit::Thread(Box::new(Thread {
+            reason: "tui.edit_open_session",
+            session_id: previous,
+            messages: std::mem::take(&mut self.messages),
+            generations: std::mem::take(&mut self.generations),
+            model: self.selected_model.clone(),
+            cost: std::mem::replace(&mut self.session_cost, Cost::zero()),
+            compare: self.compare.take(),
+        })));
+        self.load_session(&session_id).await;
+        self.selected_message =
+            message_id.and_then(|id| self.messages.iter().position(|message| message.id == id));
+        self.status_message = tr_args("tui.resumed", &[("id", &session_id)]);
     }
 
     fn render(&mut self) -> Result<()> {
         let messages = &self.messages;
+        let attachments = &self.attachments;
+        let selected_message = self.selected_message;
         let input_buffer = &self.input_buffer;
         let input_mode = self.input_mode.clone();
         let selected_model = &self.selected_model;
         let status_message = &self.status_message;
+        let session_cost = self.session_cost.to_string();
         let show_help = self.show_help;
+        let popup = self.popup.as_ref();
+        #[cfg(feature = "server")]
+        let picker = self.picker.as_ref();
+        #[cfg(feature = "server")]
+        let restore_picker = self.restore_picker.as_ref();
+        let offline = self.offline;
+        let compare = self.compare.as_ref().map(|compare| {
+            let panes = compare.panes.each_ref().map(|pane| {
+                let cost = pane
+                    .usage
+                    .as_ref()
+                    .map(|usage| self.pricing.estimate_cost(usage, &pane.model));
+                let footer = pane_footer(pane.usage.as_ref(), pane.latency, cost);
+                (pane, footer)
+            });
+            (panes, compare.continuing)
+        });
+        let model_label = match &compare {
+            Some(([(left, _), (right, _)], _)) => format!("{} ⇄ {}", left.model, right.model),
+            None => selected_model.clone(),
+        };
+        let breakers = self.chat_service.breakers();
+        let breaker_notice = match &compare {
+            Some(([(left, _), (right, _)], _)) => vec![&left.model, &right.model],
+            None => vec![selected_model],
+        }
+        .into_iter()
+        .filter_map(|model| breakers.health_of(model))
+        .filter_map(|health| breaker_notice(&health))
+        .collect::<Vec<_>>()
+        .join(" ");
 
         self.terminal.draw(|f| {
             let size = f.size();
@@ -280,75 +2542,196 @@ impl ChatUI {
                 ])
                 .split(size);
 
-            // Render messages
-            ChatUI::render_messages(f, chunks[0], messages);
+            // Render messages, side by side in compare mode
+            match &compare {
+                Some((panes, continuing)) => {
+                    let columns = Layout::default()
+                        .direction(Direction::Horizontal)
+                        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                        .split(chunks[0]);
+                    for (index, (pane, footer)) in panes.iter().enumerate() {
+                        ChatUI::render_pane(f, columns[index], pane, footer, index == *continuing);
+                    }
+                }
+                None => ChatUI::render_messages(
+                    f,
+                    chunks[0],
+                    messages,
+                    attachments,
+                    selected_message,
+                    tr("tui.title_chat"),
+                ),
+            }
 
             // Render input area
             ChatUI::render_input(f, chunks[1], input_buffer, input_mode);
 
             // Render status bar
-            ChatUI::render_status_bar(f, chunks[2], selected_model, status_message);
+            ChatUI::render_status_bar(
+                f,
+                chunks[2],
+                &model_label,
+                &session_cost,
+                status_message,
+                offline,
+                &breaker_notice,
+            );
 
             // Render help if needed
             if show_help {
                 ChatUI::render_help(f, size);
             }
+            if let Some(popup) = popup {
+                ChatUI::render_popup(f, size, popup);
+            }
+            #[cfg(feature = "server")]
+            if let Some(picker) = picker {
+                ChatUI::render_picker(f, size, picker);
+            }
+            #[cfg(feature = "server")]
+            if let Some(choice) = restore_picker {
+                ChatUI::render_snapshots(f, size, choice);
+            }
         })?;
 
         Ok(())
     }
 
-    fn render_messages(f: &mut Frame, area: Rect, messages: &[Message]) {
+    fn render_messages(
+        f: &mut Frame,
+        area: Rect,
+        messages: &[Message],
+        attachments: &HashMap<i64, Vec<Attachment>>,
+        selected: Option<usize>,
+        title: &str,
+    ) {
+        let width = area.width.saturating_sub(2) as usize;
         let messages: Vec<ListItem> = messages
             .iter()
             .map(|msg| {
-                let role = match msg.role {
+                let role = match &msg.role {
                     MessageRole::User => Span::styled(
-                        "You: ",
+                        tr("tui.label_user"),
                         Style::default()
                             .fg(Color::Blue)
                             .add_modifier(Modifier::BOLD),
                     ),
                     MessageRole::Assistant => Span::styled(
-                        "Grok: ",
+                        tr("tui.label_assistant"),
                         Style::default()
                             .fg(Color::Green)
                             .add_modifier(Modifier::BOLD),
                     ),
                     MessageRole::System => Span::styled(
-                        "System: ",
+                        tr("tui.label_system"),
                         Style::default()
                             .fg(Color::Yellow)
                             .add_modifier(Modifier::BOLD),
                     ),
+                    MessageRole::Tool => Span::styled(
+                        tr("tui.label_tool"),
+                        Style::default()
+                            .fg(Color::Magenta)
+                            .add_modifier(Modifier::BOLD),
+                    ),
+                    MessageRole::Other(role) => Span::styled(
+                        format!("{}: ", role),
+                        Style::default()
+                            .fg(Color::Gray)
+                            .add_modifier(Modifier::BOLD),
+                    ),
                 };
 
-                // For long messages, we need to wrap them properly
-                let content_lines: Vec<Line> = if msg.content.len() > 50 {
-                    // Split long messages into multiple lines
-                    msg.content
-                        .chars()
-                        .collect::<Vec<_>>()
-                        .chunks(50)
-                        .map(|chunk| Line::from(Span::raw(chunk.iter().collect::<String>())))
-                        .collect()
-                } else {
-                    vec![Line::from(Span::raw(&msg.content))]
-                };
+                // Control characters would reach the terminal through the
+                // buffer, so they are made visible before laying out
+                let mut content_lines = content_lines(&sanitize(&msg.content), width);
+                for attachment in attachments.get(&msg.id).into_iter().flatten() {
+                    content_lines.push(image_placeholder(&attachment.describe()));
+                }
 
                 // Create the main line with role
-                let mut lines = vec![Line::from(vec![role.clone()])];
+                let mut header = vec![role];
+                if msg.pinned {
+                    header.push(Span::styled(
+                        tr("tui.label_pinned"),
+                        Style::default().fg(Color::Yellow),
+                    ));
+                }
+                if msg.is_queued() {
+                    header.push(Span::styled(
+                        tr("tui.label_queued"),
+                        Style::default()
+                            .fg(Color::Magenta)
+                            .add_modifier(Modifier::ITALIC),
+                    ));
+                }
+                if let Some(annotation) = &msg.annotation {
+                    let mut marks: Vec<String> = Vec::new();
+                    match annotation.rating {
+                        Some(Rating::Up) => marks.push("👍".to_string()),
+                        Some(Rating::Down) => marks.push("👎".to_string()),
+                        None => {}
+                    }
+                    marks.extend(annotation.labels.iter().map(|label| format!("#{}", label)));
+                    if !marks.is_empty() {
+                        header.push(Span::styled(
+                            format!(" {}", marks.join(" ")),
+                            Style::default().fg(Color::Cyan),
+                        ));
+                    }
+                }
+                let mut lines = vec![Line::from(header)];
                 lines.extend(content_lines);
+                if let Some(note) = msg.annotation.as_ref().and_then(|a| a.note.as_deref()) {
+                    lines.push(Line::from(Span::styled(
+                        format!("📝 {}", sanitize(note)),
+                        Style::default()
+                            .fg(Color::Cyan)
+                            .add_modifier(Modifier::ITALIC),
+                    )));
+                }
 
-                ListItem::new(lines).style(Style::default().fg(Color::White))
+                // Examples from a session template stay in the background
+                let style = if msg.seeded {
+                    Style::default()
+                        .fg(Color::DarkGray)
+                        .add_modifier(Modifier::DIM)
+                } else {
+                    Style::default().fg(Color::White)
+                };
+                ListItem::new(lines).style(style)
             })
             .collect();
 
         let messages_list = List::new(messages)
-            .block(Block::default().borders(Borders::ALL).title("💬 Chat"))
-            .highlight_style(Style::default().add_modifier(Modifier::BOLD));
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .highlight_style(
+                Style::default()
+                    .bg(Color::DarkGray)
+                    .add_modifier(Modifier::BOLD),
+            );
+
+        let mut state = ListState::default().with_selected(selected);
+        f.render_stateful_widget(messages_list, area, &mut state);
+    }
 
-        f.render_widget(messages_list, area);
+    /// One compare pane: its thread titled with the model, above a footer
+    /// with the latest reply's latency and usage.
+    fn render_pane(f: &mut Frame, area: Rect, pane: &ComparePane, footer: &str, continuing: bool) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(1), Constraint::Length(1)])
+            .split(area);
+        let title = if continuing {
+            tr_args("tui.title_pane_continuing", &[("model", &pane.model)])
+        } else {
+            format!("💬 {}", pane.model)
+        };
+        ChatUI::render_messages(f, rows[0], &pane.messages, &HashMap::new(), None, &title);
+        f.render_widget(
+            Paragraph::new(footer).style(Style::default().fg(Color::Gray)),
+            rows[1],
+        );
     }
 
     fn render_input(f: &mut Frame, area: Rect, input_buffer: &str, input_mode: InputMode) {
@@ -357,58 +2740,99 @@ impl ChatUI {
                 InputMode::Insert => Style::default().fg(Color::White),
                 InputMode::Normal => Style::default().fg(Color::Gray),
             })
-            .block(Block::default().borders(Borders::ALL).title("Input"))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(tr("tui.title_input")),
+            )
             .wrap(Wrap { trim: true });
 
         f.render_widget(input, area);
     }
 
-    fn render_status_bar(f: &mut Frame, area: Rect, selected_model: &str, status_message: &str) {
-        let status_parts = vec![
-            Span::styled("Model: ", Style::default().fg(Color::Cyan)),
+    fn render_status_bar(
+        f: &mut Frame,
+        area: Rect,
+        selected_model: &str,
+        session_cost: &str,
+        status_message: &str,
+        offline: bool,
+        breaker_notice: &str,
+    ) {
+        let mut status_parts = Vec::new();
+        if offline {
+            status_parts.push(Span::styled(
+                tr("tui.label_offline"),
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ));
+            status_parts.push(Span::raw(" | "));
+        }
+        if !breaker_notice.is_empty() {
+            status_parts.push(Span::styled(
+                breaker_notice,
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            ));
+            status_parts.push(Span::raw(" | "));
+        }
+        status_parts.extend([
+            Span::styled(tr("tui.label_model"), Style::default().fg(Color::Cyan)),
             Span::styled(selected_model, Style::default().fg(Color::White)),
             Span::raw(" | "),
+            Span::styled(tr("tui.label_cost"), Style::default().fg(Color::Cyan)),
+            Span::styled(session_cost, Style::default().fg(Color::White)),
+            Span::raw(" | "),
             Span::styled(status_message, Style::default().fg(Color::Gray)),
-        ];
+        ]);
 
         let status = Paragraph::new(Line::from(status_parts))
-            .block(Block::default().borders(Borders::ALL).title("Status"))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(tr("tui.title_status")),
+            )
             .wrap(Wrap { trim: true });
 
         f.render_widget(status, area);
     }
 
     fn render_help(f: &mut Frame, area: Rect) {
-        let help_text = vec![
-            Line::from(vec![Span::styled(
-                "Help",
-                Style::default().add_modifier(Modifier::BOLD),
-            )]),
-            Line::from(""),
+        let heading = |key| {
             Line::from(vec![Span::styled(
-                "Normal Mode:",
+                tr(key),
                 Style::default().fg(Color::Yellow),
-            )]),
-            Line::from("  i - Enter insert mode"),
-            Line::from("  q - Quit"),
-            Line::from("  h - Toggle help"),
-            Line::from("  c - Create new session"),
-            Line::from("  m - Cycle model"),
-            Line::from("  l - Load sessions"),
-            Line::from(""),
+            )])
+        };
+        let bindings = |keys: &[(&'static str, &'static str)]| {
+            keys.iter()
+                .map(|(keys, description)| Line::from(format!("  {} - {}", keys, tr(description))))
+                .collect::<Vec<_>>()
+        };
+        let mut help_text = vec![
             Line::from(vec![Span::styled(
-                "Insert Mode:",
-                Style::default().fg(Color::Yellow),
+                tr("help.title"),
+                Style::default().add_modifier(Modifier::BOLD),
             )]),
-            Line::from("  Enter - Send message"),
-            Line::from("  Esc - Return to normal mode"),
-            Line::from("  Type your message..."),
             Line::from(""),
-            Line::from("Press any key to close help..."),
+            heading("help.normal_mode"),
         ];
+        help_text.extend(bindings(NORMAL_MODE_KEYS));
+        help_text.push(Line::from(""));
+        help_text.push(heading("help.insert_mode"));
+        help_text.extend(bindings(INSERT_MODE_KEYS));
+        help_text.extend([
+            Line::from(format!("  {}", tr("help.type"))),
+            Line::from(""),
+            Line::from(tr("help.close")),
+        ]);
 
         let help = Paragraph::new(help_text)
-            .block(Block::default().borders(Borders::ALL).title("Help"))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(tr("help.title")),
+            )
             .style(Style::default().fg(Color::White))
             .wrap(Wrap { trim: true });
 
@@ -422,20 +2846,555 @@ impl ChatUI {
         f.render_widget(Clear, help_area);
         f.render_widget(help, help_area);
     }
+
+    /// The picker over the middle of the screen: the query, then a row per
+    /// session with the matched letters bold and, for content matches, the
+    /// snippet underneath with the matched words highlighted.
+    #[cfg(feature = "server")]
+    fn render_picker(f: &mut Frame, area: Rect, picker: &SessionPicker) {
+        let area = Rect {
+            x: area.width / 8,
+            y: area.height / 8,
+            width: area.width * 3 / 4,
+            height: area.height * 3 / 4,
+        };
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(tr("tui.title_picker"));
+        let inner = block.inner(area);
+        f.render_widget(Clear, area);
+        f.render_widget(block, area);
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(1), Constraint::Min(1)])
+            .split(inner);
+
+        f.render_widget(
+            Paragraph::new(format!("🔍 {}", sanitize(picker.query()))),
+            rows[0],
+        );
+        let matched = Style::default()
+            .fg(Color::Yellow)
+            .add_modifier(Modifier::BOLD);
+        let entries = picker.entries();
+        if entries.is_empty() {
+            f.render_widget(
+                Paragraph::new(tr("tui.picker_empty")).style(Style::default().fg(Color::DarkGray)),
+                rows[1],
+            );
+            return;
+        }
+        let items: Vec<ListItem> = entries
+            .iter()
+            .map(|entry| {
+                let session = entry.session;
+                let mut header = match &session.title {
+                    Some(title) => marked_spans(title, &entry.title_matches, matched),
+                    None => vec![Span::styled(
+                        tr("tui.picker_untitled"),
+                        Style::default().fg(Color::DarkGray),
+                    )],
+                };
+                header.push(Span::raw("  "));
+                header.extend(marked_spans(
+                    &session.model,
+                    &entry.model_matches,
+                    matched.fg(Color::Cyan),
+                ));
+                header.push(Span::styled(
+                    format!("  {}", session.updated_at.format("%Y-%m-%d")),
+                    Style::default().fg(Color::DarkGray),
+                ));
+                let mut lines = vec![Line::from(header)];
+                if let Some(hit) = entry.hit {
+                    let mut snippet =
+                        vec![Span::styled("  ↳ ", Style::default().fg(Color::DarkGray))];
+                    snippet.extend(snippet_spans(&hit.snippet, matched));
+                    lines.push(Line::from(snippet));
+                }
+                ListItem::new(lines)
+            })
+            .collect();
+        let list = List::new(items).highlight_style(
+            Style::default()
+                .bg(Color::DarkGray)
+                .add_modifier(Modifier::BOLD),
+        );
+        let mut state = ListState::default().with_selected(Some(picker.selected()));
+        f.render_stateful_widget(list, rows[1], &mut state);
+    }
+
+    /// The snapshots `/restore` offers, each with its label and when it was
+    /// taken.
+    #[cfg(feature = "server")]
+    fn render_snapshots(f: &mut Frame, area: Rect, choice: &SnapshotChoice) {
+        let area = Rect {
+            x: area.width / 4,
+            y: area.height / 4,
+            width: area.width / 2,
+            height: area.height / 2,
+        };
+        let items: Vec<ListItem> = choice
+            .snapshots
+            .iter()
+            .map(|snapshot| {
+                ListItem::new(Line::from(vec![
+                    Span::raw(sanitize(&snapshot.label)),
+                    Span::styled(
+                        format!(
+                            "  {}",
+                            snapshot
+                                .created_at
+                                .with_timezone(&chrono::Local)
+                                .format("%Y-%m-%d %H:%M")
+                        ),
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                ]))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(tr("tui.title_snapshots")),
+            )
+            .highlight_style(
+                Style::default()
+                    .bg(Color::DarkGray)
+                    .add_modifier(Modifier::BOLD),
+            );
+        let mut state = ListState::default().with_selected(Some(choice.selected));
+        f.render_widget(Clear, area);
+        f.render_stateful_widget(list, area, &mut state);
+    }
+
+    fn render_popup(f: &mut Frame, area: Rect, popup: &Popup) {
+        let scroll = popup.scroll;
+        let popup = Paragraph::new(popup.lines.clone())
+            .block(Block::default().borders(Borders::ALL).title(popup.title))
+            .style(Style::default().fg(Color::White))
+            .wrap(Wrap { trim: false })
+            .scroll((scroll, 0));
+
+        let popup_area = Rect {
+            x: area.width / 8,
+            y: area.height / 8,
+            width: area.width * 3 / 4,
+            height: area.height * 3 / 4,
+        };
+
+        f.render_widget(Clear, popup_area);
+        f.render_widget(popup, popup_area);
+    }
 }
 
-pub async fn run_terminal_chat(session_id: Option<String>, model: String) -> Result<()> {
-    let mut ui = ChatUI::new().await?;
+/// Opens the terminal UI, using `model` instead of the configured default,
+/// once the startup preflight passes or is skipped. Starting `offline` skips
+/// the preflight, which needs the API.
+pub async fn run_terminal_chat(
+    session_id: Option<String>,
+    model: Option<String>,
+    skip_preflight: bool,
+    offline: bool,
+) -> Result<()> {
+    // Problems are easier to read before the terminal switches screens
+    startup_config(skip_preflight || offline).await?;
+    let orphans = offer_recovery()?;
+    let mut ui = ChatUI::new(model.as_deref(), offline).await?;
+    if !orphans.is_empty() {
+        ui.recover(orphans).await;
+    }
 
     if let Some(sid) = session_id {
+        ui.load_session(&sid).await;
         ui.current_session_id = Some(sid.clone());
-        ui.status_message = format!("🔄 Resumed session: {}", sid);
+        ui.status_message = tr_args("tui.resumed", &[("id", &sid)]);
     }
 
-    ui.selected_model = model;
     ui.run().await
 }
 
+/// Asks whether to bring back what a previous UI lost by exiting
+/// unexpectedly. Declined journals are discarded, so the question comes up
+/// once.
+fn offer_recovery() -> Result<Vec<OrphanedJournal>> {
+    // Trouble with the journals must not keep the UI from starting
+    let Some(orphans) = journal_dir().and_then(|dir| orphaned_journals(&dir).ok()) else {
+        return Ok(Vec::new());
+    };
+    if orphans.is_empty() {
+        return Ok(orphans);
+    }
+
+    let messages: usize = orphans.iter().map(|o| o.message_count()).sum();
+    let sessions: usize = orphans.iter().map(|o| o.sessions.len()).sum();
+    eprint!(
+        "{}",
+        tr_args(
+            "prompt.recover",
+            &[("messages", &messages), ("sessions", &sessions)]
+        )
+    );
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    if is_yes(&answer) {
+        Ok(orphans)
+    } else {
+        for orphan in orphans {
+            let _ = orphan.discard();
+        }
+        Ok(Vec::new())
+    }
+}
+
+/// Whether `answer` to a `[Y/n]` question accepts; a blank answer does.
+fn is_yes(answer: &str) -> bool {
+    answer.trim().is_empty() || is_affirmative(answer)
+}
+
+/// The newest compare prompt, once, followed by the reply of the pane
+/// continuing when it arrived, so the session reads as one conversation; a
+/// failed reply is left out.
+fn compare_exchange(compare: &Compare) -> Vec<Message> {
+    let pane = &compare.panes[compare.continuing];
+    let prompt = pane.messages.iter().rev().nth(1);
+    prompt
+        .into_iter()
+        .chain(pane.messages.last())
+        .filter(|message| !is_error_placeholder(message))
+        .cloned()
+        .collect()
+}
+
+/// The text after `command` in `input`, when `input` starts with that slash
+/// command as a whole word.
+fn slash_argument<'a>(input: &'a str, command: &str) -> Option<&'a str> {
+    input
+        .strip_prefix(command)
+        .filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+}
+
+/// Splits `/note` text into the note and its `#labels`.
+fn parse_note(text: &str) -> (Option<String>, Vec<String>) {
+    let (labels, words): (Vec<&str>, Vec<&str>) = text
+        .split_whitespace()
+        .partition(|word| word.len() > 1 && word.starts_with('#'));
+    let note = words.join(" ");
+    (
+        (!note.is_empty()).then_some(note),
+        labels.iter().map(|label| label[1..].to_string()).collect(),
+    )
+}
+
+/// A summary of what a dry run found, then the body it would post.
+fn dry_run_text(dry_run: &DryRun) -> String {
+    let mut lines = vec![tr_args(
+        "tui.dry_run_summary",
+        &[("tokens", &dry_run.estimated_tokens)],
+    )];
+    if !dry_run.dropped.is_empty() {
+        lines.push(tr_args(
+            "tui.dry_run_dropped",
+            &[("count", &dry_run.dropped.len())],
+        ));
+    }
+    if dry_run.redactions > 0 {
+        lines.push(tr_args(
+            "tui.dry_run_redacted",
+            &[("count", &dry_run.redactions)],
+        ));
+    }
+    lines.extend(dry_run.warnings.iter().map(ToString::to_string));
+    lines.push(String::new());
+    lines.push(serde_json::to_string_pretty(&dry_run.body).unwrap_or_default());
+    lines.join("\n")
+}
+
+/// The status line for a reply that failed because the model was retired.
+fn retired_status(retired: &RetiredModel) -> String {
+    match &retired.suggestion {
+        Some(suggestion) => tr_args(
+            "tui.model_retired_try",
+            &[("model", &retired.model), ("suggestion", suggestion)],
+        ),
+        None => tr_args("tui.model_retired", &[("model", &retired.model)]),
+    }
+}
+
+fn hook_error(hook: &Hook, error: &anyhow::Error) -> String {
+    let error = format!("{:#}", error);
+    tr_args("hooks.error", &[("name", &hook.name), ("error", &error)])
+}
+
+/// Diff spans as popup lines: additions in green, deletions in red and
+/// struck through.
+fn diff_lines(spans: &[DiffSpan]) -> Vec<Line<'static>> {
+    let mut lines = vec![Line::default()];
+    for span in spans {
+        let style = match span {
+            DiffSpan::Equal(_) => Style::default(),
+            DiffSpan::Insert(_) => Style::default().fg(Color::Green),
+            DiffSpan::Delete(_) => Style::default()
+                .fg(Color::Red)
+                .add_modifier(Modifier::CROSSED_OUT),
+        };
+        for (index, part) in sanitize(span.text()).split('\n').enumerate() {
+            if index > 0 {
+                lines.push(Line::default());
+            }
+            if !part.is_empty() {
+                let line = lines.last_mut().expect("lines start with one");
+                line.spans.push(Span::styled(part.to_string(), style));
+            }
+        }
+    }
+    lines
+}
+
+/// Message text as lines of the messages pane: markdown tables laid out to
+/// fit `width`, a placeholder under each line that shows an image, and
+/// everything else cut at 50 characters. Code blocks are left as they are.
+fn content_lines(text: &str, width: usize) -> Vec<Line<'static>> {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let mut out = Vec::new();
+    let mut fenced = false;
+    let mut index = 0;
+    while index < lines.len() {
+        let line = lines[index];
+        if line.trim_start().starts_with("```") {
+            fenced = !fenced;
+        } else if !fenced {
+            if let Some((table, spanned)) = Table::parse(&lines[index..]) {
+                out.extend(table.render(width).into_iter().map(Line::from));
+                index += spanned;
+                continue;
+            }
+        }
+
+        let chars: Vec<char> = line.chars().collect();
+        if chars.is_empty() {
+            out.push(Line::from(""));
+        }
+        out.extend(
+            chars
+                .chunks(50)
+                .map(|chunk| Line::from(chunk.iter().collect::<String>())),
+        );
+        if !fenced {
+            for image in find_images(line) {
+                let name = match image.alt.as_str() {
+                    "" => image.file_name().to_string(),
+                    alt => format!("{} ({})", alt, image.file_name()),
+                };
+                out.push(image_placeholder(&name));
+            }
+        }
+        index += 1;
+    }
+    out
+}
+
+/// Where an image would be, which `o` opens.
+fn image_placeholder(description: &str) -> Line<'static> {
+    Line::from(Span::styled(
+        format!("  🖼  {} · {}", description, tr("tui.image_hint")),
+        Style::default()
+            .fg(Color::Magenta)
+            .add_modifier(Modifier::ITALIC),
+    ))
+}
+
+impl Attachment {
+    /// `None` for a blob the media store can't have.
+    #[cfg(feature = "server")]
+    fn stored(store: &MediaStore, media: &StoredMedia) -> Option<Self> {
+        use crate::render::image_dimensions;
+        use std::io::Read;
+
+        let path = store.path(&media.hash)?;
+        // The header is enough for the dimensions
+        let mut header = Vec::new();
+        let dimensions = std::fs::File::open(&path)
+            .and_then(|file| file.take(64 * 1024).read_to_end(&mut header))
+            .ok()
+            .and_then(|_| image_dimensions(&header));
+        Some(Self {
+            path,
+            mime_type: media.mime_type.clone(),
+            size: media.size,
+            dimensions,
+        })
+    }
+
+    fn describe(&self) -> String {
+        let mut parts = vec![self.mime_type.clone()];
+        if let Some((width, height)) = self.dimensions {
+            parts.push(format!("{}×{}", width, height));
+        }
+        parts.push(format!("{} KiB", self.size.div_ceil(1024)));
+        parts.join(" · ")
+    }
+}
+
+/// Hands `target`, a file or a URL, to the desktop's default viewer.
+fn open_externally(target: &str) -> io::Result<()> {
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "explorer"
+    } else {
+        "xdg-open"
+    };
+    let mut child = Command::new(opener)
+        .arg(target)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // Reaped in the background, so the UI doesn't wait for the viewer
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// Orders the model picker by provider: xAI's models first, then each
+/// other provider's by name, keeping the order within each.
+fn group_by_provider(models: &mut [String]) {
+    let key = |model: &String| {
+        let provider = split_model(model).0;
+        (provider != DEFAULT_PROVIDER, provider.to_string())
+    };
+    models.sort_by_cached_key(key);
+}
+
+/// The model after `current` in `models`, wrapping around.
+fn next_model(models: &[String], current: &str) -> String {
+    let index = models.iter().position(|m| m == current).unwrap_or(0);
+    models[(index + 1) % models.len()].clone()
+}
+
+/// Status bar text for a model whose breaker isn't closed.
+fn breaker_notice(health: &ModelHealth) -> Option<String> {
+    match health.state {
+        BreakerState::Closed => None,
+        BreakerState::Open => Some(tr_args(
+            "tui.breaker_open",
+            &[
+                ("model", &health.model),
+                ("secs", &health.retry_in_secs.unwrap_or(0)),
+            ],
+        )),
+        BreakerState::HalfOpen => Some(tr_args("tui.breaker_probing", &[("model", &health.model)])),
+    }
+}
+
+/// Turns either kind of response into a stream of chunks, so complete and
+/// streamed replies fill a compare pane the same way.
+fn reply_chunks(response: Result<ChatResponse>) -> BoxStream<'static, Result<StreamChunk>> {
+    match response {
+        Ok(ChatResponse::Stream(chunks)) => chunks,
+        Ok(ChatResponse::Complete(response)) => {
+            let chunk = response.get_content().map(|content| StreamChunk {
+                processed: (!response.effects.is_empty()).then(|| ProcessedResponse {
+                    content: content.clone(),
+                    effects: response.effects.clone(),
+                }),
+                content,
+                usage: response.usage.clone(),
+                ..StreamChunk::default()
+            });
+            stream::once(async move { chunk }).boxed()
+        }
+        Err(e) => stream::once(async move { Err(e) }).boxed(),
+    }
+}
+
+/// The footer under a compare pane, e.g. `1.2s · 12 + 40 tokens · $0.0004`.
+fn pane_footer(
+    usage: Option<&UsageStats>,
+    latency: Option<Duration>,
+    cost: Option<Cost>,
+) -> String {
+    let mut parts = Vec::new();
+    if let Some(latency) = latency {
+        parts.push(format!("{:.1}s", latency.as_secs_f64()));
+    }
+    if let Some(usage) = usage {
+        parts.push(tr_args(
+            "tui.pane_tokens",
+            &[
+                ("prompt", &usage.prompt_tokens),
+                ("completion", &usage.completion_tokens),
+            ],
+        ));
+    }
+    if let Some(cost) = cost {
+        parts.push(cost.to_string());
+    }
+    if parts.is_empty() {
+        return tr("tui.pane_waiting").to_string();
+    }
+    parts.join(" · ")
+}
+
+/// Failed sends are shown as assistant messages from the model `error`; they
+/// were never part of the conversation.
+/// `text` with the chars at `positions` in `style`, made safe to print.
+#[cfg(feature = "server")]
+fn marked_spans(text: &str, positions: &[usize], style: Style) -> Vec<Span<'static>> {
+    let mut runs: Vec<(bool, String)> = Vec::new();
+    for (index, c) in text.chars().enumerate() {
+        let marked = positions.contains(&index);
+        match runs.last_mut() {
+            Some((run_marked, run)) if *run_marked == marked => run.push(c),
+            _ => runs.push((marked, c.to_string())),
+        }
+    }
+    runs.into_iter()
+        .map(|(marked, run)| {
+            if marked {
+                Span::styled(sanitize(&run), style)
+            } else {
+                Span::raw(sanitize(&run))
+            }
+        })
+        .collect()
+}
+
+/// A snippet on one line, its matched words in `style`.
+#[cfg(feature = "server")]
+fn snippet_spans(snippet: &Snippet, style: Style) -> Vec<Span<'static>> {
+    let flat = |text: &str| sanitize(&text.replace(['\n', '\r', '\t'], " "));
+    let mut spans = Vec::new();
+    let mut end = 0;
+    for range in &snippet.highlights {
+        if range.start > end {
+            spans.push(Span::raw(flat(&snippet.text[end..range.start])));
+        }
+        spans.push(Span::styled(flat(&snippet.text[range.clone()]), style));
+        end = range.end;
+    }
+    if end < snippet.text.len() {
+        spans.push(Span::raw(flat(&snippet.text[end..])));
+    }
+    spans
+}
+
+fn is_error_placeholder(message: &Message) -> bool {
+    message.role == MessageRole::Assistant && message.model.as_deref() == Some("error")
+}
+
+/// Whether a saved session's `stored` messages were added, removed or
+/// replaced by someone else than the conversation `shown`. Messages shown
+/// but never saved, with id 0, don't count; a session that isn't saved
+/// hasn't changed.
+#[cfg(feature = "server")]
+fn changed_elsewhere(shown: &[Message], stored: &[Message]) -> bool {
+    let shown = shown.iter().map(|m| m.id).filter(|&id| id != 0);
+    !stored.is_empty() && !shown.eq(stored.iter().map(|m| m.id))
+}
+
 fn setup_terminal() -> Result<AppTerminal> {
     let mut stdout = io::stdout();
     enable_raw_mode()?;
@@ -474,6 +3433,299 @@ mod tests {
         assert_ne!(InputMode::Normal, InputMode::Insert);
     }
 
+    #[test]
+    fn test_undo_stack_is_bounded_and_new_edits_clear_redo() {
+        let mut history = UndoStack::new();
+        for edit in 0..UNDO_LIMIT + 5 {
+            history.record(edit);
+        }
+        assert_eq!(history.done.len(), UNDO_LIMIT);
+        assert_eq!(history.done.front(), Some(&5));
+
+        let undone = history.done.pop_back().unwrap();
+        history.undone.push(undone);
+        history.record(99);
+        assert!(history.undone.is_empty());
+        assert_eq!(history.done.back(), Some(&99));
+    }
+
+    #[test]
+    fn test_slash_argument_matches_whole_commands() {
+        assert_eq!(slash_argument("/prompt terse", "/prompt"), Some(" terse"));
+        assert_eq!(slash_argument("/prompt", "/prompt"), Some(""));
+        assert_eq!(slash_argument("/prompts", "/prompt"), None);
+        assert_eq!(slash_argument("prompt terse", "/prompt"), None);
+        assert_eq!(slash_argument("/templatefoo x=1", "/template"), None);
+    }
+
+    #[test]
+    fn test_parse_note_takes_hash_words_as_labels() {
+        assert_eq!(
+            parse_note(" made up a source #hallucination  #facts"),
+            (
+                Some("made up a source".to_string()),
+                vec!["hallucination".to_string(), "facts".to_string()]
+            )
+        );
+        assert_eq!(
+            parse_note("#tone # ok"),
+            (Some("# ok".to_string()), vec!["tone".to_string()])
+        );
+        assert_eq!(parse_note("  "), (None, Vec::new()));
+    }
+
+    #[test]
+    fn test_dry_run_text_summarizes_before_the_body() {
+        let dry_run = DryRun {
+            body: serde_json::json!({"model": "grok-3", "messages": []}),
+            estimated_tokens: 12,
+            redactions: 1,
+            warnings: Vec::new(),
+            dropped: Vec::new(),
+        };
+        let text = dry_run_text(&dry_run);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "Nothing was sent. About 12 prompt tokens.");
+        assert_eq!(lines[1], "1 secret(s) redacted");
+        assert_eq!(lines[2], "");
+        assert_eq!(lines[3], "{");
+        assert!(!text.contains("left out"));
+    }
+
+    #[test]
+    fn test_diff_lines_color_changes_and_keep_line_breaks() {
+        let lines = diff_lines(&word_diff(
+            "Use a list.\nIt is fast.",
+            "Use a vector.\nIt is fast.",
+        ));
+        assert_eq!(lines.len(), 2);
+        let styled: Vec<(&str, Option<Color>)> = lines[0]
+            .spans
+            .iter()
+            .map(|span| (span.content.as_ref(), span.style.fg))
+            .collect();
+        assert_eq!(
+            styled,
+            [
+                ("Use a ", None),
+                ("list.", Some(Color::Red)),
+                ("vector.", Some(Color::Green)),
+            ]
+        );
+        assert_eq!(lines[1].spans[0].content, "It is fast.");
+    }
+
+    #[test]
+    fn test_content_lines_lay_out_tables_and_mark_images() {
+        let text = "| a | b |\n|---|---|\n| 1 | 2 |\nSee ![plot](https://x.ai/p.png)\n```\n| x | y |\n|---|---|\n```";
+        let lines: Vec<String> = content_lines(text, 40)
+            .iter()
+            .map(|line| {
+                line.spans
+                    .iter()
+                    .map(|span| span.content.as_ref())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(lines[0], "┌───┬───┐");
+        assert_eq!(lines[3], "│ 1 │ 2 │");
+        assert_eq!(lines[5], "See ![plot](https://x.ai/p.png)");
+        assert!(lines[6].contains("plot (p.png)"));
+        // Tables inside code blocks stay as written
+        assert_eq!(lines[8..10], ["| x | y |", "|---|---|"]);
+    }
+
+    #[test]
+    fn test_help_describes_every_key_in_every_locale() {
+        use crate::i18n::{lookup, Locale};
+
+        for (keys, description) in NORMAL_MODE_KEYS.iter().chain(INSERT_MODE_KEYS) {
+            for locale in Locale::ALL {
+                assert!(
+                    lookup(locale, description).is_some(),
+                    "no {} description for {}",
+                    locale,
+                    keys
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_model_picker_groups_models_by_provider() {
+        let mut models = [
+            "openai/gpt-4o",
+            "grok-3",
+            "local/llama3",
+            "local/qwen",
+            "grok-3-mini",
+        ]
+        .map(str::to_string)
+        .to_vec();
+        group_by_provider(&mut models);
+        assert_eq!(
+            models,
+            [
+                "grok-3",
+                "grok-3-mini",
+                "local/llama3",
+                "local/qwen",
+                "openai/gpt-4o"
+            ]
+        );
+        assert_eq!(next_model(&models, "grok-3-mini"), "local/llama3");
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn test_sessions_changed_elsewhere_are_noticed() {
+        let message = |id, content: &str| Message {
+            id,
+            ..Message::user("session-123".to_string(), content.to_string())
+        };
+        let stored = [message(1, "Hi"), message(2, "Hello")];
+        assert!(!changed_elsewhere(&stored, &stored));
+        // Unsaved messages on screen, such as error placeholders, don't count
+        assert!(!changed_elsewhere(
+            &[message(1, "Hi"), message(2, "Hello"), message(0, "failed")],
+            &stored
+        ));
+        // A reply added by the server, or one regenerated there
+        assert!(changed_elsewhere(&stored[..1], &stored));
+        assert!(changed_elsewhere(
+            &[message(1, "Hi"), message(3, "Hey")],
+            &stored
+        ));
+        // A conversation only on screen
+        assert!(!changed_elsewhere(&[message(0, "Hi")], &[]));
+    }
+
+    #[test]
+    fn test_breaker_notice_only_for_tripped_models() {
+        let mut health = ModelHealth {
+            model: "grok-3".to_string(),
+            state: BreakerState::Closed,
+            requests: 5,
+            success_rate: Some(0.0),
+            p95_latency_ms: None,
+            consecutive_failures: 5,
+            retry_in_secs: None,
+        };
+        assert_eq!(breaker_notice(&health), None);
+        health.state = BreakerState::Open;
+        health.retry_in_secs = Some(12);
+        assert_eq!(breaker_notice(&health).unwrap(), "⛔ grok-3 paused 12s");
+    }
+
+    #[test]
+    fn test_compare_exchange_keeps_only_the_continuing_reply() {
+        let mut compare = Compare {
+            panes: [
+                ComparePane::new("grok-4".to_string(), Vec::new()),
+                ComparePane::new("grok-3-mini".to_string(), Vec::new()),
+            ],
+            continuing: 0,
+        };
+        let mut stored = Vec::new();
+        for (prompt, continuing) in [("One?", 1), ("Two?", 0)] {
+            compare.continuing = continuing;
+            for pane in &mut compare.panes {
+                let reply = format!("{} from {}", prompt, pane.model);
+                pane.messages
+                    .push(Message::user("s1".to_string(), prompt.to_string()));
+                pane.messages.push(Message::assistant(
+                    "s1".to_string(),
+                    reply,
+                    Some(pane.model.clone()),
+                ));
+            }
+            stored.extend(compare_exchange(&compare));
+        }
+
+        // What a later send reads back alternates as a normal conversation
+        let context: Vec<_> = stored
+            .iter()
+            .map(|message| (message.role.clone(), message.content.as_str()))
+            .collect();
+        assert_eq!(
+            context,
+            [
+                (MessageRole::User, "One?"),
+                (MessageRole::Assistant, "One? from grok-3-mini"),
+                (MessageRole::User, "Two?"),
+                (MessageRole::Assistant, "Two? from grok-4"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compare_exchange_skips_failed_replies() {
+        let prompt = Message::user("s1".to_string(), "hi".to_string());
+        let pane = |reply: Message| ComparePane::new("m".to_string(), vec![prompt.clone(), reply]);
+        let mut compare = Compare {
+            panes: [
+                pane(Message::assistant(
+                    "s1".to_string(),
+                    "hello".to_string(),
+                    None,
+                )),
+                pane(Message::assistant(
+                    "s1".to_string(),
+                    "❌ Error: boom".to_string(),
+                    Some("error".to_string()),
+                )),
+            ],
+            continuing: 0,
+        };
+
+        let contents: Vec<_> = compare_exchange(&compare)
+            .into_iter()
+            .map(|message| message.content)
+            .collect();
+        assert_eq!(contents, ["hi", "hello"]);
+        compare.continuing = 1;
+        let contents: Vec<_> = compare_exchange(&compare)
+            .into_iter()
+            .map(|message| message.content)
+            .collect();
+        assert_eq!(contents, ["hi"]);
+    }
+
+    #[test]
+    fn test_compare_partner_is_the_next_model() {
+        let models = vec!["grok-4".to_string(), "grok-3-mini".to_string()];
+        assert_eq!(next_model(&models, "grok-4"), "grok-3-mini");
+        assert_eq!(next_model(&models, "grok-3-mini"), "grok-4");
+        assert_eq!(next_model(&models, "custom"), "grok-3-mini");
+    }
+
+    #[test]
+    fn test_pane_footer() {
+        assert_eq!(pane_footer(None, None, None), "waiting for a prompt");
+        let usage = UsageStats {
+            prompt_tokens: 12,
+            completion_tokens: 40,
+            total_tokens: 52,
+        };
+        assert_eq!(
+            pane_footer(Some(&usage), Some(Duration::from_millis(1250)), None),
+            "1.2s · 12 + 40 tokens"
+        );
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn test_error_placeholders_are_not_saved() {
+        let error = Message::assistant(
+            "session-123".to_string(),
+            "❌ Error: boom".to_string(),
+            Some("error".to_string()),
+        );
+        let reply = Message::assistant("session-123".to_string(), "Hi".to_string(), None);
+        assert!(is_error_placeholder(&error));
+        assert!(!is_error_placeholder(&reply));
+    }
+
     #[test]
     fn test_message_formatting() {
         let message = Message::user("session-123".to_string(), "Hello".to_string());
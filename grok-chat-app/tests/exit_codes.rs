@@ -0,0 +1,294 @@
+//! Runs the binary against a throwaway HTTP upstream and checks that each
+//! failure mode maps to its documented exit code.
+
+use assert_cmd::Command;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+/// Answers every request with `status` and `body`, returning the base URL.
+fn upstream(status: u16, body: &'static str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut content_length = 0;
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap_or(0) > 2 {
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap_or(0);
+                    }
+                }
+                line.clear();
+            }
+            let mut request_body = vec![0; content_length];
+            let _ = reader.read_exact(&mut request_body);
+
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+        }
+    });
+
+    format!("http://{}/v1", addr)
+}
+
+fn grok(base_url: &str) -> Command {
+    let mut command = Command::cargo_bin("grok-chat-app").unwrap();
+    command
+        .env("XAI_API_KEY", "test-key")
+        .env("XAI_BASE_URL", base_url)
+        .env("REQUEST_TIMEOUT_SECS", "5")
+        .env("GROK_CONFIG", "/nonexistent/grok-chat/config.toml")
+        .env_remove("GROK_PROFILE")
+        .env("GROK_NO_KEYRING", "1")
+        .env("GROK_LOCALE", "en")
+        .env_remove("NO_COLOR");
+    command
+}
+
+const COMPLETION: &str = r#"{"id":"c","object":"chat.completion","created":0,"model":"grok-test","choices":[{"index":0,"message":{"role":"assistant","content":"hello"},"finish_reason":"stop"}],"usage":{"prompt_tokens":1,"completion_tokens":1,"total_tokens":2}}"#;
+
+#[test]
+fn success_with_quiet_prints_only_the_reply() {
+    // Session notes are decoration too, whether or not sessions are compiled in
+    let dir = tempfile::tempdir().unwrap();
+    let database_url = format!("sqlite:{}", dir.path().join("quiet.db").display());
+
+    grok(&upstream(200, COMPLETION))
+        .env("DATABASE_URL", database_url)
+        .args(["chat", "hi", "--quiet", "--new-session", "t"])
+        .assert()
+        .code(0)
+        .stdout("hello\n")
+        .stderr("");
+}
+
+#[test]
+fn usage_errors_exit_2() {
+    grok(&upstream(200, COMPLETION))
+        .args(["chat", "--file", "Cargo.toml"])
+        .assert()
+        .code(2);
+
+    grok(&upstream(200, COMPLETION))
+        .args(["chat", "--temperature", "warm"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn usage_errors_follow_the_locale() {
+    let output = grok(&upstream(200, COMPLETION))
+        .args(["chat", "--locale", "es", "--file", "Cargo.toml"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--file necesita un mensaje"), "{}", stderr);
+
+    grok(&upstream(200, COMPLETION))
+        .args(["chat", "--locale", "xx", "hi"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn missing_or_rejected_key_exits_3() {
+    grok(&upstream(200, COMPLETION))
+        .env_remove("XAI_API_KEY")
+        .args(["chat", "hi"])
+        .assert()
+        .code(3);
+
+    grok(&upstream(401, r#"{"error":"invalid api key"}"#))
+        .args(["chat", "hi"])
+        .assert()
+        .code(3);
+}
+
+#[test]
+fn upstream_failures_exit_4() {
+    grok(&upstream(502, "bad gateway"))
+        .args(["chat", "hi"])
+        .assert()
+        .code(4);
+
+    // Nothing listens on a port whose listener was just dropped
+    let closed = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    grok(&format!("http://{}/v1", closed))
+        .args(["models"])
+        .assert()
+        .code(4);
+}
+
+#[test]
+fn rate_limit_exits_5() {
+    grok(&upstream(429, r#"{"error":"rate limited"}"#))
+        .args(["chat", "hi", "--output", "json"])
+        .assert()
+        .code(5);
+}
+
+#[test]
+fn context_overflow_exits_6() {
+    grok(&upstream(
+        400,
+        r#"{"error":"This model's maximum prompt length is 131072 but the request contains 200000 tokens."}"#,
+    ))
+    .args(["chat", "hi"])
+    .assert()
+    .code(6);
+}
+
+#[test]
+fn replay_regression_exits_7() {
+    let dir = tempfile::tempdir().unwrap();
+    let export = dir.path().join("export.json");
+    std::fs::write(
+        &export,
+        r#"{"session":{"id":"s1","title":null,"created_at":"2025-01-01T00:00:00Z","updated_at":"2025-01-01T00:00:00Z","model":"grok-3"},"messages":[{"id":1,"session_id":"s1","role":"user","content":"hi","timestamp":"2025-01-01T00:00:00Z","model":null,"tokens_used":null},{"id":2,"session_id":"s1","role":"assistant","content":"goodbye","timestamp":"2025-01-01T00:00:01Z","model":"grok-3","tokens_used":1}]}"#,
+    )
+    .unwrap();
+    let export = export.to_str().unwrap();
+
+    // The report is still printed before the failure
+    let assert = grok(&upstream(200, COMPLETION))
+        .args([
+            "replay",
+            export,
+            "--fail-threshold",
+            "0.5",
+            "--output",
+            "json",
+        ])
+        .assert()
+        .code(7);
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout);
+    assert!(stdout.contains(r#""passed":false"#), "{}", stdout);
+
+    grok(&upstream(200, COMPLETION))
+        .args(["replay", export, "--fail-threshold", "0"])
+        .assert()
+        .code(0);
+}
+
+#[test]
+fn unconfirmed_costly_requests_exit_8() {
+    let base_url = upstream(200, COMPLETION);
+    let costly = |args: &[&str]| {
+        let mut command = grok(&base_url);
+        command
+            .env("COST_THRESHOLD_USD", "0.01")
+            .args(["chat", "hi", "-m", "grok-4-0709", "--max-tokens", "2048"])
+            .args(args);
+        command
+    };
+
+    let assert = costly(&["--output", "json"]).assert().code(8);
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+    assert!(
+        stderr.contains(r#""kind":"cost_not_confirmed""#),
+        "{}",
+        stderr
+    );
+
+    costly(&["--yes"]).assert().code(0).stdout("hello\n");
+    costly(&[])
+        .env("COST_GUARD", "warn")
+        .assert()
+        .code(0)
+        .stdout("hello\n");
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn failed_script_expectations_exit_7() {
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("greeting.yaml");
+    std::fs::write(
+        &script,
+        "turns:\n  - user: Say {{word}}\n    expect: [\"{{word}}\"]\n",
+    )
+    .unwrap();
+    let script = script.to_str().unwrap();
+    let database_url = format!("sqlite:{}/chat.db", dir.path().display());
+
+    // The summary is still printed before the failure
+    let assert = grok(&upstream(200, COMPLETION))
+        .env("DATABASE_URL", &database_url)
+        .args(["run", script, "--var", "word=goodbye"])
+        .assert()
+        .code(7);
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout);
+    assert!(
+        stdout.contains(r#"❌ Turn 1: missing "goodbye""#),
+        "{}",
+        stdout
+    );
+
+    grok(&upstream(200, COMPLETION))
+        .env("DATABASE_URL", &database_url)
+        .env("word", "hello")
+        .args(["run", script])
+        .assert()
+        .code(0);
+
+    // An undefined placeholder is a usage error
+    grok(&upstream(200, COMPLETION))
+        .env("DATABASE_URL", &database_url)
+        .env_remove("word")
+        .args(["run", script])
+        .assert()
+        .code(2);
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn serve_preflight_reports_a_rejected_key() {
+    let dir = tempfile::tempdir().unwrap();
+    let assert = grok(&upstream(401, r#"{"error":"invalid api key"}"#))
+        .env(
+            "DATABASE_URL",
+            format!("sqlite:{}/chat.db", dir.path().display()),
+        )
+        .args(["serve", "--port", "0"])
+        .assert()
+        .code(3);
+
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).into_owned();
+    assert!(stderr.contains("Startup checks failed"), "{}", stderr);
+    assert!(stderr.contains("API key: "), "{}", stderr);
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn removing_an_unknown_prompt_lists_the_saved_ones() {
+    let dir = tempfile::tempdir().unwrap();
+    let database_url = format!("sqlite:{}/chat.db", dir.path().display());
+    let prompts = |args: &[&str]| {
+        let mut command = grok(&upstream(200, COMPLETION));
+        command
+            .env("DATABASE_URL", &database_url)
+            .arg("prompts")
+            .args(args);
+        command
+    };
+
+    prompts(&["add", "terse", "Be brief."]).assert().code(0);
+    let assert = prompts(&["rm", "tersse"]).assert().code(2);
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).into_owned();
+    assert!(stderr.contains("available: terse"), "{}", stderr);
+}
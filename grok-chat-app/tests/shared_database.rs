@@ -0,0 +1,88 @@
+//! Two handles on one database file, as when `serve` runs as a daemon and
+//! the terminal UI is opened against the same `grok_chat.db`.
+
+#![cfg(feature = "server")]
+
+use grok_chat_app::audit::Actor;
+use grok_chat_app::config::Config;
+use grok_chat_app::database::Database;
+use grok_chat_app::models::{Annotation, ChatSession, Message};
+use std::time::Duration;
+
+async fn open(path: &std::path::Path) -> Database {
+    Database::new(&Config {
+        database_url: format!("sqlite:{}", path.display()),
+        ..Config::default()
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_writes_are_seen_by_another_handle_on_the_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("chat.db");
+    let server = open(&path).await;
+    let tui = open(&path).await;
+
+    let session = server
+        .create_session(ChatSession::new("grok-3".to_string(), None))
+        .await
+        .unwrap();
+    let seen = tui.data_version().await.unwrap();
+    server
+        .create_message(Message::user(session.id.clone(), "Hello".to_string()))
+        .await
+        .unwrap();
+
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while tui.data_version().await.unwrap() == seen {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the write was noticed");
+    let messages = tui.get_messages(&session.id).await.unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].content, "Hello");
+}
+
+#[tokio::test]
+async fn test_concurrent_writers_wait_for_each_other() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("chat.db");
+    let server = open(&path).await;
+    let tui = open(&path).await;
+    let session = server
+        .create_session(ChatSession::new("grok-3".to_string(), None))
+        .await
+        .unwrap();
+
+    // Annotating looks the message up before writing, which fails at once
+    // with SQLITE_BUSY in a deferred transaction the other side wrote during
+    let writes = (0..20).flat_map(|n| {
+        [&server, &tui].map(|database| {
+            let database = database.clone();
+            let session_id = session.id.clone();
+            tokio::spawn(async move {
+                let message = Message::user(session_id.clone(), format!("message {}", n));
+                let message = database
+                    .create_message_audited(message, &Actor::new("test"))
+                    .await?;
+                let annotation = Annotation {
+                    note: Some(format!("note {}", n)),
+                    ..Annotation::default()
+                };
+                database
+                    .set_annotation(&session_id, message.id, &annotation)
+                    .await
+            })
+        })
+    });
+    for write in futures_util::future::join_all(writes).await {
+        assert!(write.unwrap().unwrap());
+    }
+    let messages = tui.get_messages(&session.id).await.unwrap();
+    assert_eq!(messages.len(), 40);
+    assert!(messages.iter().all(|message| message.annotation.is_some()));
+}